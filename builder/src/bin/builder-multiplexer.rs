@@ -0,0 +1,90 @@
+use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
+use async_std::{sync::Arc, task::spawn};
+use builder::multiplexer::{run_multiplexer_service, BuilderMultiplexer, SelectionPolicy};
+use clap::Parser;
+use cld::ClDuration;
+use snafu::Snafu;
+use std::{str::FromStr, time::Duration};
+use url::Url;
+
+#[derive(Parser, Clone, Debug)]
+struct MultiplexerOptions {
+    /// URLs of the builders to serve blocks from.
+    #[clap(
+        long,
+        env = "ESPRESSO_BUILDER_MULTIPLEXER_BUILDERS",
+        value_delimiter = ',',
+        required = true
+    )]
+    builders: Vec<Url>,
+
+    /// Port to serve the builder API on.
+    ///
+    /// HotShot should be configured with this server as its builder URL.
+    #[clap(short, long, env = "ESPRESSO_BUILDER_MULTIPLEXER_PORT")]
+    port: u16,
+
+    /// How to choose the builders to get blocks from.
+    #[clap(
+        long,
+        env = "ESPRESSO_BUILDER_MULTIPLEXER_POLICY",
+        value_enum,
+        default_value = "round-robin"
+    )]
+    policy: SelectionPolicy,
+
+    /// How often to check the health of each builder.
+    #[clap(
+        long,
+        env = "ESPRESSO_BUILDER_MULTIPLEXER_HEALTH_CHECK_INTERVAL",
+        default_value = "5s",
+        value_parser = parse_duration
+    )]
+    health_check_interval: Duration,
+
+    /// How long to wait for a builder to respond before trying another.
+    #[clap(
+        long,
+        env = "ESPRESSO_BUILDER_MULTIPLEXER_REQUEST_TIMEOUT",
+        default_value = "500ms",
+        value_parser = parse_duration
+    )]
+    request_timeout: Duration,
+}
+
+#[derive(Clone, Debug, Snafu)]
+struct ParseDurationError {
+    reason: String,
+}
+
+fn parse_duration(s: &str) -> Result<Duration, ParseDurationError> {
+    ClDuration::from_str(s)
+        .map(Duration::from)
+        .map_err(|err| ParseDurationError {
+            reason: err.to_string(),
+        })
+}
+
+#[async_std::main]
+async fn main() -> anyhow::Result<()> {
+    setup_logging();
+    setup_backtrace();
+
+    let opt = MultiplexerOptions::parse();
+    let multiplexer = Arc::new(BuilderMultiplexer::new(
+        opt.builders,
+        opt.policy,
+        opt.request_timeout,
+    ));
+
+    let health_check = {
+        let multiplexer = multiplexer.clone();
+        let interval = opt.health_check_interval;
+        spawn(async move { multiplexer.health_check_loop(interval).await })
+    };
+
+    let url = format!("http://0.0.0.0:{}", opt.port).parse()?;
+    run_multiplexer_service(url, multiplexer)?.await?;
+    health_check.cancel().await;
+    Ok(())
+}
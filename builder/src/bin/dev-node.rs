@@ -0,0 +1,420 @@
+//! Run a complete local Espresso network in one process.
+//!
+//! This launches an Anvil L1, deploys the contracts to it, and starts the sequencer nodes, a
+//! permissionless builder, the state relay server and the state prover, all wired to each other.
+//! The endpoints of every service and the addresses of the contracts are printed once the network
+//! is running, in the form of env vars which other services and tests can be configured with.
+//!
+//! The nodes communicate over an in-memory network and keep their state in memory, so the network
+//! starts from genesis every time.
+
+use anyhow::Context;
+use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
+use async_std::task::spawn;
+use builder::non_permissioned::{build_instance_state, BuilderConfig};
+use clap::Parser;
+use cld::ClDuration;
+use contract_bindings::hot_shot::HotShot;
+use es_version::{SequencerVersion, SEQUENCER_VERSION};
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http as HttpProvider, Provider},
+    signers::{LocalWallet, Signer},
+    types::Address,
+    utils::AnvilInstance,
+};
+use futures::{future::try_join_all, FutureExt};
+use hotshot_stake_table::{config::STAKE_TABLE_CAPACITY, vec_based::StakeTable};
+use hotshot_state_prover::service::{
+    genesis_from_stake_table, init_stake_table, run_prover_service_with_stake_table,
+    StateProverConfig,
+};
+use hotshot_types::{
+    data::ViewNumber,
+    light_client::{CircuitField, StateVerKey},
+    signature_key::BLSPubKey,
+    traits::node_implementation::ConsensusTime,
+};
+use portpicker::pick_unused_port;
+use sequencer::{
+    api::options::{Catchup, HotshotEvents, Http, Options, State, Status, Submit},
+    catchup::StatePeers,
+    persistence::no_storage::NoStorage,
+    reload::shutdown_signal,
+    state_signature::relay_server::{run_relay_server, RelayConfig},
+    testing::TestConfig,
+    L1Params, ValidatedState,
+};
+use sequencer_utils::deployer::{
+    deploy_fee_contract, deploy_production_stack, prover::ProverConfig, Contract, Contracts,
+};
+use snafu::Snafu;
+use std::{
+    fs::File,
+    io::{stdout, Write},
+    num::NonZeroUsize,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use url::Url;
+
+#[derive(Parser, Clone, Debug)]
+struct Args {
+    /// Number of sequencer nodes to run.
+    #[clap(long, env = "ESPRESSO_DEV_NODE_NUM_NODES", default_value = "4")]
+    num_nodes: usize,
+
+    /// Port of the HTTP API of the first sequencer node.
+    ///
+    /// Each following node serves its API on the next port. If not given, unused ports are chosen.
+    #[clap(long, env = "ESPRESSO_DEV_NODE_API_PORT")]
+    api_port: Option<u16>,
+
+    /// Port of the HotShot event streaming API of the first sequencer node.
+    #[clap(long, env = "ESPRESSO_DEV_NODE_EVENTS_PORT")]
+    events_port: Option<u16>,
+
+    /// Port of the builder server.
+    #[clap(long, env = "ESPRESSO_DEV_NODE_BUILDER_PORT")]
+    builder_port: Option<u16>,
+
+    /// Port of the state relay server.
+    #[clap(long, env = "ESPRESSO_DEV_NODE_RELAY_PORT")]
+    relay_port: Option<u16>,
+
+    /// Port of the HTTP API of the state prover.
+    #[clap(long, env = "ESPRESSO_DEV_NODE_PROVER_PORT")]
+    prover_port: Option<u16>,
+
+    /// How often the prover updates the light client contract.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEV_NODE_PROVER_INTERVAL",
+        value_parser = parse_duration,
+        default_value = "1m"
+    )]
+    prover_interval: Duration,
+
+    /// File to load the proving key from, or to save it to after generating it.
+    ///
+    /// Generating the proving key takes a long time and a lot of memory, so reusing a saved key
+    /// makes the prover start much faster after the first run.
+    #[clap(long, env = "ESPRESSO_STATE_PROVER_PROVING_KEY_PATH")]
+    proving_key_path: Option<PathBuf>,
+
+    /// Do not run the state prover.
+    ///
+    /// The light client contract then stays at its genesis state.
+    #[clap(long, env = "ESPRESSO_DEV_NODE_NO_PROVER")]
+    no_prover: bool,
+
+    /// File to write the endpoints and contract addresses to, as env vars.
+    #[clap(long, env = "ESPRESSO_DEV_NODE_ENV_FILE")]
+    env_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Snafu)]
+struct ParseDurationError {
+    reason: String,
+}
+
+fn parse_duration(s: &str) -> Result<Duration, ParseDurationError> {
+    ClDuration::from_str(s)
+        .map(Duration::from)
+        .map_err(|err| ParseDurationError {
+            reason: err.to_string(),
+        })
+}
+
+/// The endpoints of the services of the network.
+#[derive(Clone, Debug)]
+struct Endpoints {
+    l1: Url,
+    sequencers: Vec<Url>,
+    events: Url,
+    builder: Url,
+    relay: Url,
+    prover: Option<Url>,
+}
+
+impl Endpoints {
+    /// Write the endpoints and the contract addresses as a .env file.
+    fn write(&self, contracts: &Contracts, mut w: impl Write) -> anyhow::Result<()> {
+        let light_client = contracts.get_or_err(Contract::LightClientProxy)?;
+        let peers = self
+            .sequencers
+            .iter()
+            .map(|url| url.as_str().trim_end_matches('/'))
+            .collect::<Vec<_>>()
+            .join(",");
+        writeln!(w, "ESPRESSO_SEQUENCER_L1_PROVIDER={}", self.l1)?;
+        writeln!(w, "ESPRESSO_SEQUENCER_URL={}", self.sequencers[0])?;
+        writeln!(
+            w,
+            "ESPRESSO_SUBMIT_TRANSACTIONS_SUBMIT_URL={}",
+            self.sequencers[0]
+        )?;
+        writeln!(w, "ESPRESSO_SEQUENCER_STATE_PEERS={peers}")?;
+        writeln!(
+            w,
+            "ESPRESSO_SEQUENCER_HOTSHOT_EVENT_STREAMING_API_URL={}",
+            self.events
+        )?;
+        writeln!(w, "ESPRESSO_ORCHESTRATOR_BUILDER_URL={}", self.builder)?;
+        writeln!(w, "ESPRESSO_STATE_RELAY_SERVER_URL={}", self.relay)?;
+        if let Some(prover) = &self.prover {
+            writeln!(w, "ESPRESSO_STATE_PROVER_URL={prover}")?;
+        }
+        writeln!(
+            w,
+            "ESPRESSO_SEQUENCER_LIGHTCLIENT_ADDRESS={light_client:#x}"
+        )?;
+        contracts.write(w)
+    }
+}
+
+fn local_url(port: u16) -> Url {
+    format!("http://localhost:{port}").parse().unwrap()
+}
+
+fn bind_url(port: u16) -> Url {
+    format!("http://0.0.0.0:{port}").parse().unwrap()
+}
+
+fn port_or_unused(port: Option<u16>) -> anyhow::Result<u16> {
+    match port {
+        Some(port) => Ok(port),
+        None => pick_unused_port().context("no unused port"),
+    }
+}
+
+/// The stake table of the nodes in `cfg`, as the prover sees it.
+fn stake_table(
+    cfg: &TestConfig,
+) -> anyhow::Result<StakeTable<BLSPubKey, StateVerKey, CircuitField>> {
+    let nodes = &cfg.hotshot_config().known_nodes_with_stake;
+    let bls_keys = nodes
+        .iter()
+        .map(|node| node.stake_table_entry.stake_key)
+        .collect::<Vec<_>>();
+    let state_keys = nodes
+        .iter()
+        .map(|node| node.state_ver_key.clone())
+        .collect::<Vec<_>>();
+    Ok(init_stake_table(
+        &bls_keys,
+        &state_keys,
+        STAKE_TABLE_CAPACITY,
+    )?)
+}
+
+/// Deploy the contracts to `anvil` from its first account, with a light client starting from the
+/// genesis state of `stake_table`.
+async fn deploy_contracts(
+    anvil: &AnvilInstance,
+    stake_table: &StakeTable<BLSPubKey, StateVerKey, CircuitField>,
+) -> anyhow::Result<Contracts> {
+    let provider =
+        Provider::<HttpProvider>::try_from(anvil.endpoint())?.interval(Duration::from_millis(100));
+    let wallet = LocalWallet::from(anvil.keys()[0].clone()).with_chain_id(anvil.chain_id());
+    let owner = wallet.address();
+    let l1 = Arc::new(SignerMiddleware::new(provider, wallet));
+
+    let mut contracts = Contracts::default();
+    contracts
+        .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ())?)
+        .await?;
+    let genesis = genesis_from_stake_table(stake_table)?;
+    // Leave the light client permissionless, so any prover may update it.
+    deploy_production_stack(
+        l1.clone(),
+        &mut contracts,
+        genesis,
+        owner,
+        ProverConfig::default(),
+        None,
+    )
+    .await?;
+    deploy_fee_contract(l1, &mut contracts, owner).await?;
+    Ok(contracts)
+}
+
+#[async_std::main]
+async fn main() -> anyhow::Result<()> {
+    setup_logging();
+    setup_backtrace();
+
+    let args = Args::parse();
+    anyhow::ensure!(args.num_nodes > 0, "a network needs at least one node");
+
+    let mut cfg = TestConfig::with_num_nodes(args.num_nodes);
+    let api_ports = match args.api_port {
+        Some(port) => (0..args.num_nodes).map(|i| port + i as u16).collect(),
+        None => (0..args.num_nodes)
+            .map(|_| port_or_unused(None))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+    };
+    let events_port = port_or_unused(args.events_port)?;
+    let builder_port = port_or_unused(args.builder_port)?;
+    let relay_port = port_or_unused(args.relay_port)?;
+    let endpoints = Endpoints {
+        l1: cfg.anvil().endpoint().parse()?,
+        sequencers: api_ports.iter().copied().map(local_url).collect(),
+        events: local_url(events_port),
+        builder: local_url(builder_port),
+        relay: local_url(relay_port),
+        prover: if args.no_prover {
+            None
+        } else {
+            Some(local_url(port_or_unused(args.prover_port)?))
+        },
+    };
+
+    tracing::warn!(l1 = %endpoints.l1, "deploying contracts");
+    let stake_table = stake_table(&cfg)?;
+    let contracts = deploy_contracts(cfg.anvil(), &stake_table).await?;
+
+    tracing::warn!(url = %endpoints.relay, "starting state relay server");
+    let relay_stake_table = cfg
+        .hotshot_config()
+        .known_nodes_with_stake
+        .iter()
+        .map(|node| {
+            (
+                node.state_ver_key.clone(),
+                node.stake_table_entry.stake_amount,
+            )
+        })
+        .collect();
+    spawn(run_relay_server(
+        None,
+        RelayConfig {
+            threshold: 0,
+            stake_table: Some(relay_stake_table),
+            sequencers: vec![],
+            peers: vec![],
+        },
+        bind_url(relay_port),
+        SEQUENCER_VERSION,
+    ));
+    cfg.set_state_relay_url(endpoints.relay.clone());
+    cfg.set_builder_url(endpoints.builder.clone());
+
+    tracing::warn!(num_nodes = args.num_nodes, "starting sequencer nodes");
+    let mut nodes = try_join_all(api_ports.iter().enumerate().map(|(i, port)| {
+        let mut opt = Options::from(Http { port: *port })
+            .submit(Submit::default())
+            .status(Status)
+            .state(State::default())
+            .catchup(Catchup);
+        if i == 0 {
+            opt = opt.hotshot_events(HotshotEvents {
+                events_service_port: events_port,
+            });
+        }
+        let cfg = cfg.clone();
+        // Every node can catch up on missing state from any node.
+        let peers = endpoints.sequencers.clone();
+        opt.serve(
+            move |metrics| {
+                async move {
+                    cfg.init_node(
+                        i,
+                        ValidatedState::default(),
+                        NoStorage,
+                        StatePeers::<SequencerVersion>::from_urls(peers),
+                        &*metrics,
+                        STAKE_TABLE_CAPACITY,
+                        SEQUENCER_VERSION,
+                    )
+                    .await
+                }
+                .boxed()
+            },
+            SEQUENCER_VERSION,
+        )
+    }))
+    .await?;
+
+    // The builder account is funded in the genesis state of the nodes.
+    let builder_key = TestConfig::builder_key();
+    let builder_account = builder_key.fee_account();
+    tracing::warn!(url = %endpoints.builder, %builder_account, "starting builder");
+    let instance_state = build_instance_state(
+        L1Params {
+            urls: vec![endpoints.l1.clone()],
+            rate_limit: None,
+            upgrade_contract: None,
+            upgrade_start_block: 0,
+        },
+        endpoints.sequencers.clone(),
+        SEQUENCER_VERSION,
+    )?;
+    BuilderConfig::init(
+        builder_key,
+        ViewNumber::genesis(),
+        NonZeroUsize::new(1024).unwrap(),
+        instance_state,
+        endpoints.events.clone(),
+        bind_url(builder_port),
+        Duration::from_secs(1),
+        15,
+    )
+    .await?;
+
+    for node in &nodes {
+        node.start_consensus().await;
+    }
+
+    if let Some(prover_url) = &endpoints.prover {
+        tracing::warn!(url = %prover_url, "starting state prover");
+        let config = StateProverConfig {
+            relay_server: endpoints.relay.clone(),
+            update_interval: args.prover_interval,
+            l1_provider: endpoints.l1.clone(),
+            light_client_address: contracts.get_or_err(Contract::LightClientProxy)?,
+            eth_signing_key: cfg.anvil().keys()[1].clone().into(),
+            // The stake table is given to the prover directly, so there is no orchestrator.
+            orchestrator_url: endpoints.sequencers[0].clone(),
+            port: prover_url.port(),
+            stake_table_capacity: STAKE_TABLE_CAPACITY,
+            proof_threads: None,
+            profile: Default::default(),
+            proving_key_path: args.proving_key_path.clone(),
+        };
+        // The prover blocks its thread while generating proofs, so give it a thread of its own.
+        thread::spawn(move || {
+            async_std::task::block_on(run_prover_service_with_stake_table(
+                config,
+                stake_table,
+                SEQUENCER_VERSION,
+            ))
+        });
+    }
+
+    println!("Espresso dev network is running.");
+    println!("Builder fee account: {builder_account}");
+    println!("Funded L1 accounts:");
+    for key in &cfg.anvil().keys()[2..] {
+        println!("  {:#x}", key_address(key));
+    }
+    println!();
+    endpoints.write(&contracts, stdout())?;
+    if let Some(path) = &args.env_file {
+        endpoints.write(&contracts, File::create(path)?)?;
+        println!("\nWrote endpoints to {}", path.display());
+    }
+
+    shutdown_signal(None)?.await;
+    for node in &mut nodes {
+        node.shut_down().await;
+    }
+    Ok(())
+}
+
+fn key_address(key: &ethers::core::k256::SecretKey) -> Address {
+    LocalWallet::from(key.clone()).address()
+}
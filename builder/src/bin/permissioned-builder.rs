@@ -124,9 +124,34 @@ pub struct PermissionedBuilderOptions {
     #[clap(long, env = "ESPRESSO_BUILDER_ETH_ACCOUNT_INDEX", default_value = "8")]
     pub eth_account_index: u32,
 
-    /// Url we will use for RPC communication with L1.
-    #[clap(long, env = "ESPRESSO_BUILDER_L1_PROVIDER")]
-    pub l1_provider_url: Url,
+    /// Urls we will use for RPC communication with L1.
+    ///
+    /// Comma-separated list, in order of priority. If a provider fails, requests fail over to the
+    /// next one.
+    #[clap(
+        long,
+        env = "ESPRESSO_BUILDER_L1_PROVIDER",
+        value_delimiter = ',',
+        required = true
+    )]
+    pub l1_provider_url: Vec<Url>,
+
+    /// Maximum number of requests per second to send to each L1 provider.
+    #[clap(long, env = "ESPRESSO_BUILDER_L1_RATE_LIMIT")]
+    pub l1_rate_limit: Option<u64>,
+
+    /// Address of the L1 contract scheduling upgrades of the chain config, if any.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_UPGRADE_CONTRACT_ADDRESS")]
+    pub upgrade_contract_address: Option<Address>,
+
+    /// L1 block to start searching for upgrades from, such as the block the upgrade contract was
+    /// deployed in.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_UPGRADE_CONTRACT_START_BLOCK",
+        default_value = "0"
+    )]
+    pub upgrade_contract_start_block: u64,
 
     /// Peer nodes use to fetch missing state
     #[clap(long, env = "ESPRESSO_SEQUENCER_STATE_PEERS", value_delimiter = ',')]
@@ -217,7 +242,10 @@ async fn main() -> anyhow::Result<()> {
     let (private_staking_key, private_state_key) = opt.private_keys()?;
 
     let l1_params = L1Params {
-        url: opt.l1_provider_url,
+        urls: opt.l1_provider_url,
+        rate_limit: opt.l1_rate_limit,
+        upgrade_contract: opt.upgrade_contract_address,
+        upgrade_start_block: opt.upgrade_contract_start_block,
     };
 
     let builder_key_pair = EthKeyPair::from_mnemonic(&opt.eth_mnemonic, opt.eth_account_index)?;
@@ -251,6 +279,7 @@ async fn main() -> anyhow::Result<()> {
         private_staking_key: private_staking_key.clone(),
         private_state_key,
         state_peers: opt.state_peers,
+        snapshot_url: None,
     };
 
     let sequencer_version = SEQUENCER_VERSION;
@@ -3,6 +3,7 @@ use builder::non_permissioned::{build_instance_state, BuilderConfig};
 use clap::Parser;
 use cld::ClDuration;
 use es_version::SEQUENCER_VERSION;
+use ethers::types::Address;
 use hotshot_types::data::ViewNumber;
 use hotshot_types::traits::node_implementation::ConsensusTime;
 use sequencer::eth_signature_key::EthKeyPair;
@@ -34,9 +35,34 @@ struct NonPermissionedBuilderOptions {
     #[clap(long, env = "ESPRESSO_BUILDER_ETH_ACCOUNT_INDEX", default_value = "8")]
     eth_account_index: u32,
 
-    /// Url we will use for RPC communication with L1.
-    #[clap(long, env = "ESPRESSO_BUILDER_L1_PROVIDER")]
-    l1_provider_url: Url,
+    /// Urls we will use for RPC communication with L1.
+    ///
+    /// Comma-separated list, in order of priority. If a provider fails, requests fail over to the
+    /// next one.
+    #[clap(
+        long,
+        env = "ESPRESSO_BUILDER_L1_PROVIDER",
+        value_delimiter = ',',
+        required = true
+    )]
+    l1_provider_url: Vec<Url>,
+
+    /// Maximum number of requests per second to send to each L1 provider.
+    #[clap(long, env = "ESPRESSO_BUILDER_L1_RATE_LIMIT")]
+    l1_rate_limit: Option<u64>,
+
+    /// Address of the L1 contract scheduling upgrades of the chain config, if any.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_UPGRADE_CONTRACT_ADDRESS")]
+    upgrade_contract_address: Option<Address>,
+
+    /// L1 block to start searching for upgrades from, such as the block the upgrade contract was
+    /// deployed in.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_UPGRADE_CONTRACT_START_BLOCK",
+        default_value = "0"
+    )]
+    upgrade_contract_start_block: u64,
 
     /// Peer nodes use to fetch missing state
     #[clap(long, env = "ESPRESSO_SEQUENCER_STATE_PEERS", value_delimiter = ',')]
@@ -97,7 +123,10 @@ async fn main() -> anyhow::Result<()> {
     let sequencer_version = SEQUENCER_VERSION;
 
     let l1_params = L1Params {
-        url: opt.l1_provider_url,
+        urls: opt.l1_provider_url,
+        rate_limit: opt.l1_rate_limit,
+        upgrade_contract: opt.upgrade_contract_address,
+        upgrade_start_block: opt.upgrade_contract_start_block,
     };
 
     let builder_key_pair = EthKeyPair::from_mnemonic(&opt.eth_mnemonic, opt.eth_account_index)?;
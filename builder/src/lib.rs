@@ -65,6 +65,7 @@ use std::{net::Ipv4Addr, thread::Builder};
 use tide_disco::{app, method::ReadState, App, Url};
 use vbs::version::StaticVersionType;
 
+pub mod multiplexer;
 pub mod non_permissioned;
 pub mod permissioned;
 
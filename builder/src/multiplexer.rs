@@ -0,0 +1,444 @@
+//! Serving the builder API from several builders, with health checking and failover.
+//!
+//! HotShot fetches blocks from a single builder URL, so if that builder goes down, leaders can only
+//! propose empty blocks. The [`BuilderMultiplexer`] serves the builder API at that URL on behalf of
+//! a set of upstream builders: it asks healthy builders for available blocks according to a
+//! [`SelectionPolicy`], remembers which builder offered each block, and forwards claims for a block
+//! to the builder which offered it.
+
+use async_std::{
+    sync::{Arc, RwLock},
+    task::{sleep, spawn, JoinHandle},
+};
+use clap::ValueEnum;
+use futures::{future::join_all, FutureExt};
+use hotshot_builder_api::{
+    block_info::{AvailableBlockData, AvailableBlockHeaderInput, AvailableBlockInfo},
+    builder::Error as BuilderApiError,
+};
+use hotshot_types::constants::{Version01, STATIC_VER_0_1};
+use sequencer::{state::FeeAccount, SeqTypes};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    fmt::{self, Write},
+    io,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use surf_disco::Client;
+use tide_disco::{metrics::Metrics, Api, App, Error as _, RequestParams, StatusCode, Url};
+
+/// Number of offered blocks to remember the builder of.
+const OFFERED_BLOCKS_CAPACITY: usize = 1000;
+
+/// How the builders to get blocks from are chosen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SelectionPolicy {
+    /// Ask each healthy builder in turn, moving on to the next if it has no blocks.
+    #[default]
+    RoundRobin,
+    /// Ask every healthy builder, and offer all their blocks, highest fee first.
+    BestBid,
+}
+
+#[derive(Debug)]
+struct Upstream {
+    url: Url,
+    client: Client<BuilderApiError, Version01>,
+    healthy: AtomicBool,
+    blocks_claimed: AtomicUsize,
+}
+
+/// The builder API, served by a set of upstream builders.
+#[derive(Debug)]
+pub struct BuilderMultiplexer {
+    builders: Vec<Upstream>,
+    policy: SelectionPolicy,
+    request_timeout: Duration,
+    /// The builder to start from, for round robin selection.
+    next: AtomicUsize,
+    /// The builder which offered each recent block, by block hash.
+    offered: RwLock<(HashMap<String, usize>, VecDeque<String>)>,
+}
+
+impl BuilderMultiplexer {
+    /// A multiplexer for the builders at `urls`, which are initially assumed healthy.
+    pub fn new(urls: Vec<Url>, policy: SelectionPolicy, request_timeout: Duration) -> Self {
+        assert!(
+            !urls.is_empty(),
+            "builder multiplexer needs at least one builder"
+        );
+        Self {
+            builders: urls
+                .into_iter()
+                .map(|url| Upstream {
+                    client: Client::new(url.clone()),
+                    url,
+                    healthy: AtomicBool::new(true),
+                    blocks_claimed: AtomicUsize::new(0),
+                })
+                .collect(),
+            policy,
+            request_timeout,
+            next: AtomicUsize::new(0),
+            offered: Default::default(),
+        }
+    }
+
+    /// The indices of the builders to try, healthy ones first, starting from `start`.
+    fn candidates(&self, start: usize) -> Vec<usize> {
+        let n = self.builders.len();
+        let mut candidates = (0..n).map(|i| (start + i) % n).collect::<Vec<_>>();
+        // A stable sort keeps the rotation within the healthy and unhealthy builders.
+        candidates.sort_by_key(|&i| !self.builders[i].healthy.load(Ordering::Relaxed));
+        candidates
+    }
+
+    fn set_health(&self, index: usize, healthy: bool) {
+        let builder = &self.builders[index];
+        if builder.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            if healthy {
+                tracing::info!(url = %builder.url, "builder is healthy again");
+            } else {
+                tracing::warn!(url = %builder.url, "builder is unhealthy");
+            }
+        }
+    }
+
+    /// Get the blocks available from the builder at `index`.
+    async fn available_blocks_from(
+        &self,
+        index: usize,
+        path: &str,
+    ) -> Option<Vec<AvailableBlockInfo<SeqTypes>>> {
+        let builder = &self.builders[index];
+        let res = async_std::future::timeout(
+            self.request_timeout,
+            builder
+                .client
+                .get::<Vec<AvailableBlockInfo<SeqTypes>>>(path)
+                .send(),
+        )
+        .await;
+        match res {
+            Ok(Ok(blocks)) => {
+                self.set_health(index, true);
+                Some(blocks)
+            }
+            Ok(Err(err)) => {
+                tracing::warn!(url = %builder.url, "error getting available blocks: {err}");
+                self.set_health(index, false);
+                None
+            }
+            Err(_) => {
+                tracing::warn!(url = %builder.url, "timed out getting available blocks");
+                self.set_health(index, false);
+                None
+            }
+        }
+    }
+
+    /// Get the blocks available for `path`, from builders chosen by the selection policy.
+    pub async fn available_blocks(&self, path: &str) -> Vec<AvailableBlockInfo<SeqTypes>> {
+        let blocks = match self.policy {
+            SelectionPolicy::RoundRobin => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                let mut blocks = vec![];
+                for index in self.candidates(start) {
+                    if let Some(offered) = self.available_blocks_from(index, path).await {
+                        if !offered.is_empty() {
+                            blocks = offered.into_iter().map(|block| (index, block)).collect();
+                            break;
+                        }
+                    }
+                }
+                blocks
+            }
+            SelectionPolicy::BestBid => {
+                let mut candidates = self.candidates(0);
+                // Only ask unhealthy builders if there are no healthy ones.
+                if candidates
+                    .first()
+                    .is_some_and(|&i| self.builders[i].healthy.load(Ordering::Relaxed))
+                {
+                    candidates.retain(|&i| self.builders[i].healthy.load(Ordering::Relaxed));
+                }
+                let offers = join_all(
+                    candidates
+                        .iter()
+                        .map(|&index| self.available_blocks_from(index, path)),
+                )
+                .await;
+                let mut blocks = offers
+                    .into_iter()
+                    .zip(candidates)
+                    .flat_map(|(offered, index)| {
+                        offered
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(move |block| (index, block))
+                    })
+                    .collect::<Vec<_>>();
+                blocks.sort_by(|(_, a), (_, b)| b.offered_fee.cmp(&a.offered_fee));
+                blocks
+            }
+        };
+
+        let mut offered = self.offered.write().await;
+        let (by_hash, order) = &mut *offered;
+        for (index, block) in &blocks {
+            let hash = block.block_hash.to_string();
+            if by_hash.insert(hash.clone(), *index).is_none() {
+                order.push_back(hash);
+            }
+        }
+        while order.len() > OFFERED_BLOCKS_CAPACITY {
+            if let Some(hash) = order.pop_front() {
+                by_hash.remove(&hash);
+            }
+        }
+        blocks.into_iter().map(|(_, block)| block).collect()
+    }
+
+    /// The builder which offered the block `block_hash`.
+    async fn offered_by(&self, block_hash: &str) -> Result<usize, BuilderApiError> {
+        self.offered
+            .read()
+            .await
+            .0
+            .get(block_hash)
+            .copied()
+            .ok_or_else(|| {
+                BuilderApiError::catch_all(
+                    StatusCode::NotFound,
+                    format!("block {block_hash} was not offered by any builder"),
+                )
+            })
+    }
+
+    /// Claim the block `block_hash` from the builder which offered it.
+    pub async fn claim_block(
+        &self,
+        block_hash: &str,
+        path: &str,
+    ) -> Result<AvailableBlockData<SeqTypes>, BuilderApiError> {
+        let index = self.offered_by(block_hash).await?;
+        let builder = &self.builders[index];
+        let block = builder
+            .client
+            .get::<AvailableBlockData<SeqTypes>>(path)
+            .send()
+            .await
+            .inspect_err(|_| self.set_health(index, false))?;
+        builder.blocks_claimed.fetch_add(1, Ordering::Relaxed);
+        tracing::info!(url = %builder.url, block_hash, "claimed block");
+        Ok(block)
+    }
+
+    /// Claim the header input for the block `block_hash` from the builder which offered it.
+    pub async fn claim_header_input(
+        &self,
+        block_hash: &str,
+        path: &str,
+    ) -> Result<AvailableBlockHeaderInput<SeqTypes>, BuilderApiError> {
+        let index = self.offered_by(block_hash).await?;
+        self.builders[index]
+            .client
+            .get::<AvailableBlockHeaderInput<SeqTypes>>(path)
+            .send()
+            .await
+            .inspect_err(|_| self.set_health(index, false))
+    }
+
+    /// The fee account of the first healthy builder.
+    pub async fn builder_address(&self) -> Result<FeeAccount, BuilderApiError> {
+        let mut last_err = None;
+        for index in self.candidates(0) {
+            match self.builders[index]
+                .client
+                .get::<FeeAccount>("block_info/builderaddress")
+                .send()
+                .await
+            {
+                Ok(address) => return Ok(address),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.expect("there is at least one builder"))
+    }
+
+    /// Check the health of each builder every `interval`.
+    pub async fn health_check_loop(&self, interval: Duration) {
+        loop {
+            join_all(
+                self.builders
+                    .iter()
+                    .enumerate()
+                    .map(|(index, builder)| async move {
+                        let healthy = builder.client.connect(Some(self.request_timeout)).await;
+                        self.set_health(index, healthy);
+                    }),
+            )
+            .await;
+            sleep(interval).await;
+        }
+    }
+
+    /// A snapshot of the health of each builder and the blocks claimed from it.
+    pub fn metrics(&self) -> MultiplexerMetrics {
+        MultiplexerMetrics {
+            builders: self
+                .builders
+                .iter()
+                .map(|builder| {
+                    (
+                        builder.url.to_string(),
+                        builder.healthy.load(Ordering::Relaxed),
+                        builder.blocks_claimed.load(Ordering::Relaxed),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The health of each builder and the number of blocks claimed from it, exported in the
+/// Prometheus text format.
+#[derive(Clone, Debug, Default)]
+pub struct MultiplexerMetrics {
+    builders: Vec<(String, bool, usize)>,
+}
+
+impl Metrics for MultiplexerMetrics {
+    type Error = fmt::Error;
+
+    fn export(&self) -> Result<String, Self::Error> {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "# HELP builder_healthy Whether the builder is healthy."
+        )?;
+        writeln!(out, "# TYPE builder_healthy gauge")?;
+        for (url, healthy, _) in &self.builders {
+            writeln!(
+                out,
+                "builder_healthy{{builder=\"{url}\"}} {}",
+                *healthy as u8
+            )?;
+        }
+        writeln!(
+            out,
+            "# HELP builder_blocks_claimed_total Number of blocks claimed from the builder."
+        )?;
+        writeln!(out, "# TYPE builder_blocks_claimed_total counter")?;
+        for (url, _, claimed) in &self.builders {
+            writeln!(
+                out,
+                "builder_blocks_claimed_total{{builder=\"{url}\"}} {claimed}"
+            )?;
+        }
+        Ok(out)
+    }
+}
+
+/// The path of a builder API request, relative to the builder's base URL.
+fn upstream_path(
+    req: &RequestParams,
+    route: &str,
+    hash_param: &str,
+) -> Result<(String, String), BuilderApiError> {
+    let param = |name: &str| {
+        req.tagged_base64_param(name)
+            .map(|value| value.to_string())
+            .map_err(BuilderApiError::from_request_error)
+    };
+    let hash = param(hash_param)?;
+    let path = format!(
+        "block_info/{route}/{hash}/{}/{}",
+        param("sender")?,
+        param("signature")?
+    );
+    Ok((hash, path))
+}
+
+/// Serve the builder API from `multiplexer` at `url`, under the `block_info` prefix HotShot
+/// expects.
+pub fn run_multiplexer_service(
+    url: Url,
+    multiplexer: Arc<BuilderMultiplexer>,
+) -> anyhow::Result<JoinHandle<io::Result<()>>> {
+    let toml = toml::from_str::<toml::Value>(include_str!("../api/multiplexer.toml"))?;
+    let mut api = Api::<MultiplexerState, BuilderApiError, Version01>::new(toml)?;
+    api.get("available_blocks", |req, state| {
+        async move {
+            let (_, path) = upstream_path(&req, "availableblocks", "parent_hash")?;
+            Ok(state.available_blocks(&path).await)
+        }
+        .boxed()
+    })?
+    .get("claim_block", |req, state| {
+        async move {
+            let (hash, path) = upstream_path(&req, "claimblock", "block_hash")?;
+            state.claim_block(&hash, &path).await
+        }
+        .boxed()
+    })?
+    .get("claim_header_input", |req, state| {
+        async move {
+            let (hash, path) = upstream_path(&req, "claimheaderinput", "block_hash")?;
+            state.claim_header_input(&hash, &path).await
+        }
+        .boxed()
+    })?
+    .get("builder_address", |_, state| {
+        async move { state.builder_address().await }.boxed()
+    })?
+    .metrics("metrics", |_, state| {
+        async move { Ok(Cow::Owned(state.metrics())) }.boxed()
+    })?;
+
+    let mut app =
+        App::<MultiplexerState, BuilderApiError>::with_state(Arc::new(RwLock::new(multiplexer)));
+    app.register_module("block_info", api)?;
+    Ok(spawn(app.serve(url, STATIC_VER_0_1)))
+}
+
+/// The state of the multiplexer API.
+type MultiplexerState = Arc<RwLock<Arc<BuilderMultiplexer>>>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_candidates() {
+        let urls = (0..3)
+            .map(|i| format!("http://builder{i}").parse().unwrap())
+            .collect();
+        let mux =
+            BuilderMultiplexer::new(urls, SelectionPolicy::RoundRobin, Duration::from_secs(1));
+        assert_eq!(mux.candidates(0), [0, 1, 2]);
+        assert_eq!(mux.candidates(4), [1, 2, 0]);
+
+        // Unhealthy builders are tried last.
+        mux.set_health(1, false);
+        assert_eq!(mux.candidates(1), [2, 0, 1]);
+        assert_eq!(mux.candidates(0), [0, 2, 1]);
+        mux.set_health(1, true);
+        assert_eq!(mux.candidates(1), [1, 2, 0]);
+    }
+
+    #[test]
+    fn test_export_metrics() {
+        let urls = vec!["http://builder0".parse().unwrap()];
+        let mux = BuilderMultiplexer::new(urls, SelectionPolicy::BestBid, Duration::from_secs(1));
+        mux.builders[0]
+            .blocks_claimed
+            .fetch_add(2, Ordering::Relaxed);
+        mux.set_health(0, false);
+        let exported = mux.metrics().export().unwrap();
+        assert!(exported.contains("builder_healthy{builder=\"http://builder0/\"} 0\n"));
+        assert!(exported.contains("builder_blocks_claimed_total{builder=\"http://builder0/\"} 2\n"));
+    }
+}
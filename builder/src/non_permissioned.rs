@@ -33,8 +33,8 @@ use hotshot_types::{
     utils::BuilderCommitment,
 };
 use sequencer::{
-    catchup::StatePeers, eth_signature_key::EthKeyPair, l1_client::L1Client, BuilderParams,
-    ChainConfig, L1Params, NetworkParams, NodeState, Payload, PrivKey, PubKey, SeqTypes,
+    catchup::StatePeers, eth_signature_key::EthKeyPair, BuilderParams, ChainConfig, L1Params,
+    NetworkParams, NodeState, Payload, PrivKey, PubKey, SeqTypes,
 };
 
 use hotshot_events_service::{
@@ -61,12 +61,17 @@ pub fn build_instance_state<Ver: StaticVersionType + 'static>(
     state_peers: Vec<Url>,
     _: Ver,
 ) -> anyhow::Result<NodeState> {
-    let l1_client = L1Client::new(l1_params.url, Address::default());
-    let instance_state = NodeState::new(
+    let upgrade_contract = l1_params.upgrade_contract;
+    let upgrade_start_block = l1_params.upgrade_start_block;
+    let l1_client = l1_params.client(Address::default());
+    let mut instance_state = NodeState::new(
         ChainConfig::default(),
         l1_client,
         Arc::new(StatePeers::<Ver>::from_urls(state_peers)),
     );
+    if let Some(contract) = upgrade_contract {
+        instance_state = instance_state.with_upgrade_contract(contract, upgrade_start_block);
+    }
     Ok(instance_state)
 }
 
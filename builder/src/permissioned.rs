@@ -72,7 +72,6 @@ use sequencer::{catchup::mock::MockStateCatchup, eth_signature_key::EthKeyPair,
 use sequencer::{
     catchup::StatePeers,
     context::{Consensus, SequencerContext},
-    l1_client::L1Client,
     network,
     persistence::SequencerPersistence,
     state::FeeAccount,
@@ -246,13 +245,18 @@ pub async fn init_node<P: SequencerPersistence, Ver: StaticVersionType + 'static
         genesis_state.prefund_account(address.into(), U256::max_value().into());
     }
 
-    let l1_client = L1Client::new(l1_params.url, Address::default());
+    let upgrade_contract = l1_params.upgrade_contract;
+    let upgrade_start_block = l1_params.upgrade_start_block;
+    let l1_client = l1_params.client(Address::default());
 
-    let instance_state = NodeState::new(
+    let mut instance_state = NodeState::new(
         ChainConfig::default(),
         l1_client,
         Arc::new(StatePeers::<Ver>::from_urls(network_params.state_peers)),
     );
+    if let Some(contract) = upgrade_contract {
+        instance_state = instance_state.with_upgrade_contract(contract, upgrade_start_block);
+    }
 
     let stake_table_commit =
         static_stake_table_commitment(&config.config.known_nodes_with_stake, STAKE_TABLE_CAPACITY);
@@ -1,5 +1,6 @@
 //! Helpers and test mocks for Light Client logic
 
+use anyhow::{anyhow, bail, Context};
 use ark_std::str::FromStr;
 use diff_test_bn254::{field_to_u256, u256_to_field};
 use ethers::{
@@ -8,6 +9,9 @@ use ethers::{
     types::U256,
 };
 use hotshot_types::light_client::{CircuitField, LightClientState, PublicInput};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{fs, path::Path};
 
 /// Intermediate representations for `LightClientState` in Solidity
 #[derive(Clone, Debug, EthAbiType, EthAbiCodec, PartialEq)]
@@ -133,3 +137,201 @@ impl From<ParsedLightClientState> for contract_bindings::light_client::LightClie
         unsafe { std::mem::transmute(s) }
     }
 }
+
+/// The fields of a [`ParsedLightClientState`], as written in a genesis file.
+///
+/// Heights are decimal integers, and commitments and the threshold are 0x-prefixed hex strings.
+#[derive(Serialize)]
+struct GenesisFile {
+    view_num: u64,
+    block_height: u64,
+    block_comm_root: String,
+    fee_ledger_comm: String,
+    bls_key_comm: String,
+    schnorr_key_comm: String,
+    amount_comm: String,
+    threshold: String,
+}
+
+const HEIGHT_FIELDS: [&str; 2] = ["view_num", "block_height"];
+const HEX_FIELDS: [&str; 6] = [
+    "block_comm_root",
+    "fee_ledger_comm",
+    "bls_key_comm",
+    "schnorr_key_comm",
+    "amount_comm",
+    "threshold",
+];
+
+impl ParsedLightClientState {
+    /// Load a state from a JSON or TOML file, depending on the extension of `path`.
+    ///
+    /// Heights are given as decimal integers, and commitments and the threshold as 0x-prefixed hex
+    /// strings, like
+    ///
+    /// ```toml
+    /// view_num = 0
+    /// block_height = 0
+    /// block_comm_root = "0x0"
+    /// fee_ledger_comm = "0x0"
+    /// bls_key_comm = "0x7b"
+    /// schnorr_key_comm = "0x7b"
+    /// amount_comm = "0x14"
+    /// threshold = "0x1"
+    /// ```
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("error reading {}", path.display()))?;
+        let value: Value = match extension(path)? {
+            Format::Json => serde_json::from_str(&contents)
+                .with_context(|| format!("{} is not valid JSON", path.display()))?,
+            Format::Toml => toml::from_str(&contents)
+                .with_context(|| format!("{} is not valid TOML", path.display()))?,
+        };
+        Self::from_value(value).with_context(|| format!("invalid state in {}", path.display()))
+    }
+
+    /// Write this state to a JSON or TOML file, depending on the extension of `path`.
+    ///
+    /// The file can be read back with [`from_file`](Self::from_file).
+    pub fn to_file(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let file = GenesisFile {
+            view_num: self.view_num,
+            block_height: self.block_height,
+            block_comm_root: format!("{:#x}", self.block_comm_root),
+            fee_ledger_comm: format!("{:#x}", self.fee_ledger_comm),
+            bls_key_comm: format!("{:#x}", self.bls_key_comm),
+            schnorr_key_comm: format!("{:#x}", self.schnorr_key_comm),
+            amount_comm: format!("{:#x}", self.amount_comm),
+            threshold: format!("{:#x}", self.threshold),
+        };
+        let contents = match extension(path)? {
+            Format::Json => serde_json::to_string_pretty(&file)?,
+            Format::Toml => toml::to_string(&file)?,
+        };
+        fs::write(path, contents).with_context(|| format!("error writing {}", path.display()))
+    }
+
+    fn from_value(value: Value) -> anyhow::Result<Self> {
+        let Value::Object(map) = value else {
+            bail!("expected a table of fields");
+        };
+        if let Some(field) = map.keys().find(|key| {
+            !HEIGHT_FIELDS.contains(&key.as_str()) && !HEX_FIELDS.contains(&key.as_str())
+        }) {
+            bail!("unknown field `{field}`");
+        }
+        Ok(Self {
+            view_num: height_field(&map, "view_num")?,
+            block_height: height_field(&map, "block_height")?,
+            block_comm_root: hex_field(&map, "block_comm_root")?,
+            fee_ledger_comm: hex_field(&map, "fee_ledger_comm")?,
+            bls_key_comm: hex_field(&map, "bls_key_comm")?,
+            schnorr_key_comm: hex_field(&map, "schnorr_key_comm")?,
+            amount_comm: hex_field(&map, "amount_comm")?,
+            threshold: hex_field(&map, "threshold")?,
+        })
+    }
+}
+
+enum Format {
+    Json,
+    Toml,
+}
+
+fn extension(path: &Path) -> anyhow::Result<Format> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => Ok(Format::Json),
+        Some("toml") => Ok(Format::Toml),
+        _ => bail!(
+            "cannot tell the format of {} from its extension, expected .json or .toml",
+            path.display()
+        ),
+    }
+}
+
+fn height_field(map: &Map<String, Value>, name: &str) -> anyhow::Result<u64> {
+    map.get(name)
+        .with_context(|| format!("missing field `{name}`"))?
+        .as_u64()
+        .with_context(|| format!("field `{name}`: expected a non-negative decimal integer"))
+}
+
+fn hex_field(map: &Map<String, Value>, name: &str) -> anyhow::Result<U256> {
+    let value = map
+        .get(name)
+        .with_context(|| format!("missing field `{name}`"))?;
+    let expected = || anyhow!("field `{name}`: expected a 0x-prefixed hex string, got {value}");
+    let hex = value
+        .as_str()
+        .and_then(|s| s.strip_prefix("0x"))
+        .ok_or_else(expected)?;
+    U256::from_str_radix(hex, 16).map_err(|_| expected())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_genesis_file_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let mut genesis = ParsedLightClientState::dummy_genesis();
+        genesis.block_comm_root = U256::MAX;
+        for name in ["genesis.json", "genesis.toml"] {
+            let path = dir.path().join(name);
+            genesis.to_file(&path).unwrap();
+            assert_eq!(ParsedLightClientState::from_file(&path).unwrap(), genesis);
+        }
+    }
+
+    #[test]
+    fn test_genesis_file_fixture() {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../../../data/light_client_genesis.toml");
+        assert_eq!(
+            ParsedLightClientState::from_file(path).unwrap(),
+            ParsedLightClientState::dummy_genesis()
+        );
+    }
+
+    #[test]
+    fn test_genesis_file_errors() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("genesis.toml");
+        ParsedLightClientState::dummy_genesis()
+            .to_file(&path)
+            .unwrap();
+        let valid = fs::read_to_string(&path).unwrap();
+
+        for (from, to, expected) in [
+            (
+                "bls_key_comm = \"0x7b\"",
+                "bls_key_comm = \"123\"",
+                "field `bls_key_comm`: expected a 0x-prefixed hex string",
+            ),
+            (
+                "block_height = 0",
+                "block_height = \"0\"",
+                "field `block_height`: expected a non-negative decimal integer",
+            ),
+            ("threshold = \"0x1\"", "", "missing field `threshold`"),
+            (
+                "view_num = 0",
+                "view_num = 0\nextra = 1",
+                "unknown field `extra`",
+            ),
+        ] {
+            assert!(valid.contains(from), "{valid}");
+            fs::write(&path, valid.replace(from, to)).unwrap();
+            let err = format!(
+                "{:#}",
+                ParsedLightClientState::from_file(&path).unwrap_err()
+            );
+            assert!(err.contains(expected), "{err}");
+        }
+    }
+}
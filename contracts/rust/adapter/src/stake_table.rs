@@ -0,0 +1,94 @@
+//! Conversions between the keys of sequencer nodes and their representation in `StakeTable.sol`.
+
+use anyhow::{ensure, Context, Result};
+use ark_bn254::G2Affine;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ed_on_bn254::{EdwardsAffine, Fq as FqEd254};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use diff_test_bn254::{field_to_u256, u256_to_field};
+use ethers::{
+    abi::AbiEncode,
+    types::{Address, U256},
+};
+use hotshot_types::{
+    light_client::StateVerKey,
+    signature_key::{BLSPrivKey, BLSPubKey},
+};
+use jf_primitives::{
+    constants::CS_ID_BLS_BN254, signatures::bls_over_bn254::KeyPair as BLSKeyPair,
+};
+
+pub use diff_test_bn254::{ParsedG1Point, ParsedG2Point};
+
+/// The BLS verification key `key` as a `BN254.G2Point`.
+pub fn bls_key_to_sol(key: &BLSPubKey) -> ParsedG2Point {
+    key.to_affine().into()
+}
+
+/// The BLS verification key represented by a `BN254.G2Point`.
+pub fn bls_key_from_sol(point: ParsedG2Point) -> Result<BLSPubKey> {
+    let point: G2Affine = point.into();
+    ensure!(
+        point.is_on_curve() && point.is_in_correct_subgroup_assuming_on_curve(),
+        "BLS key is not a valid G2 point"
+    );
+    recast(&point)
+}
+
+/// The Schnorr verification key `key` as an `EdOnBN254.EdOnBN254Point`.
+pub fn schnorr_key_to_sol(key: &StateVerKey) -> (U256, U256) {
+    let point = key.to_affine();
+    (
+        field_to_u256::<FqEd254>(point.x),
+        field_to_u256::<FqEd254>(point.y),
+    )
+}
+
+/// The Schnorr verification key represented by an `EdOnBN254.EdOnBN254Point`.
+pub fn schnorr_key_from_sol(x: U256, y: U256) -> Result<StateVerKey> {
+    let point = EdwardsAffine::new_unchecked(u256_to_field(x), u256_to_field(y));
+    ensure!(
+        point.is_on_curve() && point.is_in_correct_subgroup_assuming_on_curve(),
+        "Schnorr key is not a valid EdOnBN254 point"
+    );
+    recast(&point)
+}
+
+/// The signature of `account` with `key`, which `StakeTable.register` requires to prove that the
+/// account registering a node controls its staking key.
+pub fn sign_account(key: &BLSPrivKey, account: Address) -> ParsedG1Point {
+    let key_pair = BLSKeyPair::generate_with_sign_key(key.clone());
+    let sig = key_pair.sign(&account.encode(), CS_ID_BLS_BN254);
+    sig.sigma.into_affine().into()
+}
+
+/// Convert a curve point to a verification key wrapping the same point.
+///
+/// The keys do not expose constructors from points, but they serialize exactly like the point they
+/// wrap.
+fn recast<T: CanonicalDeserialize>(point: &impl CanonicalSerialize) -> Result<T> {
+    let mut bytes = vec![];
+    point.serialize_compressed(&mut bytes)?;
+    T::deserialize_compressed(bytes.as_slice()).context("malformed verification key")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hotshot_types::{light_client::StateKeyPair, traits::signature_key::SignatureKey};
+
+    #[test]
+    fn test_key_round_trip() {
+        for i in 0..5 {
+            let (bls_key, _) = BLSPubKey::generated_from_seed_indexed([0; 32], i);
+            assert_eq!(bls_key_from_sol(bls_key_to_sol(&bls_key)).unwrap(), bls_key);
+
+            let schnorr_key = StateKeyPair::generate_from_seed_indexed([0; 32], i).ver_key();
+            let (x, y) = schnorr_key_to_sol(&schnorr_key);
+            assert_eq!(schnorr_key_from_sol(x, y).unwrap(), schnorr_key);
+        }
+
+        // Points off the curve are rejected.
+        schnorr_key_from_sol(1.into(), 1.into()).unwrap_err();
+    }
+}
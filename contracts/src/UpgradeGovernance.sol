@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: UNLICENSED
+
+pragma solidity ^0.8.0;
+
+import { Ownable } from "@openzeppelin/contracts/access/Ownable.sol";
+
+/// @notice Schedules upgrades of the Espresso sequencer chain config.
+/// @dev Sequencer nodes watch for `UpgradeScheduled` events in finalized L1 blocks and use the new
+/// chain config from the activation height on.
+contract UpgradeGovernance is Ownable {
+    // === Events ===
+    //
+    /// @notice Notify a new upgrade of the chain config, taking effect at `activationHeight`
+    event UpgradeScheduled(
+        uint64 activationHeight, uint256 chainId, uint64 maxBlockSize, uint256 baseFee
+    );
+
+    // === Errors ===
+    //
+    /// @notice error types
+    error ActivationHeightNotIncreasing();
+    error InvalidMaxBlockSize();
+
+    /// @notice activation height of the last scheduled upgrade
+    uint64 public lastActivationHeight;
+
+    constructor(address owner) Ownable(owner) { }
+
+    /// @notice Schedule an upgrade of the chain config at Espresso block `activationHeight`
+    /// @dev activation heights must increase, so an upgrade cannot be rescheduled or cancelled
+    function scheduleUpgrade(
+        uint64 activationHeight,
+        uint256 chainId,
+        uint64 maxBlockSize,
+        uint256 baseFee
+    ) external onlyOwner {
+        if (activationHeight <= lastActivationHeight) {
+            revert ActivationHeightNotIncreasing();
+        }
+        if (maxBlockSize == 0) {
+            revert InvalidMaxBlockSize();
+        }
+        lastActivationHeight = activationHeight;
+        emit UpgradeScheduled(activationHeight, chainId, maxBlockSize, baseFee);
+    }
+}
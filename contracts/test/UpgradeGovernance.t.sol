@@ -0,0 +1,52 @@
+// SPDX-License-Identifier: Unlicensed
+
+/* solhint-disable contract-name-camelcase, func-name-mixedcase, one-contract-per-file */
+
+pragma solidity ^0.8.0;
+
+// Libraries
+import { Test } from "forge-std/Test.sol";
+import { Ownable } from "@openzeppelin/contracts/access/Ownable.sol";
+
+// Target contract
+import { UpgradeGovernance } from "../src/UpgradeGovernance.sol";
+
+/// @title UpgradeGovernance Test
+contract UpgradeGovernanceTest is Test {
+    address public owner = makeAddr("owner");
+    UpgradeGovernance public governance;
+
+    function setUp() public {
+        governance = new UpgradeGovernance(owner);
+    }
+
+    function test_scheduleUpgrade() public {
+        vm.expectEmit(false, false, false, true);
+        emit UpgradeGovernance.UpgradeScheduled(100, 1, 20000, 2);
+
+        vm.prank(owner);
+        governance.scheduleUpgrade(100, 1, 20000, 2);
+        assertEq(governance.lastActivationHeight(), 100);
+    }
+
+    function test_revertWhen_notOwner() public {
+        address other = makeAddr("other");
+        vm.prank(other);
+        vm.expectRevert(abi.encodeWithSelector(Ownable.OwnableUnauthorizedAccount.selector, other));
+        governance.scheduleUpgrade(100, 1, 20000, 2);
+    }
+
+    function test_revertWhen_activationHeightNotIncreasing() public {
+        vm.startPrank(owner);
+        governance.scheduleUpgrade(100, 1, 20000, 2);
+        vm.expectRevert(UpgradeGovernance.ActivationHeightNotIncreasing.selector);
+        governance.scheduleUpgrade(100, 1, 30000, 2);
+        vm.stopPrank();
+    }
+
+    function test_revertWhen_maxBlockSizeZero() public {
+        vm.prank(owner);
+        vm.expectRevert(UpgradeGovernance.InvalidMaxBlockSize.selector);
+        governance.scheduleUpgrade(100, 1, 0, 2);
+    }
+}
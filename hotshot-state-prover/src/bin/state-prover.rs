@@ -6,9 +6,12 @@ use ethers::providers::{Http, Middleware, Provider};
 use ethers::signers::{coins_bip39::English, MnemonicBuilder, Signer};
 use ethers::types::Address;
 use hotshot_stake_table::config::STAKE_TABLE_CAPACITY;
-use hotshot_state_prover::service::{run_prover_once, run_prover_service, StateProverConfig};
+use hotshot_state_prover::{
+    memory::ProverProfile,
+    service::{run_prover_once, run_prover_service, StateProverConfig},
+};
 use snafu::Snafu;
-use std::{str::FromStr as _, time::Duration};
+use std::{path::PathBuf, str::FromStr as _, time::Duration};
 use url::Url;
 
 #[derive(Parser)]
@@ -71,6 +74,32 @@ struct Args {
     /// Stake table capacity for the prover circuit
     #[clap(short, long, env = "ESPRESSO_SEQUENCER_STAKE_TABLE_CAPACITY", default_value_t = STAKE_TABLE_CAPACITY)]
     pub stake_table_capacity: usize,
+
+    /// Number of threads used to generate each proof.
+    ///
+    /// Defaults to the number of cores. Lowering this leaves cores free for other processes, at
+    /// the cost of slower proofs.
+    #[clap(long, env = "ESPRESSO_STATE_PROVER_PROOF_THREADS")]
+    pub proof_threads: Option<usize>,
+
+    /// How the prover trades proving time for memory.
+    ///
+    /// The low-memory profile generates proofs with 2 threads unless --proof-threads is set. To run
+    /// on machines with little memory, combine it with --proving-key-path.
+    #[clap(
+        long,
+        value_enum,
+        env = "ESPRESSO_STATE_PROVER_PROFILE",
+        default_value = "default"
+    )]
+    pub profile: ProverProfile,
+
+    /// File to load the proving key from, or to save it to after generating it.
+    ///
+    /// Loading a saved key skips loading the SRS and preprocessing the circuit, which need the most
+    /// memory. The key can be generated once on a larger machine and copied here.
+    #[clap(long, env = "ESPRESSO_STATE_PROVER_PROVING_KEY_PATH")]
+    pub proving_key_path: Option<PathBuf>,
 }
 
 #[derive(Clone, Debug, Snafu)]
@@ -113,7 +142,16 @@ async fn main() {
         orchestrator_url: args.orchestrator_url,
         port: args.port,
         stake_table_capacity: args.stake_table_capacity,
+        proof_threads: args.proof_threads,
+        profile: args.profile,
+        proving_key_path: args.proving_key_path,
     };
+    if config.profile == ProverProfile::LowMemory && config.proving_key_path.is_none() {
+        tracing::warn!(
+            "Using the low-memory profile without a proving key path. The proving key will be \
+                generated on every start, which needs the most memory."
+        );
+    }
 
     if args.daemon {
         // Launching the prover service daemon
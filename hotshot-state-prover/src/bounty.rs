@@ -0,0 +1,158 @@
+//! Running the prover alongside other provers, and what it costs.
+//!
+//! Unless the light client contract is in permissioned prover mode, anyone may submit a state
+//! update, so several provers can run at once for liveness. They race to prove the same states, and
+//! only the first update for each state is accepted; the others revert as outdated, possibly after
+//! paying for gas. Each prover tracks how often it won and lost, and the gas it spent against the
+//! rewards it received, so operators can tell what redundancy costs them.
+
+use ethers::types::{Address, U256};
+use serde::{Deserialize, Serialize};
+
+/// Who the light client contract accepts state updates from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProverMode {
+    /// Only `prover` may submit state updates.
+    Permissioned { prover: Address },
+    /// Anyone may submit state updates.
+    Permissionless,
+}
+
+impl ProverMode {
+    /// The mode described by the `permissionedProverEnabled` and `permissionedProver` fields of the
+    /// contract.
+    pub fn new(permissioned: bool, prover: Address) -> Self {
+        if permissioned {
+            Self::Permissioned { prover }
+        } else {
+            Self::Permissionless
+        }
+    }
+
+    /// Whether the contract accepts state updates from `sender`.
+    pub fn allows(&self, sender: Address) -> bool {
+        match self {
+            Self::Permissioned { prover } => *prover == sender,
+            Self::Permissionless => true,
+        }
+    }
+}
+
+/// How the latest attempt to update the light client turned out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncOutcome {
+    /// The light client already had the latest state.
+    UpToDate,
+    /// This prover updated the light client to `block_height`.
+    Updated { block_height: u64 },
+    /// Another prover got the state at `block_height` onto L1 before this one could.
+    Preempted { block_height: u64 },
+    /// The contract only accepts state updates from a different, permissioned prover, so this one
+    /// is standing by.
+    NotPermitted,
+}
+
+/// Gas spent by this prover against the rewards it received, in wei.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BountyLedger {
+    /// State updates this prover got onto L1.
+    pub updates: u64,
+    /// State updates proved by this prover which another prover got onto L1 first.
+    pub preempted: u64,
+    /// Gas paid for by every state update transaction, including those which reverted.
+    pub gas_spent: U256,
+    /// Rewards received for state updates.
+    pub rewards: U256,
+}
+
+impl BountyLedger {
+    /// Record a state update transaction which used `gas_used` gas at `gas_price`, whether or not
+    /// it succeeded. Returns the cost of the transaction.
+    pub fn record_gas(&mut self, gas_used: U256, gas_price: U256) -> U256 {
+        let cost = gas_used.saturating_mul(gas_price);
+        self.gas_spent = self.gas_spent.saturating_add(cost);
+        cost
+    }
+
+    /// Record a state update this prover got onto L1, with a transaction which cost `cost`.
+    ///
+    /// Any reward is read from the balance of the prover's account, as whatever it grew by beyond
+    /// the cost of the transaction between `balances` before and after. This assumes the account is
+    /// used only by this prover. Returns the reward.
+    pub fn record_update(&mut self, cost: U256, balances: Option<(U256, U256)>) -> U256 {
+        self.updates += 1;
+        let reward = match balances {
+            Some((before, after)) => after.saturating_add(cost).saturating_sub(before),
+            None => U256::zero(),
+        };
+        self.rewards = self.rewards.saturating_add(reward);
+        reward
+    }
+
+    /// Record a state update proved by this prover which another prover got onto L1 first.
+    pub fn record_preempted(&mut self) {
+        self.preempted += 1;
+    }
+
+    /// How much more gas this prover has paid for than it received in rewards.
+    pub fn deficit(&self) -> U256 {
+        self.gas_spent.saturating_sub(self.rewards)
+    }
+}
+
+/// The status of the prover, as reported by its API.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProverStatus {
+    /// The account this prover submits state updates from.
+    pub prover: Address,
+    /// The prover mode of the contract, when it was last checked.
+    pub mode: Option<ProverMode>,
+    pub last_outcome: Option<SyncOutcome>,
+    pub ledger: BountyLedger,
+    /// How much more gas this prover has paid for than it received in rewards.
+    pub deficit: U256,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prover_mode() {
+        let prover = Address::repeat_byte(1);
+        assert!(ProverMode::new(false, Address::zero()).allows(prover));
+        assert!(ProverMode::new(true, prover).allows(prover));
+        assert!(!ProverMode::new(true, prover).allows(Address::repeat_byte(2)));
+        // A permissioned contract without a prover accepts no one.
+        assert!(!ProverMode::new(true, Address::zero()).allows(prover));
+    }
+
+    #[test]
+    fn test_bounty_ledger() {
+        let mut ledger = BountyLedger::default();
+
+        // An update without a reward only costs gas.
+        let cost = ledger.record_gas(100.into(), 2.into());
+        assert_eq!(cost, 200.into());
+        let reward = ledger.record_update(cost, Some((1000.into(), 800.into())));
+        assert_eq!(reward, 0.into());
+        assert_eq!(ledger.deficit(), 200.into());
+
+        // An update which pays the prover more than its gas.
+        let cost = ledger.record_gas(100.into(), 2.into());
+        let reward = ledger.record_update(cost, Some((800.into(), 1100.into())));
+        assert_eq!(reward, 500.into());
+        assert_eq!(ledger.gas_spent, 400.into());
+        assert_eq!(ledger.rewards, 500.into());
+        assert_eq!(ledger.deficit(), 0.into());
+
+        // Losing a race after the transaction is mined still costs gas.
+        ledger.record_gas(50.into(), 2.into());
+        ledger.record_preempted();
+        ledger.record_preempted();
+        assert_eq!(ledger.updates, 2);
+        assert_eq!(ledger.preempted, 2);
+        assert_eq!(ledger.gas_spent, 500.into());
+        assert_eq!(ledger.rewards, 500.into());
+    }
+}
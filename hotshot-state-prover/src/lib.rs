@@ -1,7 +1,13 @@
 //! SNARK-assisted `HotShot` light client state update verification
 
+/// Permissionless proving and its gas and reward accounting
+pub mod bounty;
 /// State verifier circuit builder
 pub mod circuit;
+/// Memory requirements and the low-memory profile of the prover
+pub mod memory;
+/// Prometheus metrics of the prover service
+pub mod metrics;
 /// Utilities for test
 pub mod mock_ledger;
 /// Prover service related functionalities
@@ -0,0 +1,169 @@
+//! Memory requirements of the prover, and a profile for machines with little memory.
+//!
+//! The most memory the prover ever needs is while generating the proving key, when the SRS, the
+//! circuit and the key are all in memory at once. Loading a proving key saved by an earlier run
+//! avoids this, since the key is streamed from the file without loading the SRS at all. The
+//! low-memory profile also generates proofs with fewer threads, each of which holds its own scratch
+//! space.
+
+use ark_bn254::{Fr, G1Affine};
+use clap::ValueEnum;
+use std::{fmt::Display, fs, mem::size_of};
+
+/// Number of threads generating each proof in the low-memory profile, unless configured.
+pub const LOW_MEMORY_PROOF_THREADS: usize = 2;
+
+/// Number of polynomials in the proving key: the selectors and permutation polynomials.
+const PROVING_KEY_POLYS: u64 = 18;
+
+/// Number of polynomials the prover evaluates over the quotient domain while generating a proof:
+/// the wires, selectors and permutation polynomials.
+const PROOF_POLYS: u64 = 24;
+
+/// Size of the quotient domain relative to the evaluation domain.
+const QUOTIENT_DOMAIN_FACTOR: u64 = 5;
+
+/// Approximate memory used by each gate of the circuit while it is being built.
+const BYTES_PER_GATE: u64 = 256;
+
+/// How the prover trades proving time for memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProverProfile {
+    /// Generate proofs as fast as possible, with a thread for each core.
+    #[default]
+    Default,
+    /// Generate proofs with few threads, to bound the scratch space they hold at once.
+    LowMemory,
+}
+
+impl ProverProfile {
+    /// Number of threads to generate each proof with, if not configured explicitly.
+    pub fn proof_threads(&self) -> Option<usize> {
+        match self {
+            Self::Default => None,
+            Self::LowMemory => Some(LOW_MEMORY_PROOF_THREADS),
+        }
+    }
+}
+
+/// Estimated memory needed by the prover, in bytes.
+///
+/// The estimates count the large data structures of the prover, and are only accurate to within a
+/// small factor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    /// Memory needed to generate the proving key from the SRS.
+    pub key_generation: u64,
+    /// Memory needed to hold the proving key and generate proofs with it.
+    pub proving: u64,
+}
+
+impl MemoryEstimate {
+    /// Estimate the memory needed for a circuit with `num_gates` gates.
+    pub fn new(num_gates: usize) -> Self {
+        let num_gates = num_gates as u64;
+        let domain_size = num_gates.next_power_of_two();
+        let field = size_of::<Fr>() as u64;
+        // The SRS, and the commit key in the proving key, hold a point for each gate.
+        let srs = (num_gates + 3) * size_of::<G1Affine>() as u64;
+        let proving_key = srs + PROVING_KEY_POLYS * domain_size * field;
+        let proof = PROOF_POLYS * QUOTIENT_DOMAIN_FACTOR * domain_size * field;
+        Self {
+            key_generation: srs + num_gates * BYTES_PER_GATE + proving_key,
+            proving: proving_key + proof,
+        }
+    }
+
+    /// The most memory needed at once, depending on whether the proving key is generated.
+    pub fn peak(&self, generate_key: bool) -> u64 {
+        if generate_key {
+            self.key_generation.max(self.proving)
+        } else {
+            self.proving
+        }
+    }
+}
+
+/// Memory available to this process, in bytes, if it can be determined.
+///
+/// This is the memory the kernel reports as available, further limited by the memory limit of the
+/// cgroup of the process, if any, as in a container.
+pub fn available_memory() -> Option<u64> {
+    let available = fs::read_to_string("/proc/meminfo")
+        .ok()
+        .and_then(|meminfo| parse_meminfo(&meminfo));
+    let limit = fs::read_to_string("/sys/fs/cgroup/memory.max")
+        .ok()
+        .and_then(|max| max.trim().parse::<u64>().ok());
+    match (available, limit) {
+        (Some(available), Some(limit)) => Some(available.min(limit)),
+        (available, limit) => available.or(limit),
+    }
+}
+
+/// The available memory in bytes, from the contents of `/proc/meminfo`.
+fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kb * 1024)
+}
+
+/// Log the memory the prover needs and the memory available, warning if it may not fit.
+pub fn report_memory(num_gates: usize, generate_key: bool) {
+    let needed = MemoryEstimate::new(num_gates).peak(generate_key);
+    match available_memory() {
+        Some(available) if available < needed => tracing::warn!(
+            needed = %Gib(needed),
+            available = %Gib(available),
+            generate_key,
+            "The prover may need more memory than is available. Use the low-memory profile with a \
+                saved proving key to reduce the memory needed."
+        ),
+        Some(available) => tracing::info!(
+            needed = %Gib(needed),
+            available = %Gib(available),
+            generate_key,
+            "Estimated prover memory."
+        ),
+        None => tracing::info!(
+            needed = %Gib(needed),
+            generate_key,
+            "Estimated prover memory. Unable to determine available memory."
+        ),
+    }
+}
+
+/// A number of bytes, displayed in GiB.
+struct Gib(u64);
+
+impl Display for Gib {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.2} GiB", self.0 as f64 / (1u64 << 30) as f64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_meminfo() {
+        let meminfo = "MemTotal:       16318480 kB\n\
+                       MemFree:         1034452 kB\n\
+                       MemAvailable:    9254020 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some(9254020 * 1024));
+        assert_eq!(parse_meminfo("MemTotal:       16318480 kB\n"), None);
+    }
+
+    #[test]
+    fn test_memory_estimate() {
+        let small = MemoryEstimate::new(1 << 10);
+        let large = MemoryEstimate::new(1 << 16);
+        assert!(large.proving > small.proving);
+        assert!(large.key_generation > small.key_generation);
+        // Loading a saved proving key never needs more memory than generating one.
+        assert!(large.peak(false) <= large.peak(true));
+    }
+}
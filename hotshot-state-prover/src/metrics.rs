@@ -0,0 +1,320 @@
+//! Metrics of the prover service, exported in the Prometheus text format.
+
+use crate::bounty::{BountyLedger, ProverMode, ProverStatus, SyncOutcome};
+use ethers::types::{Address, U256};
+use std::{
+    fmt::{self, Write},
+    fs,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tide_disco::metrics::Metrics;
+
+/// Counters and gauges describing the progress of the prover service.
+///
+/// Operators should alert on `prover_consecutive_failures` growing, or on
+/// `prover_last_success_timestamp_seconds` falling behind, either of which means the light client
+/// has stopped advancing.
+#[derive(Clone, Debug, Default)]
+pub struct ProverMetrics {
+    proofs_generated: u64,
+    proof_generation_seconds_sum: f64,
+    last_proof_generation_seconds: Option<f64>,
+    resident_memory_bytes: Option<u64>,
+    peak_memory_bytes: Option<u64>,
+    signatures_collected: Option<u64>,
+    signature_weight: Option<U256>,
+    signature_threshold: Option<U256>,
+    submissions: u64,
+    last_gas_used: Option<U256>,
+    gas_used_total: U256,
+    light_client_block_height: Option<u64>,
+    last_success: Option<u64>,
+    consecutive_failures: u64,
+    failures: u64,
+    mode: Option<ProverMode>,
+    last_outcome: Option<SyncOutcome>,
+    ledger: BountyLedger,
+}
+
+impl ProverMetrics {
+    /// Record the valid signatures collected for a state update, and their weight against the
+    /// threshold the update needs.
+    pub fn record_signatures(&mut self, collected: usize, weight: U256, threshold: U256) {
+        self.signatures_collected = Some(collected as u64);
+        self.signature_weight = Some(weight);
+        self.signature_threshold = Some(threshold);
+    }
+
+    /// Record the generation of a proof which took `elapsed`, along with the memory used by the
+    /// process afterwards.
+    pub fn record_proof(&mut self, elapsed: Duration) {
+        self.proofs_generated += 1;
+        self.proof_generation_seconds_sum += elapsed.as_secs_f64();
+        self.last_proof_generation_seconds = Some(elapsed.as_secs_f64());
+        if let Some((resident, peak)) = memory_usage() {
+            self.resident_memory_bytes = Some(resident);
+            self.peak_memory_bytes = Some(peak);
+        }
+    }
+
+    /// Record the submission of a state update to L1, which used `gas_used`, if known.
+    pub fn record_submission(&mut self, gas_used: Option<U256>) {
+        self.submissions += 1;
+        self.last_gas_used = gas_used;
+        self.gas_used_total += gas_used.unwrap_or_default();
+    }
+
+    /// Record the block height of the state in the light client contract.
+    pub fn record_block_height(&mut self, height: u64) {
+        self.light_client_block_height = Some(height);
+    }
+
+    /// Record a successful sync of the light client, resetting the consecutive failures.
+    pub fn record_success(&mut self) {
+        self.last_success = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|now| now.as_secs());
+        self.consecutive_failures = 0;
+    }
+
+    /// Record a failed attempt to sync the light client.
+    pub fn record_failure(&mut self) {
+        self.consecutive_failures += 1;
+        self.failures += 1;
+    }
+
+    /// Record the prover mode of the light client contract.
+    pub fn record_mode(&mut self, mode: ProverMode) {
+        self.mode = Some(mode);
+    }
+
+    /// Record how an attempt to update the light client turned out.
+    pub fn record_outcome(&mut self, outcome: SyncOutcome) {
+        self.last_outcome = Some(outcome);
+    }
+
+    /// The gas spent and rewards received by this prover.
+    pub fn ledger_mut(&mut self) -> &mut BountyLedger {
+        &mut self.ledger
+    }
+
+    /// The status of a prover submitting state updates from `prover`.
+    pub fn status(&self, prover: Address) -> ProverStatus {
+        ProverStatus {
+            prover,
+            mode: self.mode,
+            last_outcome: self.last_outcome,
+            ledger: self.ledger.clone(),
+            deficit: self.ledger.deficit(),
+        }
+    }
+
+    /// The consecutive failures since the last successful sync.
+    pub fn consecutive_failures(&self) -> u64 {
+        self.consecutive_failures
+    }
+
+    fn write(&self, w: &mut impl Write) -> fmt::Result {
+        let mut metric = |name: &str, kind: &str, help: &str, value: Option<String>| {
+            let Some(value) = value else {
+                return Ok(());
+            };
+            writeln!(w, "# HELP {name} {help}")?;
+            writeln!(w, "# TYPE {name} {kind}")?;
+            writeln!(w, "{name} {value}")
+        };
+        metric(
+            "prover_proofs_generated_total",
+            "counter",
+            "Number of state update proofs generated.",
+            Some(self.proofs_generated.to_string()),
+        )?;
+        metric(
+            "prover_proof_generation_seconds_total",
+            "counter",
+            "Total time spent generating state update proofs.",
+            Some(self.proof_generation_seconds_sum.to_string()),
+        )?;
+        metric(
+            "prover_last_proof_generation_seconds",
+            "gauge",
+            "Time spent generating the latest state update proof.",
+            self.last_proof_generation_seconds.map(|s| s.to_string()),
+        )?;
+        metric(
+            "prover_resident_memory_bytes",
+            "gauge",
+            "Resident memory of the prover after generating the latest proof.",
+            self.resident_memory_bytes.map(|b| b.to_string()),
+        )?;
+        metric(
+            "prover_peak_memory_bytes",
+            "gauge",
+            "Peak resident memory of the prover.",
+            self.peak_memory_bytes.map(|b| b.to_string()),
+        )?;
+        metric(
+            "prover_signatures_collected",
+            "gauge",
+            "Valid signatures collected for the latest state update.",
+            self.signatures_collected.map(|n| n.to_string()),
+        )?;
+        metric(
+            "prover_signature_weight",
+            "gauge",
+            "Stake of the signers of the latest state update.",
+            self.signature_weight.map(|w| w.to_string()),
+        )?;
+        metric(
+            "prover_signature_threshold",
+            "gauge",
+            "Stake needed to sign a state update.",
+            self.signature_threshold.map(|t| t.to_string()),
+        )?;
+        metric(
+            "prover_l1_submissions_total",
+            "counter",
+            "Number of state updates submitted to L1.",
+            Some(self.submissions.to_string()),
+        )?;
+        metric(
+            "prover_l1_last_gas_used",
+            "gauge",
+            "Gas used by the latest state update submitted to L1.",
+            self.last_gas_used.map(|g| g.to_string()),
+        )?;
+        metric(
+            "prover_l1_gas_used_total",
+            "counter",
+            "Gas used by all state updates submitted to L1.",
+            Some(self.gas_used_total.to_string()),
+        )?;
+        metric(
+            "prover_updates_total",
+            "counter",
+            "State updates this prover got onto L1.",
+            Some(self.ledger.updates.to_string()),
+        )?;
+        metric(
+            "prover_updates_preempted_total",
+            "counter",
+            "State updates proved by this prover which another prover got onto L1 first.",
+            Some(self.ledger.preempted.to_string()),
+        )?;
+        metric(
+            "prover_l1_gas_spent_wei_total",
+            "counter",
+            "Wei paid for gas by all state update transactions, including reverted ones.",
+            Some(self.ledger.gas_spent.to_string()),
+        )?;
+        metric(
+            "prover_rewards_wei_total",
+            "counter",
+            "Wei received as rewards for state updates.",
+            Some(self.ledger.rewards.to_string()),
+        )?;
+        metric(
+            "prover_permissioned",
+            "gauge",
+            "Whether the light client contract only accepts state updates from one prover.",
+            self.mode
+                .map(|mode| u8::from(matches!(mode, ProverMode::Permissioned { .. })).to_string()),
+        )?;
+        metric(
+            "prover_light_client_block_height",
+            "gauge",
+            "Block height of the state in the light client contract.",
+            self.light_client_block_height.map(|h| h.to_string()),
+        )?;
+        metric(
+            "prover_last_success_timestamp_seconds",
+            "gauge",
+            "Unix time of the latest successful sync of the light client.",
+            self.last_success.map(|t| t.to_string()),
+        )?;
+        metric(
+            "prover_consecutive_failures",
+            "gauge",
+            "Failed attempts to sync the light client since the last success.",
+            Some(self.consecutive_failures.to_string()),
+        )?;
+        metric(
+            "prover_failures_total",
+            "counter",
+            "Failed attempts to sync the light client.",
+            Some(self.failures.to_string()),
+        )
+    }
+}
+
+impl Metrics for ProverMetrics {
+    type Error = fmt::Error;
+
+    fn export(&self) -> Result<String, Self::Error> {
+        let mut out = String::new();
+        self.write(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// The current and peak resident memory of this process, in bytes, if they can be read.
+fn memory_usage() -> Option<(u64, u64)> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let field = |name: &str| {
+        let line = status.lines().find(|line| line.starts_with(name))?;
+        let kb: u64 = line[name.len()..]
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .ok()?;
+        Some(kb * 1024)
+    };
+    Some((field("VmRSS:")?, field("VmHWM:")?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_export_metrics() {
+        let mut metrics = ProverMetrics::default();
+        let exported = metrics.export().unwrap();
+        assert!(exported.contains("prover_consecutive_failures 0\n"));
+        assert!(!exported.contains("prover_signature_threshold"));
+
+        metrics.record_signatures(3, 30.into(), 20.into());
+        metrics.record_proof(Duration::from_millis(1500));
+        metrics.record_submission(Some(200_000.into()));
+        metrics.record_failure();
+        metrics.record_failure();
+        assert_eq!(metrics.consecutive_failures(), 2);
+        let exported = metrics.export().unwrap();
+        assert!(exported.contains("prover_signatures_collected 3\n"));
+        assert!(exported.contains("prover_signature_threshold 20\n"));
+        assert!(exported.contains("prover_last_proof_generation_seconds 1.5\n"));
+        assert!(exported.contains("prover_l1_gas_used_total 200000\n"));
+        assert!(exported.contains("prover_consecutive_failures 2\n"));
+        assert!(exported.contains("# TYPE prover_failures_total counter\n"));
+
+        metrics.record_success();
+        assert_eq!(metrics.consecutive_failures(), 0);
+        let exported = metrics.export().unwrap();
+        assert!(exported.contains("prover_last_success_timestamp_seconds"));
+        assert!(exported.contains("prover_failures_total 2\n"));
+        assert!(!exported.contains("prover_permissioned"));
+
+        metrics.record_mode(ProverMode::Permissionless);
+        metrics.ledger_mut().record_gas(100.into(), 3.into());
+        metrics.ledger_mut().record_preempted();
+        let exported = metrics.export().unwrap();
+        assert!(exported.contains("prover_permissioned 0\n"));
+        assert!(exported.contains("prover_updates_preempted_total 1\n"));
+        assert!(exported.contains("prover_l1_gas_spent_wei_total 300\n"));
+        let status = metrics.status(Address::zero());
+        assert_eq!(status.mode, Some(ProverMode::Permissionless));
+        assert_eq!(status.deficit, 300.into());
+    }
+}
@@ -1,10 +1,16 @@
 //! A light client prover service
 
-use crate::snark::{generate_state_update_proof, Proof, ProvingKey};
-use anyhow::anyhow;
+use crate::{
+    bounty::{ProverMode, SyncOutcome},
+    memory::{report_memory, ProverProfile},
+    metrics::ProverMetrics,
+    snark::{generate_state_update_proof, Proof, ProvingKey},
+};
+use anyhow::{anyhow, ensure};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use async_std::{
     io,
-    sync::Arc,
+    sync::{Arc, RwLock},
     task::{sleep, spawn},
 };
 use contract_bindings::light_client::{LightClient, LightClientErrors};
@@ -15,7 +21,7 @@ use ethers::{
     providers::Http,
     providers::{Middleware, Provider, ProviderError},
     signers::{LocalWallet, Signer, Wallet},
-    types::{Address, U256},
+    types::{Address, TransactionReceipt, U256},
 };
 use futures::FutureExt;
 use hotshot_contract_adapter::jellyfish::{u256_to_field, ParsedPlonkProof};
@@ -37,8 +43,14 @@ use jf_plonk::errors::PlonkError;
 use jf_primitives::constants::CS_ID_SCHNORR;
 use jf_primitives::pcs::prelude::UnivariateUniversalParams;
 use jf_relation::Circuit as _;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use sequencer_utils::{contract_send, state_relay::QuorumProgress, ContractSendError};
 use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
     iter,
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 use surf_disco::Client;
@@ -78,6 +90,21 @@ pub struct StateProverConfig {
     pub port: Option<u16>,
     /// Stake table capacity for the prover circuit.
     pub stake_table_capacity: usize,
+    /// Number of threads used to generate each proof.
+    ///
+    /// If not provided, the number of threads is chosen by `profile`.
+    pub proof_threads: Option<usize>,
+    /// How the prover trades proving time for memory.
+    pub profile: ProverProfile,
+    /// File to load the proving key from, or to save it to after generating it.
+    pub proving_key_path: Option<PathBuf>,
+}
+
+impl StateProverConfig {
+    /// Number of threads used to generate each proof, or [`None`] to use all available cores.
+    pub fn proof_threads(&self) -> Option<usize> {
+        self.proof_threads.or(self.profile.proof_threads())
+    }
 }
 
 pub fn init_stake_table(
@@ -112,27 +139,7 @@ async fn init_stake_table_from_orchestrator(
                     .send()
                     .await
                 {
-                    Ok(config) => {
-                        let mut st = StakeTable::<BLSPubKey, StateVerKey, CircuitField>::new(
-                            stake_table_capacity,
-                        );
-                        tracing::debug!("{}", config.config.known_nodes_with_stake.len());
-                        config
-                            .config
-                            .known_nodes_with_stake
-                            .into_iter()
-                            .for_each(|config| {
-                                st.register(
-                                    *config.stake_table_entry.get_key(),
-                                    config.stake_table_entry.get_stake(),
-                                    config.state_ver_key,
-                                )
-                                .expect("Key registration shouldn't fail.");
-                            });
-                        st.advance();
-                        st.advance();
-                        return st;
-                    }
+                    Ok(config) => return stake_table_from_config(config, stake_table_capacity),
                     Err(e) => {
                         tracing::warn!("Orchestrator error: {e}, retrying.");
                     }
@@ -149,11 +156,74 @@ async fn init_stake_table_from_orchestrator(
     }
 }
 
+fn stake_table_from_config(
+    config: NetworkConfig,
+    stake_table_capacity: usize,
+) -> StakeTable<BLSPubKey, StateVerKey, CircuitField> {
+    let mut st = StakeTable::<BLSPubKey, StateVerKey, CircuitField>::new(stake_table_capacity);
+    tracing::debug!("{}", config.config.known_nodes_with_stake.len());
+    config
+        .config
+        .known_nodes_with_stake
+        .into_iter()
+        .for_each(|config| {
+            st.register(
+                *config.stake_table_entry.get_key(),
+                config.stake_table_entry.get_stake(),
+                config.state_ver_key,
+            )
+            .expect("Key registration shouldn't fail.");
+        });
+    st.advance();
+    st.advance();
+    st
+}
+
 pub async fn light_client_genesis(
     orchestrator_url: &Url,
     stake_table_capacity: usize,
 ) -> anyhow::Result<ParsedLightClientState> {
     let st = init_stake_table_from_orchestrator(orchestrator_url, stake_table_capacity).await;
+    genesis_from_stake_table(&st)
+}
+
+/// Fetch the genesis light client state of a running sequencer network from its orchestrator.
+///
+/// Unlike [`light_client_genesis`], which waits until every peer has registered, this fails right
+/// away if the orchestrator cannot be reached or the peers are not ready yet. It also fails if the
+/// stake table is empty, since no state update could ever be verified against it.
+pub async fn fetch_genesis_state(
+    orchestrator_url: Url,
+    stake_table_capacity: usize,
+) -> anyhow::Result<ParsedLightClientState> {
+    let client = Client::<ServerError, OrchestratorVersion>::new(orchestrator_url.clone());
+    let ready = client
+        .get::<bool>("api/peer_pub_ready")
+        .send()
+        .await
+        .map_err(|err| anyhow!("error contacting orchestrator at {orchestrator_url}: {err}"))?;
+    ensure!(
+        ready,
+        "peers have not registered with the orchestrator at {orchestrator_url} yet"
+    );
+    let config = client
+        .get::<NetworkConfig>("api/get_config_after_peer_collected")
+        .send()
+        .await
+        .map_err(|err| {
+            anyhow!("error fetching config from orchestrator at {orchestrator_url}: {err}")
+        })?;
+    ensure!(
+        !config.config.known_nodes_with_stake.is_empty(),
+        "the stake table of the network at {orchestrator_url} is empty"
+    );
+    genesis_from_stake_table(&stake_table_from_config(config, stake_table_capacity))
+}
+
+/// The genesis light client state for a network with the stake table `st`.
+pub fn genesis_from_stake_table(
+    st: &StakeTable<BLSPubKey, StateVerKey, CircuitField>,
+) -> anyhow::Result<ParsedLightClientState> {
     let (bls_comm, schnorr_comm, stake_comm) = st
         .commitment(SnapshotVersion::LastEpochStart)
         .expect("Commitment computation shouldn't fail.");
@@ -173,15 +243,19 @@ pub async fn light_client_genesis(
     Ok(pi.into())
 }
 
+/// Number of gates in the state update circuit.
+fn num_gates(stake_table_capacity: usize) -> usize {
+    crate::circuit::build_for_preprocessing::<CircuitField, ark_ed_on_bn254::EdwardsConfig>(
+        stake_table_capacity,
+    )
+    .unwrap()
+    .0
+    .num_gates()
+}
+
 pub fn load_proving_key(stake_table_capacity: usize) -> ProvingKey {
     let srs = {
-        let num_gates = crate::circuit::build_for_preprocessing::<
-            CircuitField,
-            ark_ed_on_bn254::EdwardsConfig,
-        >(stake_table_capacity)
-        .unwrap()
-        .0
-        .num_gates();
+        let num_gates = num_gates(stake_table_capacity);
 
         std::println!("Loading SRS from Aztec's ceremony...");
         let srs_timer = Instant::now();
@@ -208,6 +282,71 @@ pub fn load_proving_key(stake_table_capacity: usize) -> ProvingKey {
     pk
 }
 
+/// Load the proving key saved at `path`, or generate it and save it there.
+///
+/// Loading a saved key skips the SRS and preprocessing, which take the most memory the prover ever
+/// needs. A saved key for a different stake table capacity, or one which fails to load, is replaced.
+pub fn load_or_generate_proving_key(
+    stake_table_capacity: usize,
+    path: Option<&Path>,
+) -> ProvingKey {
+    let saved = path.filter(|path| path.exists());
+    report_memory(num_gates(stake_table_capacity), saved.is_none());
+
+    if let Some(path) = saved {
+        match read_proving_key(path, stake_table_capacity) {
+            Ok(pk) => return pk,
+            Err(err) => {
+                tracing::warn!("Cannot load proving key from {}: {err:#}", path.display())
+            }
+        }
+    }
+    let pk = load_proving_key(stake_table_capacity);
+    if let Some(path) = path {
+        match write_proving_key(path, stake_table_capacity, &pk) {
+            Ok(()) => tracing::info!("Saved proving key to {}", path.display()),
+            Err(err) => tracing::warn!("Cannot save proving key to {}: {err:#}", path.display()),
+        }
+    }
+    pk
+}
+
+/// Read a proving key saved by [`write_proving_key`], streaming it from the file.
+fn read_proving_key(path: &Path, stake_table_capacity: usize) -> anyhow::Result<ProvingKey> {
+    tracing::info!("Loading proving key from {}", path.display());
+    let timer = Instant::now();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut capacity = [0; 8];
+    reader.read_exact(&mut capacity)?;
+    let capacity = u64::from_le_bytes(capacity);
+    ensure!(
+        capacity == stake_table_capacity as u64,
+        "proving key is for stake table capacity {capacity}, expected {stake_table_capacity}"
+    );
+    // The key was checked when it was generated, so skip the expensive curve point validation.
+    let pk = ProvingKey::deserialize_uncompressed_unchecked(reader)?;
+    let elapsed = Instant::now().signed_duration_since(timer);
+    tracing::info!("Loaded proving key in {elapsed:.3}");
+    Ok(pk)
+}
+
+/// Save a proving key, prefixed by the stake table capacity it was generated for.
+fn write_proving_key(
+    path: &Path,
+    stake_table_capacity: usize,
+    pk: &ProvingKey,
+) -> anyhow::Result<()> {
+    // Write to a temporary file first, so a partially written key is never loaded.
+    let tmp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    writer.write_all(&(stake_table_capacity as u64).to_le_bytes())?;
+    pk.serialize_uncompressed(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
 pub async fn fetch_latest_state<Ver: StaticVersionType>(
     client: &Client<ServerError, Ver>,
 ) -> Result<StateSignaturesBundle, ServerError> {
@@ -218,6 +357,13 @@ pub async fn fetch_latest_state<Ver: StaticVersionType>(
         .await
 }
 
+/// Get the progress of the relay server towards a quorum of signatures on newer states.
+pub async fn fetch_quorum_progress<Ver: StaticVersionType>(
+    client: &Client<ServerError, Ver>,
+) -> Result<QuorumProgress, ServerError> {
+    client.get::<QuorumProgress>("/api/progress").send().await
+}
+
 /// prepare a contract interface ready to be read from or written to
 async fn prepare_contract(
     config: &StateProverConfig,
@@ -249,49 +395,150 @@ pub async fn read_contract_state(
     Ok(state)
 }
 
+/// get whether the LightClient contract only accepts state updates from a permissioned prover
+pub async fn read_prover_mode(contract: &LightClient<L1Wallet>) -> Result<ProverMode, ProverError> {
+    let permissioned = contract
+        .permissioned_prover_enabled()
+        .call()
+        .await
+        .map_err(|err| ProverError::ContractError(err.into()))?;
+    let prover = contract
+        .permissioned_prover()
+        .call()
+        .await
+        .map_err(|err| ProverError::ContractError(err.into()))?;
+    Ok(ProverMode::new(permissioned, prover))
+}
+
+/// The outcome of submitting a state update to the L1 LightClient contract.
+#[derive(Clone, Debug)]
+pub enum Submission {
+    /// The state update was included on L1.
+    Accepted(TransactionReceipt),
+    /// The contract already had the state, or a newer one, from another prover.
+    ///
+    /// This carries the receipt if the transaction was mined before it reverted, and so paid for
+    /// gas.
+    Preempted(Option<TransactionReceipt>),
+    /// The transaction was mined, but reverted for another reason.
+    Reverted(TransactionReceipt),
+}
+
 /// submit the latest finalized state along with a proof to the L1 LightClient contract
 pub async fn submit_state_and_proof(
     proof: Proof,
     public_input: PublicInput,
     config: &StateProverConfig,
-) -> Result<(), ProverError> {
+) -> Result<Submission, ProverError> {
     let contract = prepare_contract(config).await?;
 
     // prepare the input the contract call and the tx itself
     let proof: ParsedPlonkProof = proof.into();
     let new_state: ParsedLightClientState = public_input.into();
+    let block_height = new_state.block_height;
     let tx = contract.new_finalized_state(new_state.into(), proof.into());
 
     // send the tx
-    let (receipt, included_block) = sequencer_utils::contract_send::<_, _, LightClientErrors>(&tx)
+    let receipt = match contract_send::<_, _, LightClientErrors>(&tx).await {
+        Ok((receipt, block_number)) => {
+            tracing::info!(
+                "Submitted state and proof to L1: tx={:x} block={block_number}",
+                receipt.transaction_hash,
+            );
+            return Ok(Submission::Accepted(receipt));
+        }
+        Err(ContractSendError::Revert(LightClientErrors::OutdatedState(_))) => {
+            return Ok(Submission::Preempted(None));
+        }
+        Err(ContractSendError::Reverted(receipt)) => receipt,
+        Err(err) => return Err(ProverError::ContractError(err.into())),
+    };
+
+    // Another prover's update for the same state may have been mined first, after this one passed
+    // gas estimation.
+    let finalized: ParsedLightClientState = contract
+        .get_finalized_state()
+        .call()
         .await
-        .map_err(ProverError::ContractError)?;
+        .map_err(|err| ProverError::ContractError(err.into()))?
+        .into();
+    if finalized.block_height >= block_height {
+        Ok(Submission::Preempted(Some(receipt)))
+    } else {
+        Ok(Submission::Reverted(receipt))
+    }
+}
 
+/// Build the thread pool which generates proofs, with `threads` threads, or one per core.
+pub fn proof_thread_pool(threads: Option<usize>) -> anyhow::Result<ThreadPool> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or_default())
+        .thread_name(|i| format!("prover-{i}"))
+        .build()?;
     tracing::info!(
-        "Submitted state and proof to L1: tx={:x} block={included_block}",
-        receipt.transaction_hash,
+        "Generating proofs with {} threads",
+        pool.current_num_threads()
     );
-
-    Ok(())
+    Ok(pool)
 }
 
 pub async fn sync_state<Ver: StaticVersionType>(
     st: &StakeTable<BLSPubKey, StateVerKey, CircuitField>,
     proving_key: &ProvingKey,
+    proof_pool: &ThreadPool,
     relay_server_client: &Client<ServerError, Ver>,
     config: &StateProverConfig,
+    metrics: &RwLock<ProverMetrics>,
 ) -> Result<(), ProverError> {
     tracing::info!("Start syncing light client state.");
 
-    let bundle = fetch_latest_state(relay_server_client).await?;
+    let bundle = match fetch_latest_state(relay_server_client).await {
+        Ok(bundle) => bundle,
+        Err(err) => {
+            // Report how close the relay server is to a quorum, to tell a slow network from
+            // missing signers.
+            match fetch_quorum_progress(relay_server_client).await {
+                Ok(progress) => tracing::info!(
+                    threshold = %progress.threshold,
+                    pending = ?progress.pending,
+                    "No light client state has enough signatures yet."
+                ),
+                Err(err) => tracing::warn!("Error fetching quorum progress: {err}"),
+            }
+            return Err(err.into());
+        }
+    };
     tracing::info!("Latest HotShot block height: {}", bundle.state.block_height);
     let old_state = read_contract_state(config).await?;
     tracing::info!(
         "Current HotShot block height on contract: {}",
         old_state.block_height
     );
+    metrics
+        .write()
+        .await
+        .record_block_height(old_state.block_height as u64);
     if old_state.block_height >= bundle.state.block_height {
         tracing::info!("No update needed.");
+        metrics.write().await.record_outcome(SyncOutcome::UpToDate);
+        return Ok(());
+    }
+
+    // Check that the contract accepts updates from this prover before spending time on a proof.
+    let contract = prepare_contract(config).await?;
+    let prover = contract.client().address();
+    let mode = read_prover_mode(&contract).await?;
+    metrics.write().await.record_mode(mode);
+    if !mode.allows(prover) {
+        tracing::warn!(
+            ?mode,
+            %prover,
+            "The light client contract does not accept state updates from this prover. Standing by."
+        );
+        metrics
+            .write()
+            .await
+            .record_outcome(SyncOutcome::NotPermitted);
         return Ok(());
     }
     tracing::debug!("Old state: {old_state:?}");
@@ -318,6 +565,11 @@ pub async fn sync_state<Ver: StaticVersionType>(
             }
         }
     });
+    metrics.write().await.record_signatures(
+        signer_bit_vec.iter().filter(|signed| **signed).count(),
+        accumulated_weight,
+        threshold,
+    );
 
     if accumulated_weight < threshold {
         return Err(ProverError::InvalidState(
@@ -333,28 +585,90 @@ pub async fn sync_state<Ver: StaticVersionType>(
 
     tracing::info!("Collected latest state and signatures. Start generating SNARK proof.");
     let proof_gen_start = Instant::now();
-    let (proof, public_input) = generate_state_update_proof::<_, _, _, _>(
-        &mut ark_std::rand::thread_rng(),
-        proving_key,
-        &entries,
-        signer_bit_vec,
-        signatures,
-        &bundle.state,
-        &threshold,
-        config.stake_table_capacity,
-    )?;
+    // The MSMs and FFTs of the prover run in parallel on whichever pool they are installed in.
+    let (proof, public_input) = proof_pool.install(|| {
+        generate_state_update_proof::<_, _, _, _>(
+            &mut ark_std::rand::thread_rng(),
+            proving_key,
+            &entries,
+            signer_bit_vec,
+            signatures,
+            &bundle.state,
+            &threshold,
+            config.stake_table_capacity,
+        )
+    })?;
     let proof_gen_elapsed = Instant::now().signed_duration_since(proof_gen_start);
     tracing::info!("Proof generation completed. Elapsed: {proof_gen_elapsed:.3}");
+    metrics
+        .write()
+        .await
+        .record_proof(proof_gen_start.elapsed());
+
+    // Other provers may have updated the contract while this one was proving, in which case
+    // submitting would only waste gas.
+    let block_height = bundle.state.block_height as u64;
+    let current_state = read_contract_state(config).await?;
+    if current_state.block_height >= bundle.state.block_height {
+        tracing::info!(
+            "Another prover updated the light client to block height {} first.",
+            current_state.block_height
+        );
+        let mut metrics = metrics.write().await;
+        metrics.ledger_mut().record_preempted();
+        metrics.record_outcome(SyncOutcome::Preempted { block_height });
+        metrics.record_block_height(current_state.block_height as u64);
+        return Ok(());
+    }
 
-    submit_state_and_proof(proof, public_input, config).await?;
-
-    tracing::info!("Successfully synced light client state.");
+    let client = contract.client();
+    let balance_before = client.get_balance(prover, None).await.ok();
+    let submission = submit_state_and_proof(proof, public_input, config).await?;
+    let balance_after = client.get_balance(prover, None).await.ok();
+    let balances = balance_before.zip(balance_after);
+
+    let mut metrics = metrics.write().await;
+    let mut record_gas = |receipt: &TransactionReceipt| {
+        metrics.record_submission(receipt.gas_used);
+        metrics.ledger_mut().record_gas(
+            receipt.gas_used.unwrap_or_default(),
+            receipt.effective_gas_price.unwrap_or_default(),
+        )
+    };
+    match submission {
+        Submission::Accepted(receipt) => {
+            let cost = record_gas(&receipt);
+            let reward = metrics.ledger_mut().record_update(cost, balances);
+            metrics.record_outcome(SyncOutcome::Updated { block_height });
+            metrics.record_block_height(block_height);
+            tracing::info!(%cost, %reward, "Successfully synced light client state.");
+        }
+        Submission::Preempted(receipt) => {
+            if let Some(receipt) = receipt {
+                record_gas(&receipt);
+            }
+            metrics.ledger_mut().record_preempted();
+            metrics.record_outcome(SyncOutcome::Preempted { block_height });
+            tracing::info!(
+                "Another prover updated the light client to block height {block_height} first."
+            );
+        }
+        Submission::Reverted(receipt) => {
+            record_gas(&receipt);
+            return Err(ProverError::ContractError(anyhow!(
+                "state update transaction {:x} reverted",
+                receipt.transaction_hash
+            )));
+        }
+    }
     Ok(())
 }
 
 fn start_http_server<Ver: StaticVersionType + 'static>(
     port: u16,
     lightclient_address: Address,
+    prover_address: Address,
+    metrics: Arc<RwLock<ProverMetrics>>,
     bind_version: Ver,
 ) -> io::Result<()> {
     let mut app = tide_disco::App::<(), ServerError>::with_state(());
@@ -367,6 +681,19 @@ fn start_http_server<Ver: StaticVersionType + 'static>(
     api.get("getlightclientcontract", move |_, _| {
         async move { Ok(lightclient_address) }.boxed()
     })
+    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?
+    .get("status", {
+        let metrics = metrics.clone();
+        move |_, _| {
+            let metrics = metrics.clone();
+            async move { Ok(metrics.read().await.status(prover_address)) }.boxed()
+        }
+    })
+    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?
+    .metrics("metrics", move |_, _| {
+        let metrics = metrics.clone();
+        async move { Ok(Cow::Owned(metrics.read().await.clone())) }.boxed()
+    })
     .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
 
     app.register_module("api", api)
@@ -381,36 +708,78 @@ pub async fn run_prover_service<Ver: StaticVersionType + 'static>(
     bind_version: Ver,
 ) {
     // TODO(#1022): maintain the following stake table
-    let st = Arc::new(
+    let st =
         init_stake_table_from_orchestrator(&config.orchestrator_url, config.stake_table_capacity)
-            .await,
-    );
+            .await;
+    run_prover_service_with_stake_table(config, st, bind_version).await
+}
+
+/// Run the prover service for a network with a known stake table, without contacting the
+/// orchestrator.
+pub async fn run_prover_service_with_stake_table<Ver: StaticVersionType + 'static>(
+    config: StateProverConfig,
+    st: StakeTable<BLSPubKey, StateVerKey, CircuitField>,
+    bind_version: Ver,
+) {
+    let st = Arc::new(st);
 
     tracing::info!("Light client address: {:?}", config.light_client_address);
     let relay_server_client =
         Arc::new(Client::<ServerError, Ver>::new(config.relay_server.clone()));
+    let metrics = Arc::new(RwLock::new(ProverMetrics::default()));
 
     // Start the HTTP server to get a functioning healthcheck before any heavy computations.
     if let Some(port) = config.port {
-        if let Err(err) = start_http_server(port, config.light_client_address, bind_version) {
+        if let Err(err) = start_http_server(
+            port,
+            config.light_client_address,
+            Wallet::from(config.eth_signing_key.clone()).address(),
+            metrics.clone(),
+            bind_version,
+        ) {
             tracing::error!("Error starting http server: {}", err);
         }
     }
 
     let proving_key = async_std::task::block_on(async move {
-        Arc::new(load_proving_key(config.stake_table_capacity))
+        Arc::new(load_or_generate_proving_key(
+            config.stake_table_capacity,
+            config.proving_key_path.as_deref(),
+        ))
     });
+    let proof_pool =
+        Arc::new(proof_thread_pool(config.proof_threads()).expect("error building prover threads"));
 
     let update_interval = config.update_interval;
     loop {
         let st = st.clone();
         let proving_key = proving_key.clone();
+        let proof_pool = proof_pool.clone();
         let relay_server_client = relay_server_client.clone();
         let config = config.clone();
+        let metrics = metrics.clone();
         // Use block_on to avoid blocking the async runtime with this computationally heavy task
         async_std::task::block_on(async move {
-            if let Err(err) = sync_state(&st, &proving_key, &relay_server_client, &config).await {
-                tracing::error!("Cannot sync the light client state: {}", err);
+            match sync_state(
+                &st,
+                &proving_key,
+                &proof_pool,
+                &relay_server_client,
+                &config,
+                &metrics,
+            )
+            .await
+            {
+                Ok(()) => metrics.write().await.record_success(),
+                Err(err) => {
+                    let mut metrics = metrics.write().await;
+                    metrics.record_failure();
+                    tracing::error!(
+                        "Cannot sync the light client state ({} consecutive failures): {}",
+                        metrics.consecutive_failures(),
+                        err
+                    );
+                }
             }
         });
         tracing::info!("Sleeping for {:?}", update_interval);
@@ -423,12 +792,25 @@ pub async fn run_prover_once<Ver: StaticVersionType>(config: StateProverConfig,
     let st =
         init_stake_table_from_orchestrator(&config.orchestrator_url, config.stake_table_capacity)
             .await;
-    let proving_key = load_proving_key(config.stake_table_capacity);
+    let proving_key = load_or_generate_proving_key(
+        config.stake_table_capacity,
+        config.proving_key_path.as_deref(),
+    );
+    let proof_pool =
+        proof_thread_pool(config.proof_threads()).expect("error building prover threads");
     let relay_server_client = Client::<ServerError, Ver>::new(config.relay_server.clone());
-
-    sync_state(&st, &proving_key, &relay_server_client, &config)
-        .await
-        .expect("Error syncing the light client state.");
+    let metrics = RwLock::new(ProverMetrics::default());
+
+    sync_state(
+        &st,
+        &proving_key,
+        &proof_pool,
+        &relay_server_client,
+        &config,
+        &metrics,
+    )
+    .await
+    .expect("Error syncing the light client state.");
 }
 
 #[derive(Debug, Display)]
@@ -633,6 +1015,9 @@ mod test {
                 orchestrator_url: Url::parse("http://localhost").unwrap(),
                 port: None,
                 stake_table_capacity: 10,
+                proof_threads: None,
+                profile: Default::default(),
+                proving_key_path: None,
             }
         }
     }
@@ -681,11 +1066,16 @@ mod test {
         let (pi, proof) = gen_state_proof(&genesis, new_state.clone(), &state_keys, &st);
         tracing::info!("Successfully generated proof for new state.");
 
-        super::submit_state_and_proof(proof, pi, &config).await?;
+        let submission = super::submit_state_and_proof(proof.clone(), pi.clone(), &config).await?;
+        assert!(matches!(submission, Submission::Accepted(_)));
         tracing::info!("Successfully submitted new finalized state to L1.");
         // test if new state is updated in l1
         let finalized_l1: ParsedLightClientState = contract.get_finalized_state().await?.into();
         assert_eq!(finalized_l1, new_state);
+
+        // A second prover submitting the same state loses the race without paying for gas.
+        let submission = super::submit_state_and_proof(proof, pi, &config).await?;
+        assert!(matches!(submission, Submission::Preempted(None)));
         Ok(())
     }
 }
@@ -0,0 +1,7 @@
+-- Compacted snapshots of the whole fee merkle tree, saved periodically so that a restarting node
+-- can load the tree from the latest snapshot and the accounts changed since, instead of from the
+-- node tables.
+CREATE TABLE fee_merkle_tree_snapshot (
+    height BIGINT PRIMARY KEY,
+    data   BYTEA NOT NULL
+);
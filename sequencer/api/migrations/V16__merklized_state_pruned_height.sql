@@ -0,0 +1,7 @@
+CREATE TABLE merklized_state_pruned_height (
+    -- The ID is always set to 0. Setting it explicitly allows us to enforce with every insert or
+    -- update that there is only a single entry in this table: the latest pruned height.
+    id INT PRIMARY KEY,
+
+    height BIGINT NOT NULL
+);
@@ -0,0 +1,17 @@
+-- Deposits into the fee contract on the L1, indexed by the fee deposit indexer.
+CREATE TABLE fee_deposit (
+    l1_block  BIGINT NOT NULL,
+    log_index BIGINT NOT NULL,
+    account   TEXT   NOT NULL,
+    data      JSONB  NOT NULL,
+    PRIMARY KEY (l1_block, log_index)
+);
+
+CREATE INDEX fee_deposit_account_idx ON fee_deposit (account);
+
+-- The L1 blocks where each scan for deposits ended, with their hashes. The latest checkpoint is
+-- where the indexer resumes, and older ones are where it rolls back to after an L1 reorg.
+CREATE TABLE fee_deposit_checkpoint (
+    l1_block      BIGINT PRIMARY KEY,
+    l1_block_hash BYTEA  NOT NULL
+);
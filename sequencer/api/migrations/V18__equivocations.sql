@@ -0,0 +1,5 @@
+-- Evidence of double voting, one entry per view, kept indefinitely.
+CREATE TABLE equivocation (
+    view BIGINT PRIMARY KEY,
+    data BYTEA  NOT NULL
+);
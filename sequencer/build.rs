@@ -0,0 +1,6 @@
+fn main() {
+    // Compiling the protobuf definitions of the gRPC API requires `protoc`, so only do it when the
+    // API is enabled.
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/sequencer.proto").expect("failed to compile protobufs");
+}
@@ -1,29 +1,64 @@
 use self::data_source::StateSignatureDataSource;
 use crate::{
-    network, persistence::SequencerPersistence, state::ValidatedState,
-    state_signature::StateSigner, Node, NodeState, SeqTypes, SequencerContext, Transaction,
+    block::entry::TxTableEntry,
+    equivocation::Equivocation,
+    events::{EventFanout, SlowConsumerPolicy},
+    namespace_policy::SignedTransaction,
+    network,
+    persistence::SequencerPersistence,
+    snapshot::{LatestDecide, StateSnapshot},
+    state::{FeeAccount, FeeAccountProof, FeeAmount, ValidatedState},
+    state_signature::StateSigner,
+    NamespaceId, Node, NodeState, PubKey, SeqTypes, SequencerContext, Transaction,
 };
+use anyhow::Context;
 use async_once_cell::Lazy;
 use async_std::sync::{Arc, RwLock};
 use async_trait::async_trait;
+use committable::{Commitment, Committable};
+use consensus_health::{ConsensusHealth, ConsensusTracker};
 use data_source::{StateDataSource, SubmitDataSource};
 use derivative::Derivative;
+use endpoints::{BatchSubmitResult, FeeEstimate};
+use ethers::types::Address;
 use futures::{
     future::{BoxFuture, Future, FutureExt},
     stream::{BoxStream, Stream},
 };
-use hotshot::types::{Event, SystemContextHandle};
+use hotshot::{
+    traits::election::static_committee::GeneralStaticCommittee,
+    types::{Event, SystemContextHandle},
+};
 use hotshot_events_service::events_source::{BuilderEvent, EventsSource, EventsStreamer};
 use hotshot_query_service::data_source::ExtensibleDataSource;
-use hotshot_types::{data::ViewNumber, light_client::StateSignatureRequestBody};
-use std::pin::Pin;
+use hotshot_types::{
+    data::ViewNumber, light_client::StateSignatureRequestBody, traits::signature_key::SignatureKey,
+};
+use mempool::{Mempool, PendingTransaction};
+use num_traits::CheckedSub;
+use preconfirmation::{Preconfirmation, SignedPreconfirmation};
+use std::{
+    collections::{HashMap, HashSet},
+    pin::Pin,
+};
+use tx_status::{TransactionStatus, TransactionTracker};
 use vbs::version::StaticVersionType;
 
+pub mod consensus_health;
 pub mod data_source;
 pub mod endpoints;
+pub mod fee_deposits;
 pub mod fs;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod light_client;
+pub mod mempool;
 pub mod options;
+pub mod pagination;
+pub mod preconfirmation;
+pub mod rate_limit;
 pub mod sql;
+pub mod tx_status;
 mod update;
 
 pub use options::Options;
@@ -35,10 +70,21 @@ type BoxLazy<T> = Pin<Arc<Lazy<T, BoxFuture<'static, T>>>>;
 struct ConsensusState<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType> {
     state_signer: Arc<StateSigner<Ver>>,
     event_streamer: Arc<RwLock<EventsStreamer<SeqTypes>>>,
+    events: Arc<EventFanout>,
     node_state: NodeState,
 
     #[derivative(Debug = "ignore")]
     handle: SystemContextHandle<SeqTypes, Node<N, P>>,
+
+    #[derivative(Debug = "ignore")]
+    membership: GeneralStaticCommittee<SeqTypes, PubKey>,
+    public_key: PubKey,
+    #[derivative(Debug = "ignore")]
+    private_key: <PubKey as SignatureKey>::PrivateKey,
+    stake_table_index: Option<usize>,
+
+    #[derivative(Debug = "ignore")]
+    persistence: Arc<RwLock<P>>,
 }
 
 impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static>
@@ -48,8 +94,14 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
         Self {
             state_signer: ctx.state_signer(),
             event_streamer: ctx.get_event_streamer(),
+            events: ctx.event_fanout(),
             node_state: ctx.node_state(),
             handle: ctx.consensus().clone(),
+            membership: ctx.membership().clone(),
+            public_key: ctx.public_key(),
+            private_key: ctx.private_key().clone(),
+            stake_table_index: ctx.stake_table_index(),
+            persistence: ctx.persistence(),
         }
     }
 }
@@ -63,22 +115,54 @@ struct ApiState<N: network::Type, P: SequencerPersistence, Ver: StaticVersionTyp
     // without waiting.
     #[derivative(Debug = "ignore")]
     consensus: BoxLazy<ConsensusState<N, P, Ver>>,
+
+    /// The status of transactions submitted through this API.
+    tx_status: Arc<RwLock<TransactionTracker>>,
+
+    /// The transactions submitted through this API which are still pending.
+    mempool: Arc<RwLock<Mempool>>,
+
+    /// The latest decided leaf, for serving state snapshots.
+    latest_decide: Arc<RwLock<LatestDecide>>,
+
+    /// The progress of consensus, for reporting its health.
+    consensus_tracker: Arc<RwLock<ConsensusTracker>>,
 }
 
 impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static>
     ApiState<N, P, Ver>
 {
-    fn new(init: impl Future<Output = ConsensusState<N, P, Ver>> + Send + 'static) -> Self {
+    fn new(
+        init: impl Future<Output = ConsensusState<N, P, Ver>> + Send + 'static,
+        submit: options::Submit,
+    ) -> Self {
         Self {
             consensus: Arc::pin(Lazy::from_future(init.boxed())),
+            tx_status: Arc::new(RwLock::new(TransactionTracker::new(
+                submit.tx_status_retention,
+            ))),
+            mempool: Arc::new(RwLock::new(Mempool::new(
+                submit.max_pending_per_namespace,
+                submit.pending_ttl,
+            ))),
+            latest_decide: Default::default(),
+            consensus_tracker: Default::default(),
         }
     }
 
-    fn event_stream(&self) -> impl Stream<Item = Event<SeqTypes>> + Unpin {
+    /// Subscribe a consumer named `name` to consensus events, once they have been stored.
+    fn event_stream(
+        &self,
+        name: &'static str,
+        policy: SlowConsumerPolicy,
+    ) -> impl Stream<Item = Event<SeqTypes>> + Unpin {
         let state = self.clone();
-        async move { state.consensus().await.get_event_stream() }
-            .boxed()
-            .flatten_stream()
+        async move {
+            let events = state.event_fanout().await.subscribe(name, policy);
+            events.into_stream()
+        }
+        .boxed()
+        .flatten_stream()
     }
 
     async fn state_signer(&self) -> &StateSigner<Ver> {
@@ -89,6 +173,10 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
         &self.consensus.as_ref().get().await.get_ref().event_streamer
     }
 
+    async fn event_fanout(&self) -> &EventFanout {
+        &self.consensus.as_ref().get().await.get_ref().events
+    }
+
     async fn consensus(&self) -> &SystemContextHandle<SeqTypes, Node<N, P>> {
         &self.consensus.as_ref().get().await.get_ref().handle
     }
@@ -96,6 +184,72 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
     async fn node_state(&self) -> &NodeState {
         &self.consensus.as_ref().get().await.get_ref().node_state
     }
+
+    /// The health of consensus as seen by this node, without its connected peers.
+    async fn consensus_health(&self) -> ConsensusHealth {
+        let consensus = self.consensus.as_ref().get().await.get_ref();
+        self.consensus_tracker.read().await.health(
+            &consensus.membership,
+            consensus.public_key,
+            consensus.stake_table_index,
+        )
+    }
+
+    /// Evidence of double voting seen by this node.
+    async fn equivocations(&self) -> anyhow::Result<Vec<Equivocation>> {
+        let consensus = self.consensus.as_ref().get().await.get_ref();
+        consensus
+            .persistence
+            .read()
+            .await
+            .load_equivocations()
+            .await
+    }
+
+    /// Sign a promise to include a transaction, with the consensus key of this node.
+    async fn sign_preconfirmation(
+        &self,
+        transaction: &Transaction,
+        from_height: u64,
+        max_height: u64,
+    ) -> anyhow::Result<SignedPreconfirmation> {
+        let consensus = self.consensus.as_ref().get().await.get_ref();
+        SignedPreconfirmation::sign(
+            Preconfirmation {
+                transaction: transaction.commit(),
+                namespace: transaction.namespace(),
+                from_height,
+                max_height,
+                node: consensus.public_key,
+            },
+            &consensus.private_key,
+        )
+    }
+
+    /// Submit `tx`, signed by `signer` if it was signed, if the namespace policy allows it.
+    async fn submit_as(&self, tx: Transaction, signer: Option<Address>) -> anyhow::Result<()> {
+        let node_state = self.node_state().await;
+        node_state
+            .chain_config()
+            .block_limits()
+            .check_transaction(tx.payload().len() as u64)?;
+        node_state
+            .namespace_policy()
+            .check_submission(&tx, signer)?;
+
+        let hash = tx.commit();
+        // A transaction refused by the mempool keeps its existing status, since it may be a copy of
+        // one which is already pending.
+        self.mempool.write().await.admit(&tx)?;
+        self.tx_status.write().await.received(hash);
+        let res = self.consensus().await.submit_transaction(tx).await;
+        if let Err(err) = &res {
+            self.mempool.write().await.remove(hash);
+            self.tx_status.write().await.rejected(hash, err.to_string());
+        }
+        res?;
+        Ok(())
+    }
 }
 
 type StorageState<N, P, D, Ver> = ExtensibleDataSource<D, ApiState<N, P, Ver>>;
@@ -126,14 +280,157 @@ impl<
     async fn submit(&self, tx: Transaction) -> anyhow::Result<()> {
         self.as_ref().submit(tx).await
     }
+
+    async fn submit_signed(&self, tx: SignedTransaction) -> anyhow::Result<()> {
+        self.as_ref().submit_signed(tx).await
+    }
+
+    async fn submit_batch(&self, txs: Vec<Transaction>) -> Vec<BatchSubmitResult> {
+        self.as_ref().submit_batch(txs).await
+    }
+
+    async fn transaction_status(&self, hash: Commitment<Transaction>) -> Option<TransactionStatus> {
+        self.as_ref().transaction_status(hash).await
+    }
+
+    async fn pending_transactions(
+        &self,
+        namespace: Option<NamespaceId>,
+    ) -> Vec<PendingTransaction> {
+        self.as_ref().pending_transactions(namespace).await
+    }
+
+    async fn estimate_fee(
+        &self,
+        namespace: NamespaceId,
+        size: u64,
+        builder: Option<FeeAccount>,
+    ) -> anyhow::Result<FeeEstimate> {
+        self.as_ref().estimate_fee(namespace, size, builder).await
+    }
 }
 
 impl<N: network::Type, Ver: StaticVersionType + 'static, P: SequencerPersistence>
     SubmitDataSource<N, P> for ApiState<N, P, Ver>
 {
     async fn submit(&self, tx: Transaction) -> anyhow::Result<()> {
-        self.consensus().await.submit_transaction(tx).await?;
-        Ok(())
+        self.submit_as(tx, None).await
+    }
+
+    async fn submit_signed(&self, tx: SignedTransaction) -> anyhow::Result<()> {
+        let signer = tx.signer().context("invalid transaction signature")?;
+        self.submit_as(tx.transaction, Some(signer)).await
+    }
+
+    async fn submit_batch(&self, txs: Vec<Transaction>) -> Vec<BatchSubmitResult> {
+        // Validate the whole batch before submitting any of it, so that a batch is either
+        // submitted in full, in order, or not at all.
+        let node_state = self.node_state().await;
+        let limits = node_state.chain_config().block_limits();
+        let policy = node_state.namespace_policy();
+        let mempool = self.mempool.read().await;
+        let mut hashes = HashSet::new();
+        let mut batched = HashMap::<NamespaceId, usize>::new();
+        let errors: Vec<_> = txs
+            .iter()
+            .map(|tx| {
+                let in_namespace = batched.entry(tx.namespace()).or_default();
+                if let Err(err) = limits.check_transaction(tx.payload().len() as u64) {
+                    Some(err.to_string())
+                } else if !hashes.insert(tx.commit()) {
+                    Some("duplicate transaction in batch".into())
+                } else if let Err(err) = policy.check_submission(tx, None) {
+                    Some(err.to_string())
+                } else if let Err(err) = mempool.check(tx, *in_namespace) {
+                    Some(err.to_string())
+                } else {
+                    *in_namespace += 1;
+                    None
+                }
+            })
+            .collect();
+        drop(mempool);
+        if errors.iter().any(Option::is_some) {
+            let mut tx_status = self.tx_status.write().await;
+            return txs
+                .iter()
+                .zip(errors)
+                .map(|(tx, error)| {
+                    let error = error.unwrap_or_else(|| {
+                        "not submitted, the batch contains invalid transactions".into()
+                    });
+                    tx_status.rejected(tx.commit(), error.clone());
+                    BatchSubmitResult {
+                        hash: tx.commit(),
+                        error: Some(error),
+                    }
+                })
+                .collect();
+        }
+
+        let mut results = vec![];
+        for tx in txs {
+            let hash = tx.commit();
+            let error = self.submit(tx).await.err().map(|err| err.to_string());
+            results.push(BatchSubmitResult { hash, error });
+        }
+        results
+    }
+
+    async fn transaction_status(&self, hash: Commitment<Transaction>) -> Option<TransactionStatus> {
+        self.tx_status.read().await.status(hash)
+    }
+
+    async fn pending_transactions(
+        &self,
+        namespace: Option<NamespaceId>,
+    ) -> Vec<PendingTransaction> {
+        self.mempool.read().await.pending(namespace)
+    }
+
+    async fn estimate_fee(
+        &self,
+        namespace: NamespaceId,
+        size: u64,
+        builder: Option<FeeAccount>,
+    ) -> anyhow::Result<FeeEstimate> {
+        let parent = self.consensus().await.get_decided_leaf().await;
+        let parent = parent.get_block_header();
+        let node_state = self.node_state().await;
+        let chain_config = node_state.chain_config_after(parent).await;
+        let policy = node_state.namespace_policy();
+
+        // Price the transaction as the next block would, were it built on the last decided one.
+        let next_base_fee =
+            chain_config.next_base_fee(parent.base_fee, parent.ns_table.payload_byte_len() as u64);
+        let base_fee = next_base_fee.unwrap_or(chain_config.base_fee());
+        // In its own namespace, the transaction also pays for the namespace's transaction count
+        // and its own entry in the transaction table.
+        let bytes = size
+            .checked_add(2 * TxTableEntry::byte_len() as u64)
+            .context("payload size overflow")?;
+        let expected_cost = if next_base_fee.is_some() || policy.prices_namespaces() {
+            policy.namespace_fee(base_fee, namespace, bytes)
+        } else {
+            FeeAmount::default()
+        };
+
+        let builder = builder.unwrap_or(parent.fee_info.account());
+        let state = self.get_decided_state().await;
+        let builder_balance = FeeAccountProof::prove(&state.fee_merkle_tree, builder.address())
+            .map(|(_, balance)| FeeAmount::from(balance));
+        let headroom = builder_balance.and_then(|balance| balance.checked_sub(&expected_cost));
+
+        Ok(FeeEstimate {
+            namespace,
+            payload_size: size,
+            base_fee,
+            fee_multiplier: policy.fee_multiplier(namespace),
+            expected_cost,
+            builder,
+            builder_balance,
+            headroom,
+        })
     }
 }
 
@@ -151,6 +448,10 @@ impl<
     async fn get_undecided_state(&self, view: ViewNumber) -> Option<Arc<ValidatedState>> {
         self.as_ref().get_undecided_state(view).await
     }
+
+    async fn get_snapshot(&self) -> anyhow::Result<StateSnapshot> {
+        self.as_ref().get_snapshot().await
+    }
 }
 
 impl<N: network::Type, Ver: StaticVersionType + 'static, P: SequencerPersistence> StateDataSource
@@ -163,6 +464,25 @@ impl<N: network::Type, Ver: StaticVersionType + 'static, P: SequencerPersistence
     async fn get_undecided_state(&self, view: ViewNumber) -> Option<Arc<ValidatedState>> {
         self.consensus().await.get_state(view).await
     }
+
+    async fn get_snapshot(&self) -> anyhow::Result<StateSnapshot> {
+        let (leaf, qc) = self
+            .latest_decide
+            .read()
+            .await
+            .clone()
+            .context("no leaf has been decided yet")?;
+        let state = self
+            .get_undecided_state(leaf.get_view_number())
+            .await
+            .context("state of the latest decided leaf is not available")?;
+        let signature = self
+            .state_signer()
+            .await
+            .get_state_signature(leaf.get_height())
+            .await;
+        Ok(StateSnapshot::new(leaf, qc, &state, signature))
+    }
 }
 
 #[async_trait]
@@ -172,6 +492,13 @@ impl<N: network::Type, D: Sync, Ver: StaticVersionType + 'static, P: SequencerPe
     async fn get_state_signature(&self, height: u64) -> Option<StateSignatureRequestBody> {
         self.as_ref().get_state_signature(height).await
     }
+
+    async fn subscribe_state_signatures(
+        &self,
+        from: u64,
+    ) -> BoxStream<'static, StateSignatureRequestBody> {
+        self.as_ref().subscribe_state_signatures(from).await
+    }
 }
 
 #[async_trait]
@@ -181,6 +508,13 @@ impl<N: network::Type, Ver: StaticVersionType + 'static, P: SequencerPersistence
     async fn get_state_signature(&self, height: u64) -> Option<StateSignatureRequestBody> {
         self.state_signer().await.get_state_signature(height).await
     }
+
+    async fn subscribe_state_signatures(
+        &self,
+        from: u64,
+    ) -> BoxStream<'static, StateSignatureRequestBody> {
+        self.state_signer().await.subscribe(from).await
+    }
 }
 
 #[cfg(test)]
@@ -392,7 +726,97 @@ mod test_helpers {
         assert_eq!(txn.commit(), hash);
 
         // Wait for a Decide event containing transaction matching the one we sent
-        wait_for_decide_on_handle(&mut events, &txn).await;
+        let height = wait_for_decide_on_handle(&mut events, &txn).await;
+
+        // The transaction status follows it into the block. The status is updated asynchronously,
+        // so it may take a moment to catch up with the event.
+        let mut status: TransactionStatus = client
+            .get(&format!("submit/status/{hash}"))
+            .send()
+            .await
+            .unwrap();
+        for _ in 0..10 {
+            if status != TransactionStatus::Received {
+                break;
+            }
+            sleep(Duration::from_millis(500)).await;
+            status = client
+                .get(&format!("submit/status/{hash}"))
+                .send()
+                .await
+                .unwrap();
+        }
+        assert_eq!(status, TransactionStatus::Included { height, index: 0 });
+
+        // A batch with an invalid transaction is not submitted at all.
+        let batch = vec![
+            Transaction::new(Default::default(), vec![5, 6, 7, 8]),
+            Transaction::new(Default::default(), vec![0; 20_000]),
+        ];
+        let results: Vec<BatchSubmitResult> = client
+            .post("submit/batch")
+            .body_json(&batch)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].hash, batch[0].commit());
+        assert!(results.iter().all(|result| result.error.is_some()));
+        assert!(
+            results[1]
+                .error
+                .as_ref()
+                .unwrap()
+                .contains("maximum block size"),
+            "{results:?}"
+        );
+
+        // A valid batch is submitted in full.
+        let batch = vec![
+            Transaction::new(Default::default(), vec![5, 6, 7, 8]),
+            Transaction::new(Default::default(), vec![9, 10, 11, 12]),
+        ];
+        let results: Vec<BatchSubmitResult> = client
+            .post("submit/batch")
+            .body_json(&batch)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            results,
+            batch
+                .iter()
+                .map(|tx| BatchSubmitResult {
+                    hash: tx.commit(),
+                    error: None,
+                })
+                .collect::<Vec<_>>()
+        );
+
+        // Until they are decided, the transactions are listed as pending.
+        let pending: Vec<PendingTransaction> = client.get("submit/pending/0").send().await.unwrap();
+        assert!(
+            pending
+                .iter()
+                .all(|pending| batch.iter().any(|tx| tx.commit() == pending.hash)),
+            "{pending:?}"
+        );
+        wait_for_decide_on_handle(&mut events, &batch[1]).await;
+
+        // Once decided, they are no longer pending. The mempool is updated asynchronously, so it
+        // may take a moment to catch up with the event.
+        let mut pending: Vec<PendingTransaction> =
+            client.get("submit/pending").send().await.unwrap();
+        for _ in 0..10 {
+            if pending.is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(500)).await;
+            pending = client.get("submit/pending").send().await.unwrap();
+        }
+        assert_eq!(pending, []);
     }
 
     /// Test the state signature API.
@@ -429,6 +853,17 @@ mod test_helpers {
             .send()
             .await
             .unwrap();
+
+        // Subscribing from that height replays its signature, followed by newer ones.
+        let mut signatures = client
+            .socket(&format!("state-signature/stream/{height}"))
+            .subscribe::<StateSignatureRequestBody>()
+            .await
+            .unwrap();
+        let first = signatures.next().await.unwrap().unwrap();
+        assert_eq!(first.state.block_height as u64, height);
+        let next = signatures.next().await.unwrap().unwrap();
+        assert!(next.state.block_height > first.state.block_height);
     }
 
     /// Test the state API with custom options.
@@ -570,14 +1005,16 @@ mod api_tests {
         Header,
     };
     use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
+    use async_std::task::sleep;
     use committable::Committable;
     use data_source::testing::TestableSequencerDataSource;
-    use endpoints::NamespaceProofQueryData;
+    use endpoints::{NamespaceProofError, NamespaceProofQueryData};
     use es_version::SequencerVersion;
     use futures::stream::StreamExt;
     use hotshot_query_service::availability::LeafQueryData;
     use hotshot_types::vid::vid_scheme;
     use portpicker::pick_unused_port;
+    use std::time::Duration;
     use surf_disco::Client;
     use test_helpers::{
         state_signature_test_helper, state_test_helper, status_test_helper, submit_test_helper,
@@ -666,6 +1103,24 @@ mod api_tests {
                 .proof
                 .verify(&vid, &header.payload_commitment, &header.ns_table)
                 .unwrap();
+            ns_query_res.verify(&header, 0.into()).unwrap();
+            assert_eq!(
+                ns_query_res.verify(&header, 1.into()),
+                Err(NamespaceProofError::WrongNamespace {
+                    expected: 1.into(),
+                    actual: 0.into()
+                })
+            );
+
+            // A response which omits transactions is rejected.
+            if !ns_query_res.transactions.is_empty() {
+                let mut incomplete = ns_query_res.clone();
+                incomplete.transactions.pop();
+                assert_eq!(
+                    incomplete.verify(&header, 0.into()),
+                    Err(NamespaceProofError::TransactionsMismatch { ns_id: 0.into() })
+                );
+            }
 
             found_empty_block = found_empty_block || ns_query_res.transactions.is_empty();
 
@@ -678,6 +1133,80 @@ mod api_tests {
         }
         assert!(found_txn);
         assert!(found_empty_block);
+
+        // The namespace stream yields the same transactions for each block.
+        let mut ns_stream = client
+            .socket("availability/stream/namespaces/0/0")
+            .subscribe::<NamespaceProofQueryData>()
+            .await
+            .unwrap();
+        for block_num in 0..=block_height {
+            let header: Header = client
+                .get(&format!("availability/header/{block_num}"))
+                .send()
+                .await
+                .unwrap();
+            let streamed = ns_stream.next().await.unwrap().unwrap();
+            streamed
+                .proof
+                .verify(&vid, &header.payload_commitment, &header.ns_table)
+                .unwrap();
+            let ns_query_res: NamespaceProofQueryData = client
+                .get(&format!("availability/block/{block_num}/namespace/0"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(streamed.transactions, ns_query_res.transactions);
+        }
+    }
+
+    #[async_std::test]
+    pub(crate) async fn test_consensus_health<D: TestableSequencerDataSource>() {
+        setup_logging();
+        setup_backtrace();
+
+        let port = pick_unused_port().expect("No ports free");
+        let storage = D::create_storage().await;
+        let network = TestNetwork::new(
+            D::options(&storage, options::Http { port }.into()),
+            [NoStorage; TestConfig::NUM_NODES],
+        )
+        .await;
+        let client: Client<ServerError, SequencerVersion> =
+            Client::new(format!("http://localhost:{port}").parse().unwrap());
+        client.connect(None).await;
+
+        // Wait for a few leaves to be decided.
+        let mut leaves = client
+            .socket("availability/stream/leaves/0")
+            .subscribe::<LeafQueryData<SeqTypes>>()
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            leaves.next().await.unwrap().unwrap();
+        }
+
+        let health: ConsensusHealth = loop {
+            let health: ConsensusHealth = client.get("node/consensus").send().await.unwrap();
+            if health.participation.decided > 0 {
+                break health;
+            }
+            sleep(Duration::from_millis(100)).await;
+        };
+        let ctx = &network.server;
+        assert_eq!(health.public_key, ctx.public_key());
+        assert_eq!(health.stake_table_index, ctx.stake_table_index());
+        assert!(health.view > 0);
+        assert_eq!(
+            health.upcoming_leaders.len(),
+            consensus_health::UPCOMING_LEADERS as usize
+        );
+        assert_eq!(health.upcoming_leaders[0].view, health.view);
+        let last_decided = health.last_decided.unwrap();
+        assert!(last_decided.signers > 0);
+        assert!(health.participation.voted <= health.participation.decided);
+        // The test network is not connected through the CDN or libp2p.
+        assert_eq!(health.peers, Default::default());
     }
 
     #[async_std::test]
@@ -751,6 +1280,7 @@ mod test {
     use crate::{
         catchup::{mock::MockStateCatchup, StatePeers},
         persistence::no_storage::NoStorage,
+        snapshot::fetch_snapshot,
         state::{FeeAccount, FeeAmount},
         testing::TestConfig,
         Header,
@@ -770,12 +1300,13 @@ mod test {
     };
     use hotshot_types::{
         event::LeafInfo,
+        simple_certificate::QuorumCertificate,
         traits::{metrics::NoMetrics, node_implementation::ConsensusTime},
     };
     use jf_primitives::merkle_tree::prelude::{MerkleProof, Sha3Node};
     use portpicker::pick_unused_port;
     use std::time::Duration;
-    use surf_disco::Client;
+    use surf_disco::{Client, Url};
     use test_helpers::{
         state_signature_test_helper, state_test_helper, status_test_helper, submit_test_helper,
         TestNetwork,
@@ -883,6 +1414,16 @@ mod test {
             assert_eq!(*path.index(), account);
             assert!(*path.elem().unwrap() > 0.into(), "{:?}", path.elem());
         }
+
+        // Without a retention, no state is pruned.
+        for module in ["block-state", "fee-state"] {
+            let earliest = client
+                .get::<u64>(&format!("{module}/earliest-height"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(earliest, 0);
+        }
     }
 
     #[async_std::test]
@@ -977,6 +1518,55 @@ mod test {
         }
     }
 
+    #[async_std::test]
+    async fn test_state_snapshot() {
+        setup_logging();
+        setup_backtrace();
+
+        let port = pick_unused_port().expect("No ports free");
+        let network = TestNetwork::with_state(
+            Options::from(options::Http { port }).catchup(Default::default()),
+            Default::default(),
+            [NoStorage; TestConfig::NUM_NODES],
+            std::array::from_fn(|_| MockStateCatchup::default()),
+        )
+        .await;
+
+        // Wait for a non-genesis snapshot.
+        let url: Url = format!("http://localhost:{port}").parse().unwrap();
+        let snapshot = loop {
+            match fetch_snapshot(url.clone(), SEQUENCER_VERSION).await {
+                Ok(snapshot) if snapshot.height() > 0 => break snapshot,
+                _ => sleep(Duration::from_millis(100)).await,
+            }
+        };
+        let stake_table = &network.cfg.hotshot_config().known_nodes_with_stake;
+        let state = snapshot
+            .verify(stake_table, test_helpers::STAKE_TABLE_CAPACITY_FOR_TEST)
+            .unwrap();
+
+        // A snapshot whose state does not match its leaf is rejected.
+        let mut wrong_state = state.clone();
+        wrong_state.prefund_account(FeeAccount::default(), 1.into());
+        let tampered = StateSnapshot::new(
+            snapshot.leaf.clone(),
+            snapshot.qc.clone(),
+            &wrong_state,
+            None,
+        );
+        let err = tampered
+            .verify(stake_table, test_helpers::STAKE_TABLE_CAPACITY_FOR_TEST)
+            .unwrap_err();
+        assert!(err.to_string().contains("fee merkle tree"), "{err:#}");
+
+        // So is a snapshot whose certificate is not for its leaf.
+        let mut tampered = snapshot.clone();
+        tampered.qc = QuorumCertificate::genesis(&network.server.node_state());
+        tampered
+            .verify(stake_table, test_helpers::STAKE_TABLE_CAPACITY_FOR_TEST)
+            .unwrap_err();
+    }
+
     #[async_std::test]
     pub(crate) async fn test_restart() {
         setup_logging();
@@ -0,0 +1,281 @@
+//! Tracking the health of consensus as seen by this node, for node operators.
+
+use crate::{Leaf, PubKey, SeqTypes};
+use async_std::sync::{Arc, RwLock};
+use committable::{Commitment, Committable};
+use futures::stream::{Stream, StreamExt};
+use hotshot::{
+    traits::election::static_committee::GeneralStaticCommittee,
+    types::{Event, EventType},
+};
+use hotshot_types::{
+    data::ViewNumber,
+    event::LeafInfo,
+    traits::{election::Membership, node_implementation::ConsensusTime},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// The number of upcoming views to report the leader of.
+pub const UPCOMING_LEADERS: u64 = 10;
+
+/// The number of recently decided QCs to measure vote participation over.
+pub const PARTICIPATION_WINDOW: usize = 100;
+
+/// The health of consensus as seen by this node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsensusHealth {
+    /// The latest view this node has seen.
+    pub view: u64,
+    /// The consensus key of this node.
+    pub public_key: PubKey,
+    /// The position of this node in the stake table, if it has stake.
+    pub stake_table_index: Option<usize>,
+    /// The leaders of the current view and the next few.
+    pub upcoming_leaders: Vec<ScheduledLeader>,
+    /// The number of views this node has timed out in since it started.
+    pub timeouts: u64,
+    /// The last view this node timed out in.
+    pub last_timeout_view: Option<u64>,
+    /// The QC of the last decided leaf.
+    pub last_decided: Option<DecidedQc>,
+    /// How many recently decided QCs this node's vote was part of.
+    pub participation: VoteParticipation,
+    /// The peers this node is connected to.
+    pub peers: ConnectedPeers,
+}
+
+/// The leader of a view.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledLeader {
+    pub view: u64,
+    pub leader: PubKey,
+    /// Whether this node is the leader.
+    pub is_self: bool,
+}
+
+/// The QC of a decided leaf.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecidedQc {
+    pub view: u64,
+    pub height: u64,
+    pub leaf: Commitment<Leaf>,
+    /// The number of stake table members who signed the QC.
+    pub signers: usize,
+    /// Whether this node's vote is part of the QC.
+    pub signed_by_self: bool,
+}
+
+/// The participation of this node in recently decided QCs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteParticipation {
+    /// The number of recently decided QCs, up to [`PARTICIPATION_WINDOW`].
+    pub decided: usize,
+    /// The number of those QCs which include this node's vote.
+    pub voted: usize,
+}
+
+/// The peers this node is connected to, by network.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectedPeers {
+    /// Whether this node reaches its peers through the CDN.
+    ///
+    /// The CDN relays messages between all nodes, so it does not connect nodes to each other
+    /// directly.
+    pub cdn: bool,
+    /// The number of peers connected directly over libp2p, if this node uses libp2p and reports
+    /// networking metrics.
+    pub libp2p: Option<usize>,
+}
+
+/// The decided QC of a leaf, with the stake table members who signed it.
+#[derive(Clone, Debug)]
+struct Decided {
+    view: u64,
+    height: u64,
+    leaf: Commitment<Leaf>,
+    signers: Vec<bool>,
+}
+
+/// Consensus events relevant to the health of consensus.
+#[derive(Debug, Default)]
+pub struct ConsensusTracker {
+    view: u64,
+    timeouts: u64,
+    last_timeout_view: Option<u64>,
+    /// Recently decided QCs, most recent last.
+    decided: VecDeque<Decided>,
+}
+
+impl ConsensusTracker {
+    /// Record the progress of consensus reported by `event`.
+    pub fn handle_event(&mut self, event: &Event<SeqTypes>) {
+        self.view = self.view.max(event.view_number.get_u64());
+        match &event.event {
+            EventType::ViewFinished { view_number } => {
+                self.view = self.view.max(view_number.get_u64() + 1);
+            }
+            EventType::ViewTimeout { view_number } => {
+                self.timeouts += 1;
+                self.last_timeout_view = Some(view_number.get_u64());
+            }
+            EventType::Decide { leaf_chain, qc, .. } => {
+                // The QC in a decide event is for the first leaf in the chain.
+                let Some(LeafInfo { leaf, .. }) = leaf_chain.first() else {
+                    return;
+                };
+                let signers = qc
+                    .signatures
+                    .as_ref()
+                    .map(|(_, signers)| signers.iter().map(|signed| *signed).collect())
+                    .unwrap_or_default();
+                self.record_decide(Decided {
+                    view: qc.view_number.get_u64(),
+                    height: leaf.get_block_header().height,
+                    leaf: leaf.commit(),
+                    signers,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn record_decide(&mut self, decided: Decided) {
+        if self.decided.len() == PARTICIPATION_WINDOW {
+            self.decided.pop_front();
+        }
+        self.decided.push_back(decided);
+    }
+
+    /// The health of consensus for the node `public_key`, at `stake_table_index` in `membership`.
+    ///
+    /// The connected peers are not tracked from consensus events, and are left for the caller.
+    pub fn health(
+        &self,
+        membership: &GeneralStaticCommittee<SeqTypes, PubKey>,
+        public_key: PubKey,
+        stake_table_index: Option<usize>,
+    ) -> ConsensusHealth {
+        let signed = |decided: &Decided| {
+            stake_table_index.is_some_and(|i| decided.signers.get(i).copied().unwrap_or(false))
+        };
+        let upcoming_leaders = (self.view..self.view + UPCOMING_LEADERS)
+            .map(|view| {
+                let leader = membership.get_leader(ViewNumber::new(view));
+                ScheduledLeader {
+                    view,
+                    leader,
+                    is_self: leader == public_key,
+                }
+            })
+            .collect();
+        ConsensusHealth {
+            view: self.view,
+            public_key,
+            stake_table_index,
+            upcoming_leaders,
+            timeouts: self.timeouts,
+            last_timeout_view: self.last_timeout_view,
+            last_decided: self.decided.back().map(|decided| DecidedQc {
+                view: decided.view,
+                height: decided.height,
+                leaf: decided.leaf,
+                signers: decided.signers.iter().filter(|signed| **signed).count(),
+                signed_by_self: signed(decided),
+            }),
+            participation: VoteParticipation {
+                decided: self.decided.len(),
+                voted: self
+                    .decided
+                    .iter()
+                    .filter(|decided| signed(decided))
+                    .count(),
+            },
+            peers: Default::default(),
+        }
+    }
+}
+
+/// Update `tracker` with the progress of consensus reported in `events`.
+pub(super) async fn track_consensus(
+    tracker: Arc<RwLock<ConsensusTracker>>,
+    mut events: impl Stream<Item = Event<SeqTypes>> + Unpin,
+) {
+    while let Some(event) = events.next().await {
+        tracker.write().await.handle_event(&event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::NodeState;
+    use hotshot_types::{
+        light_client::StateKeyPair, traits::signature_key::SignatureKey, PeerConfig,
+    };
+
+    #[test]
+    fn test_consensus_tracker_participation() {
+        let keys = (0..4)
+            .map(|i| PubKey::generated_from_seed_indexed([0; 32], i).0)
+            .collect::<Vec<_>>();
+        let stake_table = keys
+            .iter()
+            .zip(0..)
+            .map(|(key, i)| PeerConfig::<PubKey> {
+                stake_table_entry: key.get_stake_table_entry(1),
+                state_ver_key: StateKeyPair::generate_from_seed_indexed([0; 32], i).ver_key(),
+            })
+            .collect::<Vec<_>>();
+        let membership = GeneralStaticCommittee::create_election(
+            stake_table,
+            GeneralStaticCommittee::<SeqTypes, PubKey>::default_election_config(4, 0),
+            0,
+        );
+
+        let mut tracker = ConsensusTracker::default();
+        let health = tracker.health(&membership, keys[1], Some(1));
+        assert_eq!(health.last_decided, None);
+        assert_eq!(health.participation, VoteParticipation::default());
+        assert_eq!(health.upcoming_leaders.len(), UPCOMING_LEADERS as usize);
+        assert_eq!(
+            health
+                .upcoming_leaders
+                .iter()
+                .filter(|slot| slot.is_self)
+                .count(),
+            health
+                .upcoming_leaders
+                .iter()
+                .filter(|slot| slot.leader == keys[1])
+                .count()
+        );
+
+        let leaf = Leaf::genesis(&NodeState::mock()).commit();
+        for view in 0..PARTICIPATION_WINDOW as u64 + 10 {
+            // This node misses every other vote.
+            tracker.record_decide(Decided {
+                view,
+                height: view,
+                leaf,
+                signers: vec![true, view % 2 == 0, true, false],
+            });
+        }
+        let health = tracker.health(&membership, keys[1], Some(1));
+        assert_eq!(
+            health.participation,
+            VoteParticipation {
+                decided: PARTICIPATION_WINDOW,
+                voted: PARTICIPATION_WINDOW / 2,
+            }
+        );
+        let last = health.last_decided.unwrap();
+        assert_eq!(last.view, PARTICIPATION_WINDOW as u64 + 9);
+        assert_eq!(last.signers, 2);
+        assert!(!last.signed_by_self);
+
+        // A node without stake never votes.
+        let health = tracker.health(&membership, keys[1], None);
+        assert_eq!(health.participation.voted, 0);
+    }
+}
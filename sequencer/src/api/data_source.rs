@@ -1,16 +1,23 @@
 use super::{
+    endpoints::{BatchSubmitResult, FeeEstimate},
     fs,
+    mempool::PendingTransaction,
     options::{Options, Query},
     sql,
+    tx_status::TransactionStatus,
 };
 use crate::{
+    namespace_policy::SignedTransaction,
     network,
     persistence::{self, SequencerPersistence},
-    state::ValidatedState,
-    SeqTypes, Transaction,
+    snapshot::StateSnapshot,
+    state::{FeeAccount, ValidatedState},
+    NamespaceId, SeqTypes, Transaction,
 };
 use async_std::sync::Arc;
 use async_trait::async_trait;
+use committable::Commitment;
+use futures::stream::BoxStream;
 use hotshot_query_service::{
     availability::AvailabilityDataSource,
     data_source::{UpdateDataSource, VersionedDataSource},
@@ -82,17 +89,33 @@ pub fn provider<Ver: StaticVersionType + 'static>(
 #[trait_variant::make(SubmitDataSource: Send)]
 pub(crate) trait LocalSubmitDataSource<N: network::Type, P: SequencerPersistence> {
     async fn submit(&self, tx: Transaction) -> anyhow::Result<()>;
+    async fn submit_signed(&self, tx: SignedTransaction) -> anyhow::Result<()>;
+    async fn submit_batch(&self, txs: Vec<Transaction>) -> Vec<BatchSubmitResult>;
+    async fn transaction_status(&self, hash: Commitment<Transaction>) -> Option<TransactionStatus>;
+    async fn pending_transactions(&self, namespace: Option<NamespaceId>)
+        -> Vec<PendingTransaction>;
+    async fn estimate_fee(
+        &self,
+        namespace: NamespaceId,
+        size: u64,
+        builder: Option<FeeAccount>,
+    ) -> anyhow::Result<FeeEstimate>;
 }
 
 #[async_trait]
 pub(crate) trait StateSignatureDataSource<N: network::Type> {
     async fn get_state_signature(&self, height: u64) -> Option<StateSignatureRequestBody>;
+    async fn subscribe_state_signatures(
+        &self,
+        from: u64,
+    ) -> BoxStream<'static, StateSignatureRequestBody>;
 }
 
 #[trait_variant::make(StateDataSource: Send)]
 pub(crate) trait LocalStateDataSource {
     async fn get_decided_state(&self) -> Arc<ValidatedState>;
     async fn get_undecided_state(&self, view: ViewNumber) -> Option<Arc<ValidatedState>>;
+    async fn get_snapshot(&self) -> anyhow::Result<StateSnapshot>;
 }
 
 #[cfg(test)]
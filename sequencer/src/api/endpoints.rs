@@ -1,32 +1,46 @@
 //! Sequencer-specific API endpoint handlers.
 
 use super::{
+    consensus_health::ConnectedPeers,
     data_source::{
         SequencerDataSource, StateDataSource, StateSignatureDataSource, SubmitDataSource,
     },
+    fee_deposits::{get_fee_deposits, get_indexed_l1_block},
+    light_client::inclusion_proof,
+    pagination::{fetch_page, PageLimits},
+    preconfirmation::{Breach, PreconfirmationStore},
     StorageState,
 };
 use crate::{
     block::payload::{parse_ns_payload, NamespaceProof},
+    namespace_policy::SignedTransaction,
     network,
-    persistence::SequencerPersistence,
-    state::{BlockMerkleTree, FeeAccountProof, ValidatedState},
-    NamespaceId, SeqTypes, Transaction,
+    persistence::{sql::Persistence, SequencerPersistence},
+    state::{BlockMerkleTree, FeeAccount, FeeAccountProof, FeeAmount, ValidatedState},
+    Header, NamespaceId, SeqTypes, Transaction,
 };
 use anyhow::Result;
 use async_std::sync::{Arc, RwLock};
-use committable::Committable;
+use committable::{Commitment, Committable};
 use ethers::prelude::U256;
-use futures::{try_join, FutureExt};
+use futures::{try_join, FutureExt, StreamExt, TryFutureExt};
 use hotshot_query_service::{
-    availability::{self, AvailabilityDataSource, CustomSnafu, FetchBlockSnafu},
+    availability::{
+        self, AvailabilityDataSource, BlockQueryData, CustomSnafu, FetchBlockSnafu, FetchLeafSnafu,
+        VidCommonQueryData,
+    },
     merklized_state::{self, MerklizedState, MerklizedStateDataSource},
-    node, Error,
+    node,
+    status::{self, StatusDataSource},
+    Error,
+};
+use hotshot_types::{
+    data::ViewNumber, light_client::LightClientState, traits::node_implementation::ConsensusTime,
 };
-use hotshot_types::{data::ViewNumber, traits::node_implementation::ConsensusTime};
 use jf_primitives::merkle_tree::MerkleTreeScheme;
 use serde::{Deserialize, Serialize};
-use snafu::OptionExt;
+use snafu::{OptionExt, Snafu};
+use std::sync::atomic::{AtomicU64, Ordering};
 use tagged_base64::TaggedBase64;
 use tide_disco::{
     method::{ReadState, WriteState},
@@ -41,6 +55,74 @@ pub struct NamespaceProofQueryData {
     pub transactions: Vec<Transaction>,
 }
 
+/// Why a [`NamespaceProofQueryData`] failed to verify.
+#[derive(Clone, Debug, Snafu, PartialEq, Eq)]
+pub enum NamespaceProofError {
+    #[snafu(display("expected a proof for namespace {expected}, but got one for {actual}"))]
+    WrongNamespace {
+        expected: NamespaceId,
+        actual: NamespaceId,
+    },
+    #[snafu(display("invalid proof for namespace {ns_id}"))]
+    InvalidProof { ns_id: NamespaceId },
+    #[snafu(display("transactions do not match the proven payload of namespace {ns_id}"))]
+    TransactionsMismatch { ns_id: NamespaceId },
+}
+
+impl NamespaceProofQueryData {
+    /// Verify that `transactions` are exactly the transactions in namespace `ns_id` of the block
+    /// with header `header`.
+    ///
+    /// This checks both inclusion (every transaction is in the namespace) and completeness (no
+    /// transaction in the namespace is missing) against the payload commitment in the header. It
+    /// does not require a connection to a node, so clients can use it to check a response from the
+    /// availability API given only a header they trust.
+    pub fn verify(&self, header: &Header, ns_id: NamespaceId) -> Result<(), NamespaceProofError> {
+        let actual = self.proof.namespace();
+        if actual != ns_id {
+            return Err(NamespaceProofError::WrongNamespace {
+                expected: ns_id,
+                actual,
+            });
+        }
+        let (transactions, _) = self
+            .proof
+            .verify_header(header)
+            .context(InvalidProofSnafu { ns_id })?;
+        if transactions != self.transactions {
+            return Err(NamespaceProofError::TransactionsMismatch { ns_id });
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of submitting one transaction of a batch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchSubmitResult {
+    pub hash: Commitment<Transaction>,
+    /// Why the transaction was not submitted, if it was not.
+    pub error: Option<String>,
+}
+
+/// An estimate of the fee for including a transaction in the next block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeEstimate {
+    pub namespace: NamespaceId,
+    pub payload_size: u64,
+    /// The base fee per byte for the next block.
+    pub base_fee: FeeAmount,
+    /// The fee multiplier of the namespace, as a percentage of the base fee.
+    pub fee_multiplier: u64,
+    /// The fee the builder must pay for the transaction, or zero if fees are not enforced.
+    pub expected_cost: FeeAmount,
+    pub builder: FeeAccount,
+    /// The balance of the builder's fee account, if known.
+    pub builder_balance: Option<FeeAmount>,
+    /// What would be left of the builder's balance after paying for the transaction, if the
+    /// balance is known and covers the cost.
+    pub headroom: Option<FeeAmount>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AccountQueryData {
     pub balance: U256,
@@ -61,6 +143,7 @@ type AvailabilityApi<N, P, D, Ver> = Api<AvailState<N, P, D, Ver>, availability:
 
 pub(super) fn availability<N, P, D, Ver: StaticVersionType + 'static>(
     bind_version: Ver,
+    limits: PageLimits,
 ) -> Result<AvailabilityApi<N, P, D, Ver>>
 where
     N: network::Type,
@@ -105,39 +188,225 @@ where
                 }
             )?;
 
-            let proof = block
-                .payload()
-                .namespace_with_proof(
-                    block.payload().get_ns_table(),
-                    ns_id,
-                    common.common().clone(),
-                )
-                .context(CustomSnafu {
-                    message: format!("failed to make proof for namespace {ns_id}"),
-                    status: StatusCode::NotFound,
-                })?;
-
-            let transactions = if let NamespaceProof::Existence {
-                ref ns_payload_flat,
-                ..
-            } = proof
-            {
-                parse_ns_payload(ns_payload_flat, ns_id)
-            } else {
-                Vec::new()
-            };
+            namespace_proof(&block, &common, ns_id)
+        }
+        .boxed()
+    })?;
+
+    api.stream("stream_namespace", move |req, state| {
+        let state = state.clone();
+        async move {
+            let height: usize = req.integer_param("height")?;
+            let ns_id: u64 = req.integer_param("namespace")?;
+            let ns_id = NamespaceId::from(ns_id);
+            let blocks = state
+                .read(|state| async move { state.subscribe_blocks(height).await }.boxed())
+                .await;
+            Ok(blocks.then(move |block| {
+                let state = state.clone();
+                async move {
+                    let height = block.height() as usize;
+                    let common = state
+                        .read(|state| {
+                            async move {
+                                state
+                                    .get_vid_common(height)
+                                    .await
+                                    .with_timeout(timeout)
+                                    .await
+                            }
+                            .boxed()
+                        })
+                        .await
+                        .context(FetchBlockSnafu {
+                            resource: height.to_string(),
+                        })?;
+                    namespace_proof(&block, &common, ns_id)
+                }
+            }))
+        }
+        .try_flatten_stream()
+        .boxed()
+    })?;
 
-            Ok(NamespaceProofQueryData {
-                transactions,
-                proof,
+    // Each page endpoint fetches objects one at a time, so that a page is cut off before it gets too
+    // big.
+    api.get("getleafpage", move |req, state| {
+        async move {
+            let (cursor, limit, block_height) = page_params(&req, state).await?;
+            fetch_page(cursor, limit, block_height, limits, |height| async move {
+                state
+                    .get_leaf(height as usize)
+                    .await
+                    .with_timeout(timeout)
+                    .await
+                    .context(FetchLeafSnafu {
+                        resource: height.to_string(),
+                    })
+            })
+            .await
+        }
+        .boxed()
+    })?
+    .get("getheaderpage", move |req, state| {
+        async move {
+            let (cursor, limit, block_height) = page_params(&req, state).await?;
+            fetch_page(cursor, limit, block_height, limits, |height| async move {
+                let leaf = state
+                    .get_leaf(height as usize)
+                    .await
+                    .with_timeout(timeout)
+                    .await
+                    .context(FetchLeafSnafu {
+                        resource: height.to_string(),
+                    })?;
+                Ok(leaf.leaf().get_block_header().clone())
+            })
+            .await
+        }
+        .boxed()
+    })?
+    .get("getblockpage", move |req, state| {
+        async move {
+            let (cursor, limit, block_height) = page_params(&req, state).await?;
+            fetch_page(cursor, limit, block_height, limits, |height| async move {
+                state
+                    .get_block(height as usize)
+                    .await
+                    .with_timeout(timeout)
+                    .await
+                    .context(FetchBlockSnafu {
+                        resource: height.to_string(),
+                    })
+            })
+            .await
+        }
+        .boxed()
+    })?
+    .get("getpayloadpage", move |req, state| {
+        async move {
+            let (cursor, limit, block_height) = page_params(&req, state).await?;
+            fetch_page(cursor, limit, block_height, limits, |height| async move {
+                state
+                    .get_payload(height as usize)
+                    .await
+                    .with_timeout(timeout)
+                    .await
+                    .context(FetchBlockSnafu {
+                        resource: height.to_string(),
+                    })
             })
+            .await
+        }
+        .boxed()
+    })?;
+
+    api.stream("stream_block_range", move |req, state| {
+        let state = state.clone();
+        async move {
+            let (from, until) = range_params(&req)?;
+            let blocks = state
+                .read(|state| async move { state.get_block_range(from..until).await }.boxed())
+                .await;
+            Ok(blocks.zip(futures::stream::iter(from..until)).then(
+                move |(block, height)| async move {
+                    block.with_timeout(timeout).await.context(FetchBlockSnafu {
+                        resource: height.to_string(),
+                    })
+                },
+            ))
+        }
+        .try_flatten_stream()
+        .boxed()
+    })?
+    .stream("stream_payload_range", move |req, state| {
+        let state = state.clone();
+        async move {
+            let (from, until) = range_params(&req)?;
+            let payloads = state
+                .read(|state| async move { state.get_payload_range(from..until).await }.boxed())
+                .await;
+            Ok(payloads.zip(futures::stream::iter(from..until)).then(
+                move |(payload, height)| async move {
+                    payload
+                        .with_timeout(timeout)
+                        .await
+                        .context(FetchBlockSnafu {
+                            resource: height.to_string(),
+                        })
+                },
+            ))
         }
+        .try_flatten_stream()
         .boxed()
     })?;
 
     Ok(api)
 }
 
+/// The cursor and limit of a page request, and the block height the page is limited by.
+async fn page_params<S: StatusDataSource>(
+    req: &tide_disco::RequestParams,
+    state: &S,
+) -> Result<(u64, Option<usize>, u64), availability::Error> {
+    let cursor = req.integer_param("cursor")?;
+    let limit = req.opt_integer_param("limit")?;
+    let block_height = state.block_height().await.map_err(|err| {
+        availability::Error::catch_all(
+            StatusCode::InternalServerError,
+            format!("getting block height: {err}"),
+        )
+    })?;
+    Ok((cursor, limit, block_height as u64))
+}
+
+/// The `[from, until)` range of a streaming range request.
+fn range_params(req: &tide_disco::RequestParams) -> Result<(usize, usize), availability::Error> {
+    let from = req.integer_param("from")?;
+    let until = req.integer_param("until")?;
+    if until < from {
+        return Err(availability::Error::catch_all(
+            StatusCode::BadRequest,
+            format!("invalid range [{from}, {until})"),
+        ));
+    }
+    Ok((from, until))
+}
+
+/// The transactions in namespace `ns_id` of `block`, along with a proof.
+fn namespace_proof(
+    block: &BlockQueryData<SeqTypes>,
+    common: &VidCommonQueryData<SeqTypes>,
+    ns_id: NamespaceId,
+) -> Result<NamespaceProofQueryData, availability::Error> {
+    let proof = block
+        .payload()
+        .namespace_with_proof(
+            block.payload().get_ns_table(),
+            ns_id,
+            common.common().clone(),
+        )
+        .context(CustomSnafu {
+            message: format!("failed to make proof for namespace {ns_id}"),
+            status: StatusCode::NotFound,
+        })?;
+
+    let transactions = if let NamespaceProof::Existence {
+        ref ns_payload_flat,
+        ..
+    } = proof
+    {
+        parse_ns_payload(ns_payload_flat, ns_id)
+    } else {
+        Vec::new()
+    };
+
+    Ok(NamespaceProofQueryData {
+        transactions,
+        proof,
+    })
+}
+
 type NodeApi<N, P, D, Ver> = Api<AvailState<N, P, D, Ver>, node::Error, Ver>;
 
 pub(super) fn node<N, P, D, Ver: StaticVersionType + 'static>(
@@ -148,12 +417,71 @@ where
     D: SequencerDataSource + Send + Sync + 'static,
     P: SequencerPersistence,
 {
-    let api = node::define_api::<AvailState<N, P, D, Ver>, SeqTypes, Ver>(
-        &Default::default(),
-        bind_version,
-    )?;
+    let mut options = node::Options::default();
+    let extension = toml::from_str(include_str!("../../api/node.toml"))?;
+    options.extensions.push(extension);
+
+    let mut api =
+        node::define_api::<AvailState<N, P, D, Ver>, SeqTypes, Ver>(&options, bind_version)?;
+
+    api.get("consensus", |_, state| {
+        async move {
+            let mut health = state.as_ref().consensus_health().await;
+            health.peers = ConnectedPeers {
+                cdn: N::CDN,
+                libp2p: N::LIBP2P
+                    .then(|| {
+                        let networking = state.metrics().get_subgroup(["networking"]).ok()?;
+                        Some(networking.get_gauge("connected_peers").ok()?.get())
+                    })
+                    .flatten(),
+            };
+            Ok(health)
+        }
+        .boxed()
+    })?
+    .get("equivocations", |_, state| {
+        async move {
+            state.as_ref().equivocations().await.map_err(|err| {
+                node::Error::catch_all(StatusCode::InternalServerError, format!("{err:#}"))
+            })
+        }
+        .boxed()
+    })?;
+
     Ok(api)
 }
+type StatusApi<N, P, D, Ver> = Api<AvailState<N, P, D, Ver>, status::Error, Ver>;
+
+pub(super) fn status<N, P, D, Ver: StaticVersionType + 'static>(
+    bind_version: Ver,
+) -> Result<StatusApi<N, P, D, Ver>>
+where
+    N: network::Type,
+    D: StatusDataSource + Send + Sync + 'static,
+    P: SequencerPersistence,
+{
+    let mut options = status::Options::default();
+    let extension = toml::from_str(include_str!("../../api/status.toml"))?;
+    options.extensions.push(extension);
+
+    let mut api = status::define_api::<AvailState<N, P, D, Ver>, Ver>(&options, bind_version)?;
+
+    api.get("block_limits", |_, state| {
+        async move {
+            Ok(state
+                .as_ref()
+                .node_state()
+                .await
+                .chain_config()
+                .block_limits())
+        }
+        .boxed()
+    })?;
+
+    Ok(api)
+}
+
 pub(super) fn submit<N, P, S, Ver: StaticVersionType + 'static>() -> Result<Api<S, Error, Ver>>
 where
     N: network::Type,
@@ -177,6 +505,78 @@ where
             Ok(hash)
         }
         .boxed()
+    })?
+    .post("submit_signed", |req, state| {
+        async move {
+            let tx = req
+                .body_auto::<SignedTransaction, Ver>(Ver::instance())
+                .map_err(Error::from_request_error)?;
+            let hash = tx.transaction.commit();
+            state
+                .submit_signed(tx)
+                .await
+                .map_err(|err| Error::internal(err.to_string()))?;
+            Ok(hash)
+        }
+        .boxed()
+    })?
+    .post("batch", |req, state| {
+        async move {
+            let txs = req
+                .body_auto::<Vec<Transaction>, Ver>(Ver::instance())
+                .map_err(Error::from_request_error)?;
+            Ok(state.submit_batch(txs).await)
+        }
+        .boxed()
+    })?
+    .get("status", |req, state| {
+        async move {
+            let hash: Commitment<Transaction> =
+                req.blob_param("hash").map_err(Error::from_request_error)?;
+            state.transaction_status(hash).await.ok_or(Error::catch_all(
+                StatusCode::NotFound,
+                format!("transaction {hash} was not submitted to this node recently"),
+            ))
+        }
+        .boxed()
+    })?
+    .get("pending", |req, state| {
+        async move {
+            let namespace = req
+                .opt_integer_param::<_, u64>("namespace")
+                .map_err(Error::from_request_error)?;
+            Ok(state
+                .pending_transactions(namespace.map(NamespaceId::from))
+                .await)
+        }
+        .boxed()
+    })?
+    .get("estimate_fee", |req, state| {
+        async move {
+            let namespace: u64 = req
+                .integer_param("namespace")
+                .map_err(Error::from_request_error)?;
+            let size = req
+                .integer_param("size")
+                .map_err(Error::from_request_error)?;
+            let builder = match req
+                .opt_string_param("builder")
+                .map_err(Error::from_request_error)?
+            {
+                Some(builder) => Some(builder.parse().map_err(|err| {
+                    Error::catch_all(
+                        StatusCode::BadRequest,
+                        format!("malformed account {builder}: {err}"),
+                    )
+                })?),
+                None => None,
+            };
+            state
+                .estimate_fee(namespace.into(), size, builder)
+                .await
+                .map_err(|err| Error::internal(format!("{err:#}")))
+        }
+        .boxed()
     })?;
 
     Ok(api)
@@ -207,6 +607,21 @@ where
                 ))
         }
         .boxed()
+    })?
+    .stream("stream_state_signatures", |req, state| {
+        async move {
+            let height = req
+                .integer_param("height")
+                .map_err(Error::from_request_error)?;
+            state
+                .read(|state| {
+                    async move { Ok(state.subscribe_state_signatures(height).await.map(Ok)) }
+                        .boxed()
+                })
+                .await
+        }
+        .try_flatten_stream()
+        .boxed()
     })?;
 
     Ok(api)
@@ -280,6 +695,15 @@ where
             Ok(frontier)
         }
         .boxed()
+    })?
+    .get("snapshot", |_, state| {
+        async move {
+            state
+                .get_snapshot()
+                .await
+                .map_err(|err| Error::catch_all(StatusCode::NotFound, format!("{err:#}")))
+        }
+        .boxed()
     })?;
 
     Ok(api)
@@ -288,6 +712,7 @@ where
 type MerklizedStateApi<N, P, D, Ver> = Api<AvailState<N, P, D, Ver>, merklized_state::Error, Ver>;
 pub(super) fn merklized_state<N, P, D, S, Ver: StaticVersionType + 'static, const ARITY: usize>(
     _: Ver,
+    earliest_height: Arc<AtomicU64>,
 ) -> Result<MerklizedStateApi<N, P, D, Ver>>
 where
     N: network::Type,
@@ -296,8 +721,237 @@ where
     P: SequencerPersistence,
     for<'a> <S::Commit as TryFrom<&'a TaggedBase64>>::Error: std::fmt::Display,
 {
-    let api = merklized_state::define_api::<AvailState<N, P, D, Ver>, SeqTypes, S, Ver, ARITY>(
-        &Default::default(),
-    )?;
+    let mut options = merklized_state::Options::default();
+    let extension = toml::from_str(include_str!("../../api/merklized_state.toml"))?;
+    options.extensions.push(extension);
+
+    let mut api =
+        merklized_state::define_api::<AvailState<N, P, D, Ver>, SeqTypes, S, Ver, ARITY>(&options)?;
+    api.get("getearliestheight", move |_, _| {
+        let height = earliest_height.load(Ordering::Relaxed);
+        async move { Ok(height) }.boxed()
+    })?;
+    Ok(api)
+}
+
+pub(super) fn light_client<N, P, D, Ver: StaticVersionType + 'static>(
+    _: Ver,
+    finalized: Arc<RwLock<Option<LightClientState>>>,
+) -> Result<Api<AvailState<N, P, D, Ver>, Error, Ver>>
+where
+    N: network::Type,
+    D: SequencerDataSource
+        + MerklizedStateDataSource<SeqTypes, BlockMerkleTree, 3>
+        + Send
+        + Sync
+        + 'static,
+    P: SequencerPersistence,
+{
+    let toml = toml::from_str::<toml::Value>(include_str!("../../api/light_client.toml"))?;
+    let mut api = Api::<AvailState<N, P, D, Ver>, Error, Ver>::new(toml)?;
+    let timeout = availability::Options::default().fetch_timeout;
+
+    let latest = finalized.clone();
+    api.get("finalized", move |_, _| {
+        let latest = latest.clone();
+        async move {
+            latest.read().await.clone().ok_or(Error::catch_all(
+                StatusCode::NotFound,
+                "light client state has not been read yet".into(),
+            ))
+        }
+        .boxed()
+    })?
+    .get("inclusionproof", move |req, state| {
+        let finalized = finalized.clone();
+        async move {
+            let height = req
+                .integer_param("height")
+                .map_err(Error::from_request_error)?;
+            let finalized = finalized.read().await.clone().ok_or(Error::catch_all(
+                StatusCode::NotFound,
+                "light client state has not been read yet".into(),
+            ))?;
+            inclusion_proof(state, height, finalized, timeout)
+                .await
+                .map_err(|err| Error::catch_all(StatusCode::NotFound, format!("{err:#}")))
+        }
+        .boxed()
+    })?;
+
+    Ok(api)
+}
+
+pub(super) fn preconfirmation<N, P, D, Ver: StaticVersionType + 'static>(
+    _: Ver,
+    store: Arc<RwLock<PreconfirmationStore>>,
+    max_delay: u64,
+) -> Result<Api<AvailState<N, P, D, Ver>, Error, Ver>>
+where
+    N: network::Type,
+    D: SequencerDataSource + Send + Sync + 'static,
+    P: SequencerPersistence,
+{
+    let toml = toml::from_str::<toml::Value>(include_str!("../../api/preconfirmation.toml"))?;
+    let mut api = Api::<AvailState<N, P, D, Ver>, Error, Ver>::new(toml)?;
+    let timeout = availability::Options::default().fetch_timeout;
+
+    let promises = store.clone();
+    api.post("preconfirm", move |req, state| {
+        let promises = promises.clone();
+        async move {
+            let tx = req
+                .body_auto::<Transaction, Ver>(Ver::instance())
+                .map_err(Error::from_request_error)?;
+            let from_height = state
+                .block_height()
+                .await
+                .map_err(|err| Error::internal(err.to_string()))?
+                as u64;
+            let promise = state
+                .as_ref()
+                .sign_preconfirmation(&tx, from_height, from_height + max_delay)
+                .await
+                .map_err(|err| Error::internal(format!("{err:#}")))?;
+            state
+                .submit(tx)
+                .await
+                .map_err(|err| Error::internal(err.to_string()))?;
+            promises.write().await.insert(promise.clone());
+            Ok(promise)
+        }
+        .boxed()
+    })?
+    .get("preconfirmation", {
+        let promises = store.clone();
+        move |req, _| {
+            let promises = promises.clone();
+            async move {
+                let hash: Commitment<Transaction> =
+                    req.blob_param("hash").map_err(Error::from_request_error)?;
+                promises
+                    .read()
+                    .await
+                    .get(hash)
+                    .cloned()
+                    .ok_or(Error::catch_all(
+                        StatusCode::NotFound,
+                        format!("no preconfirmation for transaction {hash}"),
+                    ))
+            }
+            .boxed()
+        }
+    })?
+    .get("breach", move |req, state| {
+        let promises = store.clone();
+        async move {
+            let hash: Commitment<Transaction> =
+                req.blob_param("hash").map_err(Error::from_request_error)?;
+            let promise = promises
+                .read()
+                .await
+                .get(hash)
+                .cloned()
+                .ok_or(Error::catch_all(
+                    StatusCode::NotFound,
+                    format!("no preconfirmation for transaction {hash}"),
+                ))?;
+            let p = &promise.preconfirmation;
+            let block_height = state
+                .block_height()
+                .await
+                .map_err(|err| Error::internal(err.to_string()))?
+                as u64;
+            if block_height <= p.max_height {
+                return Err(Error::catch_all(
+                    StatusCode::NotFound,
+                    format!(
+                        "preconfirmation is open until block {}, and the block height is \
+                            {block_height}",
+                        p.max_height
+                    ),
+                ));
+            }
+
+            let mut blocks = vec![];
+            for height in p.from_height..=p.max_height {
+                let not_found = || {
+                    Error::catch_all(
+                        StatusCode::NotFound,
+                        format!("block {height} not available"),
+                    )
+                };
+                let block = state
+                    .get_block(height as usize)
+                    .await
+                    .with_timeout(timeout)
+                    .await
+                    .ok_or_else(not_found)?;
+                let common = state
+                    .get_vid_common(height as usize)
+                    .await
+                    .with_timeout(timeout)
+                    .await
+                    .ok_or_else(not_found)?;
+                let ns = namespace_proof(&block, &common, p.namespace)
+                    .map_err(|err| Error::catch_all(err.status(), err.to_string()))?;
+                if ns.transactions.iter().any(|tx| tx.commit() == hash) {
+                    return Err(Error::catch_all(
+                        StatusCode::NotFound,
+                        format!("transaction {hash} was included in block {height}"),
+                    ));
+                }
+                blocks.push((block.header().clone(), ns));
+            }
+            Ok(Breach {
+                preconfirmation: promise,
+                blocks,
+            })
+        }
+        .boxed()
+    })?;
+
+    Ok(api)
+}
+
+pub(super) fn fee_deposits<S, Ver: StaticVersionType + 'static>(
+    db: Arc<Persistence>,
+    _: Ver,
+) -> Result<Api<S, Error, Ver>>
+where
+    S: 'static + Send + Sync + ReadState,
+{
+    let toml = toml::from_str::<toml::Value>(include_str!("../../api/fee_deposits.toml"))?;
+    let mut api = Api::<S, Error, Ver>::new(toml)?;
+
+    let db_for_account = db.clone();
+    api.get("account", move |req, _| {
+        let db = db_for_account.clone();
+        async move {
+            let account = req
+                .string_param("address")
+                .map_err(Error::from_request_error)?;
+            let account = account.parse().map_err(|err| {
+                Error::catch_all(
+                    StatusCode::BadRequest,
+                    format!("malformed account {account}: {err}"),
+                )
+            })?;
+            get_fee_deposits(&db, account).await.map_err(|err| {
+                Error::catch_all(StatusCode::InternalServerError, format!("{err:#}"))
+            })
+        }
+        .boxed()
+    })?
+    .get("indexedblock", move |_, _| {
+        let db = db.clone();
+        async move {
+            get_indexed_l1_block(&db).await.map_err(|err| {
+                Error::catch_all(StatusCode::InternalServerError, format!("{err:#}"))
+            })
+        }
+        .boxed()
+    })?;
+
     Ok(api)
 }
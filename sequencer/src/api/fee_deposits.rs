@@ -0,0 +1,374 @@
+//! Indexing of the fee deposits made on the L1.
+//!
+//! Consensus only reads deposits from the L1 as it validates each header, and does not keep a
+//! record of them, so a deposit which is missed, for example while the L1 provider is down, is hard
+//! to track down. The indexer scans the deposit events of the fee contract into the database, so
+//! the deposits made by an account can be queried.
+//!
+//! Blocks are only indexed once they have a number of confirmations. After each scan, the indexer
+//! saves a checkpoint of the last block it scanned and its hash, from which it resumes after a
+//! restart, backfilling any deposits made while it was down. Before each scan, it checks that the
+//! latest checkpoint is still on the L1 chain. If it is not, the L1 reorged deeper than the
+//! confirmations, and the indexer rolls back to the latest checkpoint which is still on the chain,
+//! deleting the deposits after it so they are scanned again.
+
+use super::options::FeeDeposits;
+use crate::{
+    l1_client::L1Client,
+    persistence::sql::{sql_param, transaction, Persistence},
+    state::{FeeAccount, FeeAmount, FeeInfo},
+};
+use anyhow::{ensure, Context};
+use async_std::task::sleep;
+use contract_bindings::fee_contract::DepositFilter;
+use ethers::prelude::{LogMeta, H256};
+use futures::{
+    future::{Future, FutureExt},
+    stream::TryStreamExt,
+};
+use hotshot_query_service::data_source::storage::sql::Query;
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
+
+/// The most L1 blocks to scan for deposits at once.
+const MAX_SCAN_RANGE: u64 = 10_000;
+
+/// Number of checkpoints kept to roll back to after a reorg, older ones are deleted.
+const CHECKPOINTS_RETAINED: i64 = 256;
+
+/// A deposit into the fee contract.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeDeposit {
+    pub account: FeeAccount,
+    pub amount: FeeAmount,
+    /// The L1 block the deposit was made in.
+    pub l1_block: u64,
+    pub l1_block_hash: H256,
+    pub transaction_hash: H256,
+    /// The index of the deposit event among the logs of its L1 block.
+    pub log_index: u64,
+}
+
+impl FeeDeposit {
+    fn new(event: DepositFilter, meta: LogMeta) -> Self {
+        let info = FeeInfo::from(event);
+        Self {
+            account: info.account(),
+            amount: info.amount(),
+            l1_block: meta.block_number.as_u64(),
+            l1_block_hash: meta.block_hash,
+            transaction_hash: meta.transaction_hash,
+            log_index: meta.log_index.as_u64(),
+        }
+    }
+}
+
+/// Index the deposits made to the fee contract, forever.
+///
+/// `l1` resolves to the client used to read the L1.
+pub(super) async fn index_fee_deposits_loop(
+    mut db: Persistence,
+    l1: impl Future<Output = L1Client>,
+    opt: FeeDeposits,
+) {
+    let l1 = l1.await;
+    loop {
+        match index_fee_deposits(&mut db, &l1, &opt).await {
+            // Keep going without waiting while backfilling old blocks.
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(err) => tracing::warn!("error indexing fee deposits: {err:#}"),
+        }
+        sleep(opt.poll_interval).await;
+    }
+}
+
+/// Index the deposits in the next range of confirmed L1 blocks.
+///
+/// Returns whether there are more confirmed blocks to scan.
+async fn index_fee_deposits(
+    db: &mut Persistence,
+    l1: &L1Client,
+    opt: &FeeDeposits,
+) -> anyhow::Result<bool> {
+    let checkpoint = latest_checkpoint(db).await?;
+    if let Some((number, hash)) = checkpoint {
+        if l1.get_block_hash(number).await != Some(hash) {
+            roll_back(db, l1).await?;
+            return Ok(true);
+        }
+    }
+
+    let head = l1.get_block_number().await;
+    let Some(confirmed) = head.checked_sub(opt.confirmations) else {
+        return Ok(false);
+    };
+    let from = checkpoint.map_or(opt.start_block, |(number, _)| number + 1);
+    if from > confirmed {
+        return Ok(false);
+    }
+    let to = min(confirmed, from + MAX_SCAN_RANGE - 1);
+
+    let hash = l1
+        .get_block_hash(to)
+        .await
+        .with_context(|| format!("L1 block {to} not found"))?;
+    let deposits = l1
+        .get_deposit_logs(opt.fee_contract_address, from, to)
+        .await
+        .into_iter()
+        .map(|(event, meta)| FeeDeposit::new(event, meta))
+        .collect::<Vec<_>>();
+    // Make sure the deposits were read from the same chain as the checkpoint.
+    ensure!(
+        l1.get_block_hash(to).await == Some(hash),
+        "L1 block {to} reorged while scanning deposits"
+    );
+
+    let rows = deposits
+        .iter()
+        .map(|deposit| {
+            Ok((
+                deposit.l1_block as i64,
+                deposit.log_index as i64,
+                deposit.account.to_string(),
+                serde_json::to_value(deposit)?,
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let checkpoint = (to as i64, hash.as_bytes().to_vec());
+    transaction(db, |mut tx| {
+        async move {
+            if !rows.is_empty() {
+                tx.upsert(
+                    "fee_deposit",
+                    ["l1_block", "log_index", "account", "data"],
+                    ["l1_block", "log_index"],
+                    rows.iter().map(|(block, index, account, data)| {
+                        [
+                            sql_param(block),
+                            sql_param(index),
+                            sql_param(account),
+                            sql_param(data),
+                        ]
+                    }),
+                )
+                .await?;
+            }
+            tx.upsert(
+                "fee_deposit_checkpoint",
+                ["l1_block", "l1_block_hash"],
+                ["l1_block"],
+                [[sql_param(&checkpoint.0), sql_param(&checkpoint.1)]],
+            )
+            .await?;
+            tx.execute(
+                "DELETE FROM fee_deposit_checkpoint WHERE l1_block NOT IN
+                    (SELECT l1_block FROM fee_deposit_checkpoint
+                        ORDER BY l1_block DESC LIMIT $1)",
+                [CHECKPOINTS_RETAINED],
+            )
+            .await?;
+            Ok(())
+        }
+        .boxed()
+    })
+    .await?;
+    tracing::debug!(from, to, "indexed {} fee deposits", deposits.len());
+    Ok(to < confirmed)
+}
+
+/// The last L1 block scanned for deposits, and its hash.
+async fn latest_checkpoint(db: &Persistence) -> anyhow::Result<Option<(u64, H256)>> {
+    Ok(db
+        .query_opt_static(
+            "SELECT l1_block, l1_block_hash FROM fee_deposit_checkpoint
+                ORDER BY l1_block DESC LIMIT 1",
+        )
+        .await?
+        .map(|row| {
+            let number: i64 = row.get("l1_block");
+            let hash: Vec<u8> = row.get("l1_block_hash");
+            (number as u64, H256::from_slice(&hash))
+        }))
+}
+
+/// Roll the index back to the latest checkpoint which is still on the L1 chain.
+///
+/// The deposits after that checkpoint are deleted, so they will be scanned again from the new
+/// chain. If none of the checkpoints are still on the chain, the whole index is deleted.
+async fn roll_back(db: &mut Persistence, l1: &L1Client) -> anyhow::Result<()> {
+    let checkpoints = db
+        .query_static(
+            "SELECT l1_block, l1_block_hash FROM fee_deposit_checkpoint ORDER BY l1_block DESC",
+        )
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    let mut keep = -1;
+    for row in checkpoints {
+        let number: i64 = row.get("l1_block");
+        let hash: Vec<u8> = row.get("l1_block_hash");
+        if l1.get_block_hash(number as u64).await == Some(H256::from_slice(&hash)) {
+            keep = number;
+            break;
+        }
+    }
+    tracing::warn!(keep, "L1 reorg detected, rolling back fee deposit index");
+
+    transaction(db, |mut tx| {
+        async move {
+            tx.execute("DELETE FROM fee_deposit WHERE l1_block > $1", [keep])
+                .await?;
+            tx.execute(
+                "DELETE FROM fee_deposit_checkpoint WHERE l1_block > $1",
+                [keep],
+            )
+            .await?;
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
+/// The indexed deposits made by `account`, oldest first.
+pub(super) async fn get_fee_deposits(
+    db: &Persistence,
+    account: FeeAccount,
+) -> anyhow::Result<Vec<FeeDeposit>> {
+    db.query(
+        "SELECT data FROM fee_deposit WHERE account = $1 ORDER BY l1_block, log_index",
+        [account.to_string()],
+    )
+    .await?
+    .map_err(anyhow::Error::from)
+    .and_then(|row| async move { Ok(serde_json::from_value(row.get("data"))?) })
+    .try_collect()
+    .await
+}
+
+/// The last L1 block which has been scanned for deposits, if any.
+pub(super) async fn get_indexed_l1_block(db: &Persistence) -> anyhow::Result<Option<u64>> {
+    Ok(latest_checkpoint(db).await?.map(|(number, _)| number))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::{sql::Options, PersistenceOptions};
+    use contract_bindings::{erc1967_proxy::ERC1967Proxy, fee_contract::FeeContract};
+    use ethers::{
+        prelude::*,
+        utils::{parse_ether, Anvil},
+    };
+    use hotshot_query_service::data_source::storage::sql::testing::TmpDb;
+    use std::{sync::Arc, time::Duration};
+
+    #[async_std::test]
+    async fn test_fee_deposit_indexer_reorg() {
+        let anvil = Anvil::new().spawn();
+        let wallet: LocalWallet = anvil.keys()[0].clone().into();
+        let account = wallet.address();
+        let provider = Provider::<Http>::try_from(anvil.endpoint())
+            .unwrap()
+            .interval(Duration::from_millis(10));
+        let client = Arc::new(SignerMiddleware::new(
+            provider.clone(),
+            wallet.with_chain_id(anvil.chain_id()),
+        ));
+
+        // Deploy the fee contract behind a proxy.
+        let fee_contract = FeeContract::deploy(client.clone(), ())
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let init = fee_contract.initialize(account).calldata().unwrap();
+        let proxy = ERC1967Proxy::deploy(client.clone(), (fee_contract.address(), init))
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let fee_contract = FeeContract::new(proxy.address(), client.clone());
+        let deposit = |ether: &'static str| {
+            let fee_contract = fee_contract.clone();
+            async move {
+                fee_contract
+                    .deposit(account)
+                    .value(parse_ether(ether).unwrap())
+                    .send()
+                    .await
+                    .unwrap()
+                    .await
+                    .unwrap();
+            }
+        };
+
+        let tmp_db = TmpDb::init().await;
+        let mut db = Options {
+            port: Some(tmp_db.port()),
+            host: Some(tmp_db.host()),
+            user: Some("postgres".into()),
+            password: Some("password".into()),
+            ..Default::default()
+        }
+        .create()
+        .await
+        .unwrap();
+        let l1 = L1Client::new(anvil.endpoint().parse().unwrap(), Address::default());
+        let opt = FeeDeposits {
+            fee_contract_address: proxy.address(),
+            start_block: 0,
+            confirmations: 0,
+            poll_interval: Duration::from_millis(100),
+        };
+
+        let snapshot: U256 = provider.request("evm_snapshot", ()).await.unwrap();
+        deposit("1").await;
+        deposit("2").await;
+        assert!(!index_fee_deposits(&mut db, &l1, &opt).await.unwrap());
+        let deposits = get_fee_deposits(&db, account.into()).await.unwrap();
+        assert_eq!(
+            deposits
+                .iter()
+                .map(|deposit| U256::from(deposit.amount))
+                .collect::<Vec<_>>(),
+            [parse_ether("1").unwrap(), parse_ether("2").unwrap()]
+        );
+        let head = provider.get_block_number().await.unwrap().as_u64();
+        assert_eq!(get_indexed_l1_block(&db).await.unwrap(), Some(head));
+
+        // Replace the blocks with the deposits by a different chain.
+        let reverted: bool = provider.request("evm_revert", [snapshot]).await.unwrap();
+        assert!(reverted);
+        deposit("3").await;
+        deposit("4").await;
+        deposit("5").await;
+
+        // The indexer rolls back the deposits from the old chain and indexes the new ones.
+        while index_fee_deposits(&mut db, &l1, &opt).await.unwrap() {}
+        let deposits = get_fee_deposits(&db, account.into()).await.unwrap();
+        assert_eq!(
+            deposits
+                .iter()
+                .map(|deposit| U256::from(deposit.amount))
+                .collect::<Vec<_>>(),
+            [
+                parse_ether("3").unwrap(),
+                parse_ether("4").unwrap(),
+                parse_ether("5").unwrap()
+            ]
+        );
+        for deposit in deposits {
+            let block = provider.get_block(deposit.l1_block).await.unwrap().unwrap();
+            assert_eq!(block.hash, Some(deposit.l1_block_hash));
+        }
+
+        // Other accounts have no deposits.
+        assert!(get_fee_deposits(&db, Address::random().into())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,396 @@
+//! gRPC interface mirroring the submit, availability and status HTTP APIs.
+//!
+//! The services are defined in `proto/sequencer.proto`. They are served on a separate port from the
+//! HTTP API, from the same state, and require the query module for availability and status data.
+
+use super::{
+    data_source::{SequencerDataSource, SubmitDataSource},
+    endpoints::AvailState,
+    tx_status::TransactionStatus,
+};
+use crate::{network, persistence::SequencerPersistence, NamespaceId, SeqTypes, Transaction};
+use committable::{Commitment, Committable};
+use derivative::Derivative;
+use futures::stream::{BoxStream, StreamExt};
+use hotshot_query_service::{
+    availability::{self, AvailabilityDataSource, BlockQueryData, LeafQueryData},
+    status::StatusDataSource,
+};
+use proto::{
+    availability_server::{Availability, AvailabilityServer},
+    status_server::{Status as StatusService, StatusServer},
+    submit_server::{Submit, SubmitServer},
+};
+use std::{fmt::Display, time::Duration};
+use tagged_base64::TaggedBase64;
+use tonic::{transport::Server, Request, Response, Status};
+use vbs::version::StaticVersionType;
+
+/// Types generated from the protobuf definitions.
+pub mod proto {
+    tonic::include_proto!("espresso.sequencer.v1");
+}
+
+/// Serve the gRPC services on `port`.
+pub(super) async fn serve<N, P, D, Ver>(
+    port: u16,
+    state: AvailState<N, P, D, Ver>,
+) -> anyhow::Result<()>
+where
+    N: network::Type,
+    P: SequencerPersistence,
+    D: SequencerDataSource + Send + Sync + 'static,
+    Ver: StaticVersionType + 'static,
+{
+    let services = Services {
+        state,
+        fetch_timeout: availability::Options::default().fetch_timeout,
+    };
+    tracing::info!(port, "starting gRPC server");
+    Server::builder()
+        .add_service(SubmitServer::new(services.clone()))
+        .add_service(AvailabilityServer::new(services.clone()))
+        .add_service(StatusServer::new(services))
+        .serve(([0, 0, 0, 0], port).into())
+        .await?;
+    Ok(())
+}
+
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+struct Services<N: network::Type, P: SequencerPersistence, D, Ver: StaticVersionType> {
+    state: AvailState<N, P, D, Ver>,
+    /// How long to wait for a leaf or block this node is missing to be fetched.
+    fetch_timeout: Duration,
+}
+
+#[tonic::async_trait]
+impl<N, P, D, Ver> Submit for Services<N, P, D, Ver>
+where
+    N: network::Type,
+    P: SequencerPersistence,
+    D: SequencerDataSource + Send + Sync + 'static,
+    Ver: StaticVersionType + 'static,
+{
+    async fn submit_transaction(
+        &self,
+        req: Request<proto::Transaction>,
+    ) -> Result<Response<proto::TransactionHash>, Status> {
+        let tx = Transaction::from(req.into_inner());
+        let hash = tx.commit();
+        self.state.read().await.submit(tx).await.map_err(internal)?;
+        Ok(Response::new(proto::TransactionHash {
+            hash: hash.to_string(),
+        }))
+    }
+
+    async fn submit_batch(
+        &self,
+        req: Request<proto::SubmitBatchRequest>,
+    ) -> Result<Response<proto::SubmitBatchResponse>, Status> {
+        let txs = req
+            .into_inner()
+            .transactions
+            .into_iter()
+            .map(Transaction::from)
+            .collect();
+        let results = self.state.read().await.submit_batch(txs).await;
+        Ok(Response::new(proto::SubmitBatchResponse {
+            results: results
+                .into_iter()
+                .map(|res| proto::SubmitResult {
+                    hash: res.hash.to_string(),
+                    error: res.error,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_transaction_status(
+        &self,
+        req: Request<proto::TransactionHash>,
+    ) -> Result<Response<proto::TransactionStatus>, Status> {
+        let hash = parse_transaction_hash(&req.into_inner().hash)?;
+        let status = self
+            .state
+            .read()
+            .await
+            .transaction_status(hash)
+            .await
+            .ok_or_else(|| {
+                Status::not_found(format!(
+                    "transaction {hash} was not submitted to this node recently"
+                ))
+            })?;
+        Ok(Response::new(status.into()))
+    }
+}
+
+#[tonic::async_trait]
+impl<N, P, D, Ver> Availability for Services<N, P, D, Ver>
+where
+    N: network::Type,
+    P: SequencerPersistence,
+    D: SequencerDataSource + Send + Sync + 'static,
+    Ver: StaticVersionType + 'static,
+{
+    type StreamLeavesStream = BoxStream<'static, Result<proto::Leaf, Status>>;
+    type StreamBlocksStream = BoxStream<'static, Result<proto::Block, Status>>;
+
+    async fn get_leaf(
+        &self,
+        req: Request<proto::HeightRequest>,
+    ) -> Result<Response<proto::Leaf>, Status> {
+        let height = req.into_inner().height as usize;
+        // Release the lock before waiting for the fetch, which may need to write to the state.
+        let fetch = self.state.read().await.get_leaf(height).await;
+        let leaf = fetch
+            .with_timeout(self.fetch_timeout)
+            .await
+            .ok_or_else(|| Status::not_found(format!("leaf {height} not available")))?;
+        Ok(Response::new(leaf_message(leaf)?))
+    }
+
+    async fn get_block(
+        &self,
+        req: Request<proto::HeightRequest>,
+    ) -> Result<Response<proto::Block>, Status> {
+        let height = req.into_inner().height as usize;
+        let fetch = self.state.read().await.get_block(height).await;
+        let block = fetch
+            .with_timeout(self.fetch_timeout)
+            .await
+            .ok_or_else(|| Status::not_found(format!("block {height} not available")))?;
+        Ok(Response::new(block_message(block)?))
+    }
+
+    async fn stream_leaves(
+        &self,
+        req: Request<proto::StreamRequest>,
+    ) -> Result<Response<Self::StreamLeavesStream>, Status> {
+        let from = req.into_inner().from as usize;
+        let leaves = self.state.read().await.subscribe_leaves(from).await;
+        Ok(Response::new(leaves.map(leaf_message).boxed()))
+    }
+
+    async fn stream_blocks(
+        &self,
+        req: Request<proto::StreamRequest>,
+    ) -> Result<Response<Self::StreamBlocksStream>, Status> {
+        let from = req.into_inner().from as usize;
+        let blocks = self.state.read().await.subscribe_blocks(from).await;
+        Ok(Response::new(blocks.map(block_message).boxed()))
+    }
+}
+
+#[tonic::async_trait]
+impl<N, P, D, Ver> StatusService for Services<N, P, D, Ver>
+where
+    N: network::Type,
+    P: SequencerPersistence,
+    D: SequencerDataSource + Send + Sync + 'static,
+    Ver: StaticVersionType + 'static,
+{
+    async fn get_block_height(
+        &self,
+        _: Request<proto::BlockHeightRequest>,
+    ) -> Result<Response<proto::BlockHeight>, Status> {
+        let height = self
+            .state
+            .read()
+            .await
+            .block_height()
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(proto::BlockHeight {
+            height: height as u64,
+        }))
+    }
+
+    async fn get_success_rate(
+        &self,
+        _: Request<proto::SuccessRateRequest>,
+    ) -> Result<Response<proto::SuccessRate>, Status> {
+        let rate = self
+            .state
+            .read()
+            .await
+            .success_rate()
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(proto::SuccessRate { rate }))
+    }
+}
+
+impl From<proto::Transaction> for Transaction {
+    fn from(tx: proto::Transaction) -> Self {
+        Transaction::new(NamespaceId::from(tx.namespace), tx.payload)
+    }
+}
+
+impl From<TransactionStatus> for proto::TransactionStatus {
+    fn from(status: TransactionStatus) -> Self {
+        use proto::transaction_status::{Included, Received, Rejected, Status};
+
+        let status = match status {
+            TransactionStatus::Received => Status::Received(Received {}),
+            TransactionStatus::Included { height, index } => {
+                Status::Included(Included { height, index })
+            }
+            TransactionStatus::Rejected { reason } => Status::Rejected(Rejected { reason }),
+        };
+        Self {
+            status: Some(status),
+        }
+    }
+}
+
+fn parse_transaction_hash(hash: &str) -> Result<Commitment<Transaction>, Status> {
+    let malformed =
+        |err: &dyn Display| Status::invalid_argument(format!("malformed hash {hash}: {err}"));
+    let tb64 = TaggedBase64::parse(hash).map_err(|err| malformed(&err))?;
+    Commitment::try_from(&tb64).map_err(|err| malformed(&err))
+}
+
+fn leaf_message(leaf: LeafQueryData<SeqTypes>) -> Result<proto::Leaf, Status> {
+    Ok(proto::Leaf {
+        height: leaf.height(),
+        hash: leaf.hash().to_string(),
+        json: serde_json::to_vec(&leaf).map_err(internal)?,
+    })
+}
+
+fn block_message(block: BlockQueryData<SeqTypes>) -> Result<proto::Block, Status> {
+    Ok(proto::Block {
+        height: block.height(),
+        hash: block.hash().to_string(),
+        size: block.size(),
+        num_transactions: block.num_transactions(),
+        json: serde_json::to_vec(&block).map_err(internal)?,
+    })
+}
+
+fn internal(err: impl Display) -> Status {
+    Status::internal(err.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        proto::{
+            availability_client::AvailabilityClient, status_client::StatusClient,
+            submit_client::SubmitClient, transaction_status, StreamRequest, TransactionHash,
+        },
+        *,
+    };
+    use crate::{
+        api::{
+            data_source::testing::TestableSequencerDataSource,
+            fs,
+            options::{self, Grpc},
+            test_helpers::TestNetwork,
+            Options,
+        },
+        persistence::no_storage::NoStorage,
+        testing::TestConfig,
+    };
+    use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
+    use async_std::task::sleep;
+    use portpicker::pick_unused_port;
+
+    #[async_std::test]
+    async fn test_grpc() {
+        setup_logging();
+        setup_backtrace();
+
+        let port = pick_unused_port().expect("No ports free");
+        let grpc_port = pick_unused_port().expect("No ports free");
+        let storage = fs::DataSource::create_storage().await;
+        let opt = Options::from(options::Http { port })
+            .submit(Default::default())
+            .status(Default::default())
+            .grpc(Grpc { grpc_port });
+        let _network = TestNetwork::new(
+            fs::DataSource::options(&storage, opt),
+            [NoStorage; TestConfig::NUM_NODES],
+        )
+        .await;
+
+        let url = format!("http://localhost:{grpc_port}");
+        let mut submit = loop {
+            match SubmitClient::connect(url.clone()).await {
+                Ok(client) => break client,
+                Err(_) => sleep(Duration::from_millis(100)).await,
+            }
+        };
+        let mut availability = AvailabilityClient::connect(url.clone()).await.unwrap();
+        let mut status = StatusClient::connect(url).await.unwrap();
+
+        let tx = Transaction::new(1.into(), vec![1, 2, 3]);
+        let hash = submit
+            .submit_transaction(proto::Transaction {
+                namespace: 1,
+                payload: vec![1, 2, 3],
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .hash;
+        assert_eq!(hash, tx.commit().to_string());
+
+        // Wait for the transaction to be included in a block.
+        let mut blocks = availability
+            .stream_blocks(StreamRequest { from: 0 })
+            .await
+            .unwrap()
+            .into_inner();
+        let included = loop {
+            let block = blocks.message().await.unwrap().unwrap();
+            if block.num_transactions > 0 {
+                break block;
+            }
+        };
+        let leaf = availability
+            .get_leaf(proto::HeightRequest {
+                height: included.height,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(leaf.height, included.height);
+        let leaf: LeafQueryData<SeqTypes> = serde_json::from_slice(&leaf.json).unwrap();
+        assert_eq!(leaf.height(), included.height);
+
+        let tx_status = submit
+            .get_transaction_status(TransactionHash { hash })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            tx_status.status,
+            Some(transaction_status::Status::Included(
+                transaction_status::Included {
+                    height: included.height,
+                    index: 0
+                }
+            ))
+        );
+
+        let height = status
+            .get_block_height(proto::BlockHeightRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .height;
+        assert!(height > included.height);
+
+        // An unknown hash is an error, as in the HTTP API.
+        let other = Transaction::new(1.into(), vec![4]);
+        submit
+            .get_transaction_status(TransactionHash {
+                hash: other.commit().to_string(),
+            })
+            .await
+            .unwrap_err();
+    }
+}
@@ -0,0 +1,211 @@
+//! Proofs that a header is part of the chain finalized by the light client contract.
+//!
+//! The light client contract on the L1 stores the root of the block Merkle tree of its latest
+//! finalized state, hashed into a field element (`blockCommRoot`). The block Merkle tree at height
+//! `h` holds the commitment of every header below `h`, so a single Merkle proof against the tree at
+//! the finalized height shows that a header is part of the chain the contract has finalized.
+//!
+//! The node reads the finalized state from the contract periodically, and serves an
+//! [`InclusionProof`] for any header below the finalized height.
+
+use super::options::LightClient;
+use crate::{
+    l1_client::L1Client,
+    state::{BlockMerkleCommitment, BlockMerkleTree},
+    state_signature::block_comm_root,
+    Header, SeqTypes,
+};
+use anyhow::{ensure, Context};
+use async_std::{
+    sync::{Arc, RwLock},
+    task::sleep,
+};
+use committable::Committable;
+use futures::future::Future;
+use hotshot_query_service::{
+    availability::AvailabilityDataSource,
+    merklized_state::{MerklizedStateDataSource, Snapshot},
+};
+use hotshot_types::light_client::LightClientState;
+use jf_primitives::merkle_tree::{MerkleCommitment, MerkleTreeScheme};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A proof that `header` is in the block Merkle tree of a light client state.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub header: Header,
+    /// The state finalized by the light client contract which the proof is against, as read by the
+    /// node which made the proof.
+    pub finalized: LightClientState,
+    /// The root of the block Merkle tree at the finalized height.
+    pub block_merkle_tree_root: BlockMerkleCommitment,
+    /// The path to the commitment of `header` from `block_merkle_tree_root`.
+    pub proof: <BlockMerkleTree as MerkleTreeScheme>::MembershipProof,
+}
+
+impl InclusionProof {
+    /// Check that `header` is part of the chain finalized in `finalized`.
+    ///
+    /// `finalized` should be read from the light client contract by the caller, rather than taken
+    /// from the proof, unless the caller trusts the node which served the proof.
+    pub fn verify(&self, finalized: &LightClientState) -> anyhow::Result<()> {
+        let height = self.header.height;
+        ensure!(
+            height < finalized.block_height as u64,
+            "header {height} is not below finalized height {}",
+            finalized.block_height
+        );
+        ensure!(
+            self.block_merkle_tree_root.size() == finalized.block_height as u64,
+            "block Merkle tree root is not from finalized height {}",
+            finalized.block_height
+        );
+        ensure!(
+            block_comm_root(&self.block_merkle_tree_root)? == finalized.block_comm_root,
+            "block Merkle tree root does not match the finalized state"
+        );
+        ensure!(
+            self.proof.elem() == Some(&self.header.commit()),
+            "proof is not for header {height}"
+        );
+        ensure!(
+            BlockMerkleTree::verify(self.block_merkle_tree_root.digest(), height, &self.proof)?
+                .is_ok(),
+            "invalid proof for header {height}"
+        );
+        Ok(())
+    }
+}
+
+/// Prove that header `height` is part of the chain finalized in `finalized`.
+pub(super) async fn inclusion_proof<D>(
+    ds: &D,
+    height: u64,
+    finalized: LightClientState,
+    fetch_timeout: Duration,
+) -> anyhow::Result<InclusionProof>
+where
+    D: AvailabilityDataSource<SeqTypes> + MerklizedStateDataSource<SeqTypes, BlockMerkleTree, 3>,
+{
+    let finalized_height = finalized.block_height as u64;
+    ensure!(
+        height < finalized_height,
+        "block {height} is not finalized by the light client yet (finalized height is \
+            {finalized_height})"
+    );
+    let header = ds
+        .get_leaf(height as usize)
+        .await
+        .with_timeout(fetch_timeout)
+        .await
+        .with_context(|| format!("block {height} not available"))?
+        .leaf()
+        .get_block_header()
+        .clone();
+    let root = ds
+        .get_leaf(finalized_height as usize)
+        .await
+        .with_timeout(fetch_timeout)
+        .await
+        .with_context(|| format!("finalized block {finalized_height} not available"))?
+        .leaf()
+        .get_block_header()
+        .block_merkle_tree_root;
+    ensure!(
+        block_comm_root(&root)? == finalized.block_comm_root,
+        "block {finalized_height} does not match the state finalized by the light client"
+    );
+    let proof = ds
+        .get_path(
+            Snapshot::<SeqTypes, BlockMerkleTree, 3>::Index(finalized_height),
+            height,
+        )
+        .await
+        .with_context(|| format!("fetching path for block {height}"))?;
+
+    Ok(InclusionProof {
+        header,
+        finalized,
+        block_merkle_tree_root: root,
+        proof,
+    })
+}
+
+/// Keep `latest` up to date with the state finalized by the light client contract, forever.
+///
+/// `l1` resolves to the client used to read the L1.
+pub(super) async fn track_finalized_state(
+    latest: Arc<RwLock<Option<LightClientState>>>,
+    l1: impl Future<Output = L1Client>,
+    opt: LightClient,
+) {
+    let l1 = l1.await;
+    loop {
+        let state: LightClientState = l1
+            .get_finalized_light_client_state(opt.light_client_address)
+            .await
+            .into();
+        tracing::debug!(
+            height = state.block_height,
+            view = state.view_number,
+            "read finalized light client state"
+        );
+        *latest.write().await = Some(state);
+        sleep(opt.poll_interval).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{state_signature::form_light_client_state, Leaf, NodeState};
+    use hotshot_query_service::merklized_state::MerklizedState;
+    use jf_primitives::merkle_tree::AppendableMerkleTreeScheme;
+
+    #[test]
+    fn test_inclusion_proof() {
+        let genesis = Leaf::genesis(&NodeState::mock());
+        let headers = (0..4)
+            .map(|height| {
+                let mut header = genesis.get_block_header().clone();
+                header.height = height;
+                header
+            })
+            .collect::<Vec<_>>();
+        let mut tree = BlockMerkleTree::new(BlockMerkleTree::tree_height());
+        for header in &headers {
+            tree.push(header.commit()).unwrap();
+        }
+
+        // The light client has finalized the leaf at height 4, whose tree holds headers 0 to 3.
+        let mut leaf = genesis.clone();
+        leaf.get_block_header_mut().height = 4;
+        leaf.get_block_header_mut().block_merkle_tree_root = tree.commitment();
+        let finalized = form_light_client_state(&leaf, &Default::default()).unwrap();
+
+        let proof_for = |height: u64| {
+            let (_, proof) = tree.lookup(height).expect_ok().unwrap();
+            InclusionProof {
+                header: headers[height as usize].clone(),
+                finalized: finalized.clone(),
+                block_merkle_tree_root: tree.commitment(),
+                proof,
+            }
+        };
+        for height in 0..4 {
+            proof_for(height).verify(&finalized).unwrap();
+        }
+
+        // A proof for a different header fails.
+        let mut proof = proof_for(1);
+        proof.header = headers[2].clone();
+        proof.verify(&finalized).unwrap_err();
+
+        // A proof against a different finalized state fails.
+        let mut other = leaf.clone();
+        other.get_block_header_mut().height = 5;
+        let other = form_light_client_state(&other, &Default::default()).unwrap();
+        proof_for(1).verify(&other).unwrap_err();
+    }
+}
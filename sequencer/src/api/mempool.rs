@@ -0,0 +1,260 @@
+//! Limits and visibility for transactions pending in the submit API.
+
+use crate::{NamespaceId, SeqTypes, Transaction};
+use async_std::sync::{Arc, RwLock};
+use committable::{Commitment, Committable};
+use futures::stream::{Stream, StreamExt};
+use hotshot::{
+    traits::BlockPayload,
+    types::{Event, EventType},
+};
+use hotshot_types::{event::LeafInfo, traits::block_contents::BlockHeader};
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// A transaction submitted to this node which has not yet been included in a decided block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingTransaction {
+    pub hash: Commitment<Transaction>,
+    pub namespace: NamespaceId,
+    /// The size of the payload, in bytes.
+    pub size: u64,
+    /// How long ago the transaction was submitted.
+    pub age: Duration,
+}
+
+/// Why a transaction was not admitted to the mempool.
+#[derive(Clone, Debug, Snafu, PartialEq, Eq)]
+pub enum MempoolError {
+    #[snafu(display("transaction {hash} is already pending"))]
+    AlreadyPending { hash: Commitment<Transaction> },
+    #[snafu(display("namespace {namespace} already has {limit} pending transactions"))]
+    NamespaceFull {
+        namespace: NamespaceId,
+        limit: usize,
+    },
+}
+
+/// The transactions submitted to this node which are still pending.
+///
+/// A transaction is pending from when it is submitted until it is included in a decided block, or
+/// until it has been pending for the TTL, whichever comes first. Each namespace may have a limited
+/// number of transactions pending at once, and a transaction which is already pending is not
+/// submitted again. Once a transaction expires, it may be resubmitted, which replaces the stale
+/// entry.
+#[derive(Debug)]
+pub struct Mempool {
+    max_pending_per_namespace: usize,
+    ttl: Duration,
+    pending: HashMap<Commitment<Transaction>, (NamespaceId, u64, Instant)>,
+    per_namespace: HashMap<NamespaceId, usize>,
+    /// Pending transactions in the order they were submitted, for expiring them.
+    order: VecDeque<(Instant, Commitment<Transaction>)>,
+}
+
+impl Mempool {
+    pub fn new(max_pending_per_namespace: usize, ttl: Duration) -> Self {
+        Self {
+            max_pending_per_namespace,
+            ttl,
+            pending: Default::default(),
+            per_namespace: Default::default(),
+            order: Default::default(),
+        }
+    }
+
+    /// Check whether `tx` could be admitted, if `batched` other transactions in its namespace are
+    /// admitted first.
+    pub fn check(&self, tx: &Transaction, batched: usize) -> Result<(), MempoolError> {
+        let hash = tx.commit();
+        if self.is_pending(hash) {
+            return Err(MempoolError::AlreadyPending { hash });
+        }
+        let namespace = tx.namespace();
+        let count = self.pending_in(namespace);
+        if count + batched >= self.max_pending_per_namespace {
+            return Err(MempoolError::NamespaceFull {
+                namespace,
+                limit: self.max_pending_per_namespace,
+            });
+        }
+        Ok(())
+    }
+
+    /// Admit `tx`, if it is not pending already and its namespace has room.
+    pub fn admit(&mut self, tx: &Transaction) -> Result<(), MempoolError> {
+        let now = Instant::now();
+        self.expire(now);
+        self.check(tx, 0)?;
+
+        let hash = tx.commit();
+        let namespace = tx.namespace();
+        self.pending
+            .insert(hash, (namespace, tx.payload().len() as u64, now));
+        *self.per_namespace.entry(namespace).or_default() += 1;
+        self.order.push_back((now, hash));
+        Ok(())
+    }
+
+    /// Stop tracking `hash`, because it was included in a block or failed to submit.
+    pub fn remove(&mut self, hash: Commitment<Transaction>) {
+        let Some((namespace, _, _)) = self.pending.remove(&hash) else {
+            return;
+        };
+        if let Some(count) = self.per_namespace.get_mut(&namespace) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_namespace.remove(&namespace);
+            }
+        }
+    }
+
+    /// The pending transactions, oldest first, optionally only those in `namespace`.
+    pub fn pending(&self, namespace: Option<NamespaceId>) -> Vec<PendingTransaction> {
+        let now = Instant::now();
+        self.order
+            .iter()
+            .filter_map(|(submitted, hash)| {
+                let (ns, size, latest) = self.pending.get(hash)?;
+                // Skip stale entries for transactions which were removed and submitted again.
+                if latest != submitted || now.duration_since(*latest) > self.ttl {
+                    return None;
+                }
+                if namespace.is_some_and(|namespace| namespace != *ns) {
+                    return None;
+                }
+                Some(PendingTransaction {
+                    hash: *hash,
+                    namespace: *ns,
+                    size: *size,
+                    age: now.duration_since(*latest),
+                })
+            })
+            .collect()
+    }
+
+    /// Stop tracking the transactions included in the blocks decided by `event`.
+    pub fn handle_event(&mut self, event: &Event<SeqTypes>) {
+        let EventType::Decide { leaf_chain, .. } = &event.event else {
+            return;
+        };
+        for LeafInfo { leaf, .. } in leaf_chain.iter() {
+            let Some(payload) = leaf.get_block_payload() else {
+                continue;
+            };
+            let metadata = leaf.get_block_header().metadata();
+            for hash in payload.transaction_commitments(metadata) {
+                self.remove(hash);
+            }
+        }
+        self.expire(Instant::now());
+    }
+
+    fn is_pending(&self, hash: Commitment<Transaction>) -> bool {
+        self.pending
+            .get(&hash)
+            .is_some_and(|(_, _, submitted)| submitted.elapsed() <= self.ttl)
+    }
+
+    fn pending_in(&self, namespace: NamespaceId) -> usize {
+        self.per_namespace.get(&namespace).copied().unwrap_or(0)
+    }
+
+    /// Forget transactions which have been pending for longer than the TTL.
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(submitted, hash)) = self.order.front() {
+            if now.duration_since(submitted) <= self.ttl {
+                break;
+            }
+            self.order.pop_front();
+            // Only remove the transaction if this was its latest submission.
+            if self
+                .pending
+                .get(&hash)
+                .is_some_and(|(_, _, latest)| *latest == submitted)
+            {
+                self.remove(hash);
+            }
+        }
+    }
+}
+
+/// Remove transactions from `mempool` as they are included in the blocks decided in `events`.
+pub(super) async fn track_pending(
+    mempool: Arc<RwLock<Mempool>>,
+    mut events: impl Stream<Item = Event<SeqTypes>> + Unpin,
+) {
+    while let Some(event) = events.next().await {
+        mempool.write().await.handle_event(&event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn test_mempool_limits() {
+        let mut mempool = Mempool::new(2, Duration::from_secs(60));
+        let txs = (0..3u8)
+            .map(|i| Transaction::new(1.into(), vec![i]))
+            .collect::<Vec<_>>();
+        let other = Transaction::new(2.into(), vec![0]);
+
+        mempool.admit(&txs[0]).unwrap();
+        assert_eq!(
+            mempool.admit(&txs[0]),
+            Err(MempoolError::AlreadyPending {
+                hash: txs[0].commit()
+            })
+        );
+        assert_eq!(
+            mempool.check(&txs[1], 1),
+            Err(MempoolError::NamespaceFull {
+                namespace: 1.into(),
+                limit: 2
+            })
+        );
+        mempool.admit(&txs[1]).unwrap();
+        assert!(matches!(
+            mempool.admit(&txs[2]),
+            Err(MempoolError::NamespaceFull { .. })
+        ));
+
+        // The limit is per namespace.
+        mempool.admit(&other).unwrap();
+        assert_eq!(mempool.pending(None).len(), 3);
+        let pending = mempool.pending(Some(1.into()));
+        assert_eq!(
+            pending.iter().map(|tx| tx.hash).collect::<Vec<_>>(),
+            [txs[0].commit(), txs[1].commit()]
+        );
+
+        // Removing a transaction makes room in its namespace.
+        mempool.remove(txs[0].commit());
+        mempool.admit(&txs[2]).unwrap();
+        assert_eq!(mempool.pending(Some(1.into())).len(), 2);
+    }
+
+    #[test]
+    fn test_mempool_ttl() {
+        let mut mempool = Mempool::new(1, Duration::from_millis(100));
+        let tx = Transaction::new(1.into(), vec![1]);
+        let other = Transaction::new(1.into(), vec![2]);
+
+        mempool.admit(&tx).unwrap();
+        sleep(Duration::from_millis(200));
+        assert_eq!(mempool.pending(None), []);
+
+        // An expired transaction no longer counts against the limit, and can be resubmitted.
+        mempool.admit(&other).unwrap();
+        mempool.remove(other.commit());
+        mempool.admit(&tx).unwrap();
+        assert_eq!(mempool.pending(None)[0].hash, tx.commit());
+    }
+}
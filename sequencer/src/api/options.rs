@@ -1,32 +1,47 @@
 //! Sequencer-specific API options and initialization.
 
 use super::{
+    consensus_health::track_consensus,
     data_source::{
         provider, SequencerDataSource, StateDataSource, StateSignatureDataSource, SubmitDataSource,
     },
-    endpoints, fs, sql,
+    endpoints,
+    fee_deposits::index_fee_deposits_loop,
+    fs,
+    light_client::track_finalized_state,
+    mempool::track_pending,
+    pagination::PageLimits,
+    preconfirmation::PreconfirmationStore,
+    rate_limit::{RateLimitListener, RateLimiter, SharedRateLimit},
+    sql,
+    tx_status::track_transactions,
     update::update_loop,
     ApiState, StorageState,
 };
 use crate::{
     context::{SequencerContext, TaskList},
+    events::SlowConsumerPolicy,
     network,
-    persistence::{self, SequencerPersistence},
+    options::{parse_duration, parse_size},
+    persistence::{self, PersistenceOptions, SequencerPersistence},
+    snapshot::track_decides,
     state::{update_state_storage_loop, BlockMerkleTree, FeeMerkleTree},
 };
 use anyhow::bail;
 use async_std::sync::{Arc, RwLock};
 use clap::Parser;
+use ethers::types::Address;
 use futures::{
     channel::oneshot,
     future::{BoxFuture, FutureExt},
 };
 use hotshot_query_service::{
     data_source::{ExtensibleDataSource, MetricsDataSource},
-    status::{self, UpdateStatusData},
+    status::UpdateStatusData,
     Error,
 };
 use hotshot_types::traits::metrics::{Metrics, NoMetrics};
+use std::{sync::atomic::AtomicU64, time::Duration};
 use tide_disco::{
     method::{ReadState, WriteState},
     App, Url,
@@ -43,7 +58,13 @@ pub struct Options {
     pub status: Option<Status>,
     pub catchup: Option<Catchup>,
     pub state: Option<State>,
+    pub fee_deposits: Option<FeeDeposits>,
+    pub light_client: Option<LightClient>,
+    pub preconfirmation: Option<Preconfirmation>,
+    pub rate_limit: Option<SharedRateLimit>,
     pub hotshot_events: Option<HotshotEvents>,
+    #[cfg(feature = "grpc")]
+    pub grpc: Option<Grpc>,
     pub storage_fs: Option<persistence::fs::Options>,
     pub storage_sql: Option<persistence::sql::Options>,
 }
@@ -57,7 +78,13 @@ impl From<Http> for Options {
             status: None,
             catchup: None,
             state: None,
+            fee_deposits: None,
+            light_client: None,
+            preconfirmation: None,
+            rate_limit: None,
             hotshot_events: None,
+            #[cfg(feature = "grpc")]
+            grpc: None,
             storage_fs: None,
             storage_sql: None,
         }
@@ -103,12 +130,60 @@ impl Options {
         self
     }
 
+    /// Add a fee deposits API module.
+    pub fn fee_deposits(mut self, opt: FeeDeposits) -> Self {
+        self.fee_deposits = Some(opt);
+        self
+    }
+
+    /// Add a light client API module.
+    pub fn light_client(mut self, opt: LightClient) -> Self {
+        self.light_client = Some(opt);
+        self
+    }
+
+    /// Add a preconfirmation API module.
+    pub fn preconfirmation(mut self, opt: Preconfirmation) -> Self {
+        self.preconfirmation = Some(opt);
+        self
+    }
+
+    /// Limit the rate of requests to the HTTP API.
+    pub fn rate_limit(mut self, opt: RateLimit) -> Self {
+        self.rate_limit = Some(opt.into());
+        self
+    }
+
+    /// The rate limits of the HTTP API, if any, which can be changed while the server runs.
+    pub fn shared_rate_limit(&self) -> Option<SharedRateLimit> {
+        self.rate_limit.clone()
+    }
+
     /// Add a Hotshot events streaming API module.
     pub fn hotshot_events(mut self, opt: HotshotEvents) -> Self {
         self.hotshot_events = Some(opt);
         self
     }
 
+    /// Add a gRPC server mirroring the submit, availability and status APIs.
+    ///
+    /// The gRPC server is only started along with the query API.
+    #[cfg(feature = "grpc")]
+    pub fn grpc(mut self, opt: Grpc) -> Self {
+        self.grpc = Some(opt);
+        self
+    }
+
+    /// The listener for the HTTP API, which enforces rate limits if they are configured.
+    fn http_listener<S>(&self, metrics: &dyn Metrics) -> RateLimitListener<S> {
+        RateLimitListener::new(
+            self.http.port,
+            self.rate_limit
+                .clone()
+                .map(|opt| RateLimiter::new(opt, metrics)),
+        )
+    }
+
     /// Whether these options will run the query API.
     pub fn has_query_module(&self) -> bool {
         self.query.is_some() && (self.storage_fs.is_some() || self.storage_sql.is_some())
@@ -133,11 +208,14 @@ impl Options {
         // allows the web server to start before initialization can complete, since initialization
         // can take a long time (and is dependent on other nodes).
         let (send_ctx, recv_ctx) = oneshot::channel();
-        let state = ApiState::new(async move {
-            recv_ctx
-                .await
-                .expect("context initialized and sent over channel")
-        });
+        let state = ApiState::new(
+            async move {
+                recv_ctx
+                    .await
+                    .expect("context initialized and sent over channel")
+            },
+            self.submit.unwrap_or_default(),
+        );
         let init_context = move |metrics| {
             let fut = init_context(metrics);
             async move {
@@ -150,6 +228,40 @@ impl Options {
             .boxed()
         };
         let mut tasks = TaskList::default();
+        if self.submit.is_some() {
+            tasks.spawn(
+                "transaction status tracker",
+                track_transactions(
+                    state.tx_status.clone(),
+                    state.event_stream("transaction_status", SlowConsumerPolicy::Drop),
+                ),
+            );
+            tasks.spawn(
+                "mempool tracker",
+                track_pending(
+                    state.mempool.clone(),
+                    state.event_stream("mempool", SlowConsumerPolicy::Drop),
+                ),
+            );
+        }
+        if self.query.is_some() {
+            tasks.spawn(
+                "consensus health tracker",
+                track_consensus(
+                    state.consensus_tracker.clone(),
+                    state.event_stream("consensus_health", SlowConsumerPolicy::Drop),
+                ),
+            );
+        }
+        if self.catchup.is_some() {
+            tasks.spawn(
+                "decide tracker",
+                track_decides(
+                    state.latest_decide.clone(),
+                    state.event_stream("decide_tracker", SlowConsumerPolicy::Drop),
+                ),
+            );
+        }
 
         // The server state type depends on whether we are running a query or status API or not, so
         // we handle the two cases differently.
@@ -185,8 +297,7 @@ impl Options {
             )));
 
             // Initialize status API.
-            let status_api = status::define_api(&Default::default(), bind_version)?;
-            app.register_module("status", status_api)?;
+            app.register_module("status", endpoints::status(bind_version)?)?;
 
             self.init_hotshot_modules::<_, _, _, Ver>(&mut app)?;
 
@@ -200,7 +311,7 @@ impl Options {
 
             tasks.spawn(
                 "API server",
-                app.serve(format!("0.0.0.0:{}", self.http.port), bind_version),
+                app.serve(self.http_listener(&*metrics), bind_version),
             );
 
             metrics
@@ -225,7 +336,7 @@ impl Options {
 
             tasks.spawn(
                 "API server",
-                app.serve(format!("0.0.0.0:{}", self.http.port), bind_version),
+                app.serve(self.http_listener(&NoMetrics), bind_version),
             );
 
             Box::new(NoMetrics)
@@ -236,6 +347,7 @@ impl Options {
 
     async fn init_app_modules<N, P, D, Ver: StaticVersionType + 'static>(
         &self,
+        query_opt: &Query,
         ds: D,
         state: ApiState<N, P, Ver>,
         tasks: &mut TaskList,
@@ -257,24 +369,47 @@ impl Options {
 
         // Initialize status API
         if self.status.is_some() {
-            let status_api = status::define_api::<endpoints::AvailState<N, P, D, Ver>, Ver>(
-                &Default::default(),
-                bind_version,
-            )?;
-            app.register_module("status", status_api)?;
+            app.register_module("status", endpoints::status(bind_version)?)?;
         }
 
         // Initialize availability and node APIs (these both use the same data source).
-        app.register_module("availability", endpoints::availability(bind_version)?)?;
+        app.register_module(
+            "availability",
+            endpoints::availability(bind_version, query_opt.page_limits())?,
+        )?;
         app.register_module("node", endpoints::node(bind_version)?)?;
 
+        if let Some(preconfirmation_opt) = self.preconfirmation {
+            app.register_module(
+                "preconfirmation",
+                endpoints::preconfirmation(
+                    bind_version,
+                    Arc::new(RwLock::new(PreconfirmationStore::new(
+                        preconfirmation_opt.retention,
+                    ))),
+                    preconfirmation_opt.max_delay,
+                )?,
+            )?;
+        }
+
         self.init_hotshot_modules::<_, _, _, Ver>(&mut app)?;
 
         tasks.spawn(
             "query storage updater",
-            update_loop(ds.clone(), state.event_stream()),
+            update_loop(
+                ds.clone(),
+                state.event_stream("query_storage", SlowConsumerPolicy::Spill),
+            ),
         );
 
+        #[cfg(feature = "grpc")]
+        if let Some(grpc) = self.grpc {
+            tasks.spawn(
+                "gRPC server",
+                super::grpc::serve(grpc.grpc_port, ds.clone()),
+            );
+        }
+
         Ok((metrics, ds, app))
     }
 
@@ -291,10 +426,15 @@ impl Options {
         P: SequencerPersistence,
         D: SequencerDataSource + Send + Sync + 'static,
     {
-        let ds = D::create(mod_opt, provider(query_opt.peers, bind_version), false).await?;
+        let ds = D::create(
+            mod_opt,
+            provider(query_opt.peers.clone(), bind_version),
+            false,
+        )
+        .await?;
 
         let (metrics, _, app) = self
-            .init_app_modules(ds, state.clone(), tasks, bind_version)
+            .init_app_modules(&query_opt, ds, state.clone(), tasks, bind_version)
             .await?;
 
         if self.hotshot_events.is_some() {
@@ -303,7 +443,7 @@ impl Options {
 
         tasks.spawn(
             "API server",
-            app.serve(format!("0.0.0.0:{}", self.http.port), Ver::instance()),
+            app.serve(self.http_listener(&*metrics), Ver::instance()),
         );
         Ok(metrics)
     }
@@ -327,19 +467,41 @@ impl Options {
         )
         .await?;
         let (metrics, ds, mut app) = self
-            .init_app_modules(ds, state.clone(), tasks, bind_version)
+            .init_app_modules(&query_opt, ds, state.clone(), tasks, bind_version)
             .await?;
 
-        if self.state.is_some() {
+        if let Some(deposits_opt) = self.fee_deposits {
+            let db = Arc::new(mod_opt.clone().create().await?);
+            app.register_module("fee-deposits", endpoints::fee_deposits(db, bind_version)?)?;
+
+            let db = mod_opt.clone().create().await?;
+            let state = state.clone();
+            let l1_client = async move { state.node_state().await.l1_client().clone() };
+            tasks.spawn(
+                "fee deposit indexer",
+                index_fee_deposits_loop(db, l1_client, deposits_opt),
+            );
+        }
+
+        if let Some(state_opt) = self.state {
+            // The earliest height with merklized state, which the pruner advances.
+            let earliest_height = Arc::new(AtomicU64::new(0));
+
             // Initialize merklized state module for block merkle tree
             app.register_module(
                 "block-state",
-                endpoints::merklized_state::<N, P, _, BlockMerkleTree, _, 3>(bind_version)?,
+                endpoints::merklized_state::<N, P, _, BlockMerkleTree, _, 3>(
+                    bind_version,
+                    earliest_height.clone(),
+                )?,
             )?;
             // Initialize merklized state module for fee merkle tree
             app.register_module(
                 "fee-state",
-                endpoints::merklized_state::<N, P, _, FeeMerkleTree, _, 256>(bind_version)?,
+                endpoints::merklized_state::<N, P, _, FeeMerkleTree, _, 256>(
+                    bind_version,
+                    earliest_height.clone(),
+                )?,
             )?;
 
             let state = state.clone();
@@ -348,6 +510,39 @@ impl Options {
                 "merklized state storage update loop",
                 update_state_storage_loop(ds, get_node_state),
             );
+
+            if state_opt.snapshot_interval > 0 {
+                let db = mod_opt.clone().create().await?;
+                tasks.spawn(
+                    "fee merkle tree snapshot loop",
+                    persistence::sql::snapshot_fee_merkle_tree_loop(
+                        db,
+                        state_opt.snapshot_interval,
+                    ),
+                );
+            }
+            if let Some(retention) = state_opt.merklized_state_retention {
+                let db = mod_opt.create().await?;
+                tasks.spawn(
+                    "merklized state pruner",
+                    persistence::sql::prune_merklized_state_loop(db, retention, earliest_height),
+                );
+            }
+        }
+
+        if let Some(light_client_opt) = self.light_client {
+            let finalized = Arc::new(RwLock::new(None));
+            app.register_module(
+                "light-client",
+                endpoints::light_client(bind_version, finalized.clone())?,
+            )?;
+
+            let state = state.clone();
+            let l1_client = async move { state.node_state().await.l1_client().clone() };
+            tasks.spawn(
+                "light client state tracker",
+                track_finalized_state(finalized, l1_client, light_client_opt),
+            );
         }
 
         if self.hotshot_events.is_some() {
@@ -356,7 +551,7 @@ impl Options {
 
         tasks.spawn(
             "API server",
-            app.serve(format!("0.0.0.0:{}", self.http.port), Ver::instance()),
+            app.serve(self.http_listener(&*metrics), Ver::instance()),
         );
         Ok(metrics)
     }
@@ -452,8 +647,47 @@ pub struct Http {
 }
 
 /// Options for the submission API module.
-#[derive(Parser, Clone, Copy, Debug, Default)]
-pub struct Submit;
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct Submit {
+    /// How long to keep the status of a submitted transaction after it last changed.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_TX_STATUS_RETENTION",
+        value_parser = parse_duration,
+        default_value = "1h"
+    )]
+    pub tx_status_retention: Duration,
+
+    /// Maximum number of transactions from one namespace pending at once.
+    ///
+    /// Further transactions in the namespace are refused until some of its pending transactions
+    /// are included in a block or expire.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_MAX_PENDING_PER_NAMESPACE",
+        default_value = "1000"
+    )]
+    pub max_pending_per_namespace: usize,
+
+    /// How long a submitted transaction stays pending before it expires and may be resubmitted.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_PENDING_TX_TTL",
+        value_parser = parse_duration,
+        default_value = "5m"
+    )]
+    pub pending_ttl: Duration,
+}
+
+impl Default for Submit {
+    fn default() -> Self {
+        Self {
+            tx_status_retention: Duration::from_secs(60 * 60),
+            max_pending_per_namespace: 1000,
+            pending_ttl: Duration::from_secs(5 * 60),
+        }
+    }
+}
 
 /// Options for the status API module.
 #[derive(Parser, Clone, Copy, Debug, Default)]
@@ -464,16 +698,231 @@ pub struct Status;
 pub struct Catchup;
 
 /// Options for the query API module.
-#[derive(Parser, Clone, Debug, Default)]
+#[derive(Parser, Clone, Debug)]
 pub struct Query {
     /// Peers for fetching missing data for the query service.
     #[clap(long, env = "ESPRESSO_SEQUENCER_API_PEERS")]
     pub peers: Vec<Url>,
+
+    /// Maximum number of objects in one page of a paginated range query.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_API_MAX_PAGE_SIZE",
+        default_value = "100"
+    )]
+    pub max_page_size: usize,
+
+    /// Size at which a page of blocks or payloads is cut off.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_API_MAX_PAGE_BYTES",
+        value_parser = parse_size,
+        default_value = "10mb"
+    )]
+    pub max_page_bytes: u64,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Self {
+            peers: vec![],
+            max_page_size: 100,
+            max_page_bytes: 10_000_000,
+        }
+    }
+}
+
+impl Query {
+    fn page_limits(&self) -> PageLimits {
+        PageLimits {
+            max_items: self.max_page_size,
+            max_bytes: self.max_page_bytes,
+        }
+    }
 }
 
 /// Options for the state API module.
-#[derive(Parser, Clone, Copy, Debug, Default)]
-pub struct State;
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct State {
+    /// Number of blocks between snapshots of the fee merkle tree.
+    ///
+    /// Snapshots let a restarting node load the fee merkle tree without reading it node by node
+    /// from the merklized state tables. Set to 0 to disable snapshots.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_STATE_SNAPSHOT_INTERVAL",
+        default_value = "1000"
+    )]
+    pub snapshot_interval: u64,
+
+    /// Number of recent blocks to keep the merklized state of.
+    ///
+    /// Older versions of the merkle tree nodes are pruned, so the state can only be queried at the
+    /// latest heights. By default, the state at every height is kept.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_MERKLIZED_STATE_RETENTION")]
+    pub merklized_state_retention: Option<u64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            snapshot_interval: 1000,
+            merklized_state_retention: None,
+        }
+    }
+}
+
+/// Options for the fee deposits API module.
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct FeeDeposits {
+    /// Address of the fee contract on the L1.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_FEE_CONTRACT_ADDRESS")]
+    pub fee_contract_address: Address,
+
+    /// L1 block to start indexing deposits from.
+    ///
+    /// This is usually the block the fee contract was deployed in. When the index already has
+    /// deposits, indexing resumes from the last block scanned instead.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_FEE_DEPOSITS_START_BLOCK",
+        default_value = "0"
+    )]
+    pub start_block: u64,
+
+    /// Number of L1 blocks which must be built on a block before its deposits are indexed.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_FEE_DEPOSITS_CONFIRMATIONS",
+        default_value = "12"
+    )]
+    pub confirmations: u64,
+
+    /// How often to check the L1 for new deposits.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_FEE_DEPOSITS_POLL_INTERVAL",
+        value_parser = parse_duration,
+        default_value = "12s"
+    )]
+    pub poll_interval: Duration,
+}
+
+/// Options for the light client API module.
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct LightClient {
+    /// Address of the light client contract on the L1.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_LIGHT_CLIENT_PROXY_ADDRESS")]
+    pub light_client_address: Address,
+
+    /// How often to read the finalized state from the light client contract.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_LIGHT_CLIENT_POLL_INTERVAL",
+        value_parser = parse_duration,
+        default_value = "12s"
+    )]
+    pub poll_interval: Duration,
+}
+
+/// Options for the preconfirmation API module.
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct Preconfirmation {
+    /// Number of blocks after the current block height within which this node promises to include
+    /// a preconfirmed transaction.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_PRECONFIRMATION_MAX_DELAY",
+        default_value = "10"
+    )]
+    pub max_delay: u64,
+
+    /// Number of blocks after the end of its range for which a promise is kept, so that evidence of
+    /// a broken promise can be served.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_PRECONFIRMATION_RETENTION",
+        default_value = "10000"
+    )]
+    pub retention: u64,
+}
+
+impl Default for Preconfirmation {
+    fn default() -> Self {
+        Self {
+            max_delay: 10,
+            retention: 10000,
+        }
+    }
+}
+
+/// Options for rate limiting the HTTP API.
+///
+/// Clients are identified by API key if they send a known one in the `X-Api-Key` header, and by IP
+/// address otherwise. Each client is limited separately on transaction submission and on all other
+/// endpoints. A limit of 0 disables limiting.
+#[derive(Parser, Clone, Debug)]
+pub struct RateLimit {
+    /// Requests per second to the submit API allowed from each IP address.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_API_SUBMIT_RATE_LIMIT",
+        default_value = "10"
+    )]
+    pub submit_rate_limit: f64,
+
+    /// Requests per second to query endpoints allowed from each IP address.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_API_QUERY_RATE_LIMIT",
+        default_value = "20"
+    )]
+    pub query_rate_limit: f64,
+
+    /// Requests per second to the submit API allowed with each API key.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_API_KEY_SUBMIT_RATE_LIMIT",
+        default_value = "100"
+    )]
+    pub api_key_submit_rate_limit: f64,
+
+    /// Requests per second to query endpoints allowed with each API key.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_API_KEY_QUERY_RATE_LIMIT",
+        default_value = "200"
+    )]
+    pub api_key_query_rate_limit: f64,
+
+    /// Window over which rates are measured.
+    ///
+    /// A client which has been idle may burst a whole window's worth of requests at once.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_API_RATE_LIMIT_WINDOW",
+        value_parser = parse_duration,
+        default_value = "10s"
+    )]
+    pub rate_limit_window: Duration,
+
+    /// API keys which are accepted.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_API_KEYS", value_delimiter = ',')]
+    pub api_keys: Vec<String>,
+
+    /// Refuse requests without a known API key.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_API_REQUIRE_API_KEY")]
+    pub require_api_key: bool,
+}
+
+/// Options for the gRPC API module.
+#[cfg(feature = "grpc")]
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct Grpc {
+    /// Port that the gRPC API will use.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_GRPC_PORT")]
+    pub grpc_port: u16,
+}
 
 /// Options for the Hotshot events streaming API module.
 #[derive(Parser, Clone, Copy, Debug, Default)]
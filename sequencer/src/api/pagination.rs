@@ -0,0 +1,140 @@
+//! Paginated range queries for the availability API.
+//!
+//! The range endpoints of the query service return a whole range in one response, which for a large
+//! range of large blocks can exhaust the memory of the node or outlast the timeout of the client.
+//! The page endpoints instead return one [`Page`] at a time, starting from a cursor, with a limited
+//! number of objects and, for payload-heavy objects, a limited number of bytes. Each page gives the
+//! cursor of the next one. Clients which want a long range of blocks or payloads without paging
+//! through it can stream the range over a WebSocket, which sends one object at a time.
+
+use futures::Future;
+use serde::{Deserialize, Serialize};
+
+/// A page of consecutive objects from a range query.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The cursor to request the next page with: the height after the last object in this page.
+    pub next: u64,
+    /// The block height of the chain when the page was made.
+    ///
+    /// The range is complete up to the head of the chain once `next` reaches `block_height`.
+    pub block_height: u64,
+}
+
+/// Server-enforced limits on the size of a page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageLimits {
+    /// The most objects in one page.
+    pub max_items: usize,
+    /// The size in bytes at which a page is cut off.
+    ///
+    /// A page may go over this size by one object, and always contains at least one object if there
+    /// are any in its range, so that clients make progress even through oversized objects.
+    pub max_bytes: u64,
+}
+
+/// Fetch the page of objects starting at `cursor`, with at most `limit` objects.
+///
+/// Only objects below `block_height` are included. `fetch` fetches the object at a given height.
+pub(super) async fn fetch_page<T, E, F, Fut>(
+    cursor: u64,
+    limit: Option<usize>,
+    block_height: u64,
+    limits: PageLimits,
+    mut fetch: F,
+) -> Result<Page<T>, E>
+where
+    T: Serialize,
+    F: FnMut(u64) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let limit = limit
+        .unwrap_or(limits.max_items)
+        .clamp(1, limits.max_items.max(1));
+    let end = block_height.min(cursor.saturating_add(limit as u64));
+
+    let mut items = vec![];
+    let mut bytes = 0;
+    let mut next = cursor;
+    while next < end && (items.is_empty() || bytes < limits.max_bytes) {
+        let item = fetch(next).await?;
+        bytes += bincode::serialized_size(&item).unwrap_or_default();
+        items.push(item);
+        next += 1;
+    }
+    Ok(Page {
+        items,
+        next,
+        block_height,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::Infallible;
+
+    async fn page(
+        cursor: u64,
+        limit: Option<usize>,
+        block_height: u64,
+        max_items: usize,
+        max_bytes: u64,
+    ) -> Page<Vec<u8>> {
+        // Each object serializes to 8 bytes of length prefix and 8 bytes of data.
+        fetch_page(
+            cursor,
+            limit,
+            block_height,
+            PageLimits {
+                max_items,
+                max_bytes,
+            },
+            |height| async move { Ok::<_, Infallible>(height.to_le_bytes().to_vec()) },
+        )
+        .await
+        .unwrap()
+    }
+
+    fn heights(page: &Page<Vec<u8>>) -> Vec<u64> {
+        page.items
+            .iter()
+            .map(|item| u64::from_le_bytes(item.as_slice().try_into().unwrap()))
+            .collect()
+    }
+
+    #[async_std::test]
+    async fn test_fetch_page() {
+        // Pages are limited by the requested limit, up to the server's maximum.
+        let p = page(0, Some(3), 100, 10, 1000).await;
+        assert_eq!(heights(&p), [0, 1, 2]);
+        assert_eq!(p.next, 3);
+        assert_eq!(p.block_height, 100);
+        let p = page(3, None, 100, 10, 1000).await;
+        assert_eq!(heights(&p), (3..13).collect::<Vec<_>>());
+        assert_eq!(p.next, 13);
+        let p = page(3, Some(50), 100, 10, 1000).await;
+        assert_eq!(p.next, 13);
+        let p = page(3, Some(0), 100, 10, 1000).await;
+        assert_eq!(heights(&p), [3]);
+
+        // Pages end at the head of the chain.
+        let p = page(98, None, 100, 10, 1000).await;
+        assert_eq!(heights(&p), [98, 99]);
+        assert_eq!(p.next, 100);
+        let p = page(100, None, 100, 10, 1000).await;
+        assert_eq!(heights(&p), Vec::<u64>::new());
+        assert_eq!(p.next, 100);
+
+        // Pages are cut off once they reach the byte limit.
+        let p = page(0, None, 100, 10, 32).await;
+        assert_eq!(heights(&p), [0, 1]);
+        let p = page(0, None, 100, 10, 33).await;
+        assert_eq!(heights(&p), [0, 1, 2]);
+        // But always make progress.
+        let p = page(0, None, 100, 10, 1).await;
+        assert_eq!(heights(&p), [0]);
+        assert_eq!(p.next, 1);
+    }
+}
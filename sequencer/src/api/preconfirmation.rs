@@ -0,0 +1,268 @@
+//! Signed promises to include submitted transactions within a bounded number of blocks.
+//!
+//! When a transaction is submitted through the preconfirmation API, the node signs a
+//! [`Preconfirmation`] with its consensus key, promising that the transaction will be in one of the
+//! blocks from the current block height up to a maximum height. Rollups can show the promise to
+//! their users as a soft confirmation. If the promise is broken, the node serves a [`Breach`]: the
+//! signed promise, along with the complete contents of the transaction's namespace in every block of
+//! the promised range, which anyone can check to see that the transaction is in none of them.
+
+use super::endpoints::NamespaceProofQueryData;
+use crate::{Header, NamespaceId, PubKey, Transaction};
+use anyhow::{ensure, Context};
+use committable::{Commitment, Committable, RawCommitmentBuilder};
+use hotshot_types::traits::signature_key::SignatureKey;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// A promise to include a transaction in a block with height in `[from_height, max_height]`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Preconfirmation {
+    pub transaction: Commitment<Transaction>,
+    pub namespace: NamespaceId,
+    /// The block height when the promise was made.
+    pub from_height: u64,
+    pub max_height: u64,
+    /// The consensus key of the node which made the promise.
+    pub node: PubKey,
+}
+
+impl Committable for Preconfirmation {
+    fn tag() -> String {
+        "PRECONFIRMATION".into()
+    }
+
+    fn commit(&self) -> Commitment<Self> {
+        RawCommitmentBuilder::new(&Self::tag())
+            .field("transaction", self.transaction)
+            .u64_field("namespace", self.namespace.into())
+            .u64_field("from_height", self.from_height)
+            .u64_field("max_height", self.max_height)
+            .var_size_field("node", &self.node.to_bytes())
+            .finalize()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedPreconfirmation {
+    pub preconfirmation: Preconfirmation,
+    /// The signature of the promising node over the commitment of `preconfirmation`.
+    pub signature: <PubKey as SignatureKey>::PureAssembledSignatureType,
+}
+
+impl SignedPreconfirmation {
+    pub fn sign(
+        preconfirmation: Preconfirmation,
+        private_key: &<PubKey as SignatureKey>::PrivateKey,
+    ) -> anyhow::Result<Self> {
+        let signature = PubKey::sign(private_key, preconfirmation.commit().as_ref())
+            .context("signing preconfirmation")?;
+        Ok(Self {
+            preconfirmation,
+            signature,
+        })
+    }
+
+    /// Check that the promise was signed by the node it names.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.preconfirmation
+                .node
+                .validate(&self.signature, self.preconfirmation.commit().as_ref()),
+            "invalid preconfirmation signature"
+        );
+        Ok(())
+    }
+}
+
+/// Evidence that a node broke a [`Preconfirmation`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Breach {
+    pub preconfirmation: SignedPreconfirmation,
+    /// For each block in the promised range, in order, its header and the complete transactions of
+    /// the promised namespace.
+    pub blocks: Vec<(Header, NamespaceProofQueryData)>,
+}
+
+impl Breach {
+    /// Check that the signed promise was broken in the blocks given.
+    ///
+    /// This checks each namespace against the payload commitment of its header, but not the headers
+    /// themselves: the caller should check that they are part of the chain, for example with an
+    /// [inclusion proof](super::light_client::InclusionProof).
+    pub fn verify(&self) -> anyhow::Result<()> {
+        self.preconfirmation.verify()?;
+        let promise = &self.preconfirmation.preconfirmation;
+        ensure!(
+            self.blocks
+                .iter()
+                .map(|(header, _)| header.height)
+                .eq(promise.from_height..=promise.max_height),
+            "blocks do not cover the promised range [{}, {}]",
+            promise.from_height,
+            promise.max_height
+        );
+        for (header, ns) in &self.blocks {
+            ns.verify(header, promise.namespace)
+                .with_context(|| format!("namespace proof for block {}", header.height))?;
+            ensure!(
+                !ns.transactions
+                    .iter()
+                    .any(|tx| tx.commit() == promise.transaction),
+                "transaction is included in block {}",
+                header.height
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The promises made by this node which may still be checked for breaches.
+#[derive(Debug)]
+pub struct PreconfirmationStore {
+    /// How many blocks after the end of its range a promise is kept.
+    retention: u64,
+    promises: HashMap<Commitment<Transaction>, SignedPreconfirmation>,
+    /// The promises ending at each height, for expiring them.
+    by_max_height: BTreeMap<u64, Vec<Commitment<Transaction>>>,
+}
+
+impl PreconfirmationStore {
+    pub fn new(retention: u64) -> Self {
+        Self {
+            retention,
+            promises: Default::default(),
+            by_max_height: Default::default(),
+        }
+    }
+
+    /// The latest promise made for the transaction `hash`, if it has not expired.
+    pub fn get(&self, hash: Commitment<Transaction>) -> Option<&SignedPreconfirmation> {
+        self.promises.get(&hash)
+    }
+
+    /// Remember a promise, replacing any earlier promise for the same transaction.
+    pub fn insert(&mut self, promise: SignedPreconfirmation) {
+        let from_height = promise.preconfirmation.from_height;
+        let hash = promise.preconfirmation.transaction;
+        self.by_max_height
+            .entry(promise.preconfirmation.max_height)
+            .or_default()
+            .push(hash);
+        self.promises.insert(hash, promise);
+        self.expire(from_height);
+    }
+
+    /// Forget promises whose range ended more than the retention period before `block_height`.
+    fn expire(&mut self, block_height: u64) {
+        while let Some(entry) = self.by_max_height.first_entry() {
+            if entry.key() + self.retention >= block_height {
+                break;
+            }
+            let max_height = *entry.key();
+            for hash in entry.remove() {
+                // Only forget the promise if it has not been replaced by a later one.
+                if self
+                    .promises
+                    .get(&hash)
+                    .is_some_and(|p| p.preconfirmation.max_height == max_height)
+                {
+                    self.promises.remove(&hash);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{block::payload::NamespaceProof, Leaf, NodeState};
+
+    fn promise(tx: &Transaction, from_height: u64, max_height: u64) -> SignedPreconfirmation {
+        let (node, private_key) = PubKey::generated_from_seed_indexed([0; 32], 0);
+        SignedPreconfirmation::sign(
+            Preconfirmation {
+                transaction: tx.commit(),
+                namespace: tx.namespace(),
+                from_height,
+                max_height,
+                node,
+            },
+            &private_key,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_preconfirmation_signature() {
+        let tx = Transaction::new(Default::default(), vec![1, 2, 3]);
+        let signed = promise(&tx, 1, 3);
+        signed.verify().unwrap();
+
+        // Changing the promise invalidates the signature.
+        let mut forged = signed.clone();
+        forged.preconfirmation.max_height = 10;
+        forged.verify().unwrap_err();
+
+        // So does claiming it was made by another node.
+        let mut forged = signed;
+        forged.preconfirmation.node = PubKey::generated_from_seed_indexed([0; 32], 1).0;
+        forged.verify().unwrap_err();
+    }
+
+    #[test]
+    fn test_breach() {
+        let tx = Transaction::new(Default::default(), vec![1, 2, 3]);
+        let genesis = Leaf::genesis(&NodeState::mock());
+        // The genesis block has no namespaces, so every block in the range proves the namespace of
+        // the transaction is empty.
+        let empty = NamespaceProofQueryData {
+            proof: NamespaceProof::NonExistence {
+                ns_id: tx.namespace(),
+            },
+            transactions: vec![],
+        };
+        let blocks = (1..=3)
+            .map(|height| {
+                let mut header = genesis.get_block_header().clone();
+                header.height = height;
+                (header, empty.clone())
+            })
+            .collect::<Vec<_>>();
+
+        let breach = Breach {
+            preconfirmation: promise(&tx, 1, 3),
+            blocks: blocks.clone(),
+        };
+        breach.verify().unwrap();
+
+        // The blocks must cover the whole promised range.
+        let breach = Breach {
+            preconfirmation: promise(&tx, 1, 4),
+            blocks,
+        };
+        breach.verify().unwrap_err();
+    }
+
+    #[test]
+    fn test_preconfirmation_store() {
+        let tx = Transaction::new(Default::default(), vec![1, 2, 3]);
+        let other = Transaction::new(Default::default(), vec![4, 5, 6]);
+        let mut store = PreconfirmationStore::new(5);
+
+        store.insert(promise(&tx, 0, 10));
+        assert_eq!(store.get(tx.commit()), Some(&promise(&tx, 0, 10)));
+
+        // A new promise for the same transaction replaces the old one, and is not expired with it.
+        store.insert(promise(&tx, 12, 20));
+        store.insert(promise(&other, 16, 26));
+        assert_eq!(store.get(tx.commit()), Some(&promise(&tx, 12, 20)));
+        assert_eq!(store.get(other.commit()), Some(&promise(&other, 16, 26)));
+
+        // Promises are forgotten after the retention period.
+        store.insert(promise(&other, 26, 30));
+        assert_eq!(store.get(tx.commit()), None);
+        assert_eq!(store.get(other.commit()), Some(&promise(&other, 26, 30)));
+    }
+}
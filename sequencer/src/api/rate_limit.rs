@@ -0,0 +1,568 @@
+//! Rate limiting and API key authentication for the HTTP API.
+//!
+//! Public query nodes serve anyone who connects, and some requests, such as range queries, are
+//! expensive to answer. To keep one client from starving the rest, each client may only make a
+//! limited number of requests per second to each [group](EndpointGroup) of endpoints. Clients which
+//! send a known API key in the `X-Api-Key` header are identified by their key and get their own
+//! limits; other clients are identified by IP address. Requests over the limit are refused with
+//! `429 Too Many Requests` before they reach the API.
+//!
+//! The API framework has no hook which runs before the handlers of every module, so the limits are
+//! enforced by a [`RateLimitListener`] which accepts connections for the HTTP server.
+//!
+//! The limits and API keys are held in a [`SharedRateLimit`], so they can be changed while the
+//! server is running, without dropping the state of existing clients.
+
+use super::options::RateLimit;
+use async_std::{
+    net::{TcpListener, TcpStream},
+    stream::StreamExt,
+    sync::Arc,
+    task::{sleep, spawn},
+};
+use derivative::Derivative;
+use hotshot_types::traits::metrics::{Counter, Metrics};
+use http_types::{Request, Response, StatusCode};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    io,
+    net::{IpAddr, Ipv4Addr},
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tide::{
+    listener::{ListenInfo, Listener},
+    Server,
+};
+
+/// The header in which clients send their API key.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Endpoints which are limited together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EndpointGroup {
+    /// Transaction submission.
+    Submit,
+    /// Everything else: availability, node, state and other queries.
+    Query,
+}
+
+impl EndpointGroup {
+    /// The group of the endpoint at `path`, or [`None`] if the endpoint is not limited.
+    fn of(path: &str) -> Option<Self> {
+        let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+        let mut module = segments.next()?;
+        // Skip an API version prefix, like `v0`.
+        if module.starts_with('v') && module[1..].parse::<u64>().is_ok() {
+            module = segments.next()?;
+        }
+        match module {
+            // Health checks come from load balancers and monitoring, and are cheap.
+            "healthcheck" | "version" => None,
+            "submit" => Some(Self::Submit),
+            _ => Some(Self::Query),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Submit => "submit",
+            Self::Query => "query",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Client {
+    Ip(IpAddr),
+    ApiKey(String),
+}
+
+/// What to do with a request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Verdict {
+    Allow,
+    Limited { retry_after: Duration },
+    Unauthorized,
+}
+
+/// A token bucket, which refills at the rate limit of its client.
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct RateLimitMetrics {
+    limited: HashMap<EndpointGroup, Box<dyn Counter>>,
+    unauthorized: Box<dyn Counter>,
+}
+
+impl RateLimitMetrics {
+    fn new(metrics: &dyn Metrics) -> Self {
+        Self {
+            limited: [EndpointGroup::Submit, EndpointGroup::Query]
+                .into_iter()
+                .map(|group| {
+                    (
+                        group,
+                        metrics.create_counter(
+                            format!("rate_limited_{}_requests", group.name()),
+                            None,
+                        ),
+                    )
+                })
+                .collect(),
+            unauthorized: metrics.create_counter("unauthorized_requests".into(), None),
+        }
+    }
+}
+
+/// Rate limits which can be changed while the server enforcing them is running.
+#[derive(Clone, Debug)]
+pub struct SharedRateLimit(Arc<RwLock<Limits>>);
+
+#[derive(Debug)]
+struct Limits {
+    opt: RateLimit,
+    api_keys: HashSet<String>,
+}
+
+impl From<RateLimit> for SharedRateLimit {
+    fn from(opt: RateLimit) -> Self {
+        Self(Arc::new(RwLock::new(Limits::new(opt))))
+    }
+}
+
+impl SharedRateLimit {
+    /// The limits currently in force.
+    pub fn get(&self) -> RateLimit {
+        self.0.read().unwrap().opt.clone()
+    }
+
+    /// Replace the limits.
+    ///
+    /// Clients keep the requests they have left, up to the capacity allowed by the new limits.
+    pub fn set(&self, opt: RateLimit) {
+        *self.0.write().unwrap() = Limits::new(opt);
+    }
+}
+
+impl Limits {
+    fn new(opt: RateLimit) -> Self {
+        Self {
+            api_keys: opt.api_keys.iter().cloned().collect(),
+            opt,
+        }
+    }
+
+    /// The rate limit for `client` on endpoints in `group`, in requests per second.
+    fn rate(&self, client: &Client, group: EndpointGroup) -> f64 {
+        match (client, group) {
+            (Client::Ip(_), EndpointGroup::Submit) => self.opt.submit_rate_limit,
+            (Client::Ip(_), EndpointGroup::Query) => self.opt.query_rate_limit,
+            (Client::ApiKey(_), EndpointGroup::Submit) => self.opt.api_key_submit_rate_limit,
+            (Client::ApiKey(_), EndpointGroup::Query) => self.opt.api_key_query_rate_limit,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: SharedRateLimit,
+    buckets: Mutex<HashMap<(Client, EndpointGroup), Bucket>>,
+    last_pruned: Mutex<Instant>,
+    metrics: RateLimitMetrics,
+}
+
+impl RateLimiter {
+    pub fn new(limits: impl Into<SharedRateLimit>, metrics: &dyn Metrics) -> Self {
+        Self {
+            limits: limits.into(),
+            buckets: Default::default(),
+            last_pruned: Mutex::new(Instant::now()),
+            metrics: RateLimitMetrics::new(metrics),
+        }
+    }
+
+    fn check(&self, ip: IpAddr, api_key: Option<&str>, path: &str, now: Instant) -> Verdict {
+        let Some(group) = EndpointGroup::of(path) else {
+            return Verdict::Allow;
+        };
+        let limits = self.limits.0.read().unwrap();
+        let client = match api_key {
+            Some(key) if limits.api_keys.contains(key) => Client::ApiKey(key.to_string()),
+            Some(_) => return Verdict::Unauthorized,
+            None if limits.opt.require_api_key => return Verdict::Unauthorized,
+            None => Client::Ip(ip),
+        };
+        let rate = limits.rate(&client, group);
+        if rate <= 0. {
+            return Verdict::Allow;
+        }
+        // A client can spend a whole window of its allowance at once.
+        let window = limits.opt.rate_limit_window;
+        let capacity = (rate * window.as_secs_f64()).max(1.);
+        drop(limits);
+
+        let mut buckets = self.buckets.lock().unwrap();
+        self.prune(&mut buckets, window, now);
+        let bucket = buckets.entry((client, group)).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1. {
+            bucket.tokens -= 1.;
+            Verdict::Allow
+        } else {
+            Verdict::Limited {
+                retry_after: Duration::from_secs_f64((1. - bucket.tokens) / rate),
+            }
+        }
+    }
+
+    /// Forget clients which have been idle for a whole window.
+    ///
+    /// Their buckets have refilled, so forgetting them changes nothing but the memory we use.
+    fn prune(
+        &self,
+        buckets: &mut HashMap<(Client, EndpointGroup), Bucket>,
+        window: Duration,
+        now: Instant,
+    ) {
+        let mut last_pruned = self.last_pruned.lock().unwrap();
+        if now.saturating_duration_since(*last_pruned) < window {
+            return;
+        }
+        buckets.retain(|_, bucket| now.saturating_duration_since(bucket.updated) < window);
+        *last_pruned = now;
+    }
+
+    /// Decide whether to serve `req`, responding on behalf of the server if not.
+    fn filter(&self, ip: IpAddr, req: &Request) -> Option<Response> {
+        let api_key = req
+            .header(API_KEY_HEADER)
+            .map(|values| values.last().as_str());
+        let path = req.url().path();
+        match self.check(ip, api_key, path, Instant::now()) {
+            Verdict::Allow => None,
+            Verdict::Limited { retry_after } => {
+                if let Some(group) = EndpointGroup::of(path) {
+                    self.metrics.limited[&group].add(1);
+                }
+                tracing::debug!(%ip, path, ?retry_after, "rate limited request");
+                let mut res = Response::new(StatusCode::TooManyRequests);
+                res.insert_header("Retry-After", retry_after.as_secs().max(1).to_string());
+                res.set_body("rate limit exceeded");
+                Some(res)
+            }
+            Verdict::Unauthorized => {
+                tracing::debug!(%ip, path, "unauthorized request");
+                self.metrics.unauthorized.add(1);
+                let mut res = Response::new(StatusCode::Unauthorized);
+                res.set_body(format!("missing or unknown {API_KEY_HEADER}"));
+                Some(res)
+            }
+        }
+    }
+}
+
+/// A TCP listener for the HTTP server which applies rate limits to each request, if configured.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+pub struct RateLimitListener<State> {
+    port: u16,
+    limiter: Option<Arc<RateLimiter>>,
+    listener: Option<TcpListener>,
+    #[derivative(Debug = "ignore")]
+    server: Option<Server<State>>,
+    info: Option<ListenInfo>,
+}
+
+impl<State> RateLimitListener<State> {
+    pub fn new(port: u16, limiter: Option<RateLimiter>) -> Self {
+        Self {
+            port,
+            limiter: limiter.map(Arc::new),
+            listener: None,
+            server: None,
+            info: None,
+        }
+    }
+}
+
+impl<State> Display for RateLimitListener<State> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "http://0.0.0.0:{}", self.port)
+    }
+}
+
+#[async_trait::async_trait]
+impl<State> Listener<State> for RateLimitListener<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    async fn bind(&mut self, server: Server<State>) -> io::Result<()> {
+        self.listener = Some(TcpListener::bind(("0.0.0.0", self.port)).await?);
+        self.server = Some(server);
+        self.info = Some(ListenInfo::new(self.to_string(), "tcp".into(), false));
+        Ok(())
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        let server = self
+            .server
+            .take()
+            .expect("listener must be bound before accepting connections");
+        let listener = self
+            .listener
+            .take()
+            .expect("listener must be bound before accepting connections");
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(stream) => {
+                    spawn(handle_connection(
+                        server.clone(),
+                        self.limiter.clone(),
+                        stream,
+                    ));
+                }
+                Err(err) => {
+                    tracing::warn!("error accepting connection: {err}");
+                    sleep(Duration::from_millis(500)).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        self.info.iter().cloned().collect()
+    }
+}
+
+async fn handle_connection<State>(
+    server: Server<State>,
+    limiter: Option<Arc<RateLimiter>>,
+    stream: TcpStream,
+) where
+    State: Clone + Send + Sync + 'static,
+{
+    let local_addr = stream.local_addr().ok();
+    let peer_addr = stream.peer_addr().ok();
+    let ip = peer_addr.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |addr| addr.ip());
+    let res = async_h1::accept(stream, |mut req| {
+        let server = server.clone();
+        let limiter = limiter.clone();
+        async move {
+            if let Some(res) = limiter.and_then(|limiter| limiter.filter(ip, &req)) {
+                return Ok(res);
+            }
+            req.set_local_addr(local_addr);
+            req.set_peer_addr(peer_addr);
+            server.respond(req).await
+        }
+    })
+    .await;
+    if let Err(err) = res {
+        tracing::debug!(%ip, "HTTP connection error: {err}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hotshot_types::traits::metrics::NoMetrics;
+
+    fn limiter(api_keys: Vec<String>, require_api_key: bool) -> RateLimiter {
+        RateLimiter::new(
+            RateLimit {
+                submit_rate_limit: 1.,
+                query_rate_limit: 2.,
+                api_key_submit_rate_limit: 10.,
+                api_key_query_rate_limit: 0.,
+                rate_limit_window: Duration::from_secs(2),
+                api_keys,
+                require_api_key,
+            },
+            &NoMetrics,
+        )
+    }
+
+    #[test]
+    fn test_endpoint_group() {
+        assert_eq!(
+            EndpointGroup::of("/submit/submit"),
+            Some(EndpointGroup::Submit)
+        );
+        assert_eq!(
+            EndpointGroup::of("/v0/submit/batch"),
+            Some(EndpointGroup::Submit)
+        );
+        assert_eq!(
+            EndpointGroup::of("/availability/leaf/0/100"),
+            Some(EndpointGroup::Query)
+        );
+        assert_eq!(
+            EndpointGroup::of("/v1/node/header/window/0/1"),
+            Some(EndpointGroup::Query)
+        );
+        assert_eq!(EndpointGroup::of("/healthcheck"), None);
+        assert_eq!(
+            EndpointGroup::of("/v0/status/healthcheck"),
+            Some(EndpointGroup::Query)
+        );
+        assert_eq!(EndpointGroup::of("/"), None);
+    }
+
+    #[test]
+    fn test_rate_limit_per_ip() {
+        let limiter = limiter(vec![], false);
+        let a = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let b = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let now = Instant::now();
+
+        // Each client can burst a window of requests to each group.
+        for _ in 0..2 {
+            assert_eq!(
+                limiter.check(a, None, "/submit/submit", now),
+                Verdict::Allow
+            );
+        }
+        assert_eq!(
+            limiter.check(a, None, "/submit/submit", now),
+            Verdict::Limited {
+                retry_after: Duration::from_secs(1)
+            }
+        );
+        // Other groups and other clients are limited separately.
+        for _ in 0..4 {
+            assert_eq!(
+                limiter.check(a, None, "/availability/block/0", now),
+                Verdict::Allow
+            );
+        }
+        assert!(matches!(
+            limiter.check(a, None, "/availability/block/0", now),
+            Verdict::Limited { .. }
+        ));
+        assert_eq!(
+            limiter.check(b, None, "/submit/submit", now),
+            Verdict::Allow
+        );
+        // Unlimited endpoints are always allowed.
+        assert_eq!(limiter.check(a, None, "/healthcheck", now), Verdict::Allow);
+
+        // The bucket refills at the rate limit, up to the window.
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            limiter.check(a, None, "/submit/submit", later),
+            Verdict::Allow
+        );
+        assert!(matches!(
+            limiter.check(a, None, "/submit/submit", later),
+            Verdict::Limited { .. }
+        ));
+        let much_later = now + Duration::from_secs(100);
+        for _ in 0..2 {
+            assert_eq!(
+                limiter.check(a, None, "/submit/submit", much_later),
+                Verdict::Allow
+            );
+        }
+        assert!(matches!(
+            limiter.check(a, None, "/submit/submit", much_later),
+            Verdict::Limited { .. }
+        ));
+
+        // Idle clients were forgotten.
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_rate_limit_api_keys() {
+        let key = "key".to_string();
+        let limiter = limiter(vec![key.clone()], false);
+        let ip = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let now = Instant::now();
+
+        // Clients with a key get their own limits, regardless of IP.
+        for _ in 0..20 {
+            assert_eq!(
+                limiter.check(ip, Some(&key), "/submit/submit", now),
+                Verdict::Allow
+            );
+        }
+        assert!(matches!(
+            limiter.check(ip, Some(&key), "/submit/submit", now),
+            Verdict::Limited { .. }
+        ));
+        assert_eq!(
+            limiter.check(ip, None, "/submit/submit", now),
+            Verdict::Allow
+        );
+        // A rate of 0 is unlimited.
+        for _ in 0..100 {
+            assert_eq!(
+                limiter.check(ip, Some(&key), "/availability/block/0", now),
+                Verdict::Allow
+            );
+        }
+
+        // Unknown keys are refused.
+        assert_eq!(
+            limiter.check(ip, Some("other"), "/submit/submit", now),
+            Verdict::Unauthorized
+        );
+
+        // Keys can be required.
+        let limiter = self::limiter(vec![key.clone()], true);
+        assert_eq!(
+            limiter.check(ip, None, "/availability/block/0", now),
+            Verdict::Unauthorized
+        );
+        assert_eq!(
+            limiter.check(ip, Some(&key), "/availability/block/0", now),
+            Verdict::Allow
+        );
+        assert_eq!(limiter.check(ip, None, "/healthcheck", now), Verdict::Allow);
+    }
+
+    #[test]
+    fn test_rate_limit_update() {
+        let limiter = limiter(vec![], false);
+        let ip = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let now = Instant::now();
+        assert_eq!(
+            limiter.check(ip, Some("key"), "/submit/submit", now),
+            Verdict::Unauthorized
+        );
+        for _ in 0..2 {
+            assert_eq!(
+                limiter.check(ip, None, "/submit/submit", now),
+                Verdict::Allow
+            );
+        }
+
+        // New keys are accepted as soon as the limits are replaced.
+        let mut opt = limiter.limits.get();
+        opt.api_keys = vec!["key".into()];
+        opt.submit_rate_limit = 0.;
+        limiter.limits.set(opt);
+        assert_eq!(
+            limiter.check(ip, Some("key"), "/submit/submit", now),
+            Verdict::Allow
+        );
+        // And clients which were limited are not, under the new limits.
+        assert_eq!(
+            limiter.check(ip, None, "/submit/submit", now),
+            Verdict::Allow
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Tracking the status of transactions submitted through the submit API.
+
+use crate::{SeqTypes, Transaction};
+use async_std::sync::{Arc, RwLock};
+use committable::Commitment;
+use futures::stream::{Stream, StreamExt};
+use hotshot::{
+    traits::BlockPayload,
+    types::{Event, EventType},
+};
+use hotshot_types::{event::LeafInfo, traits::block_contents::BlockHeader};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// The status of a transaction submitted to this node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionStatus {
+    /// The transaction was submitted to consensus, but has not been included in a decided block.
+    Received,
+    /// The transaction was included in the decided block at `height`, at position `index` within
+    /// the block. Decided blocks are final.
+    Included { height: u64, index: u64 },
+    /// The transaction was rejected by this node, and never reached consensus.
+    Rejected { reason: String },
+}
+
+/// The status of recently submitted transactions.
+///
+/// A status is forgotten once it has not changed for the retention period.
+#[derive(Debug)]
+pub struct TransactionTracker {
+    retention: Duration,
+    statuses: HashMap<Commitment<Transaction>, (TransactionStatus, Instant)>,
+    /// Every status update, in order, for expiring statuses.
+    updates: VecDeque<(Instant, Commitment<Transaction>)>,
+}
+
+impl TransactionTracker {
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            retention,
+            statuses: Default::default(),
+            updates: Default::default(),
+        }
+    }
+
+    /// The status of the transaction `hash`, if it was submitted to this node recently.
+    pub fn status(&self, hash: Commitment<Transaction>) -> Option<TransactionStatus> {
+        let (status, updated) = self.statuses.get(&hash)?;
+        (updated.elapsed() <= self.retention).then(|| status.clone())
+    }
+
+    /// Record that the transaction `hash` was submitted to consensus.
+    pub fn received(&mut self, hash: Commitment<Transaction>) {
+        self.update(hash, TransactionStatus::Received);
+    }
+
+    /// Record that the transaction `hash` was rejected.
+    pub fn rejected(&mut self, hash: Commitment<Transaction>, reason: String) {
+        self.update(hash, TransactionStatus::Rejected { reason });
+    }
+
+    /// Record that the transaction `hash` was included in a decided block, if it is tracked.
+    ///
+    /// Transactions which were not submitted to this node are ignored.
+    pub fn included(&mut self, hash: Commitment<Transaction>, height: u64, index: u64) {
+        if self.statuses.contains_key(&hash) {
+            self.update(hash, TransactionStatus::Included { height, index });
+        }
+    }
+
+    /// Record the inclusion of tracked transactions in the blocks decided by `event`.
+    pub fn handle_event(&mut self, event: &Event<SeqTypes>) {
+        let EventType::Decide { leaf_chain, .. } = &event.event else {
+            return;
+        };
+        for LeafInfo { leaf, .. } in leaf_chain.iter() {
+            let Some(payload) = leaf.get_block_payload() else {
+                continue;
+            };
+            let header = leaf.get_block_header();
+            let hashes = payload.transaction_commitments(header.metadata());
+            for (index, hash) in hashes.into_iter().enumerate() {
+                self.included(hash, header.block_number(), index as u64);
+            }
+        }
+    }
+
+    fn update(&mut self, hash: Commitment<Transaction>, status: TransactionStatus) {
+        let now = Instant::now();
+        self.statuses.insert(hash, (status, now));
+        self.updates.push_back((now, hash));
+        self.expire(now);
+    }
+
+    /// Forget statuses which have not changed for the retention period.
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(updated, hash)) = self.updates.front() {
+            if now.duration_since(updated) <= self.retention {
+                break;
+            }
+            self.updates.pop_front();
+            // Only forget the status if this was its latest update.
+            if self
+                .statuses
+                .get(&hash)
+                .is_some_and(|(_, latest)| *latest == updated)
+            {
+                self.statuses.remove(&hash);
+            }
+        }
+    }
+}
+
+/// Update `tracker` with the blocks decided in `events`.
+pub(super) async fn track_transactions(
+    tracker: Arc<RwLock<TransactionTracker>>,
+    mut events: impl Stream<Item = Event<SeqTypes>> + Unpin,
+) {
+    while let Some(event) = events.next().await {
+        tracker.write().await.handle_event(&event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use committable::Committable;
+    use std::thread::sleep;
+
+    #[test]
+    fn test_transaction_tracker() {
+        let tx = Transaction::new(Default::default(), vec![1, 2, 3]);
+        let other = Transaction::new(Default::default(), vec![4, 5, 6]);
+        let mut tracker = TransactionTracker::new(Duration::from_secs(60));
+
+        assert_eq!(tracker.status(tx.commit()), None);
+        tracker.received(tx.commit());
+        assert_eq!(
+            tracker.status(tx.commit()),
+            Some(TransactionStatus::Received)
+        );
+        tracker.included(tx.commit(), 3, 1);
+        assert_eq!(
+            tracker.status(tx.commit()),
+            Some(TransactionStatus::Included {
+                height: 3,
+                index: 1
+            })
+        );
+
+        // Transactions which were not submitted here are not tracked.
+        tracker.included(other.commit(), 3, 0);
+        assert_eq!(tracker.status(other.commit()), None);
+
+        tracker.rejected(other.commit(), "too large".into());
+        assert_eq!(
+            tracker.status(other.commit()),
+            Some(TransactionStatus::Rejected {
+                reason: "too large".into()
+            })
+        );
+    }
+
+    #[test]
+    fn test_transaction_tracker_retention() {
+        let tx = Transaction::new(Default::default(), vec![1, 2, 3]);
+        let other = Transaction::new(Default::default(), vec![4, 5, 6]);
+        let mut tracker = TransactionTracker::new(Duration::from_millis(100));
+
+        tracker.received(tx.commit());
+        sleep(Duration::from_millis(200));
+        assert_eq!(tracker.status(tx.commit()), None);
+
+        // The expired status is dropped on the next update.
+        tracker.received(other.commit());
+        assert!(!tracker.statuses.contains_key(&tx.commit()));
+        assert_eq!(
+            tracker.status(other.commit()),
+            Some(TransactionStatus::Received)
+        );
+    }
+}
@@ -1,19 +1,64 @@
-use anyhow::Context;
+use anyhow::{bail, ensure, Context};
 use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
 use async_std::sync::Arc;
-use clap::Parser;
-use contract_bindings::{
-    erc1967_proxy::ERC1967Proxy, hot_shot::HotShot, light_client::LightClient,
+use clap::{Args, Parser, Subcommand};
+use contract_bindings::hot_shot::HotShot;
+use ethers::{
+    prelude::{coins_bip39::English, *},
+    utils::parse_units,
+};
+use hotshot::types::SignatureKey;
+use hotshot_contract_adapter::light_client::ParsedLightClientState;
+use hotshot_state_prover::service::{fetch_genesis_state, light_client_genesis};
+use hotshot_types::{
+    light_client::{StateKeyPair, StateSignKey},
+    signature_key::{BLSPrivKey, BLSPubKey},
+};
+use rusoto_core::Region;
+use rusoto_kms::KmsClient;
+use sequencer::{
+    keystore::{read_key_file, read_password, Keystore},
+    options::parse_duration,
 };
-use ethers::prelude::{coins_bip39::English, *};
-use futures::future::FutureExt;
-use hotshot_stake_table::config::STAKE_TABLE_CAPACITY;
-use hotshot_state_prover::service::light_client_genesis;
 use sequencer_utils::deployer::{
-    deploy_light_client_contract, deploy_mock_light_client_contract, Contract, Contracts,
-    DeployedContracts,
+    artifact::BytecodeSource,
+    create2::Create2Config,
+    deploy_fee_contract, deploy_light_client, deploy_production_stack,
+    deposits::{deposit_builder_balances, BuilderDeposits},
+    diff::{diff_deployment, load_deployment_file},
+    dry_run::DeployMode,
+    explorer::Explorer,
+    fees::GasConfig,
+    flavor::Flavor,
+    fork::{Fork, ForkConfig},
+    genesis::{check_genesis, reconcile_genesis},
+    multichain::{ChainTargets, EnvLayout, MultiChainContracts},
+    notify::{planned_contracts, DeploymentNotifier, Webhook},
+    output::OutputFormat,
+    ownership::transfer_ownership,
+    params::LightClientDeployParams,
+    prover::{set_permissioned_prover, ProverConfig},
+    receipt::ReceiptPolling,
+    replace::cancel_transaction,
+    signer::PromptingSigner,
+    stake_table::{
+        deploy_stake_table, read_stake_table, register_node, request_exit, withdraw_stake,
+        RegisteredNode, STAKE_TABLE_ARTIFACT,
+    },
+    status::{audit_networks, load_env_file, NetworkArg, NetworkTarget},
+    template::{RenderTarget, TemplateVars},
+    upgrade::upgrade_light_client,
+    verify::{verify_contracts, EtherscanConfig},
+    warnings::{is_local_chain, Severity, Warning},
+    Contract, Contracts, DeployedContracts, LightClientArtifact, LightClientKind, RetryPolicy,
+};
+use std::{
+    fs::File,
+    io::{stderr, stdout},
+    mem,
+    path::PathBuf,
+    time::Duration,
 };
-use std::{fs::File, io::stdout, path::PathBuf};
 use url::Url;
 
 /// Deploy contracts needed to run the sequencer.
@@ -73,22 +118,906 @@ struct Options {
     )]
     account_index: u32,
 
-    /// Write deployment results to OUT as a .env file.
+    /// Sign deployment transactions with a Ledger hardware wallet instead of MNEMONIC.
+    ///
+    /// The account is selected by DERIVATION_PATH if given, or else by ACCOUNT_INDEX using the
+    /// Ledger Live derivation scheme. Before each transaction is sent to the device, the contract
+    /// it deploys and a summary of the transaction are printed, to check against the device.
+    /// Rejecting a transaction on the device stops the deployment.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_LEDGER",
+        conflicts_with_all = ["AWS_KMS_KEY_ID", "UNSIGNED_DIR"]
+    )]
+    ledger: bool,
+    /// HD derivation path of the Ledger account to deploy from, like `m/44'/60'/0'/0/0`.
+    #[clap(
+        long,
+        name = "DERIVATION_PATH",
+        env = "ESPRESSO_DEPLOYER_LEDGER_DERIVATION_PATH",
+        requires = "ledger"
+    )]
+    derivation_path: Option<String>,
+    /// Sign deployment transactions with the AWS KMS key AWS_KMS_KEY_ID instead of MNEMONIC.
+    ///
+    /// AWS credentials and the region are read from the environment, as usual for AWS tools.
+    #[clap(
+        long,
+        name = "AWS_KMS_KEY_ID",
+        env = "ESPRESSO_DEPLOYER_AWS_KMS_KEY_ID",
+        conflicts_with = "UNSIGNED_DIR"
+    )]
+    aws_kms_key_id: Option<String>,
+    /// Write the deployment transactions to UNSIGNED_DIR, unsigned, instead of sending them.
+    ///
+    /// This is a dry run, except that each transaction is prepared for the account FROM to sign
+    /// offline: it is given the nonce it will be sent with, the chain ID, a gas limit and fees, and
+    /// written to its own numbered JSON file, along with its RLP encoding. Contracts are given the
+    /// addresses these transactions will deploy them to, so the transactions must be signed and
+    /// broadcast in order.
+    #[clap(long, name = "UNSIGNED_DIR")]
+    unsigned: Option<PathBuf>,
+    /// The account which will sign the transactions written to UNSIGNED_DIR.
+    ///
+    /// Defaults to the account indicated by MNEMONIC and ACCOUNT_INDEX.
+    #[clap(long, name = "FROM", requires = "UNSIGNED_DIR")]
+    from: Option<Address>,
+
+    /// Write deployment results to OUT, as a .env file unless OUTPUT_FORMAT says otherwise.
     ///
     /// If not provided, the results will be written to stdout.
     #[clap(short, long, name = "OUT", env = "ESPRESSO_DEPLOYER_OUT_PATH")]
     out: Option<PathBuf>,
 
+    /// Update the contract addresses in OUT in place instead of overwriting it.
+    ///
+    /// Every other line of OUT, including addresses of contracts not deployed by this run, is kept.
+    #[clap(long, requires = "OUT", env = "ESPRESSO_DEPLOYER_UPDATE_OUT")]
+    update_out: bool,
+
+    /// The format to write deployment results to OUT (or stdout) in.
+    ///
+    /// `env` and `toml` map the env var name of each contract to its address, and `json` does the
+    /// same as a JSON object. `broadcast` writes a Foundry-style broadcast artifact, listing the
+    /// transaction hash, block number, receipt and init code (including constructor arguments) of
+    /// each contract deployed in this run, along with the chain ID and linked libraries.
+    #[clap(
+        long,
+        name = "OUTPUT_FORMAT",
+        env = "ESPRESSO_DEPLOYER_OUTPUT_FORMAT",
+        value_enum,
+        default_value = "env",
+        conflicts_with = "update_out"
+    )]
+    output_format: OutputFormat,
+
+    /// Also write deployment results to JSON_OUT as a JSON object.
+    ///
+    /// The object maps the env var name of each contract to its checksummed address.
+    #[clap(long, name = "JSON_OUT", env = "ESPRESSO_DEPLOYER_JSON_OUT_PATH")]
+    json_out: Option<PathBuf>,
+
+    /// Write a report of the transaction which deployed each contract to REPORT.
+    ///
+    /// For each contract deployed in this run, the report lists the transaction hash, block number
+    /// and gas used; predeployed contracts are listed with just their address.
+    #[clap(long, name = "REPORT", env = "ESPRESSO_DEPLOYER_REPORT_PATH")]
+    report: Option<PathBuf>,
+
+    /// Also write the deployment report to JSON_REPORT as a JSON object.
+    #[clap(long, name = "JSON_REPORT", env = "ESPRESSO_DEPLOYER_JSON_REPORT_PATH")]
+    json_report: Option<PathBuf>,
+
+    /// Write the full receipt of every deployment transaction sent in this run to RECEIPTS, as a
+    /// JSON array.
+    ///
+    /// This is an audit trail of the deployment: each entry has the contract, transaction hash,
+    /// block and status. Predeployed contracts have no receipt and are not listed.
+    #[clap(long, name = "RECEIPTS", env = "ESPRESSO_DEPLOYER_RECEIPTS_PATH")]
+    receipts_out: Option<PathBuf>,
+
+    /// Write transactions which must be sent by a Safe to SAFE_BATCH_DIR.
+    ///
+    /// Ownership transfers and upgrades of contracts owned by a Safe cannot be sent by the
+    /// deployer. They are collected in one batch per Safe, written to `safe-<address>.json` for
+    /// import into the Safe Transaction Builder.
+    #[clap(
+        long,
+        name = "SAFE_BATCH_DIR",
+        env = "ESPRESSO_DEPLOYER_SAFE_BATCH_DIR",
+        default_value = "."
+    )]
+    safe_batch_dir: PathBuf,
+
+    /// Record deployment progress in STATE_FILE, to resume an interrupted deployment.
+    ///
+    /// Contracts recorded in STATE_FILE are treated as predeployed, and each newly deployed
+    /// contract is recorded as soon as it is deployed, with the hash, block and init code
+    /// (including constructor arguments) of its deployment transaction. The file also records the
+    /// L1 chain ID, and the deployment fails if STATE_FILE was recorded on a different chain.
+    #[clap(
+        long,
+        alias = "deployment-state",
+        name = "STATE_FILE",
+        env = "ESPRESSO_DEPLOYER_STATE_FILE"
+    )]
+    state_file: Option<PathBuf>,
+
+    /// Render a config template after deployment, in the form TEMPLATE:OUT.
+    ///
+    /// Placeholders like `{{LIGHT_CLIENT_PROXY}}` in TEMPLATE are replaced with the corresponding
+    /// contract address, `{{CHAIN_ID}}` with the L1 chain ID, and `{{DEPLOY_BLOCK_<NAME>}}` with the
+    /// block each contract deployed in this run was mined in. The result is written to OUT.
+    /// Deployment output is still written as usual. This option may be repeated, or given a
+    /// comma-separated list.
+    #[clap(
+        long = "render",
+        name = "TEMPLATE:OUT",
+        env = "ESPRESSO_DEPLOYER_RENDER_TEMPLATES",
+        value_delimiter = ','
+    )]
+    render: Vec<RenderTarget>,
+
     #[clap(flatten)]
     contracts: DeployedContracts,
 
+    /// The ID of the chain the predeployed contract addresses belong to.
+    ///
+    /// If given, the deployment is refused unless the L1 provider is connected to this chain, so
+    /// that addresses from a .env file written for one chain are never reused on another.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_CONTRACTS_CHAIN_ID")]
+    contracts_chain_id: Option<u64>,
+
+    /// A label for the chain at --rpc-url.
+    ///
+    /// Predeployed addresses scoped to this label, like `--light-client-proxy l1:0x...`, are used
+    /// on this chain.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_CHAIN_NAME", default_value = "l1")]
+    chain_name: String,
+
+    /// Another chain to deploy to, in the form NAME=RPC_URL.
+    ///
+    /// May be repeated. Contracts are deployed to other chains only if requested, like with
+    /// --fee-contract-chain. Predeployed addresses scoped to NAME, like
+    /// `--fee-contract-proxy NAME:0x...`, are used on this chain.
+    #[clap(long = "chain", name = "NAME=RPC_URL")]
+    chains: Vec<NetworkArg<Url>>,
+
+    /// Deploy the fee contract to the chain FEE_CHAIN, given with --chain, instead of the chain at
+    /// --rpc-url.
+    ///
+    /// The fee contract is deployed from the account indicated by MNEMONIC and ACCOUNT_INDEX. The
+    /// addresses on every chain are written to OUT according to --env-layout. Reports, JSON output
+    /// and verification cover only the chain at --rpc-url.
+    #[clap(
+        long,
+        name = "FEE_CHAIN",
+        env = "ESPRESSO_DEPLOYER_FEE_CONTRACT_CHAIN",
+        conflicts_with_all = ["ledger", "AWS_KMS_KEY_ID", "UNSIGNED_DIR", "STATE_FILE", "update_out"]
+    )]
+    fee_contract_chain: Option<String>,
+
+    /// Fund builder accounts in the fee contract with the balances listed in BUILDER_DEPOSITS.
+    ///
+    /// BUILDER_DEPOSITS is a JSON file like `{"deposits": [{"account": "0x...", "amount": "0.5"}]}`,
+    /// with amounts in ETH. Once the fee contract is deployed, each account is topped up to its
+    /// balance from the deployer account; accounts already funded are skipped.
+    #[clap(
+        long,
+        name = "BUILDER_DEPOSITS",
+        env = "ESPRESSO_DEPLOYER_BUILDER_DEPOSITS_PATH"
+    )]
+    builder_deposits: Option<PathBuf>,
+
+    /// Deploy the stake table, staking the ERC20 token STAKE_TOKEN, after the light client.
+    ///
+    /// Nodes register in the stake table with the `staking` command. If not given, no stake table
+    /// is deployed.
+    #[clap(
+        long,
+        name = "STAKE_TOKEN",
+        env = "ESPRESSO_DEPLOYER_STAKE_TABLE_TOKEN"
+    )]
+    stake_table_token: Option<Address>,
+
+    /// The maximum number of nodes which may join or leave the stake table in each epoch.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_STAKE_TABLE_CHURN_RATE",
+        default_value = "10",
+        requires = "STAKE_TOKEN"
+    )]
+    stake_table_churn_rate: u64,
+
+    /// The Foundry artifact of StakeTable.sol to deploy the stake table from.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_STAKE_TABLE_ARTIFACT",
+        default_value = STAKE_TABLE_ARTIFACT,
+        requires = "STAKE_TOKEN"
+    )]
+    stake_table_artifact: PathBuf,
+
+    /// How to write the addresses deployed to several chains.
+    ///
+    /// With `prefixed`, a single .env file is written, in which each variable is prefixed with the
+    /// label of its chain in upper case, like `L1_ESPRESSO_SEQUENCER_HOTSHOT_ADDRESS`. With
+    /// `per-chain`, OUT is a directory, in which a file `NAME.env` is written for each chain.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_ENV_LAYOUT",
+        value_enum,
+        default_value = "prefixed",
+        requires = "OUT"
+    )]
+    env_layout: EnvLayout,
+
+    /// Maximum number of times to send each deployment transaction.
+    ///
+    /// Transient failures, such as network errors, nonce conflicts and underpriced transactions,
+    /// are retried with exponential backoff. A transaction which reverts is never retried.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_MAX_ATTEMPTS", default_value = "3")]
+    max_attempts: usize,
+
+    /// Delay before the first retry of a deployment transaction.
+    ///
+    /// The delay doubles after each subsequent attempt, up to MAX_BACKOFF.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_INITIAL_BACKOFF",
+        default_value = "1s",
+        value_parser = parse_duration
+    )]
+    initial_backoff: Duration,
+
+    /// Maximum delay between attempts to send a deployment transaction.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_MAX_BACKOFF",
+        default_value = "30s",
+        value_parser = parse_duration
+    )]
+    max_backoff: Duration,
+
+    /// How often to poll the L1 while waiting for a transaction to be mined.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_RECEIPT_POLL_INTERVAL",
+        default_value = "1s",
+        value_parser = parse_duration
+    )]
+    receipt_poll_interval: Duration,
+
+    /// Number of blocks a transaction may go unseen by the L1 before it is considered dropped.
+    ///
+    /// A transaction is also considered dropped as soon as its nonce is used by a different
+    /// transaction. Until then, a transaction which the L1 provider has forgotten about may still
+    /// be mined, so it is not resent.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_DROP_AFTER_BLOCKS",
+        default_value = "10"
+    )]
+    drop_after_blocks: u64,
+
+    /// Number of blocks, including the one it was mined in, to wait for before a transaction is
+    /// considered mined.
+    ///
+    /// A contract is only recorded as deployed once its deployment transaction has this many
+    /// confirmations. This applies to every transaction the deployer sends, including proxy
+    /// initialization and ownership transfers. On public networks, use more than 1 to protect
+    /// against reorgs: a transaction which is reorged out while waiting is waited for again, and
+    /// resent if it is dropped.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_CONFIRMATIONS", default_value = "1")]
+    confirmations: usize,
+
+    /// How long to wait for a transaction to be mined and confirmed before giving up on it.
+    ///
+    /// A transaction which times out is resent, subject to --max-attempts.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_PENDING_TIMEOUT",
+        default_value = "5m",
+        value_parser = parse_duration
+    )]
+    pending_timeout: Duration,
+
+    /// Maximum total fee per gas, in gwei, for every transaction the deployer sends.
+    ///
+    /// Unless --legacy-gas-price is given, all transactions are EIP-1559 transactions. If not
+    /// given, the fee cap is estimated by the L1 provider for each transaction. While the base fee
+    /// exceeds this cap, the deployer waits for it to drop rather than sending transactions which
+    /// cannot be included.
+    #[clap(
+        long,
+        name = "MAX_FEE_GWEI",
+        env = "ESPRESSO_DEPLOYER_MAX_FEE_PER_GAS",
+        value_parser = parse_gwei
+    )]
+    max_fee_per_gas: Option<U256>,
+
+    /// Maximum priority fee per gas, in gwei, for every transaction the deployer sends.
+    ///
+    /// If not given, the priority fee is estimated by the L1 provider for each transaction.
+    #[clap(
+        long,
+        alias = "max-priority-fee",
+        name = "PRIORITY_FEE_GWEI",
+        env = "ESPRESSO_DEPLOYER_MAX_PRIORITY_FEE_PER_GAS",
+        value_parser = parse_gwei
+    )]
+    max_priority_fee_per_gas: Option<U256>,
+
+    /// Send legacy transactions with this gas price, in gwei, instead of EIP-1559 transactions.
+    ///
+    /// For chains which do not support EIP-1559. While the base fee, if any, exceeds this price,
+    /// the deployer waits for it to drop.
+    #[clap(
+        long,
+        name = "GAS_PRICE_GWEI",
+        env = "ESPRESSO_DEPLOYER_LEGACY_GAS_PRICE",
+        value_parser = parse_gwei,
+        conflicts_with_all = ["MAX_FEE_GWEI", "PRIORITY_FEE_GWEI"]
+    )]
+    legacy_gas_price: Option<U256>,
+
+    /// Send legacy transactions, with the gas price estimated by the L1 provider.
+    ///
+    /// This is the default on chains whose blocks have no base fee, unless --max-fee-per-gas or
+    /// --max-priority-fee-per-gas is given.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_LEGACY",
+        conflicts_with_all = ["MAX_FEE_GWEI", "PRIORITY_FEE_GWEI"]
+    )]
+    legacy: bool,
+
+    /// Send every transaction with a gas limit of MULTIPLIER times the estimated gas.
+    ///
+    /// For chains whose gas estimates are unreliable, where transactions with the estimated gas
+    /// limit may run out of gas.
+    #[clap(
+        long,
+        name = "MULTIPLIER",
+        env = "ESPRESSO_DEPLOYER_GAS_LIMIT_MULTIPLIER",
+        default_value = "1"
+    )]
+    gas_limit_multiplier: f64,
+
+    /// How long to wait for the base fee to drop below the maximum fee before giving up.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_FEE_WAIT_TIMEOUT",
+        default_value = "10m",
+        value_parser = parse_duration
+    )]
+    fee_wait_timeout: Duration,
+
+    /// Replace a deployment transaction which is not mined within TX_TIMEOUT with one paying
+    /// higher fees.
+    ///
+    /// The replacement has the same nonce, so only one of the transactions can be mined. Its fees
+    /// are raised by --fee-bump-percent, but never above --max-fee-per-gas or --legacy-gas-price.
+    /// This should be well below --pending-timeout, after which the transaction is given up on.
+    #[clap(
+        long,
+        name = "TX_TIMEOUT",
+        env = "ESPRESSO_DEPLOYER_TX_TIMEOUT",
+        value_parser = parse_duration
+    )]
+    tx_timeout: Option<Duration>,
+
+    /// Percentage by which a replacement transaction raises the fees of the one it replaces.
+    ///
+    /// Most nodes reject replacements which raise the fees by less than 10%.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_FEE_BUMP_PERCENT", default_value = "15")]
+    fee_bump_percent: u64,
+
+    /// Base URL of a block explorer to link to in logs, errors and notifications.
+    ///
+    /// Links are generated for addresses (BASE/address/ADDRESS) and transactions (BASE/tx/HASH). If
+    /// not given, a built-in explorer is used for well-known chains, and no links are generated for
+    /// other chains.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_EXPLORER_URL")]
+    explorer_url: Option<Url>,
+
+    /// Verify the source of each contract deployed in this run on a block explorer.
+    ///
+    /// After deployment, the source and constructor arguments of each newly deployed contract are
+    /// submitted to an Etherscan-compatible verification API, and the result is logged. A contract
+    /// which fails to verify is reported as a warning. Requires the solc build info written by
+    /// `forge build --build-info`.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_VERIFY", overrides_with = "no_verify")]
+    verify: bool,
+    /// Do not verify deployed contracts, even if --verify is set by the environment.
+    #[clap(long, overrides_with = "verify")]
+    no_verify: bool,
+    /// The Etherscan-compatible API to verify contracts with, like a Blockscout instance's `/api`.
+    ///
+    /// If not given, Etherscan is used for well-known chains.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_VERIFIER_URL")]
+    verifier_url: Option<Url>,
+    /// API key for the verification API.
+    #[clap(long, alias = "etherscan-api-key", env = "ETHERSCAN_API_KEY")]
+    verifier_api_key: Option<String>,
+    /// Directory of solc build info files for the deployed contracts, used for verification.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_BUILD_INFO",
+        default_value = "contracts/out/build-info"
+    )]
+    build_info: PathBuf,
+
+    /// Deploy the libraries the light client links with using CREATE2.
+    ///
+    /// The libraries are deployed through a CREATE2 factory, so that they land at the same address
+    /// on every network. If a library is already deployed at its address, it is reused without
+    /// sending a transaction.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_CREATE2")]
+    create2: bool,
+
+    /// The CREATE2 factory to deploy libraries through, when using --create2.
+    ///
+    /// Defaults to the canonical deterministic deployment proxy, which is available on most chains.
+    #[clap(
+        long,
+        name = "CREATE2_FACTORY",
+        env = "ESPRESSO_DEPLOYER_CREATE2_FACTORY",
+        default_value = "0x4e59b44847b379578588920cA78FbF26c0B4956C"
+    )]
+    create2_factory: Address,
+
+    /// Extra input to the salt for CREATE2 deployments, when using --create2.
+    ///
+    /// The salt for each library is derived from its name. Changing this value deploys a fresh set
+    /// of libraries at different addresses.
+    #[clap(long, name = "CREATE2_SALT", env = "ESPRESSO_DEPLOYER_CREATE2_SALT")]
+    create2_salt: Option<String>,
+
+    /// Transfer ownership of the light client and the fee contract to OWNER once they are deployed.
+    ///
+    /// OWNER is typically a timelock or multisig. For upgradable contracts, the owner is also the
+    /// only account allowed to upgrade them. If not given, the deployer account remains the owner.
+    /// If a contract was predeployed and is already owned by an account other than the deployer,
+    /// its current owner is reported and nothing is transferred.
+    #[clap(long, name = "OWNER", env = "ESPRESSO_DEPLOYER_OWNER")]
+    owner: Option<Address>,
+
+    /// Restrict light client state updates to PROVER.
+    ///
+    /// The prover is set on the light client proxy once it is initialized, before ownership is
+    /// transferred to OWNER, and read back to check it. If not given, the light client is left in
+    /// permissionless mode, so anyone with a valid proof can update its state.
+    #[clap(
+        long,
+        name = "PROVER",
+        env = "ESPRESSO_DEPLOYER_PERMISSIONED_PROVER",
+        conflicts_with = "use_mock_contract"
+    )]
+    permissioned_prover: Option<Address>,
+
+    /// Replace the permissioned prover of a predeployed light client which is already restricted
+    /// to a different prover.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_FORCE_PROVER_UPDATE",
+        requires = "PROVER"
+    )]
+    force_prover_update: bool,
+
+    /// Allow deploying identical bytecode under different contract names in the same run.
+    ///
+    /// By default, the deployer refuses to broadcast a deployment whose init code (bytecode and
+    /// constructor arguments) is identical to one already deployed in this run for a different
+    /// contract, since this is usually a mistake.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_ALLOW_DUPLICATE_BYTECODE")]
+    allow_duplicate_bytecode: bool,
+
+    /// Plan the deployment without sending any transactions.
+    ///
+    /// Prints which contracts would be deployed and which are already deployed, the estimated gas,
+    /// calldata size and init code hash for each deployment, and the total cost at the current gas
+    /// price. Exits with a nonzero status if any deployment would fail. No output files are
+    /// written.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Rehearse the deployment on a local fork of the chain at FORK_URL, instead of deploying.
+    ///
+    /// A local `anvil` fork of FORK_URL is started, the deployment account is impersonated on it,
+    /// and the full deployment is run against the fork. Afterwards, the deployed contracts are
+    /// checked like with the `diff` command, including the genesis state of the light client.
+    /// Prints the checks, and exits with a nonzero status if the deployment or any check fails.
+    /// Nothing is sent to FORK_URL or --rpc-url, and no output files are written.
+    #[clap(
+        long,
+        name = "FORK_URL",
+        env = "ESPRESSO_DEPLOYER_FORK_URL",
+        conflicts_with_all = ["dry_run", "FEE_CHAIN"]
+    )]
+    fork_url: Option<Url>,
+
+    /// Block of FORK_URL to fork at, instead of the latest block.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_FORK_BLOCK_NUMBER",
+        requires = "FORK_URL"
+    )]
+    fork_block_number: Option<u64>,
+
+    /// Post notifications about the deployment to this webhook.
+    ///
+    /// A JSON payload is posted when the deployment starts, finishes, and fails. Delivery failures
+    /// are logged and never affect the deployment.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_NOTIFY_WEBHOOK")]
+    notify_webhook: Option<Url>,
+
+    /// Fail if any warning of at least MIN_SEVERITY was emitted.
+    ///
+    /// Warnings, like a predeployed contract whose code differs from the bundled artifacts, are
+    /// listed in the summary and JSON output either way. With this flag, the deployer exits with a
+    /// nonzero status after writing its output if any warning was at least as severe as
+    /// MIN_SEVERITY (low, medium or high). If no severity is given, any warning fails the run.
+    #[clap(
+        long,
+        name = "MIN_SEVERITY",
+        env = "ESPRESSO_DEPLOYER_WARNINGS_AS_ERRORS",
+        num_args = 0..=1,
+        default_missing_value = "low"
+    )]
+    warnings_as_errors: Option<Severity>,
+
+    /// Skip checking the code of predeployed contracts before deploying.
+    ///
+    /// By default, the deployer checks that every predeployed contract address has code, and warns
+    /// if the code differs from the bundled contract artifacts. This flag disables the check, which
+    /// may be necessary on exotic chains.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_SKIP_VERIFY")]
+    skip_verify: bool,
+
+    /// Check that each predeployed contract looks like the right kind of contract.
+    ///
+    /// The code at each address must dispatch on a function we expect for that contract, like
+    /// `getFinalizedState` for the light client. For a proxy, the code of its implementation is
+    /// checked. This catches an address which belongs to an entirely different contract, even one
+    /// this deployer has no artifact for.
+    #[clap(long, env = "ESPRESSO_DEPLOYER_CHECK_CODE")]
+    check_code: bool,
+
+    /// Initialize the light client with this genesis state, ABI-encoded in hex.
+    ///
+    /// If not given, the genesis state is computed from the stake table the orchestrator reports.
+    #[clap(long, name = "GENESIS", env = "ESPRESSO_DEPLOYER_GENESIS_STATE")]
+    genesis: Option<ParsedLightClientState>,
+
+    /// Initialize the light client with the genesis state in GENESIS_FILE.
+    ///
+    /// The file is JSON or TOML, depending on its extension, with heights as decimal integers and
+    /// commitments and the threshold as 0x-prefixed hex strings. See
+    /// `data/light_client_genesis.toml` for an example.
+    #[clap(
+        long,
+        name = "GENESIS_FILE",
+        env = "ESPRESSO_DEPLOYER_GENESIS_FILE",
+        conflicts_with = "GENESIS"
+    )]
+    genesis_file: Option<PathBuf>,
+
+    /// Fetch the genesis state of the running sequencer network from its orchestrator at URL.
+    ///
+    /// Unlike the default, this fails right away if the network is not ready, rather than waiting
+    /// for it. If GENESIS or GENESIS_FILE is also given, the two must match, unless
+    /// --force-genesis is given.
+    #[clap(long, name = "URL", env = "ESPRESSO_DEPLOYER_GENESIS_FROM_SEQUENCER")]
+    genesis_from_sequencer: Option<Url>,
+
+    /// Use GENESIS or GENESIS_FILE even if it does not match the genesis state of the running
+    /// network.
+    #[clap(long)]
+    force_genesis: bool,
+
     /// If toggled, launch a mock prover contract that does not do any proof verification.
     #[clap(short, long)]
     pub use_mock_contract: bool,
 
-    /// Stake table capacity for the prover circuit
-    #[clap(short, long, env = "ESPRESSO_SEQUENCER_STAKE_TABLE_CAPACITY", default_value_t = STAKE_TABLE_CAPACITY)]
-    pub stake_table_capacity: usize,
+    /// Deploy the light client from the artifact at LIGHT_CLIENT_ARTIFACT, instead of the one
+    /// built into this binary.
+    ///
+    /// This is for deploying a locally recompiled light client (or mock light client, with
+    /// --use-mock-contract) without rebuilding the deployer. The file is either the JSON artifact
+    /// Foundry writes for the contract, or a JSON string of its unlinked bytecode. It is linked
+    /// with the deployed libraries like the built-in artifact.
+    #[clap(
+        long,
+        name = "LIGHT_CLIENT_ARTIFACT",
+        env = "ESPRESSO_DEPLOYER_LIGHT_CLIENT_ARTIFACT"
+    )]
+    light_client_artifact: Option<PathBuf>,
+
+    /// Deploy the light client from the artifacts in ARTIFACTS_DIR, instead of the ones built into
+    /// this binary.
+    ///
+    /// The directory is laid out like contract-bindings/artifacts, with LightClient_bytecode.json
+    /// and LightClientMock_bytecode.json, and the artifact for the light client being deployed
+    /// (depending on --use-mock-contract) is loaded like LIGHT_CLIENT_ARTIFACT. The path and hash
+    /// of the artifact are included in the deployment report.
+    #[clap(
+        long,
+        name = "ARTIFACTS_DIR",
+        env = "ESPRESSO_DEPLOYER_ARTIFACTS_DIR",
+        conflicts_with = "LIGHT_CLIENT_ARTIFACT"
+    )]
+    artifacts_dir: Option<PathBuf>,
+
+    #[clap(flatten)]
+    light_client: LightClientDeployParams,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// Operations other than deploying contracts.
+///
+/// If no subcommand is given, contracts are deployed.
+#[derive(Clone, Debug, Subcommand)]
+enum Command {
+    /// Audit deployed contracts without sending any transactions.
+    ///
+    /// For each network, reports whether each contract has code, the hash of that code, the
+    /// implementation behind each proxy, and the light client prover configuration. When several
+    /// networks are given, properties which differ between them are highlighted. Exits with a
+    /// nonzero status if any network could not be audited or the networks diverge.
+    Status(StatusOptions),
+    /// Upgrade the light client proxy to a newly deployed implementation.
+    ///
+    /// The proxy is taken from ESPRESSO_SEQUENCER_LIGHT_CLIENT_PROXY_ADDRESS. A new `LightClient.sol`
+    /// implementation is deployed, linked with the given or newly deployed libraries, and the proxy
+    /// is upgraded to it. The deployer account must own the light client, unless it is owned by a
+    /// Safe or --safe is given, in which case the upgrade is written to a Safe batch in
+    /// --safe-batch-dir. The old and new implementations and the upgrade transaction are listed in
+    /// the deployment report.
+    #[clap(alias = "deploy-and-upgrade")]
+    Upgrade(UpgradeOptions),
+    /// Clear a stuck transaction from the deployer account.
+    ///
+    /// The transaction with the given nonce is replaced by a zero-value transfer from the deployer
+    /// account to itself, paying higher fees. Fees are raised by --fee-bump-percent, up to
+    /// --max-fee-per-gas, until the replacement is accepted.
+    Cancel(CancelOptions),
+    /// Compare the contracts deployed on chain against a deployment file, without sending any
+    /// transactions.
+    ///
+    /// For each contract listed in the file, checks that there is code at its address and that it
+    /// matches the artifact built into this binary, and that each proxy points at the listed
+    /// implementation. The light client is checked against --blocks-per-epoch and, if given, the
+    /// genesis state in --genesis-file or --genesis. Prints a table of the checks, and exits with a
+    /// nonzero status if any of them fails.
+    Diff(DiffOptions),
+    /// Rotate the permissioned prover of the light client proxy.
+    ///
+    /// The proxy is taken from ESPRESSO_SEQUENCER_LIGHT_CLIENT_PROXY_ADDRESS and must be owned by
+    /// the deployer account. The current prover, if any, is replaced by the new one, which is read
+    /// back to check it and listed in the deployment report.
+    SetProver(SetProverOptions),
+    /// Register, exit and list nodes in the stake table.
+    ///
+    /// The stake table is taken from ESPRESSO_SEQUENCER_STAKE_TABLE_ADDRESS. Transactions are sent
+    /// from the deployer account, which must hold the stake tokens of a node to register it, and
+    /// is the only account which can later exit the node and withdraw its stake.
+    Staking(StakingOptions),
+}
+
+#[derive(Clone, Debug, Args)]
+struct StakingOptions {
+    #[clap(subcommand)]
+    command: StakingCommand,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum StakingCommand {
+    /// Register a node, depositing stake for it.
+    Register(RegisterOptions),
+    /// Request that a node leave the stake table.
+    ///
+    /// Its stake can be withdrawn once the exit escrow period is over.
+    Exit(NodeKeyOptions),
+    /// Withdraw the stake of a node which has exited.
+    Withdraw(NodeKeyOptions),
+    /// Print the nodes in the stake table.
+    ///
+    /// Lists every node which has registered and not requested to exit, as the stake table the
+    /// sequencer is configured with: a JSON list of stake table entries and state keys, the format
+    /// of `known_nodes_with_stake` in the network config.
+    List(ListStakeTableOptions),
+}
+
+#[derive(Clone, Debug, Args)]
+struct RegisterOptions {
+    #[clap(flatten)]
+    keys: NodeKeyOptions,
+
+    /// Amount of stake tokens to deposit, in the smallest unit of the token.
+    #[clap(long)]
+    amount: u64,
+
+    /// Abort the registration if it cannot take effect by this epoch.
+    ///
+    /// Defaults to the first epoch a registration can currently take effect in, so that a
+    /// registration which is delayed by others being processed first fails instead of waiting.
+    #[clap(long)]
+    valid_until_epoch: Option<u64>,
+}
+
+/// The keys of a node, as written by `keygen` or `keystore`.
+#[derive(Clone, Debug, Args)]
+struct NodeKeyOptions {
+    /// Key file of the node, in .env format as written by `keygen`.
+    #[clap(
+        long,
+        name = "NODE_KEY_FILE",
+        required_unless_present = "NODE_KEYSTORE",
+        conflicts_with = "NODE_KEYSTORE"
+    )]
+    key_file: Option<PathBuf>,
+
+    /// Keystore of the node, as written by `keystore`.
+    #[clap(long, name = "NODE_KEYSTORE", requires = "NODE_KEYSTORE_PASSWORD_FILE")]
+    keystore: Option<PathBuf>,
+
+    /// File containing the password of NODE_KEYSTORE.
+    #[clap(long, name = "NODE_KEYSTORE_PASSWORD_FILE")]
+    keystore_password_file: Option<PathBuf>,
+}
+
+impl NodeKeyOptions {
+    fn private_keys(&self) -> anyhow::Result<(BLSPrivKey, StateSignKey)> {
+        match (&self.key_file, &self.keystore, &self.keystore_password_file) {
+            (Some(path), _, _) => read_key_file(path),
+            (None, Some(dir), Some(password_file)) => {
+                let keystore = Keystore::open(dir)?;
+                let password = read_password(password_file)?;
+                Ok((
+                    keystore.staking_key(&password)?,
+                    keystore.state_key(&password)?,
+                ))
+            }
+            _ => bail!("either a key file or a keystore and its password file must be given"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Args)]
+struct ListStakeTableOptions {
+    /// Write the stake table to OUT instead of stdout.
+    #[clap(long, name = "STAKE_TABLE_OUT")]
+    out: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Args)]
+struct SetProverOptions {
+    /// The new permissioned prover.
+    #[clap(long = "prover", name = "NEW_PROVER")]
+    prover: Address,
+}
+
+#[derive(Clone, Debug, Args)]
+struct DiffOptions {
+    /// The expected deployment: a .env file, as written by --out, or a JSON file, as written by
+    /// --json-out.
+    #[clap(long, name = "DEPLOYMENT")]
+    deployment: PathBuf,
+
+    /// Print the checks as JSON instead of a table.
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Clone, Debug, Args)]
+struct CancelOptions {
+    /// Nonce of the stuck transaction.
+    #[clap(long)]
+    nonce: u64,
+}
+
+#[derive(Clone, Debug, Args)]
+struct UpgradeOptions {
+    /// Calldata, in hex, for the new implementation to run while upgrading, like a reinitializer.
+    #[clap(long)]
+    call_data: Option<Bytes>,
+
+    /// Deploy the new implementation, but only print the upgrade transaction instead of sending
+    /// it.
+    ///
+    /// This is for a light client owned by a multisig, whose owners send the printed transaction.
+    /// The deployer account does not need to own the light client. A light client owned by a Safe
+    /// is always handled this way. Either way, the transaction is also written to a batch in
+    /// --safe-batch-dir, to import into the Safe Transaction Builder.
+    #[clap(long)]
+    safe: bool,
+}
+
+#[derive(Clone, Debug, Args)]
+struct StatusOptions {
+    /// A network to audit, in the form NAME=RPC_URL.
+    ///
+    /// May be repeated. If no network is given, the network at --rpc-url is audited.
+    #[clap(long = "network", name = "NAME=RPC_URL")]
+    networks: Vec<NetworkArg<Url>>,
+
+    /// A .env file listing the contract addresses on a network, in the form NAME=PATH.
+    ///
+    /// Networks without an env file are audited using the contract addresses given via the command
+    /// line or environment.
+    #[clap(long = "env", name = "NAME=PATH")]
+    env_files: Vec<NetworkArg<PathBuf>>,
+
+    /// Maximum number of networks to audit concurrently.
+    #[clap(long, default_value = "4")]
+    parallelism: usize,
+
+    /// Print the report as JSON instead of a table.
+    #[clap(long)]
+    json: bool,
+}
+
+async fn status(opt: Options, status: StatusOptions) -> anyhow::Result<()> {
+    for warning in opt.contracts.validate()? {
+        tracing::warn!("{warning}");
+    }
+    let networks = if status.networks.is_empty() {
+        vec![NetworkArg {
+            name: "default".into(),
+            value: opt.rpc_url,
+        }]
+    } else {
+        status.networks
+    };
+
+    let mut targets = vec![];
+    for network in networks {
+        let contracts = match status.env_files.iter().find(|env| env.name == network.name) {
+            Some(env) => load_env_file(&env.value)?,
+            None => opt
+                .contracts
+                .for_chain(&network.name, &ChainTargets::new(&network.name)),
+        };
+        targets.push(NetworkTarget {
+            name: network.name,
+            rpc_url: network.value,
+            contracts,
+        });
+    }
+
+    let report = audit_networks(targets, status.parallelism).await;
+    if status.json {
+        report.write_json(stdout())?;
+    } else {
+        report.write_table(stdout())?;
+    }
+    ensure!(report.is_consistent(), "audit failed or networks diverge");
+    Ok(())
+}
+
+async fn diff(opt: Options, diff: DiffOptions) -> anyhow::Result<()> {
+    let contracts =
+        load_deployment_file(&diff.deployment)?.with_light_client_params(opt.light_client)?;
+    let genesis = match &opt.genesis_file {
+        Some(path) => Some(ParsedLightClientState::from_file(path)?),
+        None => opt.genesis.clone(),
+    };
+    let provider = Provider::<Http>::try_from(opt.rpc_url.to_string())?;
+    let report = diff_deployment(Arc::new(provider), &contracts, genesis.as_ref()).await?;
+    if diff.json {
+        report.write_json(stdout())?;
+    } else {
+        report.write_table(stdout())?;
+    }
+    ensure!(
+        report.is_clean(),
+        "deployment does not match {}",
+        diff.deployment.display()
+    );
+    Ok(())
 }
 
 #[async_std::main]
@@ -97,63 +1026,698 @@ async fn main() -> anyhow::Result<()> {
     setup_backtrace();
 
     let opt = Options::parse();
-    let mut contracts = Contracts::from(opt.contracts);
+    if let Some(Command::Status(status_opt)) = opt.command.clone() {
+        return status(opt, status_opt).await;
+    }
+    if let Some(Command::Diff(diff_opt)) = opt.command.clone() {
+        return diff(opt, diff_opt).await;
+    }
+
+    for label in opt.contracts.scoped_chains() {
+        ensure!(
+            label == opt.chain_name || opt.chains.iter().any(|chain| chain.name == label),
+            "predeployed address is scoped to unknown chain {label}"
+        );
+    }
+    let address_warnings = opt.contracts.validate()?;
+    let predeployed = opt
+        .contracts
+        .for_chain(&opt.chain_name, &chain_targets(&opt));
+    let mut contracts = configure(&opt, predeployed)?;
+    for warning in address_warnings {
+        contracts.warn(warning);
+    }
 
     let provider = Provider::<Http>::try_from(opt.rpc_url.to_string())?;
     let chain_id = provider.get_chainid().await?.as_u64();
-    let wallet = MnemonicBuilder::<English>::default()
+    if let Some(contracts_chain_id) = opt.contracts_chain_id {
+        contracts = contracts.with_chain_id(contracts_chain_id);
+        contracts.validate(&provider).await?;
+    }
+    if let Some(explorer) = Explorer::resolve(opt.explorer_url.clone(), chain_id) {
+        contracts = contracts.with_explorer(explorer);
+    }
+    let unsigned_sender = match &opt.unsigned {
+        Some(dir) => {
+            let sender = match opt.from {
+                Some(from) => from,
+                None => mnemonic_wallet(&opt)?.address(),
+            };
+            contracts = contracts.with_unsigned_output(dir, sender);
+            Some(sender)
+        }
+        None => None,
+    };
+    if opt.create2 {
+        contracts = contracts.with_create2(Create2Config {
+            factory: opt.create2_factory,
+            salt: opt.create2_salt.clone(),
+        });
+    }
+    if let Some(path) = &opt.state_file {
+        contracts = contracts.with_state_file(path, chain_id)?;
+    }
+    let fee_chain = match &opt.fee_contract_chain {
+        Some(name) => Some(FeeChain::connect(&opt, name).await?),
+        None => None,
+    };
+
+    // Build the signer, and run the deployment with it.
+    if let Some(sender) = unsigned_sender {
+        let l1 = Arc::new(provider.with_sender(sender));
+        run(&opt, contracts, l1, sender, chain_id, fee_chain).await
+    } else if opt.ledger {
+        let path = match &opt.derivation_path {
+            Some(path) => HDPath::Other(path.clone()),
+            None => HDPath::LedgerLive(opt.account_index as usize),
+        };
+        let ledger = Ledger::new(path, chain_id).await?;
+        let signer = PromptingSigner::new(ledger);
+        let owner = signer.address();
+        tracing::info!("deploying from Ledger account {owner:#x}");
+        contracts = contracts.with_observer(signer.observer());
+        let l1 = Arc::new(SignerMiddleware::new(provider, signer));
+        run(&opt, contracts, l1, owner, chain_id, fee_chain).await
+    } else if let Some(key_id) = &opt.aws_kms_key_id {
+        let kms = KmsClient::new(Region::default());
+        let signer = AwsSigner::new(kms, key_id, chain_id).await?;
+        let owner = signer.address();
+        tracing::info!("deploying from AWS KMS account {owner:#x}");
+        let l1 = Arc::new(SignerMiddleware::new(provider, signer));
+        run(&opt, contracts, l1, owner, chain_id, fee_chain).await
+    } else {
+        let wallet = mnemonic_wallet(&opt)?.with_chain_id(chain_id);
+        let owner = wallet.address();
+        let l1 = Arc::new(SignerMiddleware::new(provider, wallet));
+        run(&opt, contracts, l1, owner, chain_id, fee_chain).await
+    }
+}
+
+/// Apply the deployment options shared by every chain to `contracts`.
+fn configure(opt: &Options, contracts: Contracts) -> anyhow::Result<Contracts> {
+    if let (Some(max_fee), Some(priority_fee)) = (opt.max_fee_per_gas, opt.max_priority_fee_per_gas)
+    {
+        ensure!(
+            priority_fee <= max_fee,
+            "--max-priority-fee-per-gas must not exceed --max-fee-per-gas"
+        );
+    }
+    ensure!(
+        opt.fee_bump_percent > 0,
+        "--fee-bump-percent must be positive"
+    );
+    ensure!(
+        opt.gas_limit_multiplier.is_finite() && opt.gas_limit_multiplier >= 1.0,
+        "--gas-limit-multiplier must be at least 1"
+    );
+    let artifact = if opt.use_mock_contract {
+        LightClientArtifact::Mock
+    } else {
+        LightClientArtifact::Production
+    };
+    let bytecode_source = match (&opt.artifacts_dir, &opt.light_client_artifact) {
+        (Some(dir), _) => BytecodeSource::from_dir(dir, artifact),
+        (None, Some(path)) => BytecodeSource::File(path.clone()),
+        (None, None) => BytecodeSource::Embedded,
+    };
+    let contracts = contracts
+        .with_retry_policy(RetryPolicy {
+            max_attempts: opt.max_attempts,
+            initial_backoff: opt.initial_backoff,
+            max_backoff: opt.max_backoff,
+        })
+        .with_receipt_polling(ReceiptPolling {
+            interval: opt.receipt_poll_interval,
+            drop_after_blocks: opt.drop_after_blocks,
+            confirmations: opt.confirmations,
+            timeout: opt.pending_timeout,
+        })
+        .allow_duplicate_bytecode(opt.allow_duplicate_bytecode)
+        .prepare_owner_transactions(matches!(
+            &opt.command,
+            Some(Command::Upgrade(UpgradeOptions { safe: true, .. }))
+        ))
+        .with_light_client_params(opt.light_client)?
+        .with_bytecode_source(artifact, bytecode_source)
+        .with_flavor(if opt.use_mock_contract {
+            Flavor::Mock
+        } else {
+            Flavor::Production
+        })
+        // These contracts are the same whether or not the light client is a mock.
+        .allow_cross_flavor(Contract::HotShot)
+        .allow_cross_flavor(Contract::PlonkVerifier)
+        .allow_cross_flavor(Contract::FeeContract)
+        .allow_cross_flavor(Contract::FeeContractProxy)
+        .with_gas_config(GasConfig {
+            max_fee_per_gas: opt.max_fee_per_gas,
+            max_priority_fee_per_gas: opt.max_priority_fee_per_gas,
+            legacy_gas_price: opt.legacy_gas_price,
+            legacy: opt.legacy,
+            gas_limit_percent: (opt.gas_limit_multiplier * 100.0).round() as u64,
+            fee_wait_timeout: opt.fee_wait_timeout,
+            tx_timeout: opt.tx_timeout,
+            fee_bump_percent: opt.fee_bump_percent,
+        });
+    Ok(if opt.dry_run {
+        contracts.with_mode(DeployMode::DryRun)
+    } else {
+        contracts
+    })
+}
+
+/// Which chain each contract is deployed to.
+fn chain_targets(opt: &Options) -> ChainTargets {
+    let targets = ChainTargets::new(&opt.chain_name);
+    match &opt.fee_contract_chain {
+        Some(name) => targets
+            .deploy_on(Contract::FeeContract, name)
+            .deploy_on(Contract::FeeContractProxy, name),
+        None => targets,
+    }
+}
+
+/// A chain other than the one at --rpc-url, which the fee contract is deployed to.
+struct FeeChain {
+    name: String,
+    l1: Arc<SignerMiddleware<Provider<Http>, LocalWallet>>,
+    owner: Address,
+    contracts: Contracts,
+}
+
+impl FeeChain {
+    /// Connect to the chain `name`, given with --chain.
+    async fn connect(opt: &Options, name: &str) -> anyhow::Result<Self> {
+        ensure!(
+            name != opt.chain_name,
+            "--fee-contract-chain must differ from --chain-name"
+        );
+        let rpc_url = &opt
+            .chains
+            .iter()
+            .find(|chain| chain.name == name)
+            .with_context(|| format!("unknown chain {name}, use --chain {name}=RPC_URL"))?
+            .value;
+        let provider = Provider::<Http>::try_from(rpc_url.to_string())?;
+        let chain_id = provider.get_chainid().await?.as_u64();
+
+        // The cache only ever holds addresses on this chain, so record it.
+        let mut contracts = configure(opt, opt.contracts.for_chain(name, &chain_targets(opt)))?
+            .with_chain_id(chain_id);
+        if let Some(explorer) = Explorer::resolve(None, chain_id) {
+            contracts = contracts.with_explorer(explorer);
+        }
+
+        let wallet = mnemonic_wallet(opt)?.with_chain_id(chain_id);
+        let owner = wallet.address();
+        tracing::info!("deploying the fee contract to {name} (chain {chain_id})");
+        Ok(Self {
+            name: name.into(),
+            l1: Arc::new(SignerMiddleware::new(provider, wallet)),
+            owner,
+            contracts,
+        })
+    }
+
+    /// Deploy the fee contract, fund the builders in `deposits`, and transfer it to OWNER if given.
+    async fn deploy(&mut self, opt: &Options, deposits: &BuilderDeposits) -> anyhow::Result<()> {
+        if opt.skip_verify {
+            self.contracts.skip_verify_predeployed();
+        } else {
+            self.contracts.verify_predeployed(self.l1.clone()).await?;
+        }
+        if opt.check_code {
+            self.contracts.check_selectors(self.l1.clone()).await?;
+        }
+        deploy_fee_contract(self.l1.clone(), &mut self.contracts, self.owner).await?;
+        deposit_builder_balances(self.l1.clone(), &mut self.contracts, deposits).await?;
+        if let Some(new_owner) = opt.owner {
+            transfer_ownership(
+                self.l1.clone(),
+                &mut self.contracts,
+                Contract::FeeContractProxy,
+                self.owner,
+                new_owner,
+            )
+            .await?;
+        }
+        self.contracts
+            .check_owner(self.l1.clone(), self.owner)
+            .await
+    }
+}
+
+/// The contracts deployed to every chain.
+fn multi_chain(
+    opt: &Options,
+    contracts: &Contracts,
+    fee_chain: &FeeChain,
+) -> anyhow::Result<MultiChainContracts> {
+    let mut multi = MultiChainContracts::new(chain_targets(opt));
+    multi.add_chain(&opt.chain_name, contracts.clone())?;
+    multi.add_chain(&fee_chain.name, fee_chain.contracts.clone())?;
+    Ok(multi)
+}
+
+/// The wallet for the account indicated by MNEMONIC and ACCOUNT_INDEX.
+fn mnemonic_wallet(opt: &Options) -> anyhow::Result<LocalWallet> {
+    Ok(MnemonicBuilder::<English>::default()
         .phrase(opt.mnemonic.as_str())
         .index(opt.account_index)?
-        .build()?
-        .with_chain_id(chain_id);
-    let owner = wallet.address();
-    let l1 = Arc::new(SignerMiddleware::new(provider, wallet));
+        .build()?)
+}
+
+/// Deploy the contracts using `l1`, which sends transactions from `owner`, and write the results.
+///
+/// If `fee_chain` is given, the fee contract is deployed to it instead of `l1`.
+async fn run<M: Middleware + 'static>(
+    opt: &Options,
+    mut contracts: Contracts,
+    l1: Arc<M>,
+    owner: Address,
+    chain_id: u64,
+    mut fee_chain: Option<FeeChain>,
+) -> anyhow::Result<()> {
+    if let Some(Command::Cancel(cancel)) = &opt.command {
+        ensure!(
+            contracts.mode() == DeployMode::Execute,
+            "a transaction cannot be cancelled in a dry run"
+        );
+        cancel_transaction(l1, &contracts, cancel.nonce.into()).await?;
+        return Ok(());
+    }
+    if let Some(Command::Staking(staking)) = &opt.command {
+        return stake(l1, &contracts, &staking.command).await;
+    }
+    if let Some(url) = &opt.fork_url {
+        return rehearse(opt, &contracts, owner, url.clone()).await;
+    }
+
+    if contracts.mode() == DeployMode::DryRun {
+        deploy(opt, l1.clone(), &mut contracts, owner, fee_chain.as_mut()).await?;
+        contracts.write_plan(stdout(), l1.get_gas_price().await?)?;
+        if let Some(fee_chain) = &fee_chain {
+            println!("On {}:", fee_chain.name);
+            fee_chain
+                .contracts
+                .write_plan(stdout(), fee_chain.l1.get_gas_price().await?)?;
+            fee_chain.contracts.write_warnings(stdout())?;
+            ensure!(
+                fee_chain.contracts.plan_succeeds(),
+                "some deployments on {} would fail",
+                fee_chain.name
+            );
+        }
+        if let Some(dir) = &opt.unsigned {
+            println!(
+                "Wrote {} unsigned transactions to {}; sign and broadcast them in order.",
+                contracts.unsigned_written(),
+                dir.display()
+            );
+        }
+        contracts.write_warnings(stdout())?;
+        ensure!(contracts.plan_succeeds(), "some deployments would fail");
+        if let Some(min) = opt.warnings_as_errors {
+            contracts.check_warnings(min)?;
+        }
+        return Ok(());
+    }
+
+    // Assign nonces explicitly, so that concurrent deployments and retries never collide.
+    contracts = contracts.with_managed_nonce(&*l1).await?;
+    if let Some(fee_chain) = fee_chain.as_mut() {
+        fee_chain.contracts = mem::take(&mut fee_chain.contracts)
+            .with_managed_nonce(&*fee_chain.l1)
+            .await?;
+    }
+
+    let verifier = if opt.verify && !opt.no_verify {
+        let api = match &opt.verifier_url {
+            Some(url) => EtherscanConfig::new(url.clone(), &opt.build_info),
+            None => EtherscanConfig::for_chain(chain_id, &opt.build_info).with_context(|| {
+                format!("no verification API is known for chain {chain_id}, use --verifier-url")
+            })?,
+        };
+        Some(match &opt.verifier_api_key {
+            Some(key) => api.with_api_key(key),
+            None => api,
+        })
+    } else {
+        None
+    };
+
+    let notifier = match &opt.notify_webhook {
+        Some(url) => {
+            let planned = planned_contracts(&contracts, opt.use_mock_contract);
+            let notifier =
+                DeploymentNotifier::start(Webhook::new(url.clone()), chain_id, owner, planned)
+                    .await;
+            contracts = contracts.with_observer(notifier.observer());
+            if let Some(fee_chain) = fee_chain.as_mut() {
+                fee_chain.contracts =
+                    mem::take(&mut fee_chain.contracts).with_observer(notifier.observer());
+            }
+            Some(notifier)
+        }
+        None => None,
+    };
+    let balance = l1.get_balance(owner, None).await?;
+
+    if let Err(err) = deploy(opt, l1.clone(), &mut contracts, owner, fee_chain.as_mut()).await {
+        if let Some(notifier) = &notifier {
+            notifier.fail(&err).await;
+        }
+        return Err(err);
+    }
+    if let Some(notifier) = &notifier {
+        let cost = match l1.get_balance(owner, None).await {
+            Ok(remaining) => balance.saturating_sub(remaining),
+            Err(err) => {
+                tracing::warn!("error fetching deployer balance: {err}");
+                U256::zero()
+            }
+        };
+        notifier.finish(&contracts, cost).await;
+    }
+    if let Some(api) = verifier {
+        for warning in verify_contracts(&contracts, api).await {
+            contracts.warn(warning);
+        }
+    }
+
+    if let Some(fee_chain) = &fee_chain {
+        ensure!(
+            opt.output_format == OutputFormat::Env,
+            "only env output is supported when deploying to several chains"
+        );
+        let multi = multi_chain(opt, &contracts, fee_chain)?;
+        match &opt.out {
+            Some(dir) if opt.env_layout == EnvLayout::PerChain => {
+                for path in multi.write_per_chain(dir)? {
+                    tracing::info!("wrote {}", path.display());
+                }
+            }
+            Some(out) => multi.write_prefixed(File::create(out)?)?,
+            None => multi.write_prefixed(stdout())?,
+        }
+    } else if let Some(out) = opt.out.as_ref().filter(|_| opt.update_out) {
+        contracts.update_env_file(out)?;
+    } else {
+        let chain_id = l1.get_chainid().await?.as_u64();
+        match &opt.out {
+            Some(out) => {
+                let file = File::options()
+                    .create(true)
+                    .truncate(true)
+                    .write(true)
+                    .open(out)?;
+                contracts.write_as(opt.output_format, file, chain_id)?;
+            }
+            None => contracts.write_as(opt.output_format, stdout(), chain_id)?,
+        }
+    }
+    if let Some(out) = &opt.json_out {
+        contracts.write_json(File::create(out)?)?;
+    }
+    if let Some(path) = &opt.report {
+        contracts.write_report(File::create(path)?)?;
+    }
+    if let Some(path) = &opt.json_report {
+        contracts.write_report_json(File::create(path)?)?;
+    }
+    if let Some(path) = &opt.receipts_out {
+        contracts.write_receipts_json(File::create(path)?)?;
+    }
+    contracts.write_summary(stderr())?;
+    if let Some(fee_chain) = &fee_chain {
+        eprintln!("On {}:", fee_chain.name);
+        fee_chain.contracts.write_summary(stderr())?;
+    }
+    contracts.write_gas_report(stderr())?;
+    contracts.write_report(stderr())?;
+    for path in contracts.write_safe_batches(&opt.safe_batch_dir)? {
+        eprintln!(
+            "Wrote Safe transaction batch to {}; import it into the Safe Transaction Builder to \
+             execute it.",
+            path.display()
+        );
+    }
+
+    if !opt.render.is_empty() {
+        let vars = TemplateVars::new(&contracts).chain_id(chain_id);
+        for target in &opt.render {
+            target.render(&vars)?;
+        }
+    }
+
+    if let Some(min) = opt.warnings_as_errors {
+        contracts.check_warnings(min)?;
+        if let Some(fee_chain) = &fee_chain {
+            fee_chain.contracts.check_warnings(min)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run a `staking` command, sending any transactions with `l1`.
+async fn stake<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &Contracts,
+    command: &StakingCommand,
+) -> anyhow::Result<()> {
+    if let StakingCommand::List(list) = command {
+        let nodes = read_stake_table(l1, contracts).await?;
+        for node in &nodes {
+            tracing::info!(
+                "node {} registered by {:#x} with stake {} from epoch {}",
+                node.staking_key,
+                node.account,
+                node.balance,
+                node.register_epoch
+            );
+        }
+        let peers: Vec<_> = nodes.iter().map(RegisteredNode::peer_config).collect();
+        match &list.out {
+            Some(path) => serde_json::to_writer_pretty(File::create(path)?, &peers)?,
+            None => serde_json::to_writer_pretty(stdout(), &peers)?,
+        }
+        return Ok(());
+    }
+
+    ensure!(
+        contracts.mode() == DeployMode::Execute,
+        "staking transactions cannot be sent in a dry run"
+    );
+    match command {
+        StakingCommand::Register(register) => {
+            let (staking_key, state_key) = register.keys.private_keys()?;
+            let state_key = StateKeyPair::from_sign_key(state_key).ver_key();
+            register_node(
+                l1,
+                contracts,
+                &staking_key,
+                &state_key,
+                register.amount,
+                register.valid_until_epoch,
+            )
+            .await?;
+        }
+        StakingCommand::Exit(keys) => {
+            let (staking_key, _) = keys.private_keys()?;
+            request_exit(l1, contracts, &BLSPubKey::from_private(&staking_key)).await?;
+        }
+        StakingCommand::Withdraw(keys) => {
+            let (staking_key, _) = keys.private_keys()?;
+            withdraw_stake(l1, contracts, &BLSPubKey::from_private(&staking_key)).await?;
+        }
+        StakingCommand::List(_) => {}
+    }
+    Ok(())
+}
+
+/// Parse an amount in gwei, like `1.5`, into wei.
+fn parse_gwei(s: &str) -> Result<U256, String> {
+    parse_units(s, "gwei")
+        .map(U256::from)
+        .map_err(|err| format!("invalid gwei amount {s}: {err}"))
+}
+
+async fn deploy<M: Middleware + 'static>(
+    opt: &Options,
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    owner: Address,
+    fee_chain: Option<&mut FeeChain>,
+) -> anyhow::Result<Option<ParsedLightClientState>> {
+    if opt.skip_verify {
+        contracts.skip_verify_predeployed();
+    } else {
+        contracts.verify_predeployed(l1.clone()).await?;
+    }
+    if opt.check_code {
+        contracts.check_selectors(l1.clone()).await?;
+    }
+
+    if let Some(Command::Upgrade(upgrade)) = &opt.command {
+        let proxy = contracts
+            .address(Contract::LightClientProxy)
+            .context("the light client proxy to upgrade must be given with --light-client-proxy")?;
+        upgrade_light_client(l1, contracts, proxy, None, upgrade.call_data.clone()).await?;
+        for batch in contracts.safe_batches() {
+            println!(
+                "Upgrade transaction for the owner of the light client, {:#x}:",
+                batch.meta.created_from_safe_address
+            );
+            for tx in &batch.transactions {
+                println!("  to   {:#x}", tx.to);
+                println!("  data {}", tx.data);
+            }
+        }
+        return Ok(None);
+    }
+    if let Some(Command::SetProver(set_prover)) = &opt.command {
+        ensure!(
+            contracts.address(Contract::LightClientProxy).is_some(),
+            "the light client proxy must be given with --light-client-proxy"
+        );
+        let config = ProverConfig {
+            prover: Some(set_prover.prover),
+            force: true,
+        };
+        set_permissioned_prover(l1, contracts, Contract::LightClientProxy, config).await?;
+        return Ok(None);
+    }
+
+    // Settle the genesis state before deploying anything, so a bogus genesis is caught early.
+    let fetched = match &opt.genesis_from_sequencer {
+        Some(url) => {
+            Some(fetch_genesis_state(url.clone(), opt.light_client.stake_table_capacity).await?)
+        }
+        None => None,
+    };
+    let explicit = match &opt.genesis_file {
+        Some(path) => Some(ParsedLightClientState::from_file(path)?),
+        None => opt.genesis.clone(),
+    };
+    let genesis = reconcile_genesis(contracts, explicit, fetched, opt.force_genesis)?;
+    let deposits = match &opt.builder_deposits {
+        Some(path) => BuilderDeposits::from_file(path)?,
+        None => BuilderDeposits::default(),
+    };
 
     contracts
         .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ())?)
         .await?;
 
-    if opt.use_mock_contract {
+    let genesis = if opt.use_mock_contract {
         // LightClientMock is a non-upgradable contract, thus directly initialize
         // it via its constructor
-        contracts
-            .deploy_fn(Contract::LightClient, |contracts| {
-                deploy_mock_light_client_contract(l1.clone(), contracts, None).boxed()
-            })
-            .await?;
+        let blocks_per_epoch = opt.light_client.blocks_per_epoch;
+        let constructor_args = genesis
+            .clone()
+            .map(|genesis| (genesis.into(), blocks_per_epoch));
+        deploy_light_client(
+            l1.clone(),
+            contracts,
+            LightClientKind::Mock { constructor_args },
+        )
+        .await?;
+        let chain_id = l1.get_chainid().await?.as_u64();
+        if !is_local_chain(chain_id) {
+            contracts.warn(Warning::MockOnPublicNetwork {
+                contract: Contract::LightClient,
+                chain_id,
+            });
+        }
+        genesis
     } else {
         // LightClient is a upgradable contract, thus deploy first,
         // then initialize it through a proxy contract
-        let lc_address = contracts
-            .deploy_fn(Contract::LightClient, |contracts| {
-                deploy_light_client_contract(l1.clone(), contracts).boxed()
-            })
-            .await?;
-        let light_client = LightClient::new(lc_address, l1.clone());
-
-        let genesis = light_client_genesis(&opt.orchestrator_url, opt.stake_table_capacity).await?;
-        let data = light_client
-            .initialize(genesis.into(), u32::MAX, owner)
-            .calldata()
-            .context("calldata for initialize transaction not available")?;
-        contracts
-            .deploy_tx(
-                Contract::LightClientProxy,
-                ERC1967Proxy::deploy(l1.clone(), (lc_address, data))?,
-            )
-            .await?;
+        let genesis = match genesis {
+            Some(genesis) => genesis,
+            None => {
+                let genesis = light_client_genesis(
+                    &opt.orchestrator_url,
+                    opt.light_client.stake_table_capacity,
+                )
+                .await?;
+                check_genesis(&genesis)?;
+                genesis
+            }
+        };
+        // The light client is handed over to OWNER as soon as it is initialized.
+        let prover = ProverConfig {
+            prover: opt.permissioned_prover,
+            force: opt.force_prover_update,
+        };
+        deploy_production_stack(
+            l1.clone(),
+            contracts,
+            genesis.clone(),
+            owner,
+            prover,
+            opt.owner,
+        )
+        .await?;
+        Some(genesis)
+    };
+    if let Some(token) = opt.stake_table_token {
+        deploy_stake_table(
+            l1.clone(),
+            contracts,
+            &opt.stake_table_artifact,
+            token,
+            opt.stake_table_churn_rate,
+        )
+        .await?;
+    }
+    let fee_on_l1 = fee_chain.is_none();
+    match fee_chain {
+        Some(fee_chain) => fee_chain.deploy(opt, &deposits).await?,
+        None => {
+            deploy_fee_contract(l1.clone(), contracts, owner).await?;
+            deposit_builder_balances(l1.clone(), contracts, &deposits).await?;
+        }
     }
 
-    if let Some(out) = &opt.out {
-        let file = File::options()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(out)?;
-        contracts.write(file)?;
-    } else {
-        contracts.write(stdout())?;
+    if let Some(new_owner) = opt.owner {
+        let mut contracts_to_transfer = vec![];
+        if opt.use_mock_contract {
+            contracts_to_transfer.push(Contract::LightClient);
+        }
+        if fee_on_l1 {
+            contracts_to_transfer.push(Contract::FeeContractProxy);
+        }
+        for contract in contracts_to_transfer {
+            transfer_ownership(l1.clone(), contracts, contract, owner, new_owner).await?;
+        }
     }
+    contracts.check_owner(l1, owner).await?;
+    Ok(genesis)
+}
 
+/// Rehearse the deployment on a fork of the chain at `url`, sending from `owner`, and check the
+/// deployed contracts.
+async fn rehearse(
+    opt: &Options,
+    contracts: &Contracts,
+    owner: Address,
+    url: Url,
+) -> anyhow::Result<()> {
+    let fork = Fork::spawn(&ForkConfig {
+        rpc_url: url,
+        block_number: opt.fork_block_number,
+        deployer: owner,
+    })
+    .await?;
+    let mut contracts = fork.contracts(contracts);
+    let genesis = deploy(opt, fork.l1.clone(), &mut contracts, owner, None)
+        .await
+        .context("deployment failed on the fork")?;
+    let diff = diff_deployment(fork.l1.clone(), &contracts, genesis.as_ref()).await?;
+    println!("Rehearsed deployment on a fork at block {}:", fork.block);
+    diff.write_table(stdout())?;
+    contracts.write_warnings(stdout())?;
+    ensure!(diff.is_clean(), "deployment on the fork does not check out");
     Ok(())
 }
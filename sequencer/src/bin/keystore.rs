@@ -0,0 +1,98 @@
+//! Utility program to manage encrypted keystores
+
+use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
+
+use clap::{Parser, Subcommand};
+use hotshot::types::SignatureKey;
+use hotshot_types::{light_client::StateKeyPair, signature_key::BLSPubKey};
+use rand::{RngCore, SeedableRng};
+use sequencer::keystore::{read_key_file, read_password, Keystore};
+use std::path::PathBuf;
+
+/// Utility program to manage encrypted keystores
+///
+/// A keystore holds the private keys of a sequencer node, encrypted with a password. A node can
+/// load its keys from a keystore with the `--keystore` and `--keystore-password-file` options,
+/// instead of reading them in plaintext from a key file or the environment.
+#[derive(Clone, Debug, Parser)]
+struct Options {
+    /// Keystore directory.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_KEYSTORE")]
+    keystore: PathBuf,
+
+    /// File containing the keystore password.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_KEYSTORE_PASSWORD_FILE")]
+    password_file: PathBuf,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum Command {
+    /// Generate new keys into a new keystore.
+    Generate,
+
+    /// Encrypt the keys in a key file, as generated by `keygen`, into a new keystore.
+    Import {
+        /// Key file in .env format.
+        key_file: PathBuf,
+    },
+
+    /// Replace the state key in an existing keystore with a newly generated one.
+    ///
+    /// A node running from the keystore starts signing with the new key within a few seconds,
+    /// without a restart. The old key is kept in the keystore directory.
+    Rotate,
+}
+
+fn gen_seed() -> [u8; 32] {
+    let mut seed = [0u8; 32];
+    let mut rng = rand_chacha::ChaChaRng::from_entropy();
+    rng.fill_bytes(&mut seed);
+    seed
+}
+
+fn main() -> anyhow::Result<()> {
+    setup_logging();
+    setup_backtrace();
+
+    let opt = Options::parse();
+    let password = read_password(&opt.password_file)?;
+
+    match opt.command {
+        Command::Generate => {
+            let (pub_key, staking_key) = BLSPubKey::generated_from_seed_indexed(gen_seed(), 0);
+            let state_key = StateKeyPair::generate_from_seed_indexed(gen_seed(), 0);
+            Keystore::create(
+                &opt.keystore,
+                &password,
+                &staking_key,
+                state_key.sign_key_ref(),
+            )?;
+            tracing::info!(%pub_key, "generated staking key");
+            tracing::info!(pub_key = %state_key.ver_key(), "generated state key");
+        }
+        Command::Import { key_file } => {
+            let (staking_key, state_key) = read_key_file(&key_file)?;
+            Keystore::create(&opt.keystore, &password, &staking_key, &state_key)?;
+            tracing::info!(
+                pub_key = %BLSPubKey::from_private(&staking_key),
+                "imported staking key"
+            );
+            tracing::info!(
+                pub_key = %StateKeyPair::from_sign_key(state_key).ver_key(),
+                "imported state key"
+            );
+        }
+        Command::Rotate => {
+            let keystore = Keystore::open(&opt.keystore)?;
+            let state_key = StateKeyPair::generate_from_seed_indexed(gen_seed(), 0);
+            keystore.rotate_state_key(&password, state_key.sign_key_ref())?;
+            tracing::info!(pub_key = %state_key.ver_key(), "rotated state key");
+        }
+    }
+    tracing::info!("keystore written to {}", opt.keystore.display());
+
+    Ok(())
+}
@@ -0,0 +1,200 @@
+//! Utility program to replay decided blocks and find where the state diverges from storage.
+
+use anyhow::{bail, Context};
+use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
+use clap::{Parser, Subcommand};
+use es_version::SequencerVersion;
+use ethers::types::{Address, U256};
+use hotshot_query_service::{
+    availability::{AvailabilityDataSource, LeafQueryData},
+    status::StatusDataSource,
+};
+use sequencer::{
+    api::data_source::{DataSourceOptions, SequencerDataSource},
+    catchup::StatePeers,
+    genesis::Genesis,
+    persistence,
+    replay::replay,
+    ChainConfig, L1Params, NodeState, SeqTypes, ValidatedState,
+};
+use std::{path::PathBuf, process::exit, time::Duration};
+use url::Url;
+
+/// Replay decided blocks and compare the state they produce with the state roots in their headers.
+///
+/// Each block in the range is re-executed on top of the state committed to by its parent, and
+/// replay stops at the first block whose state roots differ or whose header fails validation. Only
+/// the state of the parent of the first block is taken on trust: replaying from genesis checks the
+/// whole chain, while replaying from a later block fetches the state it needs from state peers.
+#[derive(Clone, Debug, Parser)]
+struct Options {
+    /// First block to replay.
+    #[clap(long, default_value = "0")]
+    from: u64,
+
+    /// Stop replaying before block TO.
+    ///
+    /// Defaults to the current block height of the source.
+    #[clap(long)]
+    to: Option<u64>,
+
+    /// The genesis file the chain was started from.
+    ///
+    /// This determines the genesis state, and the chain config of headers which only commit to it.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_GENESIS_FILE")]
+    genesis_file: Option<PathBuf>,
+
+    /// Builder accounts the chain was started with, prefunded for demos.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_PREFUNDED_BUILDER_ACCOUNTS",
+        value_delimiter = ','
+    )]
+    prefunded_builder_accounts: Vec<Address>,
+
+    /// URLs of L1 RPC providers, to read fee deposits from.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_L1_PROVIDER",
+        value_delimiter = ',',
+        required = true
+    )]
+    l1_provider_url: Vec<Url>,
+
+    /// Peer nodes to fetch the state from, when replaying from a block other than genesis.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_STATE_PEERS", value_delimiter = ',')]
+    state_peers: Vec<Url>,
+
+    /// How long to wait for each leaf from the source.
+    #[clap(long, value_parser = sequencer::options::parse_duration, default_value = "10s")]
+    fetch_timeout: Duration,
+
+    /// Where to read decided leaves from.
+    #[clap(subcommand)]
+    source: Source,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum Source {
+    /// Read decided leaves from file system storage.
+    Fs(persistence::fs::Options),
+    /// Read decided leaves from SQL storage.
+    Sql(persistence::sql::Options),
+    /// Read decided leaves from a query service.
+    Query {
+        /// URL of the query service.
+        url: Url,
+    },
+}
+
+type SequencerClient = surf_disco::Client<hotshot_query_service::Error, SequencerVersion>;
+
+#[async_std::main]
+async fn main() -> anyhow::Result<()> {
+    setup_logging();
+    setup_backtrace();
+
+    let opt = Options::parse();
+    let instance = node_state(&opt)?;
+    let res = match &opt.source {
+        Source::Fs(storage) => replay_storage(&opt, &instance, storage.clone()).await,
+        Source::Sql(storage) => replay_storage(&opt, &instance, storage.clone()).await,
+        Source::Query { url } => replay_query(&opt, &instance, url.clone()).await,
+    };
+    match res? {
+        Some(divergence) => {
+            tracing::error!("{divergence}");
+            exit(1);
+        }
+        None => {
+            tracing::info!("replayed state matches stored state");
+            Ok(())
+        }
+    }
+}
+
+/// The node state the chain was started with, as far as replay depends on it.
+fn node_state(opt: &Options) -> anyhow::Result<NodeState> {
+    let genesis = opt
+        .genesis_file
+        .as_ref()
+        .map(Genesis::from_file)
+        .transpose()?;
+
+    let mut genesis_state = ValidatedState::default();
+    for address in &opt.prefunded_builder_accounts {
+        genesis_state.prefund_account((*address).into(), U256::max_value().into());
+    }
+    if let Some(genesis) = &genesis {
+        genesis.fund_accounts(&mut genesis_state);
+    }
+    let chain_config = genesis
+        .as_ref()
+        .map(Genesis::chain_config)
+        .unwrap_or_else(ChainConfig::default);
+
+    let l1_params = L1Params {
+        urls: opt.l1_provider_url.clone(),
+        rate_limit: None,
+        upgrade_contract: None,
+        upgrade_start_block: 0,
+    };
+    Ok(NodeState::new(
+        chain_config,
+        l1_params.client(Address::default()),
+        StatePeers::<SequencerVersion>::from_urls(opt.state_peers.clone()),
+    )
+    .with_genesis(genesis_state))
+}
+
+async fn replay_storage<O: DataSourceOptions>(
+    opt: &Options,
+    instance: &NodeState,
+    storage: O,
+) -> anyhow::Result<Option<sequencer::replay::Divergence>> {
+    let ds = O::DataSource::create(storage, Default::default(), false).await?;
+    let to = match opt.to {
+        Some(to) => to,
+        None => ds.block_height().await? as u64,
+    };
+    tracing::info!("replaying blocks [{}, {to}) from storage", opt.from);
+
+    let ds = &ds;
+    replay(instance, opt.from, to, |height| async move {
+        let leaf = ds
+            .get_leaf(height as usize)
+            .await
+            .with_timeout(opt.fetch_timeout)
+            .await
+            .with_context(|| format!("leaf {height} not in storage"))?;
+        Ok(leaf.leaf().clone())
+    })
+    .await
+}
+
+async fn replay_query(
+    opt: &Options,
+    instance: &NodeState,
+    url: Url,
+) -> anyhow::Result<Option<sequencer::replay::Divergence>> {
+    let client = SequencerClient::new(url);
+    if !client.connect(Some(opt.fetch_timeout)).await {
+        bail!("could not connect to query service");
+    }
+    let to = match opt.to {
+        Some(to) => to,
+        None => client.get("status/block-height").send().await?,
+    };
+    tracing::info!("replaying blocks [{}, {to}) from query service", opt.from);
+
+    let client = &client;
+    replay(instance, opt.from, to, |height| async move {
+        let leaf: LeafQueryData<SeqTypes> = client
+            .get(&format!("availability/leaf/{height}"))
+            .send()
+            .await
+            .with_context(|| format!("fetching leaf {height}"))?;
+        Ok(leaf.leaf().clone())
+    })
+    .await
+}
@@ -1,7 +1,10 @@
 use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
 use clap::Parser;
 use es_version::SEQUENCER_VERSION;
-use sequencer::state_signature::relay_server::run_relay_server;
+use sequencer::state_signature::relay_server::{
+    run_relay_server, stake_table_from_orchestrator, RelayConfig,
+};
+use url::Url;
 
 #[derive(Parser)]
 struct Args {
@@ -14,7 +17,7 @@ struct Args {
     )]
     port: u16,
 
-    /// Threshold to form an available state signature package.
+    /// Threshold to form an available state signature package, if there is no stake table.
     /// WARNING: this is a temporary flag, should remove after integrating with stake table.
     /// Related issue: [https://github.com/EspressoSystems/espresso-sequencer/issues/1022]
     #[clap(
@@ -24,6 +27,32 @@ struct Args {
         default_value = "3"
     )]
     threshold: u64,
+
+    /// URL of the HotShot orchestrator, to read the stake table from.
+    ///
+    /// If given, signatures are weighted by stake, only nodes in the stake table may sign, and the
+    /// threshold is two thirds of the total stake.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_ORCHESTRATOR_URL")]
+    orchestrator_url: Option<Url>,
+
+    /// URLs of sequencer nodes to pull state signatures from.
+    ///
+    /// This is in addition to signatures nodes push to the relay server, so that signatures are
+    /// collected even from nodes which are not configured with this relay server.
+    #[clap(
+        long,
+        env = "ESPRESSO_STATE_RELAY_SERVER_SEQUENCER_URLS",
+        value_delimiter = ','
+    )]
+    sequencer_url: Vec<Url>,
+
+    /// URLs of other relay servers to forward new signatures to.
+    #[clap(
+        long,
+        env = "ESPRESSO_STATE_RELAY_SERVER_PEER_URLS",
+        value_delimiter = ','
+    )]
+    peer_url: Vec<Url>,
 }
 
 #[async_std::main]
@@ -33,10 +62,23 @@ async fn main() {
 
     let args = Args::parse();
 
+    let stake_table = match args.orchestrator_url {
+        Some(url) => {
+            tracing::info!("reading stake table from orchestrator at {url}");
+            Some(stake_table_from_orchestrator(url).await)
+        }
+        None => None,
+    };
+
     tracing::info!("starting state relay server on port {}", args.port);
     run_relay_server(
         None,
-        args.threshold,
+        RelayConfig {
+            threshold: args.threshold,
+            stake_table,
+            sequencers: args.sequencer_url,
+            peers: args.peer_url,
+        },
         format!("http://0.0.0.0:{}", args.port).parse().unwrap(),
         SEQUENCER_VERSION,
     )
@@ -1,6 +1,6 @@
 use crate::block::entry::{TxTableEntry, TxTableEntryWord};
 use crate::block::payload;
-use crate::{BlockBuildingSnafu, Error, NamespaceId, Transaction};
+use crate::{BlockBuildingSnafu, Error, Header, NamespaceId, Transaction};
 use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use derivative::Derivative;
 use hotshot::traits::BlockPayload;
@@ -108,8 +108,6 @@ impl<TableWord: TableWordTraits> Payload<TableWord> {
         ))
     }
 
-    // TODO dead code even with `pub` because this module is private in lib.rs
-    #[allow(dead_code)]
     /// Returns the flat bytes for namespace `ns_id`, along with a proof of correctness for those bytes.
     ///
     /// RPC-friendly proof contains:
@@ -148,6 +146,16 @@ impl<TableWord: TableWordTraits> Payload<TableWord> {
         })
     }
 
+    /// Returns the id and the flat bytes of each namespace, in the order of the namespace table.
+    pub fn namespace_payloads(&self) -> impl Iterator<Item = (NamespaceId, &[u8])> + '_ {
+        (0..self.ns_table.len()).map(|ns_index| {
+            let (ns_id, ns_payload_range) = self
+                .ns_table
+                .get_payload_range(ns_index, self.raw_payload.len());
+            (ns_id, &self.raw_payload[ns_payload_range])
+        })
+    }
+
     pub fn get_ns_table(&self) -> &NameSpaceTable<TableWord> {
         &self.ns_table
     }
@@ -247,7 +255,6 @@ impl NamespaceProof {
     /// Verify a [`NamespaceProof`].
     ///
     /// All args must be available to the verifier in the block header.
-    #[allow(dead_code)] // TODO temporary
     pub fn verify(
         &self,
         vid: &VidSchemeType,
@@ -293,6 +300,34 @@ impl NamespaceProof {
             }
         }
     }
+
+    /// Verify a [`NamespaceProof`] against the block header `header`.
+    ///
+    /// The VID scheme is reconstructed from the VID common data carried in the proof, so the caller
+    /// needs nothing but the header, which it can check independently (e.g. against a light client).
+    pub fn verify_header(&self, header: &Header) -> Option<(Vec<Transaction>, NamespaceId)> {
+        let num_storage_nodes = match self {
+            NamespaceProof::Existence { vid_common, .. } => {
+                VidSchemeType::get_num_storage_nodes(vid_common) as usize
+            }
+            // The VID scheme is not used to verify non-existence.
+            NamespaceProof::NonExistence { .. } => 1,
+        };
+        self.verify(
+            &vid_scheme(num_storage_nodes),
+            &header.payload_commitment,
+            &header.ns_table,
+        )
+    }
+
+    /// The namespace this proof is for.
+    pub fn namespace(&self) -> NamespaceId {
+        match self {
+            NamespaceProof::Existence { ns_id, .. } | NamespaceProof::NonExistence { ns_id } => {
+                *ns_id
+            }
+        }
+    }
 }
 
 pub fn parse_ns_payload(ns_bytes: &[u8], ns_id: NamespaceId) -> Vec<Transaction> {
@@ -170,6 +170,13 @@ impl<TableWord: TableWordTraits> NameSpaceTable<TableWord> {
         (ns_id, ns_offset)
     }
 
+    /// The total size of the namespaces in this table, according to their offsets.
+    pub fn payload_byte_len(&self) -> usize {
+        (0..self.len())
+            .map(|ns_index| self.get_payload_range(ns_index, usize::MAX).1.len())
+            .sum()
+    }
+
     /// Like `tx_payload_range` except for namespaces.
     /// Returns the ns id and the ns byte range in the block payload bytes.
     ///
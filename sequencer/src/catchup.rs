@@ -2,11 +2,15 @@ use crate::{
     api::endpoints::{AccountQueryData, BlocksFrontier},
     state::{BlockMerkleTree, FeeAccount, FeeMerkleCommitment},
 };
+use anyhow::{bail, ensure};
 use async_trait::async_trait;
 use hotshot_types::{data::ViewNumber, traits::node_implementation::ConsensusTime as _};
 use jf_primitives::merkle_tree::{ForgetableMerkleTreeScheme, MerkleTreeScheme};
 use serde::de::DeserializeOwned;
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use surf_disco::Request;
 use tide_disco::error::ServerError;
 use url::Url;
@@ -47,14 +51,61 @@ pub trait StateCatchup: Send + Sync + std::fmt::Debug {
         view: ViewNumber,
         mt: &mut BlockMerkleTree,
     ) -> anyhow::Result<()>;
+
+    /// Replace the peers to catch up from, for providers which fetch state from peers.
+    fn set_peers(&self, _urls: Vec<Url>) -> anyhow::Result<()> {
+        bail!("state catchup is not configured with peers")
+    }
+}
+
+/// The track record of a catchup peer.
+#[derive(Debug, Clone, Copy, Default)]
+struct PeerScore {
+    successes: u64,
+    failures: u64,
+    /// Failures since the last success.
+    consecutive_failures: u64,
+}
+
+impl PeerScore {
+    /// The fraction of requests to this peer which succeeded, assuming one of each to start.
+    fn success_rate(&self) -> f64 {
+        (self.successes + 1) as f64 / (self.successes + self.failures + 2) as f64
+    }
 }
 
+/// State catchup from a list of peers' HTTP APIs.
+///
+/// Peers are scored by the responses they give: a peer which fails to respond, or responds with
+/// an invalid proof, is tried after peers which have been responding, until it succeeds again.
+/// All peers are still tried before giving up on a round, so a peer with a poor score remains a
+/// fallback. Clones share their peers, which can be replaced with [`set_peers`](StateCatchup::set_peers)
+/// while the node is running.
 #[derive(Debug, Clone, Default)]
 pub struct StatePeers<Ver: StaticVersionType> {
-    clients: Vec<Client<ServerError, Ver>>,
+    peers: Arc<Mutex<Peers<Ver>>>,
     interval: Duration,
 }
 
+#[derive(Debug, Default)]
+struct Peers<Ver: StaticVersionType> {
+    clients: Vec<Client<ServerError, Ver>>,
+    scores: Vec<PeerScore>,
+}
+
+impl<Ver: StaticVersionType> Peers<Ver> {
+    fn record(&mut self, index: usize, success: bool) {
+        let score = &mut self.scores[index];
+        if success {
+            score.successes += 1;
+            score.consecutive_failures = 0;
+        } else {
+            score.failures += 1;
+            score.consecutive_failures += 1;
+        }
+    }
+}
+
 impl<Ver: StaticVersionType> StatePeers<Ver> {
     pub fn from_urls(urls: Vec<Url>) -> Self {
         if urls.is_empty() {
@@ -62,22 +113,77 @@ impl<Ver: StaticVersionType> StatePeers<Ver> {
         }
 
         Self {
-            clients: urls.into_iter().map(Client::new).collect(),
+            peers: Arc::new(Mutex::new(Peers {
+                scores: vec![Default::default(); urls.len()],
+                clients: urls.into_iter().map(Client::new).collect(),
+            })),
             interval: Duration::from_secs(1),
         }
     }
 
+    /// The indices of the peers, in the order they should be tried.
+    ///
+    /// Peers which failed most recently come last, and otherwise peers with a better success rate
+    /// come first. Ties keep the configured order.
+    fn ranked(&self) -> Vec<usize> {
+        self.ranked_clients().into_iter().map(|(i, _)| i).collect()
+    }
+
+    /// The peers, with their indices, in the order they should be tried.
+    fn ranked_clients(&self) -> Vec<(usize, Client<ServerError, Ver>)> {
+        let peers = self.peers.lock().unwrap();
+        let scores = &peers.scores;
+        let mut ranked = (0..peers.clients.len()).collect::<Vec<_>>();
+        ranked.sort_by(|&i, &j| {
+            scores[i]
+                .consecutive_failures
+                .cmp(&scores[j].consecutive_failures)
+                .then(
+                    scores[j]
+                        .success_rate()
+                        .total_cmp(&scores[i].success_rate()),
+                )
+        });
+        ranked
+            .into_iter()
+            .map(|i| (i, peers.clients[i].clone()))
+            .collect()
+    }
+
+    /// Record the outcome of a request to the peer at `index`, if it is still the peer at `url`.
+    ///
+    /// If the peers were replaced since the request was made, the outcome is ignored.
+    fn record_peer(&self, index: usize, url: &Url, success: bool) {
+        let mut peers = self.peers.lock().unwrap();
+        if peers
+            .clients
+            .get(index)
+            .is_some_and(|client| &client.url == url)
+        {
+            peers.record(index, success);
+        }
+    }
+
+    /// Record the outcome of a request to the peer at `index`.
+    fn record(&self, index: usize, success: bool) {
+        self.peers.lock().unwrap().record(index, success);
+    }
+
+    fn ensure_peers(&self) {
+        if self.peers.lock().unwrap().clients.is_empty() {
+            panic!("No peers to fetch state from");
+        }
+    }
+
     async fn fetch_account(
         &self,
         view: ViewNumber,
         fee_merkle_tree_root: FeeMerkleCommitment,
         account: FeeAccount,
     ) -> AccountQueryData {
-        if self.clients.is_empty() {
-            panic!("No peers to fetch account from");
-        }
+        self.ensure_peers();
         loop {
-            for client in self.clients.iter() {
+            for (i, client) in self.ranked_clients() {
                 tracing::info!(
                     "Fetching account {account:?} for view {view:?} from {}",
                     client.url
@@ -91,13 +197,17 @@ impl<Ver: StaticVersionType> StatePeers<Ver> {
                     .await
                 {
                     Ok(res) => match res.proof.verify(&fee_merkle_tree_root) {
-                        Ok(_) => return res,
+                        Ok(_) => {
+                            self.record_peer(i, &client.url, true);
+                            return res;
+                        }
                         Err(err) => tracing::warn!("Error verifying account proof: {}", err),
                     },
                     Err(err) => {
                         tracing::warn!("Error fetching account from peer: {}", err);
                     }
                 }
+                self.record_peer(i, &client.url, false);
             }
             tracing::warn!("Could not fetch account from any peer, retrying");
             async_std::task::sleep(self.interval).await;
@@ -131,11 +241,9 @@ impl<Ver: StaticVersionType> StateCatchup for StatePeers<Ver> {
         view: ViewNumber,
         mt: &mut BlockMerkleTree,
     ) -> anyhow::Result<()> {
-        if self.clients.is_empty() {
-            panic!("No peers to fetch frontier from");
-        }
+        self.ensure_peers();
         loop {
-            for client in self.clients.iter() {
+            for (i, client) in self.ranked_clients() {
                 tracing::info!("Fetching frontier from {}", client.url);
                 match client
                     .get::<BlocksFrontier>(&format!("catchup/{}/blocks", view.get_u64()))
@@ -145,13 +253,16 @@ impl<Ver: StaticVersionType> StateCatchup for StatePeers<Ver> {
                     Ok(frontier) => {
                         let Some(elem) = frontier.elem() else {
                             tracing::warn!("Provided frontier is missing leaf element");
+                            self.record_peer(i, &client.url, false);
                             continue;
                         };
                         match mt.remember(mt.num_leaves() - 1, *elem, &frontier) {
-                            Ok(_) => return Ok(()),
+                            Ok(_) => {
+                                self.record_peer(i, &client.url, true);
+                                return Ok(());
+                            }
                             Err(err) => {
                                 tracing::warn!("Error verifying block proof: {}", err);
-                                continue;
                             }
                         }
                     }
@@ -159,11 +270,36 @@ impl<Ver: StaticVersionType> StateCatchup for StatePeers<Ver> {
                         tracing::warn!("Error fetching blocks from peer: {}", err);
                     }
                 }
+                self.record_peer(i, &client.url, false);
             }
             tracing::warn!("Could not fetch frontier from any peer, retrying");
             async_std::task::sleep(self.interval).await;
         }
     }
+
+    /// Replace the peers, keeping the scores of peers which remain.
+    ///
+    /// Requests already in progress finish with the old peers.
+    fn set_peers(&self, urls: Vec<Url>) -> anyhow::Result<()> {
+        ensure!(!urls.is_empty(), "cannot replace state peers with no peers");
+        let mut peers = self.peers.lock().unwrap();
+        let scores = urls
+            .iter()
+            .map(|url| {
+                peers
+                    .clients
+                    .iter()
+                    .position(|client| &client.url == url)
+                    .map(|i| peers.scores[i])
+                    .unwrap_or_default()
+            })
+            .collect();
+        *peers = Peers {
+            clients: urls.into_iter().map(Client::new).collect(),
+            scores,
+        };
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -186,6 +322,10 @@ impl<T: StateCatchup + ?Sized> StateCatchup for Box<T> {
     ) -> anyhow::Result<()> {
         (**self).remember_blocks_merkle_tree(view, mt).await
     }
+
+    fn set_peers(&self, urls: Vec<Url>) -> anyhow::Result<()> {
+        (**self).set_peers(urls)
+    }
 }
 
 #[async_trait]
@@ -208,6 +348,10 @@ impl<T: StateCatchup + ?Sized> StateCatchup for Arc<T> {
     ) -> anyhow::Result<()> {
         (**self).remember_blocks_merkle_tree(view, mt).await
     }
+
+    fn set_peers(&self, urls: Vec<Url>) -> anyhow::Result<()> {
+        (**self).set_peers(urls)
+    }
 }
 
 #[cfg(any(test, feature = "testing"))]
@@ -276,3 +420,56 @@ pub mod mock {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use es_version::SequencerVersion;
+
+    #[test]
+    fn test_peer_ranking() {
+        let peers = StatePeers::<SequencerVersion>::from_urls(
+            ["http://a", "http://b", "http://c"]
+                .into_iter()
+                .map(|url| url.parse().unwrap())
+                .collect(),
+        );
+        assert_eq!(peers.ranked(), [0, 1, 2]);
+
+        // A failing peer is tried last, even if it used to be reliable.
+        peers.record(0, true);
+        peers.record(0, false);
+        assert_eq!(peers.ranked(), [1, 2, 0]);
+
+        // Among responsive peers, the more reliable one is tried first.
+        peers.record(1, false);
+        peers.record(1, true);
+        peers.record(2, true);
+        assert_eq!(peers.ranked(), [2, 1, 0]);
+
+        // A peer which recovers is tried again.
+        peers.record(0, true);
+        assert_eq!(peers.ranked(), [2, 0, 1]);
+    }
+
+    #[test]
+    fn test_set_peers() {
+        let urls = |urls: &[&str]| urls.iter().map(|url| url.parse().unwrap()).collect();
+        let peers = StatePeers::<SequencerVersion>::from_urls(urls(&["http://a", "http://b"]));
+        peers.record(0, false);
+        assert_eq!(peers.ranked(), [1, 0]);
+
+        // Peers which remain keep their scores, and new peers start fresh.
+        let clone = peers.clone();
+        clone
+            .set_peers(urls(&["http://c", "http://a", "http://b"]))
+            .unwrap();
+        assert_eq!(peers.ranked(), [0, 2, 1]);
+
+        // Outcomes of requests to replaced peers are ignored.
+        peers.record_peer(0, &"http://a".parse().unwrap(), true);
+        assert_eq!(peers.ranked(), [0, 2, 1]);
+
+        peers.set_peers(vec![]).unwrap_err();
+    }
+}
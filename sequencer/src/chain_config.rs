@@ -1,11 +1,15 @@
-use crate::state::FeeAmount;
+use crate::{genesis::Genesis, namespace_policy::NamespacePolicy, state::FeeAmount};
+use anyhow::ensure;
 use committable::{Commitment, Committable};
 use derive_more::{From, Into};
-use ethers::types::U256;
+use ethers::types::{Address, U256};
 use itertools::Either;
 use sequencer_utils::impl_to_fixed_bytes;
 use serde::{Deserialize, Serialize};
 
+/// The base fee changes by at most 1/8 of its value from one block to the next, as in EIP-1559.
+const BASE_FEE_MAX_CHANGE_DENOMINATOR: u64 = 8;
+
 #[derive(Default, Hash, Copy, Clone, Debug, Deserialize, Serialize, PartialEq, Eq, From, Into)]
 pub struct ChainId(U256);
 
@@ -26,6 +30,37 @@ pub struct ChainConfig {
     max_block_size: u64,
     /// Minimum fee in WEI per byte of payload
     base_fee: FeeAmount,
+    /// Commitment to the namespace policy, if the chain restricts namespaces
+    #[serde(default)]
+    namespace_policy: Option<Commitment<NamespacePolicy>>,
+    /// Target size in bytes of a block, if the base fee adjusts to congestion
+    #[serde(default)]
+    target_block_size: Option<u64>,
+    /// Commitment to the genesis file the chain was started from, if any
+    #[serde(default)]
+    genesis: Option<Commitment<Genesis>>,
+    /// L1 contract scheduling upgrades of this config, if upgrades are governed on L1
+    #[serde(default)]
+    upgrade_contract: Option<Address>,
+}
+
+/// The limits on the contents of a block set by a [`ChainConfig`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BlockLimits {
+    /// Maximum size in bytes of a block payload.
+    pub max_block_size: u64,
+}
+
+impl BlockLimits {
+    /// Check that a transaction with `size` bytes of payload can be included in a block.
+    pub fn check_transaction(&self, size: u64) -> anyhow::Result<()> {
+        ensure!(
+            size <= self.max_block_size,
+            "payload of {size} bytes exceeds the maximum block size of {} bytes",
+            self.max_block_size
+        );
+        Ok(())
+    }
 }
 
 impl Default for ChainConfig {
@@ -48,11 +83,125 @@ impl ChainConfig {
             chain_id: chain_id.into(),
             max_block_size,
             base_fee: base_fee.into(),
+            namespace_policy: None,
+            target_block_size: None,
+            genesis: None,
+            upgrade_contract: None,
         }
     }
+
+    /// Follow upgrades scheduled by the `UpgradeGovernance` contract at `contract` on L1.
+    pub fn with_upgrade_contract(mut self, contract: Address) -> Self {
+        self.upgrade_contract = Some(contract);
+        self
+    }
+
+    /// This config with the parameters set by a protocol upgrade.
+    ///
+    /// Everything else, including the contract governing further upgrades, is unchanged.
+    pub fn upgrade(
+        self,
+        chain_id: impl Into<ChainId>,
+        max_block_size: u64,
+        base_fee: impl Into<FeeAmount>,
+    ) -> Self {
+        Self {
+            chain_id: chain_id.into(),
+            max_block_size,
+            base_fee: base_fee.into(),
+            ..self
+        }
+    }
+
+    /// Commit to the genesis file the chain was started from.
+    pub fn with_genesis(mut self, genesis: Commitment<Genesis>) -> Self {
+        self.genesis = Some(genesis);
+        self
+    }
+
+    /// Adjust the base fee to congestion, aiming for blocks of `target_block_size` bytes.
+    ///
+    /// The base fee of each block is then recorded in its header. It rises when blocks are larger
+    /// than the target and falls when they are smaller, but never below the configured base fee.
+    /// Every proposal must pay that base fee for each byte of payload, so builders must read it
+    /// from the parent header (or compute it with [`next_base_fee`](Self::next_base_fee)) rather
+    /// than rely on the fixed base fee of the chain config. The builders in this repository get
+    /// their fees from hotshot-builder-core, which does not, so this cannot be configured from
+    /// the command line or a genesis file yet.
+    pub fn with_target_block_size(mut self, target_block_size: u64) -> Self {
+        self.target_block_size = Some(target_block_size.max(1));
+        self
+    }
+
+    /// Restrict namespaces according to `policy`.
+    ///
+    /// An empty policy leaves the configuration, and so its commitment, unchanged.
+    pub fn with_namespace_policy(mut self, policy: &NamespacePolicy) -> Self {
+        self.namespace_policy = (!policy.is_empty()).then(|| policy.commit());
+        self
+    }
+
     pub fn max_block_size(&self) -> u64 {
         self.max_block_size
     }
+
+    pub fn base_fee(&self) -> FeeAmount {
+        self.base_fee
+    }
+
+    pub fn namespace_policy(&self) -> Option<Commitment<NamespacePolicy>> {
+        self.namespace_policy
+    }
+
+    pub fn target_block_size(&self) -> Option<u64> {
+        self.target_block_size
+    }
+
+    pub fn genesis(&self) -> Option<Commitment<Genesis>> {
+        self.genesis
+    }
+
+    pub fn upgrade_contract(&self) -> Option<Address> {
+        self.upgrade_contract
+    }
+
+    pub fn block_limits(&self) -> BlockLimits {
+        BlockLimits {
+            max_block_size: self.max_block_size,
+        }
+    }
+
+    /// The base fee of the genesis block, if the base fee is dynamic.
+    pub fn genesis_base_fee(&self) -> Option<FeeAmount> {
+        self.target_block_size.map(|_| self.base_fee)
+    }
+
+    /// The base fee of the block after one with `parent_base_fee` and `parent_size` bytes of
+    /// payload, if the base fee is dynamic.
+    ///
+    /// Like EIP-1559, the base fee changes by at most 1/8 per block, in proportion to how far the
+    /// parent was from the target size. A parent without a base fee is treated as paying the configured base fee,
+    /// which is also the floor.
+    pub fn next_base_fee(
+        &self,
+        parent_base_fee: Option<FeeAmount>,
+        parent_size: u64,
+    ) -> Option<FeeAmount> {
+        let target = self.target_block_size?;
+        let parent_base_fee = U256::from(parent_base_fee.unwrap_or(self.base_fee));
+        let change = |diff: u64| {
+            parent_base_fee.saturating_mul(diff.into())
+                / U256::from(target)
+                / U256::from(BASE_FEE_MAX_CHANGE_DENOMINATOR)
+        };
+        let next = if parent_size > target {
+            // Always increase by at least 1, so that a zero base fee can rise.
+            parent_base_fee.saturating_add(change(parent_size - target).max(U256::one()))
+        } else {
+            parent_base_fee.saturating_sub(change(target - parent_size))
+        };
+        Some(next.max(self.base_fee.into()).into())
+    }
 }
 
 impl Committable for ChainConfig {
@@ -61,11 +210,29 @@ impl Committable for ChainConfig {
     }
 
     fn commit(&self) -> Commitment<Self> {
-        committable::RawCommitmentBuilder::new(&Self::tag())
+        let comm = committable::RawCommitmentBuilder::new(&Self::tag())
             .fixed_size_field("chain_id", &self.chain_id.to_fixed_bytes())
             .u64_field("max_block_size", self.max_block_size)
-            .fixed_size_field("base_fee", &self.base_fee.to_fixed_bytes())
-            .finalize()
+            .fixed_size_field("base_fee", &self.base_fee.to_fixed_bytes());
+        // Only chains which restrict namespaces commit to a policy, so that the commitment of other
+        // chain configs is unchanged.
+        let comm = match self.namespace_policy {
+            Some(policy) => comm.field("namespace_policy", policy),
+            None => comm,
+        };
+        let comm = match self.target_block_size {
+            Some(target) => comm.u64_field("target_block_size", target),
+            None => comm,
+        };
+        let comm = match self.genesis {
+            Some(genesis) => comm.field("genesis", genesis),
+            None => comm,
+        };
+        let comm = match self.upgrade_contract {
+            Some(contract) => comm.fixed_size_field("upgrade_contract", contract.as_fixed_bytes()),
+            None => comm,
+        };
+        comm.finalize()
     }
 }
 
@@ -122,6 +289,74 @@ mod tests {
         assert!(chain_config != other_config);
     }
 
+    #[test]
+    fn test_chain_config_namespace_policy() {
+        let chain_config = ChainConfig::default();
+        assert_eq!(
+            chain_config.with_namespace_policy(&Default::default()),
+            chain_config
+        );
+
+        let policy = NamespacePolicy {
+            reserved: [0.into()].into(),
+            ..Default::default()
+        };
+        let restricted = chain_config.with_namespace_policy(&policy);
+        assert_eq!(restricted.namespace_policy(), Some(policy.commit()));
+        assert_ne!(restricted.commit(), chain_config.commit());
+    }
+
+    #[test]
+    fn test_next_base_fee() {
+        let chain_config = ChainConfig::new(0, 10000, 100);
+        assert_eq!(chain_config.genesis_base_fee(), None);
+        assert_eq!(chain_config.next_base_fee(None, 1000), None);
+
+        let chain_config = chain_config.with_target_block_size(1000);
+        assert_ne!(
+            chain_config.commit(),
+            ChainConfig::new(0, 10000, 100).commit()
+        );
+        assert_eq!(chain_config.genesis_base_fee(), Some(100.into()));
+
+        // Full blocks raise the fee by at most 1/8, empty blocks lower it by at most 1/8.
+        assert_eq!(
+            chain_config.next_base_fee(Some(800.into()), 1000),
+            Some(800.into())
+        );
+        assert_eq!(
+            chain_config.next_base_fee(Some(800.into()), 2000),
+            Some(900.into())
+        );
+        assert_eq!(
+            chain_config.next_base_fee(Some(800.into()), 1500),
+            Some(850.into())
+        );
+        assert_eq!(
+            chain_config.next_base_fee(Some(800.into()), 0),
+            Some(700.into())
+        );
+
+        // The fee never drops below the configured base fee.
+        assert_eq!(
+            chain_config.next_base_fee(Some(100.into()), 0),
+            Some(100.into())
+        );
+        assert_eq!(chain_config.next_base_fee(None, 0), Some(100.into()));
+
+        // A zero fee can still rise.
+        let free = ChainConfig::new(0, 10000, 0).with_target_block_size(1000);
+        assert_eq!(free.next_base_fee(Some(0.into()), 1001), Some(1.into()));
+    }
+
+    #[test]
+    fn test_block_limits() {
+        let chain_config = ChainConfig::new(0, 1000, 0);
+        let limits = chain_config.block_limits();
+        limits.check_transaction(1000).unwrap();
+        limits.check_transaction(1001).unwrap_err();
+    }
+
     #[test]
     fn test_resolve_chain_config() {
         let chain_config = ChainConfig::default();
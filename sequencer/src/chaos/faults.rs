@@ -0,0 +1,397 @@
+//! Faults injected by the adversarial client.
+//!
+//! Unlike the other actions, which misuse the API through well-formed requests, faults misuse the
+//! transport: they drop connections halfway through a request, send requests which are not valid
+//! HTTP, or hold connections open without ever finishing a request. They are sent over raw TCP, so
+//! they are only injected against `http` URLs.
+//!
+//! Each fault checks an invariant of the server:
+//! * a malformed request gets a client error, or the connection is closed without a response, but
+//!   never a success, a server error, or no answer at all
+//! * a header requested from ahead of the chain, if it is served at all, has the requested height,
+//!   and a header from behind the chain is always served with the right height
+//! * the server stays responsive after dropped connections and while slow connections are open
+
+use super::Options;
+use crate::{options::parse_duration, Header};
+use anyhow::{bail, ensure, Context};
+use async_std::{
+    future::timeout,
+    io::{ReadExt, WriteExt},
+    net::TcpStream,
+    task::sleep,
+};
+use clap::Args;
+use es_version::SequencerVersion;
+use rand::{seq::SliceRandom, RngCore};
+use std::time::{Duration, Instant};
+use strum::{EnumDiscriminants, VariantArray};
+use surf_disco::{error::ClientError, Url};
+
+#[derive(Clone, Debug, Args)]
+pub struct FaultOptions {
+    /// The weight of dropping a connection in the middle of a request.
+    #[clap(
+        long,
+        env = "ESPRESSO_NASTY_CLIENT_WEIGHT_DISCONNECT",
+        default_value = "2"
+    )]
+    pub weight_disconnect: u8,
+
+    /// The weight of sending a request which is not valid.
+    #[clap(
+        long,
+        env = "ESPRESSO_NASTY_CLIENT_WEIGHT_MALFORMED_REQUEST",
+        default_value = "2"
+    )]
+    pub weight_malformed_request: u8,
+
+    /// The weight of querying headers just ahead of and behind the current block height.
+    #[clap(
+        long,
+        env = "ESPRESSO_NASTY_CLIENT_WEIGHT_STALE_HEIGHT",
+        default_value = "3"
+    )]
+    pub weight_stale_height: u8,
+
+    /// The weight of holding many connections open without finishing a request.
+    #[clap(
+        long,
+        env = "ESPRESSO_NASTY_CLIENT_WEIGHT_SLOW_LORIS",
+        default_value = "1"
+    )]
+    pub weight_slow_loris: u8,
+
+    /// The maximum number of connections opened at once by a slow loris fault.
+    #[clap(
+        long,
+        env = "ESPRESSO_NASTY_CLIENT_MAX_SLOW_CONNECTIONS",
+        default_value = "50"
+    )]
+    pub max_slow_connections: u16,
+
+    /// How long a slow loris fault holds its connections open.
+    #[clap(
+        long,
+        env = "ESPRESSO_NASTY_CLIENT_SLOW_LORIS_DURATION",
+        default_value = "10s",
+        value_parser = parse_duration
+    )]
+    pub slow_loris_duration: Duration,
+}
+
+impl FaultOptions {
+    fn weight(&self, fault: FaultDiscriminants) -> u8 {
+        match fault {
+            FaultDiscriminants::Disconnect => self.weight_disconnect,
+            FaultDiscriminants::MalformedRequest => self.weight_malformed_request,
+            FaultDiscriminants::StaleHeight => self.weight_stale_height,
+            FaultDiscriminants::SlowLoris => self.weight_slow_loris,
+        }
+    }
+
+    /// The combined weight of all faults, relative to the other actions of the client.
+    pub fn total_weight(&self) -> u32 {
+        FaultDiscriminants::VARIANTS
+            .iter()
+            .map(|fault| self.weight(*fault) as u32)
+            .sum()
+    }
+}
+
+#[derive(Clone, Copy, Debug, VariantArray, PartialEq, Eq)]
+pub enum Malformed {
+    /// A well-formed request with a block height which is not a number.
+    NonNumericHeight,
+    /// Bytes which are not an HTTP request at all.
+    Garbage,
+    /// A request with a header far larger than any legitimate client would send.
+    OversizedHeader,
+}
+
+#[derive(Clone, Copy, Debug, EnumDiscriminants)]
+#[strum_discriminants(derive(VariantArray, Hash))]
+pub enum Fault {
+    Disconnect { at: u64 },
+    MalformedRequest { kind: Malformed, at: u64 },
+    StaleHeight { offset: u64 },
+    SlowLoris { connections: u16 },
+}
+
+impl FaultDiscriminants {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Disconnect => "disconnect",
+            Self::MalformedRequest => "malformed_request",
+            Self::StaleHeight => "stale_height",
+            Self::SlowLoris => "slow_loris",
+        }
+    }
+}
+
+impl Fault {
+    pub fn random(rng: &mut impl RngCore, opt: &FaultOptions) -> Self {
+        match FaultDiscriminants::VARIANTS
+            .choose_weighted(rng, |fault| opt.weight(*fault))
+            .unwrap()
+        {
+            FaultDiscriminants::Disconnect => Self::Disconnect { at: rng.next_u64() },
+            FaultDiscriminants::MalformedRequest => Self::MalformedRequest {
+                kind: *Malformed::VARIANTS.choose(rng).unwrap(),
+                at: rng.next_u64(),
+            },
+            FaultDiscriminants::StaleHeight => Self::StaleHeight {
+                offset: rng.next_u64() % 16 + 1,
+            },
+            FaultDiscriminants::SlowLoris => Self::SlowLoris {
+                connections: (rng.next_u32() % opt.max_slow_connections.max(1) as u32) as u16 + 1,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FaultInjector {
+    client: surf_disco::Client<ClientError, SequencerVersion>,
+    url: Url,
+    http_timeout: Duration,
+    opt: FaultOptions,
+}
+
+impl FaultInjector {
+    pub fn new(opt: &Options, faults: FaultOptions) -> Self {
+        Self {
+            client: surf_disco::Client::builder(opt.url.clone())
+                .set_timeout(Some(opt.http_timeout))
+                .build(),
+            url: opt.url.clone(),
+            http_timeout: opt.http_timeout,
+            opt: faults,
+        }
+    }
+
+    pub fn options(&self) -> &FaultOptions {
+        &self.opt
+    }
+
+    pub async fn inject(&self, fault: Fault) -> anyhow::Result<()> {
+        if self.url.scheme() != "http" && !matches!(fault, Fault::StaleHeight { .. }) {
+            tracing::warn!(?fault, "not injecting transport fault against non-HTTP URL");
+            return Ok(());
+        }
+
+        match fault {
+            Fault::Disconnect { at } => self.disconnect(at).await,
+            Fault::MalformedRequest { kind, at } => self.malformed_request(kind, at).await,
+            Fault::StaleHeight { offset } => self.stale_height(offset).await,
+            Fault::SlowLoris { connections } => self.slow_loris(connections).await,
+        }
+    }
+
+    async fn disconnect(&self, at: u64) -> anyhow::Result<()> {
+        let at = at % self.block_height().await?.max(1);
+        let request = self.request(&format!("availability/block/{at}"), "")?;
+
+        // Send half of a request and hang up. Then send a whole request and hang up before reading
+        // the response.
+        let mut conn = self.connect().await?;
+        conn.write_all(&request.as_bytes()[..request.len() / 2])
+            .await
+            .context("writing partial request")?;
+        drop(conn);
+        let mut conn = self.connect().await?;
+        conn.write_all(request.as_bytes())
+            .await
+            .context("writing request")?;
+        drop(conn);
+
+        self.check_responsive()
+            .await
+            .context("server is not responsive after dropped connections")
+    }
+
+    async fn malformed_request(&self, kind: Malformed, at: u64) -> anyhow::Result<()> {
+        let request = match kind {
+            Malformed::NonNumericHeight => {
+                self.request(&format!("availability/header/{at:x}g"), "")?
+            }
+            Malformed::Garbage => format!("\x00\x01{at}\r\n\r\n\r\nGET GET GET\r\n\r\n"),
+            Malformed::OversizedHeader => self.request(
+                &format!("availability/header/{}", at % 16),
+                &format!("X-Nasty: {}\r\n", "a".repeat(1 << 20)),
+            )?,
+        };
+
+        match self.raw_request(request.as_bytes()).await? {
+            Some(status) => ensure!(
+                (400..500).contains(&status),
+                "malformed request ({kind:?}) got status {status}, expected a client error"
+            ),
+            None => tracing::debug!(?kind, "server closed connection on malformed request"),
+        }
+        Ok(())
+    }
+
+    async fn stale_height(&self, offset: u64) -> anyhow::Result<()> {
+        let block_height = self.block_height().await?;
+
+        // A header which does not exist yet may or may not be served, depending on whether the
+        // chain catches up before the request times out, but if it is served it must be right.
+        let ahead = block_height + offset;
+        if let Ok(header) = self
+            .client
+            .get::<Header>(&format!("availability/header/{ahead}"))
+            .send()
+            .await
+        {
+            ensure!(
+                header.height == ahead,
+                "requested header {ahead} ahead of the chain, got header {}",
+                header.height
+            );
+        }
+
+        // A header behind the chain must always be served.
+        if let Some(behind) = block_height.checked_sub(offset) {
+            let header: Header = self
+                .client
+                .get(&format!("availability/header/{behind}"))
+                .send()
+                .await
+                .context(format!("fetching header {behind} behind the chain"))?;
+            ensure!(
+                header.height == behind,
+                "requested header {behind} behind the chain, got header {}",
+                header.height
+            );
+        }
+        Ok(())
+    }
+
+    async fn slow_loris(&self, connections: u16) -> anyhow::Result<()> {
+        let request = self.request("status/block-height", "")?;
+        // Everything but the blank line which ends the headers.
+        let partial = &request.as_bytes()[..request.len() - 2];
+
+        let mut conns = vec![];
+        for _ in 0..connections {
+            let mut conn = self.connect().await?;
+            // The server may give up on a slow connection at any time, which is fine.
+            if conn.write_all(partial).await.is_ok() {
+                conns.push(conn);
+            }
+        }
+        tracing::info!(
+            connections,
+            open = conns.len(),
+            "holding slow connections open"
+        );
+
+        let start = Instant::now();
+        let mut i = 0;
+        while start.elapsed() < self.opt.slow_loris_duration {
+            // Keep each connection alive with another header, without ever finishing the request.
+            for conn in &mut conns {
+                conn.write_all(format!("X-Nasty-{i}: 1\r\n").as_bytes())
+                    .await
+                    .ok();
+            }
+            self.check_responsive().await.context(format!(
+                "server is not responsive with {connections} slow connections"
+            ))?;
+            sleep(Duration::from_secs(1)).await;
+            i += 1;
+        }
+        Ok(())
+    }
+
+    async fn block_height(&self) -> anyhow::Result<u64> {
+        self.client
+            .get("status/block-height")
+            .send()
+            .await
+            .context("getting block height")
+    }
+
+    async fn check_responsive(&self) -> anyhow::Result<()> {
+        self.block_height().await.map(|_| ())
+    }
+
+    /// Format a GET request for the API path `path`, with extra `headers`.
+    fn request(&self, path: &str, headers: &str) -> anyhow::Result<String> {
+        let url = self
+            .url
+            .join(path)
+            .context(format!("invalid path {path}"))?;
+        Ok(format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\n{headers}Connection: close\r\n\r\n",
+            url.path(),
+            self.url.host_str().unwrap_or_default(),
+        ))
+    }
+
+    async fn connect(&self) -> anyhow::Result<TcpStream> {
+        let Some(host) = self.url.host_str() else {
+            bail!("URL {} has no host", self.url);
+        };
+        let port = self.url.port_or_known_default().unwrap_or(80);
+        timeout(self.http_timeout, TcpStream::connect((host, port)))
+            .await
+            .context(format!("timed out connecting to {host}:{port}"))?
+            .context(format!("connecting to {host}:{port}"))
+    }
+
+    /// Send `request` on a new connection, returning the status of the response.
+    ///
+    /// Returns [`None`] if the server closes the connection without responding.
+    async fn raw_request(&self, request: &[u8]) -> anyhow::Result<Option<u16>> {
+        let mut conn = self.connect().await?;
+        if let Err(err) = conn.write_all(request).await {
+            // The server may close the connection before it has read the whole request.
+            tracing::debug!("server closed connection while writing request: {err:#}");
+        }
+
+        let mut buf = [0; 64];
+        match timeout(self.http_timeout, conn.read(&mut buf))
+            .await
+            .context("server did not respond")?
+        {
+            Ok(0) => Ok(None),
+            Ok(n) => parse_status(&buf[..n]).map(Some).context(format!(
+                "invalid response {:?}",
+                String::from_utf8_lossy(&buf[..n])
+            )),
+            Err(err) => {
+                tracing::debug!("server closed connection while reading response: {err:#}");
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Parse the status code from the start of an HTTP/1.x response.
+fn parse_status(response: &[u8]) -> Option<u16> {
+    let line = std::str::from_utf8(response).ok()?.lines().next()?;
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(
+            parse_status(b"HTTP/1.1 400 Bad Request\r\ncontent-length: 0"),
+            Some(400)
+        );
+        assert_eq!(parse_status(b"HTTP/1.0 200 OK"), Some(200));
+        assert_eq!(parse_status(b"HTTP/2 200"), None);
+        assert_eq!(parse_status(b"garbage"), None);
+        assert_eq!(parse_status(b""), None);
+    }
+}
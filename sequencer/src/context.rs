@@ -4,26 +4,33 @@ use async_std::{
 };
 use derivative::Derivative;
 use futures::{
-    future::{join_all, Future},
+    future::{join_all, select, Either, Future},
     stream::{Stream, StreamExt},
 };
 use hotshot::{
     traits::election::static_committee::GeneralStaticCommittee,
-    types::{Event, SystemContextHandle},
+    types::{Event, EventType, SystemContextHandle},
     Memberships, Networks, SystemContext,
 };
 use hotshot_orchestrator::client::OrchestratorClient;
 use hotshot_types::{
     consensus::ConsensusMetricsValue,
-    traits::{election::Membership, metrics::Metrics},
+    traits::{election::Membership, metrics::Metrics, signature_key::SignatureKey},
     HotShotConfig,
 };
 use std::fmt::Display;
+use tracing::Instrument;
 use url::Url;
 use vbs::version::StaticVersionType;
 
 use crate::{
-    network, persistence::SequencerPersistence, state_signature::StateSigner,
+    equivocation::EquivocationDetector,
+    events::{EventFanout, SlowConsumerPolicy, Subscription},
+    network,
+    persistence::SequencerPersistence,
+    snapshot::StateSnapshot,
+    state::ValidatedState,
+    state_signature::StateSigner,
     static_stake_table_commitment, ElectionConfig, Node, NodeState, PubKey, SeqTypes, Transaction,
 };
 use hotshot_events_service::events_source::{EventConsumer, EventsStreamer};
@@ -59,9 +66,29 @@ pub struct SequencerContext<
     /// events streamer to stream hotshot events to external clients
     events_streamer: Arc<RwLock<EventsStreamer<SeqTypes>>>,
 
+    /// Distributes consensus events, once stored, to the tasks which consume them.
+    events: Arc<EventFanout>,
+
     detached: bool,
 
     node_state: NodeState,
+
+    /// The committee running consensus, for computing the leader schedule.
+    #[derivative(Debug = "ignore")]
+    membership: GeneralStaticCommittee<SeqTypes, PubKey>,
+
+    /// The consensus key of this node.
+    public_key: PubKey,
+
+    /// The private consensus key of this node, for signing promises made through the API.
+    #[derivative(Debug = "ignore")]
+    private_key: <PubKey as SignatureKey>::PrivateKey,
+
+    /// The position of this node in the stake table, if it has stake.
+    stake_table_index: Option<usize>,
+
+    #[derivative(Debug = "ignore")]
+    persistence: Arc<RwLock<P>>,
 }
 
 impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static>
@@ -71,6 +98,7 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
         config,
         instance_state,
         persistence,
+        snapshot,
         networks,
         state_relay_server,
         metrics,
@@ -81,6 +109,7 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
         config: HotShotConfig<PubKey, ElectionConfig>,
         instance_state: NodeState,
         persistence: P,
+        snapshot: Option<(StateSnapshot, ValidatedState)>,
         networks: Networks<SeqTypes, Node<N, P>>,
         state_relay_server: Option<Url>,
         metrics: &dyn Metrics,
@@ -88,10 +117,16 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
         stake_table_capacity: usize,
         _: Ver,
     ) -> anyhow::Result<Self> {
-        // Load saved consensus state from storage.
-        let initializer = persistence
-            .load_consensus_state(instance_state.clone())
-            .await?;
+        // Start from the verified snapshot, if given, or else load saved consensus state from
+        // storage.
+        let initializer = match snapshot {
+            Some((snapshot, state)) => snapshot.initializer(instance_state.clone(), state),
+            None => {
+                persistence
+                    .load_consensus_state(instance_state.clone())
+                    .await?
+            }
+        };
 
         let election_config = GeneralStaticCommittee::<SeqTypes, PubKey>::default_election_config(
             config.num_nodes_with_stake.get() as u64,
@@ -112,6 +147,20 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
         let stake_table_commit =
             static_stake_table_commitment(&config.known_nodes_with_stake, stake_table_capacity);
         let state_key_pair = config.my_own_validator_config.state_key_pair.clone();
+        let public_key = config.my_own_validator_config.public_key;
+        let private_key = config.my_own_validator_config.private_key.clone();
+        let stake_table_index = config
+            .known_nodes_with_stake
+            .iter()
+            .position(|peer| peer.stake_table_entry.stake_key == public_key);
+        let equivocation_detector = EquivocationDetector::new(
+            membership.clone(),
+            config
+                .known_nodes_with_stake
+                .iter()
+                .map(|peer| peer.stake_table_entry.stake_key)
+                .collect(),
+        );
 
         let event_streamer = Arc::new(RwLock::new(EventsStreamer::<SeqTypes>::new(
             config.known_nodes_with_stake.clone(),
@@ -119,6 +168,7 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
         )));
 
         let persistence = Arc::new(RwLock::new(persistence));
+        let events = Arc::new(EventFanout::new(metrics));
 
         let handle = SystemContext::init(
             config.my_own_validator_config.public_key,
@@ -145,20 +195,36 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
             node_id,
             state_signer,
             event_streamer,
+            events,
             instance_state,
+            membership,
+            public_key,
+            private_key,
+            stake_table_index,
+            equivocation_detector,
         ))
     }
 
     /// Constructor
+    #[allow(clippy::too_many_arguments)]
     fn new(
         handle: Consensus<N, P>,
         persistence: Arc<RwLock<P>>,
         node_index: u64,
         state_signer: StateSigner<Ver>,
         event_streamer: Arc<RwLock<EventsStreamer<SeqTypes>>>,
+        events: Arc<EventFanout>,
         node_state: NodeState,
+        membership: GeneralStaticCommittee<SeqTypes, PubKey>,
+        public_key: PubKey,
+        private_key: <PubKey as SignatureKey>::PrivateKey,
+        stake_table_index: Option<usize>,
+        equivocation_detector: EquivocationDetector,
     ) -> Self {
-        let events = handle.get_event_stream();
+        let consensus_events = handle.get_event_stream();
+        // Subscribe the consumers before any events are published, so they see every event.
+        let signer_events = events.subscribe("state_signer", SlowConsumerPolicy::Drop);
+        let streamer_events = events.subscribe("event_streaming", SlowConsumerPolicy::Spill);
 
         let mut ctx = Self {
             handle,
@@ -168,16 +234,25 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
             detached: false,
             wait_for_orchestrator: None,
             events_streamer: event_streamer.clone(),
+            events: events.clone(),
             node_state,
+            membership,
+            public_key,
+            private_key,
+            stake_table_index,
+            persistence: persistence.clone(),
         };
         ctx.spawn(
             "main event handler",
-            handle_events(
-                events,
-                persistence,
-                ctx.state_signer.clone(),
-                Some(event_streamer.clone()),
-            ),
+            handle_events(consensus_events, persistence, events, equivocation_detector),
+        );
+        ctx.spawn(
+            "state signer",
+            sign_states(signer_events, ctx.state_signer.clone()),
+        );
+        ctx.spawn(
+            "event streamer",
+            stream_events(streamer_events, event_streamer),
         );
 
         ctx
@@ -215,6 +290,11 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
         self.events_streamer.clone()
     }
 
+    /// Consensus events, published after they have been stored.
+    pub fn event_fanout(&self) -> Arc<EventFanout> {
+        self.events.clone()
+    }
+
     /// Return a reference to the underlying consensus handle.
     pub fn consensus(&self) -> &Consensus<N, P> {
         &self.handle
@@ -224,6 +304,31 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
         self.node_state.clone()
     }
 
+    /// The committee running consensus.
+    pub fn membership(&self) -> &GeneralStaticCommittee<SeqTypes, PubKey> {
+        &self.membership
+    }
+
+    /// The consensus key of this node.
+    pub fn public_key(&self) -> PubKey {
+        self.public_key
+    }
+
+    /// The private consensus key of this node.
+    pub(crate) fn private_key(&self) -> &<PubKey as SignatureKey>::PrivateKey {
+        &self.private_key
+    }
+
+    /// The position of this node in the stake table, if it has stake.
+    pub fn stake_table_index(&self) -> Option<usize> {
+        self.stake_table_index
+    }
+
+    /// The storage of this node.
+    pub fn persistence(&self) -> Arc<RwLock<P>> {
+        self.persistence.clone()
+    }
+
     /// Return a mutable reference to the underlying consensus handle.
     pub fn consensus_mut(&mut self) -> &mut Consensus<N, P> {
         &mut self.handle
@@ -250,9 +355,14 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
     }
 
     /// Stop participating in consensus.
+    ///
+    /// Consensus is stopped first, which closes the network connections and ends the stream of
+    /// events. Any event the main event handler is in the middle of storing is then allowed to finish
+    /// persisting before the background tasks are cancelled.
     pub async fn shut_down(&mut self) {
         tracing::info!("shutting down SequencerContext");
         self.handle.shut_down().await;
+        drop(self.persistence.write().await);
         self.tasks.shut_down().await;
     }
 
@@ -264,6 +374,18 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
         self.tasks.join().await;
     }
 
+    /// Wait for consensus to complete, or shut down as soon as `shutdown` resolves.
+    pub async fn run_until(mut self, shutdown: impl Future<Output = ()>) {
+        let tasks = join_all(self.tasks.0.iter_mut().map(|(_, task)| task));
+        let shutdown = matches!(select(tasks, Box::pin(shutdown)).await, Either::Right(_));
+        if shutdown {
+            tracing::warn!("shutting down");
+            self.shut_down().await;
+            // Already shut down, so the drop handler need not do it again.
+            self.detach();
+        }
+    }
+
     /// Allow this node to continue participating in consensus even after it is dropped.
     pub fn detach(&mut self) {
         // Set `detached` so the drop handler doesn't call `shut_down`.
@@ -281,27 +403,78 @@ impl<N: network::Type, P: SequencerPersistence, Ver: StaticVersionType + 'static
     }
 }
 
-async fn handle_events<Ver: StaticVersionType>(
+async fn handle_events(
     mut events: impl Stream<Item = Event<SeqTypes>> + Unpin,
     persistence: Arc<RwLock<impl SequencerPersistence>>,
-    state_signer: Arc<StateSigner<Ver>>,
-    events_streamer: Option<Arc<RwLock<EventsStreamer<SeqTypes>>>>,
+    fanout: Arc<EventFanout>,
+    mut equivocation_detector: EquivocationDetector,
 ) {
     while let Some(event) = events.next().await {
         tracing::debug!(?event, "consensus event");
 
-        {
-            let mut p = persistence.write().await;
+        // Decides get their own span, so that the time spent storing a decided leaf shows up in
+        // exported traces.
+        let span = match &event.event {
+            EventType::Decide { leaf_chain, .. } => tracing::info_span!(
+                "decide",
+                view = ?event.view_number,
+                height = leaf_chain
+                    .first()
+                    .map(|info| info.leaf.get_block_header().height),
+            ),
+            _ => tracing::debug_span!("consensus event", view = ?event.view_number),
+        };
+        async {
             // Store latest consensus state.
-            p.handle_event(&event).await;
+            persistence.write().await.handle_event(&event).await;
+
+            // Keep evidence of double voting. Checking signatures is slow, so only take the lock
+            // once there is evidence to save.
+            let evidence = equivocation_detector.handle_event(&event);
+            if evidence.is_empty() {
+                return;
+            }
+            let mut p = persistence.write().await;
+            for evidence in evidence {
+                tracing::error!(
+                    view = evidence.view,
+                    signers = ?evidence.signers,
+                    "detected double voting"
+                );
+                if let Err(err) = p.append_equivocation(&evidence).await {
+                    tracing::error!(view = evidence.view, "failed to save evidence: {err:#}");
+                }
+            }
         }
-        // Generate state signature.
+        .instrument(span)
+        .await;
+
+        // Pass the event on to the other consumers, without waiting for them.
+        fanout.publish(event);
+    }
+}
+
+/// Generate state signatures for the light client.
+async fn sign_states<Ver: StaticVersionType>(
+    mut events: Subscription,
+    state_signer: Arc<StateSigner<Ver>>,
+) {
+    while let Some(event) = events.next().await {
         state_signer.handle_event(&event).await;
+    }
+}
 
-        // Send the event via the event streaming service
-        if let Some(events_streamer) = events_streamer.as_ref() {
-            events_streamer.write().await.handle_event(event).await;
-        }
+/// Send events via the event streaming service.
+async fn stream_events(
+    mut events: Subscription,
+    events_streamer: Arc<RwLock<EventsStreamer<SeqTypes>>>,
+) {
+    while let Some(event) = events.next().await {
+        events_streamer
+            .write()
+            .await
+            .handle_event(Arc::unwrap_or_clone(event))
+            .await;
     }
 }
 
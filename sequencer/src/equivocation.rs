@@ -0,0 +1,258 @@
+//! Detection of double voting by consensus participants.
+//!
+//! Every QC records which members of the stake table signed it. Honest nodes vote for at most one
+//! leaf per view, so two valid QCs from the same view which certify different leaves prove that
+//! every node which signed both of them voted twice. Since each QC needs signatures from a quorum,
+//! any two such QCs have signers in common, who can be identified from the QCs alone.
+//!
+//! The [`EquivocationDetector`] watches the QCs a node sees in proposals and decides for such
+//! conflicts. The evidence is persisted and served by the node API. The stake table contract has
+//! no slashing function yet, so it is up to a watcher to act on it; anyone can
+//! [verify](Equivocation::verify) the evidence against the stake table.
+
+use crate::{PubKey, SeqTypes};
+use anyhow::ensure;
+use derivative::Derivative;
+use hotshot::{
+    traits::election::static_committee::GeneralStaticCommittee,
+    types::{Event, EventType},
+};
+use hotshot_types::{
+    event::LeafInfo,
+    simple_certificate::QuorumCertificate,
+    traits::{election::Membership, node_implementation::ConsensusTime},
+    vote::Certificate,
+    PeerConfig,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, iter::once};
+
+/// The number of recent views whose QCs are kept to compare new QCs against.
+pub const VIEW_WINDOW: usize = 1000;
+
+/// Evidence that some stake table members voted for two different leaves in the same view.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Equivocation {
+    pub view: u64,
+    /// Two valid QCs from `view` certifying different leaves.
+    pub qcs: [QuorumCertificate<SeqTypes>; 2],
+    /// The stake table members who signed both QCs.
+    pub signers: Vec<PubKey>,
+}
+
+impl Equivocation {
+    /// Check this evidence against the stake table `known_nodes_with_stake`.
+    pub fn verify(&self, known_nodes_with_stake: &[PeerConfig<PubKey>]) -> anyhow::Result<()> {
+        let [first, second] = &self.qcs;
+        ensure!(
+            first.view_number.get_u64() == self.view && second.view_number.get_u64() == self.view,
+            "QCs are not both from view {}",
+            self.view
+        );
+        ensure!(
+            first.data.leaf_commit != second.data.leaf_commit,
+            "QCs certify the same leaf"
+        );
+
+        let membership = GeneralStaticCommittee::create_election(
+            known_nodes_with_stake.to_vec(),
+            GeneralStaticCommittee::<SeqTypes, PubKey>::default_election_config(
+                known_nodes_with_stake.len() as u64,
+                0,
+            ),
+            0,
+        );
+        for qc in &self.qcs {
+            ensure!(
+                qc.is_valid_cert(&membership),
+                "QC for leaf {} is not signed by the stake table",
+                qc.data.leaf_commit
+            );
+        }
+
+        let keys = known_nodes_with_stake
+            .iter()
+            .map(|peer| peer.stake_table_entry.stake_key)
+            .collect::<Vec<_>>();
+        ensure!(
+            common_signers(first, second, &keys) == self.signers,
+            "signers do not match the QCs"
+        );
+        Ok(())
+    }
+}
+
+/// Compares the QCs seen by this node to find equivocations.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct EquivocationDetector {
+    #[derivative(Debug = "ignore")]
+    membership: GeneralStaticCommittee<SeqTypes, PubKey>,
+    /// The consensus keys of the stake table, in the order of the signer bits in a QC.
+    stake_table: Vec<PubKey>,
+    /// The QCs seen in each recent view, one for each distinct leaf.
+    qcs: BTreeMap<u64, Vec<QuorumCertificate<SeqTypes>>>,
+}
+
+impl EquivocationDetector {
+    pub fn new(
+        membership: GeneralStaticCommittee<SeqTypes, PubKey>,
+        stake_table: Vec<PubKey>,
+    ) -> Self {
+        Self {
+            membership,
+            stake_table,
+            qcs: Default::default(),
+        }
+    }
+
+    /// Check the QCs carried by `event` against the QCs seen so far.
+    ///
+    /// Returns evidence of each new equivocation.
+    pub fn handle_event(&mut self, event: &Event<SeqTypes>) -> Vec<Equivocation> {
+        let qcs = match &event.event {
+            EventType::QuorumProposal { proposal, .. } => vec![proposal.data.justify_qc.clone()],
+            // The decide QC is for the first leaf in the chain, and each leaf carries the QC for
+            // its parent.
+            EventType::Decide { leaf_chain, qc, .. } => once((**qc).clone())
+                .chain(
+                    leaf_chain
+                        .iter()
+                        .map(|LeafInfo { leaf, .. }| leaf.get_justify_qc()),
+                )
+                .collect(),
+            _ => return vec![],
+        };
+        qcs.iter().filter_map(|qc| self.observe(qc)).collect()
+    }
+
+    fn observe(&mut self, qc: &QuorumCertificate<SeqTypes>) -> Option<Equivocation> {
+        if qc.is_genesis || self.is_known(qc) {
+            return None;
+        }
+        // Only QCs signed by the stake table count as evidence; anyone can make up an unsigned one.
+        if !qc.is_valid_cert(&self.membership) {
+            tracing::warn!(view = ?qc.view_number, leaf = %qc.data.leaf_commit, "invalid QC");
+            return None;
+        }
+        self.record(qc.clone())
+    }
+
+    fn is_known(&self, qc: &QuorumCertificate<SeqTypes>) -> bool {
+        self.qcs.get(&qc.view_number.get_u64()).is_some_and(|qcs| {
+            qcs.iter()
+                .any(|known| known.data.leaf_commit == qc.data.leaf_commit)
+        })
+    }
+
+    /// Remember a new, valid QC, returning evidence if it conflicts with one seen before.
+    fn record(&mut self, qc: QuorumCertificate<SeqTypes>) -> Option<Equivocation> {
+        let view = qc.view_number.get_u64();
+        if self.is_known(&qc) {
+            return None;
+        }
+        if self.qcs.len() == VIEW_WINDOW && !self.qcs.contains_key(&view) {
+            // Make room for the new view, unless it is older than everything in the window.
+            if self
+                .qcs
+                .first_key_value()
+                .is_some_and(|(oldest, _)| view < *oldest)
+            {
+                return None;
+            }
+            self.qcs.pop_first();
+        }
+
+        let seen = self.qcs.entry(view).or_default();
+        // Report the conflict with the first QC seen for this view. Any further QCs conflict with
+        // it just the same, and each is reported in turn.
+        let evidence = seen.first().map(|first| Equivocation {
+            view,
+            signers: common_signers(first, &qc, &self.stake_table),
+            qcs: [first.clone(), qc.clone()],
+        });
+        seen.push(qc);
+        evidence
+    }
+}
+
+/// The members of `stake_table` who signed both `first` and `second`.
+fn common_signers(
+    first: &QuorumCertificate<SeqTypes>,
+    second: &QuorumCertificate<SeqTypes>,
+    stake_table: &[PubKey],
+) -> Vec<PubKey> {
+    let (Some((_, first)), Some((_, second))) = (&first.signatures, &second.signatures) else {
+        return vec![];
+    };
+    first
+        .iter()
+        .zip(second.iter())
+        .zip(stake_table)
+        .filter(|((a, b), _)| **a && **b)
+        .map(|(_, key)| *key)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Leaf, NodeState, ViewNumber};
+    use committable::Committable;
+    use hotshot_types::{light_client::StateKeyPair, traits::signature_key::SignatureKey};
+
+    fn qc(view: u64, leaf: u64) -> QuorumCertificate<SeqTypes> {
+        let mut leaf_data = Leaf::genesis(&NodeState::mock());
+        leaf_data.get_block_header_mut().height = leaf;
+        let mut qc = QuorumCertificate::genesis(&NodeState::mock());
+        qc.view_number = ViewNumber::new(view);
+        qc.data.leaf_commit = leaf_data.commit();
+        qc.vote_commitment = qc.data.commit();
+        qc.is_genesis = false;
+        qc
+    }
+
+    #[test]
+    fn test_equivocation_detector() {
+        let keys = (0..4)
+            .map(|i| PubKey::generated_from_seed_indexed([0; 32], i).0)
+            .collect::<Vec<_>>();
+        let stake_table = keys
+            .iter()
+            .zip(0..)
+            .map(|(key, i)| PeerConfig::<PubKey> {
+                stake_table_entry: key.get_stake_table_entry(1),
+                state_ver_key: StateKeyPair::generate_from_seed_indexed([0; 32], i).ver_key(),
+            })
+            .collect::<Vec<_>>();
+        let membership = GeneralStaticCommittee::create_election(
+            stake_table.clone(),
+            GeneralStaticCommittee::<SeqTypes, PubKey>::default_election_config(4, 0),
+            0,
+        );
+        let mut detector = EquivocationDetector::new(membership, keys);
+
+        // QCs for different views, or repeats of the same QC, are not evidence.
+        assert_eq!(detector.record(qc(1, 1)), None);
+        assert_eq!(detector.record(qc(2, 2)), None);
+        assert_eq!(detector.record(qc(1, 1)), None);
+
+        // A second leaf in the same view is.
+        let evidence = detector.record(qc(1, 3)).unwrap();
+        assert_eq!(evidence.view, 1);
+        assert_eq!(evidence.qcs, [qc(1, 1), qc(1, 3)]);
+        // It is reported only once.
+        assert_eq!(detector.record(qc(1, 3)), None);
+
+        // These QCs are not signed, so the evidence does not hold up against the stake table.
+        evidence.verify(&stake_table).unwrap_err();
+
+        // Only the most recent views are remembered.
+        for view in 3..VIEW_WINDOW as u64 + 3 {
+            detector.record(qc(view, view));
+        }
+        assert_eq!(detector.qcs.len(), VIEW_WINDOW);
+        assert_eq!(detector.record(qc(1, 4)), None);
+        assert_eq!(detector.record(qc(2, 4)), None);
+    }
+}
@@ -0,0 +1,402 @@
+//! Distribution of consensus events to the tasks which consume them.
+//!
+//! The main event handler stores each event from HotShot and then publishes it to an
+//! [`EventFanout`], which gives every consumer (the state signer, the event streaming API, the query
+//! service and the API trackers) a bounded queue of its own. One stalled consumer therefore cannot
+//! hold up the others, nor make the node buffer events without bound: once a consumer's queue is
+//! full, further events are handled according to its [`SlowConsumerPolicy`]. They are either
+//! dropped, in which case the consumer resumes with the next event it has room for, or spilled to a
+//! temporary file and delivered in order once the consumer catches up.
+//!
+//! Each consumer reports, under its own metrics subgroup, the number of events waiting for it and
+//! the number of events it has had dropped or spilled.
+
+use crate::SeqTypes;
+use anyhow::Context;
+use async_std::channel::{self, Receiver, Sender, TryRecvError, TrySendError};
+use derivative::Derivative;
+use futures::stream::{self, Stream};
+use hotshot::types::Event;
+use hotshot_types::traits::metrics::{Counter, Gauge, Metrics};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    sync::{Arc, Mutex},
+};
+
+/// Number of events each consumer may have queued in memory.
+pub const EVENT_QUEUE_CAPACITY: usize = 256;
+
+/// What to do with events for a consumer whose queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Drop the events, and warn the consumer of how many it missed when it catches up.
+    ///
+    /// This suits consumers which only care about the latest state, or which can fetch what they
+    /// missed from elsewhere.
+    Drop,
+    /// Write the events to a temporary file, and deliver them in order once the consumer catches
+    /// up.
+    ///
+    /// This suits consumers which must see every event, at the cost of disk space while they lag.
+    Spill,
+}
+
+/// Publishes each consensus event to every subscribed consumer.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct EventFanout {
+    queues: Mutex<Vec<Publisher>>,
+    capacity: usize,
+    #[derivative(Debug = "ignore")]
+    metrics: Box<dyn Metrics>,
+}
+
+impl EventFanout {
+    pub fn new(metrics: &dyn Metrics) -> Self {
+        Self::with_capacity(EVENT_QUEUE_CAPACITY, metrics)
+    }
+
+    /// Give each consumer a queue of `capacity` events in memory.
+    pub fn with_capacity(capacity: usize, metrics: &dyn Metrics) -> Self {
+        Self {
+            queues: Default::default(),
+            capacity,
+            metrics: metrics.subgroup("events".into()),
+        }
+    }
+
+    /// Subscribe a consumer to all events published from now on.
+    ///
+    /// `name` identifies the consumer in logs and metrics, and must be unique.
+    pub fn subscribe(&self, name: impl Display, policy: SlowConsumerPolicy) -> Subscription {
+        let name = name.to_string();
+        let (sender, receiver) = channel::bounded(self.capacity);
+        let metrics = self.metrics.subgroup(name.clone());
+        let queue = Arc::new(Queue {
+            policy,
+            overflow: Default::default(),
+            metrics: QueueMetrics {
+                lag: metrics.create_gauge("lag".into(), None),
+                dropped: metrics.create_counter("dropped".into(), None),
+                spilled: metrics.create_counter("spilled".into(), None),
+            },
+            name,
+        });
+        self.queues.lock().unwrap().push(Publisher {
+            queue: queue.clone(),
+            sender,
+        });
+        Subscription { queue, receiver }
+    }
+
+    /// Send `event` to every consumer.
+    ///
+    /// This never waits for a consumer, so it never holds up consensus.
+    pub fn publish(&self, event: Event<SeqTypes>) {
+        let event = Arc::new(event);
+        self.queues.lock().unwrap().retain(|publisher| {
+            if publisher.sender.is_closed() {
+                tracing::info!(
+                    consumer = publisher.queue.name,
+                    "event consumer unsubscribed"
+                );
+                return false;
+            }
+            publisher.push(&event);
+            true
+        });
+    }
+}
+
+/// The events published to a single consumer.
+#[derive(Debug)]
+pub struct Subscription {
+    queue: Arc<Queue>,
+    receiver: Receiver<Arc<Event<SeqTypes>>>,
+}
+
+impl Subscription {
+    /// The next event, or [`None`] if the [`EventFanout`] has been dropped.
+    pub async fn next(&mut self) -> Option<Arc<Event<SeqTypes>>> {
+        // Events in the queue are older than any events in the spill file, since events are only
+        // spilled while the queue is full or there are older events already spilled.
+        let closed = match self.receiver.try_recv() {
+            Ok(event) => return Some(self.delivered(event)),
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Closed) => true,
+        };
+        if let Some(event) = self.queue.unspill() {
+            return Some(self.delivered(event));
+        }
+        if closed {
+            return None;
+        }
+        let event = self.receiver.recv().await.ok()?;
+        Some(self.delivered(event))
+    }
+
+    /// Stream events, for consumers which take ownership of each event.
+    pub fn into_stream(self) -> impl Stream<Item = Event<SeqTypes>> + Unpin {
+        Box::pin(stream::unfold(self, |mut events| async move {
+            let event = events.next().await?;
+            Some((Arc::unwrap_or_clone(event), events))
+        }))
+    }
+
+    fn delivered(&self, event: Arc<Event<SeqTypes>>) -> Arc<Event<SeqTypes>> {
+        let mut overflow = self.queue.overflow.lock().unwrap();
+        if overflow.dropped > 0 {
+            tracing::warn!(
+                consumer = self.queue.name,
+                missed = overflow.dropped,
+                "event consumer fell behind and missed events"
+            );
+            overflow.dropped = 0;
+        }
+        self.queue.update_lag(self.receiver.len(), &overflow);
+        event
+    }
+}
+
+/// The sending half of the queue of a consumer.
+#[derive(Debug)]
+struct Publisher {
+    queue: Arc<Queue>,
+    sender: Sender<Arc<Event<SeqTypes>>>,
+}
+
+impl Publisher {
+    fn push(&self, event: &Arc<Event<SeqTypes>>) {
+        let queue = &self.queue;
+        let mut overflow = queue.overflow.lock().unwrap();
+        if overflow.spilled() == 0 {
+            match self.sender.try_send(event.clone()) {
+                Ok(()) => {
+                    queue.update_lag(self.sender.len(), &overflow);
+                    return;
+                }
+                Err(TrySendError::Closed(_)) => return,
+                Err(TrySendError::Full(_)) => {}
+            }
+        }
+
+        let spilled = match queue.policy {
+            SlowConsumerPolicy::Drop => false,
+            SlowConsumerPolicy::Spill => match overflow.spill(event) {
+                Ok(()) => true,
+                Err(err) => {
+                    tracing::error!(consumer = queue.name, "failed to spill event: {err:#}");
+                    false
+                }
+            },
+        };
+        if spilled {
+            queue.metrics.spilled.add(1);
+        } else {
+            overflow.dropped += 1;
+            queue.metrics.dropped.add(1);
+        }
+        queue.update_lag(self.sender.len(), &overflow);
+    }
+}
+
+/// The state of the queue of a consumer shared between the publisher and the consumer.
+#[derive(Debug)]
+struct Queue {
+    name: String,
+    policy: SlowConsumerPolicy,
+    overflow: Mutex<Overflow>,
+    metrics: QueueMetrics,
+}
+
+impl Queue {
+    /// Take the oldest spilled event, if there is one.
+    fn unspill(&self) -> Option<Arc<Event<SeqTypes>>> {
+        let mut overflow = self.overflow.lock().unwrap();
+        let spill = overflow.spill.as_mut()?;
+        match spill.pop() {
+            Ok(event) => event.map(Arc::new),
+            Err(err) => {
+                // The file can no longer be trusted, so the events in it are lost.
+                tracing::error!(
+                    consumer = self.name,
+                    "failed to read spilled event: {err:#}"
+                );
+                overflow.dropped += spill.len as u64;
+                self.metrics.dropped.add(spill.len);
+                overflow.spill = None;
+                None
+            }
+        }
+    }
+
+    fn update_lag(&self, queued: usize, overflow: &Overflow) {
+        self.metrics.lag.set(queued + overflow.spilled());
+    }
+}
+
+#[derive(Debug)]
+struct QueueMetrics {
+    /// Events waiting for the consumer, in memory or spilled.
+    lag: Box<dyn Gauge>,
+    dropped: Box<dyn Counter>,
+    spilled: Box<dyn Counter>,
+}
+
+/// Events which did not fit in the queue of a consumer.
+#[derive(Debug, Default)]
+struct Overflow {
+    /// Number of events dropped since the consumer last received one.
+    dropped: u64,
+    /// Events waiting in a spill file, created the first time the consumer falls behind.
+    spill: Option<SpillFile>,
+}
+
+impl Overflow {
+    fn spilled(&self) -> usize {
+        self.spill.as_ref().map_or(0, |spill| spill.len)
+    }
+
+    fn spill(&mut self, event: &Event<SeqTypes>) -> anyhow::Result<()> {
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => self.spill.insert(SpillFile::new()?),
+        };
+        spill.push(event)
+    }
+}
+
+/// A temporary file of length-prefixed, serialized events, read in the order they were written.
+#[derive(Debug)]
+struct SpillFile {
+    file: File,
+    read_pos: u64,
+    write_pos: u64,
+    /// Number of events written but not yet read.
+    len: usize,
+}
+
+impl SpillFile {
+    fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            file: tempfile::tempfile().context("creating spill file")?,
+            read_pos: 0,
+            write_pos: 0,
+            len: 0,
+        })
+    }
+
+    fn push(&mut self, event: &Event<SeqTypes>) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(event).context("serializing event")?;
+        self.file.seek(SeekFrom::Start(self.write_pos))?;
+        self.file.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        self.file.write_all(&bytes)?;
+        self.write_pos += 8 + bytes.len() as u64;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> anyhow::Result<Option<Event<SeqTypes>>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.file.seek(SeekFrom::Start(self.read_pos))?;
+        let mut len = [0; 8];
+        self.file.read_exact(&mut len)?;
+        let mut bytes = vec![0; u64::from_le_bytes(len) as usize];
+        self.file.read_exact(&mut bytes)?;
+        self.read_pos += 8 + bytes.len() as u64;
+        self.len -= 1;
+
+        // Once the consumer has caught up, start the file over rather than letting it grow.
+        if self.len == 0 {
+            self.file.set_len(0)?;
+            self.read_pos = 0;
+            self.write_pos = 0;
+        }
+        Ok(Some(
+            bincode::deserialize(&bytes).context("deserializing event")?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ViewNumber;
+    use hotshot::types::EventType;
+    use hotshot_types::traits::{metrics::NoMetrics, node_implementation::ConsensusTime};
+
+    fn event(view: u64) -> Event<SeqTypes> {
+        Event {
+            view_number: ViewNumber::new(view),
+            event: EventType::ViewFinished {
+                view_number: ViewNumber::new(view),
+            },
+        }
+    }
+
+    async fn views(events: &mut Subscription, n: usize) -> Vec<u64> {
+        let mut views = vec![];
+        for _ in 0..n {
+            views.push(events.next().await.unwrap().view_number.get_u64());
+        }
+        views
+    }
+
+    #[async_std::test]
+    async fn test_slow_consumer_drop() {
+        let fanout = EventFanout::with_capacity(2, &NoMetrics);
+        let mut slow = fanout.subscribe("slow", SlowConsumerPolicy::Drop);
+        let mut fast = fanout.subscribe("fast", SlowConsumerPolicy::Drop);
+
+        for view in 0..2 {
+            fanout.publish(event(view));
+        }
+        assert_eq!(views(&mut fast, 2).await, [0, 1]);
+
+        // The slow consumer misses events which do not fit in its queue, without holding up the
+        // fast consumer.
+        for view in 2..4 {
+            fanout.publish(event(view));
+        }
+        assert_eq!(views(&mut fast, 2).await, [2, 3]);
+        assert_eq!(views(&mut slow, 2).await, [0, 1]);
+
+        // Once it has room, it resumes with the latest events.
+        fanout.publish(event(4));
+        assert_eq!(views(&mut slow, 1).await, [4]);
+        assert_eq!(views(&mut fast, 1).await, [4]);
+    }
+
+    #[async_std::test]
+    async fn test_slow_consumer_spill() {
+        let fanout = EventFanout::with_capacity(2, &NoMetrics);
+        let mut events = fanout.subscribe("slow", SlowConsumerPolicy::Spill);
+
+        // Events which do not fit in the queue are delivered in order after those which do.
+        for view in 0..5 {
+            fanout.publish(event(view));
+        }
+        assert_eq!(views(&mut events, 3).await, [0, 1, 2]);
+
+        // Events published while older events are spilled are spilled behind them, even if there
+        // is room in the queue.
+        fanout.publish(event(5));
+        assert_eq!(views(&mut events, 3).await, [3, 4, 5]);
+
+        // Once the consumer has caught up, events go through the queue again.
+        fanout.publish(event(6));
+        assert_eq!(views(&mut events, 1).await, [6]);
+
+        // Consumers which go away are unsubscribed, and the stream ends once the fanout is dropped.
+        drop(fanout.subscribe("gone", SlowConsumerPolicy::Spill));
+        fanout.publish(event(7));
+        assert_eq!(fanout.queues.lock().unwrap().len(), 1);
+        drop(fanout);
+        assert_eq!(views(&mut events, 1).await, [7]);
+        assert!(events.next().await.is_none());
+    }
+}
@@ -0,0 +1,418 @@
+//! Mirroring namespace payloads to an external DA layer.
+//!
+//! Espresso DA is all consensus needs, but some rollups want their data to be available from a
+//! second DA layer as well. When the `external-da` module is enabled, each namespace payload in a
+//! decided block is posted to EigenDA (through an EigenDA proxy) or Celestia (through the RPC API
+//! of a Celestia node), and the commitment returned by that layer is recorded.
+//!
+//! The external commitments are not part of the Espresso header. Every node must derive the same
+//! header, but posting to another layer is slow and its result differs from node to node, so it
+//! cannot happen during consensus. Instead, the commitments are appended to a local file of
+//! [`ExternalDaRecord`]s, one JSON object per line, which rollups can use to locate their data on
+//! the external layer.
+
+use crate::{block::entry::TxTableEntryWord, NamespaceId, Payload, SeqTypes};
+use anyhow::{anyhow, bail, ensure, Context};
+use async_std::task::sleep;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Parser, ValueEnum};
+use ethers::types::Bytes;
+use futures::{Future, Stream, StreamExt};
+use hotshot::types::{Event, EventType};
+use hotshot_types::{event::LeafInfo, traits::block_contents::BlockHeader};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    time::Duration,
+};
+use url::Url;
+
+/// Attempts to post a namespace payload before giving up on it.
+const POST_ATTEMPTS: usize = 5;
+
+/// Delay before retrying a failed post, doubled after each attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// A DA layer which namespace payloads can be mirrored to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExternalDaLayer {
+    #[value(name = "eigenda")]
+    EigenDa,
+    Celestia,
+}
+
+/// Options for the external DA module.
+#[derive(Parser, Clone, Debug)]
+pub struct Options {
+    /// The DA layer to mirror namespace payloads to.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_EXTERNAL_DA_LAYER", value_enum)]
+    pub layer: ExternalDaLayer,
+
+    /// URL of an EigenDA proxy, or of the RPC API of a Celestia node.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_EXTERNAL_DA_URL")]
+    pub url: Url,
+
+    /// Auth token for the RPC API of the Celestia node.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_EXTERNAL_DA_AUTH_TOKEN")]
+    pub auth_token: Option<String>,
+
+    /// Namespaces to mirror.
+    ///
+    /// If none are given, every namespace is mirrored.
+    #[clap(
+        long = "namespace",
+        env = "ESPRESSO_SEQUENCER_EXTERNAL_DA_NAMESPACES",
+        value_delimiter = ','
+    )]
+    pub namespaces: Vec<u64>,
+
+    /// File to append the external commitments to, one JSON object per line.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_EXTERNAL_DA_RECORD_PATH",
+        default_value = "external-da.jsonl"
+    )]
+    pub record_path: PathBuf,
+}
+
+impl Options {
+    /// The task mirroring the namespace payloads decided in `events`.
+    pub fn mirror(
+        self,
+        events: impl Stream<Item = Event<SeqTypes>> + Unpin + Send + 'static,
+    ) -> anyhow::Result<impl Future<Output = ()>> {
+        let da: Box<dyn ExternalDa> = match self.layer {
+            ExternalDaLayer::EigenDa => Box::new(EigenDaProxy::new(self.url)),
+            ExternalDaLayer::Celestia => Box::new(Celestia::new(self.url, self.auth_token)),
+        };
+        let records = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.record_path)
+            .with_context(|| {
+                format!(
+                    "error opening external DA records {}",
+                    self.record_path.display()
+                )
+            })?;
+        let namespaces = self.namespaces.into_iter().map(NamespaceId::from).collect();
+        Ok(mirror_payloads(da, namespaces, records, events))
+    }
+}
+
+/// A commitment to a namespace payload on an external DA layer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalCommitment {
+    pub layer: ExternalDaLayer,
+    /// The commitment returned by the layer.
+    ///
+    /// For EigenDA, this is the certificate returned by the proxy, which the proxy accepts to
+    /// retrieve the payload. For Celestia, this is the namespace of the blob, which together with
+    /// `height` identifies the blob.
+    pub commitment: Bytes,
+    /// The block of the external layer which includes the payload, if the layer reports it.
+    pub height: Option<u64>,
+}
+
+/// The external commitment to the payload of `namespace` in the Espresso block at `height`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalDaRecord {
+    pub height: u64,
+    pub namespace: NamespaceId,
+    pub commitment: ExternalCommitment,
+}
+
+/// A DA layer which namespace payloads can be posted to.
+#[async_trait]
+pub trait ExternalDa: Send + Sync {
+    /// Post the payload of the namespace `ns_id`, returning the commitment of the layer to it.
+    async fn post(&self, ns_id: NamespaceId, payload: &[u8]) -> anyhow::Result<ExternalCommitment>;
+}
+
+#[async_trait]
+impl<T: ExternalDa + ?Sized> ExternalDa for Box<T> {
+    async fn post(&self, ns_id: NamespaceId, payload: &[u8]) -> anyhow::Result<ExternalCommitment> {
+        (**self).post(ns_id, payload).await
+    }
+}
+
+/// EigenDA, through the REST API of an EigenDA proxy.
+#[derive(Clone, Debug)]
+pub struct EigenDaProxy {
+    url: Url,
+}
+
+impl EigenDaProxy {
+    pub fn new(url: Url) -> Self {
+        Self { url }
+    }
+}
+
+#[async_trait]
+impl ExternalDa for EigenDaProxy {
+    async fn post(&self, _: NamespaceId, payload: &[u8]) -> anyhow::Result<ExternalCommitment> {
+        let mut url = self.url.join("put/")?;
+        url.query_pairs_mut()
+            .append_pair("commitment_mode", "simple");
+        let mut res = surf::post(&url)
+            .body_bytes(payload)
+            .await
+            .map_err(|err| anyhow!("error contacting EigenDA proxy: {err}"))?;
+        ensure!(
+            res.status().is_success(),
+            "EigenDA proxy responded with {}",
+            res.status()
+        );
+        let commitment = res
+            .body_bytes()
+            .await
+            .map_err(|err| anyhow!("malformed response from EigenDA proxy: {err}"))?;
+        Ok(ExternalCommitment {
+            layer: ExternalDaLayer::EigenDa,
+            commitment: commitment.into(),
+            height: None,
+        })
+    }
+}
+
+/// Celestia, through the JSON-RPC API of a Celestia node.
+#[derive(Clone, Debug)]
+pub struct Celestia {
+    url: Url,
+    auth_token: Option<String>,
+}
+
+impl Celestia {
+    pub fn new(url: Url, auth_token: Option<String>) -> Self {
+        Self { url, auth_token }
+    }
+}
+
+/// A response from the Celestia node RPC API.
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: Option<u64>,
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    message: String,
+}
+
+#[async_trait]
+impl ExternalDa for Celestia {
+    async fn post(&self, ns_id: NamespaceId, payload: &[u8]) -> anyhow::Result<ExternalCommitment> {
+        let namespace = celestia_namespace(ns_id);
+        // A gas price of -1 lets the node choose the gas price.
+        let body = json!({
+            "id": 1,
+            "jsonrpc": "2.0",
+            "method": "blob.Submit",
+            "params": [
+                [{
+                    "namespace": STANDARD.encode(namespace),
+                    "data": STANDARD.encode(payload),
+                    "share_version": 0,
+                }],
+                -1.0,
+            ],
+        });
+        let mut req = surf::post(&self.url)
+            .body_json(&body)
+            .map_err(|err| anyhow!("error encoding Celestia request: {err}"))?;
+        if let Some(token) = &self.auth_token {
+            req = req.header("Authorization", format!("Bearer {token}"));
+        }
+        let mut res = req
+            .await
+            .map_err(|err| anyhow!("error contacting Celestia node: {err}"))?;
+        ensure!(
+            res.status().is_success(),
+            "Celestia node responded with {}",
+            res.status()
+        );
+        let res: RpcResponse = res
+            .body_json()
+            .await
+            .map_err(|err| anyhow!("malformed response from Celestia node: {err}"))?;
+        if let Some(err) = res.error {
+            bail!("Celestia node rejected blob: {}", err.message);
+        }
+        let height = res
+            .result
+            .context("Celestia node did not return an inclusion height")?;
+        Ok(ExternalCommitment {
+            layer: ExternalDaLayer::Celestia,
+            commitment: namespace.to_vec().into(),
+            height: Some(height),
+        })
+    }
+}
+
+/// The Celestia namespace mirroring the Espresso namespace `ns_id`.
+///
+/// This is a version 0 namespace, whose 10 byte ID holds `ns_id` in big-endian order.
+fn celestia_namespace(ns_id: NamespaceId) -> [u8; 29] {
+    let mut namespace = [0; 29];
+    namespace[21..].copy_from_slice(&u64::from(ns_id).to_be_bytes());
+    namespace
+}
+
+/// Post the namespace payloads decided in `events` to `da`, appending the commitments to `records`.
+///
+/// Only the namespaces in `namespaces` are mirrored, or every namespace if it is empty. A payload
+/// which cannot be posted after a few attempts is skipped, so that one bad payload cannot hold up
+/// the rest.
+pub async fn mirror_payloads(
+    da: impl ExternalDa,
+    namespaces: Vec<NamespaceId>,
+    mut records: File,
+    mut events: impl Stream<Item = Event<SeqTypes>> + Unpin,
+) {
+    while let Some(event) = events.next().await {
+        let EventType::Decide { leaf_chain, .. } = event.event else {
+            continue;
+        };
+        // The leaf chain is in reverse chronological order.
+        for LeafInfo { leaf, .. } in leaf_chain.iter().rev() {
+            let Some(payload) = leaf.get_block_payload() else {
+                tracing::warn!(
+                    "not mirroring block {}, payload unavailable",
+                    leaf.get_block_header().block_number()
+                );
+                continue;
+            };
+            let height = leaf.get_block_header().block_number();
+            for record in mirror_block(&da, &namespaces, height, &payload).await {
+                if let Err(err) = write_record(&mut records, &record) {
+                    tracing::error!("error recording external DA commitment {record:?}: {err:#}");
+                }
+            }
+        }
+    }
+}
+
+fn write_record(records: &mut File, record: &ExternalDaRecord) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut *records, record)?;
+    writeln!(records)?;
+    Ok(())
+}
+
+/// Post the namespace payloads of the block at `height` to `da`.
+async fn mirror_block(
+    da: &impl ExternalDa,
+    namespaces: &[NamespaceId],
+    height: u64,
+    payload: &Payload<TxTableEntryWord>,
+) -> Vec<ExternalDaRecord> {
+    let mut records = vec![];
+    for (ns_id, ns_payload) in payload.namespace_payloads() {
+        if !namespaces.is_empty() && !namespaces.contains(&ns_id) {
+            continue;
+        }
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=POST_ATTEMPTS {
+            match da.post(ns_id, ns_payload).await {
+                Ok(commitment) => {
+                    tracing::info!("mirrored namespace {ns_id} of block {height}: {commitment:?}");
+                    records.push(ExternalDaRecord {
+                        height,
+                        namespace: ns_id,
+                        commitment,
+                    });
+                    break;
+                }
+                Err(err) if attempt < POST_ATTEMPTS => {
+                    tracing::warn!(
+                        "error mirroring namespace {ns_id} of block {height} (attempt \
+                         {attempt}/{POST_ATTEMPTS}), retrying in {delay:?}: {err:#}"
+                    );
+                    sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(err) => tracing::error!(
+                    "failed to mirror namespace {ns_id} of block {height}, skipping: {err:#}"
+                ),
+            }
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Transaction;
+    use async_std::sync::Mutex;
+
+    /// A DA layer which records what it is sent, failing the first post of each payload.
+    #[derive(Default)]
+    struct MockDa {
+        posted: Mutex<Vec<(NamespaceId, Vec<u8>)>>,
+        failed: Mutex<bool>,
+    }
+
+    #[async_trait]
+    impl ExternalDa for MockDa {
+        async fn post(
+            &self,
+            ns_id: NamespaceId,
+            payload: &[u8],
+        ) -> anyhow::Result<ExternalCommitment> {
+            let mut failed = self.failed.lock().await;
+            if !*failed {
+                *failed = true;
+                bail!("transient failure");
+            }
+            *failed = false;
+            let mut posted = self.posted.lock().await;
+            posted.push((ns_id, payload.to_vec()));
+            Ok(ExternalCommitment {
+                layer: ExternalDaLayer::EigenDa,
+                commitment: vec![posted.len() as u8].into(),
+                height: None,
+            })
+        }
+    }
+
+    #[async_std::test]
+    async fn test_mirror_block() {
+        let txs = [
+            Transaction::new(1.into(), vec![1, 2, 3]),
+            Transaction::new(2.into(), vec![4, 5]),
+            Transaction::new(3.into(), vec![6]),
+        ];
+        let payload = Payload::from_txs(txs.clone()).unwrap();
+
+        // Only the selected namespaces are mirrored, each after retrying once.
+        let da = MockDa::default();
+        let records = mirror_block(&da, &[1.into(), 3.into()], 7, &payload).await;
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| (record.height, record.namespace))
+                .collect::<Vec<_>>(),
+            [(7, 1.into()), (7, 3.into())]
+        );
+        let posted = da.posted.lock().await;
+        assert_eq!(posted.len(), 2);
+        for ((ns_id, ns_payload), tx) in posted.iter().zip([&txs[0], &txs[2]]) {
+            assert_eq!(*ns_id, tx.namespace());
+            assert!(ns_payload.ends_with(tx.payload()));
+        }
+    }
+
+    #[test]
+    fn test_celestia_namespace() {
+        let namespace = celestia_namespace(0x0102.into());
+        assert_eq!(namespace.len(), 29);
+        assert!(namespace[..27].iter().all(|b| *b == 0));
+        assert_eq!(namespace[27..], [1, 2]);
+    }
+}
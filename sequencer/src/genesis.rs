@@ -0,0 +1,285 @@
+//! Genesis files, describing the initial configuration of a chain.
+//!
+//! A genesis file lets operators start a new chain with their own chain config and pre-funded fee
+//! accounts, without changing any code. The commitment of the whole file is included in the chain
+//! config, and so in every header starting with the first, so nodes started from different genesis
+//! files cannot build the same chain.
+
+use crate::{state::FeeAmount, ChainConfig, ValidatedState};
+use anyhow::{bail, ensure, Context};
+use committable::{Commitment, Committable, RawCommitmentBuilder};
+use ethers::types::{Address, U256};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The initial configuration of a chain.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Genesis {
+    pub chain_config: GenesisChainConfig,
+    /// Fee accounts with a balance at genesis.
+    #[serde(default, rename = "account")]
+    pub accounts: Vec<GenesisAccount>,
+    /// Protocol versions the chain will upgrade to, and when.
+    ///
+    /// Nothing acts on this schedule yet, so genesis files which schedule upgrades are rejected.
+    #[serde(default, rename = "upgrade")]
+    pub upgrades: Vec<Upgrade>,
+}
+
+/// The chain config parameters set at genesis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisChainConfig {
+    pub chain_id: u64,
+    /// Maximum size in bytes of a block.
+    pub max_block_size: u64,
+    /// Minimum fee in WEI per byte of payload.
+    #[serde(with = "amount")]
+    pub base_fee: U256,
+}
+
+/// A fee account funded at genesis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    pub address: Address,
+    /// The balance in WEI.
+    #[serde(with = "amount")]
+    pub balance: U256,
+}
+
+/// A scheduled protocol upgrade.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Upgrade {
+    /// The protocol version to upgrade to, as `major.minor`.
+    pub version: String,
+    /// The first block height using the new version.
+    pub height: u64,
+}
+
+impl Genesis {
+    /// Load a genesis file, in JSON or TOML depending on the extension of `path`.
+    ///
+    /// Balances and the base fee may be given as integers or as strings, in decimal or in hex with
+    /// a `0x` prefix, so that they can exceed the range of integers in the file format.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading genesis file {}", path.display()))?;
+        let genesis: Self = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&contents)?,
+            Some("toml") => toml::from_str(&contents)?,
+            _ => bail!(
+                "cannot tell the format of {} from its extension, expected .json or .toml",
+                path.display()
+            ),
+        };
+        genesis
+            .validate()
+            .with_context(|| format!("invalid genesis file {}", path.display()))?;
+        Ok(genesis)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.chain_config.max_block_size > 0,
+            "max block size must be positive"
+        );
+        for (i, account) in self.accounts.iter().enumerate() {
+            ensure!(
+                !self.accounts[..i]
+                    .iter()
+                    .any(|other| other.address == account.address),
+                "account {:#x} is funded more than once",
+                account.address
+            );
+        }
+        ensure!(
+            self.upgrades.is_empty(),
+            "scheduling protocol upgrades in a genesis file is not supported yet"
+        );
+        Ok(())
+    }
+
+    /// The chain config for this genesis, committing to the genesis itself.
+    pub fn chain_config(&self) -> ChainConfig {
+        let GenesisChainConfig {
+            chain_id,
+            max_block_size,
+            base_fee,
+        } = self.chain_config;
+        ChainConfig::new(U256::from(chain_id), max_block_size, base_fee).with_genesis(self.commit())
+    }
+
+    /// Fund the genesis accounts in `state`.
+    pub fn fund_accounts(&self, state: &mut ValidatedState) {
+        for account in &self.accounts {
+            tracing::info!(
+                address = ?account.address,
+                balance = %account.balance,
+                "funding genesis account"
+            );
+            state.prefund_account(account.address.into(), FeeAmount::from(account.balance));
+        }
+    }
+}
+
+impl Committable for Genesis {
+    fn tag() -> String {
+        "GENESIS".to_string()
+    }
+
+    fn commit(&self) -> Commitment<Self> {
+        let config = &self.chain_config;
+        let mut comm = RawCommitmentBuilder::new(&Self::tag())
+            .u64_field("chain_id", config.chain_id)
+            .u64_field("max_block_size", config.max_block_size)
+            .fixed_size_field(
+                "base_fee",
+                &FeeAmount::from(config.base_fee).to_fixed_bytes(),
+            )
+            .u64_field("num_accounts", self.accounts.len() as u64);
+        for account in &self.accounts {
+            comm = comm
+                .fixed_size_field("address", account.address.as_fixed_bytes())
+                .fixed_size_field(
+                    "balance",
+                    &FeeAmount::from(account.balance).to_fixed_bytes(),
+                );
+        }
+        comm = comm.u64_field("num_upgrades", self.upgrades.len() as u64);
+        for upgrade in &self.upgrades {
+            comm = comm
+                .var_size_field("version", upgrade.version.as_bytes())
+                .u64_field("height", upgrade.height);
+        }
+        comm.finalize()
+    }
+}
+
+/// (De)serialize a `U256` as a decimal string, accepting integers and hex strings as well.
+mod amount {
+    use ethers::types::U256;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(amount: &U256, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&amount.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<U256, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Amount {
+            Int(u64),
+            Str(String),
+        }
+
+        match Amount::deserialize(d)? {
+            Amount::Int(amount) => Ok(amount.into()),
+            Amount::Str(s) => match s.strip_prefix("0x") {
+                Some(hex) => U256::from_str_radix(hex, 16).map_err(D::Error::custom),
+                None => U256::from_dec_str(&s).map_err(D::Error::custom),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::FeeAccount;
+    use jf_primitives::merkle_tree::MerkleTreeScheme;
+
+    fn genesis() -> Genesis {
+        Genesis {
+            chain_config: GenesisChainConfig {
+                chain_id: 999,
+                max_block_size: 30000,
+                base_fee: 1.into(),
+            },
+            accounts: vec![
+                GenesisAccount {
+                    address: Address::repeat_byte(1),
+                    balance: U256::exp10(18),
+                },
+                GenesisAccount {
+                    address: Address::repeat_byte(2),
+                    balance: 100.into(),
+                },
+            ],
+            upgrades: vec![],
+        }
+    }
+
+    #[test]
+    fn test_genesis_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let toml = dir.path().join("genesis.toml");
+        std::fs::write(
+            &toml,
+            r#"
+            [chain_config]
+            chain_id = 999
+            max_block_size = 30000
+            base_fee = 1
+
+            [[account]]
+            address = "0x0101010101010101010101010101010101010101"
+            balance = "1000000000000000000"
+
+            [[account]]
+            address = "0x0202020202020202020202020202020202020202"
+            balance = "0x64"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(Genesis::from_file(&toml).unwrap(), genesis());
+
+        let json = dir.path().join("genesis.json");
+        std::fs::write(&json, serde_json::to_string(&genesis()).unwrap()).unwrap();
+        assert_eq!(Genesis::from_file(&json).unwrap(), genesis());
+
+        let other = dir.path().join("genesis.yaml");
+        std::fs::write(&other, "").unwrap();
+        Genesis::from_file(&other).unwrap_err();
+    }
+
+    #[test]
+    fn test_genesis_validation() {
+        genesis().validate().unwrap();
+
+        let mut duplicate = genesis();
+        duplicate.accounts[1].address = duplicate.accounts[0].address;
+        duplicate.validate().unwrap_err();
+
+        let mut upgrade = genesis();
+        upgrade.upgrades.push(Upgrade {
+            version: "0.2".into(),
+            height: 1000,
+        });
+        upgrade.validate().unwrap_err();
+    }
+
+    #[test]
+    fn test_genesis_chain_config() {
+        let chain_config = genesis().chain_config();
+        assert_eq!(chain_config.max_block_size(), 30000);
+        assert_eq!(chain_config.base_fee(), 1.into());
+        assert_eq!(chain_config.target_block_size(), None);
+        assert_eq!(chain_config.genesis(), Some(genesis().commit()));
+
+        // Any change to the genesis changes the chain config.
+        let mut other = genesis();
+        other.accounts[1].balance = 101.into();
+        assert_ne!(other.chain_config().commit(), chain_config.commit());
+
+        let mut state = ValidatedState::default();
+        genesis().fund_accounts(&mut state);
+        let (balance, _) = state
+            .fee_merkle_tree
+            .lookup(FeeAccount::from(Address::repeat_byte(2)))
+            .expect_ok()
+            .unwrap();
+        assert_eq!(*balance, 100.into());
+        assert_eq!(state.fee_merkle_tree.num_leaves(), 2);
+    }
+}
@@ -2,7 +2,7 @@ use crate::{
     block::{entry::TxTableEntryWord, tables::NameSpaceTable, NsTable},
     chain_config::ResolvableChainConfig,
     l1_client::L1Snapshot,
-    state::{BlockMerkleCommitment, FeeAccount, FeeInfo, FeeMerkleCommitment},
+    state::{BlockMerkleCommitment, FeeAccount, FeeAmount, FeeInfo, FeeMerkleCommitment},
     ChainConfig, L1BlockInfo, Leaf, NodeState, SeqTypes, ValidatedState,
 };
 use anyhow::Context;
@@ -83,6 +83,13 @@ pub struct Header {
     /// Account (etheruem address) of builder
     pub builder_signature: Option<types::Signature>,
     pub fee_info: FeeInfo,
+    /// Fee in WEI per byte of payload for this block, if the chain adjusts it to congestion.
+    ///
+    /// This is determined by the base fee and size of the parent block (see
+    /// [`ChainConfig::next_base_fee`]), and the builder fee must cover it for every byte of the
+    /// payload.
+    #[serde(default)]
+    pub base_fee: Option<FeeAmount>,
 }
 
 impl Committable for Header {
@@ -96,7 +103,7 @@ impl Committable for Header {
             .serialize_with_mode(&mut fmt_bytes, ark_serialize::Compress::Yes)
             .unwrap();
 
-        RawCommitmentBuilder::new(&Self::tag())
+        let comm = RawCommitmentBuilder::new(&Self::tag())
             .field("chain_config", self.chain_config.commit())
             .u64_field("height", self.height)
             .u64_field("timestamp", self.timestamp)
@@ -109,8 +116,13 @@ impl Committable for Header {
             .field("ns_table", self.ns_table.commit())
             .var_size_field("block_merkle_tree_root", &bmt_bytes)
             .var_size_field("fee_merkle_tree_root", &fmt_bytes)
-            .field("fee_info", self.fee_info.commit())
-            .finalize()
+            .field("fee_info", self.fee_info.commit());
+        // Only headers with a dynamic base fee commit to it, so that other headers are unchanged.
+        let comm = match self.base_fee {
+            Some(base_fee) => comm.fixed_size_field("base_fee", &base_fee.to_fixed_bytes()),
+            None => comm,
+        };
+        comm.finalize()
     }
 
     fn tag() -> String {
@@ -210,6 +222,10 @@ impl Header {
             .context(format!("invalid builder fee {builder_fee:?}"))?;
 
         let fee_merkle_tree_root = state.fee_merkle_tree.commitment();
+        let base_fee = chain_config.next_base_fee(
+            parent_header.base_fee,
+            parent_header.ns_table.payload_byte_len() as u64,
+        );
         Ok(Self {
             chain_config: chain_config.commit().into(),
             height,
@@ -223,6 +239,7 @@ impl Header {
             block_merkle_tree_root,
             fee_info: builder_fee,
             builder_signature: Some(builder_signature),
+            base_fee,
         })
     }
 
@@ -341,7 +358,9 @@ impl BlockHeader<SeqTypes> for Header {
             builder_fee.fee_signature,
             OffsetDateTime::now_utc().unix_timestamp() as u64,
             validated_state,
-            instance_state.chain_config,
+            instance_state
+                .chain_config_after(parent_leaf.get_block_header())
+                .await,
         )
         // TODO we should be able to return an error from `Header::new`
         .unwrap_or_else(|err| panic!("invalid proposal: {err:#}"))
@@ -375,6 +394,7 @@ impl BlockHeader<SeqTypes> for Header {
             fee_merkle_tree_root,
             fee_info: FeeInfo::genesis(),
             builder_signature: None,
+            base_fee: instance_state.chain_config.genesis_base_fee(),
         }
     }
 
@@ -738,6 +758,17 @@ mod test_headers {
 
         assert!(format!("{}", result.root_cause()).starts_with("Invalid Chain Config:"));
 
+        // With a dynamic base fee, the proposal must carry the base fee determined by its parent.
+        let dynamic = genesis
+            .instance_state
+            .chain_config
+            .with_target_block_size(1000);
+        let mut dynamic_proposal = proposal.clone();
+        dynamic_proposal.chain_config = dynamic.into();
+        let result =
+            validate_proposal(&state, dynamic, &parent_leaf, &dynamic_proposal).unwrap_err();
+        assert!(format!("{}", result.root_cause()).starts_with("Invalid Base Fee:"));
+
         // Advance `proposal.height` to trigger validation error.
 
         let validated_state = apply_proposal(&validated_state, &mut delta, &parent_leaf, vec![]);
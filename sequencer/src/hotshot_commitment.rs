@@ -251,7 +251,10 @@ async fn sync_with_l1(
     // example, if there are multiple commitment tasks racing.
     contract_send::<_, _, HotShotErrors>(&txn)
         .await
-        .map_err(|e| SyncError::TransactionFailed { err: e, num_leaves })?;
+        .map_err(|e| SyncError::TransactionFailed {
+            err: e.into(),
+            num_leaves,
+        })?;
 
     Ok(())
 }
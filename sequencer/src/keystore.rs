@@ -0,0 +1,251 @@
+//! Encrypted storage for the private keys of a sequencer node.
+//!
+//! A keystore is a directory holding the staking key and the state key of a node, each in its own
+//! file in the Web3 secret storage format: encrypted with AES-128-CTR under a key derived from a
+//! password with scrypt. This keeps private keys out of environment variables and plaintext files.
+//!
+//! The state key can be rotated while the node is running. [`Keystore::rotate_state_key`] replaces
+//! the key file atomically, and a node watching its keystore with [`Keystore::watch_state_key`]
+//! signs light client states with the new key from then on. Note that signatures under the new key
+//! only count once it has replaced the old one in the stake table. The staking key identifies the
+//! node in consensus, so it cannot be changed without a restart.
+
+use crate::state_signature::StateSigner;
+use anyhow::{anyhow, ensure, Context};
+use async_std::{sync::Arc, task::sleep};
+use hotshot_types::{
+    light_client::{StateKeyPair, StateSignKey},
+    signature_key::BLSPrivKey,
+};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use vbs::version::StaticVersionType;
+
+/// The file in a keystore holding the staking key.
+pub const STAKING_KEY_FILE: &str = "staking.json";
+
+/// The file in a keystore holding the state key.
+pub const STATE_KEY_FILE: &str = "state.json";
+
+/// How often a running node checks its keystore for a new state key.
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A directory of encrypted private keys.
+#[derive(Clone, Debug)]
+pub struct Keystore {
+    dir: PathBuf,
+}
+
+impl Keystore {
+    /// Open an existing keystore.
+    pub fn open(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        for file in [STAKING_KEY_FILE, STATE_KEY_FILE] {
+            ensure!(
+                dir.join(file).is_file(),
+                "keystore {} is missing {file}",
+                dir.display()
+            );
+        }
+        Ok(Self { dir })
+    }
+
+    /// Create a keystore in `dir` holding `staking_key` and `state_key`, encrypted with `password`.
+    ///
+    /// `dir` is created if it does not exist. It is an error if it already holds any keys.
+    pub fn create(
+        dir: impl Into<PathBuf>,
+        password: &str,
+        staking_key: &BLSPrivKey,
+        state_key: &StateSignKey,
+    ) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        for file in [STAKING_KEY_FILE, STATE_KEY_FILE] {
+            ensure!(
+                !dir.join(file).exists(),
+                "keystore {} already has {file}",
+                dir.display()
+            );
+        }
+        let keystore = Self { dir };
+        keystore.write(STAKING_KEY_FILE, password, staking_key)?;
+        keystore.write(STATE_KEY_FILE, password, state_key)?;
+        Ok(keystore)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Decrypt the staking key.
+    pub fn staking_key(&self, password: &str) -> anyhow::Result<BLSPrivKey> {
+        self.read(STAKING_KEY_FILE, password)
+    }
+
+    /// Decrypt the state key.
+    pub fn state_key(&self, password: &str) -> anyhow::Result<StateSignKey> {
+        self.read(STATE_KEY_FILE, password)
+    }
+
+    /// Replace the state key with `new_key`.
+    ///
+    /// The old key is kept in the keystore directory, in a file named after the time of the
+    /// rotation, in case it is still needed.
+    pub fn rotate_state_key(&self, password: &str, new_key: &StateSignKey) -> anyhow::Result<()> {
+        // Check the password first, so that all keys in the keystore stay under the same password.
+        self.state_key(password)?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let backup = self.dir.join(format!("state.{now}.json"));
+        fs::copy(self.dir.join(STATE_KEY_FILE), &backup)
+            .with_context(|| format!("backing up old state key to {}", backup.display()))?;
+        self.write(STATE_KEY_FILE, password, new_key)
+    }
+
+    /// Switch `signer` to a new state key whenever the state key in this keystore changes.
+    pub async fn watch_state_key<Ver: StaticVersionType>(
+        self,
+        password: String,
+        signer: Arc<StateSigner<Ver>>,
+    ) {
+        let path = self.dir.join(STATE_KEY_FILE);
+        let mut modified = modified_time(&path);
+        loop {
+            sleep(WATCH_INTERVAL).await;
+            let latest = modified_time(&path);
+            if latest == modified {
+                continue;
+            }
+            modified = latest;
+
+            match self.state_key(&password) {
+                Ok(key) => {
+                    let key_pair = StateKeyPair::from_sign_key(key);
+                    tracing::warn!(ver_key = %key_pair.ver_key(), "switching to new state key");
+                    signer.rotate_key(key_pair).await;
+                }
+                Err(err) => tracing::error!("failed to reload state key: {err:#}"),
+            }
+        }
+    }
+
+    fn write(&self, file: &str, password: &str, key: &impl Display) -> anyhow::Result<()> {
+        // Encrypt to a temporary file and move it into place, so that a node reloading the key
+        // never sees a partially written file.
+        let tmp = format!(".{file}.tmp");
+        eth_keystore::encrypt_key(
+            &self.dir,
+            &mut rand::thread_rng(),
+            key.to_string(),
+            password,
+            Some(&tmp),
+        )?;
+        fs::rename(self.dir.join(&tmp), self.dir.join(file))?;
+        Ok(())
+    }
+
+    fn read<K>(&self, file: &str, password: &str) -> anyhow::Result<K>
+    where
+        K: FromStr,
+        K::Err: Display,
+    {
+        let path = self.dir.join(file);
+        let bytes = eth_keystore::decrypt_key(&path, password)
+            .with_context(|| format!("decrypting {}", path.display()))?;
+        String::from_utf8(bytes)?
+            .parse()
+            .map_err(|err| anyhow!("malformed key in {}: {err}", path.display()))
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Read a keystore password from `path`, ignoring a trailing newline.
+pub fn read_password(path: &Path) -> anyhow::Result<String> {
+    let password = fs::read_to_string(path)
+        .with_context(|| format!("reading password file {}", path.display()))?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Read private keys from an unencrypted key file in .env format, as written by `keygen`.
+pub fn read_key_file(path: &Path) -> anyhow::Result<(BLSPrivKey, StateSignKey)> {
+    let vars = dotenvy::from_path_iter(path)?.collect::<Result<HashMap<_, _>, _>>()?;
+    let staking = vars
+        .get("ESPRESSO_SEQUENCER_PRIVATE_STAKING_KEY")
+        .context("key file missing ESPRESSO_SEQUENCER_PRIVATE_STAKING_KEY")?
+        .parse()?;
+    let state = vars
+        .get("ESPRESSO_SEQUENCER_PRIVATE_STATE_KEY")
+        .context("key file missing ESPRESSO_SEQUENCER_PRIVATE_STATE_KEY")?
+        .parse()?;
+    Ok((staking, state))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PubKey;
+    use hotshot_types::traits::signature_key::SignatureKey;
+
+    #[test]
+    fn test_keystore() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys");
+        let (_, staking_key) = PubKey::generated_from_seed_indexed([0; 32], 0);
+        let state_key = StateKeyPair::generate_from_seed_indexed([0; 32], 0);
+
+        // Compare keys by their encoding, which is what the keystore stores.
+        let keystore =
+            Keystore::create(&path, "password", &staking_key, state_key.sign_key_ref()).unwrap();
+        assert_eq!(
+            keystore.staking_key("password").unwrap().to_string(),
+            staking_key.to_string()
+        );
+        assert_eq!(
+            keystore.state_key("password").unwrap().to_string(),
+            state_key.sign_key_ref().to_string()
+        );
+        keystore.state_key("wrong").unwrap_err();
+
+        // An existing keystore is never overwritten.
+        Keystore::create(&path, "password", &staking_key, state_key.sign_key_ref()).unwrap_err();
+
+        let keystore = Keystore::open(&path).unwrap();
+        let new_key = StateKeyPair::generate_from_seed_indexed([0; 32], 1);
+        keystore
+            .rotate_state_key("wrong", new_key.sign_key_ref())
+            .unwrap_err();
+        keystore
+            .rotate_state_key("password", new_key.sign_key_ref())
+            .unwrap();
+        assert_eq!(
+            keystore.state_key("password").unwrap().to_string(),
+            new_key.sign_key_ref().to_string()
+        );
+        assert_eq!(
+            keystore.staking_key("password").unwrap().to_string(),
+            staking_key.to_string()
+        );
+
+        // The old key is kept.
+        let backups = fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("state.") && name != STATE_KEY_FILE)
+            .collect::<Vec<_>>();
+        assert_eq!(backups.len(), 1);
+        let old: StateSignKey = keystore.read(&backups[0], "password").unwrap();
+        assert_eq!(old.to_string(), state_key.sign_key_ref().to_string());
+
+        Keystore::open(dir.path()).unwrap_err();
+    }
+}
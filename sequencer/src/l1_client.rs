@@ -15,14 +15,31 @@
 //!   Any failures or delays in interacting with the L1 will just slow the updating of the L1
 //!   snapshot, which will cause the block builder to propose with a slightly old snapshot, but they
 //!   will still be able to propose on time.
-
-use crate::state::FeeInfo;
+//!
+//! The client can be given a prioritized list of L1 RPC providers. Each request goes to the
+//! highest priority provider which is healthy, and fails over to the next one if it fails. A
+//! provider which fails is not used again until a backoff period has passed, after which the next
+//! request probes it again. Requests to each provider can be rate limited, and when there are
+//! several providers, the finalized block reported by one is checked against the others, so that a
+//! single faulty provider cannot feed the sequencer a bogus L1 block.
+
+use crate::{state::FeeInfo, upgrade::UpgradeScheduledFilter};
 use async_std::task::sleep;
 use committable::{Commitment, Committable, RawCommitmentBuilder};
+use contract_bindings::{
+    fee_contract::{DepositFilter, FeeContract},
+    light_client::LightClient,
+};
 use ethers::prelude::*;
-use futures::join;
+use futures::{future::join_all, join, Future};
+use hotshot_contract_adapter::light_client::ParsedLightClientState;
 use serde::{Deserialize, Serialize};
-use std::{cmp::Ordering, sync::Arc, time::Duration};
+use std::{
+    cmp::{min, Ordering},
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use url::Url;
 
 #[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Hash, PartialEq, Eq)]
@@ -85,12 +102,94 @@ impl Committable for L1BlockInfo {
     }
 }
 
+/// The longest a failed provider is skipped before it is tried again.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+/// One of the RPC providers used by an [`L1Client`].
+struct L1Provider {
+    url: Url,
+    /// `Provider` from `ethers-provider`.
+    provider: Provider<Http>,
+    status: Mutex<ProviderStatus>,
+}
+
+#[derive(Debug, Default)]
+struct ProviderStatus {
+    consecutive_failures: u32,
+    /// If the provider has failed, when it may be tried again.
+    retry_at: Option<Instant>,
+    /// When the rate limit allows the next request to be sent.
+    next_request: Option<Instant>,
+}
+
+impl L1Provider {
+    fn new(url: Url) -> Self {
+        Self {
+            provider: Provider::new(Http::new(url.clone())),
+            url,
+            status: Default::default(),
+        }
+    }
+
+    /// Whether the provider is healthy, or has been failing but is due to be probed again.
+    fn is_available(&self, now: Instant) -> bool {
+        self.status
+            .lock()
+            .unwrap()
+            .retry_at
+            .map_or(true, |retry_at| retry_at <= now)
+    }
+
+    fn record_success(&self) {
+        let mut status = self.status.lock().unwrap();
+        if status.consecutive_failures > 0 {
+            tracing::info!(url = %self.url, "L1 provider recovered");
+        }
+        status.consecutive_failures = 0;
+        status.retry_at = None;
+    }
+
+    /// Skip this provider for a while, backing off exponentially from `base` while it keeps
+    /// failing.
+    fn record_failure(&self, base: Duration) {
+        let mut status = self.status.lock().unwrap();
+        status.consecutive_failures += 1;
+        let backoff = min(
+            base.saturating_mul(1 << min(status.consecutive_failures - 1, 6)),
+            MAX_BACKOFF,
+        );
+        status.retry_at = Some(Instant::now() + backoff);
+    }
+
+    /// Wait until the rate limit allows another request to this provider.
+    async fn throttle(&self, min_interval: Option<Duration>) {
+        let Some(min_interval) = min_interval else {
+            return;
+        };
+        let wait = {
+            let mut status = self.status.lock().unwrap();
+            let now = Instant::now();
+            let next = status.next_request.map_or(now, |next| next.max(now));
+            status.next_request = Some(next + min_interval);
+            next - now
+        };
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
-/// An Http Provider and configuration to interact with the L1.
+/// Http Providers and configuration to interact with the L1.
 pub struct L1Client {
     retry_delay: Duration,
-    /// `Provider` from `ethers-provider`.
-    provider: Provider<Http>,
+    /// RPC providers, in order of priority.
+    providers: Arc<Vec<L1Provider>>,
+    /// The minimum time between requests to each provider.
+    min_request_interval: Option<Duration>,
+    /// The latest finalized block which was checked against all the providers.
+    verified_finalized: Arc<Mutex<Option<L1BlockInfo>>>,
     /// `Address` of fee contract.
     _address: Address,
 }
@@ -98,40 +197,159 @@ pub struct L1Client {
 impl L1Client {
     /// Instantiate an `L1Client` for a given `Url`.
     pub fn new(url: Url, contract_address: Address) -> Self {
+        Self::with_providers(vec![url], contract_address)
+    }
+
+    /// Instantiate an `L1Client` which fails over between `urls`, in order of priority.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `urls` is empty.
+    pub fn with_providers(urls: Vec<Url>, contract_address: Address) -> Self {
+        assert!(!urls.is_empty(), "L1 client requires at least one provider");
         Self {
             retry_delay: Duration::from_secs(1),
-            provider: Provider::new(Http::new(url)),
+            providers: Arc::new(urls.into_iter().map(L1Provider::new).collect()),
+            min_request_interval: None,
+            verified_finalized: Default::default(),
             _address: contract_address,
         }
     }
+
+    /// Limit the requests sent to each provider to `requests_per_second`.
+    pub fn with_rate_limit(mut self, requests_per_second: u64) -> Self {
+        self.min_request_interval =
+            Some(Duration::from_secs(1) / requests_per_second.max(1) as u32);
+        self
+    }
+
     /// Get a snapshot from the l1.
     pub async fn snapshot(&self) -> L1Snapshot {
         let (head, finalized) = join!(self.get_block_number(), self.get_finalized_block());
         L1Snapshot { head, finalized }
     }
     /// Proxy to `Provider.get_block_number`.
-    async fn get_block_number(&self) -> u64 {
+    pub(crate) async fn get_block_number(&self) -> u64 {
+        let (_, n) = self
+            .with_failover("Blocknumber", |provider| async move {
+                provider.get_block_number().await
+            })
+            .await;
+        n.as_u64()
+    }
+    /// Proxy to `get_finalized_block`.
+    async fn get_finalized_block(&self) -> Option<L1BlockInfo> {
         loop {
-            match self.provider.get_block_number().await {
-                Ok(n) => return n.as_u64(),
-                Err(e) => {
-                    tracing::warn!("Blocknumber error: {}", e);
-                    sleep(self.retry_delay).await;
-                }
+            let (index, block) = self
+                .with_failover("Finalized block", |provider| async move {
+                    get_finalized_block(&provider).await
+                })
+                .await;
+            let block = block?;
+            if self.check_finalized_block(index, block).await {
+                return Some(block);
             }
         }
     }
-    /// Proxy to `get_finalized_block`.
-    async fn get_finalized_block(&self) -> Option<L1BlockInfo> {
+
+    /// The providers to try, in order.
+    ///
+    /// These are the available providers in order of priority, or all of them if none are
+    /// available, since a failing provider is better than none.
+    fn candidates(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let available = (0..self.providers.len())
+            .filter(|&i| self.providers[i].is_available(now))
+            .collect::<Vec<_>>();
+        if available.is_empty() {
+            (0..self.providers.len()).collect()
+        } else {
+            available
+        }
+    }
+
+    /// Make a request, failing over between providers until one of them succeeds.
+    ///
+    /// Returns the index of the provider which succeeded, and its response.
+    async fn with_failover<T, E, F, Fut>(&self, what: &str, request: F) -> (usize, T)
+    where
+        E: Display,
+        F: Fn(Provider<Http>) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
         loop {
-            match get_finalized_block(&self.provider).await {
-                Ok(block) => return block,
+            for index in self.candidates() {
+                let provider = &self.providers[index];
+                provider.throttle(self.min_request_interval).await;
+                match request(provider.provider.clone()).await {
+                    Ok(res) => {
+                        provider.record_success();
+                        return (index, res);
+                    }
+                    Err(e) => {
+                        tracing::warn!(url = %provider.url, "{} error: {}", what, e);
+                        provider.record_failure(self.retry_delay);
+                    }
+                }
+            }
+            sleep(self.retry_delay).await;
+        }
+    }
+
+    /// Check `block`, the finalized block reported by provider `index`, against the other
+    /// providers.
+    ///
+    /// Providers which report a different block at the same height are skipped for a while. If
+    /// they outnumber the providers which agree with `index`, provider `index` is skipped instead,
+    /// and this returns `false`.
+    async fn check_finalized_block(&self, index: usize, block: L1BlockInfo) -> bool {
+        if self.providers.len() == 1 || *self.verified_finalized.lock().unwrap() == Some(block) {
+            return true;
+        }
+
+        let others = self
+            .candidates()
+            .into_iter()
+            .filter(|&i| i != index)
+            .collect::<Vec<_>>();
+        let hashes = join_all(others.iter().map(|&i| async move {
+            let provider = &self.providers[i];
+            provider.throttle(self.min_request_interval).await;
+            match provider
+                .provider
+                .get_block(BlockNumber::Number(block.number.into()))
+                .await
+            {
+                Ok(other) => other.and_then(|other| other.hash),
                 Err(e) => {
-                    tracing::warn!("Finalized block error: {}", e);
-                    sleep(self.retry_delay).await;
+                    tracing::warn!(url = %provider.url, "Finalized block check error: {}", e);
+                    provider.record_failure(self.retry_delay);
+                    None
                 }
             }
+        }))
+        .await;
+        let (agree, disagree) = tally_finalized_block(block.hash, others.into_iter().zip(hashes));
+
+        if disagree.len() > agree {
+            tracing::error!(
+                url = %self.providers[index].url,
+                ?block,
+                "L1 provider reported a finalized block which other providers disagree with"
+            );
+            self.providers[index].record_failure(self.retry_delay);
+            return false;
+        }
+        for i in disagree {
+            tracing::error!(
+                url = %self.providers[i].url,
+                ?block,
+                "L1 provider disagrees with the finalized block"
+            );
+            self.providers[i].record_failure(self.retry_delay);
         }
+        *self.verified_finalized.lock().unwrap() = Some(block);
+        true
     }
     /// Get fee info for each `Deposit` occurring between `prev`
     /// and `new`. Returns `Vec<FeeInfo>`
@@ -151,26 +369,110 @@ impl L1Client {
         let prev = prev_finalized.map(|prev| prev + 1).unwrap_or(0);
 
         // query for deposit events, loop until successful.
-        let events = loop {
-            match contract_bindings::fee_contract::FeeContract::new(
-                self._address,
-                Arc::new(&self.provider),
-            )
-            .deposit_filter()
-            .from_block(prev)
-            .to_block(new_finalized)
-            .query()
-            .await
-            {
-                Ok(events) => break events,
-                Err(e) => {
-                    tracing::warn!("Fee Event Error: {}", e);
-                    sleep(self.retry_delay).await;
-                }
-            }
-        };
+        let (_, events) = self
+            .with_failover("Fee Event", |provider| async move {
+                FeeContract::new(self._address, Arc::new(provider))
+                    .deposit_filter()
+                    .from_block(prev)
+                    .to_block(new_finalized)
+                    .query()
+                    .await
+            })
+            .await;
         events.into_iter().map(Into::into).collect()
     }
+
+    /// Get the hash of L1 block `number`, or `None` if there is no such block yet.
+    pub(crate) async fn get_block_hash(&self, number: u64) -> Option<H256> {
+        let (_, block) = self
+            .with_failover("Block", |provider| async move {
+                provider.get_block(BlockNumber::Number(number.into())).await
+            })
+            .await;
+        block.and_then(|block| block.hash)
+    }
+
+    /// Get each `Deposit` to `fee_contract` in L1 blocks `from` through `to`, along with the
+    /// location of its log.
+    pub(crate) async fn get_deposit_logs(
+        &self,
+        fee_contract: Address,
+        from: u64,
+        to: u64,
+    ) -> Vec<(DepositFilter, LogMeta)> {
+        let (_, events) = self
+            .with_failover("Fee Event", |provider| async move {
+                FeeContract::new(fee_contract, Arc::new(provider))
+                    .deposit_filter()
+                    .from_block(from)
+                    .to_block(to)
+                    .query_with_meta()
+                    .await
+            })
+            .await;
+        events
+    }
+
+    /// Get the latest state finalized by the light client contract at `light_client`.
+    pub(crate) async fn get_finalized_light_client_state(
+        &self,
+        light_client: Address,
+    ) -> ParsedLightClientState {
+        let (_, state) = self
+            .with_failover("Light Client State", |provider| async move {
+                LightClient::new(light_client, Arc::new(provider))
+                    .get_finalized_state()
+                    .call()
+                    .await
+            })
+            .await;
+        state.into()
+    }
+
+    /// Get each upgrade scheduled by `upgrade_contract` in L1 blocks `from` through `to`, along
+    /// with the location of its log.
+    pub(crate) async fn get_upgrade_logs(
+        &self,
+        upgrade_contract: Address,
+        from: u64,
+        to: u64,
+    ) -> Vec<(UpgradeScheduledFilter, LogMeta)> {
+        let (_, events) = self
+            .with_failover("Upgrade Event", |provider| async move {
+                UpgradeScheduledFilter::new::<_, Provider<Http>>(
+                    Filter::new().address(upgrade_contract),
+                    Arc::new(provider),
+                )
+                .from_block(from)
+                .to_block(to)
+                .query_with_meta()
+                .await
+            })
+            .await;
+        events
+    }
+}
+
+/// Count the providers which agree and disagree with the finalized block hash `ours`.
+///
+/// `others` are the hashes the other providers report at the same height, if they have a block
+/// there. The provider which reported `ours` counts as agreeing. Returns the number of providers
+/// which agree, and the providers which disagree.
+fn tally_finalized_block(
+    ours: H256,
+    others: impl IntoIterator<Item = (usize, Option<H256>)>,
+) -> (usize, Vec<usize>) {
+    let mut agree = 1;
+    let mut disagree = vec![];
+    for (i, hash) in others {
+        match hash {
+            Some(hash) if hash == ours => agree += 1,
+            Some(_) => disagree.push(i),
+            // The provider has not seen the block yet, or could not be reached.
+            None => {}
+        }
+    }
+    (agree, disagree)
 }
 
 async fn get_finalized_block<P: JsonRpcClient>(
@@ -210,7 +512,7 @@ mod test {
     use super::*;
     use crate::NodeState;
     use contract_bindings::fee_contract::FeeContract;
-    use ethers::utils::{parse_ether, Anvil};
+    use ethers::utils::{parse_ether, Anvil, AnvilInstance};
 
     #[async_std::test]
     async fn test_l1_block_fetching() -> anyhow::Result<()> {
@@ -218,7 +520,7 @@ mod test {
         // also some sanity testing demonstrating `Anvil` availability.
         let anvil = Anvil::new().block_time(1u32).spawn();
         let l1_client = L1Client::new(anvil.endpoint().parse().unwrap(), Address::default());
-        let provider = &l1_client.provider;
+        let provider = &l1_client.providers[0].provider;
 
         let version = provider.client_version().await.unwrap();
         assert_eq!("anvil/v0.2.0", version);
@@ -229,7 +531,11 @@ mod test {
             anvil.endpoint().parse().unwrap(),
             Address::default(),
         ));
-        let version = state.l1_client().provider.client_version().await.unwrap();
+        let version = state.l1_client().providers[0]
+            .provider
+            .client_version()
+            .await
+            .unwrap();
         assert_eq!("anvil/v0.2.0", version);
 
         // compare response of underlying provider w/ `get_block_number`
@@ -250,6 +556,107 @@ mod test {
         Ok(())
     }
 
+    #[async_std::test]
+    async fn test_l1_provider_failover() {
+        let anvil = Anvil::new().spawn();
+        let mut l1_client = L1Client::with_providers(
+            vec![
+                "http://localhost:1".parse().unwrap(),
+                anvil.endpoint().parse().unwrap(),
+            ],
+            Address::default(),
+        );
+        l1_client.retry_delay = Duration::from_millis(100);
+
+        // The first provider is unreachable, so the client fails over to the second.
+        let expected_head = anvil_provider(&anvil).get_block_number().await.unwrap();
+        assert_eq!(l1_client.get_block_number().await, expected_head.as_u64());
+        assert_eq!(l1_client.candidates(), [1]);
+
+        // Once the backoff has passed, the first provider is probed again.
+        sleep(Duration::from_millis(150)).await;
+        assert_eq!(l1_client.candidates(), [0, 1]);
+        l1_client.get_block_number().await;
+        assert_eq!(l1_client.candidates(), [1]);
+    }
+
+    #[test]
+    fn test_l1_provider_backoff() {
+        let provider = L1Provider::new("http://localhost:1".parse().unwrap());
+        let base = Duration::from_secs(1);
+        assert!(provider.is_available(Instant::now()));
+
+        provider.record_failure(base);
+        assert!(!provider.is_available(Instant::now()));
+        assert!(provider.is_available(Instant::now() + base));
+
+        // The backoff grows while the provider keeps failing.
+        provider.record_failure(base);
+        assert!(!provider.is_available(Instant::now() + base));
+        assert!(provider.is_available(Instant::now() + 2 * base));
+        for _ in 0..10 {
+            provider.record_failure(base);
+        }
+        assert!(provider.is_available(Instant::now() + MAX_BACKOFF));
+
+        provider.record_success();
+        assert!(provider.is_available(Instant::now()));
+    }
+
+    #[async_std::test]
+    async fn test_l1_provider_rate_limit() {
+        let provider = L1Provider::new("http://localhost:1".parse().unwrap());
+        let interval = Duration::from_millis(100);
+        let start = Instant::now();
+        for _ in 0..3 {
+            provider.throttle(Some(interval)).await;
+        }
+        assert!(start.elapsed() >= 2 * interval);
+    }
+
+    #[test]
+    fn test_tally_finalized_block() {
+        let ours = H256::repeat_byte(1);
+        let theirs = H256::repeat_byte(2);
+        assert_eq!(tally_finalized_block(ours, []), (1, vec![]));
+        assert_eq!(
+            tally_finalized_block(ours, [(1, Some(ours)), (2, Some(theirs)), (3, None)]),
+            (2, vec![2])
+        );
+    }
+
+    #[async_std::test]
+    async fn test_l1_finalized_block_consistency() {
+        // Two L1s with different genesis blocks, so they disagree on the finalized block.
+        let anvil = Anvil::new().args(["--timestamp", "1"]).spawn();
+        let other = Anvil::new().args(["--timestamp", "2"]).spawn();
+        let expected = get_finalized_block(&anvil_provider(&anvil))
+            .await
+            .unwrap()
+            .unwrap();
+
+        // A provider which disagrees with the rest is skipped, whether or not it has priority.
+        for urls in [
+            [anvil.endpoint(), anvil.endpoint(), other.endpoint()],
+            [other.endpoint(), anvil.endpoint(), anvil.endpoint()],
+        ] {
+            let l1_client = L1Client::with_providers(
+                urls.iter().map(|url| url.parse().unwrap()).collect(),
+                Address::default(),
+            );
+            assert_eq!(l1_client.get_finalized_block().await, Some(expected));
+            let faulty = urls
+                .iter()
+                .position(|url| *url == other.endpoint())
+                .unwrap();
+            assert!(!l1_client.candidates().contains(&faulty));
+        }
+    }
+
+    fn anvil_provider(anvil: &AnvilInstance) -> Provider<Http> {
+        Provider::try_from(anvil.endpoint()).unwrap()
+    }
+
     #[async_std::test]
     async fn test_get_finalized_deposits() -> anyhow::Result<()> {
         // how many deposits will we make
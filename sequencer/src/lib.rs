@@ -2,12 +2,24 @@ pub mod api;
 pub mod block;
 pub mod catchup;
 mod chain_config;
+pub mod chaos;
 pub mod context;
+pub mod equivocation;
 pub mod eth_signature_key;
+pub mod events;
+pub mod external_da;
+pub mod genesis;
 mod header;
 pub mod hotshot_commitment;
+pub mod keystore;
+pub mod logging;
+pub mod namespace_policy;
 pub mod options;
+pub mod reload;
+pub mod replay;
+pub mod snapshot;
 pub mod state_signature;
+pub mod upgrade;
 
 use anyhow::Context;
 use async_std::sync::RwLock;
@@ -16,6 +28,10 @@ use block::entry::TxTableEntryWord;
 use catchup::{StateCatchup, StatePeers};
 use context::SequencerContext;
 use ethers::types::{Address, U256};
+use genesis::Genesis;
+use namespace_policy::NamespacePolicy;
+use snapshot::fetch_snapshot;
+use upgrade::UpgradeSchedule;
 
 // Should move `STAKE_TABLE_CAPACITY` in the sequencer repo when we have variate stake table support
 
@@ -70,13 +86,10 @@ use std::{collections::BTreeMap, fmt::Debug, marker::PhantomData, net::SocketAdd
 use vbs::version::StaticVersionType;
 
 #[cfg(feature = "libp2p")]
-use std::time::Duration;
-
-#[cfg(feature = "libp2p")]
-use hotshot::traits::implementations::{CombinedNetworks, Libp2pNetwork};
+use hotshot::traits::implementations::Libp2pNetwork;
 
 pub use block::payload::Payload;
-pub use chain_config::ChainConfig;
+pub use chain_config::{BlockLimits, ChainConfig};
 pub use header::Header;
 pub use l1_client::L1BlockInfo;
 pub use options::Options;
@@ -125,6 +138,7 @@ impl<N: network::Type, P: SequencerPersistence> NodeImplementation<SeqTypes> for
 
 #[async_trait]
 impl<P: SequencerPersistence> Storage<SeqTypes> for Arc<RwLock<P>> {
+    #[tracing::instrument(skip_all, fields(view = ?proposal.data.view_number))]
     async fn append_vid(
         &self,
         proposal: &Proposal<SeqTypes, VidDisperseShare<SeqTypes>>,
@@ -132,12 +146,14 @@ impl<P: SequencerPersistence> Storage<SeqTypes> for Arc<RwLock<P>> {
         self.write().await.append_vid(proposal).await
     }
 
+    #[tracing::instrument(skip_all, fields(view = ?proposal.data.view_number))]
     async fn append_da(
         &self,
         proposal: &Proposal<SeqTypes, DAProposal<SeqTypes>>,
     ) -> anyhow::Result<()> {
         self.write().await.append_da(proposal).await
     }
+    #[tracing::instrument(skip(self))]
     async fn record_action(&self, view: ViewNumber, action: HotShotAction) -> anyhow::Result<()> {
         self.write().await.record_action(view, action).await
     }
@@ -157,9 +173,11 @@ impl<P: SequencerPersistence> Storage<SeqTypes> for Arc<RwLock<P>> {
 #[derive(Debug, Clone)]
 pub struct NodeState {
     chain_config: ChainConfig,
+    namespace_policy: Arc<NamespacePolicy>,
     l1_client: L1Client,
     peers: Arc<dyn StateCatchup>,
     genesis_state: ValidatedState,
+    upgrades: Arc<UpgradeSchedule>,
 }
 
 impl NodeState {
@@ -170,9 +188,11 @@ impl NodeState {
     ) -> Self {
         Self {
             chain_config,
+            namespace_policy: Default::default(),
             l1_client,
             peers: Arc::new(catchup),
             genesis_state: Default::default(),
+            upgrades: Default::default(),
         }
     }
 
@@ -195,9 +215,52 @@ impl NodeState {
         self
     }
 
+    /// Restrict namespaces according to `policy`, committing to it in the chain config.
+    pub fn with_namespace_policy(mut self, policy: NamespacePolicy) -> Self {
+        self.chain_config = self.chain_config.with_namespace_policy(&policy);
+        self.namespace_policy = Arc::new(policy);
+        self
+    }
+
+    /// Follow upgrades of the chain config scheduled on L1 by the governance contract at
+    /// `contract`, committing to it in the chain config.
+    ///
+    /// Upgrades are searched for from L1 block `start_block` on.
+    pub fn with_upgrade_contract(mut self, contract: Address, start_block: u64) -> Self {
+        self.chain_config = self.chain_config.with_upgrade_contract(contract);
+        self.upgrades = Arc::new(UpgradeSchedule::new(start_block));
+        self
+    }
+
     fn l1_client(&self) -> &L1Client {
         &self.l1_client
     }
+
+    pub fn chain_config(&self) -> &ChainConfig {
+        &self.chain_config
+    }
+
+    pub fn namespace_policy(&self) -> &NamespacePolicy {
+        &self.namespace_policy
+    }
+
+    /// Replace the peers this node fetches missing state from.
+    pub fn set_state_peers(&self, urls: Vec<Url>) -> anyhow::Result<()> {
+        self.peers.set_peers(urls)
+    }
+
+    /// The chain config for the child of `parent`, including any upgrade scheduled on L1 as of the
+    /// L1 block finalized by `parent`.
+    pub async fn chain_config_after(&self, parent: &Header) -> ChainConfig {
+        self.upgrades
+            .chain_config(
+                &self.l1_client,
+                self.chain_config,
+                parent.height + 1,
+                parent.l1_finalized.map(|block| block.number),
+            )
+            .await
+    }
 }
 
 impl InstanceState for NodeState {}
@@ -249,6 +312,8 @@ pub struct NetworkParams {
     pub private_staking_key: BLSPrivKey,
     pub private_state_key: StateSignKey,
     pub state_peers: Vec<Url>,
+    /// A peer to bootstrap from a snapshot of its state, if there is no saved consensus state
+    pub snapshot_url: Option<Url>,
     /// The address to send to other Libp2p nodes to contact us
     pub libp2p_advertise_address: SocketAddr,
     /// The address to bind to for Libp2p
@@ -261,7 +326,25 @@ pub struct BuilderParams {
 }
 
 pub struct L1Params {
-    pub url: Url,
+    /// L1 RPC providers, in order of priority.
+    pub urls: Vec<Url>,
+    /// The maximum number of requests per second to send to each provider.
+    pub rate_limit: Option<u64>,
+    /// The governance contract scheduling upgrades of the chain config, if any.
+    pub upgrade_contract: Option<Address>,
+    /// The first L1 block to search for upgrades scheduled by `upgrade_contract`.
+    pub upgrade_start_block: u64,
+}
+
+impl L1Params {
+    /// A client which fails over between the providers in these parameters.
+    pub fn client(self, fee_contract_address: Address) -> L1Client {
+        let client = L1Client::with_providers(self.urls, fee_contract_address);
+        match self.rate_limit {
+            Some(rate_limit) => client.with_rate_limit(rate_limit),
+            None => client,
+        }
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -274,6 +357,8 @@ pub async fn init_node<P: SequencerPersistence, Ver: StaticVersionType + 'static
     stake_table_capacity: usize,
     bind_version: Ver,
     chain_config: ChainConfig,
+    namespace_policy: NamespacePolicy,
+    genesis: Option<Genesis>,
 ) -> anyhow::Result<SequencerContext<network::Production, P, Ver>> {
     // Orchestrator client
     let validator_args = ValidatorArgs {
@@ -352,21 +437,12 @@ pub async fn init_node<P: SequencerPersistence, Ver: StaticVersionType + 'static
     .await
     .with_context(|| "Failed to create libp2p network")?;
 
-    // Combine the communication channels
+    // Combine the communication channels, sending each message over whichever is healthier for its
+    // recipient.
     #[cfg(feature = "libp2p")]
     let (da_network, quorum_network) = {
-        (
-            Arc::from(CombinedNetworks::new(
-                cdn_network.clone(),
-                p2p_network.clone(),
-                Duration::from_secs(1),
-            )),
-            Arc::from(CombinedNetworks::new(
-                cdn_network,
-                p2p_network,
-                Duration::from_secs(1),
-            )),
-        )
+        let network = network::hybrid::HybridNetwork::new(cdn_network, p2p_network, metrics);
+        (Arc::from(network.clone()), Arc::from(network))
     };
 
     // Wait for the CDN network to be ready if we're not using the P2P network
@@ -396,20 +472,55 @@ pub async fn init_node<P: SequencerPersistence, Ver: StaticVersionType + 'static
         tracing::info!("Prefunding account {:?} for demo", address);
         genesis_state.prefund_account(address.into(), U256::max_value().into());
     }
+    if let Some(genesis) = &genesis {
+        genesis.fund_accounts(&mut genesis_state);
+    }
 
-    let l1_client = L1Client::new(l1_params.url, Address::default());
+    let upgrade_contract = l1_params.upgrade_contract;
+    let upgrade_start_block = l1_params.upgrade_start_block;
+    let l1_client = l1_params.client(Address::default());
 
-    let instance_state = NodeState {
+    let mut instance_state = NodeState::new(
         chain_config,
         l1_client,
-        genesis_state,
-        peers: Arc::new(StatePeers::<Ver>::from_urls(network_params.state_peers)),
+        StatePeers::<Ver>::from_urls(network_params.state_peers),
+    )
+    .with_genesis(genesis_state)
+    .with_namespace_policy(namespace_policy);
+    if let Some(contract) = upgrade_contract {
+        tracing::info!(?contract, "following upgrades scheduled on L1");
+        instance_state = instance_state.with_upgrade_contract(contract, upgrade_start_block);
+    }
+
+    // Bootstrap from a snapshot only if we have nothing to resume from.
+    let snapshot = match network_params.snapshot_url {
+        Some(url) if persistence.load_anchor_leaf().await?.is_none() => {
+            let snapshot = fetch_snapshot(url, bind_version)
+                .await
+                .and_then(|snapshot| {
+                    let state = snapshot
+                        .verify(&config.config.known_nodes_with_stake, stake_table_capacity)?;
+                    Ok((snapshot, state))
+                });
+            match snapshot {
+                Ok((snapshot, state)) => {
+                    tracing::info!(height = snapshot.height(), "bootstrapping from snapshot");
+                    Some((snapshot, state))
+                }
+                Err(err) => {
+                    tracing::error!("invalid snapshot, starting from genesis: {err:#}");
+                    None
+                }
+            }
+        }
+        _ => None,
     };
 
     let mut ctx = SequencerContext::init(
         config.config,
         instance_state,
         persistence,
+        snapshot,
         networks,
         Some(network_params.state_relay_server_url),
         metrics,
@@ -477,12 +588,19 @@ pub mod testing {
         state_key_pairs: Vec<StateKeyPair>,
         master_map: Arc<MasterMap<Message<SeqTypes>, PubKey>>,
         anvil: Arc<AnvilInstance>,
+        state_relay_url: Option<Url>,
     }
 
     impl Default for TestConfig {
         fn default() -> Self {
-            let num_nodes = Self::NUM_NODES;
+            Self::with_num_nodes(Self::NUM_NODES)
+        }
+    }
 
+    impl TestConfig {
+        pub const NUM_NODES: usize = 4;
+
+        pub fn with_num_nodes(num_nodes: usize) -> Self {
             // Generate keys for the nodes.
             let seed = [0; 32];
             let (pub_keys, priv_keys): (Vec<_>, Vec<_>) = (0..num_nodes)
@@ -538,12 +656,9 @@ pub mod testing {
                 state_key_pairs,
                 master_map,
                 anvil: Arc::new(Anvil::new().spawn()),
+                state_relay_url: None,
             }
         }
-    }
-
-    impl TestConfig {
-        pub const NUM_NODES: usize = 4;
 
         pub fn num_nodes(&self) -> usize {
             self.priv_keys.len()
@@ -557,6 +672,16 @@ pub mod testing {
             self.config.builder_url = builder_url;
         }
 
+        /// Have the nodes push their state signatures to the relay server at `url`.
+        pub fn set_state_relay_url(&mut self, url: Url) {
+            self.state_relay_url = Some(url);
+        }
+
+        /// The L1 the nodes are connected to.
+        pub fn anvil(&self) -> &AnvilInstance {
+            &self.anvil
+        }
+
         pub async fn init_nodes<Ver: StaticVersionType + 'static>(
             &self,
             bind_version: Ver,
@@ -631,8 +756,9 @@ pub mod testing {
                 config,
                 node_state,
                 persistence,
-                networks,
                 None,
+                networks,
+                self.state_relay_url.clone(),
                 metrics,
                 i as u64,
                 stake_table_capacity,
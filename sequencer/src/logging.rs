@@ -0,0 +1,118 @@
+//! Log output and trace export.
+//!
+//! Logs are written to stderr, either for humans or as one JSON object per line for log
+//! aggregators. Spans can also be exported over OTLP to a tracing backend such as Jaeger or Tempo.
+//! Since HotShot opens spans for each of its tasks (proposal, VID dispersal, DA voting and so on)
+//! and the sequencer opens spans for building and validating headers, storing proposals and
+//! handling decides, exported traces from several nodes can be lined up to see where the time goes
+//! in each view, without correlating timestamps across log files.
+//!
+//! The log filter starts out as `RUST_LOG`, and can be changed while the node runs through the
+//! [`LogFilter`] returned when logging is initialized.
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime::AsyncStd, trace, Resource};
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Layer, Registry,
+};
+use url::Url;
+
+/// Options for logging and trace export.
+#[derive(Clone, Debug, Args)]
+pub struct Config {
+    /// Format of log output.
+    #[clap(long, env = "RUST_LOG_FORMAT", value_enum, default_value_t = LogFormat::Full)]
+    pub log_format: LogFormat,
+
+    /// OTLP gRPC endpoint to export spans to, such as `http://localhost:4317`.
+    ///
+    /// Spans are exported in batches in the background. Which spans are exported is controlled by
+    /// `RUST_LOG`, the same as for log output.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<Url>,
+
+    /// Service name to report exported spans under, to tell nodes apart in the tracing backend.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_OTLP_SERVICE_NAME",
+        default_value = "sequencer"
+    )]
+    pub otlp_service_name: String,
+}
+
+/// Format of log output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable, one line per event with the full span context.
+    #[default]
+    Full,
+    /// Human-readable, with less context.
+    Compact,
+    /// Newline-delimited JSON.
+    Json,
+}
+
+/// A handle to change which logs and spans are recorded.
+#[derive(Clone, Debug)]
+pub struct LogFilter(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilter {
+    /// Replace the filter with `directives`, in the syntax of `RUST_LOG`.
+    pub fn set(&self, directives: &str) -> anyhow::Result<()> {
+        let filter = EnvFilter::try_new(directives)
+            .with_context(|| format!("invalid log filter {directives}"))?;
+        self.0.reload(filter)?;
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Install the global subscriber for logs and, if configured, trace export.
+    ///
+    /// This must be called from within an async-std runtime if spans are exported.
+    pub fn init(&self) -> anyhow::Result<LogFilter> {
+        let output = match self.log_format {
+            LogFormat::Full => fmt::layer().boxed(),
+            LogFormat::Compact => fmt::layer().compact().boxed(),
+            LogFormat::Json => fmt::layer().json().boxed(),
+        };
+        let otlp = match &self.otlp_endpoint {
+            Some(endpoint) => {
+                let tracer = opentelemetry_otlp::new_pipeline()
+                    .tracing()
+                    .with_exporter(
+                        opentelemetry_otlp::new_exporter()
+                            .tonic()
+                            .with_endpoint(endpoint.as_str()),
+                    )
+                    .with_trace_config(trace::config().with_resource(Resource::new([
+                        KeyValue::new("service.name", self.otlp_service_name.clone()),
+                    ])))
+                    .install_batch(AsyncStd)
+                    .context("installing OTLP trace exporter")?;
+                Some(tracing_opentelemetry::layer().with_tracer(tracer))
+            }
+            None => None,
+        };
+        let (filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(output)
+            .with(otlp)
+            .try_init()?;
+        if let Some(endpoint) = &self.otlp_endpoint {
+            tracing::info!(%endpoint, "exporting spans");
+        }
+        Ok(LogFilter(handle))
+    }
+
+    /// Flush spans which have not been exported yet.
+    pub fn shut_down(&self) {
+        if self.otlp_endpoint.is_some() {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
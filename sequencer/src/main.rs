@@ -1,58 +1,92 @@
 use std::net::ToSocketAddrs;
 
-use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
+use anyhow::Context;
+use async_compatibility_layer::logging::setup_backtrace;
 use clap::Parser;
 use es_version::SEQUENCER_VERSION;
 use futures::future::FutureExt;
 use hotshot_types::traits::metrics::NoMetrics;
 use sequencer::{
     api::{self, data_source::DataSourceOptions},
+    genesis::Genesis,
     init_node,
+    logging::LogFilter,
+    namespace_policy::NamespacePolicy,
     options::{Modules, Options},
-    persistence, BuilderParams, ChainConfig, L1Params, NetworkParams,
+    persistence,
+    reload::{shutdown_signal, Reloader},
+    BuilderParams, ChainConfig, L1Params, NetworkParams,
 };
 use vbs::version::StaticVersionType;
 
 #[async_std::main]
 async fn main() -> anyhow::Result<()> {
-    setup_logging();
+    let opt = Options::parse();
+    let logging = opt.logging.clone();
+    let log_filter = logging.init()?;
     setup_backtrace();
 
     tracing::warn!("sequencer starting up");
-    let opt = Options::parse();
     let mut modules = opt.modules();
     tracing::warn!("modules: {:?}", modules);
 
-    if let Some(storage) = modules.storage_fs.take() {
-        init_with_storage(modules, opt, storage, SEQUENCER_VERSION).await
+    let res = if let Some(storage) = modules.storage_fs.take() {
+        init_with_storage(modules, opt, storage, log_filter, SEQUENCER_VERSION).await
     } else if let Some(storage) = modules.storage_sql.take() {
-        init_with_storage(modules, opt, storage, SEQUENCER_VERSION).await
+        init_with_storage(modules, opt, storage, log_filter, SEQUENCER_VERSION).await
     } else {
         // Persistence is required. If none is provided, just use the local file system.
         init_with_storage(
             modules,
             opt,
             persistence::fs::Options::default(),
+            log_filter,
             SEQUENCER_VERSION,
         )
         .await
-    }
+    };
+    logging.shut_down();
+    res
 }
 
 async fn init_with_storage<S, Ver: StaticVersionType + 'static>(
     modules: Modules,
     opt: Options,
     storage_opt: S,
+    log_filter: LogFilter,
     bind_version: Ver,
 ) -> anyhow::Result<()>
 where
     S: DataSourceOptions,
 {
     let (private_staking_key, private_state_key) = opt.private_keys()?;
+    let keystore = opt.keystore()?;
     let stake_table_capacity = opt.stake_table_capacity;
-    let chain_config = ChainConfig::new(opt.chain_id, opt.max_block_size, opt.base_fee);
+    let genesis = opt
+        .genesis_file
+        .as_ref()
+        .map(Genesis::from_file)
+        .transpose()?;
+    let chain_config = match &genesis {
+        Some(genesis) => genesis.chain_config(),
+        None => {
+            // Clap ensures these are present without a genesis file.
+            ChainConfig::new(
+                opt.chain_id,
+                opt.max_block_size.context("missing max block size")?,
+                opt.base_fee.context("missing base fee")?,
+            )
+        }
+    };
+    let namespace_policy = match &opt.namespace_policy {
+        Some(path) => NamespacePolicy::from_file(path)?,
+        None => Default::default(),
+    };
     let l1_params = L1Params {
-        url: opt.l1_provider_url,
+        urls: opt.l1_provider_url,
+        rate_limit: opt.l1_rate_limit,
+        upgrade_contract: opt.upgrade_contract_address,
+        upgrade_start_block: opt.upgrade_contract_start_block,
     };
     let builder_params = BuilderParams {
         prefunded_accounts: opt.prefunded_builder_accounts,
@@ -83,12 +117,16 @@ where
         private_staking_key,
         private_state_key,
         state_peers: opt.state_peers,
+        snapshot_url: opt.snapshot_url,
     };
 
+    let reload_file = opt.reload_file;
+    let mut rate_limit = None;
+
     // Inititialize HotShot. If the user requested the HTTP module, we must initialize the handle in
     // a special way, in order to populate the API with consensus metrics. Otherwise, we initialize
     // the handle directly, with no metrics.
-    let ctx = match modules.http {
+    let mut ctx = match modules.http {
         Some(opt) => {
             // Add optional API modules as requested.
             let mut opt = api::Options::from(opt);
@@ -107,9 +145,27 @@ where
             if let Some(catchup) = modules.catchup {
                 opt = opt.catchup(catchup);
             }
+            if let Some(fee_deposits) = modules.fee_deposits {
+                opt = opt.fee_deposits(fee_deposits);
+            }
+            if let Some(light_client) = modules.light_client {
+                opt = opt.light_client(light_client);
+            }
+            if let Some(preconfirmation) = modules.preconfirmation {
+                opt = opt.preconfirmation(preconfirmation);
+            }
+            if let Some(rate_limit) = modules.rate_limit {
+                opt = opt.rate_limit(rate_limit);
+            }
             if let Some(hotshot_events) = modules.hotshot_events {
                 opt = opt.hotshot_events(hotshot_events);
             }
+            #[cfg(feature = "grpc")]
+            if let Some(grpc) = modules.grpc {
+                opt = opt.grpc(grpc);
+            }
+
+            rate_limit = opt.shared_rate_limit();
 
             let storage = storage_opt.create().await?;
             opt.serve(
@@ -124,6 +180,8 @@ where
                             stake_table_capacity,
                             bind_version,
                             chain_config,
+                            namespace_policy,
+                            genesis,
                         )
                         .await
                         .unwrap()
@@ -144,14 +202,37 @@ where
                 stake_table_capacity,
                 bind_version,
                 chain_config,
+                namespace_policy,
+                genesis,
             )
             .await?
         }
     };
 
+    if let Some(external_da) = modules.external_da {
+        let mirror = external_da.mirror(ctx.get_event_stream())?;
+        ctx.spawn("external DA mirror", mirror);
+    }
+    if let Some((keystore, password)) = keystore {
+        let signer = ctx.state_signer();
+        ctx.spawn(
+            "state key watcher",
+            keystore.watch_state_key(password, signer),
+        );
+    }
+
+    // Register signal handlers before starting consensus, so that a SIGTERM during startup is not
+    // lost.
+    let reloader =
+        reload_file.map(|path| Reloader::new(path, log_filter, ctx.node_state(), rate_limit));
+    if let Some(reloader) = &reloader {
+        reloader.reload()?;
+    }
+    let shutdown = shutdown_signal(reloader)?;
+
     // Start doing consensus.
     ctx.start_consensus().await;
-    ctx.join().await;
+    ctx.run_until(shutdown).await;
 
     Ok(())
 }
@@ -0,0 +1,510 @@
+//! Namespace access control for shared-sequencer deployments.
+//!
+//! A [`NamespacePolicy`] is part of the chain configuration: its commitment is included in the
+//! [`ChainConfig`](crate::ChainConfig), so all nodes must agree on it. It can reserve namespaces so
+//! they cannot be used at all, limit the size of a namespace's payload in each block, restrict a
+//! namespace to an allowlist of submitters, and price namespaces differently by scaling the base
+//! fee per byte.
+//!
+//! Reserved namespaces, size limits and fees only depend on the namespace table, so they are
+//! enforced when validating each block. Allowlists are enforced by the submit API: a transaction in an
+//! allowlisted namespace must be submitted as a [`SignedTransaction`], signed by one of the allowed
+//! keys.
+
+use crate::{
+    block::{entry::TxTableEntryWord, tables::NameSpaceTable},
+    eth_signature_key::{EthKeyPair, SigningError},
+    state::{FeeAccount, FeeAmount},
+    NamespaceId, Transaction,
+};
+use anyhow::{ensure, Context};
+use committable::{Commitment, Committable, RawCommitmentBuilder};
+use ethers::types::{Address, Signature, SignatureError, U256};
+use hotshot_types::traits::signature_key::BuilderSignatureKey;
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+use std::{collections::BTreeSet, path::Path};
+
+/// Rules restricting the use of namespaces.
+///
+/// The default policy places no restrictions on any namespace.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespacePolicy {
+    /// Namespaces which may not be used at all.
+    #[serde(default)]
+    pub reserved: BTreeSet<NamespaceId>,
+    /// Rules for individual namespaces, sorted by namespace.
+    #[serde(default, rename = "namespace")]
+    pub namespaces: Vec<NamespaceRules>,
+    /// The fee multiplier for namespaces which do not set their own, as a percentage of the base
+    /// fee.
+    #[serde(default)]
+    pub default_fee_multiplier: Option<u64>,
+}
+
+/// The rules for a single namespace.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceRules {
+    pub id: NamespaceId,
+    /// The only keys allowed to submit transactions in this namespace, if restricted.
+    #[serde(default)]
+    pub allowlist: Option<BTreeSet<Address>>,
+    /// The maximum size in bytes of this namespace's payload in a block.
+    #[serde(default)]
+    pub max_payload_size: Option<u64>,
+    /// The fee per byte of this namespace's payload, as a percentage of the base fee.
+    #[serde(default)]
+    pub fee_multiplier: Option<u64>,
+}
+
+/// Why a transaction or block violates a [`NamespacePolicy`].
+#[derive(Clone, Debug, Snafu, PartialEq, Eq)]
+pub enum NamespacePolicyError {
+    #[snafu(display("namespace {namespace} is reserved"))]
+    Reserved { namespace: NamespaceId },
+    #[snafu(display(
+        "payload of {size} bytes exceeds the maximum of {max} bytes for namespace {namespace}"
+    ))]
+    TooLarge {
+        namespace: NamespaceId,
+        size: u64,
+        max: u64,
+    },
+    #[snafu(display("namespace {namespace} requires a signed transaction"))]
+    Unsigned { namespace: NamespaceId },
+    #[snafu(display("{signer:#x} is not allowed to submit to namespace {namespace}"))]
+    NotAllowed {
+        namespace: NamespaceId,
+        signer: Address,
+    },
+}
+
+impl NamespacePolicy {
+    /// Load a policy from a TOML file.
+    ///
+    /// The file lists `reserved` namespace IDs and an optional `default_fee_multiplier`, followed by
+    /// a `[[namespace]]` table for each namespace with an `allowlist` of addresses, a
+    /// `max_payload_size` and/or a `fee_multiplier`.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("reading namespace policy {}", path.display()))?;
+        let mut policy: Self = toml::from_str(&toml)
+            .with_context(|| format!("parsing namespace policy {}", path.display()))?;
+
+        // Sort the rules so that the commitment does not depend on the order of the file.
+        policy.namespaces.sort_by_key(|rules| rules.id);
+        for pair in policy.namespaces.windows(2) {
+            ensure!(
+                pair[0].id != pair[1].id,
+                "namespace {} has more than one set of rules",
+                pair[0].id
+            );
+        }
+        Ok(policy)
+    }
+
+    /// Whether this policy places no restrictions on any namespace.
+    pub fn is_empty(&self) -> bool {
+        self.reserved.is_empty()
+            && self.namespaces.is_empty()
+            && self.default_fee_multiplier.is_none()
+    }
+
+    /// The rules for `namespace`, if it has any.
+    pub fn rules(&self, namespace: NamespaceId) -> Option<&NamespaceRules> {
+        self.namespaces.iter().find(|rules| rules.id == namespace)
+    }
+
+    /// Check whether `tx` may be submitted, by `signer` if it was signed.
+    pub fn check_submission(
+        &self,
+        tx: &Transaction,
+        signer: Option<Address>,
+    ) -> Result<(), NamespacePolicyError> {
+        let namespace = tx.namespace();
+        if self.reserved.contains(&namespace) {
+            return Err(NamespacePolicyError::Reserved { namespace });
+        }
+        let Some(rules) = self.rules(namespace) else {
+            return Ok(());
+        };
+        if let Some(max) = rules.max_payload_size {
+            let size = tx.payload().len() as u64;
+            if size > max {
+                return Err(NamespacePolicyError::TooLarge {
+                    namespace,
+                    size,
+                    max,
+                });
+            }
+        }
+        if let Some(allowlist) = &rules.allowlist {
+            let signer = signer.ok_or(NamespacePolicyError::Unsigned { namespace })?;
+            if !allowlist.contains(&signer) {
+                return Err(NamespacePolicyError::NotAllowed { namespace, signer });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the namespaces in a proposed block against this policy.
+    pub fn validate_ns_table(
+        &self,
+        ns_table: &NameSpaceTable<TxTableEntryWord>,
+    ) -> Result<(), NamespacePolicyError> {
+        for ns_index in 0..ns_table.len() {
+            // The payload length is not known from the header alone, but the offsets in the table
+            // bound the size of each namespace regardless.
+            let (namespace, range) = ns_table.get_payload_range(ns_index, usize::MAX);
+            if self.reserved.contains(&namespace) {
+                return Err(NamespacePolicyError::Reserved { namespace });
+            }
+            let max = self
+                .rules(namespace)
+                .and_then(|rules| rules.max_payload_size);
+            if let Some(max) = max {
+                let size = range.len() as u64;
+                if size > max {
+                    return Err(NamespacePolicyError::TooLarge {
+                        namespace,
+                        size,
+                        max,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether this policy sets any fee multipliers.
+    pub fn prices_namespaces(&self) -> bool {
+        self.default_fee_multiplier.is_some()
+            || self
+                .namespaces
+                .iter()
+                .any(|rules| rules.fee_multiplier.is_some())
+    }
+
+    /// The minimum fee for a block with namespace table `ns_table`, given the `base_fee` per byte.
+    ///
+    /// Each namespace pays the base fee for each byte of its payload, scaled by its fee multiplier,
+    /// or by the default multiplier if it does not have one. Namespaces without a multiplier pay
+    /// the base fee if there is no default.
+    pub fn min_fee(
+        &self,
+        base_fee: FeeAmount,
+        ns_table: &NameSpaceTable<TxTableEntryWord>,
+    ) -> FeeAmount {
+        // Sum the size of each namespace weighted by its multiplier, and scale by the base fee only
+        // at the end, so that rounding does not favor splitting a payload across namespaces.
+        let weighted = (0..ns_table.len())
+            .map(|ns_index| {
+                let (namespace, range) = ns_table.get_payload_range(ns_index, usize::MAX);
+                U256::from(range.len()) * U256::from(self.fee_multiplier(namespace))
+            })
+            .fold(U256::zero(), |total, fee| total.saturating_add(fee));
+        let fee = weighted.saturating_mul(base_fee.into()) / 100;
+        fee.into()
+    }
+
+    /// The fee multiplier of `namespace`, as a percentage of the base fee.
+    pub fn fee_multiplier(&self, namespace: NamespaceId) -> u64 {
+        self.rules(namespace)
+            .and_then(|rules| rules.fee_multiplier)
+            .or(self.default_fee_multiplier)
+            .unwrap_or(100)
+    }
+
+    /// The fee for `bytes` bytes of payload in `namespace`, given the `base_fee` per byte.
+    ///
+    /// This is what [`min_fee`](Self::min_fee) charges for those bytes.
+    pub fn namespace_fee(
+        &self,
+        base_fee: FeeAmount,
+        namespace: NamespaceId,
+        bytes: u64,
+    ) -> FeeAmount {
+        let weighted = U256::from(bytes) * U256::from(self.fee_multiplier(namespace));
+        (weighted.saturating_mul(base_fee.into()) / 100).into()
+    }
+}
+
+impl Committable for NamespacePolicy {
+    fn tag() -> String {
+        "NAMESPACE_POLICY".to_string()
+    }
+
+    fn commit(&self) -> Commitment<Self> {
+        let mut comm = RawCommitmentBuilder::new(&Self::tag())
+            .u64_field("num_reserved", self.reserved.len() as u64);
+        for namespace in &self.reserved {
+            comm = comm.u64_field("reserved", (*namespace).into());
+        }
+        comm = comm
+            .u64_field(
+                "has_default_fee_multiplier",
+                self.default_fee_multiplier.is_some() as u64,
+            )
+            .u64_field(
+                "default_fee_multiplier",
+                self.default_fee_multiplier.unwrap_or_default(),
+            )
+            .u64_field("num_namespaces", self.namespaces.len() as u64);
+        for rules in &self.namespaces {
+            comm = comm
+                .u64_field("id", rules.id.into())
+                .u64_field(
+                    "has_max_payload_size",
+                    rules.max_payload_size.is_some() as u64,
+                )
+                .u64_field(
+                    "max_payload_size",
+                    rules.max_payload_size.unwrap_or_default(),
+                )
+                .u64_field("has_fee_multiplier", rules.fee_multiplier.is_some() as u64)
+                .u64_field("fee_multiplier", rules.fee_multiplier.unwrap_or_default())
+                .u64_field("has_allowlist", rules.allowlist.is_some() as u64);
+            for address in rules.allowlist.iter().flatten() {
+                comm = comm.fixed_size_field("allowed", address.as_fixed_bytes());
+            }
+        }
+        comm.finalize()
+    }
+}
+
+/// A transaction signed by its submitter, for submitting to an allowlisted namespace.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedTransaction {
+    pub transaction: Transaction,
+    /// Signature over the commitment of `transaction`.
+    pub signature: Signature,
+}
+
+impl SignedTransaction {
+    pub fn new(transaction: Transaction, key: &EthKeyPair) -> Result<Self, SigningError> {
+        let hash = transaction.commit();
+        let msg: &[u8] = hash.as_ref();
+        let signature = FeeAccount::sign_builder_message(key, msg)?;
+        Ok(Self {
+            transaction,
+            signature,
+        })
+    }
+
+    /// The address which signed this transaction.
+    pub fn signer(&self) -> Result<Address, SignatureError> {
+        let hash = self.transaction.commit();
+        let msg: &[u8] = hash.as_ref();
+        self.signature.recover(msg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(index: u32) -> EthKeyPair {
+        EthKeyPair::from_mnemonic(
+            "test test test test test test test test test test test junk",
+            index,
+        )
+        .unwrap()
+    }
+
+    fn policy() -> NamespacePolicy {
+        NamespacePolicy {
+            reserved: [NamespaceId::from(0)].into(),
+            namespaces: vec![NamespaceRules {
+                id: 1.into(),
+                allowlist: Some([key(0).address()].into()),
+                max_payload_size: Some(4),
+                fee_multiplier: None,
+            }],
+            default_fee_multiplier: None,
+        }
+    }
+
+    #[test]
+    fn test_namespace_policy_submission() {
+        let policy = policy();
+        let signer = key(0).address();
+
+        // Unrestricted namespaces accept anything.
+        policy
+            .check_submission(&Transaction::new(2.into(), vec![0; 100]), None)
+            .unwrap();
+        assert_eq!(
+            policy.check_submission(&Transaction::new(0.into(), vec![]), Some(signer)),
+            Err(NamespacePolicyError::Reserved {
+                namespace: 0.into()
+            })
+        );
+
+        let tx =
+            SignedTransaction::new(Transaction::new(1.into(), vec![1, 2, 3]), &key(0)).unwrap();
+        assert_eq!(tx.signer().unwrap(), signer);
+        policy
+            .check_submission(&tx.transaction, Some(tx.signer().unwrap()))
+            .unwrap();
+        assert_eq!(
+            policy.check_submission(&tx.transaction, None),
+            Err(NamespacePolicyError::Unsigned {
+                namespace: 1.into()
+            })
+        );
+
+        let other = SignedTransaction::new(tx.transaction.clone(), &key(1)).unwrap();
+        assert_eq!(
+            policy.check_submission(&other.transaction, Some(other.signer().unwrap())),
+            Err(NamespacePolicyError::NotAllowed {
+                namespace: 1.into(),
+                signer: key(1).address()
+            })
+        );
+
+        // A signature does not transfer to a different transaction.
+        let forged = SignedTransaction {
+            transaction: Transaction::new(1.into(), vec![4]),
+            signature: tx.signature,
+        };
+        assert_ne!(forged.signer().unwrap(), signer);
+
+        assert_eq!(
+            policy.check_submission(&Transaction::new(1.into(), vec![0; 5]), Some(signer)),
+            Err(NamespacePolicyError::TooLarge {
+                namespace: 1.into(),
+                size: 5,
+                max: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_namespace_policy_validation() {
+        let policy = policy();
+
+        let ns_table =
+            NameSpaceTable::from_namespace_offsets(vec![(1.into(), 4), (2.into(), 100)]).unwrap();
+        policy.validate_ns_table(&ns_table).unwrap();
+
+        let ns_table =
+            NameSpaceTable::from_namespace_offsets(vec![(2.into(), 100), (1.into(), 105)]).unwrap();
+        assert_eq!(
+            policy.validate_ns_table(&ns_table),
+            Err(NamespacePolicyError::TooLarge {
+                namespace: 1.into(),
+                size: 5,
+                max: 4
+            })
+        );
+
+        let ns_table = NameSpaceTable::from_namespace_offsets(vec![(0.into(), 0)]).unwrap();
+        assert_eq!(
+            policy.validate_ns_table(&ns_table),
+            Err(NamespacePolicyError::Reserved {
+                namespace: 0.into()
+            })
+        );
+    }
+
+    #[test]
+    fn test_namespace_policy_fees() {
+        let ns_table =
+            NameSpaceTable::from_namespace_offsets(vec![(1.into(), 4), (2.into(), 10)]).unwrap();
+
+        // Without any multipliers, every namespace pays the base fee.
+        assert!(!policy().prices_namespaces());
+        assert_eq!(policy().min_fee(2.into(), &ns_table), (10 * 2).into());
+
+        // Namespace 1 pays 3x the base fee, everyone else pays half.
+        let mut policy = policy();
+        policy.namespaces[0].fee_multiplier = Some(300);
+        policy.default_fee_multiplier = Some(50);
+        assert!(policy.prices_namespaces());
+        assert_eq!(policy.min_fee(2.into(), &ns_table), (4 * 2 * 3 + 6).into());
+
+        // Without a default, other namespaces pay the base fee.
+        policy.default_fee_multiplier = None;
+        assert_eq!(
+            policy.min_fee(2.into(), &ns_table),
+            (4 * 2 * 3 + 6 * 2).into()
+        );
+        assert_eq!(policy.min_fee(0.into(), &ns_table), 0.into());
+
+        // The fee for a single namespace agrees with the fee for the whole table.
+        assert_eq!(policy.fee_multiplier(1.into()), 300);
+        assert_eq!(policy.fee_multiplier(2.into()), 100);
+        assert_eq!(
+            policy.namespace_fee(2.into(), 1.into(), 4)
+                + policy.namespace_fee(2.into(), 2.into(), 6),
+            policy.min_fee(2.into(), &ns_table)
+        );
+    }
+
+    #[test]
+    fn test_namespace_policy_commitment() {
+        assert_eq!(policy().commit(), policy().commit());
+        assert_ne!(policy().commit(), NamespacePolicy::default().commit());
+
+        let mut other = policy();
+        other.namespaces[0].allowlist = Some([key(1).address()].into());
+        assert_ne!(policy().commit(), other.commit());
+
+        let mut other = policy();
+        other.default_fee_multiplier = Some(100);
+        assert!(!other.is_empty());
+        assert_ne!(policy().commit(), other.commit());
+
+        // An explicit limit or multiplier is distinct from none, whatever its value.
+        let mut unset = policy();
+        unset.default_fee_multiplier = None;
+        unset.namespaces[0].max_payload_size = None;
+        unset.namespaces[0].fee_multiplier = None;
+        for value in [0, u64::MAX] {
+            let mut other = unset.clone();
+            other.default_fee_multiplier = Some(value);
+            assert_ne!(unset.commit(), other.commit());
+
+            let mut other = unset.clone();
+            other.namespaces[0].max_payload_size = Some(value);
+            assert_ne!(unset.commit(), other.commit());
+
+            let mut other = unset.clone();
+            other.namespaces[0].fee_multiplier = Some(value);
+            assert_ne!(unset.commit(), other.commit());
+        }
+    }
+
+    #[test]
+    fn test_namespace_policy_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.toml");
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                reserved = [0]
+
+                [[namespace]]
+                id = 1
+                allowlist = ["{:#x}"]
+                max_payload_size = 4
+                fee_multiplier = 300
+                "#,
+                key(0).address()
+            ),
+        )
+        .unwrap();
+        let mut expected = policy();
+        expected.namespaces[0].fee_multiplier = Some(300);
+        assert_eq!(NamespacePolicy::from_file(&path).unwrap(), expected);
+
+        std::fs::write(
+            &path,
+            "[[namespace]]\nid = 1\n[[namespace]]\nid = 1\nmax_payload_size = 4\n",
+        )
+        .unwrap();
+        NamespacePolicy::from_file(&path).unwrap_err();
+    }
+}
@@ -2,9 +2,18 @@ use hotshot_types::message::Message;
 
 use super::*;
 
+#[cfg(feature = "libp2p")]
+pub mod hybrid;
+pub mod path;
+
 pub trait Type: 'static {
     type DAChannel: ConnectedNetwork<Message<SeqTypes>, PubKey>;
     type QuorumChannel: ConnectedNetwork<Message<SeqTypes>, PubKey>;
+
+    /// Whether messages are relayed through the CDN.
+    const CDN: bool;
+    /// Whether nodes also connect to each other directly over libp2p.
+    const LIBP2P: bool;
 }
 
 #[derive(Clone, Copy, Default)]
@@ -12,14 +21,20 @@ pub struct Production;
 
 #[cfg(feature = "libp2p")]
 impl Type for Production {
-    type DAChannel = CombinedNetworks<SeqTypes>;
-    type QuorumChannel = CombinedNetworks<SeqTypes>;
+    type DAChannel = hybrid::HybridNetwork;
+    type QuorumChannel = hybrid::HybridNetwork;
+
+    const CDN: bool = true;
+    const LIBP2P: bool = true;
 }
 
 #[cfg(not(feature = "libp2p"))]
 impl Type for Production {
     type DAChannel = PushCdnNetwork<SeqTypes>;
     type QuorumChannel = PushCdnNetwork<SeqTypes>;
+
+    const CDN: bool = true;
+    const LIBP2P: bool = false;
 }
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -28,6 +43,9 @@ pub struct Memory;
 impl Type for Memory {
     type DAChannel = MemoryNetwork<Message<SeqTypes>, PubKey>;
     type QuorumChannel = MemoryNetwork<Message<SeqTypes>, PubKey>;
+
+    const CDN: bool = false;
+    const LIBP2P: bool = false;
 }
 
 /// Trait implementations for the CDN
@@ -0,0 +1,316 @@
+//! A network which sends each message over the CDN or libp2p, whichever is healthier.
+//!
+//! Unlike HotShot's `CombinedNetworks`, which always prefers the CDN and only falls back to libp2p
+//! once the CDN has been failing for a while, [`HybridNetwork`] chooses the path separately for each
+//! peer, using a [`PathSelector`] fed by the outcome of every send and the path every message
+//! arrives on. Messages are received from both paths, with duplicates discarded.
+
+use super::path::{MessageKind, Path, PathMetrics, PathSelector};
+use crate::{PubKey, SeqTypes};
+use async_compatibility_layer::channel::UnboundedSendError;
+use async_trait::async_trait;
+use futures::future::{join, select, Either, Future};
+use hotshot::traits::implementations::{Libp2pNetwork, PushCdnNetwork};
+use hotshot_types::{
+    boxed_sync,
+    data::ViewNumber,
+    message::Message,
+    traits::{
+        metrics::Metrics,
+        network::{ConnectedNetwork, NetworkError},
+    },
+    BoxSyncFuture,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use vbs::version::StaticVersionType;
+
+/// Number of recently received messages remembered, to discard copies arriving over both paths.
+const SEEN_MESSAGES: usize = 1000;
+
+#[derive(Clone)]
+pub struct HybridNetwork {
+    cdn: PushCdnNetwork<SeqTypes>,
+    libp2p: Libp2pNetwork<Message<SeqTypes>, PubKey>,
+    selector: Arc<Mutex<PathSelector<PubKey>>>,
+    seen: Arc<Mutex<SeenMessages>>,
+    metrics: Arc<PathMetrics>,
+}
+
+impl HybridNetwork {
+    pub fn new(
+        cdn: PushCdnNetwork<SeqTypes>,
+        libp2p: Libp2pNetwork<Message<SeqTypes>, PubKey>,
+        metrics: &dyn Metrics,
+    ) -> Self {
+        Self {
+            cdn,
+            libp2p,
+            selector: Default::default(),
+            seen: Default::default(),
+            metrics: Arc::new(PathMetrics::new(metrics)),
+        }
+    }
+
+    /// Send a message over each of `paths`, falling back to the other path if all of them fail.
+    ///
+    /// `peer` is the recipient of a direct message, or [`None`] for a broadcast.
+    async fn send<F, Fut>(
+        &self,
+        paths: Vec<Path>,
+        kind: MessageKind,
+        peer: Option<&PubKey>,
+        send: F,
+    ) -> Result<(), NetworkError>
+    where
+        F: Fn(Path) -> Fut,
+        Fut: Future<Output = Result<(), NetworkError>>,
+    {
+        let mut delivered = false;
+        let mut error = None;
+        for path in &paths {
+            match send(*path).await {
+                Ok(()) => {
+                    self.record(*path, kind, peer, true);
+                    delivered = true;
+                }
+                Err(err) => {
+                    tracing::debug!(%path, ?kind, "failed to send message: {err}");
+                    self.record(*path, kind, peer, false);
+                    error = Some(err);
+                }
+            }
+        }
+
+        if !delivered && paths.len() == 1 {
+            let path = paths[0].other();
+            tracing::info!(%path, ?kind, "sending message over fallback path");
+            self.metrics.fallback();
+            match send(path).await {
+                Ok(()) => {
+                    self.record(path, kind, peer, true);
+                    delivered = true;
+                }
+                Err(err) => {
+                    self.record(path, kind, peer, false);
+                    error = Some(err);
+                }
+            }
+        }
+
+        match error {
+            Some(err) if !delivered => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn record(&self, path: Path, kind: MessageKind, peer: Option<&PubKey>, success: bool) {
+        let mut selector = self.selector.lock().unwrap();
+        match peer {
+            Some(peer) => selector.sent(peer, path, success),
+            None => selector.broadcast(path, success),
+        }
+        self.metrics.sent(path, kind, success);
+        self.metrics.update_peers(&selector, Instant::now());
+    }
+
+    /// Record where `messages` came from and drop those already received over the other path.
+    fn received(&self, path: Path, messages: Vec<Message<SeqTypes>>) -> Vec<Message<SeqTypes>> {
+        let now = Instant::now();
+        let mut selector = self.selector.lock().unwrap();
+        let mut seen = self.seen.lock().unwrap();
+        messages
+            .into_iter()
+            .filter(|message| {
+                selector.received(&message.sender, path, now);
+                seen.insert(message)
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ConnectedNetwork<Message<SeqTypes>, PubKey> for HybridNetwork {
+    fn pause(&self) {
+        self.cdn.pause();
+        self.libp2p.pause();
+    }
+
+    fn resume(&self) {
+        self.cdn.resume();
+        self.libp2p.resume();
+    }
+
+    /// Wait until either path is ready, so that the node can start while the CDN is down.
+    async fn wait_for_ready(&self) {
+        select(
+            Box::pin(self.cdn.wait_for_ready()),
+            Box::pin(self.libp2p.wait_for_ready()),
+        )
+        .await;
+    }
+
+    async fn is_ready(&self) -> bool {
+        self.cdn.is_ready().await || self.libp2p.is_ready().await
+    }
+
+    fn shut_down<'a, 'b>(&'a self) -> BoxSyncFuture<'b, ()>
+    where
+        'a: 'b,
+        Self: 'b,
+    {
+        boxed_sync(async move {
+            join(self.cdn.shut_down(), self.libp2p.shut_down()).await;
+        })
+    }
+
+    async fn broadcast_message<VER: StaticVersionType + 'static>(
+        &self,
+        message: Message<SeqTypes>,
+        recipients: BTreeSet<PubKey>,
+        bind_version: VER,
+    ) -> Result<(), NetworkError> {
+        let paths = self
+            .selector
+            .lock()
+            .unwrap()
+            .route_broadcast(&recipients, Instant::now());
+        self.send(paths, MessageKind::Broadcast, None, |path| {
+            let message = message.clone();
+            let recipients = recipients.clone();
+            async move {
+                match path {
+                    Path::Cdn => {
+                        self.cdn
+                            .broadcast_message(message, recipients, bind_version)
+                            .await
+                    }
+                    Path::Libp2p => {
+                        self.libp2p
+                            .broadcast_message(message, recipients, bind_version)
+                            .await
+                    }
+                }
+            }
+        })
+        .await
+    }
+
+    async fn da_broadcast_message<VER: StaticVersionType + 'static>(
+        &self,
+        message: Message<SeqTypes>,
+        recipients: BTreeSet<PubKey>,
+        bind_version: VER,
+    ) -> Result<(), NetworkError> {
+        let paths = self
+            .selector
+            .lock()
+            .unwrap()
+            .route_broadcast(&recipients, Instant::now());
+        self.send(paths, MessageKind::DaBroadcast, None, |path| {
+            let message = message.clone();
+            let recipients = recipients.clone();
+            async move {
+                match path {
+                    Path::Cdn => {
+                        self.cdn
+                            .da_broadcast_message(message, recipients, bind_version)
+                            .await
+                    }
+                    Path::Libp2p => {
+                        self.libp2p
+                            .da_broadcast_message(message, recipients, bind_version)
+                            .await
+                    }
+                }
+            }
+        })
+        .await
+    }
+
+    async fn direct_message<VER: StaticVersionType + 'static>(
+        &self,
+        message: Message<SeqTypes>,
+        recipient: PubKey,
+        bind_version: VER,
+    ) -> Result<(), NetworkError> {
+        let path = self
+            .selector
+            .lock()
+            .unwrap()
+            .route(&recipient, Instant::now());
+        self.send(vec![path], MessageKind::Direct, Some(&recipient), |path| {
+            let message = message.clone();
+            async move {
+                match path {
+                    Path::Cdn => {
+                        self.cdn
+                            .direct_message(message, recipient, bind_version)
+                            .await
+                    }
+                    Path::Libp2p => {
+                        self.libp2p
+                            .direct_message(message, recipient, bind_version)
+                            .await
+                    }
+                }
+            }
+        })
+        .await
+    }
+
+    async fn recv_msgs(&self) -> Result<Vec<Message<SeqTypes>>, NetworkError> {
+        let (path, messages) = match select(
+            Box::pin(self.cdn.recv_msgs()),
+            Box::pin(self.libp2p.recv_msgs()),
+        )
+        .await
+        {
+            Either::Left((messages, _)) => (Path::Cdn, messages?),
+            Either::Right((messages, _)) => (Path::Libp2p, messages?),
+        };
+        Ok(self.received(path, messages))
+    }
+
+    async fn queue_node_lookup(
+        &self,
+        view_number: ViewNumber,
+        pk: PubKey,
+    ) -> Result<(), UnboundedSendError<Option<(ViewNumber, PubKey)>>> {
+        self.libp2p.queue_node_lookup(view_number, pk).await
+    }
+}
+
+/// Hashes of recently received messages.
+#[derive(Debug, Default)]
+struct SeenMessages {
+    hashes: HashSet<u64>,
+    order: VecDeque<u64>,
+}
+
+impl SeenMessages {
+    /// Remember `message`, returning whether it is new.
+    fn insert(&mut self, message: &Message<SeqTypes>) -> bool {
+        let Ok(bytes) = bincode::serialize(message) else {
+            return true;
+        };
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if !self.hashes.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > SEEN_MESSAGES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,343 @@
+//! Health of the network paths to each peer, and the choice of path for each message.
+//!
+//! A node built with libp2p can reach each peer two ways: through the CDN, or directly over the
+//! libp2p mesh. The [`PathSelector`] tracks the health of both paths to each peer from the traffic
+//! consensus already generates. For each peer and path, it records when a message from the peer last
+//! arrived over that path, and how many sends to the peer over that path have failed in a row.
+//!
+//! A path is unhealthy for a peer if sends over it keep failing, or if the peer is still heard from
+//! over the other path but has gone quiet on this one. Messages to a peer go through the CDN unless
+//! it is unhealthy for that peer and libp2p is not, so a CDN outage moves traffic onto libp2p without
+//! any reconfiguration. Traffic returns to the CDN as soon as messages arrive over it again.
+
+use hotshot_types::traits::metrics::{Counter, Gauge, Metrics};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// How long a path may go without delivering a message from a peer, while the other path still
+/// delivers messages from that peer, before it is considered unhealthy for the peer.
+pub const STALE_AFTER: Duration = Duration::from_secs(10);
+
+/// Number of sends in a row which may fail over a path before it is considered unhealthy.
+pub const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+
+/// A way of reaching peers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Path {
+    Cdn,
+    Libp2p,
+}
+
+impl Path {
+    pub const ALL: [Self; 2] = [Self::Cdn, Self::Libp2p];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cdn => "cdn",
+            Self::Libp2p => "libp2p",
+        }
+    }
+
+    pub fn other(&self) -> Self {
+        match self {
+            Self::Cdn => Self::Libp2p,
+            Self::Libp2p => Self::Cdn,
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Self::Cdn => 0,
+            Self::Libp2p => 1,
+        }
+    }
+}
+
+impl Display for Path {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The ways messages are sent, for counting the messages each path carries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    Broadcast,
+    DaBroadcast,
+    Direct,
+}
+
+impl MessageKind {
+    pub const ALL: [Self; 3] = [Self::Broadcast, Self::DaBroadcast, Self::Direct];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Broadcast => "broadcast",
+            Self::DaBroadcast => "da_broadcast",
+            Self::Direct => "direct",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct PathHealth {
+    last_received: Option<Instant>,
+    consecutive_failures: u32,
+}
+
+impl PathHealth {
+    fn received_since(&self, since: Option<Instant>) -> bool {
+        match (self.last_received, since) {
+            (Some(received), Some(since)) => received >= since,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+/// Chooses the path to each peer from the recent health of both paths.
+#[derive(Debug)]
+pub struct PathSelector<K> {
+    peers: HashMap<K, [PathHealth; 2]>,
+    /// The health of each path for broadcasts, which are not addressed to a single peer.
+    broadcast: [PathHealth; 2],
+    stale_after: Duration,
+    max_failures: u32,
+}
+
+impl<K: Clone + Eq + Hash> Default for PathSelector<K> {
+    fn default() -> Self {
+        Self::new(STALE_AFTER, MAX_CONSECUTIVE_FAILURES)
+    }
+}
+
+impl<K: Clone + Eq + Hash> PathSelector<K> {
+    pub fn new(stale_after: Duration, max_failures: u32) -> Self {
+        Self {
+            peers: Default::default(),
+            broadcast: Default::default(),
+            stale_after,
+            max_failures,
+        }
+    }
+
+    /// Record a message from `peer` arriving over `path`.
+    pub fn received(&mut self, peer: &K, path: Path, now: Instant) {
+        let health = &mut self.peers.entry(peer.clone()).or_default()[path.index()];
+        health.last_received = Some(now);
+        health.consecutive_failures = 0;
+        // A path which delivers messages is working for broadcasts, too.
+        self.broadcast[path.index()].consecutive_failures = 0;
+    }
+
+    /// Record the outcome of sending a message to `peer` over `path`.
+    pub fn sent(&mut self, peer: &K, path: Path, success: bool) {
+        let health = &mut self.peers.entry(peer.clone()).or_default()[path.index()];
+        record(health, success);
+    }
+
+    /// Record the outcome of broadcasting a message over `path`.
+    pub fn broadcast(&mut self, path: Path, success: bool) {
+        record(&mut self.broadcast[path.index()], success);
+    }
+
+    /// Whether `path` is healthy for `peer`.
+    pub fn is_healthy(&self, peer: &K, path: Path, now: Instant) -> bool {
+        let Some(health) = self.peers.get(peer) else {
+            return true;
+        };
+        let this = &health[path.index()];
+        let other = &health[path.other().index()];
+        if this.consecutive_failures >= self.max_failures {
+            return false;
+        }
+        // If the peer has gone quiet on this path while still talking over the other one, this
+        // path is not delivering its messages.
+        let since = now.checked_sub(self.stale_after);
+        !(other.received_since(since) && !this.received_since(since))
+    }
+
+    /// The path to send messages to `peer` over.
+    pub fn route(&self, peer: &K, now: Instant) -> Path {
+        if !self.is_healthy(peer, Path::Cdn, now) && self.is_healthy(peer, Path::Libp2p, now) {
+            Path::Libp2p
+        } else {
+            Path::Cdn
+        }
+    }
+
+    /// The paths to broadcast a message to `recipients` over.
+    ///
+    /// This is the CDN, unless broadcasts over the CDN are failing, along with libp2p if any
+    /// recipient is better reached over libp2p. Receivers discard the duplicates.
+    pub fn route_broadcast<'a>(
+        &self,
+        recipients: impl IntoIterator<Item = &'a K>,
+        now: Instant,
+    ) -> Vec<Path>
+    where
+        K: 'a,
+    {
+        if self.broadcast[Path::Cdn.index()].consecutive_failures >= self.max_failures {
+            return vec![Path::Libp2p];
+        }
+        if recipients
+            .into_iter()
+            .any(|peer| self.route(peer, now) == Path::Libp2p)
+        {
+            vec![Path::Cdn, Path::Libp2p]
+        } else {
+            vec![Path::Cdn]
+        }
+    }
+
+    /// Number of known peers currently reached over `path`.
+    pub fn peers_on(&self, path: Path, now: Instant) -> usize {
+        self.peers
+            .keys()
+            .filter(|peer| self.route(peer, now) == path)
+            .count()
+    }
+}
+
+fn record(health: &mut PathHealth, success: bool) {
+    if success {
+        health.consecutive_failures = 0;
+    } else {
+        health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+    }
+}
+
+/// Metrics on which path carries each kind of message.
+#[derive(Debug)]
+pub struct PathMetrics {
+    messages: HashMap<(Path, MessageKind), Box<dyn Counter>>,
+    failures: HashMap<Path, Box<dyn Counter>>,
+    /// Messages which were sent over the other path after the chosen one failed.
+    fallbacks: Box<dyn Counter>,
+    peers: HashMap<Path, Box<dyn Gauge>>,
+}
+
+impl PathMetrics {
+    pub fn new(metrics: &dyn Metrics) -> Self {
+        let metrics = metrics.subgroup("network_paths".into());
+        Self {
+            messages: Path::ALL
+                .into_iter()
+                .flat_map(|path| MessageKind::ALL.map(|kind| (path, kind)))
+                .map(|(path, kind)| {
+                    let name = format!("{}_{}_messages", path.name(), kind.name());
+                    ((path, kind), metrics.create_counter(name, None))
+                })
+                .collect(),
+            failures: Path::ALL
+                .into_iter()
+                .map(|path| {
+                    let name = format!("{}_send_failures", path.name());
+                    (path, metrics.create_counter(name, None))
+                })
+                .collect(),
+            fallbacks: metrics.create_counter("fallback_sends".into(), None),
+            peers: Path::ALL
+                .into_iter()
+                .map(|path| {
+                    let name = format!("{}_peers", path.name());
+                    (path, metrics.create_gauge(name, None))
+                })
+                .collect(),
+        }
+    }
+
+    /// Count a message sent over `path`.
+    pub fn sent(&self, path: Path, kind: MessageKind, success: bool) {
+        if success {
+            self.messages[&(path, kind)].add(1);
+        } else {
+            self.failures[&path].add(1);
+        }
+    }
+
+    pub fn fallback(&self) {
+        self.fallbacks.add(1);
+    }
+
+    pub fn update_peers<K: Clone + Eq + Hash>(&self, selector: &PathSelector<K>, now: Instant) {
+        for path in Path::ALL {
+            self.peers[&path].set(selector.peers_on(path, now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_route_on_failures() {
+        let mut selector = PathSelector::new(Duration::from_secs(10), 2);
+        let now = Instant::now();
+
+        // Unknown peers are reached over the CDN.
+        assert_eq!(selector.route(&0, now), Path::Cdn);
+
+        // After repeated failures, traffic moves to libp2p.
+        selector.sent(&0, Path::Cdn, false);
+        assert_eq!(selector.route(&0, now), Path::Cdn);
+        selector.sent(&0, Path::Cdn, false);
+        assert_eq!(selector.route(&0, now), Path::Libp2p);
+        assert_eq!(selector.route(&1, now), Path::Cdn);
+        assert_eq!(selector.peers_on(Path::Libp2p, now), 1);
+
+        // If both paths are failing, the CDN is still preferred.
+        selector.sent(&0, Path::Libp2p, false);
+        selector.sent(&0, Path::Libp2p, false);
+        assert_eq!(selector.route(&0, now), Path::Cdn);
+
+        // A message over the CDN shows it has recovered.
+        selector.received(&0, Path::Cdn, now);
+        assert_eq!(selector.route(&0, now), Path::Cdn);
+    }
+
+    #[test]
+    fn test_route_on_silence() {
+        let stale_after = Duration::from_secs(10);
+        let mut selector = PathSelector::new(stale_after, 2);
+        let start = Instant::now();
+        selector.received(&0, Path::Cdn, start);
+        selector.received(&0, Path::Libp2p, start);
+        assert_eq!(selector.route(&0, start), Path::Cdn);
+
+        // The peer is still heard from over libp2p, but has gone quiet on the CDN.
+        let later = start + 2 * stale_after;
+        selector.received(&0, Path::Libp2p, later);
+        assert_eq!(selector.route(&0, later), Path::Libp2p);
+
+        // A peer which is quiet on both paths has given no sign that either is broken.
+        assert_eq!(selector.route(&0, later + 2 * stale_after), Path::Cdn);
+    }
+
+    #[test]
+    fn test_route_broadcast() {
+        let mut selector = PathSelector::new(Duration::from_secs(10), 1);
+        let now = Instant::now();
+        assert_eq!(selector.route_broadcast(&[0, 1], now), [Path::Cdn]);
+
+        // One recipient is better reached over libp2p.
+        selector.sent(&1, Path::Cdn, false);
+        assert_eq!(
+            selector.route_broadcast(&[0, 1], now),
+            [Path::Cdn, Path::Libp2p]
+        );
+        assert_eq!(selector.route_broadcast(&[0], now), [Path::Cdn]);
+
+        // Broadcasts over the CDN are failing altogether.
+        selector.broadcast(Path::Cdn, false);
+        assert_eq!(selector.route_broadcast(&[0], now), [Path::Libp2p]);
+        selector.received(&0, Path::Cdn, now);
+        assert_eq!(selector.route_broadcast(&[0], now), [Path::Cdn]);
+    }
+}
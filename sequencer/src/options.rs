@@ -1,4 +1,8 @@
-use crate::{api, persistence};
+use crate::{
+    api, external_da,
+    keystore::{self, Keystore},
+    logging, persistence,
+};
 use anyhow::{bail, Context};
 use bytesize::ByteSize;
 use clap::{error::ErrorKind, Args, FromArgMatches, Parser};
@@ -9,13 +13,7 @@ use hotshot_stake_table::config::STAKE_TABLE_CAPACITY;
 use hotshot_types::light_client::StateSignKey;
 use hotshot_types::signature_key::BLSPrivKey;
 use snafu::Snafu;
-use std::{
-    collections::{HashMap, HashSet},
-    iter::once,
-    path::PathBuf,
-    str::FromStr,
-    time::Duration,
-};
+use std::{collections::HashSet, iter::once, path::PathBuf, str::FromStr, time::Duration};
 use url::Url;
 
 // This options struct is a bit unconventional. The sequencer has multiple optional modules which
@@ -106,7 +104,7 @@ pub struct Options {
     #[clap(
         long,
         env = "ESPRESSO_SEQUENCER_PRIVATE_STAKING_KEY",
-        conflicts_with = "key_file"
+        conflicts_with_all = ["key_file", "keystore"]
     )]
     pub private_staking_key: Option<BLSPrivKey>,
 
@@ -116,10 +114,27 @@ pub struct Options {
     #[clap(
         long,
         env = "ESPRESSO_SEQUENCER_PRIVATE_STATE_KEY",
-        conflicts_with = "key_file"
+        conflicts_with_all = ["key_file", "keystore"]
     )]
     pub private_state_key: Option<StateSignKey>,
 
+    /// Directory of encrypted private keys.
+    ///
+    /// This can be used as an alternative to KEY_FILE, keeping the private keys encrypted at rest.
+    /// Keystores are managed with the `keystore` utility program. While the node is running, it
+    /// picks up a new state key from the keystore within a few seconds of `keystore rotate`.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_KEYSTORE",
+        conflicts_with = "key_file",
+        requires = "keystore_password_file"
+    )]
+    pub keystore: Option<PathBuf>,
+
+    /// File containing the password for the keystore.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_KEYSTORE_PASSWORD_FILE")]
+    pub keystore_password_file: Option<PathBuf>,
+
     /// Add optional modules to the service.
     ///
     /// Modules are added by specifying the name of the module followed by it's arguments, as in
@@ -146,24 +161,95 @@ pub struct Options {
     )]
     pub prefunded_builder_accounts: Vec<Address>,
 
-    /// Url we will use for RPC communication with L1.
-    #[clap(long, env = "ESPRESSO_SEQUENCER_L1_PROVIDER")]
-    pub l1_provider_url: Url,
+    /// Urls we will use for RPC communication with L1.
+    ///
+    /// Comma-separated list, in order of priority. If a provider fails, requests fail over to the
+    /// next one.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_L1_PROVIDER",
+        value_delimiter = ',',
+        required = true
+    )]
+    pub l1_provider_url: Vec<Url>,
+
+    /// Maximum number of requests per second to send to each L1 provider.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_L1_RATE_LIMIT")]
+    pub l1_rate_limit: Option<u64>,
+
+    /// Address of the L1 contract scheduling upgrades of the chain config, if any.
+    ///
+    /// Upgrades scheduled by the `UpgradeGovernance` contract at this address take effect at their
+    /// activation height, once the L1 block scheduling them is finalized. The address is part of
+    /// the chain config, so every node must use the same contract.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_UPGRADE_CONTRACT_ADDRESS")]
+    pub upgrade_contract_address: Option<Address>,
+
+    /// L1 block to start searching for upgrades from, such as the block the upgrade contract was
+    /// deployed in.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_UPGRADE_CONTRACT_START_BLOCK",
+        default_value = "0"
+    )]
+    pub upgrade_contract_start_block: u64,
 
     /// Peer nodes use to fetch missing state
     #[clap(long, env = "ESPRESSO_SEQUENCER_STATE_PEERS", value_delimiter = ',')]
     pub state_peers: Vec<Url>,
 
+    /// Peer node to bootstrap from a snapshot of its decided state, instead of starting from
+    /// genesis.
+    ///
+    /// The snapshot is only used when this node has no saved consensus state. The peer must serve
+    /// the catchup API.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_SNAPSHOT_URL")]
+    pub snapshot_url: Option<Url>,
+
     /// Stake table capacity for the prover circuit
     #[clap(short, long, env = "ESPRESSO_SEQUENCER_STAKE_TABLE_CAPACITY", default_value_t = STAKE_TABLE_CAPACITY)]
     pub stake_table_capacity: usize,
     /// Maximum size in bytes of a block
-    #[clap(long, env = "ESPRESSO_SEQUENCER_MAX_BLOCK_SIZE", value_parser = parse_size)]
-    pub max_block_size: u64,
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_MAX_BLOCK_SIZE",
+        value_parser = parse_size,
+        required_unless_present = "genesis_file"
+    )]
+    pub max_block_size: Option<u64>,
 
-    #[clap(long, env = "ESPRESSO_SEQUENCER_BASE_FEE")]
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_BASE_FEE",
+        required_unless_present = "genesis_file"
+    )]
     /// Minimum fee in WEI per byte of payload
-    pub base_fee: U256,
+    pub base_fee: Option<U256>,
+
+    /// Path to a TOML file restricting the use of namespaces.
+    ///
+    /// The policy can reserve namespaces, limit the payload size of a namespace in each block,
+    /// restrict a namespace to an allowlist of submitters, and scale the base fee per namespace. It
+    /// is part of the chain config, so every node must use the same policy.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_NAMESPACE_POLICY")]
+    pub namespace_policy: Option<PathBuf>,
+
+    /// Path to a genesis file, in TOML or JSON, describing the initial state of a new chain.
+    ///
+    /// The genesis file sets the chain ID, maximum block size and base fee, which take precedence
+    /// over the corresponding options, along with the initial balances of fee accounts. Its commitment is part of the chain config, so every node must use the same file.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_GENESIS_FILE")]
+    pub genesis_file: Option<PathBuf>,
+
+    /// Path to a TOML file of settings to apply while the node is running.
+    ///
+    /// The file can set the `log_filter`, the `state_peers` and a `[rate_limit]` table of API rate
+    /// limits. It is applied at startup, and again whenever the node receives SIGHUP.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_RELOAD_FILE")]
+    pub reload_file: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub logging: logging::Config,
 }
 
 impl Options {
@@ -172,26 +258,38 @@ impl Options {
     }
 
     pub fn private_keys(&self) -> anyhow::Result<(BLSPrivKey, StateSignKey)> {
-        if let Some(path) = &self.key_file {
-            let vars = dotenvy::from_path_iter(path)?.collect::<Result<HashMap<_, _>, _>>()?;
-            let staking = vars
-                .get("ESPRESSO_SEQUENCER_PRIVATE_STAKING_KEY")
-                .context("key file missing ESPRESSO_SEQUENCER_PRIVATE_STAKING_KEY")?
-                .parse()?;
-            let state = vars
-                .get("ESPRESSO_SEQUENCER_PRIVATE_STATE_KEY")
-                .context("key file missing ESPRESSO_SEQUENCER_PRIVATE_STATE_KEY")?
-                .parse()?;
-            Ok((staking, state))
+        if let Some((keystore, password)) = self.keystore()? {
+            Ok((
+                keystore.staking_key(&password)?,
+                keystore.state_key(&password)?,
+            ))
+        } else if let Some(path) = &self.key_file {
+            keystore::read_key_file(path)
         } else if let (Some(staking), Some(state)) = (
             self.private_staking_key.clone(),
             self.private_state_key.clone(),
         ) {
             Ok((staking, state))
         } else {
-            bail!("neither key file nor full set of private keys was provided")
+            bail!("neither keystore, key file nor full set of private keys was provided")
         }
     }
+
+    /// The keystore holding the private keys, if any, and its password.
+    pub fn keystore(&self) -> anyhow::Result<Option<(Keystore, String)>> {
+        let Some(dir) = &self.keystore else {
+            return Ok(None);
+        };
+        // Clap ensures the password file is present with a keystore.
+        let password_file = self
+            .keystore_password_file
+            .as_ref()
+            .context("missing keystore password file")?;
+        Ok(Some((
+            Keystore::open(dir)?,
+            keystore::read_password(password_file)?,
+        )))
+    }
 }
 
 #[derive(Clone, Debug, Snafu)]
@@ -255,9 +353,26 @@ impl ModuleArgs {
                 SequencerModule::Status(m) => curr = m.add(&mut modules.status, &mut provided)?,
                 SequencerModule::State(m) => curr = m.add(&mut modules.state, &mut provided)?,
                 SequencerModule::Catchup(m) => curr = m.add(&mut modules.catchup, &mut provided)?,
+                SequencerModule::FeeDeposits(m) => {
+                    curr = m.add(&mut modules.fee_deposits, &mut provided)?
+                }
+                SequencerModule::LightClient(m) => {
+                    curr = m.add(&mut modules.light_client, &mut provided)?
+                }
+                SequencerModule::Preconfirmation(m) => {
+                    curr = m.add(&mut modules.preconfirmation, &mut provided)?
+                }
+                SequencerModule::RateLimit(m) => {
+                    curr = m.add(&mut modules.rate_limit, &mut provided)?
+                }
+                #[cfg(feature = "grpc")]
+                SequencerModule::Grpc(m) => curr = m.add(&mut modules.grpc, &mut provided)?,
                 SequencerModule::HotshotEvents(m) => {
                     curr = m.add(&mut modules.hotshot_events, &mut provided)?
                 }
+                SequencerModule::ExternalDa(m) => {
+                    curr = m.add(&mut modules.external_da, &mut provided)?
+                }
             }
         }
 
@@ -290,7 +405,14 @@ module!("submit", api::options::Submit, requires: "http");
 module!("status", api::options::Status, requires: "http");
 module!("state", api::options::State, requires: "http", "storage-sql");
 module!("catchup", api::options::Catchup, requires: "http");
+module!("fee-deposits", api::options::FeeDeposits, requires: "http", "storage-sql");
+module!("light-client", api::options::LightClient, requires: "http", "query", "state");
+module!("preconfirmation", api::options::Preconfirmation, requires: "http", "query", "submit");
+module!("rate-limit", api::options::RateLimit, requires: "http");
 module!("hotshot-events", api::options::HotshotEvents, requires: "http");
+#[cfg(feature = "grpc")]
+module!("grpc", api::options::Grpc, requires: "http", "query");
+module!("external-da", external_da::Options);
 
 #[derive(Clone, Debug, Args)]
 struct Module<Options: ModuleInfo> {
@@ -364,10 +486,33 @@ enum SequencerModule {
     ///
     /// This module requires the http and storage-sql modules to be started.
     State(Module<api::options::State>),
+    /// Index fee deposits on the L1 and run the fee deposits API module.
+    ///
+    /// This module requires the http, storage-sql and query modules to be started.
+    FeeDeposits(Module<api::options::FeeDeposits>),
+    /// Prove headers against the state finalized by the light client contract.
+    ///
+    /// This module requires the http, query and state modules to be started.
+    LightClient(Module<api::options::LightClient>),
+    /// Sign promises to include submitted transactions, and serve evidence of broken promises.
+    ///
+    /// This module requires the http, query and submit modules to be started.
+    Preconfirmation(Module<api::options::Preconfirmation>),
+    /// Limit the rate of requests to the HTTP API, per IP address or API key.
+    ///
+    /// This module requires the http module to be started.
+    RateLimit(Module<api::options::RateLimit>),
     /// Run the hotshot events API module.
     ///
     /// This module requires the http module to be started.
     HotshotEvents(Module<api::options::HotshotEvents>),
+    /// Run a gRPC server mirroring the submit, availability and status APIs.
+    ///
+    /// This module requires the http and query modules to be started.
+    #[cfg(feature = "grpc")]
+    Grpc(Module<api::options::Grpc>),
+    /// Mirror decided namespace payloads to an external DA layer.
+    ExternalDa(Module<external_da::Options>),
 }
 
 #[derive(Clone, Debug, Default)]
@@ -380,5 +525,12 @@ pub struct Modules {
     pub status: Option<api::options::Status>,
     pub state: Option<api::options::State>,
     pub catchup: Option<api::options::Catchup>,
+    pub fee_deposits: Option<api::options::FeeDeposits>,
+    pub light_client: Option<api::options::LightClient>,
+    pub preconfirmation: Option<api::options::Preconfirmation>,
+    pub rate_limit: Option<api::options::RateLimit>,
     pub hotshot_events: Option<api::options::HotshotEvents>,
+    #[cfg(feature = "grpc")]
+    pub grpc: Option<api::options::Grpc>,
+    pub external_da: Option<external_da::Options>,
 }
@@ -9,7 +9,8 @@
 //! persistence which is _required_ to run a node.
 
 use crate::{
-    ElectionConfig, Header, Leaf, NodeState, PubKey, SeqTypes, ValidatedState, ViewNumber,
+    equivocation::Equivocation, ElectionConfig, Header, Leaf, NodeState, PubKey, SeqTypes,
+    ValidatedState, ViewNumber,
 };
 use anyhow::{ensure, Context};
 use async_std::sync::Arc;
@@ -204,6 +205,15 @@ pub trait SequencerPersistence: Send + Sync + 'static {
         view: ViewNumber,
         action: HotShotAction,
     ) -> anyhow::Result<()>;
+
+    /// Save evidence of double voting.
+    ///
+    /// Only the first evidence for each view is kept. Evidence is never garbage collected.
+    async fn append_equivocation(&mut self, evidence: &Equivocation) -> anyhow::Result<()>;
+
+    /// Load all evidence saved with [`append_equivocation`](Self::append_equivocation), in order of
+    /// view.
+    async fn load_equivocations(&self) -> anyhow::Result<Vec<Equivocation>>;
 }
 
 #[cfg(test)]
@@ -453,4 +463,44 @@ mod persistence_tests {
             Some(vid_share3)
         );
     }
+
+    #[async_std::test]
+    pub async fn test_equivocations<P: TestablePersistence>() {
+        setup_logging();
+        setup_backtrace();
+
+        let tmp = P::tmp_storage().await;
+        let mut storage = P::connect(&tmp).await;
+        assert_eq!(storage.load_equivocations().await.unwrap(), vec![]);
+
+        let evidence = |view: u64, height: u64| {
+            let mut leaf = Leaf::genesis(&NodeState::mock());
+            leaf.get_block_header_mut().height = height;
+            let first = QuorumCertificate::genesis(&NodeState::mock());
+            let mut second = first.clone();
+            second.data.leaf_commit = leaf.commit();
+            second.vote_commitment = second.data.commit();
+            Equivocation {
+                view,
+                qcs: [first, second],
+                signers: vec![BLSPubKey::generated_from_seed_indexed([0; 32], 0).0],
+            }
+        };
+
+        // Evidence is loaded in order of view.
+        storage.append_equivocation(&evidence(2, 1)).await.unwrap();
+        storage.append_equivocation(&evidence(1, 1)).await.unwrap();
+        assert_eq!(
+            storage.load_equivocations().await.unwrap(),
+            vec![evidence(1, 1), evidence(2, 1)]
+        );
+
+        // The first evidence for a view is kept, and garbage collection does not remove it.
+        storage.append_equivocation(&evidence(1, 2)).await.unwrap();
+        storage.collect_garbage(ViewNumber::new(2)).await.unwrap();
+        assert_eq!(
+            storage.load_equivocations().await.unwrap(),
+            vec![evidence(1, 1), evidence(2, 1)]
+        );
+    }
 }
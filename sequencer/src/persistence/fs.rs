@@ -1,5 +1,5 @@
 use super::{NetworkConfig, PersistenceOptions, SequencerPersistence};
-use crate::{Header, Leaf, SeqTypes, ValidatedState, ViewNumber};
+use crate::{equivocation::Equivocation, Header, Leaf, SeqTypes, ValidatedState, ViewNumber};
 use anyhow::{anyhow, bail, Context};
 use async_trait::async_trait;
 use clap::Parser;
@@ -70,6 +70,10 @@ impl Persistence {
         self.0.join("da")
     }
 
+    fn equivocation_dir_path(&self) -> PathBuf {
+        self.0.join("equivocation")
+    }
+
     /// Overwrite a file if a condition is met.
     ///
     /// The file at `path`, if it exists, is opened in read mode and passed to `pred`. If `pred`
@@ -353,6 +357,45 @@ impl SequencerPersistence for Persistence {
     async fn load_validated_state(&self, _header: &Header) -> anyhow::Result<ValidatedState> {
         bail!("state persistence not implemented");
     }
+
+    async fn append_equivocation(&mut self, evidence: &Equivocation) -> anyhow::Result<()> {
+        let dir_path = self.equivocation_dir_path();
+        fs::create_dir_all(dir_path.clone()).context("failed to create equivocation dir")?;
+
+        let file_path = dir_path
+            .join(evidence.view.to_string())
+            .with_extension("txt");
+        self.replace(
+            &file_path,
+            |_| Ok(false),
+            |mut file| {
+                let bytes = bincode::serialize(evidence).context("serialize evidence")?;
+                file.write_all(&bytes)?;
+                Ok(())
+            },
+        )
+    }
+
+    async fn load_equivocations(&self) -> anyhow::Result<Vec<Equivocation>> {
+        let dir_path = self.equivocation_dir_path();
+        if !dir_path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut evidence = vec![];
+        for entry in fs::read_dir(dir_path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+                continue;
+            }
+            let bytes = fs::read(&path)?;
+            let parsed: Equivocation = bincode::deserialize(&bytes)
+                .with_context(|| format!("malformed evidence in {}", path.display()))?;
+            evidence.push(parsed);
+        }
+        evidence.sort_by_key(|evidence| evidence.view);
+        Ok(evidence)
+    }
 }
 
 #[cfg(test)]
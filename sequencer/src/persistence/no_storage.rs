@@ -2,7 +2,7 @@
 #![cfg(any(test, feature = "testing"))]
 
 use super::{NetworkConfig, PersistenceOptions, SequencerPersistence};
-use crate::{Header, Leaf, SeqTypes, ValidatedState, ViewNumber};
+use crate::{equivocation::Equivocation, Header, Leaf, SeqTypes, ValidatedState, ViewNumber};
 use anyhow::bail;
 use async_trait::async_trait;
 use hotshot_types::{
@@ -100,4 +100,12 @@ impl SequencerPersistence for NoStorage {
     async fn load_validated_state(&self, _header: &Header) -> anyhow::Result<ValidatedState> {
         bail!("state persistence not implemented");
     }
+
+    async fn append_equivocation(&mut self, _evidence: &Equivocation) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn load_equivocations(&self) -> anyhow::Result<Vec<Equivocation>> {
+        Ok(vec![])
+    }
 }
@@ -1,13 +1,18 @@
 use super::{NetworkConfig, PersistenceOptions, SequencerPersistence};
 use crate::{
+    equivocation::Equivocation,
     options::parse_duration,
-    state::{BlockMerkleTree, FeeMerkleTree},
+    state::{BlockMerkleTree, FeeAccount, FeeMerkleCommitment, FeeMerkleTree},
     Header, Leaf, SeqTypes, ValidatedState, ViewNumber,
 };
 use anyhow::{bail, Context};
+use async_std::task::sleep;
 use async_trait::async_trait;
 use clap::Parser;
-use futures::future::{BoxFuture, FutureExt};
+use futures::{
+    future::{BoxFuture, FutureExt},
+    stream::TryStreamExt,
+};
 use hotshot_query_service::{
     data_source::{
         storage::{
@@ -18,7 +23,9 @@ use hotshot_query_service::{
         },
         VersionedDataSource,
     },
-    merklized_state::{MerklizedState, MerklizedStateDataSource, Snapshot},
+    merklized_state::{
+        MerklizedState, MerklizedStateDataSource, MerklizedStateHeightPersistence, Snapshot,
+    },
 };
 use hotshot_types::{
     data::{DAProposal, VidDisperseShare},
@@ -28,8 +35,25 @@ use hotshot_types::{
     traits::node_implementation::ConsensusTime,
     vote::HasViewNumber,
 };
-use jf_primitives::merkle_tree::{ForgetableMerkleTreeScheme, MerkleTreeScheme};
-use std::time::Duration;
+use jf_primitives::merkle_tree::{
+    ForgetableMerkleTreeScheme, MerkleTreeScheme, UniversalMerkleTreeScheme,
+};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Number of fee merkle tree snapshots kept, older ones are deleted.
+const FEE_SNAPSHOTS_RETAINED: i64 = 2;
+
+/// How often to check whether a new fee merkle tree snapshot is due.
+const FEE_SNAPSHOT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often to prune merklized state.
+const MERKLIZED_STATE_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 /// Options for Postgres-backed persistence.
 #[derive(Parser, Clone, Debug, Default)]
@@ -214,7 +238,7 @@ impl PersistenceOptions for Options {
 /// Postgres-backed persistence.
 pub type Persistence = SqlStorage;
 
-async fn transaction(
+pub(crate) async fn transaction(
     db: &mut Persistence,
     f: impl FnOnce(Transaction) -> BoxFuture<anyhow::Result<()>>,
 ) -> anyhow::Result<()> {
@@ -487,23 +511,274 @@ impl SequencerPersistence for Persistence {
 
         // For the fee Merkle tree, we need the entire thing, since we never know which accounts we
         // may need to access.
-        let snapshot = Snapshot::<_, FeeMerkleTree, { FeeMerkleTree::ARITY }>::Index(height);
-        let fee_merkle_tree = self
-            .get_snapshot(snapshot)
-            .await
-            .context("loading fee merkle tree")?;
+        let fee_merkle_tree =
+            load_fee_merkle_tree(self, height, header.fee_merkle_tree_root).await?;
 
         Ok(ValidatedState {
             block_merkle_tree,
             fee_merkle_tree,
         })
     }
+
+    async fn append_equivocation(&mut self, evidence: &Equivocation) -> anyhow::Result<()> {
+        let stmt = "
+            INSERT INTO equivocation (view, data) VALUES ($1, $2)
+            ON CONFLICT (view) DO NOTHING";
+
+        let view = evidence.view as i64;
+        let data = bincode::serialize(evidence)?;
+        transaction(self, |mut tx| {
+            async move {
+                tx.execute(stmt, [sql_param(&view), sql_param(&data)])
+                    .await?;
+                Ok(())
+            }
+            .boxed()
+        })
+        .await
+    }
+
+    async fn load_equivocations(&self) -> anyhow::Result<Vec<Equivocation>> {
+        let rows = self
+            .query_static("SELECT data FROM equivocation ORDER BY view")
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let data: Vec<u8> = row.get("data");
+                Ok(bincode::deserialize(&data)?)
+            })
+            .collect()
+    }
 }
 
-fn sql_param<T: ToSql + Sync>(param: &T) -> &(dyn ToSql + Sync) {
+pub(crate) fn sql_param<T: ToSql + Sync>(param: &T) -> &(dyn ToSql + Sync) {
     param
 }
 
+/// Load the fee merkle tree at `height`, whose root is `root`.
+///
+/// If a [snapshot](snapshot_fee_merkle_tree_loop) at or below `height` exists, the tree is rebuilt
+/// from the snapshot and the accounts which changed since, which is much cheaper than loading the
+/// whole tree from the merklized state tables. Otherwise, or if the rebuilt tree does not match
+/// `root`, the whole tree is loaded.
+async fn load_fee_merkle_tree(
+    db: &Persistence,
+    height: u64,
+    root: FeeMerkleCommitment,
+) -> anyhow::Result<FeeMerkleTree> {
+    match load_fee_merkle_tree_from_snapshot(db, height).await {
+        Ok(Some(tree)) if tree.commitment() == root => return Ok(tree),
+        Ok(Some(_)) => {
+            tracing::warn!(
+                height,
+                "fee merkle tree snapshot is inconsistent, loading full tree"
+            )
+        }
+        Ok(None) => {}
+        Err(err) => tracing::warn!(
+            height,
+            "error loading fee merkle tree from snapshot, loading full tree: {err:#}"
+        ),
+    }
+    let snapshot = Snapshot::<_, FeeMerkleTree, { FeeMerkleTree::ARITY }>::Index(height);
+    db.get_snapshot(snapshot)
+        .await
+        .context("loading fee merkle tree")
+}
+
+/// Rebuild the fee merkle tree at `height` from the latest snapshot at or below `height`.
+///
+/// Returns `None` if there is no such snapshot.
+async fn load_fee_merkle_tree_from_snapshot(
+    db: &Persistence,
+    height: u64,
+) -> anyhow::Result<Option<FeeMerkleTree>> {
+    let Some(row) = db
+        .query_opt(
+            "SELECT height, data FROM fee_merkle_tree_snapshot WHERE height <= $1
+                ORDER BY height DESC LIMIT 1",
+            [height as i64],
+        )
+        .await?
+    else {
+        return Ok(None);
+    };
+    let snapshot_height: i64 = row.get("height");
+    let data: Vec<u8> = row.get("data");
+    let mut tree: FeeMerkleTree = bincode::deserialize(&data)?;
+    if snapshot_height as u64 == height {
+        return Ok(Some(tree));
+    }
+
+    // Bring the snapshot up to date with the accounts which changed after it was taken.
+    let changed = db
+        .query(
+            "SELECT DISTINCT index FROM fee_merkle_tree
+                WHERE created > $1 AND created <= $2 AND index IS NOT NULL",
+            [snapshot_height, height as i64],
+        )
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    tracing::info!(
+        snapshot_height,
+        height,
+        "updating {} accounts in fee merkle tree snapshot",
+        changed.len()
+    );
+    for row in changed {
+        let account: FeeAccount = serde_json::from_value(row.get("index"))?;
+        let snapshot = Snapshot::<_, FeeMerkleTree, { FeeMerkleTree::ARITY }>::Index(height);
+        let proof = db
+            .get_path(snapshot, account)
+            .await
+            .with_context(|| format!("loading fee account {account}"))?;
+        tree.update_with(account, |_| proof.elem().copied())?;
+    }
+    Ok(Some(tree))
+}
+
+/// Save a snapshot of the fee merkle tree every `interval` blocks of merklized state.
+///
+/// Merklized state is stored incrementally as blocks are decided, but loading the entire fee
+/// merkle tree from the node tables when a node restarts gets slower as the state grows. A
+/// snapshot is the whole tree in one row, which lets a restarting node load it from the latest
+/// snapshot plus the accounts changed since. Each snapshot is itself built from the previous one,
+/// and only the latest few snapshots are kept.
+pub async fn snapshot_fee_merkle_tree_loop(mut db: Persistence, interval: u64) {
+    loop {
+        if let Err(err) = snapshot_fee_merkle_tree(&mut db, interval).await {
+            tracing::warn!("error saving fee merkle tree snapshot: {err:#}");
+        }
+        sleep(FEE_SNAPSHOT_POLL_INTERVAL).await;
+    }
+}
+
+/// Save a snapshot of the fee merkle tree, if one is due.
+async fn snapshot_fee_merkle_tree(db: &mut Persistence, interval: u64) -> anyhow::Result<()> {
+    let height = db.get_last_state_height().await? as u64;
+    let last = db
+        .query_opt_static(
+            "SELECT height FROM fee_merkle_tree_snapshot ORDER BY height DESC LIMIT 1",
+        )
+        .await?
+        .map(|row| row.get::<_, i64>("height") as u64)
+        .unwrap_or_default();
+    if height < last + interval {
+        return Ok(());
+    }
+
+    let tree = match load_fee_merkle_tree_from_snapshot(db, height).await? {
+        Some(tree) => tree,
+        None => {
+            let snapshot = Snapshot::<_, FeeMerkleTree, { FeeMerkleTree::ARITY }>::Index(height);
+            db.get_snapshot(snapshot)
+                .await
+                .context("loading fee merkle tree")?
+        }
+    };
+    let data = bincode::serialize(&tree)?;
+    let height = height as i64;
+    transaction(db, |mut tx| {
+        async move {
+            tx.upsert(
+                "fee_merkle_tree_snapshot",
+                ["height", "data"],
+                ["height"],
+                [[sql_param(&height), sql_param(&data)]],
+            )
+            .await?;
+            tx.execute(
+                "DELETE FROM fee_merkle_tree_snapshot WHERE height NOT IN
+                    (SELECT height FROM fee_merkle_tree_snapshot ORDER BY height DESC LIMIT $1)",
+                [FEE_SNAPSHOTS_RETAINED],
+            )
+            .await?;
+            Ok(())
+        }
+        .boxed()
+    })
+    .await?;
+    tracing::info!(height, "saved fee merkle tree snapshot");
+    Ok(())
+}
+
+/// Prune the merklized state of all but the latest `retention` blocks.
+///
+/// The merkle tree tables keep every version of every node, so the state at any past height can be
+/// queried, and they grow with every block. The pruner deletes each version of a node which was
+/// already replaced by a newer version at the cutoff height. Every node needed for the state at
+/// the cutoff or later is kept, so that state remains available. `earliest_height` is set to the
+/// cutoff, the earliest height at which the state is still available.
+pub async fn prune_merklized_state_loop(
+    mut db: Persistence,
+    retention: u64,
+    earliest_height: Arc<AtomicU64>,
+) {
+    match load_merklized_state_pruned_height(&db).await {
+        Ok(height) => earliest_height.store(height, Ordering::Relaxed),
+        Err(err) => tracing::warn!("error loading merklized state pruned height: {err:#}"),
+    }
+    loop {
+        match prune_merklized_state(&mut db, retention, &earliest_height).await {
+            Ok(()) => {}
+            Err(err) => tracing::warn!("error pruning merklized state: {err:#}"),
+        }
+        sleep(MERKLIZED_STATE_PRUNE_INTERVAL).await;
+    }
+}
+
+async fn load_merklized_state_pruned_height(db: &Persistence) -> anyhow::Result<u64> {
+    Ok(db
+        .query_opt_static("SELECT height FROM merklized_state_pruned_height WHERE id = 0")
+        .await?
+        .map(|row| row.get::<_, i64>("height") as u64)
+        .unwrap_or_default())
+}
+
+async fn prune_merklized_state(
+    db: &mut Persistence,
+    retention: u64,
+    earliest_height: &AtomicU64,
+) -> anyhow::Result<()> {
+    let height = db.get_last_state_height().await? as u64;
+    let cutoff = height.saturating_sub(retention);
+    if cutoff <= earliest_height.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    let tables = [FeeMerkleTree::state_type(), BlockMerkleTree::state_type()];
+    let cutoff_param = cutoff as i64;
+    transaction(db, |mut tx| {
+        async move {
+            for table in tables {
+                let stmt = format!(
+                    "DELETE FROM {table} AS t WHERE t.created < $1 AND EXISTS (
+                        SELECT 1 FROM {table} AS n
+                            WHERE n.pos = t.pos AND n.created > t.created AND n.created <= $1
+                    )"
+                );
+                let pruned = tx.execute(stmt.as_str(), [cutoff_param]).await?;
+                tracing::info!(cutoff_param, "pruned {pruned} old nodes from {table}");
+            }
+            tx.upsert(
+                "merklized_state_pruned_height",
+                ["id", "height"],
+                ["id"],
+                [[sql_param(&0i32), sql_param(&cutoff_param)]],
+            )
+            .await?;
+            Ok(())
+        }
+        .boxed()
+    })
+    .await?;
+    earliest_height.store(cutoff, Ordering::Relaxed);
+    Ok(())
+}
+
 #[cfg(test)]
 mod testing {
     use hotshot_query_service::data_source::storage::sql::testing::TmpDb;
@@ -0,0 +1,195 @@
+//! Signal handling for the sequencer binary.
+//!
+//! SIGTERM and SIGINT shut the node down cleanly: consensus is stopped and any decided state which
+//! is being stored is allowed to finish persisting before the process exits. SIGHUP reloads the
+//! settings in the [reload file](ReloadableConfig), which can be changed without restarting the
+//! node: the log filter, the state peers to fetch missing state from, and the API rate limits.
+//! Everything else, including the builder URLs in the HotShot config, is fixed at startup.
+
+use crate::{
+    api::options::RateLimit, api::rate_limit::SharedRateLimit, logging::LogFilter, NodeState,
+};
+use anyhow::Context;
+use futures::{future::Future, stream::StreamExt};
+use serde::Deserialize;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook_async_std::Signals;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Settings which can be changed while the node is running.
+///
+/// Every setting is optional, and settings which are missing from the file are left as they are.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReloadableConfig {
+    /// Log filter, in the syntax of `RUST_LOG`.
+    pub log_filter: Option<String>,
+    /// Peers to fetch missing state from.
+    pub state_peers: Option<Vec<Url>>,
+    /// API rate limits.
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+/// Changes to the API rate limits.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub submit_rate_limit: Option<f64>,
+    pub query_rate_limit: Option<f64>,
+    pub api_key_submit_rate_limit: Option<f64>,
+    pub api_key_query_rate_limit: Option<f64>,
+    pub api_keys: Option<Vec<String>>,
+    pub require_api_key: Option<bool>,
+}
+
+impl ReloadableConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("reading reload file {}", path.display()))?;
+        toml::from_str(&toml).with_context(|| format!("parsing reload file {}", path.display()))
+    }
+}
+
+impl RateLimitConfig {
+    /// Apply these changes to `opt`.
+    fn apply(&self, mut opt: RateLimit) -> RateLimit {
+        if let Some(limit) = self.submit_rate_limit {
+            opt.submit_rate_limit = limit;
+        }
+        if let Some(limit) = self.query_rate_limit {
+            opt.query_rate_limit = limit;
+        }
+        if let Some(limit) = self.api_key_submit_rate_limit {
+            opt.api_key_submit_rate_limit = limit;
+        }
+        if let Some(limit) = self.api_key_query_rate_limit {
+            opt.api_key_query_rate_limit = limit;
+        }
+        if let Some(keys) = &self.api_keys {
+            opt.api_keys = keys.clone();
+        }
+        if let Some(require) = self.require_api_key {
+            opt.require_api_key = require;
+        }
+        opt
+    }
+}
+
+/// Applies the reload file to a running node.
+#[derive(Clone, Debug)]
+pub struct Reloader {
+    path: PathBuf,
+    log_filter: LogFilter,
+    node_state: NodeState,
+    rate_limit: Option<SharedRateLimit>,
+}
+
+impl Reloader {
+    pub fn new(
+        path: PathBuf,
+        log_filter: LogFilter,
+        node_state: NodeState,
+        rate_limit: Option<SharedRateLimit>,
+    ) -> Self {
+        Self {
+            path,
+            log_filter,
+            node_state,
+            rate_limit,
+        }
+    }
+
+    /// Read the reload file and apply the settings it contains.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let config = ReloadableConfig::from_file(&self.path)?;
+        if let Some(filter) = &config.log_filter {
+            self.log_filter.set(filter)?;
+            tracing::warn!(filter, "reloaded log filter");
+        }
+        if let Some(peers) = config.state_peers {
+            tracing::warn!(?peers, "reloaded state peers");
+            self.node_state
+                .set_state_peers(peers)
+                .context("reloading state peers")?;
+        }
+        if let Some(rate_limit) = &config.rate_limit {
+            match &self.rate_limit {
+                Some(limits) => {
+                    let opt = rate_limit.apply(limits.get());
+                    tracing::warn!(?opt, "reloaded rate limits");
+                    limits.set(opt);
+                }
+                None => tracing::warn!("ignoring rate limits, rate limiting is not enabled"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Listen for signals, reloading on SIGHUP.
+///
+/// Returns a future which resolves when the node is asked to shut down, by SIGTERM or SIGINT.
+pub fn shutdown_signal(reloader: Option<Reloader>) -> anyhow::Result<impl Future<Output = ()>> {
+    let mut signals =
+        Signals::new([SIGTERM, SIGINT, SIGHUP]).context("registering signal handlers")?;
+    Ok(async move {
+        while let Some(signal) = signals.next().await {
+            if signal != SIGHUP {
+                tracing::warn!(signal, "received shutdown signal");
+                return;
+            }
+            match &reloader {
+                Some(reloader) => {
+                    if let Err(err) = reloader.reload() {
+                        tracing::error!("failed to reload: {err:#}");
+                    }
+                }
+                None => tracing::warn!("ignoring SIGHUP, no reload file was given"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_reloadable_config() {
+        let config: ReloadableConfig = toml::from_str(
+            r#"
+            log_filter = "info,libp2p=warn"
+            state_peers = ["http://peer:8080"]
+
+            [rate_limit]
+            submit_rate_limit = 5.0
+            api_keys = ["key"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.log_filter.as_deref(), Some("info,libp2p=warn"));
+        assert_eq!(
+            config.state_peers,
+            Some(vec!["http://peer:8080".parse().unwrap()])
+        );
+
+        // Only the limits in the file are changed.
+        let opt = RateLimit::parse_from(["sequencer"]);
+        let reloaded = config.rate_limit.unwrap().apply(opt.clone());
+        assert_eq!(reloaded.submit_rate_limit, 5.0);
+        assert_eq!(reloaded.api_keys, ["key"]);
+        assert_eq!(reloaded.query_rate_limit, opt.query_rate_limit);
+        assert_eq!(reloaded.require_api_key, opt.require_api_key);
+
+        // An empty file changes nothing.
+        assert_eq!(
+            toml::from_str::<ReloadableConfig>("").unwrap(),
+            Default::default()
+        );
+        // Misspelled settings are rejected rather than ignored.
+        toml::from_str::<ReloadableConfig>("log_level = \"info\"").unwrap_err();
+    }
+}
@@ -0,0 +1,224 @@
+//! Deterministic replay of decided chain segments.
+//!
+//! Replay re-executes the state transition of every block in a range of decided leaves, starting
+//! from the state committed to by the parent of the range, and compares the state roots it computes
+//! with the ones stored in each header. The first block where they differ, or which fails header
+//! validation, is reported as a [`Divergence`]. This is the tool to reach for when nodes disagree
+//! about the state after an upgrade: replaying the chain with a candidate build shows exactly which
+//! block the build handles differently.
+
+use crate::{
+    state::{validate_builder_fee, validate_proposal, BlockMerkleCommitment, FeeMerkleCommitment},
+    Leaf, NodeState, ValidatedState,
+};
+use anyhow::Context;
+use futures::Future;
+use hotshot::traits::ValidatedState as _;
+use jf_primitives::merkle_tree::MerkleTreeScheme;
+use std::fmt::{self, Display, Formatter};
+
+/// The first difference between replayed state and the stored chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    BlockMerkleTreeRoot {
+        height: u64,
+        stored: BlockMerkleCommitment,
+        replayed: BlockMerkleCommitment,
+    },
+    FeeMerkleTreeRoot {
+        height: u64,
+        stored: FeeMerkleCommitment,
+        replayed: FeeMerkleCommitment,
+    },
+    /// The header fails validation against its parent and the replayed state.
+    InvalidHeader { height: u64, reason: String },
+}
+
+impl Divergence {
+    /// The height of the first divergent block.
+    pub fn height(&self) -> u64 {
+        match self {
+            Self::BlockMerkleTreeRoot { height, .. }
+            | Self::FeeMerkleTreeRoot { height, .. }
+            | Self::InvalidHeader { height, .. } => *height,
+        }
+    }
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::BlockMerkleTreeRoot {
+                height,
+                stored,
+                replayed,
+            } => write!(
+                f,
+                "block {height} has block Merkle tree root {stored}, but replay computed {replayed}"
+            ),
+            Self::FeeMerkleTreeRoot {
+                height,
+                stored,
+                replayed,
+            } => write!(
+                f,
+                "block {height} has fee Merkle tree root {stored}, but replay computed {replayed}"
+            ),
+            Self::InvalidHeader { height, reason } => {
+                write!(f, "block {height} is invalid: {reason}")
+            }
+        }
+    }
+}
+
+/// Replay the decided leaves with heights in `[from, to)`.
+///
+/// `fetch` fetches the decided leaf at a given height. Replay starts from the genesis state of
+/// `instance` if `from` is 0, and otherwise from the state committed to by leaf `from - 1`, in which
+/// case the state needed to replay the range is fetched from the catchup peers of `instance`. L1
+/// deposits are read with the L1 client of `instance`.
+///
+/// Returns the first divergence found, or [`None`] if the whole range replays to the stored state.
+/// Errors are reserved for failures to fetch leaves or state, which say nothing about the chain.
+pub async fn replay<F, Fut>(
+    instance: &NodeState,
+    from: u64,
+    to: u64,
+    mut fetch: F,
+) -> anyhow::Result<Option<Divergence>>
+where
+    F: FnMut(u64) -> Fut,
+    Fut: Future<Output = anyhow::Result<Leaf>>,
+{
+    if from >= to {
+        return Ok(None);
+    }
+
+    let (mut state, mut parent) = if from == 0 {
+        let genesis = fetch(0).await.context("fetching genesis leaf")?;
+        let state = ValidatedState::genesis(instance).0;
+        if let Some(divergence) = compare_roots(&state, &genesis) {
+            return Ok(Some(divergence));
+        }
+        (state, genesis)
+    } else {
+        let parent = fetch(from - 1)
+            .await
+            .with_context(|| format!("fetching leaf {}", from - 1))?;
+        (
+            ValidatedState::from_header(parent.get_block_header()),
+            parent,
+        )
+    };
+
+    for height in (from.max(1))..to {
+        let leaf = fetch(height)
+            .await
+            .with_context(|| format!("fetching leaf {height}"))?;
+        let header = leaf.get_block_header();
+
+        if let Err(err) = validate_builder_fee(header) {
+            return Ok(Some(Divergence::InvalidHeader {
+                height,
+                reason: format!("{err:#}"),
+            }));
+        }
+        let (next, _) = state
+            .apply_header(instance, &parent, header)
+            .await
+            .with_context(|| format!("applying header {height}"))?;
+        if let Some(divergence) = compare_roots(&next, &leaf) {
+            return Ok(Some(divergence));
+        }
+
+        // Validate the header against the chain config it commits to, if it carries one, and
+        // otherwise against the chain config `instance` expects.
+        let chain_config = match header.chain_config.resolve() {
+            Some(chain_config) => chain_config,
+            None => instance.chain_config_after(parent.get_block_header()).await,
+        };
+        if let Err(err) = validate_proposal(&next, chain_config, &parent, header) {
+            return Ok(Some(Divergence::InvalidHeader {
+                height,
+                reason: format!("{err:#}"),
+            }));
+        }
+
+        tracing::debug!(height, "replayed block");
+        state = next;
+        parent = leaf;
+    }
+    Ok(None)
+}
+
+fn compare_roots(state: &ValidatedState, leaf: &Leaf) -> Option<Divergence> {
+    let header = leaf.get_block_header();
+    let replayed = state.block_merkle_tree.commitment();
+    if replayed != header.block_merkle_tree_root {
+        return Some(Divergence::BlockMerkleTreeRoot {
+            height: header.height,
+            stored: header.block_merkle_tree_root,
+            replayed,
+        });
+    }
+    let replayed = state.fee_merkle_tree.commitment();
+    if replayed != header.fee_merkle_tree_root {
+        return Some(Divergence::FeeMerkleTreeRoot {
+            height: header.height,
+            stored: header.fee_merkle_tree_root,
+            replayed,
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::{FeeAccount, FeeAmount};
+
+    #[async_std::test]
+    async fn test_replay_genesis() {
+        let instance = NodeState::mock();
+        let genesis = Leaf::genesis(&instance);
+        let fetch = |leaf: Leaf| {
+            move |_: u64| {
+                let leaf = leaf.clone();
+                async move { Ok::<_, anyhow::Error>(leaf) }
+            }
+        };
+
+        // The genesis leaf replays to its own state.
+        assert_eq!(
+            replay(&instance, 0, 1, fetch(genesis.clone()))
+                .await
+                .unwrap(),
+            None
+        );
+        // An empty range is trivially consistent.
+        assert_eq!(
+            replay(&instance, 0, 0, fetch(genesis.clone()))
+                .await
+                .unwrap(),
+            None
+        );
+
+        // A node started with a different genesis state diverges at block 0.
+        let mut state = ValidatedState::default();
+        state.prefund_account(FeeAccount::default(), FeeAmount::from(1));
+        let other = NodeState::mock().with_genesis(state.clone());
+        let divergence = replay(&other, 0, 1, fetch(genesis.clone()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            divergence,
+            Divergence::FeeMerkleTreeRoot {
+                height: 0,
+                stored: genesis.get_block_header().fee_merkle_tree_root,
+                replayed: state.fee_merkle_tree.commitment(),
+            }
+        );
+        assert_eq!(divergence.height(), 0);
+    }
+}
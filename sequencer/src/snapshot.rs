@@ -0,0 +1,203 @@
+//! Snapshots of the decided state, for bootstrapping new nodes.
+//!
+//! A new node normally starts from genesis and rebuilds the state by replaying every block. With a
+//! snapshot, it can instead start consensus from a recent decided leaf: a node serving the catchup
+//! API exports a [`StateSnapshot`] of its latest decided state, and the new node
+//! [verifies](StateSnapshot::verify) it against the stake table, starts from it, and validates each
+//! block decided after it as usual.
+
+use crate::{
+    state::{BlockMerkleTree, FeeMerkleTree, ValidatedState},
+    state_signature::{form_light_client_state, static_stake_table_commitment},
+    Leaf, NodeState, PubKey, SeqTypes,
+};
+use anyhow::{bail, ensure, Context};
+use async_std::sync::{Arc, RwLock};
+use committable::Committable;
+use futures::stream::{Stream, StreamExt};
+use hotshot::{
+    traits::election::static_committee::GeneralStaticCommittee,
+    types::{Event, EventType},
+    HotShotInitializer,
+};
+use hotshot_types::{
+    event::LeafInfo,
+    light_client::{StateSignatureRequestBody, StateSignatureScheme},
+    simple_certificate::QuorumCertificate,
+    traits::{election::Membership, node_implementation::ConsensusTime},
+    vote::Certificate,
+    PeerConfig,
+};
+use jf_primitives::{merkle_tree::MerkleTreeScheme, signatures::SignatureScheme};
+use serde::{Deserialize, Serialize};
+use surf_disco::{Client, Url};
+use tide_disco::error::ServerError;
+use vbs::version::StaticVersionType;
+
+/// The decided state at some height, with the certificate which decided it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    /// The decided leaf the snapshot was taken at.
+    pub leaf: Leaf,
+    /// The quorum certificate for `leaf`, which anchors the snapshot to the stake table.
+    pub qc: QuorumCertificate<SeqTypes>,
+    pub fee_merkle_tree: FeeMerkleTree,
+    pub block_merkle_tree: BlockMerkleTree,
+    /// The serving node's signature on the light client state of `leaf`, if it still has it.
+    ///
+    /// Once the light client contract reaches the height of `leaf`, this state can be checked
+    /// against the contract as well.
+    pub signature: Option<StateSignatureRequestBody>,
+}
+
+impl StateSnapshot {
+    pub fn new(
+        leaf: Leaf,
+        qc: QuorumCertificate<SeqTypes>,
+        state: &ValidatedState,
+        signature: Option<StateSignatureRequestBody>,
+    ) -> Self {
+        Self {
+            leaf,
+            qc,
+            fee_merkle_tree: state.fee_merkle_tree.clone(),
+            block_merkle_tree: state.block_merkle_tree.clone(),
+            signature,
+        }
+    }
+
+    /// The height of the snapshot.
+    pub fn height(&self) -> u64 {
+        self.leaf.get_height()
+    }
+
+    /// Check that this snapshot was decided by the stake table `known_nodes_with_stake`.
+    ///
+    /// The quorum certificate must be valid for the leaf, and the Merkle trees must match the roots
+    /// in its header. The light client state signature, if there is one, must be a valid signature
+    /// by a member of the stake table on the light client state of the leaf.
+    ///
+    /// Returns the validated state after the leaf.
+    pub fn verify(
+        &self,
+        known_nodes_with_stake: &[PeerConfig<PubKey>],
+        stake_table_capacity: usize,
+    ) -> anyhow::Result<ValidatedState> {
+        ensure!(
+            self.qc.view_number == self.leaf.get_view_number(),
+            "snapshot QC is for view {:?}, but leaf is from view {:?}",
+            self.qc.view_number,
+            self.leaf.get_view_number()
+        );
+        ensure!(
+            self.qc.data.leaf_commit == self.leaf.commit(),
+            "snapshot QC is not for the snapshot leaf"
+        );
+        let election_config = GeneralStaticCommittee::<SeqTypes, PubKey>::default_election_config(
+            known_nodes_with_stake.len() as u64,
+            0,
+        );
+        let membership = GeneralStaticCommittee::create_election(
+            known_nodes_with_stake.to_vec(),
+            election_config,
+            0,
+        );
+        ensure!(
+            self.qc.is_valid_cert(&membership),
+            "snapshot QC is not signed by the stake table"
+        );
+
+        let header = self.leaf.get_block_header();
+        ensure!(
+            self.fee_merkle_tree.commitment() == header.fee_merkle_tree_root,
+            "snapshot fee merkle tree does not match the header"
+        );
+        ensure!(
+            self.block_merkle_tree.commitment() == header.block_merkle_tree_root,
+            "snapshot block merkle tree does not match the header"
+        );
+
+        if let Some(signature) = &self.signature {
+            let stake_table_comm =
+                static_stake_table_commitment(known_nodes_with_stake, stake_table_capacity);
+            let state = form_light_client_state(&self.leaf, &stake_table_comm)
+                .context("computing light client state")?;
+            ensure!(
+                signature.state == state,
+                "signed light client state does not match the snapshot"
+            );
+            ensure!(
+                known_nodes_with_stake
+                    .iter()
+                    .any(|peer| peer.state_ver_key == signature.key),
+                "light client state is not signed by a member of the stake table"
+            );
+            if let Err(err) = StateSignatureScheme::verify(
+                &(),
+                &signature.key,
+                <[_; 7]>::from(&state),
+                &signature.signature,
+            ) {
+                bail!("invalid light client state signature: {err}");
+            }
+        }
+
+        Ok(ValidatedState {
+            fee_merkle_tree: self.fee_merkle_tree.clone(),
+            block_merkle_tree: self.block_merkle_tree.clone(),
+        })
+    }
+
+    /// An initializer to start consensus from this snapshot, with the already verified `state`.
+    pub fn initializer(
+        self,
+        instance: NodeState,
+        state: ValidatedState,
+    ) -> HotShotInitializer<SeqTypes> {
+        let view = self.leaf.get_view_number() + 1;
+        HotShotInitializer::from_reload(
+            self.leaf,
+            instance,
+            Some(Arc::new(state)),
+            view,
+            self.qc,
+            Default::default(),
+            Default::default(),
+        )
+    }
+}
+
+/// Fetch the latest snapshot from the catchup API of the node at `url`.
+pub async fn fetch_snapshot<Ver: StaticVersionType>(
+    url: Url,
+    _: Ver,
+) -> anyhow::Result<StateSnapshot> {
+    let client = Client::<ServerError, Ver>::new(url.clone());
+    client
+        .get::<StateSnapshot>("catchup/snapshot")
+        .send()
+        .await
+        .with_context(|| format!("fetching state snapshot from {url}"))
+}
+
+/// The latest decided leaf and the certificate which decided it.
+pub(crate) type LatestDecide = Option<(Leaf, QuorumCertificate<SeqTypes>)>;
+
+/// Keep `latest` up to date with the leaves decided in `events`.
+pub(crate) async fn track_decides(
+    latest: Arc<RwLock<LatestDecide>>,
+    mut events: impl Stream<Item = Event<SeqTypes>> + Unpin,
+) {
+    while let Some(event) = events.next().await {
+        let EventType::Decide { leaf_chain, qc, .. } = event.event else {
+            continue;
+        };
+        let Some(LeafInfo { leaf, .. }) = leaf_chain.first() else {
+            continue;
+        };
+        // The QC in a decide event is for the first leaf in the chain.
+        if qc.view_number == leaf.get_view_number() {
+            *latest.write().await = Some((leaf.clone(), QuorumCertificate::clone(&qc)));
+        }
+    }
+}
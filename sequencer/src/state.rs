@@ -191,12 +191,6 @@ impl ValidatedState {
     }
 }
 
-// TODO remove this, data will come from HotShot:
-// https://github.com/EspressoSystems/HotShot/issues/2744
-fn get_proposed_payload_size() -> u64 {
-    1
-}
-
 pub fn validate_proposal(
     state: &ValidatedState,
     expected_chain_config: ChainConfig,
@@ -215,12 +209,28 @@ pub fn validate_proposal(
         )
     );
 
+    // Validate the payload size, which the header commits to through the size of each namespace.
+    let payload_size = proposal.ns_table.payload_byte_len() as u64;
     anyhow::ensure!(
-        get_proposed_payload_size() < expected_chain_config.max_block_size(),
+        payload_size <= expected_chain_config.max_block_size(),
         anyhow::anyhow!(
-            "Invalid Payload Size: local={:?}, proposal={:?}",
-            expected_chain_config,
-            proposal.chain_config
+            "Invalid Payload Size: local={}, proposal={}",
+            expected_chain_config.max_block_size(),
+            payload_size
+        )
+    );
+
+    // validate base fee
+    let expected_base_fee = expected_chain_config.next_base_fee(
+        parent_header.base_fee,
+        parent_header.ns_table.payload_byte_len() as u64,
+    );
+    anyhow::ensure!(
+        proposal.base_fee == expected_base_fee,
+        anyhow::anyhow!(
+            "Invalid Base Fee: local={:?}, proposal={:?}",
+            expected_base_fee,
+            proposal.base_fee
         )
     );
 
@@ -271,8 +281,31 @@ fn charge_fee(
     Ok(())
 }
 
+/// Validate the namespaces in a proposal, and the fee paid for them, against the namespace policy.
+fn validate_namespaces(
+    instance: &NodeState,
+    chain_config: ChainConfig,
+    proposed_header: &Header,
+) -> anyhow::Result<()> {
+    let policy = instance.namespace_policy();
+    policy.validate_ns_table(&proposed_header.ns_table)?;
+
+    // The builder fee is only checked if the base fee is dynamic or the policy prices namespaces.
+    if proposed_header.base_fee.is_some() || policy.prices_namespaces() {
+        let base_fee = proposed_header.base_fee.unwrap_or(chain_config.base_fee());
+        let min_fee = policy.min_fee(base_fee, &proposed_header.ns_table);
+        anyhow::ensure!(
+            proposed_header.fee_info.amount() >= min_fee,
+            "Insufficient Builder Fee: paid {:?}, required {:?}",
+            proposed_header.fee_info.amount(),
+            min_fee
+        );
+    }
+    Ok(())
+}
+
 /// Validate builder account by verifying signature
-fn validate_builder_fee(proposed_header: &Header) -> anyhow::Result<()> {
+pub(crate) fn validate_builder_fee(proposed_header: &Header) -> anyhow::Result<()> {
     // Beware of Malice!
     let signature = proposed_header
         .builder_signature
@@ -608,7 +641,7 @@ impl<T> SequencerStateDataSource for T where
 }
 
 impl ValidatedState {
-    async fn apply_header(
+    pub(crate) async fn apply_header(
         &self,
         instance: &NodeState,
         parent_leaf: &Leaf,
@@ -756,16 +789,20 @@ impl HotShotState<SeqTypes> for ValidatedState {
             .await
             .unwrap();
 
-        // validate the proposal
-        if let Err(err) = validate_proposal(
-            &validated_state,
-            instance.chain_config,
-            parent_leaf,
-            proposed_header,
-        ) {
+        // validate the proposal, against the chain config including any upgrades scheduled on L1
+        let chain_config = instance
+            .chain_config_after(parent_leaf.get_block_header())
+            .await;
+        if let Err(err) =
+            validate_proposal(&validated_state, chain_config, parent_leaf, proposed_header)
+        {
             tracing::error!("invalid proposal: {err:#}");
             return Err(BlockError::InvalidBlockHeader);
         }
+        if let Err(err) = validate_namespaces(instance, chain_config, proposed_header) {
+            tracing::error!("proposal violates namespace policy: {err:#}");
+            return Err(BlockError::InvalidBlockHeader);
+        }
 
         // log successful progress about once in 10 - 20 seconds,
         // TODO: we may want to make this configurable
@@ -1256,4 +1293,44 @@ mod test {
         FeeAccountProof::prove(&tree, account1).unwrap();
         FeeAccountProof::prove(&tree, account2).unwrap();
     }
+
+    #[test]
+    fn test_validate_dynamic_base_fee() {
+        use crate::{NamespaceId, Payload, Transaction};
+        use hotshot::traits::BlockPayload;
+
+        setup_logging();
+        setup_backtrace();
+
+        let instance = NodeState::mock();
+        let chain_config = instance.chain_config.with_target_block_size(1000);
+        let (_, ns_table) =
+            Payload::from_transactions([Transaction::new(NamespaceId::from(1), vec![0; 100])])
+                .unwrap();
+        let mut header = Leaf::genesis(&instance).get_block_header().clone();
+        header.ns_table = ns_table;
+        header.base_fee = Some(2.into());
+
+        // Once the base fee is dynamic, a builder must pay it for every byte, even though the
+        // chain config charges nothing. Builders which ignore `Header::base_fee` and offer no fee
+        // have their blocks rejected.
+        let min_fee = instance
+            .namespace_policy()
+            .min_fee(2.into(), &header.ns_table);
+        assert!(min_fee > 0.into());
+        header.fee_info = FeeInfo::new(Address::default(), 0);
+        let err = validate_namespaces(&instance, chain_config, &header).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Insufficient Builder Fee"),
+            "{err}"
+        );
+
+        header.fee_info = FeeInfo::new(Address::default(), min_fee);
+        validate_namespaces(&instance, chain_config, &header).unwrap();
+
+        // With a fixed base fee of zero, no fee is required.
+        header.base_fee = None;
+        header.fee_info = FeeInfo::new(Address::default(), 0);
+        validate_namespaces(&instance, instance.chain_config, &header).unwrap();
+    }
 }
@@ -1,9 +1,16 @@
 //! Utilities for generating and storing the most recent light client state signatures.
 
-use crate::{Leaf, SeqTypes, StateKeyPair};
+use crate::{state::BlockMerkleCommitment, Leaf, SeqTypes, StateKeyPair};
 use ark_ff::PrimeField;
 use ark_serialize::CanonicalSerialize;
-use async_std::sync::RwLock;
+use async_std::{
+    channel::{self, Sender},
+    sync::{Mutex, RwLock},
+};
+use futures::{
+    future::ready,
+    stream::{self, BoxStream, StreamExt},
+};
 use hotshot::types::{Event, EventType};
 use hotshot_stake_table::vec_based::StakeTable;
 use hotshot_types::light_client::{
@@ -11,7 +18,7 @@ use hotshot_types::light_client::{
 };
 use hotshot_types::{
     event::LeafInfo,
-    light_client::{StateSignature, StateSignatureScheme},
+    light_client::StateSignatureScheme,
     signature_key::BLSPubKey,
     traits::{
         node_implementation::ConsensusTime,
@@ -36,14 +43,21 @@ pub mod relay_server;
 /// Capacity for the in memory signature storage.
 const SIGNATURE_STORAGE_CAPACITY: usize = 100;
 
+/// Number of signatures buffered for each subscriber. A subscriber which falls this far behind is
+/// disconnected.
+const SUBSCRIBER_CAPACITY: usize = SIGNATURE_STORAGE_CAPACITY;
+
 #[derive(Debug)]
 pub struct StateSigner<Ver: StaticVersionType> {
     /// Key pair for signing a new light client state
-    key_pair: StateKeyPair,
+    key_pair: RwLock<StateKeyPair>,
 
     /// The most recent light client state signatures
     signatures: RwLock<StateSignatureMemStorage>,
 
+    /// Subscribers to new signatures
+    subscribers: Mutex<Vec<Sender<StateSignatureRequestBody>>>,
+
     /// Commitment for current fixed stake table
     stake_table_comm: StakeTableCommitmentType,
 
@@ -54,9 +68,10 @@ pub struct StateSigner<Ver: StaticVersionType> {
 impl<Ver: StaticVersionType> StateSigner<Ver> {
     pub fn new(key_pair: StateKeyPair, stake_table_comm: StakeTableCommitmentType) -> Self {
         Self {
-            key_pair,
+            key_pair: RwLock::new(key_pair),
             stake_table_comm,
             signatures: Default::default(),
+            subscribers: Default::default(),
             relay_server_client: Default::default(),
         }
     }
@@ -67,6 +82,11 @@ impl<Ver: StaticVersionType> StateSigner<Ver> {
         self
     }
 
+    /// Sign light client states with `key_pair` from now on.
+    pub async fn rotate_key(&self, key_pair: StateKeyPair) {
+        *self.key_pair.write().await = key_pair;
+    }
+
     pub(super) async fn handle_event(&self, event: &Event<SeqTypes>) {
         let EventType::Decide { leaf_chain, .. } = &event.event else {
             return;
@@ -76,18 +96,13 @@ impl<Ver: StaticVersionType> StateSigner<Ver> {
         };
         match form_light_client_state(leaf, &self.stake_table_comm) {
             Ok(state) => {
-                let signature = self.sign_new_state(&state).await;
+                let request_body = self.sign_new_state(&state).await;
                 tracing::debug!(
                     "New leaves decided. Latest block height: {}",
                     leaf.get_height(),
                 );
 
                 if let Some(client) = &self.relay_server_client {
-                    let request_body = StateSignatureRequestBody {
-                        key: self.key_pair.ver_key(),
-                        state,
-                        signature,
-                    };
                     if let Err(error) = client
                         .post::<()>("api/state")
                         .body_binary(&request_body)
@@ -111,30 +126,50 @@ impl<Ver: StaticVersionType> StateSigner<Ver> {
         pool_guard.get_signature(height)
     }
 
+    /// Stream the signatures of light client states at height `from` and up.
+    ///
+    /// The stream starts with the stored signatures, in order of height, followed by each new
+    /// signature as it is made. Only the most recent signatures are stored, so a client resuming
+    /// from an old height may miss some. A subscriber which falls too far behind is disconnected:
+    /// its stream ends, and it can subscribe again from the last height it received.
+    pub async fn subscribe(&self, from: u64) -> BoxStream<'static, StateSignatureRequestBody> {
+        // Register while holding the storage lock, so that every signature is either stored
+        // already or sent to the new subscriber, but not both.
+        let pool_guard = self.signatures.read().await;
+        let stored = pool_guard.signatures_from(from);
+        let (sender, receiver) = channel::bounded(SUBSCRIBER_CAPACITY);
+        self.subscribers.lock().await.push(sender);
+        drop(pool_guard);
+        stream::iter(stored)
+            .chain(receiver.filter(move |body| ready(body.state.block_height as u64 >= from)))
+            .boxed()
+    }
+
     /// Sign the light client state at given height and store it.
-    async fn sign_new_state(&self, state: &LightClientState) -> StateSignature {
+    async fn sign_new_state(&self, state: &LightClientState) -> StateSignatureRequestBody {
         let msg: [CircuitField; 7] = state.into();
-        let signature = StateSignatureScheme::sign(
-            &(),
-            self.key_pair.sign_key_ref(),
-            msg,
-            &mut rand::thread_rng(),
-        )
-        .unwrap();
+        let key_pair = self.key_pair.read().await;
+        let signature =
+            StateSignatureScheme::sign(&(), key_pair.sign_key_ref(), msg, &mut rand::thread_rng())
+                .unwrap();
+        let body = StateSignatureRequestBody {
+            key: key_pair.ver_key(),
+            state: state.clone(),
+            signature,
+        };
+        drop(key_pair);
         let mut pool_guard = self.signatures.write().await;
-        pool_guard.push(
-            state.block_height as u64,
-            StateSignatureRequestBody {
-                key: self.key_pair.ver_key(),
-                state: state.clone(),
-                signature: signature.clone(),
-            },
-        );
+        pool_guard.push(state.block_height as u64, body.clone());
+        // Drop subscribers which have disconnected or fallen behind.
+        self.subscribers
+            .lock()
+            .await
+            .retain(|sender| sender.try_send(body.clone()).is_ok());
         tracing::debug!(
             "New signature added for block height {}",
             state.block_height
         );
-        signature
+        body
     }
 }
 
@@ -148,16 +183,19 @@ fn hash_bytes_to_field(bytes: &[u8]) -> Result<CircuitField, PrimitivesError> {
     Ok(VariableLengthRescueCRHF::<_, 1>::evaluate(elem)?[0])
 }
 
-fn form_light_client_state(
+/// The `block_comm_root` of a light client state whose block Merkle tree has root `root`.
+pub fn block_comm_root(root: &BlockMerkleCommitment) -> Result<CircuitField, PrimitivesError> {
+    let mut bytes = vec![];
+    root.serialize_compressed(&mut bytes)?;
+    hash_bytes_to_field(&bytes)
+}
+
+/// The light client state after `leaf`, as signed by the [`StateSigner`].
+pub(crate) fn form_light_client_state(
     leaf: &Leaf,
     stake_table_comm: &StakeTableCommitmentType,
 ) -> Result<LightClientState, PrimitivesError> {
     let header = leaf.get_block_header();
-    let mut block_comm_root_bytes = vec![];
-    header
-        .block_merkle_tree_root
-        .serialize_compressed(&mut block_comm_root_bytes)?;
-
     let mut fee_ledger_comm_bytes = vec![];
     header
         .fee_merkle_tree_root
@@ -165,7 +203,7 @@ fn form_light_client_state(
     Ok(LightClientState {
         view_number: leaf.get_view_number().get_u64() as usize,
         block_height: leaf.get_height() as usize,
-        block_comm_root: hash_bytes_to_field(&block_comm_root_bytes)?,
+        block_comm_root: block_comm_root(&header.block_merkle_tree_root)?,
         fee_ledger_comm: hash_bytes_to_field(&fee_ledger_comm_bytes)?,
         stake_table_comm: *stake_table_comm,
     })
@@ -190,6 +228,21 @@ impl StateSignatureMemStorage {
     pub fn get_signature(&self, height: u64) -> Option<StateSignatureRequestBody> {
         self.pool.get(&height).cloned()
     }
+
+    /// The stored signatures at height `from` and up, in order of height.
+    pub fn signatures_from(&self, from: u64) -> Vec<StateSignatureRequestBody> {
+        let mut heights: Vec<_> = self
+            .pool
+            .keys()
+            .copied()
+            .filter(|height| *height >= from)
+            .collect();
+        heights.sort();
+        heights
+            .into_iter()
+            .map(|height| self.pool[&height].clone())
+            .collect()
+    }
 }
 
 /// Type for stake table commitment
@@ -1,18 +1,31 @@
 use super::{LightClientState, StateSignatureRequestBody};
+use crate::SeqTypes;
 use async_compatibility_layer::channel::OneShotReceiver;
-use async_std::sync::RwLock;
+use async_std::{
+    channel::{self, Receiver, Sender},
+    sync::{Arc, RwLock},
+    task::{sleep, spawn},
+};
 use clap::Args;
 use ethers::types::U256;
-use futures::FutureExt;
+use futures::{FutureExt, StreamExt};
+use hotshot_orchestrator::{
+    client::{OrchestratorClient, ValidatorArgs},
+    config::NetworkConfig,
+};
 use hotshot_stake_table::vec_based::config::FieldType;
-use hotshot_types::light_client::{
-    StateSignature, StateSignatureScheme, StateSignaturesBundle, StateVerKey,
+use hotshot_types::{
+    light_client::{StateSignature, StateSignatureScheme, StateSignaturesBundle, StateVerKey},
+    traits::{node_implementation::NodeType, signature_key::StakeTableEntryType},
 };
 use jf_primitives::signatures::SignatureScheme;
+use sequencer_utils::state_relay::{PendingState, QuorumProgress};
 use std::{
     collections::{BTreeSet, HashMap},
     path::PathBuf,
+    time::Duration,
 };
+use surf_disco::Client;
 use tide_disco::{
     api::ApiError,
     error::ServerError,
@@ -22,12 +35,20 @@ use tide_disco::{
 use url::Url;
 use vbs::version::StaticVersionType;
 
+/// Number of new signatures buffered for forwarding to peer relay servers.
+const FORWARD_BUFFER: usize = 1000;
+
+/// How long to wait before reconnecting to a sequencer node.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
 /// State that checks the light client state update and the signature collection
 #[derive(Default)]
 struct StateRelayServerState {
     /// Minimum weight to form an available state signature bundle
     threshold: U256,
     /// Stake table
+    ///
+    /// If empty, signatures from any key are accepted, with a weight of 1.
     known_nodes: HashMap<StateVerKey, U256>,
     /// Signatures bundles for each block height
     bundles: HashMap<u64, HashMap<LightClientState, StateSignaturesBundle>>,
@@ -40,6 +61,9 @@ struct StateRelayServerState {
     /// A ordered queue of block heights, used for garbage collection.
     queue: BTreeSet<u64>,
 
+    /// Newly accepted signatures, to forward to peer relay servers.
+    forward: Option<Sender<StateSignatureRequestBody>>,
+
     /// shutdown signal
     shutdown: Option<OneShotReceiver<()>>,
 }
@@ -52,6 +76,24 @@ impl StateRelayServerState {
         }
     }
 
+    /// Weigh signatures by stake, only accepting signatures from keys in `stake_table`.
+    ///
+    /// The threshold becomes two thirds of the total stake, the weight the prover needs.
+    pub fn with_stake_table(mut self, stake_table: HashMap<StateVerKey, U256>) -> Self {
+        let total = stake_table
+            .values()
+            .fold(U256::zero(), |total, stake| total + stake);
+        self.threshold = total * 2 / 3;
+        self.known_nodes = stake_table;
+        self
+    }
+
+    /// Send each newly accepted signature to `forward`.
+    fn with_forwarding(mut self, forward: Sender<StateSignatureRequestBody>) -> Self {
+        self.forward = Some(forward);
+        self
+    }
+
     pub fn with_shutdown_signal(mut self, shutdown_listener: Option<OneShotReceiver<()>>) -> Self {
         if self.shutdown.is_some() {
             panic!("A shutdown signal is already registered and can not be registered twice");
@@ -63,7 +105,7 @@ impl StateRelayServerState {
 
 // TODO(Chengyu): move this `RwLock` inside `StateRelayServerState` so that when nodes are submitting
 //                signatures, it won't block the prover from fetching the available signatures.
-type State = RwLock<StateRelayServerState>;
+type State = Arc<RwLock<StateRelayServerState>>;
 type Error = ServerError;
 
 pub trait StateRelayServerDataSource {
@@ -72,9 +114,16 @@ pub trait StateRelayServerDataSource {
     /// Errors if there's no available signatures bundle.
     fn get_latest_signature_bundle(&self) -> Result<StateSignaturesBundle, Error>;
 
+    /// Get the progress towards a quorum of signatures on the states newer than the latest
+    /// available one.
+    fn get_quorum_progress(&self) -> QuorumProgress;
+
     /// Post a signature to the relay server
+    ///
+    /// Posting a signature which was already collected, possibly through another relay server or
+    /// from the node directly, has no effect.
     /// # Errors
-    /// Errors if the signature is invalid, already posted, or no longer needed.
+    /// Errors if the signature is invalid or its key is not in the stake table.
     fn post_signature(
         &mut self,
         key: StateVerKey,
@@ -94,6 +143,32 @@ impl StateRelayServerDataSource for StateRelayServerState {
         }
     }
 
+    fn get_quorum_progress(&self) -> QuorumProgress {
+        let mut pending = self
+            .bundles
+            .iter()
+            .flat_map(|(block_height, bundles)| {
+                bundles.values().map(|bundle| PendingState {
+                    block_height: *block_height,
+                    view_number: bundle.state.view_number as u64,
+                    accumulated_weight: bundle.accumulated_weight,
+                    signers: bundle.signatures.len(),
+                })
+            })
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|state| (state.block_height, state.view_number));
+        QuorumProgress {
+            threshold: self.threshold,
+            total_weight: (!self.known_nodes.is_empty()).then(|| {
+                self.known_nodes
+                    .values()
+                    .fold(U256::zero(), |total, stake| total + stake)
+            }),
+            latest_available: self.latest_block_height,
+            pending,
+        }
+    }
+
     fn post_signature(
         &mut self,
         key: StateVerKey,
@@ -104,14 +179,14 @@ impl StateRelayServerDataSource for StateRelayServerState {
             // This signature is no longer needed
             return Ok(());
         }
-        let one = U256::one();
-        let weight = self.known_nodes.get(&key).unwrap_or(&one);
-        // TODO(Chengyu): We don't know where to fetch the stake table yet.
-        // Related issue: [https://github.com/EspressoSystems/espresso-sequencer/issues/1022]
-        // .ok_or(tide_disco::error::ServerError::catch_all(
-        //     StatusCode::Unauthorized,
-        //     "The posted key is not found in the stake table.".to_owned(),
-        // ))?;
+        let weight = if self.known_nodes.is_empty() {
+            U256::one()
+        } else {
+            *self.known_nodes.get(&key).ok_or(ServerError::catch_all(
+                StatusCode::Unauthorized,
+                "The posted key is not found in the stake table.".to_owned(),
+            ))?
+        };
         let state_msg: [FieldType; 7] = (&state).into();
         if StateSignatureScheme::verify(&(), &key, state_msg, &signature).is_err() {
             return Err(tide_disco::error::ServerError::catch_all(
@@ -128,26 +203,35 @@ impl StateRelayServerDataSource for StateRelayServerState {
         let bundle = bundles_at_height
             .entry(state.clone())
             .or_insert(StateSignaturesBundle {
-                state,
+                state: state.clone(),
                 signatures: Default::default(),
                 accumulated_weight: U256::from(0),
             });
-        tracing::debug!(
-            "Accepting new signature for block height {} from {}.",
-            block_height,
-            key
-        );
-        match bundle.signatures.entry(key) {
+        match bundle.signatures.entry(key.clone()) {
             std::collections::hash_map::Entry::Occupied(_) => {
-                // A signature is already posted for this key with this state
-                return Err(tide_disco::error::ServerError::catch_all(
-                    StatusCode::BadRequest,
-                    "A signature of this light client state is already posted at this block height for this key.".to_owned(),
-                ));
+                // This signature was already collected, through this or another relay server.
+                return Ok(());
             }
             std::collections::hash_map::Entry::Vacant(entry) => {
-                entry.insert(signature);
-                bundle.accumulated_weight += *weight;
+                tracing::debug!(
+                    "Accepting new signature for block height {} from {}.",
+                    block_height,
+                    key
+                );
+                entry.insert(signature.clone());
+                bundle.accumulated_weight += weight;
+            }
+        }
+        if let Some(forward) = &self.forward {
+            if forward
+                .try_send(StateSignatureRequestBody {
+                    key,
+                    state,
+                    signature,
+                })
+                .is_err()
+            {
+                tracing::warn!("Forwarding buffer is full, not forwarding signature.");
             }
         }
 
@@ -180,6 +264,19 @@ pub struct Options {
     pub api_path: Option<PathBuf>,
 }
 
+/// Where the relay server collects signatures from, and how it weighs them.
+#[derive(Clone, Debug, Default)]
+pub struct RelayConfig {
+    /// Weight needed to form an available bundle, if there is no stake table.
+    pub threshold: u64,
+    /// The state key and stake of each node, if known.
+    pub stake_table: Option<HashMap<StateVerKey, U256>>,
+    /// Sequencer nodes to pull signatures from, in addition to the signatures nodes push.
+    pub sequencers: Vec<Url>,
+    /// Other relay servers to forward new signatures to.
+    pub peers: Vec<Url>,
+}
+
 /// Set up APIs for relay server
 fn define_api<State, Ver: StaticVersionType + 'static>(
     options: &Options,
@@ -205,6 +302,9 @@ where
     api.get("getlateststate", |_req, state| {
         async move { state.get_latest_signature_bundle() }.boxed()
     })?
+    .get("getprogress", |_req, state| {
+        async move { Ok(state.get_quorum_progress()) }.boxed()
+    })?
     .post("poststatesignature", |req, state| {
         async move {
             let StateSignatureRequestBody {
@@ -222,9 +322,99 @@ where
     Ok(api)
 }
 
+/// The state key and stake of each node, from the network config of the orchestrator at `url`.
+pub async fn stake_table_from_orchestrator(url: Url) -> HashMap<StateVerKey, U256> {
+    let client = OrchestratorClient::new(ValidatorArgs {
+        url,
+        advertise_address: None,
+        network_config_file: None,
+    });
+    let config: NetworkConfig<
+        <SeqTypes as NodeType>::SignatureKey,
+        <SeqTypes as NodeType>::ElectionConfigType,
+    > = client.get_config_after_collection().await;
+    config
+        .config
+        .known_nodes_with_stake
+        .into_iter()
+        .map(|peer| (peer.state_ver_key, peer.stake_table_entry.get_stake()))
+        .collect()
+}
+
+/// Collect the signatures made by the sequencer node at `url`, forever.
+///
+/// Signatures are streamed from the node starting after the latest available state, reconnecting
+/// whenever the node is unreachable, so an unreachable node only withholds its own weight.
+async fn pull_signatures<Ver: StaticVersionType>(state: State, url: Url) {
+    let client = Client::<ServerError, Ver>::new(url.clone());
+    loop {
+        let from = state
+            .read()
+            .await
+            .latest_block_height
+            .map_or(0, |height| height + 1);
+        match client
+            .socket(&format!("state-signature/stream/{from}"))
+            .subscribe::<StateSignatureRequestBody>()
+            .await
+        {
+            Ok(mut signatures) => {
+                while let Some(res) = signatures.next().await {
+                    let body = match res {
+                        Ok(body) => body,
+                        Err(err) => {
+                            tracing::warn!(%url, "Error receiving signature from node: {err}");
+                            break;
+                        }
+                    };
+                    if let Err(err) =
+                        state
+                            .write()
+                            .await
+                            .post_signature(body.key, body.state, body.signature)
+                    {
+                        tracing::warn!(%url, "Rejected signature from node: {err}");
+                    }
+                }
+            }
+            Err(err) => {
+                tracing::warn!(%url, "Error subscribing to signatures from node: {err}");
+            }
+        }
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Forward each new signature to the relay servers at `peers`.
+///
+/// Peers only forward signatures they have not seen, so signatures do not circulate forever.
+async fn forward_signatures<Ver: StaticVersionType>(
+    mut signatures: Receiver<StateSignatureRequestBody>,
+    peers: Vec<Url>,
+) {
+    let peers = peers
+        .into_iter()
+        .map(|url| (Client::<ServerError, Ver>::new(url.clone()), url))
+        .collect::<Vec<_>>();
+    while let Some(body) = signatures.next().await {
+        for (client, url) in &peers {
+            let req = match client.post::<()>("api/state").body_binary(&body) {
+                Ok(req) => req,
+                Err(err) => {
+                    tracing::error!("Error serializing signature: {err}");
+                    break;
+                }
+            };
+            if let Err(err) = req.send().await {
+                tracing::warn!(%url, "Error forwarding signature to peer relay server: {err}");
+            }
+        }
+    }
+}
+
 pub async fn run_relay_server<Ver: StaticVersionType + 'static>(
     shutdown_listener: Option<OneShotReceiver<()>>,
-    threshold: u64,
+    config: RelayConfig,
     url: Url,
     bind_version: Ver,
 ) -> std::io::Result<()> {
@@ -232,11 +422,22 @@ pub async fn run_relay_server<Ver: StaticVersionType + 'static>(
 
     let api = define_api(&options, bind_version).unwrap();
 
-    // We don't have a stake table yet, putting some temporary value here.
-    // Related issue: [https://github.com/EspressoSystems/espresso-sequencer/issues/1022]
-    let threshold = U256::from(threshold);
-    let state =
-        State::new(StateRelayServerState::new(threshold).with_shutdown_signal(shutdown_listener));
+    let mut relay_state = match config.stake_table {
+        Some(stake_table) => StateRelayServerState::default().with_stake_table(stake_table),
+        None => StateRelayServerState::new(U256::from(config.threshold)),
+    };
+    if !config.peers.is_empty() {
+        let (sender, receiver) = channel::bounded(FORWARD_BUFFER);
+        relay_state = relay_state.with_forwarding(sender);
+        spawn(forward_signatures::<Ver>(receiver, config.peers));
+    }
+    let state = State::new(RwLock::new(
+        relay_state.with_shutdown_signal(shutdown_listener),
+    ));
+    for url in config.sequencers {
+        spawn(pull_signatures::<Ver>(state.clone(), url));
+    }
+
     let mut app = App::<State, Error>::with_state(state);
 
     app.register_module("api", api).unwrap();
@@ -245,3 +446,84 @@ pub async fn run_relay_server<Ver: StaticVersionType + 'static>(
 
     app_future.await
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{state_signature::form_light_client_state, Leaf, NodeState};
+    use hotshot_types::light_client::{CircuitField, StateKeyPair};
+
+    fn state_at(height: u64) -> LightClientState {
+        let mut leaf = Leaf::genesis(&NodeState::mock());
+        leaf.get_block_header_mut().height = height;
+        form_light_client_state(&leaf, &Default::default()).unwrap()
+    }
+
+    fn sign(key_pair: &StateKeyPair, state: &LightClientState) -> StateSignature {
+        let msg: [CircuitField; 7] = state.into();
+        StateSignatureScheme::sign(&(), key_pair.sign_key_ref(), msg, &mut rand::thread_rng())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_stake_weighted_aggregation() {
+        let keys = (0..4)
+            .map(|i| StateKeyPair::generate_from_seed_indexed([0; 32], i))
+            .collect::<Vec<_>>();
+        // The last node has as much stake as the other three together.
+        let stake_table = keys
+            .iter()
+            .zip([1, 1, 1, 3])
+            .map(|(key, stake)| (key.ver_key(), U256::from(stake)))
+            .collect();
+        let (forward, forwarded) = channel::unbounded();
+        let mut relay = StateRelayServerState::default()
+            .with_stake_table(stake_table)
+            .with_forwarding(forward);
+        assert_eq!(relay.threshold, U256::from(4));
+
+        let state = state_at(1);
+        relay
+            .post_signature(keys[0].ver_key(), state.clone(), sign(&keys[0], &state))
+            .unwrap();
+        relay
+            .post_signature(keys[1].ver_key(), state.clone(), sign(&keys[1], &state))
+            .unwrap();
+        relay.get_latest_signature_bundle().unwrap_err();
+
+        // The same signature arriving again, say through a peer relay server, is ignored.
+        relay
+            .post_signature(keys[1].ver_key(), state.clone(), sign(&keys[1], &state))
+            .unwrap();
+        assert_eq!(forwarded.len(), 2);
+        let progress = relay.get_quorum_progress();
+        assert_eq!(progress.total_weight, Some(U256::from(6)));
+        assert_eq!(progress.latest_available, None);
+        assert_eq!(
+            progress.pending,
+            [PendingState {
+                block_height: 1,
+                view_number: state.view_number as u64,
+                accumulated_weight: U256::from(2),
+                signers: 2,
+            }]
+        );
+
+        // Keys outside the stake table are rejected.
+        let outsider = StateKeyPair::generate_from_seed_indexed([1; 32], 0);
+        relay
+            .post_signature(outsider.ver_key(), state.clone(), sign(&outsider, &state))
+            .unwrap_err();
+
+        // The heavy node completes the quorum, even with one node unreachable.
+        relay
+            .post_signature(keys[3].ver_key(), state.clone(), sign(&keys[3], &state))
+            .unwrap();
+        let bundle = relay.get_latest_signature_bundle().unwrap();
+        assert_eq!(bundle.state, state);
+        assert_eq!(bundle.accumulated_weight, U256::from(5));
+        let progress = relay.get_quorum_progress();
+        assert_eq!(progress.latest_available, Some(1));
+        assert_eq!(progress.pending, []);
+    }
+}
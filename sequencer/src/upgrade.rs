@@ -0,0 +1,222 @@
+//! Protocol upgrades scheduled by governance on L1.
+//!
+//! Instead of every operator changing their configuration in lockstep, a new chain config can be
+//! scheduled by calling `scheduleUpgrade` on the `UpgradeGovernance` contract. Nodes watch the
+//! contract, whose address is part of the chain config, and switch to the new parameters from the
+//! activation height on.
+//!
+//! An upgrade only counts once the L1 block which scheduled it is finalized. The chain config for
+//! each block is determined by the upgrades scheduled as of the L1 block finalized by its parent,
+//! which consensus has already agreed on, so every node computes the same config for each block no
+//! matter when it learns of the upgrade. An upgrade which is scheduled after its activation height
+//! has passed takes effect at the next block.
+
+use crate::{l1_client::L1Client, ChainConfig};
+use async_std::sync::Mutex;
+use ethers::prelude::*;
+use std::cmp::min;
+
+/// The most L1 blocks to search for upgrades at once.
+const MAX_SCAN_RANGE: u64 = 10_000;
+
+/// The event emitted by the `UpgradeGovernance` contract when an upgrade is scheduled.
+#[derive(Clone, Debug, Default, PartialEq, Eq, EthEvent)]
+#[ethevent(
+    name = "UpgradeScheduled",
+    abi = "UpgradeScheduled(uint64,uint256,uint64,uint256)"
+)]
+pub struct UpgradeScheduledFilter {
+    pub activation_height: u64,
+    pub chain_id: U256,
+    pub max_block_size: u64,
+    pub base_fee: U256,
+}
+
+/// An upgrade scheduled on L1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScheduledUpgrade {
+    /// The L1 block in which the upgrade was scheduled.
+    pub l1_block: u64,
+    /// The first Espresso block height using the new chain config.
+    pub activation_height: u64,
+    pub chain_id: U256,
+    pub max_block_size: u64,
+    pub base_fee: U256,
+}
+
+impl ScheduledUpgrade {
+    fn new(event: UpgradeScheduledFilter, meta: LogMeta) -> Self {
+        Self {
+            l1_block: meta.block_number.as_u64(),
+            activation_height: event.activation_height,
+            chain_id: event.chain_id,
+            max_block_size: event.max_block_size,
+            base_fee: event.base_fee,
+        }
+    }
+
+    /// The chain config after applying this upgrade to `chain_config`.
+    pub fn apply(&self, chain_config: ChainConfig) -> ChainConfig {
+        chain_config.upgrade(self.chain_id, self.max_block_size, self.base_fee)
+    }
+}
+
+/// The upgrades scheduled on L1, fetched as they are needed.
+#[derive(Debug, Default)]
+pub struct UpgradeSchedule {
+    /// The first L1 block to search for upgrades.
+    start_block: u64,
+    state: Mutex<ScheduleState>,
+}
+
+#[derive(Debug, Default)]
+struct ScheduleState {
+    /// The last L1 block searched for upgrades.
+    scanned: Option<u64>,
+    /// Upgrades found so far, in the order they were scheduled.
+    upgrades: Vec<ScheduledUpgrade>,
+}
+
+impl UpgradeSchedule {
+    /// A schedule of upgrades scheduled in L1 block `start_block` or later.
+    ///
+    /// This should be the block the governance contract was deployed in, or any block before it.
+    pub fn new(start_block: u64) -> Self {
+        Self {
+            start_block,
+            state: Default::default(),
+        }
+    }
+
+    /// The chain config in effect at `height`, as of L1 block `l1_finalized`.
+    ///
+    /// Starting from `chain_config`, this applies the upgrade with the latest activation height up
+    /// to `height`, among those scheduled on the contract in `chain_config` up to L1 block
+    /// `l1_finalized`.
+    pub async fn chain_config(
+        &self,
+        l1: &L1Client,
+        chain_config: ChainConfig,
+        height: u64,
+        l1_finalized: Option<u64>,
+    ) -> ChainConfig {
+        let (Some(contract), Some(l1_finalized)) = (chain_config.upgrade_contract(), l1_finalized)
+        else {
+            return chain_config;
+        };
+
+        // Scan in chunks, since providers limit the range of a log query, and don't hold the lock
+        // across L1 requests, which retry until they succeed, so that a slow provider only holds up
+        // the callers which need the missing blocks.
+        loop {
+            let from = self.next_block(&*self.state.lock().await);
+            if from > l1_finalized {
+                break;
+            }
+            let to = min(l1_finalized, from + MAX_SCAN_RANGE - 1);
+            let logs = l1.get_upgrade_logs(contract, from, to).await;
+
+            let mut state = self.state.lock().await;
+            // Another caller may have scanned the same blocks in the meantime.
+            if self.next_block(&state) != from {
+                continue;
+            }
+            for (event, meta) in logs {
+                let upgrade = ScheduledUpgrade::new(event, meta);
+                tracing::info!(?upgrade, "found scheduled upgrade");
+                state.upgrades.push(upgrade);
+            }
+            state.scanned = Some(to);
+        }
+
+        let state = self.state.lock().await;
+        upgraded_chain_config(&state.upgrades, chain_config, height, l1_finalized)
+    }
+
+    /// The next L1 block to search for upgrades.
+    fn next_block(&self, state: &ScheduleState) -> u64 {
+        state.scanned.map_or(self.start_block, |block| block + 1)
+    }
+}
+
+fn upgraded_chain_config(
+    upgrades: &[ScheduledUpgrade],
+    chain_config: ChainConfig,
+    height: u64,
+    l1_finalized: u64,
+) -> ChainConfig {
+    // If several upgrades share an activation height, the one scheduled last wins.
+    upgrades
+        .iter()
+        .filter(|upgrade| upgrade.l1_block <= l1_finalized && upgrade.activation_height <= height)
+        .max_by_key(|upgrade| upgrade.activation_height)
+        .map(|upgrade| upgrade.apply(chain_config))
+        .unwrap_or(chain_config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::utils::Anvil;
+
+    fn upgrade(l1_block: u64, activation_height: u64, max_block_size: u64) -> ScheduledUpgrade {
+        ScheduledUpgrade {
+            l1_block,
+            activation_height,
+            chain_id: 1.into(),
+            max_block_size,
+            base_fee: 0.into(),
+        }
+    }
+
+    #[test]
+    fn test_upgraded_chain_config() {
+        let chain_config = ChainConfig::new(1, 1000, 0).with_upgrade_contract(Address::random());
+        let upgrades = [
+            upgrade(10, 100, 2000),
+            upgrade(20, 200, 3000),
+            upgrade(30, 200, 4000),
+        ];
+        let max_block_size = |height, l1_finalized| {
+            upgraded_chain_config(&upgrades, chain_config, height, l1_finalized).max_block_size()
+        };
+
+        // Nothing changes before the first activation height.
+        assert_eq!(max_block_size(99, 100), 1000);
+        assert_eq!(max_block_size(100, 100), 2000);
+        assert_eq!(max_block_size(199, 100), 2000);
+
+        // Upgrades only count once they are finalized on L1.
+        assert_eq!(max_block_size(100, 9), 1000);
+        assert_eq!(max_block_size(200, 20), 3000);
+        assert_eq!(max_block_size(200, 30), 4000);
+
+        // The upgraded config still watches the same contract.
+        let upgraded = upgraded_chain_config(&upgrades, chain_config, 200, 30);
+        assert_eq!(upgraded.upgrade_contract(), chain_config.upgrade_contract());
+        assert_ne!(upgraded, chain_config);
+    }
+
+    #[async_std::test]
+    async fn test_upgrade_schedule_scan() {
+        let anvil = Anvil::new().spawn();
+        let l1 = L1Client::new(anvil.endpoint().parse().unwrap(), Address::default());
+        let provider = Provider::<Http>::try_from(anvil.endpoint()).unwrap();
+        let head = 2 * MAX_SCAN_RANGE + 100;
+        provider
+            .request::<_, ()>("anvil_mine", [U256::from(head)])
+            .await
+            .unwrap();
+
+        // The whole range is scanned, in several queries, from the start block.
+        let chain_config = ChainConfig::new(1, 1000, 0).with_upgrade_contract(Address::random());
+        let schedule = UpgradeSchedule::new(50);
+        assert_eq!(
+            schedule
+                .chain_config(&l1, chain_config, 1, Some(head))
+                .await,
+            chain_config
+        );
+        assert_eq!(schedule.state.lock().await.scanned, Some(head));
+    }
+}
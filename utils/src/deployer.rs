@@ -1,44 +1,144 @@
-use anyhow::{ensure, Context};
-use async_std::sync::Arc;
+use anyhow::{anyhow, ensure, Context};
+use artifact::{ArtifactInfo, BytecodeSource};
+use async_std::{
+    channel::{unbounded, Receiver},
+    future::timeout,
+    sync::Arc,
+    task::sleep,
+};
 use clap::{builder::OsStr, Parser};
 use contract_bindings::{
-    light_client::LIGHTCLIENT_ABI, light_client_mock::LIGHTCLIENTMOCK_ABI,
+    erc1967_proxy::ERC1967Proxy,
+    fee_contract::FeeContract,
+    light_client::{LightClient, LIGHTCLIENT_ABI},
+    light_client_mock::LIGHTCLIENTMOCK_ABI,
     light_client_state_update_vk::LightClientStateUpdateVK,
-    light_client_state_update_vk_mock::LightClientStateUpdateVKMock, plonk_verifier::PlonkVerifier,
+    light_client_state_update_vk_mock::LightClientStateUpdateVKMock,
+    plonk_verifier::PlonkVerifier,
     shared_types::LightClientState,
 };
+use create2::Create2Config;
+use deposits::DepositInfo;
 use derive_more::Display;
-use ethers::{prelude::*, solc::artifacts::BytecodeObject};
-use futures::future::{BoxFuture, FutureExt};
+use dry_run::{placeholder_address, DeployMode, PlanStep};
+use error::{
+    DeployerError, DuplicateBytecodeSnafu, EmptyLibrarySnafu, LinkingSnafu, NotDeployedSnafu,
+    RevertedSnafu, UnresolvedPlaceholdersSnafu,
+};
+use ethers::{
+    abi::{Abi, Tokenize},
+    prelude::*,
+    providers::{JsonRpcError, MiddlewareError, RpcError as _},
+    solc::artifacts::BytecodeObject,
+    types::transaction::eip2718::TypedTransaction,
+    utils::{get_contract_address, keccak256, to_checksum},
+};
+use explorer::{fmt_address, fmt_tx, Explorer};
+use fees::GasConfig;
+use flavor::Flavor;
+use futures::future::{BoxFuture, Future, FutureExt, TryFutureExt};
 use hotshot_contract_adapter::light_client::ParsedLightClientState;
-use std::{collections::HashMap, io::Write, ops::Deref};
+use multichain::{ChainTargets, ScopedAddress};
+use params::LightClientDeployParams;
+use prover::{ProverConfig, ProverInfo};
+use receipt::{wait_for_receipt, ReceiptPolling};
+use report::UpgradeInfo;
+use safe::SafeBatch;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use state::StateFile;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, BufRead, Write},
+    ops::Deref,
+    path::Path,
+    str::FromStr,
+    sync::OnceLock,
+    time::Duration,
+};
+use strum::VariantArray;
+use unsigned::UnsignedOutput;
+use warnings::Warning;
+
+pub mod artifact;
+pub mod batch;
+pub mod chain;
+pub mod code;
+pub mod create2;
+pub mod deposits;
+pub mod diff;
+pub mod dry_run;
+pub mod error;
+pub mod explorer;
+pub mod fees;
+pub mod flavor;
+pub mod fork;
+pub mod gas;
+pub mod genesis;
+pub mod multichain;
+pub mod nonce;
+pub mod notify;
+pub mod output;
+pub mod ownership;
+pub mod params;
+pub mod prover;
+pub mod receipt;
+pub mod replace;
+pub mod report;
+pub mod safe;
+pub mod signer;
+pub mod stake_table;
+pub mod state;
+pub mod status;
+pub mod template;
+#[cfg(any(test, feature = "testing"))]
+pub mod test_helpers;
+pub mod unsigned;
+pub mod upgrade;
+pub mod verify;
+pub mod warnings;
 
 /// Set of predeployed contracts.
+///
+/// Each address may be scoped to a chain by prefixing it with the label of the chain, like
+/// `sepolia:0x...`. An unscoped address belongs to whichever chain its contract is deployed to.
 #[derive(Clone, Debug, Parser)]
 pub struct DeployedContracts {
     /// Use an already-deployed HotShot.sol instead of deploying a new one.
     #[clap(long, env = Contract::HotShot)]
-    hotshot: Option<Address>,
+    hotshot: Option<ScopedAddress>,
 
     /// Use an already-deployed PlonkVerifier.sol instead of deploying a new one.
     #[clap(long, env = Contract::PlonkVerifier)]
-    plonk_verifier: Option<Address>,
+    plonk_verifier: Option<ScopedAddress>,
 
     /// Use an already-deployed LightClientStateUpdateVK.sol instead of deploying a new one.
     #[clap(long, env = Contract::StateUpdateVK)]
-    light_client_state_update_vk: Option<Address>,
+    light_client_state_update_vk: Option<ScopedAddress>,
 
     /// Use an already-deployed LightClient.sol instead of deploying a new one.
     #[clap(long, env = Contract::LightClient)]
-    light_client: Option<Address>,
+    light_client: Option<ScopedAddress>,
 
     /// Use an already-deployed LightClient.sol proxy instead of deploying a new one.
     #[clap(long, env = Contract::LightClientProxy)]
-    light_client_proxy: Option<Address>,
+    light_client_proxy: Option<ScopedAddress>,
+
+    /// Use an already-deployed FeeContract.sol instead of deploying a new one.
+    #[clap(long, env = Contract::FeeContract)]
+    fee_contract: Option<ScopedAddress>,
+
+    /// Use an already-deployed FeeContract.sol proxy instead of deploying a new one.
+    #[clap(long, env = Contract::FeeContractProxy)]
+    fee_contract_proxy: Option<ScopedAddress>,
+
+    /// Use an already-deployed StakeTable.sol instead of deploying a new one.
+    #[clap(long, env = Contract::StakeTable)]
+    stake_table: Option<ScopedAddress>,
 }
 
 /// An identifier for a particular contract.
-#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, PartialOrd, Ord, Hash, VariantArray)]
 pub enum Contract {
     #[display(fmt = "ESPRESSO_SEQUENCER_HOTSHOT_ADDRESS")]
     HotShot,
@@ -50,6 +150,12 @@ pub enum Contract {
     LightClient,
     #[display(fmt = "ESPRESSO_SEQUENCER_LIGHT_CLIENT_PROXY_ADDRESS")]
     LightClientProxy,
+    #[display(fmt = "ESPRESSO_SEQUENCER_FEE_CONTRACT_ADDRESS")]
+    FeeContract,
+    #[display(fmt = "ESPRESSO_SEQUENCER_FEE_CONTRACT_PROXY_ADDRESS")]
+    FeeContractProxy,
+    #[display(fmt = "ESPRESSO_SEQUENCER_STAKE_TABLE_ADDRESS")]
+    StakeTable,
 }
 
 impl From<Contract> for OsStr {
@@ -58,64 +164,434 @@ impl From<Contract> for OsStr {
     }
 }
 
+impl FromStr for Contract {
+    type Err = anyhow::Error;
+
+    /// Parse a contract from its env var name, e.g. `ESPRESSO_SEQUENCER_HOTSHOT_ADDRESS`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::VARIANTS
+            .iter()
+            .find(|c| c.to_string() == s)
+            .copied()
+            .with_context(|| format!("unknown contract {s}"))
+    }
+}
+
+impl Serialize for Contract {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Contract {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
 /// Cache of contracts predeployed or deployed during this current run.
 #[derive(Debug, Clone, Default)]
-pub struct Contracts(HashMap<Contract, Address>);
+pub struct Contracts {
+    addresses: HashMap<Contract, Address>,
+    retry: RetryPolicy,
+    observers: Vec<Observer>,
+    explorer: Option<Explorer>,
+    state_file: Option<StateFile>,
+    mode: DeployMode,
+    plan: Vec<PlanStep>,
+    plan_init_codes: HashMap<Contract, Bytes>,
+    init_code_hashes: HashMap<H256, Contract>,
+    allow_duplicate_bytecode: bool,
+    warnings: Vec<Warning>,
+    create2: Option<Create2Config>,
+    receipt_polling: ReceiptPolling,
+    gas_config: GasConfig,
+    gas_used: Vec<(Contract, U256, U256)>,
+    flavor: Flavor,
+    flavors: HashMap<Contract, Flavor>,
+    cross_flavor: HashSet<Contract>,
+    unsigned: Option<UnsignedOutput>,
+    receipts: Vec<(Contract, TransactionReceipt)>,
+    init_codes: HashMap<Contract, Bytes>,
+    upgrades: Vec<UpgradeInfo>,
+    chain_id: Option<u64>,
+    chain_validated: bool,
+    nonce: Option<(Address, U256)>,
+    prover: Option<ProverInfo>,
+    deposits: Vec<DepositInfo>,
+    bytecode_sources: HashMap<LightClientArtifact, BytecodeSource>,
+    artifact_overrides: Vec<ArtifactInfo>,
+    light_client_params: LightClientDeployParams,
+    safe_batches: Vec<SafeBatch>,
+    prepare_owner_transactions: bool,
+}
 
-impl From<DeployedContracts> for Contracts {
-    fn from(deployed: DeployedContracts) -> Self {
-        let mut m = HashMap::new();
-        if let Some(addr) = deployed.hotshot {
-            m.insert(Contract::HotShot, addr);
+/// A step in a deployment, reported to observers registered with
+/// [`with_observer`](Contracts::with_observer) or [`subscribe`](Contracts::subscribe).
+///
+/// Every contract a deployment function is asked for produces either a [`Skipped`](Self::Skipped)
+/// event, or a [`Started`](Self::Started) event followed by [`Deployed`](Self::Deployed) or
+/// [`Failed`](Self::Failed), so consumers can account for all of them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DeployEvent {
+    /// Deployment of a contract has started.
+    Started { contract: Contract },
+    /// A contract was not deployed because it is already in the cache.
+    Skipped {
+        contract: Contract,
+        address: Address,
+    },
+    /// A deployment transaction was broadcast.
+    ///
+    /// If the transaction is retried or [replaced](replace), each attempt is reported with its own
+    /// hash.
+    TxSubmitted { contract: Contract, tx_hash: H256 },
+    /// A contract was deployed.
+    ///
+    /// `gas_used` is not known if the contract was deployed by a transaction from an earlier run,
+    /// or not actually deployed in [dry-run mode](DeployMode::DryRun).
+    Deployed {
+        contract: Contract,
+        address: Address,
+        gas_used: Option<U256>,
+    },
+    /// Deployment of a contract failed.
+    Failed { contract: Contract, error: String },
+}
+
+#[derive(Clone)]
+struct Observer(Arc<dyn Fn(&DeployEvent) + Send + Sync>);
+
+/// Log `event`, and pass it on to `observers`.
+fn emit(observers: &[Observer], explorer: Option<&Explorer>, event: DeployEvent) {
+    match &event {
+        DeployEvent::Started { contract } => tracing::info!("deploying {contract}"),
+        DeployEvent::Skipped { contract, address } => {
+            tracing::info!("skipping deployment of {contract}, already deployed at {address:#x}")
         }
-        if let Some(addr) = deployed.plonk_verifier {
-            m.insert(Contract::PlonkVerifier, addr);
+        DeployEvent::TxSubmitted { contract, tx_hash } => tracing::debug!(
+            "sent {contract} deployment transaction {}",
+            fmt_tx(explorer, *tx_hash)
+        ),
+        DeployEvent::Deployed {
+            contract, address, ..
+        } => tracing::info!("deployed {contract} at {}", fmt_address(explorer, *address)),
+        DeployEvent::Failed { .. } => {}
+    }
+    for observer in observers {
+        (observer.0)(&event);
+    }
+}
+
+impl std::fmt::Debug for Observer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+/// Policy for retrying deployment transactions which fail for transient reasons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of times to try sending a transaction, including the first attempt.
+    pub max_attempts: usize,
+    /// The delay before the first retry. The delay doubles after each subsequent attempt.
+    pub initial_backoff: Duration,
+    /// The maximum delay between attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
         }
-        if let Some(addr) = deployed.light_client_state_update_vk {
-            m.insert(Contract::StateUpdateVK, addr);
+    }
+}
+
+impl RetryPolicy {
+    /// The delay to wait after failed attempt number `attempt` (starting from 1).
+    fn delay(&self, attempt: usize) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1 << (attempt - 1).min(16) as u32)
+            .min(self.max_backoff)
+    }
+}
+
+impl DeployedContracts {
+    /// The predeployed address of each contract, if given.
+    fn addresses(&self) -> [(Contract, Option<&ScopedAddress>); 8] {
+        [
+            (Contract::HotShot, self.hotshot.as_ref()),
+            (Contract::PlonkVerifier, self.plonk_verifier.as_ref()),
+            (
+                Contract::StateUpdateVK,
+                self.light_client_state_update_vk.as_ref(),
+            ),
+            (Contract::LightClient, self.light_client.as_ref()),
+            (Contract::LightClientProxy, self.light_client_proxy.as_ref()),
+            (Contract::FeeContract, self.fee_contract.as_ref()),
+            (Contract::FeeContractProxy, self.fee_contract_proxy.as_ref()),
+            (Contract::StakeTable, self.stake_table.as_ref()),
+        ]
+    }
+
+    /// The cache of contracts predeployed on the chain `label`, where `targets` says which chain
+    /// each contract is deployed to.
+    ///
+    /// This includes addresses scoped to `label`, and unscoped addresses of contracts deployed to
+    /// `label`.
+    pub fn for_chain(&self, label: &str, targets: &ChainTargets) -> Contracts {
+        let addresses = self
+            .addresses()
+            .into_iter()
+            .filter_map(|(contract, addr)| {
+                let addr = addr?;
+                addr.belongs_to(label, targets.target(contract))
+                    .then_some((contract, addr.address))
+            })
+            .collect();
+        Contracts {
+            addresses,
+            ..Default::default()
         }
-        if let Some(addr) = deployed.light_client {
-            m.insert(Contract::LightClient, addr);
+    }
+
+    /// Sanity check the predeployed addresses.
+    ///
+    /// The zero address is rejected, since it is never a deployed contract. An address given in
+    /// mixed case which is not a valid EIP-55 checksum is likely a typo, and is reported as a
+    /// [`Warning::BadChecksum`]. Errors and warnings name the env var of the contract, so the
+    /// operator knows which setting to fix.
+    pub fn validate(&self) -> anyhow::Result<Vec<Warning>> {
+        let mut warnings = vec![];
+        for (contract, addr) in self.addresses() {
+            let Some(addr) = addr else {
+                continue;
+            };
+            ensure!(
+                !addr.address.is_zero(),
+                "{contract} is the zero address, which cannot be a predeployed {contract:?}"
+            );
+            if !addr.checksum_valid() {
+                warnings.push(Warning::BadChecksum {
+                    contract,
+                    address: addr.address,
+                });
+            }
         }
-        if let Some(addr) = deployed.light_client_proxy {
-            m.insert(Contract::LightClientProxy, addr);
+        Ok(warnings)
+    }
+
+    /// The labels of all chains which predeployed addresses are explicitly scoped to.
+    pub fn scoped_chains(&self) -> Vec<&str> {
+        let mut chains: Vec<_> = self
+            .addresses()
+            .into_iter()
+            .filter_map(|(_, addr)| addr?.chain.as_deref())
+            .collect();
+        chains.sort();
+        chains.dedup();
+        chains
+    }
+}
+
+impl Serialize for Contracts {
+    /// Serialize the addresses in the cache as a map from the env var of each contract to its
+    /// checksummed address, as in [`write_json`](Self::write_json).
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_map(
+            self.sorted()
+                .into_iter()
+                .map(|(contract, address)| (contract, to_checksum(&address, None))),
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for Contracts {
+    /// Deserialize a cache of predeployed contracts from a map from the env var of each contract to
+    /// its address.
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            addresses: HashMap::deserialize(d)?,
+            ..Default::default()
+        })
+    }
+}
+
+impl From<DeployedContracts> for Contracts {
+    /// The cache of predeployed contracts, when deploying to a single chain.
+    ///
+    /// Addresses scoped to a chain are ignored; use [`DeployedContracts::for_chain`] to include
+    /// them.
+    fn from(deployed: DeployedContracts) -> Self {
+        let addresses = deployed
+            .addresses()
+            .into_iter()
+            .filter_map(|(contract, addr)| {
+                let addr = addr?;
+                addr.chain.is_none().then_some((contract, addr.address))
+            })
+            .collect();
+        Self {
+            addresses,
+            ..Default::default()
         }
-        Self(m)
     }
 }
 
 impl Contracts {
+    /// Retry deployment transactions which fail for transient reasons.
+    ///
+    /// Each deployment transaction will be sent up to `max_attempts` times, with exponential
+    /// backoff between attempts. Failures which will not go away by retrying, such as the
+    /// constructor reverting, are never retried.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = RetryPolicy {
+            max_attempts: retry.max_attempts.max(1),
+            ..retry
+        };
+        self
+    }
+
+    /// Configure how to wait for deployment transactions to be mined.
+    pub fn with_receipt_polling(mut self, polling: ReceiptPolling) -> Self {
+        self.receipt_polling = polling;
+        self
+    }
+
+    /// Wait for `confirmations` blocks, including the one it was mined in, before considering a
+    /// transaction mined.
+    ///
+    /// This applies to every transaction sent, including proxy deployments (which initialize the
+    /// proxy) and ownership transfers, and a deployed contract is only added to the cache once its
+    /// transaction is confirmed. A transaction which is reorged out while waiting is waited for
+    /// again, and resent if it is dropped. The default is 1. With 0, a transaction is considered
+    /// mined as soon as there is a receipt.
+    pub fn with_confirmations(mut self, confirmations: usize) -> Self {
+        self.receipt_polling.confirmations = confirmations;
+        self
+    }
+
+    /// Give up on a transaction which is not mined and confirmed within `timeout`.
+    pub fn with_pending_timeout(mut self, timeout: Duration) -> Self {
+        self.receipt_polling.timeout = timeout;
+        self
+    }
+
+    /// Link to deployed contracts and transactions on `explorer` in logs and error messages.
+    pub fn with_explorer(mut self, explorer: Explorer) -> Self {
+        self.explorer = Some(explorer);
+        self
+    }
+
+    /// The block explorer used for links, if any.
+    pub fn explorer(&self) -> Option<&Explorer> {
+        self.explorer.as_ref()
+    }
+
+    /// Call `observer` with each [`DeployEvent`] in deployments using this cache.
+    ///
+    /// Each event is also logged, whether or not there are observers.
+    pub fn with_observer(
+        mut self,
+        observer: impl Fn(&DeployEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.observers.push(Observer(Arc::new(observer)));
+        self
+    }
+
+    /// Receive each [`DeployEvent`] in deployments using this cache from a channel.
+    ///
+    /// Unlike an [observer](Self::with_observer), the receiver does not need to live as long as
+    /// the cache, so this is convenient for consuming events in a scope which borrows local state,
+    /// like a task rendering progress. Events sent after the receiver is dropped are discarded.
+    pub fn subscribe(&mut self) -> Receiver<DeployEvent> {
+        let (tx, rx) = unbounded();
+        self.observers.push(Observer(Arc::new(move |event| {
+            tx.try_send(event.clone()).ok();
+        })));
+        rx
+    }
+
+    fn emit(&self, event: DeployEvent) {
+        emit(&self.observers, self.explorer.as_ref(), event);
+    }
+
     /// Deploy a contract by calling a function.
     ///
     /// The `deploy` function will be called only if contract `name` is not already deployed;
     /// otherwise this function will just return the predeployed address. The `deploy` function may
     /// access this [`Contracts`] object, so this can be used to deploy contracts recursively in
     /// dependency order.
+    ///
+    /// A cached contract deployed by a deployment of a different [`Flavor`] is not reused; see
+    /// [`flavor`] for details.
+    ///
+    /// A [`DeployerError`] returned by `deploy` as an [`anyhow::Error`] is passed through as is.
     pub async fn deploy_fn(
         &mut self,
         name: Contract,
         deploy: impl FnOnce(&mut Self) -> BoxFuture<'_, anyhow::Result<Address>>,
-    ) -> anyhow::Result<Address> {
-        if let Some(addr) = self.0.get(&name) {
-            tracing::info!("skipping deployment of {name}, already deployed at {addr:#x}");
-            return Ok(*addr);
+    ) -> Result<Address, DeployerError> {
+        if let Some(&addr) = self.addresses.get(&name) {
+            self.check_flavor(name)?;
+            if self.mode == DeployMode::DryRun {
+                self.plan.push(PlanStep::Skip {
+                    contract: name,
+                    address: addr,
+                });
+            }
+            self.emit(DeployEvent::Skipped {
+                contract: name,
+                address: addr,
+            });
+            return Ok(addr);
         }
-        tracing::info!("deploying {name}");
-        let addr = deploy(self).await?;
-        tracing::info!("deployed {name} at {addr:#x}");
-
-        self.0.insert(name, addr);
+        self.emit(DeployEvent::Started { contract: name });
+        let addr = match deploy(self).await {
+            Ok(addr) => addr,
+            Err(err) => {
+                self.emit(DeployEvent::Failed {
+                    contract: name,
+                    error: format!("{err:#}"),
+                });
+                return Err(err.into());
+            }
+        };
+        self.record_deployed(name, addr)?;
         Ok(addr)
     }
 
+    /// Add a newly deployed contract to the cache, and to the state file if there is one.
+    fn record_deployed(&mut self, name: Contract, addr: Address) -> anyhow::Result<()> {
+        self.emit(DeployEvent::Deployed {
+            contract: name,
+            address: addr,
+            gas_used: self.receipt(name).and_then(|receipt| receipt.gas_used),
+        });
+
+        self.addresses.insert(name, addr);
+        self.flavors.insert(name, self.flavor);
+        if let (Some(state), DeployMode::Execute) = (&self.state_file, self.mode) {
+            state.save(self)?;
+        }
+        Ok(())
+    }
+
     /// Deploy a contract by executing its deploy transaction.
     ///
-    /// The transaction will only be broadcast if contract `name` is not already deployed.
+    /// The transaction will only be broadcast if contract `name` is not already deployed. If
+    /// retries are enabled (see [`with_retry_policy`](Self::with_retry_policy)) the transaction is
+    /// resent on transient failures.
     pub async fn deploy_tx<M, C>(
         &mut self,
         name: Contract,
         tx: ContractDeployer<M, C>,
-    ) -> anyhow::Result<Address>
+    ) -> Result<Address, DeployerError>
     where
         M: Middleware + 'static,
         C: Deref<Target = ethers::contract::Contract<M>>
@@ -123,23 +599,839 @@ impl Contracts {
             + Send
             + 'static,
     {
-        self.deploy_fn(name, |_| {
-            async {
-                let contract = tx.send().await?;
-                Ok(contract.address())
+        self.deploy_fn(name, |contracts| {
+            async move {
+                contracts
+                    .send_tx(name, tx.client(), tx.deployer.tx.clone())
+                    .await
             }
             .boxed()
         })
         .await
     }
 
+    /// Send a deployment transaction for `name`, regardless of whether it is already deployed.
+    ///
+    /// In [dry-run mode](DeployMode::DryRun), the transaction is not sent. Instead, its gas usage is
+    /// estimated and recorded in the [`plan`](Self::plan), and a placeholder address is returned,
+    /// unless the transaction is [written out unsigned](Self::with_unsigned_output), in which case
+    /// the address it will deploy to is returned.
+    async fn send_tx<M: Middleware + 'static>(
+        &mut self,
+        name: Contract,
+        client: &M,
+        tx: TypedTransaction,
+    ) -> anyhow::Result<Address> {
+        self.validate(client).await?;
+        match self.mode {
+            DeployMode::Execute => {
+                self.check_duplicate(name, tx.data().map(|data| &data[..]).unwrap_or_default())?;
+                let sender = tx.from().copied().or_else(|| client.default_sender());
+                let mut nonce = self.next_nonce(client, sender).await?;
+                let gas = self.gas_config.for_chain(client).await;
+                let observers = &self.observers;
+                let explorer = self.explorer.as_ref();
+                let (addr, receipt) = send_deploy_tx(
+                    client,
+                    name,
+                    tx,
+                    &mut nonce,
+                    self.retry,
+                    self.receipt_polling,
+                    gas,
+                    explorer,
+                    &|tx_hash| {
+                        emit(
+                            observers,
+                            explorer,
+                            DeployEvent::TxSubmitted {
+                                contract: name,
+                                tx_hash,
+                            },
+                        )
+                    },
+                )
+                .await?;
+                if let Some(nonce) = nonce {
+                    self.advance_nonce(sender, nonce + 1);
+                }
+                if let Some(receipt) = receipt {
+                    self.record_receipt(name, &receipt);
+                }
+                Ok(addr)
+            }
+            DeployMode::DryRun => {
+                let step = self.estimate(name, client, &tx).await;
+                let gas = step.gas();
+                self.plan.push(step);
+                self.plan_init_codes
+                    .insert(name, tx.data().cloned().unwrap_or_default());
+                if self.unsigned.is_some() {
+                    return self
+                        .write_unsigned(&format!("{name:?}"), client, tx, gas)
+                        .await;
+                }
+                Ok(placeholder_address(name))
+            }
+        }
+    }
+
+    /// Allow deploying identical init code (bytecode and constructor arguments) under different
+    /// contract names in the same run.
+    ///
+    /// By default, this is refused, since it usually means the same contract is being deployed
+    /// twice by mistake, wasting gas. It is legitimate, though, for example when deploying two
+    /// identical mock contracts.
+    pub fn allow_duplicate_bytecode(mut self, allow: bool) -> Self {
+        self.allow_duplicate_bytecode = allow;
+        self
+    }
+
+    /// Check that `init_code` has not already been deployed under a different name in this run.
+    ///
+    /// This must be called before every deployment transaction is broadcast. It records the hash of
+    /// `init_code`, so that later deployments of the same init code can be detected, and the init
+    /// code itself, so the contract can be [verified](verify) on a block explorer.
+    fn check_duplicate(&mut self, name: Contract, init_code: &[u8]) -> anyhow::Result<()> {
+        self.init_codes
+            .insert(name, Bytes::from(init_code.to_vec()));
+        let hash = H256(keccak256(init_code));
+        match self.init_code_hashes.get(&hash) {
+            Some(&prev) if prev != name && !self.allow_duplicate_bytecode => {
+                tracing::error!(
+                    "refusing to deploy {name}: identical init code {hash:#x} was already \
+                     deployed as {prev}"
+                );
+                return Err(DuplicateBytecodeSnafu {
+                    contract: name,
+                    original: prev,
+                }
+                .build()
+                .into());
+            }
+            Some(&prev) if prev != name => {
+                tracing::info!("deploying {name} with init code {hash:#x}, as explicitly allowed");
+                self.warn(Warning::DuplicateBytecode {
+                    contract: name,
+                    original: prev,
+                });
+            }
+            _ => {
+                tracing::debug!("recording init code {hash:#x} for {name}");
+                self.init_code_hashes.insert(hash, name);
+            }
+        }
+        Ok(())
+    }
+
+    /// The address of `name`, if it is deployed or was given as predeployed.
+    pub fn address(&self, name: Contract) -> Option<Address> {
+        self.addresses.get(&name).copied()
+    }
+
+    /// The address of `name`, or an error naming the setting to give it with if it is not in the
+    /// cache.
+    pub fn get_or_err(&self, name: Contract) -> Result<Address, DeployerError> {
+        match self.address(name) {
+            Some(addr) => Ok(addr),
+            None => NotDeployedSnafu { contract: name }.fail(),
+        }
+    }
+
+    /// Whether `name` is deployed or was given as predeployed.
+    pub fn contains(&self, name: Contract) -> bool {
+        self.addresses.contains_key(&name)
+    }
+
+    /// All the contracts in the cache, sorted by the name of their env var.
+    pub fn iter(&self) -> impl Iterator<Item = (Contract, Address)> {
+        self.sorted().into_iter()
+    }
+
+    /// The number of contracts in the cache.
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Add `name` to the cache at `address`, as if it were predeployed.
+    ///
+    /// Returns the address previously in the cache, if any.
+    pub fn insert(&mut self, name: Contract, address: Address) -> Option<Address> {
+        self.addresses.insert(name, address)
+    }
+
+    /// Remove `name` from the cache, so that it is deployed again.
+    ///
+    /// Returns the address which was in the cache, if any.
+    pub fn remove(&mut self, name: Contract) -> Option<Address> {
+        self.addresses.remove(&name)
+    }
+
+    /// The contracts in the cache, sorted by name, so that output is deterministic.
+    fn sorted(&self) -> Vec<(Contract, Address)> {
+        let mut contracts: Vec<_> = self.addresses.iter().map(|(&c, &a)| (c, a)).collect();
+        contracts.sort_by_cached_key(|(contract, _)| contract.to_string());
+        contracts
+    }
+
     /// Write a .env file.
     pub fn write(&self, mut w: impl Write) -> anyhow::Result<()> {
-        for (contract, address) in &self.0 {
+        for (contract, address) in self.sorted() {
             writeln!(w, "{contract}={address:#x}")?;
         }
         Ok(())
     }
+
+    /// Write a human-readable summary table of the contracts, with explorer links if available.
+    ///
+    /// The table is followed by a section listing any [warnings](Self::warnings).
+    pub fn write_summary(&self, mut w: impl Write) -> anyhow::Result<()> {
+        for (contract, address) in self.sorted() {
+            writeln!(
+                w,
+                "{:<20} {}",
+                format!("{contract:?}"),
+                fmt_address(self.explorer.as_ref(), address)
+            )?;
+        }
+        self.write_warnings(w)
+    }
+
+    /// Write a JSON object mapping the env var name of each contract to its checksummed address.
+    ///
+    /// If any [warnings](Self::warnings) were recorded, they are listed in an additional
+    /// `warnings` array.
+    pub fn write_json(&self, w: impl Write) -> anyhow::Result<()> {
+        let mut map: serde_json::Map<_, _> = self
+            .sorted()
+            .into_iter()
+            .map(|(contract, address)| (contract.to_string(), to_checksum(&address, None).into()))
+            .collect();
+        if !self.warnings.is_empty() {
+            map.insert("warnings".into(), serde_json::to_value(&self.warnings)?);
+        }
+        serde_json::to_writer_pretty(w, &map)?;
+        Ok(())
+    }
+
+    /// Read a .env file, as written by [`write`](Self::write).
+    ///
+    /// Blank lines and lines starting with `#` are ignored, as are keys which do not name a
+    /// [`Contract`], after logging a warning. Any line which is not of the form `KEY=VALUE`, or
+    /// which has a malformed address, is an error.
+    pub fn read(r: impl BufRead) -> anyhow::Result<Self> {
+        let mut contracts = Self::default();
+        for (i, line) in r.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.with_context(|| format!("error reading line {line_no}"))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("line {line_no}: expected KEY=VALUE, got {line}"))?;
+            let (key, value) = (key.trim(), value.trim().trim_matches('"'));
+            let Ok(contract) = key.parse::<Contract>() else {
+                tracing::warn!("line {line_no}: skipping unknown key {key}");
+                continue;
+            };
+            let address = value
+                .parse()
+                .with_context(|| format!("line {line_no}: invalid address for {key}: {value}"))?;
+            contracts.addresses.insert(contract, address);
+        }
+        Ok(contracts)
+    }
+
+    /// Update the contract addresses in the .env file at `path`, leaving everything else as is.
+    ///
+    /// Each line setting the address of a contract in the cache is replaced with its current
+    /// address, and contracts which have no line yet are appended. Every other line, including
+    /// comments, other configuration, and addresses of contracts which are not in the cache, keeps
+    /// its content and position. If the file does not exist, it is created.
+    pub fn update_env_file(&self, path: &Path) -> anyhow::Result<()> {
+        let existing = match fs::read_to_string(path) {
+            Ok(existing) => existing,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("error reading {}", path.display()))
+            }
+        };
+        let mut updated = HashSet::new();
+        let mut lines: Vec<String> = existing
+            .lines()
+            .map(|line| {
+                let contract = line
+                    .split_once('=')
+                    .and_then(|(key, _)| key.trim().parse::<Contract>().ok());
+                match contract.and_then(|contract| Some((contract, self.address(contract)?))) {
+                    Some((contract, address)) => {
+                        updated.insert(contract);
+                        format!("{contract}={address:#x}")
+                    }
+                    None => line.to_string(),
+                }
+            })
+            .collect();
+        for (contract, address) in self.sorted() {
+            if !updated.contains(&contract) {
+                lines.push(format!("{contract}={address:#x}"));
+            }
+        }
+
+        let mut contents = lines.join("\n");
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        fs::write(path, contents).with_context(|| format!("error writing {}", path.display()))
+    }
+}
+
+/// A failed attempt to send a deployment transaction.
+enum SendError {
+    /// A failure which may go away if the transaction is resent, like a network error.
+    Transient(anyhow::Error),
+    /// A failure which will recur no matter how many times we try, like a constructor revert.
+    Fatal(anyhow::Error),
+}
+
+/// Whether an RPC error (if any) indicates a failure worth retrying.
+fn is_transient(resp: Option<&JsonRpcError>) -> bool {
+    match resp {
+        // Transport-level errors (timeouts, connection resets, rate limiting) are transient.
+        None => true,
+        // Reverts are deterministic; so is running out of funds, at least on the time scale of our
+        // retries. Everything else (nonce too low, underpriced replacement, etc.) may resolve.
+        Some(resp) => !resp.is_revert() && !resp.message.contains("insufficient funds"),
+    }
+}
+
+/// Send a deployment transaction, retrying transient failures according to `retry`.
+///
+/// The nonce is re-fetched before every attempt. If a previous attempt landed after all (for
+/// example if we timed out waiting for a receipt) we return the address it deployed to instead of
+/// sending again. Otherwise, we reuse the nonce of the previous attempt as long as it has not been
+/// consumed, so that at most one copy of the contract can ever be deployed. The gas limit (scaled
+/// as configured in `gas`), and any fees not set by `gas`, are estimated afresh for each attempt.
+/// Before each attempt, we wait for the base fee to come within the configured cap, if any.
+///
+/// `nonce` is the nonce of an attempt made before calling this function, if any, which is treated
+/// like a previous attempt of our own, or a [managed nonce](Contracts::with_managed_nonce) which
+/// has not been mined yet. On success, it is the nonce of the attempt which landed.
+///
+/// Returns the address of the contract, and the receipt of the deployment transaction, unless the
+/// contract turned out to have been deployed by an earlier attempt, whose receipt we never saw.
+#[allow(clippy::too_many_arguments)]
+async fn send_deploy_tx<M: Middleware + 'static>(
+    client: &M,
+    name: Contract,
+    tx: TypedTransaction,
+    nonce: &mut Option<U256>,
+    retry: RetryPolicy,
+    polling: ReceiptPolling,
+    gas: GasConfig,
+    explorer: Option<&Explorer>,
+    on_submit: &(dyn Fn(H256) + Sync),
+) -> anyhow::Result<(Address, Option<TransactionReceipt>)> {
+    let sender = tx.from().copied().or_else(|| client.default_sender());
+    for attempt in 1..=retry.max_attempts {
+        let res = match prepare_attempt(client, sender, nonce).await {
+            Ok(Some(addr)) => {
+                tracing::info!("{name} deployment from a previous attempt landed at {addr:#x}");
+                return Ok((addr, None));
+            }
+            Ok(None) => {
+                let nonce = *nonce;
+                async {
+                    gas.wait_for_base_fee(client, polling.interval).await?;
+                    let mut tx = tx.clone();
+                    if let Some(nonce) = nonce {
+                        tx.set_nonce(nonce);
+                    }
+                    gas.apply(&mut tx);
+                    gas.scale_gas_limit(client, &mut tx).await;
+                    tracing::info!(
+                        "sending {name} deployment transaction (attempt {attempt}/{}, nonce {})",
+                        retry.max_attempts,
+                        nonce.map_or("auto".into(), |n| n.to_string()),
+                    );
+                    send_deploy_tx_once(client, name, tx, polling, gas, explorer, on_submit).await
+                }
+                .await
+            }
+            // If we can't tell whether a previous attempt landed, it is not safe to send again.
+            // Try again later, when the RPC is hopefully in better shape.
+            Err(err) => Err(SendError::Transient(err)),
+        };
+        match res {
+            Ok((addr, receipt)) => return Ok((addr, Some(receipt))),
+            Err(SendError::Transient(err)) if attempt < retry.max_attempts => {
+                let delay = retry.delay(attempt);
+                tracing::warn!(
+                    "{name} deployment failed (attempt {attempt}/{}), retrying in {delay:?}: {err:#}",
+                    retry.max_attempts
+                );
+                sleep(delay).await;
+            }
+            Err(SendError::Transient(err) | SendError::Fatal(err)) => {
+                return Err(DeployerError::classify::<M>(name, err).into());
+            }
+        }
+    }
+    unreachable!("retry loop always returns on the last attempt")
+}
+
+/// Clear the gas limit and gas price of `tx`, so the middleware estimates them when sending.
+fn clear_gas(tx: &mut TypedTransaction) {
+    match tx {
+        TypedTransaction::Legacy(tx) => {
+            tx.gas = None;
+            tx.gas_price = None;
+        }
+        TypedTransaction::Eip2930(tx) => {
+            tx.tx.gas = None;
+            tx.tx.gas_price = None;
+        }
+        TypedTransaction::Eip1559(tx) => {
+            tx.gas = None;
+            tx.max_fee_per_gas = None;
+            tx.max_priority_fee_per_gas = None;
+        }
+    }
+}
+
+/// Prepare for an attempt to send a deployment transaction.
+///
+/// Determines the nonce to use for the attempt. If a previous attempt already used `nonce` and
+/// successfully deployed the contract, returns the address of the deployed contract.
+async fn prepare_attempt<M: Middleware + 'static>(
+    client: &M,
+    sender: Option<Address>,
+    nonce: &mut Option<U256>,
+) -> anyhow::Result<Option<Address>> {
+    // Without a known sender, we can neither predict the deployed address nor manage the nonce, so
+    // just let the middleware fill in the nonce.
+    let Some(sender) = sender else {
+        return Ok(None);
+    };
+    if let Some(n) = *nonce {
+        let addr = get_contract_address(sender, n);
+        let code = client
+            .get_code(addr, None)
+            .await
+            .context("error checking for deployed code")?;
+        if !code.is_empty() {
+            return Ok(Some(addr));
+        }
+        let latest = client
+            .get_transaction_count(sender, None)
+            .await
+            .context("error fetching nonce")?;
+        if latest > n {
+            // The nonce was consumed by some other transaction, or our transaction was mined but
+            // did not create a contract. Either way, the next attempt needs a fresh nonce.
+            *nonce = None;
+        }
+    }
+    if nonce.is_none() {
+        let pending = client
+            .get_transaction_count(sender, Some(BlockNumber::Pending.into()))
+            .await
+            .context("error fetching nonce")?;
+        *nonce = Some(pending);
+    }
+    Ok(None)
+}
+
+/// Send the deployment transaction of `name` once and wait for it to be mined.
+///
+/// If `gas` has a [`tx_timeout`](GasConfig::tx_timeout), a transaction which is stuck for that
+/// long is [replaced](replace) with a higher fee, at the same nonce.
+async fn send_deploy_tx_once<M: Middleware + 'static>(
+    client: &M,
+    name: Contract,
+    tx: TypedTransaction,
+    polling: ReceiptPolling,
+    gas: GasConfig,
+    explorer: Option<&Explorer>,
+    on_submit: &(dyn Fn(H256) + Sync),
+) -> Result<(Address, TransactionReceipt), SendError> {
+    let kind = format!("{name:?} deployment");
+    let receipt =
+        replace::send_replaceable(client, tx, gas, polling, explorer, &kind, on_submit).await?;
+    Ok((deployed_address(name, &receipt, explorer)?, receipt))
+}
+
+/// The address of the contract created by the deployment transaction of `name`.
+fn deployed_address(
+    name: Contract,
+    receipt: &TransactionReceipt,
+    explorer: Option<&Explorer>,
+) -> Result<Address, SendError> {
+    receipt.contract_address.ok_or_else(|| {
+        SendError::Fatal(anyhow!(
+            "{name:?} deployment did not create a contract (tx {})",
+            fmt_tx(explorer, receipt.transaction_hash)
+        ))
+    })
+}
+
+/// Send a transaction once and wait for it to be mined successfully.
+///
+/// `kind` describes the transaction in error messages, like `HotShot deployment`. Every error
+/// after the transaction is broadcast includes its hash, so it can be looked up.
+async fn send_tx_once<M: Middleware + 'static>(
+    client: &M,
+    tx: TypedTransaction,
+    polling: ReceiptPolling,
+    explorer: Option<&Explorer>,
+    kind: &str,
+) -> Result<TransactionReceipt, SendError> {
+    let sender = tx.from().copied().or_else(|| client.default_sender());
+    let nonce = tx.nonce().copied();
+    let tx_hash = broadcast_tx(client, tx, kind).await?;
+    await_tx(client, tx_hash, sender, nonce, polling, explorer, kind).await
+}
+
+/// Send a transaction other than a deployment, retrying transient failures according to the
+/// [retry policy](Contracts::with_retry_policy) of `contracts`.
+///
+/// The transaction is built afresh for each attempt by `next_tx`, which is given the number of the
+/// attempt, starting from 1. It should check whether the transaction is still needed, so that an
+/// attempt which landed after all (for example if we timed out waiting for its receipt) is not
+/// repeated, and return `None` if not. Before each attempt, we wait for the base fee to come within
+/// the configured cap, if any. Errors name the transaction by `what`.
+///
+/// Returns the receipt of the transaction, or `None` if `next_tx` found nothing left to send.
+async fn send_with_retry<M, F, Fut>(
+    client: &M,
+    contracts: &Contracts,
+    what: &str,
+    mut next_tx: F,
+) -> anyhow::Result<Option<TransactionReceipt>>
+where
+    M: Middleware + 'static,
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = anyhow::Result<Option<TypedTransaction>>>,
+{
+    let retry = contracts.retry;
+    let polling = contracts.receipt_polling;
+    let explorer = contracts.explorer();
+    for attempt in 1..=retry.max_attempts {
+        let Some(tx) = next_tx(attempt).await? else {
+            return Ok(None);
+        };
+        tracing::info!("sending {what} (attempt {attempt}/{})", retry.max_attempts);
+        let res = async {
+            contracts
+                .gas_config
+                .wait_for_base_fee(client, polling.interval)
+                .await?;
+            send_tx_once(client, tx, polling, explorer, what).await
+        }
+        .await;
+        match res {
+            Ok(receipt) => return Ok(Some(receipt)),
+            Err(SendError::Transient(err)) if attempt < retry.max_attempts => {
+                let delay = retry.delay(attempt);
+                tracing::warn!(
+                    "{what} failed (attempt {attempt}/{}), retrying in {delay:?}: {err:#}",
+                    retry.max_attempts
+                );
+                sleep(delay).await;
+            }
+            Err(SendError::Transient(err) | SendError::Fatal(err)) => {
+                return Err(err.context(format!("{what} failed")));
+            }
+        }
+    }
+    unreachable!("retry loop always returns on the last attempt")
+}
+
+/// Send a transaction once, without waiting for it to be mined.
+async fn broadcast_tx<M: Middleware + 'static>(
+    client: &M,
+    tx: TypedTransaction,
+    kind: &str,
+) -> Result<H256, SendError> {
+    let pending = client.send_transaction(tx, None).await.map_err(|err| {
+        // An error which did not come from the provider at all, like a signer refusing to sign
+        // (for example because the transaction was rejected on a hardware wallet), will not go away
+        // by retrying.
+        let transient = err.as_provider_error().is_some() && is_transient(err.as_error_response());
+        let err = anyhow::Error::new(err).context(format!("error sending {kind} transaction"));
+        if transient {
+            SendError::Transient(err)
+        } else {
+            SendError::Fatal(err)
+        }
+    })?;
+    Ok(pending.tx_hash())
+}
+
+/// Wait for the transaction `tx_hash`, sent by `sender` with `nonce` if known, to be mined
+/// successfully.
+///
+/// Errors name the transaction by `kind` and include `tx_hash`.
+async fn await_tx<M: Middleware + 'static>(
+    client: &M,
+    tx_hash: H256,
+    sender: Option<Address>,
+    nonce: Option<U256>,
+    polling: ReceiptPolling,
+    explorer: Option<&Explorer>,
+    kind: &str,
+) -> Result<TransactionReceipt, SendError> {
+    let wait = wait_for_receipt(client, tx_hash, sender, nonce, polling);
+    let Ok(res) = timeout(polling.timeout, wait).await else {
+        return Err(SendError::Transient(anyhow!(
+            "{kind} not mined within {} seconds (tx {})",
+            polling.timeout.as_secs(),
+            fmt_tx(explorer, tx_hash)
+        )));
+    };
+    check_receipt(res, tx_hash, explorer, kind)
+}
+
+/// Interpret the outcome `res` of [`wait_for_receipt`] for the transaction `tx_hash`.
+///
+/// Errors name the transaction by `kind` and include `tx_hash`.
+fn check_receipt<E: MiddlewareError + 'static>(
+    res: Result<Option<TransactionReceipt>, E>,
+    tx_hash: H256,
+    explorer: Option<&Explorer>,
+    kind: &str,
+) -> Result<TransactionReceipt, SendError> {
+    let hash = fmt_tx(explorer, tx_hash);
+    let receipt = match res {
+        Ok(Some(receipt)) => receipt,
+        Ok(None) => {
+            return Err(SendError::Transient(anyhow!(
+                "{kind} dropped from mempool (tx {hash})"
+            )))
+        }
+        Err(err) => {
+            let transient = is_transient(err.as_error_response());
+            let err =
+                anyhow::Error::new(err).context(format!("error waiting for {kind} (tx {hash})"));
+            return Err(if transient {
+                SendError::Transient(err)
+            } else {
+                SendError::Fatal(err)
+            });
+        }
+    };
+    if receipt.status != Some(1.into()) {
+        return Err(SendError::Fatal(
+            anyhow::Error::new(RevertedSnafu { tx_hash }.build())
+                .context(format!("{kind} reverted (tx {hash})")),
+        ));
+    }
+    Ok(receipt)
+}
+
+/// Serialize `n` as a decimal string, as expected by JSON consumers which cannot represent large
+/// integers, rather than the hex string [`U256`] serializes as by default.
+pub(crate) fn serialize_decimal<S: Serializer>(n: &U256, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_str(n)
+}
+
+/// Which build of `LightClient.sol` to link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum LightClientArtifact {
+    /// `LightClient.sol`, linked with `LightClientStateUpdateVK.sol`.
+    Production,
+    /// `LightClientMock.sol`, linked with `LightClientStateUpdateVKMock.sol`.
+    Mock,
+}
+
+impl LightClientArtifact {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Production => "LightClient.sol",
+            Self::Mock => "LightClientMock.sol",
+        }
+    }
+
+    /// The name of the bytecode artifact of this build, as embedded in this crate.
+    fn file_name(self) -> &'static str {
+        match self {
+            Self::Production => "LightClient_bytecode.json",
+            Self::Mock => "LightClientMock_bytecode.json",
+        }
+    }
+
+    /// The ABI of this build.
+    fn abi(self) -> Abi {
+        match self {
+            Self::Production => LIGHTCLIENT_ABI.clone(),
+            Self::Mock => LIGHTCLIENTMOCK_ABI.clone(),
+        }
+    }
+
+    /// The fully qualified name of the verification key library this build links with.
+    fn vk_library(self) -> &'static str {
+        match self {
+            Self::Production => {
+                "contracts/src/libraries/LightClientStateUpdateVK.sol:LightClientStateUpdateVK"
+            }
+            Self::Mock => {
+                "contracts/tests/mocks/LightClientStateUpdateVKMock.sol:LightClientStateUpdateVKMock"
+            }
+        }
+    }
+
+    /// The libraries this build links with: the fully qualified name of each, and the contract it
+    /// is deployed as.
+    fn libraries(self) -> [(&'static str, Contract); 2] {
+        [
+            (
+                "contracts/src/libraries/PlonkVerifier.sol:PlonkVerifier",
+                Contract::PlonkVerifier,
+            ),
+            (self.vk_library(), Contract::StateUpdateVK),
+        ]
+    }
+
+    /// The unlinked bytecode of this build.
+    ///
+    /// We include the unlinked bytecode for the contract in this binary so that the contract
+    /// artifacts do not have to be distributed with the binary. This should be fine because if the
+    /// bindings we are importing are up to date, so should be the contract artifacts: this is no
+    /// different than foundry inlining bytecode objects in generated bindings, except that foundry
+    /// doesn't provide the bytecode for contracts that link with libraries, so we have to do it
+    /// ourselves. The artifact is only parsed once per process.
+    fn unlinked(self) -> anyhow::Result<&'static BytecodeObject> {
+        static PRODUCTION: OnceLock<BytecodeObject> = OnceLock::new();
+        static MOCK: OnceLock<BytecodeObject> = OnceLock::new();
+        let (cell, json) = match self {
+            Self::Production => (
+                &PRODUCTION,
+                include_str!("../../contract-bindings/artifacts/LightClient_bytecode.json"),
+            ),
+            Self::Mock => (
+                &MOCK,
+                include_str!("../../contract-bindings/artifacts/LightClientMock_bytecode.json"),
+            ),
+        };
+        if let Some(bytecode) = cell.get() {
+            return Ok(bytecode);
+        }
+        let bytecode = serde_json::from_str(json)
+            .with_context(|| format!("error parsing bytecode for {}", self.name()))?;
+        Ok(cell.get_or_init(|| bytecode))
+    }
+}
+
+/// Link the bytecode of `LightClient.sol` with its libraries.
+///
+/// `plonk` is the address of `PlonkVerifier.sol`, which both builds link with, and `vk` is the
+/// address of the verification key library matching `artifact`. The result can be deployed any
+/// number of times, as long as the libraries stay where they are.
+pub fn link_light_client_bytecode(
+    artifact: LightClientArtifact,
+    plonk: Address,
+    vk: Address,
+) -> Result<Bytes, DeployerError> {
+    link_light_client_bytecode_from(artifact, &BytecodeSource::Embedded, plonk, vk)
+}
+
+/// Link the bytecode of `LightClient.sol`, loaded from `source`, with its libraries.
+///
+/// This is [`link_light_client_bytecode`] for an artifact which is not necessarily the one embedded
+/// in this crate.
+pub fn link_light_client_bytecode_from(
+    artifact: LightClientArtifact,
+    source: &BytecodeSource,
+    plonk: Address,
+    vk: Address,
+) -> Result<Bytes, DeployerError> {
+    link_unlinked_light_client(artifact, source.load(artifact)?.into_owned(), plonk, vk)
+}
+
+/// Link `bytecode`, the unlinked bytecode of `artifact`, with its libraries.
+fn link_unlinked_light_client(
+    artifact: LightClientArtifact,
+    mut bytecode: BytecodeObject,
+    plonk: Address,
+    vk: Address,
+) -> Result<Bytes, DeployerError> {
+    for (library, contract) in artifact.libraries() {
+        let address = match contract {
+            Contract::PlonkVerifier => plonk,
+            _ => vk,
+        };
+        if bytecode
+            .link_fully_qualified(library, address)
+            .resolve()
+            .is_none()
+        {
+            return LinkingSnafu {
+                contract: Contract::LightClient,
+                library,
+            }
+            .fail();
+        }
+    }
+    if bytecode.is_unlinked() {
+        return UnresolvedPlaceholdersSnafu {
+            artifact,
+            placeholders: artifact::placeholders(&bytecode),
+        }
+        .fail();
+    }
+    let bytecode = bytecode
+        .into_bytes()
+        .with_context(|| format!("error parsing bytecode for linked {}", artifact.name()))?;
+    artifact::check_abi(artifact, &bytecode)?;
+    Ok(bytecode)
+}
+
+/// Deploy `PlonkVerifier.sol` and the verification key library `vk`, which `LightClient.sol` links
+/// with.
+///
+/// The libraries are independent, so they are deployed concurrently with
+/// [`Contracts::deploy_all`], unless CREATE2 is configured, in which case they are deployed one
+/// after the other with [`Contracts::deploy_library`]. Returns the addresses of `PlonkVerifier.sol`
+/// and `vk`.
+pub async fn deploy_light_client_libraries<M, C>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    vk: ContractDeployer<M, C>,
+) -> anyhow::Result<(Address, Address)>
+where
+    M: Middleware + 'static,
+    C: Deref<Target = ethers::contract::Contract<M>>
+        + From<ContractInstance<Arc<M>, M>>
+        + Send
+        + 'static,
+{
+    let plonk_verifier = PlonkVerifier::deploy(l1.clone(), ())?;
+    if contracts.create2.is_some() {
+        let plonk_verifier = contracts
+            .deploy_library(Contract::PlonkVerifier, plonk_verifier)
+            .await?;
+        let vk = contracts
+            .deploy_library(Contract::StateUpdateVK, vk)
+            .await?;
+        return Ok((plonk_verifier, vk));
+    }
+    let addresses = contracts
+        .deploy_all(
+            l1,
+            vec![
+                (Contract::PlonkVerifier, plonk_verifier.deployer.tx),
+                (Contract::StateUpdateVK, vk.deployer.tx),
+            ],
+        )
+        .await?;
+    Ok((addresses[0], addresses[1]))
 }
 
 /// Default deployment function `LightClient.sol` in production
@@ -148,60 +1440,303 @@ impl Contracts {
 /// currently, `LightClient.sol` follows upgradable contract, thus a follow-up
 /// call to `.initialize()` with proper genesis block (and other constructor args)
 /// are expected to be *delegatecall-ed through the proxy contract*.
+///
+/// The libraries are deployed with [`deploy_light_client_libraries`].
 pub async fn deploy_light_client_contract<M: Middleware + 'static>(
     l1: Arc<M>,
     contracts: &mut Contracts,
-) -> anyhow::Result<Address> {
+) -> Result<Address, DeployerError> {
+    deploy_linked_light_client(l1, contracts, LightClientArtifact::Production, ()).await
+}
+
+/// Deploy the libraries of `artifact`, then `artifact` itself, linked with them and constructed
+/// with `constructor_args`.
+async fn deploy_linked_light_client<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    artifact: LightClientArtifact,
+    constructor_args: impl Tokenize,
+) -> Result<Address, DeployerError> {
     // Deploy library contracts.
-    let plonk_verifier = contracts
-        .deploy_tx(
-            Contract::PlonkVerifier,
-            PlonkVerifier::deploy(l1.clone(), ())?,
-        )
-        .await?;
-    let vk = contracts
+    let (plonk_verifier, vk) = match artifact {
+        LightClientArtifact::Production => {
+            deploy_light_client_libraries(
+                l1.clone(),
+                contracts,
+                LightClientStateUpdateVK::deploy(l1.clone(), ())?,
+            )
+            .await?
+        }
+        LightClientArtifact::Mock => {
+            deploy_light_client_libraries(
+                l1.clone(),
+                contracts,
+                LightClientStateUpdateVKMock::deploy(l1.clone(), ())?,
+            )
+            .await?
+        }
+    };
+
+    // Linking an address without code would succeed, but every call into the library would then
+    // silently do nothing, so make sure the libraries are really there.
+    check_library_code(&*l1, contracts, Contract::PlonkVerifier, plonk_verifier).await?;
+    check_library_code(&*l1, contracts, Contract::StateUpdateVK, vk).await?;
+
+    // Deploy light client.
+    let (unlinked, info) = contracts
+        .bytecode_source(artifact)
+        .cloned()
+        .unwrap_or_default()
+        .load_with_info(artifact)?;
+    let bytecode = link_unlinked_light_client(artifact, unlinked.into_owned(), plonk_verifier, vk)?;
+    if let Some(info) = info {
+        contracts.record_artifact_override(info);
+    }
+    let light_client_factory = ContractFactory::new(artifact.abi(), bytecode, l1.clone());
+    let deployer = light_client_factory.deploy(constructor_args)?;
+    Ok(contracts
+        .send_tx(Contract::LightClient, &*l1, deployer.tx)
+        .await?)
+}
+
+/// Check that there is code at `address`, where `library` is linked into the light client.
+///
+/// In a dry run, libraries which would be deployed are not checked, since they only have a
+/// placeholder or predicted address.
+async fn check_library_code<M: Middleware + 'static>(
+    l1: &M,
+    contracts: &Contracts,
+    library: Contract,
+    address: Address,
+) -> Result<(), DeployerError> {
+    if contracts.mode == DeployMode::DryRun
+        && !contracts
+            .plan
+            .iter()
+            .any(|step| matches!(step, PlanStep::Skip { contract, .. } if *contract == library))
+    {
+        return Ok(());
+    }
+    let code = l1
+        .get_code(address, None)
+        .await
+        .map_err(|err| DeployerError::classify::<M>(Contract::LightClient, err.into()))?;
+    if code.is_empty() {
+        return EmptyLibrarySnafu { library, address }.fail();
+    }
+    Ok(())
+}
+
+/// Storage slot holding the implementation address of an ERC1967 proxy.
+///
+/// This is `keccak256("eip1967.proxy.implementation") - 1`, as specified by EIP-1967.
+pub const ERC1967_IMPLEMENTATION_SLOT: H256 = H256([
+    0x36, 0x08, 0x94, 0xa1, 0x3b, 0xa1, 0xa3, 0x21, 0x06, 0x67, 0xc8, 0x28, 0x49, 0x2d, 0xb9, 0x8d,
+    0xca, 0x3e, 0x20, 0x76, 0xcc, 0x37, 0x35, 0xa9, 0x20, 0xa3, 0xca, 0x50, 0x5d, 0x38, 0x2b, 0xbc,
+]);
+
+/// Read the implementation address an ERC1967 proxy currently delegates to.
+pub async fn read_proxy_implementation<M: Middleware + 'static>(
+    l1: &M,
+    proxy: Address,
+) -> anyhow::Result<Address> {
+    let slot = l1
+        .get_storage_at(proxy, ERC1967_IMPLEMENTATION_SLOT, None)
+        .await
+        .with_context(|| format!("error reading implementation slot of proxy {proxy:#x}"))?;
+    Ok(Address::from(slot))
+}
+
+/// Storage slot holding the state of OpenZeppelin's `Initializable` contract.
+///
+/// This is the ERC-7201 namespaced slot for `openzeppelin.storage.Initializable`. The low 64 bits
+/// hold the version the contract has been initialized to, or 0 if it is uninitialized.
+pub const INITIALIZABLE_SLOT: H256 = H256([
+    0xf0, 0xc5, 0x7e, 0x16, 0x84, 0x0d, 0xf0, 0x40, 0xf1, 0x50, 0x88, 0xdc, 0x2f, 0x81, 0xfe, 0x39,
+    0x1c, 0x39, 0x23, 0xbe, 0xc7, 0x3e, 0x23, 0xa9, 0x66, 0x2e, 0xfc, 0x9c, 0x22, 0x9c, 0x6a, 0x00,
+]);
+
+/// Read the version an upgradable contract (or the proxy in front of it) has been initialized to.
+///
+/// Returns 0 if the contract has not been initialized.
+pub async fn read_initialized_version<M: Middleware + 'static>(
+    l1: &M,
+    address: Address,
+) -> anyhow::Result<u64> {
+    let slot = l1
+        .get_storage_at(address, INITIALIZABLE_SLOT, None)
+        .await
+        .with_context(|| format!("error reading initialization state of {address:#x}"))?;
+    let mut version = [0; 8];
+    version.copy_from_slice(&slot[24..]);
+    Ok(u64::from_be_bytes(version))
+}
+
+/// Deploy an ERC1967 proxy for `LightClient.sol` and initialize it.
+///
+/// `impl_addr` is the address of an already deployed `LightClient.sol` implementation contract, as
+/// returned by [`deploy_light_client_contract`]. The proxy is constructed with a delegatecall to
+/// `initialize`, setting the genesis state and the owner of the light client, and the number of
+/// blocks per epoch from the [light client parameters](Contracts::light_client_params). After
+/// construction, we check that the proxy really points at `impl_addr`.
+///
+/// If [`Contract::LightClientProxy`] is already in `contracts`, nothing is deployed and the
+/// predeployed address is returned, provided the predeployed proxy has been initialized. A proxy
+/// which was deployed but never initialized is an error, since anyone could initialize it.
+pub async fn deploy_light_client_proxy<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    impl_addr: Address,
+    genesis: ParsedLightClientState,
+    owner: Address,
+) -> anyhow::Result<Address> {
+    let light_client = LightClient::new(impl_addr, l1.clone());
+    let blocks_per_epoch = contracts.light_client_params().blocks_per_epoch;
+    let data = light_client
+        .initialize(genesis.into(), blocks_per_epoch, owner)
+        .calldata()
+        .context("calldata for initialize transaction not available")?;
+    deploy_proxy(l1, contracts, Contract::LightClientProxy, impl_addr, data).await
+}
+
+/// Deploy an ERC1967 proxy `name` for the implementation at `impl_addr`.
+///
+/// The proxy is constructed with a delegatecall to the implementation with `init_data`, which
+/// should initialize the proxy. If `name` is already in `contracts`, nothing is deployed, but the
+/// predeployed proxy must have been initialized.
+async fn deploy_proxy<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    name: Contract,
+    impl_addr: Address,
+    init_data: Bytes,
+) -> anyhow::Result<Address> {
+    let predeployed = contracts.address(name);
+    if let Some(proxy) = predeployed {
+        check_initialized(&*l1, name, proxy).await?;
+    }
+
+    let proxy = contracts
         .deploy_tx(
-            Contract::StateUpdateVK,
-            LightClientStateUpdateVK::deploy(l1.clone(), ())?,
+            name,
+            ERC1967Proxy::deploy(l1.clone(), (impl_addr, init_data))?,
         )
         .await?;
 
-    // Link with LightClient's bytecode artifacts. We include the unlinked bytecode for the contract
-    // in this binary so that the contract artifacts do not have to be distributed with the binary.
-    // This should be fine because if the bindings we are importing are up to date, so should be the
-    // contract artifacts: this is no different than foundry inlining bytecode objects in generated
-    // bindings, except that foundry doesn't provide the bytecode for contracts that link with
-    // libraries, so we have to do it ourselves.
-    let mut bytecode: BytecodeObject = serde_json::from_str(include_str!(
-        "../../contract-bindings/artifacts/LightClient_bytecode.json",
-    ))?;
-    bytecode
-        .link_fully_qualified(
-            "contracts/src/libraries/PlonkVerifier.sol:PlonkVerifier",
-            plonk_verifier,
-        )
-        .resolve()
-        .context("error linking PlonkVerifier lib")?;
-    bytecode
-        .link_fully_qualified(
-            "contracts/src/libraries/LightClientStateUpdateVK.sol:LightClientStateUpdateVK",
-            vk,
-        )
-        .resolve()
-        .context("error linking LightClientStateUpdateVK lib")?;
-    ensure!(!bytecode.is_unlinked(), "failed to link LightClient.sol");
+    // A predeployed proxy may legitimately have been upgraded to a different implementation, but a
+    // proxy we just deployed must point at the implementation we gave it.
+    if predeployed.is_none() && contracts.mode() == DeployMode::Execute {
+        let implementation = read_proxy_implementation(&*l1, proxy).await?;
+        ensure!(
+            implementation == impl_addr,
+            "proxy {proxy:#x} points at implementation {implementation:#x}, expected {impl_addr:#x}"
+        );
+    }
+    Ok(proxy)
+}
 
-    // Deploy light client.
-    let light_client_factory = ContractFactory::new(
-        LIGHTCLIENT_ABI.clone(),
-        bytecode
-            .as_bytes()
-            .context("error parsing bytecode for linked LightClient contract")?
-            .clone(),
-        l1,
+/// Check that the predeployed proxy `name` at `proxy` has been initialized.
+///
+/// A proxy which was deployed but never initialized is unusable, and dangerous, since anyone could
+/// initialize it.
+async fn check_initialized<M: Middleware + 'static>(
+    l1: &M,
+    name: Contract,
+    proxy: Address,
+) -> anyhow::Result<()> {
+    ensure!(
+        read_initialized_version(l1, proxy).await? > 0,
+        "predeployed {name:?} {proxy:#x} has not been initialized"
     );
-    let contract = light_client_factory.deploy(())?.send().await?;
-    Ok(contract.address())
+    Ok(())
+}
+
+/// Deploy the production light client: its libraries, the `LightClient.sol` implementation, and a
+/// proxy initialized with `genesis` and `owner`.
+///
+/// This is the entry point for deploying the light client in production; see
+/// [`deploy_mock_light_client_contract`] for testing. Each contract is deployed through
+/// [`Contracts::deploy_fn`] in dependency order, and any contract already in `contracts`, whether
+/// predeployed, read from a `.env` file, or resumed from a state file, is reused. In particular, if
+/// the proxy is already in `contracts` then nothing at all is deployed, not even an implementation
+/// which is missing from `contracts`, and the proxy is returned once it is confirmed to be
+/// initialized. So running this twice against the same `contracts`, or against a `.env` file
+/// written after the first run, is a no-op. Returns the address of the proxy.
+///
+/// Once the proxy is deployed and initialized, state updates are restricted to the permissioned
+/// prover in `prover`, if any, with [`set_permissioned_prover`](prover::set_permissioned_prover).
+/// If `transfer_to` is given, ownership of the light client is then handed from `owner`, which
+/// must be the sender of `l1`, to `transfer_to` with
+/// [`transfer_ownership`](ownership::transfer_ownership). Both steps are skipped if they were
+/// already done.
+pub async fn deploy_production_stack<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    genesis: ParsedLightClientState,
+    owner: Address,
+    prover: ProverConfig,
+    transfer_to: Option<Address>,
+) -> anyhow::Result<Address> {
+    let proxy = if let Some(proxy) = contracts.address(Contract::LightClientProxy) {
+        check_initialized(&*l1, Contract::LightClientProxy, proxy).await?;
+        contracts
+            .deploy_fn(Contract::LightClientProxy, |_| unreachable!())
+            .await?
+    } else {
+        deploy_light_client_and_initialize_proxy(l1.clone(), contracts, genesis, owner).await?
+    };
+    prover::set_permissioned_prover(l1.clone(), contracts, Contract::LightClientProxy, prover)
+        .await?;
+    if let Some(new_owner) = transfer_to {
+        ownership::transfer_ownership(l1, contracts, Contract::LightClientProxy, owner, new_owner)
+            .await?;
+    }
+    Ok(proxy)
+}
+
+/// Deploy `LightClient.sol` behind an initialized proxy.
+///
+/// This deploys the libraries and the light client implementation contract using
+/// [`deploy_light_client_contract`], then deploys and initializes the proxy using
+/// [`deploy_light_client_proxy`]. Any of these contracts which are already in `contracts` are
+/// reused rather than redeployed, so it is safe to call this function on a cache populated from a
+/// previous, partial deployment. Returns the address of the proxy.
+pub async fn deploy_light_client_and_initialize_proxy<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    genesis: ParsedLightClientState,
+    owner: Address,
+) -> anyhow::Result<Address> {
+    let impl_addr = contracts
+        .deploy_fn(Contract::LightClient, |contracts| {
+            deploy_light_client_contract(l1.clone(), contracts)
+                .err_into()
+                .boxed()
+        })
+        .await?;
+    deploy_light_client_proxy(l1, contracts, impl_addr, genesis, owner).await
+}
+
+/// Deploy `FeeContract.sol` behind an initialized proxy.
+///
+/// This deploys the fee contract implementation, then an ERC1967 proxy which is initialized with
+/// `owner` as the owner of the fee contract, and checks that the proxy points at the
+/// implementation. As with [`deploy_light_client_and_initialize_proxy`], contracts which are
+/// already in `contracts` are reused, so it is possible to pin just the implementation and deploy
+/// a fresh proxy for it. Returns the address of the proxy.
+pub async fn deploy_fee_contract<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    owner: Address,
+) -> anyhow::Result<Address> {
+    let impl_addr = contracts
+        .deploy_tx(Contract::FeeContract, FeeContract::deploy(l1.clone(), ())?)
+        .await?;
+    let data = FeeContract::new(impl_addr, l1.clone())
+        .initialize(owner)
+        .calldata()
+        .context("calldata for initialize transaction not available")?;
+    deploy_proxy(l1, contracts, Contract::FeeContractProxy, impl_addr, data).await
 }
 
 /// Default deployment function `LightClientMock.sol` for testing
@@ -210,63 +1745,953 @@ pub async fn deploy_light_client_contract<M: Middleware + 'static>(
 /// unlike [`deploy_light_client_contract()`], the `LightClientMock` doesn't
 /// use upgradable contract for simplicity, thus there's no follow-up `.initialize()`
 /// necessary, as we have already call its un-disabled constructor.
+///
+/// Without `constructor_args`, the mock is constructed with a dummy genesis state and the number of
+/// blocks per epoch from the [light client parameters](Contracts::light_client_params).
 pub async fn deploy_mock_light_client_contract<M: Middleware + 'static>(
     l1: Arc<M>,
     contracts: &mut Contracts,
     constructor_args: Option<(LightClientState, u32)>,
+) -> Result<Address, DeployerError> {
+    let constructor_args = match constructor_args {
+        Some(args) => args,
+        None => (
+            ParsedLightClientState::dummy_genesis().into(),
+            contracts.light_client_params().blocks_per_epoch,
+        ),
+    };
+    deploy_linked_light_client(l1, contracts, LightClientArtifact::Mock, constructor_args).await
+}
+
+/// Which light client to deploy with [`deploy_light_client`].
+#[derive(Clone, Debug)]
+pub enum LightClientKind {
+    /// `LightClient.sol`, behind a proxy initialized with `genesis` and owned by `owner`.
+    Production {
+        genesis: ParsedLightClientState,
+        owner: Address,
+    },
+    /// `LightClientMock.sol`, deployed without a proxy and initialized by its constructor, as in
+    /// [`deploy_mock_light_client_contract`].
+    Mock {
+        constructor_args: Option<(LightClientState, u32)>,
+    },
+}
+
+impl LightClientKind {
+    /// The build of `LightClient.sol` this kind of light client links.
+    pub fn artifact(&self) -> LightClientArtifact {
+        match self {
+            Self::Production { .. } => LightClientArtifact::Production,
+            Self::Mock { .. } => LightClientArtifact::Mock,
+        }
+    }
+
+    /// The contract clients of this kind of light client should talk to.
+    pub fn entry_point(&self) -> Contract {
+        match self {
+            Self::Production { .. } => Contract::LightClientProxy,
+            Self::Mock { .. } => Contract::LightClient,
+        }
+    }
+}
+
+/// Deploy a light client of the given `kind`, along with the libraries it links.
+///
+/// Returns the address clients should use: the proxy for [`LightClientKind::Production`], as with
+/// [`deploy_light_client_and_initialize_proxy`], or the light client itself for
+/// [`LightClientKind::Mock`]. Contracts which are already in `contracts` are reused.
+pub async fn deploy_light_client<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    kind: LightClientKind,
 ) -> anyhow::Result<Address> {
-    // Deploy library contracts.
-    let plonk_verifier = contracts
-        .deploy_tx(
+    match kind {
+        LightClientKind::Production { genesis, owner } => {
+            deploy_light_client_and_initialize_proxy(l1, contracts, genesis, owner).await
+        }
+        LightClientKind::Mock { constructor_args } => Ok(contracts
+            .deploy_fn(Contract::LightClient, |contracts| {
+                deploy_mock_light_client_contract(l1.clone(), contracts, constructor_args)
+                    .err_into()
+                    .boxed()
+            })
+            .await?),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{init_signer, AnvilOptions, Signer};
+    use contract_bindings::hot_shot::HotShot;
+    use test_helpers::TEST_MNEMONIC;
+
+    async fn anvil_signer() -> (crate::Anvil, Arc<Signer>) {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        (anvil, l1)
+    }
+
+    async fn nonce(l1: &Signer) -> U256 {
+        l1.get_transaction_count(l1.address(), None).await.unwrap()
+    }
+
+    #[test]
+    fn test_validate_deployed_contracts() {
+        let deployed = DeployedContracts::parse_from([
+            "deploy",
+            "--hotshot=0x4e59b44847b379578588920cA78FbF26c0B4956C",
+            "--light-client=0x4e59b44847b379578588920ca78fbf26c0b4956c",
+            "--fee-contract=0x4E59B44847B379578588920CA78FBF26C0B4956C",
+            "--plonk-verifier=0x4e59b44847b379578588920CA78FbF26c0B4956c",
+        ]);
+        // Only the mixed-case address with a bad checksum is reported.
+        assert_eq!(
+            deployed.validate().unwrap(),
+            [Warning::BadChecksum {
+                contract: Contract::PlonkVerifier,
+                address: "0x4e59b44847b379578588920ca78fbf26c0b4956c"
+                    .parse()
+                    .unwrap(),
+            }]
+        );
+
+        let deployed = DeployedContracts::parse_from([
+            "deploy",
+            &format!("--plonk-verifier={:#x}", Address::zero()),
+        ]);
+        let err = deployed.validate().unwrap_err().to_string();
+        assert!(err.contains(&Contract::PlonkVerifier.to_string()), "{err}");
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let retry = RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        assert_eq!(retry.delay(1), Duration::from_millis(100));
+        assert_eq!(retry.delay(2), Duration::from_millis(200));
+        assert_eq!(retry.delay(4), Duration::from_millis(800));
+        // The delay is capped.
+        assert_eq!(retry.delay(5), Duration::from_secs(1));
+        // Large attempt numbers do not overflow.
+        assert_eq!(retry.delay(usize::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_clear_gas() {
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .gas(100)
+            .max_fee_per_gas(10)
+            .max_priority_fee_per_gas(1)
+            .nonce(5)
+            .into();
+        clear_gas(&mut tx);
+        assert_eq!(tx.gas(), None);
+        assert_eq!(tx.gas_price(), None);
+        // Everything else is preserved.
+        assert_eq!(tx.nonce(), Some(&5.into()));
+
+        let mut tx: TypedTransaction = TransactionRequest::new().gas(100).gas_price(10).into();
+        clear_gas(&mut tx);
+        assert_eq!(tx.gas(), None);
+        assert_eq!(tx.gas_price(), None);
+    }
+
+    #[test]
+    fn test_link_light_client_bytecode() {
+        let plonk = Address::repeat_byte(0xaa);
+        let vk = Address::repeat_byte(0xbb);
+        for artifact in [LightClientArtifact::Production, LightClientArtifact::Mock] {
+            let linked = link_light_client_bytecode(artifact, plonk, vk).unwrap();
+            for lib in [plonk, vk] {
+                assert!(
+                    linked.windows(20).any(|w| w == lib.as_bytes()),
+                    "{artifact:?} not linked with {lib:#x}"
+                );
+            }
+            // Linking again gives the same result, without parsing the artifact again.
+            assert_eq!(
+                link_light_client_bytecode(artifact, plonk, vk).unwrap(),
+                linked
+            );
+            assert!(std::ptr::eq(
+                artifact.unlinked().unwrap(),
+                artifact.unlinked().unwrap()
+            ));
+        }
+        assert_ne!(
+            link_light_client_bytecode(LightClientArtifact::Production, plonk, vk).unwrap(),
+            link_light_client_bytecode(LightClientArtifact::Mock, plonk, vk).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_accessors() {
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::HotShot, Address::repeat_byte(1));
+        contracts.insert(Contract::LightClientProxy, Address::repeat_byte(2));
+
+        assert_eq!(
+            contracts.address(Contract::HotShot),
+            Some(Address::repeat_byte(1))
+        );
+        assert_eq!(contracts.address(Contract::LightClient), None);
+        assert!(contracts.contains(Contract::LightClientProxy));
+        assert!(!contracts.contains(Contract::PlonkVerifier));
+
+        let mut all: Vec<_> = contracts.iter().collect();
+        all.sort();
+        assert_eq!(
+            all,
+            [
+                (Contract::HotShot, Address::repeat_byte(1)),
+                (Contract::LightClientProxy, Address::repeat_byte(2)),
+            ]
+        );
+        assert_eq!(contracts.len(), 2);
+        assert!(!contracts.is_empty());
+
+        assert_eq!(
+            contracts.get_or_err(Contract::HotShot).unwrap(),
+            Address::repeat_byte(1)
+        );
+        let err = contracts.get_or_err(Contract::LightClient).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                DeployerError::NotDeployed {
+                    contract: Contract::LightClient
+                }
+            ),
+            "{err:?}"
+        );
+        assert!(err.to_string().contains(&Contract::LightClient.to_string()));
+
+        assert_eq!(
+            contracts.remove(Contract::HotShot),
+            Some(Address::repeat_byte(1))
+        );
+        assert_eq!(contracts.remove(Contract::HotShot), None);
+        assert_eq!(contracts.len(), 1);
+    }
+
+    #[test]
+    fn test_iter_is_sorted() {
+        let mut contracts = Contracts::default();
+        for (i, contract) in Contract::VARIANTS.iter().enumerate() {
+            contracts.insert(*contract, Address::repeat_byte(i as u8 + 1));
+        }
+        let names: Vec<_> = contracts.iter().map(|(c, _)| c.to_string()).collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+    }
+
+    #[test]
+    fn test_serde() {
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::HotShot, Address::repeat_byte(0xab));
+        contracts.insert(Contract::FeeContractProxy, Address::repeat_byte(0xcd));
+
+        // Keys are env vars, and addresses are checksummed.
+        let json = serde_json::to_value(&contracts).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                (Contract::HotShot.to_string()): to_checksum(&Address::repeat_byte(0xab), None),
+                (Contract::FeeContractProxy.to_string()):
+                    to_checksum(&Address::repeat_byte(0xcd), None),
+            })
+        );
+
+        let parsed: Contracts = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.addresses, contracts.addresses);
+        serde_json::from_str::<Contracts>(r#"{"NOT_A_CONTRACT": "0x00"}"#).unwrap_err();
+    }
+
+    #[test]
+    fn test_duplicate_init_code() {
+        let mut contracts = Contracts::default();
+        // Distinct init code is fine.
+        contracts
+            .check_duplicate(Contract::PlonkVerifier, &[1, 2, 3])
+            .unwrap();
+        contracts
+            .check_duplicate(Contract::StateUpdateVK, &[4, 5, 6])
+            .unwrap();
+        // So is resending the same init code for the same contract.
+        contracts
+            .check_duplicate(Contract::PlonkVerifier, &[1, 2, 3])
+            .unwrap();
+        // The same init code for a different contract is refused.
+        let err = contracts
+            .check_duplicate(Contract::LightClient, &[1, 2, 3])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&Contract::PlonkVerifier.to_string()), "{err}");
+
+        // Unless explicitly allowed.
+        let mut contracts = contracts.allow_duplicate_bytecode(true);
+        contracts
+            .check_duplicate(Contract::LightClient, &[1, 2, 3])
+            .unwrap();
+        assert_eq!(
+            contracts.warnings(),
+            [Warning::DuplicateBytecode {
+                contract: Contract::LightClient,
+                original: Contract::PlonkVerifier,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_transient_errors() {
+        let rpc_err = |message: &str| JsonRpcError {
+            code: -32000,
+            message: message.into(),
+            data: None,
+        };
+        assert!(is_transient(None));
+        assert!(is_transient(Some(&rpc_err("nonce too low"))));
+        assert!(is_transient(Some(&rpc_err(
+            "replacement transaction underpriced"
+        ))));
+        assert!(!is_transient(Some(&rpc_err("execution reverted"))));
+        assert!(!is_transient(Some(&rpc_err(
+            "insufficient funds for gas * price + value"
+        ))));
+    }
+
+    #[test]
+    fn test_read_write_round_trip() {
+        let mut contracts = Contracts::default();
+        // Every contract, including the fee contract and its proxy, survives the round trip.
+        for (i, contract) in Contract::VARIANTS.iter().enumerate() {
+            contracts.insert(*contract, Address::repeat_byte(i as u8 + 1));
+        }
+        assert!(contracts.contains(Contract::FeeContract));
+        assert!(contracts.contains(Contract::FeeContractProxy));
+        let mut buf = vec![];
+        contracts.write(&mut buf).unwrap();
+        assert_eq!(
+            Contracts::read(buf.as_slice()).unwrap().addresses,
+            contracts.addresses
+        );
+    }
+
+    #[test]
+    fn test_write_json() {
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::PlonkVerifier, Address::repeat_byte(0xab));
+        contracts.insert(Contract::HotShot, Address::repeat_byte(0xcd));
+        let mut buf = vec![];
+        contracts.write_json(&mut buf).unwrap();
+        let json = String::from_utf8(buf).unwrap();
+        assert_eq!(
+            json,
+            format!(
+                "{{\n  \"{}\": \"{}\",\n  \"{}\": \"{}\"\n}}",
+                Contract::HotShot,
+                to_checksum(&Address::repeat_byte(0xcd), None),
+                Contract::PlonkVerifier,
+                to_checksum(&Address::repeat_byte(0xab), None),
+            )
+        );
+
+        // The .env output is sorted the same way.
+        let mut buf = vec![];
+        contracts.write(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            format!(
+                "{}={:#x}\n{}={:#x}\n",
+                Contract::HotShot,
+                Address::repeat_byte(0xcd),
+                Contract::PlonkVerifier,
+                Address::repeat_byte(0xab),
+            )
+        );
+    }
+
+    #[test]
+    fn test_write_summary() {
+        let mut contracts =
+            Contracts::default().with_explorer(Explorer::new("http://explorer".parse().unwrap()));
+        contracts.insert(Contract::HotShot, Address::repeat_byte(0xcd));
+        let mut buf = vec![];
+        contracts.write_summary(&mut buf).unwrap();
+        let addr = Address::repeat_byte(0xcd);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            format!("HotShot              {addr:#x} (http://explorer/address/{addr:#x})\n")
+        );
+    }
+
+    #[test]
+    fn test_read_env_file() {
+        let env = format!(
+            "# deployed contracts\n\
+             \n\
+             {}={:#x}\n\
+             ESPRESSO_SEQUENCER_UNKNOWN_ADDRESS=0x0000000000000000000000000000000000000000\n  \
+             {} = \"{:#x}\"\n",
             Contract::PlonkVerifier,
-            PlonkVerifier::deploy(l1.clone(), ())?,
+            Address::repeat_byte(1),
+            Contract::LightClient,
+            Address::repeat_byte(2),
+        );
+        let contracts = Contracts::read(env.as_bytes()).unwrap();
+        assert_eq!(
+            contracts.addresses,
+            HashMap::from([
+                (Contract::PlonkVerifier, Address::repeat_byte(1)),
+                (Contract::LightClient, Address::repeat_byte(2)),
+            ])
+        );
+
+        // Errors report the line number.
+        let env = format!("# header\n{}=0xnotanaddress\n", Contract::HotShot);
+        let err = Contracts::read(env.as_bytes()).unwrap_err().to_string();
+        assert!(err.starts_with("line 2: invalid address"), "{err}");
+        let err = Contracts::read("\n\ngarbage\n".as_bytes())
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("line 3:"), "{err}");
+    }
+
+    #[test]
+    fn test_update_env_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(".env");
+
+        // A missing file is created.
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::HotShot, Address::repeat_byte(1));
+        contracts.update_env_file(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}={:#x}\n", Contract::HotShot, Address::repeat_byte(1))
+        );
+
+        // Only the lines for contracts in the cache change.
+        fs::write(
+            &path,
+            format!(
+                "# sequencer config\n\
+                 ESPRESSO_SEQUENCER_L1_PROVIDER=http://localhost:8545\n\
+                 {}=0x0000000000000000000000000000000000000000\n\
+                 \n\
+                 {}={:#x}\n\
+                 ESPRESSO_SEQUENCER_API_PORT=8080",
+                Contract::HotShot,
+                Contract::FeeContract,
+                Address::repeat_byte(9),
+            ),
         )
-        .await?;
-    let vk = contracts
-        .deploy_tx(
-            Contract::StateUpdateVK,
-            LightClientStateUpdateVKMock::deploy(l1.clone(), ())?,
+        .unwrap();
+        contracts.insert(Contract::LightClientProxy, Address::repeat_byte(2));
+        contracts.update_env_file(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "# sequencer config\n\
+                 ESPRESSO_SEQUENCER_L1_PROVIDER=http://localhost:8545\n\
+                 {}={:#x}\n\
+                 \n\
+                 {}={:#x}\n\
+                 ESPRESSO_SEQUENCER_API_PORT=8080\n\
+                 {}={:#x}\n",
+                Contract::HotShot,
+                Address::repeat_byte(1),
+                Contract::FeeContract,
+                Address::repeat_byte(9),
+                Contract::LightClientProxy,
+                Address::repeat_byte(2),
+            )
+        );
+    }
+
+    async fn check_light_client_proxy(l1: Arc<Signer>, proxy: Address, impl_addr: Address) {
+        assert_eq!(
+            read_proxy_implementation(&*l1, proxy).await.unwrap(),
+            impl_addr
+        );
+        let light_client = LightClient::new(proxy, l1.clone());
+        assert_eq!(
+            ParsedLightClientState::from(light_client.get_genesis_state().call().await.unwrap()),
+            ParsedLightClientState::dummy_genesis()
+        );
+        assert_eq!(light_client.owner().call().await.unwrap(), l1.address());
+    }
+
+    #[async_std::test]
+    async fn test_revert_error_has_tx_hash() {
+        let (_anvil, l1) = anvil_signer().await;
+
+        // A constructor which always reverts: `PUSH1 0 DUP1 REVERT`. With the gas limit set, the
+        // transaction is not estimated, so it is mined and reverts on chain.
+        let tx = TransactionRequest::new()
+            .data(vec![0x60, 0x00, 0x80, 0xfd])
+            .gas(100_000);
+        let hash = l1.send_transaction(tx, None).await.unwrap().tx_hash();
+        let err = match await_tx(
+            &*l1,
+            hash,
+            None,
+            None,
+            ReceiptPolling::default(),
+            None,
+            "HotShot deployment",
         )
-        .await?;
+        .await
+        {
+            Err(SendError::Fatal(err)) => err.to_string(),
+            _ => panic!("expected a fatal error"),
+        };
+        assert_eq!(err, format!("HotShot deployment reverted (tx {hash:#x})"));
+    }
+
+    #[async_std::test]
+    async fn test_deploy_revert_is_typed() {
+        let (_anvil, l1) = anvil_signer().await;
+
+        // A constructor which always reverts: `PUSH1 0 DUP1 REVERT`.
+        let tx: TypedTransaction = TransactionRequest::new()
+            .data(vec![0x60, 0x00, 0x80, 0xfd])
+            .into();
+        let mut contracts = Contracts::default();
+        let err = contracts
+            .deploy_fn(Contract::HotShot, |contracts| {
+                async move { contracts.send_tx(Contract::HotShot, &*l1, tx).await }.boxed()
+            })
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                DeployerError::Revert {
+                    contract: Contract::HotShot,
+                    tx_hash: None,
+                    ..
+                }
+            ),
+            "{err:?}"
+        );
+        assert!(!contracts.contains(Contract::HotShot));
+    }
 
-    let mut bytecode: BytecodeObject = serde_json::from_str(include_str!(
-        "../../contract-bindings/artifacts/LightClientMock_bytecode.json",
-    ))?;
-    bytecode
-        .link_fully_qualified(
-            "contracts/src/libraries/PlonkVerifier.sol:PlonkVerifier",
-            plonk_verifier,
+    #[async_std::test]
+    async fn test_deploy_light_client_proxy_fresh() {
+        let (_anvil, l1) = anvil_signer().await;
+        let mut contracts = Contracts::default();
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            l1.address(),
         )
-        .resolve()
-        .context("error linking PlonkVerifier lib")?;
-    bytecode
-        .link_fully_qualified(
-            "contracts/tests/mocks/LightClientStateUpdateVKMock.sol:LightClientStateUpdateVKMock",
-            vk,
+        .await
+        .unwrap();
+        assert_eq!(contracts.addresses[&Contract::LightClientProxy], proxy);
+        check_light_client_proxy(l1, proxy, contracts.addresses[&Contract::LightClient]).await;
+    }
+
+    #[async_std::test]
+    async fn test_deploy_events() {
+        let (_anvil, l1) = anvil_signer().await;
+        let mut contracts = Contracts::default();
+        let events = contracts.subscribe();
+
+        let hotshot = contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+        contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+        contracts
+            .deploy_fn(Contract::PlonkVerifier, |_| {
+                async { Err(anyhow!("no")) }.boxed()
+            })
+            .await
+            .unwrap_err();
+
+        let info = contracts.deployment_info(Contract::HotShot).unwrap();
+        let mut received = vec![];
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        assert_eq!(
+            received,
+            [
+                DeployEvent::Started {
+                    contract: Contract::HotShot
+                },
+                DeployEvent::TxSubmitted {
+                    contract: Contract::HotShot,
+                    tx_hash: info.tx_hash.unwrap(),
+                },
+                DeployEvent::Deployed {
+                    contract: Contract::HotShot,
+                    address: hotshot,
+                    gas_used: info.gas_used,
+                },
+                DeployEvent::Skipped {
+                    contract: Contract::HotShot,
+                    address: hotshot,
+                },
+                DeployEvent::Started {
+                    contract: Contract::PlonkVerifier
+                },
+                DeployEvent::Failed {
+                    contract: Contract::PlonkVerifier,
+                    error: "no".into(),
+                },
+            ]
+        );
+        assert!(info.gas_used.is_some());
+
+        // Events are discarded once the receiver is gone.
+        drop(events);
+        contracts
+            .deploy_tx(
+                Contract::PlonkVerifier,
+                PlonkVerifier::deploy(l1.clone(), ()).unwrap(),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_predeployed_library_without_code() {
+        let (_anvil, l1) = anvil_signer().await;
+        let empty = Address::repeat_byte(1);
+
+        // A predeployed library with no code is refused before the light client is deployed.
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::PlonkVerifier, empty);
+        let err = deploy_light_client_contract(l1.clone(), &mut contracts)
+            .await
+            .unwrap_err();
+        let DeployerError::EmptyLibrary { library, address } = &err else {
+            panic!("{err:?}");
+        };
+        assert_eq!(*library, Contract::PlonkVerifier);
+        assert_eq!(*address, empty);
+        assert_eq!(contracts.address(Contract::LightClient), None);
+
+        // Likewise in a dry run, which does not deploy the libraries either.
+        let mut contracts = Contracts::default().with_mode(DeployMode::DryRun);
+        contracts.insert(Contract::StateUpdateVK, empty);
+        let err = deploy_light_client_contract(l1.clone(), &mut contracts)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                DeployerError::EmptyLibrary {
+                    library: Contract::StateUpdateVK,
+                    ..
+                }
+            ),
+            "{err:?}"
+        );
+
+        // Libraries which would be deployed in a dry run are not checked.
+        let mut contracts = Contracts::default().with_mode(DeployMode::DryRun);
+        deploy_light_client_contract(l1.clone(), &mut contracts)
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_deploy_light_client_kinds() {
+        let (_anvil, l1) = anvil_signer().await;
+
+        let kind = LightClientKind::Production {
+            genesis: ParsedLightClientState::dummy_genesis(),
+            owner: l1.address(),
+        };
+        assert_eq!(kind.artifact(), LightClientArtifact::Production);
+        let mut production = Contracts::default();
+        let proxy = deploy_light_client(l1.clone(), &mut production, kind.clone())
+            .await
+            .unwrap();
+        assert_eq!(production.address(kind.entry_point()), Some(proxy));
+        check_light_client_proxy(
+            l1.clone(),
+            proxy,
+            production.addresses[&Contract::LightClient],
         )
-        .resolve()
-        .context("error linking LightClientStateUpdateVKMock lib")?;
-    ensure!(
-        !bytecode.is_unlinked(),
-        "failed to link LightClientMock.sol"
-    );
+        .await;
 
-    // Deploy light client.
-    let light_client_factory = ContractFactory::new(
-        LIGHTCLIENTMOCK_ABI.clone(),
-        bytecode
-            .as_bytes()
-            .context("error parsing bytecode for linked LightClientMock contract")?
-            .clone(),
-        l1,
-    );
-    let constructor_args = match constructor_args {
-        Some(args) => args,
-        None => (ParsedLightClientState::dummy_genesis().into(), u32::MAX),
-    };
-    let contract = light_client_factory
-        .deploy(constructor_args)?
-        .send()
-        .await?;
-    Ok(contract.address())
+        let kind = LightClientKind::Mock {
+            constructor_args: None,
+        };
+        assert_eq!(kind.artifact(), LightClientArtifact::Mock);
+        let mut mock = Contracts::default();
+        let address = deploy_light_client(l1.clone(), &mut mock, kind.clone())
+            .await
+            .unwrap();
+        assert_eq!(mock.address(kind.entry_point()), Some(address));
+        assert_eq!(mock.address(Contract::LightClientProxy), None);
+        let light_client = LightClient::new(address, l1.clone());
+        assert_eq!(
+            ParsedLightClientState::from(light_client.get_genesis_state().call().await.unwrap()),
+            ParsedLightClientState::dummy_genesis()
+        );
+
+        // The libraries are linked per kind, so the two deployments do not share them.
+        assert_ne!(
+            production.address(Contract::StateUpdateVK),
+            mock.address(Contract::StateUpdateVK)
+        );
+
+        // Deploying again reuses everything.
+        let before = nonce(&l1).await;
+        assert_eq!(
+            deploy_light_client(l1.clone(), &mut mock, kind)
+                .await
+                .unwrap(),
+            address
+        );
+        assert_eq!(nonce(&l1).await, before);
+    }
+
+    #[async_std::test]
+    async fn test_deploy_light_client_proxy_predeployed_impl() {
+        let (_anvil, l1) = anvil_signer().await;
+
+        // Deploy just the implementation in one run.
+        let mut contracts = Contracts::default();
+        let impl_addr = contracts
+            .deploy_fn(Contract::LightClient, |contracts| {
+                deploy_light_client_contract(l1.clone(), contracts)
+                    .err_into()
+                    .boxed()
+            })
+            .await
+            .unwrap();
+
+        // Deploy the proxy in a second run, only knowing about the implementation.
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::LightClient, impl_addr);
+        let before = nonce(&l1).await;
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            l1.address(),
+        )
+        .await
+        .unwrap();
+        // Only the proxy was deployed.
+        assert_eq!(nonce(&l1).await, before + 1);
+        check_light_client_proxy(l1, proxy, impl_addr).await;
+    }
+
+    #[async_std::test]
+    async fn test_deploy_light_client_proxy_fully_predeployed() {
+        let (_anvil, l1) = anvil_signer().await;
+        let mut contracts = Contracts::default();
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            l1.address(),
+        )
+        .await
+        .unwrap();
+
+        // Running again with everything predeployed sends no transactions.
+        let mut predeployed = Contracts::default();
+        predeployed.addresses = contracts.addresses.clone();
+        let before = nonce(&l1).await;
+        assert_eq!(
+            deploy_light_client_and_initialize_proxy(
+                l1.clone(),
+                &mut predeployed,
+                ParsedLightClientState::dummy_genesis(),
+                l1.address(),
+            )
+            .await
+            .unwrap(),
+            proxy
+        );
+        assert_eq!(nonce(&l1).await, before);
+    }
+
+    #[async_std::test]
+    async fn test_deploy_production_stack_idempotent() {
+        let (_anvil, l1) = anvil_signer().await;
+        let mut contracts = Contracts::default();
+        let proxy = deploy_production_stack(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            l1.address(),
+            ProverConfig::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        check_light_client_proxy(
+            l1.clone(),
+            proxy,
+            contracts.addresses[&Contract::LightClient],
+        )
+        .await;
+
+        // Running again from a .env file written by the first run redeploys nothing.
+        let mut env = vec![];
+        contracts.write(&mut env).unwrap();
+        let mut resumed = Contracts::read(&env[..]).unwrap();
+        let before = nonce(&l1).await;
+        for _ in 0..2 {
+            assert_eq!(
+                deploy_production_stack(
+                    l1.clone(),
+                    &mut resumed,
+                    ParsedLightClientState::dummy_genesis(),
+                    l1.address(),
+                    ProverConfig::default(),
+                    None,
+                )
+                .await
+                .unwrap(),
+                proxy
+            );
+        }
+        assert_eq!(nonce(&l1).await, before);
+
+        // Knowing only the proxy is enough.
+        let mut proxy_only = Contracts::default();
+        proxy_only.insert(Contract::LightClientProxy, proxy);
+        assert_eq!(
+            deploy_production_stack(
+                l1.clone(),
+                &mut proxy_only,
+                ParsedLightClientState::dummy_genesis(),
+                l1.address(),
+                ProverConfig::default(),
+                None,
+            )
+            .await
+            .unwrap(),
+            proxy
+        );
+        assert_eq!(nonce(&l1).await, before);
+        assert!(!proxy_only.contains(Contract::LightClient));
+
+        // Handing over ownership at the end is also only done once.
+        let new_owner = Address::repeat_byte(1);
+        for _ in 0..2 {
+            deploy_production_stack(
+                l1.clone(),
+                &mut proxy_only,
+                ParsedLightClientState::dummy_genesis(),
+                l1.address(),
+                ProverConfig::default(),
+                Some(new_owner),
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(
+            LightClient::new(proxy, l1.clone())
+                .owner()
+                .call()
+                .await
+                .unwrap(),
+            new_owner
+        );
+        assert_eq!(nonce(&l1).await, before + 1);
+    }
+
+    #[async_std::test]
+    async fn test_deploy_light_client_proxy_uninitialized() {
+        let (_anvil, l1) = anvil_signer().await;
+        let mut contracts = Contracts::default();
+        let impl_addr = contracts
+            .deploy_fn(Contract::LightClient, |contracts| {
+                deploy_light_client_contract(l1.clone(), contracts)
+                    .err_into()
+                    .boxed()
+            })
+            .await
+            .unwrap();
+
+        // Deploy a proxy without initializing it.
+        let proxy = ERC1967Proxy::deploy(l1.clone(), (impl_addr, Bytes::new()))
+            .unwrap()
+            .send()
+            .await
+            .unwrap()
+            .address();
+        assert_eq!(read_initialized_version(&*l1, proxy).await.unwrap(), 0);
+        contracts.insert(Contract::LightClientProxy, proxy);
+
+        let err = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            l1.address(),
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("has not been initialized"),
+            "{err:#}"
+        );
+    }
+
+    #[async_std::test]
+    async fn test_deploy_fee_contract() {
+        let (_anvil, l1) = anvil_signer().await;
+        let owner = Address::repeat_byte(1);
+        let mut contracts = Contracts::default();
+        let proxy = deploy_fee_contract(l1.clone(), &mut contracts, owner)
+            .await
+            .unwrap();
+        assert_eq!(contracts.address(Contract::FeeContractProxy), Some(proxy));
+        let impl_addr = contracts.address(Contract::FeeContract).unwrap();
+        assert_eq!(
+            read_proxy_implementation(&*l1, proxy).await.unwrap(),
+            impl_addr
+        );
+        let fee_contract = FeeContract::new(proxy, l1.clone());
+        assert_eq!(fee_contract.owner().call().await.unwrap(), owner);
+
+        // Pin just the implementation, and deploy a fresh proxy for it.
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::FeeContract, impl_addr);
+        let before = nonce(&l1).await;
+        let new_proxy = deploy_fee_contract(l1.clone(), &mut contracts, owner)
+            .await
+            .unwrap();
+        assert_ne!(new_proxy, proxy);
+        assert_eq!(nonce(&l1).await, before + 1);
+        assert_eq!(
+            read_proxy_implementation(&*l1, new_proxy).await.unwrap(),
+            impl_addr
+        );
+
+        // Both addresses are written to the .env file.
+        let mut buf = vec![];
+        contracts.write(&mut buf).unwrap();
+        let env = String::from_utf8(buf).unwrap();
+        assert!(
+            env.contains(&format!("{}={impl_addr:#x}", Contract::FeeContract)),
+            "{env}"
+        );
+        assert!(
+            env.contains(&format!("{}={new_proxy:#x}", Contract::FeeContractProxy)),
+            "{env}"
+        );
+    }
 }
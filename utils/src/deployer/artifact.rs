@@ -0,0 +1,418 @@
+//! Where the unlinked bytecode of the light client comes from.
+//!
+//! By default, the light client is deployed from the artifacts embedded in this crate at compile
+//! time (see [`LightClientArtifact`]). Deploying a locally recompiled light client would then mean
+//! rebuilding the crate, so [`Contracts::with_bytecode_source`] lets the deployer load the unlinked
+//! bytecode from a file, or from memory, instead. Only the source of the bytecode changes: it is
+//! linked with its libraries exactly like the embedded artifact.
+//!
+//! An artifact loaded from anywhere but the embedded copy is identified in the
+//! [deployment report](Contracts::write_report) by its path and the hash of its contents, as an
+//! [`ArtifactInfo`], so that it can later be told which build was deployed.
+//!
+//! Whatever the source, the linked bytecode is checked against the ABI of the bindings with
+//! [`check_abi`] before it is deployed. A bytecode artifact which is stale relative to the bindings
+//! would otherwise deploy fine, and only fail once the light client is called.
+
+use super::{
+    code::has_selector,
+    error::{AbiMismatchSnafu, DeployerError},
+    Contracts, LightClientArtifact,
+};
+use anyhow::{ensure, Context};
+use ethers::{
+    prelude::*,
+    solc::{artifacts::BytecodeObject, utils::library_hash_placeholder},
+    utils::keccak256,
+};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Where to load the unlinked bytecode of a [`LightClientArtifact`] from.
+///
+/// Artifacts other than the embedded ones may be given in the same format as the embedded ones, a
+/// JSON string containing the hex bytecode with library placeholders, or as the JSON artifact
+/// Foundry writes for the contract, from which `bytecode.object` is used.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum BytecodeSource {
+    /// The artifact embedded in this crate at compile time.
+    #[default]
+    Embedded,
+    /// An artifact file, read when the light client is deployed.
+    File(PathBuf),
+    /// The contents of an artifact file.
+    Bytes(Bytes),
+}
+
+/// An artifact which was deployed from somewhere other than the embedded copy.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ArtifactInfo {
+    pub artifact: LightClientArtifact,
+    /// The file the artifact was loaded from, if it was loaded from a file.
+    pub path: Option<PathBuf>,
+    /// The hash of the contents of the artifact, as loaded.
+    pub keccak: H256,
+}
+
+impl BytecodeSource {
+    /// The artifact for `artifact` in `dir`, a directory laid out like the artifacts embedded in
+    /// this crate, with `LightClient_bytecode.json` and `LightClientMock_bytecode.json`.
+    pub fn from_dir(dir: impl AsRef<Path>, artifact: LightClientArtifact) -> Self {
+        Self::File(dir.as_ref().join(artifact.file_name()))
+    }
+
+    /// Load the unlinked bytecode of `artifact` from this source.
+    pub fn load(
+        &self,
+        artifact: LightClientArtifact,
+    ) -> anyhow::Result<Cow<'static, BytecodeObject>> {
+        Ok(self.load_with_info(artifact)?.0)
+    }
+
+    /// Load the unlinked bytecode of `artifact` from this source, along with where it came from.
+    ///
+    /// The [`ArtifactInfo`] is `None` for the embedded artifact.
+    pub fn load_with_info(
+        &self,
+        artifact: LightClientArtifact,
+    ) -> anyhow::Result<(Cow<'static, BytecodeObject>, Option<ArtifactInfo>)> {
+        let (bytes, path) = match self {
+            Self::Embedded => return Ok((Cow::Borrowed(artifact.unlinked()?), None)),
+            Self::File(path) => {
+                let bytes = fs::read(path).with_context(|| {
+                    format!("error reading {artifact:?} artifact {}", path.display())
+                })?;
+                (Cow::Owned(bytes), Some(path.clone()))
+            }
+            Self::Bytes(bytes) => (Cow::Borrowed(bytes.as_ref()), None),
+        };
+        let describe = || match &path {
+            Some(path) => format!("{artifact:?} artifact {}", path.display()),
+            None => format!("{artifact:?} artifact"),
+        };
+        let bytecode =
+            parse_artifact(&bytes).with_context(|| format!("error parsing {}", describe()))?;
+
+        // Bytecode which was linked elsewhere, or is for some other contract, would deploy fine
+        // but not use our libraries, so make sure there is something to link.
+        for (library, _) in artifact.libraries() {
+            ensure!(
+                bytecode.contains_fully_qualified_placeholder(library),
+                "{} does not link with {library}",
+                describe()
+            );
+        }
+        // Conversely, bytecode which links with a library we do not deploy could never be linked.
+        let expected: Vec<_> = artifact
+            .libraries()
+            .into_iter()
+            .map(|(library, _)| library_hash_placeholder(library))
+            .collect();
+        let unexpected: Vec<_> = placeholders(&bytecode)
+            .into_iter()
+            .filter(|placeholder| !expected.contains(placeholder))
+            .collect();
+        ensure!(
+            unexpected.is_empty(),
+            "{} links with unknown libraries: {}",
+            describe(),
+            unexpected.join(", ")
+        );
+
+        let info = ArtifactInfo {
+            artifact,
+            path,
+            keccak: keccak256(&bytes).into(),
+        };
+        Ok((Cow::Owned(bytecode), Some(info)))
+    }
+}
+
+/// The distinct library placeholders left in `bytecode`, in order of first appearance.
+///
+/// Placeholders are of the form `__$<34 hex digits>$__`, as written by `solc`.
+pub(super) fn placeholders(bytecode: &BytecodeObject) -> Vec<String> {
+    let BytecodeObject::Unlinked(code) = bytecode else {
+        return vec![];
+    };
+    let mut found: Vec<String> = vec![];
+    let mut rest = code.as_str();
+    while let Some(start) = rest.find("__$") {
+        let candidate = &rest[start..];
+        match candidate.get(..40) {
+            Some(placeholder) if placeholder.ends_with("$__") => {
+                if !found.iter().any(|p| p == placeholder) {
+                    found.push(placeholder.to_string());
+                }
+                rest = &candidate[40..];
+            }
+            _ => rest = &candidate[3..],
+        }
+    }
+    found
+}
+
+/// Check that `bytecode`, the linked init code of `artifact`, implements every function in the ABI
+/// of `artifact`.
+///
+/// The runtime code is part of the init code, and its function dispatcher contains the selector of
+/// every external function, so a function whose selector does not appear anywhere in the bytecode
+/// cannot be implemented. This only takes a scan of the bytecode per function, so it is cheap
+/// enough to run on every deployment.
+pub fn check_abi(artifact: LightClientArtifact, bytecode: &[u8]) -> Result<(), DeployerError> {
+    let missing: Vec<_> = artifact
+        .abi()
+        .functions()
+        .filter(|function| !has_selector(bytecode, function.short_signature()))
+        .map(|function| function.signature())
+        .collect();
+    if !missing.is_empty() {
+        return AbiMismatchSnafu { artifact, missing }.fail();
+    }
+    Ok(())
+}
+
+pub(super) fn parse_artifact(bytes: &[u8]) -> anyhow::Result<BytecodeObject> {
+    let json: serde_json::Value = serde_json::from_slice(bytes)?;
+    let object = if json.is_string() {
+        json
+    } else {
+        json.pointer("/bytecode/object")
+            .cloned()
+            .context("artifact has no bytecode.object")?
+    };
+    Ok(serde_json::from_value(object)?)
+}
+
+impl Contracts {
+    /// Load the unlinked bytecode of `artifact` from `source` when deploying it.
+    pub fn with_bytecode_source(
+        mut self,
+        artifact: LightClientArtifact,
+        source: BytecodeSource,
+    ) -> Self {
+        self.bytecode_sources.insert(artifact, source);
+        self
+    }
+
+    /// Where the unlinked bytecode of `artifact` is loaded from, if not the embedded artifact.
+    pub fn bytecode_source(&self, artifact: LightClientArtifact) -> Option<&BytecodeSource> {
+        self.bytecode_sources.get(&artifact)
+    }
+
+    /// The artifacts deployed in this run from somewhere other than the embedded copy.
+    pub fn artifact_overrides(&self) -> &[ArtifactInfo] {
+        &self.artifact_overrides
+    }
+
+    /// Record that `info` was deployed in this run.
+    pub(super) fn record_artifact_override(&mut self, info: ArtifactInfo) {
+        tracing::info!(
+            "deploying {:?} from {} with keccak {:#x}",
+            info.artifact,
+            info.path
+                .as_ref()
+                .map_or("memory".into(), |path| path.display().to_string()),
+            info.keccak
+        );
+        self.artifact_overrides
+            .retain(|recorded| recorded.artifact != info.artifact);
+        self.artifact_overrides.push(info);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deployer::{
+        link_light_client_bytecode, link_light_client_bytecode_from, link_unlinked_light_client,
+    };
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_bytecode_source() {
+        let plonk = Address::repeat_byte(0xaa);
+        let vk = Address::repeat_byte(0xbb);
+        let artifact = LightClientArtifact::Mock;
+        let embedded = link_light_client_bytecode(artifact, plonk, vk).unwrap();
+        let json = serde_json::to_vec(artifact.unlinked().unwrap()).unwrap();
+
+        // The embedded format, in memory and in a file.
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("LightClientMock_bytecode.json");
+        fs::write(&path, &json).unwrap();
+        for source in [
+            BytecodeSource::Embedded,
+            BytecodeSource::Bytes(json.clone().into()),
+            BytecodeSource::File(path),
+        ] {
+            assert_eq!(
+                link_light_client_bytecode_from(artifact, &source, plonk, vk).unwrap(),
+                embedded,
+                "{source:?}"
+            );
+        }
+
+        // A Foundry artifact.
+        let foundry = serde_json::json!({
+            "abi": [],
+            "bytecode": { "object": artifact.unlinked().unwrap() },
+        });
+        let source = BytecodeSource::Bytes(serde_json::to_vec(&foundry).unwrap().into());
+        assert_eq!(
+            link_light_client_bytecode_from(artifact, &source, plonk, vk).unwrap(),
+            embedded
+        );
+
+        // Bytecode which does not link with the light client libraries is rejected.
+        let source = BytecodeSource::Bytes(br#""0x6000""#.to_vec().into());
+        let err = link_light_client_bytecode_from(artifact, &source, plonk, vk).unwrap_err();
+        assert!(err.to_string().contains("does not link with"), "{err}");
+        let source = BytecodeSource::Bytes(br#"{"abi": []}"#.to_vec().into());
+        link_light_client_bytecode_from(artifact, &source, plonk, vk).unwrap_err();
+        let source = BytecodeSource::File(dir.path().join("missing.json"));
+        link_light_client_bytecode_from(artifact, &source, plonk, vk).unwrap_err();
+
+        let contracts = Contracts::default().with_bytecode_source(artifact, source.clone());
+        assert_eq!(contracts.bytecode_source(artifact), Some(&source));
+        assert_eq!(
+            contracts.bytecode_source(LightClientArtifact::Production),
+            None
+        );
+    }
+
+    #[test]
+    fn test_artifacts_dir() {
+        let plonk = Address::repeat_byte(0xaa);
+        let vk = Address::repeat_byte(0xbb);
+        let dir = TempDir::new().unwrap();
+        for artifact in [LightClientArtifact::Production, LightClientArtifact::Mock] {
+            let json = serde_json::to_vec(artifact.unlinked().unwrap()).unwrap();
+            fs::write(dir.path().join(artifact.file_name()), &json).unwrap();
+
+            // The override is loaded from the file named like the embedded artifact, and
+            // identified by its path and hash.
+            let source = BytecodeSource::from_dir(dir.path(), artifact);
+            let (unlinked, info) = source.load_with_info(artifact).unwrap();
+            assert_eq!(unlinked.as_ref(), artifact.unlinked().unwrap());
+            assert_eq!(
+                info,
+                Some(ArtifactInfo {
+                    artifact,
+                    path: Some(dir.path().join(artifact.file_name())),
+                    keccak: keccak256(&json).into(),
+                })
+            );
+            assert_eq!(
+                link_light_client_bytecode_from(artifact, &source, plonk, vk).unwrap(),
+                link_light_client_bytecode(artifact, plonk, vk).unwrap()
+            );
+        }
+        let (_, info) = BytecodeSource::Embedded
+            .load_with_info(LightClientArtifact::Production)
+            .unwrap();
+        assert_eq!(info, None);
+
+        // Overridden artifacts are listed in the report.
+        let (_, info) = BytecodeSource::from_dir(dir.path(), LightClientArtifact::Mock)
+            .load_with_info(LightClientArtifact::Mock)
+            .unwrap();
+        let info = info.unwrap();
+        let mut contracts = Contracts::default();
+        contracts.record_artifact_override(info.clone());
+        assert_eq!(contracts.artifact_overrides(), [info.clone()]);
+        let mut report = vec![];
+        contracts.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("Bytecode overrides:"), "{report}");
+        assert!(
+            report.contains(&format!("keccak {:#x}", info.keccak)),
+            "{report}"
+        );
+        let mut json = vec![];
+        contracts.write_report_json(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(
+            json["bytecode_overrides"][0]["keccak"],
+            serde_json::to_value(info.keccak).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unknown_library() {
+        let plonk = Address::repeat_byte(0xaa);
+        let vk = Address::repeat_byte(0xbb);
+        let artifact = LightClientArtifact::Production;
+        let BytecodeObject::Unlinked(code) = artifact.unlinked().unwrap() else {
+            panic!("embedded artifact is already linked");
+        };
+        let unknown = library_hash_placeholder("contracts/src/libraries/Unknown.sol:Unknown");
+        let mut expected: Vec<_> = artifact
+            .libraries()
+            .into_iter()
+            .map(|(library, _)| library_hash_placeholder(library))
+            .collect();
+        assert_eq!(
+            placeholders(artifact.unlinked().unwrap()).len(),
+            expected.len()
+        );
+
+        // An artifact which links with a library we do not deploy is rejected when loaded...
+        let bytecode = BytecodeObject::Unlinked(format!("{code}{unknown}"));
+        let source = BytecodeSource::Bytes(serde_json::to_vec(&bytecode).unwrap().into());
+        let err = source.load(artifact).unwrap_err();
+        assert!(err.to_string().contains("unknown libraries"), "{err}");
+        assert!(err.to_string().contains(&unknown), "{err}");
+
+        // ...and its placeholder is reported if it is linked anyway.
+        let err = link_unlinked_light_client(artifact, bytecode.clone(), plonk, vk).unwrap_err();
+        let DeployerError::UnresolvedPlaceholders {
+            artifact: unresolved,
+            placeholders: remaining,
+        } = &err
+        else {
+            panic!("{err:?}");
+        };
+        assert_eq!(*unresolved, artifact);
+        assert_eq!(remaining, &[unknown.clone()]);
+
+        expected.push(unknown);
+        let mut found = placeholders(&bytecode);
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_check_abi() {
+        let plonk = Address::repeat_byte(0xaa);
+        let vk = Address::repeat_byte(0xbb);
+        let production =
+            link_light_client_bytecode(LightClientArtifact::Production, plonk, vk).unwrap();
+        let mock = link_light_client_bytecode(LightClientArtifact::Mock, plonk, vk).unwrap();
+        check_abi(LightClientArtifact::Production, &production).unwrap();
+        check_abi(LightClientArtifact::Mock, &mock).unwrap();
+
+        // The mock extends the production light client, so the production bytecode is missing the
+        // functions only the mock has.
+        let err = check_abi(LightClientArtifact::Mock, &production).unwrap_err();
+        let DeployerError::AbiMismatch { artifact, missing } = &err else {
+            panic!("{err:?}");
+        };
+        assert_eq!(*artifact, LightClientArtifact::Mock);
+        assert!(
+            missing.contains(&"setCurrentEpoch(uint64)".to_string()),
+            "{missing:?}"
+        );
+        assert!(
+            missing.iter().any(|f| f.starts_with("setFinalizedState(")),
+            "{missing:?}"
+        );
+        assert!(!missing.contains(&"owner()".to_string()), "{missing:?}");
+        assert!(err.to_string().contains("stale"), "{err}");
+    }
+}
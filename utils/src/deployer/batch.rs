@@ -0,0 +1,334 @@
+//! Deploying independent contracts concurrently.
+//!
+//! Deploying contracts one at a time, waiting for each to be mined before sending the next, makes a
+//! full deployment take minutes on chains with slow blocks. Contracts which do not depend on each
+//! other, like the libraries `LightClient.sol` links with, can instead be deployed as a batch with
+//! [`Contracts::deploy_all`]: their transactions are sent back to back with consecutive nonces, so
+//! they can be mined in the same block, and then all the receipts are awaited together.
+
+use super::{
+    await_tx, broadcast_tx, deployed_address, dry_run::DeployMode, emit, send_deploy_tx, Contract,
+    Contracts, DeployEvent, SendError,
+};
+use anyhow::Context;
+use async_std::{sync::Arc, task::sleep};
+use ethers::{prelude::*, types::transaction::eip2718::TypedTransaction};
+use futures::future::{join_all, FutureExt};
+
+impl Contracts {
+    /// Deploy several independent contracts concurrently.
+    ///
+    /// As with [`deploy_tx`](Self::deploy_tx), contracts which are already in the cache are
+    /// skipped. The deployment transactions of the others are sent with consecutive nonces, without
+    /// waiting for each to be mined, and then all the receipts are awaited. Each contract is only
+    /// added to the cache once its transaction is mined, so `deployments` must not depend on each
+    /// other. A transaction which fails transiently is retried on its own according to the
+    /// [retry policy](Self::with_retry_policy), reusing its nonce unless that has been consumed.
+    ///
+    /// If any deployment fails, the error names the contracts which failed, and those which landed
+    /// anyway. The latter are kept in the cache (and the state file, if there is one), and can be
+    /// passed as predeployed on the next run. Otherwise, the addresses are returned in the order of
+    /// `deployments`.
+    ///
+    /// In [dry-run mode](DeployMode::DryRun), or if the sender of the transactions is unknown, so
+    /// that nonces cannot be assigned, the contracts are deployed one at a time instead.
+    pub async fn deploy_all<M: Middleware + 'static>(
+        &mut self,
+        l1: Arc<M>,
+        deployments: Vec<(Contract, TypedTransaction)>,
+    ) -> anyhow::Result<Vec<Address>> {
+        let sender = deployments
+            .first()
+            .and_then(|(_, tx)| tx.from().copied())
+            .or_else(|| l1.default_sender());
+        let (DeployMode::Execute, Some(sender)) = (self.mode, sender) else {
+            let mut addresses = vec![];
+            for (name, tx) in deployments {
+                let l1 = l1.clone();
+                let addr = self
+                    .deploy_fn(name, |contracts| {
+                        async move { contracts.send_tx(name, &*l1, tx).await }.boxed()
+                    })
+                    .await?;
+                addresses.push(addr);
+            }
+            return Ok(addresses);
+        };
+
+        // Skip contracts which are already deployed, and vet the rest before sending anything.
+        self.validate(&*l1).await?;
+        let mut pending = vec![];
+        for (name, tx) in &deployments {
+            if self.contains(*name) {
+                self.deploy_fn(*name, |_| unreachable!()).await?;
+            } else {
+                self.check_duplicate(*name, tx.data().map(|data| &data[..]).unwrap_or_default())?;
+                pending.push((*name, tx.clone()));
+            }
+        }
+        if pending.is_empty() {
+            return Ok(self.batch_addresses(&deployments));
+        }
+
+        // Broadcast all the transactions. If one cannot be sent, the ones after it would be stuck
+        // behind the gap in the nonces, so stop there.
+        if let Err(SendError::Transient(err) | SendError::Fatal(err)) = self
+            .gas_config
+            .wait_for_base_fee(&*l1, self.receipt_polling.interval)
+            .await
+        {
+            return Err(err.context("failed to deploy batch, no transactions were sent"));
+        }
+        let mut nonce = match self.next_nonce(&*l1, Some(sender)).await? {
+            Some(nonce) => nonce,
+            None => l1
+                .get_transaction_count(sender, Some(BlockNumber::Pending.into()))
+                .await
+                .context("error fetching nonce")?,
+        };
+        let mut sent = vec![];
+        let mut failed = vec![];
+        let mut unsent = vec![];
+        for (name, mut tx) in pending {
+            if !failed.is_empty() {
+                unsent.push(name);
+                continue;
+            }
+            self.emit(DeployEvent::Started { contract: name });
+            tx.set_nonce(nonce);
+            self.gas_config.apply(&mut tx);
+            tracing::info!("sending {name} deployment transaction (nonce {nonce})");
+            match broadcast_tx(&*l1, tx.clone(), &format!("{name:?} deployment")).await {
+                Ok(hash) => {
+                    self.emit(DeployEvent::TxSubmitted {
+                        contract: name,
+                        tx_hash: hash,
+                    });
+                    sent.push((name, tx, nonce, hash));
+                    nonce += U256::one();
+                }
+                Err(SendError::Transient(err) | SendError::Fatal(err)) => {
+                    failed.push((name, err.context(format!("failed to deploy {name}"))));
+                }
+            }
+        }
+
+        self.advance_nonce(Some(sender), nonce);
+
+        // Wait for all the transactions together.
+        let retry = self.retry;
+        let polling = self.receipt_polling;
+        let gas = self.gas_config;
+        let explorer = self.explorer.clone();
+        let observers = &self.observers;
+        let results = join_all(sent.into_iter().map(|(name, tx, nonce, hash)| {
+            let l1 = l1.clone();
+            let explorer = explorer.clone();
+            async move {
+                let explorer = explorer.as_ref();
+                let res = await_tx(
+                    &*l1,
+                    hash,
+                    Some(sender),
+                    Some(nonce),
+                    polling,
+                    explorer,
+                    &format!("{name:?} deployment"),
+                )
+                .await
+                .and_then(|receipt| {
+                    Ok((deployed_address(name, &receipt, explorer)?, Some(receipt)))
+                });
+                let res = match res {
+                    Ok(deployed) => Ok(deployed),
+                    Err(SendError::Transient(err)) if retry.max_attempts > 1 => {
+                        let delay = retry.delay(1);
+                        tracing::warn!(
+                            "{name} deployment failed in batch, retrying on its own in \
+                             {delay:?}: {err:#}"
+                        );
+                        sleep(delay).await;
+                        send_deploy_tx(
+                            &*l1,
+                            name,
+                            tx,
+                            &mut Some(nonce),
+                            retry,
+                            polling,
+                            gas,
+                            explorer,
+                            &|tx_hash| {
+                                emit(
+                                    observers,
+                                    explorer,
+                                    DeployEvent::TxSubmitted {
+                                        contract: name,
+                                        tx_hash,
+                                    },
+                                )
+                            },
+                        )
+                        .await
+                    }
+                    Err(SendError::Transient(err) | SendError::Fatal(err)) => {
+                        Err(err.context(format!("failed to deploy {name}")))
+                    }
+                };
+                (name, res)
+            }
+        }))
+        .await;
+
+        let mut landed = vec![];
+        for (name, res) in results {
+            match res {
+                Ok((addr, receipt)) => {
+                    if let Some(receipt) = receipt {
+                        self.record_receipt(name, &receipt);
+                    }
+                    self.record_deployed(name, addr)?;
+                    landed.push((name, addr));
+                }
+                Err(err) => failed.push((name, err)),
+            }
+        }
+        if failed.is_empty() {
+            return Ok(self.batch_addresses(&deployments));
+        }
+
+        for (name, err) in &failed {
+            self.emit(DeployEvent::Failed {
+                contract: *name,
+                error: format!("{err:#}"),
+            });
+        }
+        let mut msg = format!(
+            "batch deployment failed: {}",
+            failed
+                .iter()
+                .map(|(name, err)| format!("{name:?}: {err:#}"))
+                .collect::<Vec<_>>()
+                .join("; ")
+        );
+        if !unsent.is_empty() {
+            msg += &format!("; not sent: {unsent:?}");
+        }
+        if landed.is_empty() {
+            msg += "; no other contract in the batch landed";
+        } else {
+            msg += &format!(
+                "; these landed and can be passed as predeployed on the next run: {}",
+                landed
+                    .iter()
+                    .map(|(name, addr)| format!("{name}={addr:#x}"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            );
+        }
+        tracing::error!("{msg}");
+        Err(anyhow::anyhow!(msg))
+    }
+
+    /// The addresses of a batch of contracts, all of which are in the cache.
+    fn batch_addresses(&self, deployments: &[(Contract, TypedTransaction)]) -> Vec<Address> {
+        deployments
+            .iter()
+            .map(|(name, _)| self.addresses[name])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{deployer::test_helpers::TEST_MNEMONIC, init_signer, AnvilOptions};
+    use contract_bindings::{
+        light_client_state_update_vk::LightClientStateUpdateVK, plonk_verifier::PlonkVerifier,
+    };
+    use ethers::utils::get_contract_address;
+
+    #[async_std::test]
+    async fn test_deploy_all() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let deployer = l1.address();
+        let batch = || {
+            vec![
+                (
+                    Contract::PlonkVerifier,
+                    PlonkVerifier::deploy(l1.clone(), ()).unwrap().deployer.tx,
+                ),
+                (
+                    Contract::StateUpdateVK,
+                    LightClientStateUpdateVK::deploy(l1.clone(), ())
+                        .unwrap()
+                        .deployer
+                        .tx,
+                ),
+            ]
+        };
+
+        let nonce = l1.get_transaction_count(deployer, None).await.unwrap();
+        let mut contracts = Contracts::default();
+        let addresses = contracts.deploy_all(l1.clone(), batch()).await.unwrap();
+
+        // The contracts were deployed with consecutive nonces, in order.
+        assert_eq!(
+            addresses,
+            [
+                get_contract_address(deployer, nonce),
+                get_contract_address(deployer, nonce + 1),
+            ]
+        );
+        for (addr, name) in addresses
+            .iter()
+            .zip([Contract::PlonkVerifier, Contract::StateUpdateVK])
+        {
+            assert_eq!(contracts.address(name), Some(*addr));
+            assert!(!l1.get_code(*addr, None).await.unwrap().is_empty());
+        }
+
+        // Deploying the same batch again is a no-op.
+        let nonce = l1.get_transaction_count(deployer, None).await.unwrap();
+        assert_eq!(
+            contracts.deploy_all(l1.clone(), batch()).await.unwrap(),
+            addresses
+        );
+        assert_eq!(
+            l1.get_transaction_count(deployer, None).await.unwrap(),
+            nonce
+        );
+    }
+
+    #[async_std::test]
+    async fn test_deploy_all_partial_failure() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        // A deployment whose constructor reverts immediately.
+        let mut revert = PlonkVerifier::deploy(l1.clone(), ()).unwrap().deployer.tx;
+        revert.set_data(vec![0x60, 0x00, 0x80, 0xfd].into());
+        revert.set_gas(100_000);
+        let batch = vec![
+            (
+                Contract::PlonkVerifier,
+                PlonkVerifier::deploy(l1.clone(), ()).unwrap().deployer.tx,
+            ),
+            (Contract::StateUpdateVK, revert),
+        ];
+
+        let mut contracts = Contracts::default();
+        let err = contracts
+            .deploy_all(l1.clone(), batch)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("StateUpdateVK"), "{err}");
+        let plonk = contracts.address(Contract::PlonkVerifier).unwrap();
+        assert!(
+            err.contains(&format!("{}={plonk:#x}", Contract::PlonkVerifier)),
+            "{err}"
+        );
+        assert!(!contracts.contains(Contract::StateUpdateVK));
+    }
+}
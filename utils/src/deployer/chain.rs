@@ -0,0 +1,107 @@
+//! Keeping contract addresses from being reused on the wrong chain.
+//!
+//! An address is only meaningful on the chain the contract was deployed to. A cache populated from a
+//! `.env` file written for Sepolia and then used against a local devnet holds addresses which point
+//! at nothing, or at some unrelated contract, and predeployed contracts are reused without any
+//! transaction being sent, so nothing would catch the mistake. With [`Contracts::with_chain_id`],
+//! the cache records the chain its addresses belong to, and [`Contracts::validate`] checks that the
+//! L1 provider is connected to that chain. The same check is made before any contract is deployed,
+//! so nothing is ever sent to the wrong chain.
+
+use super::{
+    error::{DeployerError, WrongChainSnafu},
+    Contracts,
+};
+use anyhow::Context;
+use ethers::prelude::*;
+
+impl Contracts {
+    /// Record that the contracts in this cache belong to the chain with ID `chain_id`.
+    ///
+    /// Deploying with a provider connected to any other chain is an error, so that predeployed
+    /// addresses are never reused on the wrong chain.
+    pub fn with_chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = Some(chain_id);
+        self.chain_validated = false;
+        self
+    }
+
+    /// The chain the contracts in this cache belong to, if recorded.
+    pub fn chain_id(&self) -> Option<u64> {
+        self.chain_id
+    }
+
+    /// Check that `l1` is connected to the chain the contracts in this cache belong to.
+    ///
+    /// Without a [recorded chain ID](Self::with_chain_id), this always passes. Once `l1` has passed,
+    /// the check is not repeated.
+    pub async fn validate<M: Middleware + 'static>(&mut self, l1: &M) -> Result<(), DeployerError> {
+        let Some(expected) = self.chain_id else {
+            return Ok(());
+        };
+        if self.chain_validated {
+            return Ok(());
+        }
+        let actual = l1
+            .get_chainid()
+            .await
+            .context("error fetching chain ID")?
+            .as_u64();
+        if actual != expected {
+            return WrongChainSnafu { expected, actual }.fail();
+        }
+        self.chain_validated = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{test_helpers::TEST_MNEMONIC, Contract},
+        init_signer, AnvilOptions,
+    };
+    use async_std::sync::Arc;
+    use contract_bindings::hot_shot::HotShot;
+
+    #[async_std::test]
+    async fn test_wrong_chain() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let chain_id = l1.get_chainid().await.unwrap().as_u64();
+
+        // Addresses recorded for another chain are refused.
+        let mut contracts = Contracts::default().with_chain_id(chain_id + 1);
+        contracts.insert(Contract::PlonkVerifier, Address::repeat_byte(1));
+        let err = contracts.validate(&*l1).await.unwrap_err();
+        assert!(
+            matches!(err, DeployerError::WrongChain { expected, actual }
+                if expected == chain_id + 1 && actual == chain_id),
+            "{err:?}"
+        );
+        let msg = err.to_string();
+        assert!(msg.contains(&(chain_id + 1).to_string()), "{msg}");
+        assert!(msg.contains(&chain_id.to_string()), "{msg}");
+
+        // Nothing is sent to the wrong chain, even without explicit validation.
+        let nonce = l1.get_transaction_count(l1.address(), None).await.unwrap();
+        let err = contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DeployerError::WrongChain { .. }), "{err:?}");
+        assert_eq!(
+            l1.get_transaction_count(l1.address(), None).await.unwrap(),
+            nonce
+        );
+
+        // The right chain passes.
+        let mut contracts = Contracts::default().with_chain_id(chain_id);
+        contracts.validate(&*l1).await.unwrap();
+        contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+//! Checks on the code of deployed contracts.
+
+use super::{
+    read_proxy_implementation, stake_table::STAKETABLE_ABI, warnings::Warning, Contract, Contracts,
+};
+use anyhow::{ensure, Context};
+use async_std::sync::Arc;
+use contract_bindings::{
+    erc1967_proxy::ERC1967PROXY_DEPLOYED_BYTECODE,
+    fee_contract::{FEECONTRACT_ABI, FEECONTRACT_DEPLOYED_BYTECODE},
+    hot_shot::{HOTSHOT_ABI, HOTSHOT_DEPLOYED_BYTECODE},
+    light_client::{LIGHTCLIENT_ABI, LIGHTCLIENT_DEPLOYED_BYTECODE},
+    light_client_mock::LIGHTCLIENTMOCK_DEPLOYED_BYTECODE,
+    light_client_state_update_vk::LIGHTCLIENTSTATEUPDATEVK_DEPLOYED_BYTECODE,
+    light_client_state_update_vk_mock::LIGHTCLIENTSTATEUPDATEVKMOCK_DEPLOYED_BYTECODE,
+    plonk_verifier::PLONKVERIFIER_DEPLOYED_BYTECODE,
+};
+use ethers::{abi::Function, prelude::*};
+
+impl Contracts {
+    /// Check the code of every contract in the cache.
+    ///
+    /// This is meant to be called before deploying, when the cache contains only predeployed
+    /// contracts. It fails if any address has no code, which usually means the address belongs to a
+    /// different chain or the chain has been reset since the contract was deployed. For contracts
+    /// whose runtime bytecode is bundled with this crate, it also compares the deployed code to the
+    /// expected code, recording a [`Warning::CodeMismatch`] if they differ; this is not an error,
+    /// since the deployed contract may simply be an older or newer version.
+    pub async fn verify_predeployed<M: Middleware + 'static>(
+        &mut self,
+        l1: Arc<M>,
+    ) -> anyhow::Result<()> {
+        for (contract, address) in self.sorted() {
+            let code = l1
+                .get_code(address, None)
+                .await
+                .with_context(|| format!("error fetching code for {contract:?} at {address:#x}"))?;
+            ensure!(
+                !code.is_empty(),
+                "no code at {address:#x} for {contract:?} ({contract}), did your chain get reset?"
+            );
+
+            let expected = expected_runtime_code(contract);
+            if !expected.is_empty() && !expected.iter().any(|exp| code_matches(exp, &code)) {
+                self.warn(Warning::CodeMismatch { contract, address });
+            } else {
+                tracing::debug!("verified code for {contract:?} at {address:#x}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the code of every contract in the cache looks like the right kind of contract.
+    ///
+    /// This catches an address which points at the wrong contract entirely, like a proxy address
+    /// given as the implementation, even if the code is a version this crate has no artifact for.
+    /// For each contract with an [expected function](expected_function), the deployed code must
+    /// dispatch on its selector. The code behind a proxy is read from its implementation slot.
+    /// Libraries, which have no external functions, are only checked for having code.
+    pub async fn check_selectors<M: Middleware + 'static>(&self, l1: Arc<M>) -> anyhow::Result<()> {
+        for (contract, address) in self.sorted() {
+            let target = match contract {
+                Contract::LightClientProxy | Contract::FeeContractProxy => {
+                    read_proxy_implementation(&*l1, address)
+                        .await
+                        .with_context(|| format!("error checking {contract}"))?
+                }
+                _ => address,
+            };
+            let code = l1
+                .get_code(target, None)
+                .await
+                .with_context(|| format!("error fetching code for {contract} at {target:#x}"))?;
+            ensure!(
+                !code.is_empty(),
+                "{contract} points at {address:#x}, which has no code"
+            );
+            let Some(function) = expected_function(contract) else {
+                continue;
+            };
+            ensure!(
+                has_selector(&code, function.short_signature()),
+                "{contract} points at {address:#x}, which does not look like a {contract:?}: its \
+                 code has no {} function",
+                function.name
+            );
+            tracing::debug!(
+                "{contract:?} at {address:#x} has a {} function",
+                function.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Record that the code of the predeployed contracts in the cache was not verified.
+    ///
+    /// This is the counterpart of [`verify_predeployed`](Self::verify_predeployed) when the check
+    /// is skipped, so that the skipped check is not forgotten.
+    pub fn skip_verify_predeployed(&mut self) {
+        for (contract, address) in self.sorted() {
+            self.warn(Warning::Unverified { contract, address });
+        }
+    }
+}
+
+/// The runtime bytecode we expect to find deployed for `contract`.
+///
+/// There may be several candidates, since for example [`Contract::LightClient`] is either the
+/// production light client or the mock, depending on how it was deployed.
+pub fn expected_runtime_code(contract: Contract) -> Vec<&'static Bytes> {
+    match contract {
+        Contract::HotShot => vec![&HOTSHOT_DEPLOYED_BYTECODE],
+        Contract::PlonkVerifier => vec![&PLONKVERIFIER_DEPLOYED_BYTECODE],
+        Contract::StateUpdateVK => vec![
+            &LIGHTCLIENTSTATEUPDATEVK_DEPLOYED_BYTECODE,
+            &LIGHTCLIENTSTATEUPDATEVKMOCK_DEPLOYED_BYTECODE,
+        ],
+        Contract::LightClient => vec![
+            &LIGHTCLIENT_DEPLOYED_BYTECODE,
+            &LIGHTCLIENTMOCK_DEPLOYED_BYTECODE,
+        ],
+        Contract::LightClientProxy | Contract::FeeContractProxy => {
+            vec![&ERC1967PROXY_DEPLOYED_BYTECODE]
+        }
+        Contract::FeeContract => vec![&FEECONTRACT_DEPLOYED_BYTECODE],
+        // The stake table is deployed from a Foundry artifact rather than bundled bytecode.
+        Contract::StakeTable => vec![],
+    }
+}
+
+/// A function we expect every deployment of `contract` to have, if it has external functions.
+///
+/// For a proxy, this is a function of its implementation.
+pub fn expected_function(contract: Contract) -> Option<&'static Function> {
+    let (abi, name) = match contract {
+        Contract::HotShot => (&*HOTSHOT_ABI, "blockHeight"),
+        Contract::LightClient | Contract::LightClientProxy => {
+            (&*LIGHTCLIENT_ABI, "getFinalizedState")
+        }
+        Contract::FeeContract | Contract::FeeContractProxy => (&*FEECONTRACT_ABI, "deposit"),
+        Contract::StakeTable => (&*STAKETABLE_ABI, "lookupNode"),
+        Contract::PlonkVerifier | Contract::StateUpdateVK => return None,
+    };
+    abi.function(name).ok()
+}
+
+/// Whether runtime code `code` pushes `selector`, as the function dispatcher does for each external
+/// function.
+///
+/// The compiler pushes selectors with the shortest `PUSH` instruction which fits, so leading zero
+/// bytes are omitted.
+pub fn has_selector(code: &[u8], selector: [u8; 4]) -> bool {
+    let start = selector.iter().position(|&b| b != 0).unwrap_or(3);
+    let value = &selector[start..];
+    let push = 0x5f + value.len() as u8;
+    code.windows(value.len() + 1)
+        .any(|window| window[0] == push && window[1..] == *value)
+}
+
+/// Strip the CBOR-encoded metadata trailer the Solidity compiler appends to runtime bytecode.
+///
+/// The last two bytes of the code give the length of the metadata section preceding them. If the
+/// code is too short to contain the claimed trailer, it is returned unchanged.
+pub fn strip_metadata(code: &[u8]) -> &[u8] {
+    let Some(len_bytes) = code.len().checked_sub(2).map(|i| &code[i..]) else {
+        return code;
+    };
+    let len = u16::from_be_bytes([len_bytes[0], len_bytes[1]]) as usize;
+    match code.len().checked_sub(len + 2) {
+        Some(end) => &code[..end],
+        None => code,
+    }
+}
+
+/// Whether deployed runtime code `actual` matches the compiled artifact `expected`.
+///
+/// The metadata trailer is ignored, since it depends on the build environment. Other than that, the
+/// code must be identical, except at bytes which are zero in the artifact. The compiler leaves
+/// these zero as placeholders for values which are only known at deploy time, such as immutable
+/// variables and a library's own address.
+pub fn code_matches(expected: &[u8], actual: &[u8]) -> bool {
+    let expected = strip_metadata(expected);
+    let actual = strip_metadata(actual);
+    expected.len() == actual.len()
+        && expected
+            .iter()
+            .zip(actual)
+            .all(|(exp, act)| exp == act || *exp == 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strip_metadata() {
+        // Code followed by 3 bytes of metadata and the 2-byte length.
+        assert_eq!(strip_metadata(&[1, 2, 3, 9, 9, 9, 0, 3]), &[1, 2, 3]);
+        // Claimed metadata length longer than the code.
+        assert_eq!(strip_metadata(&[1, 0, 9]), &[1, 0, 9]);
+        assert_eq!(strip_metadata(&[1]), &[1]);
+        assert_eq!(strip_metadata(&[]), &[] as &[u8]);
+    }
+
+    #[test]
+    fn test_code_matches() {
+        let expected = [0x60, 0x80, 0x00, 0x00, 0x55, 0xaa, 0, 1];
+        // Identical.
+        assert!(code_matches(&expected, &expected));
+        // Placeholder filled in, different metadata.
+        assert!(code_matches(
+            &expected,
+            &[0x60, 0x80, 0x12, 0x34, 0x55, 0xbb, 0xcc, 0, 2]
+        ));
+        // Different non-placeholder byte.
+        assert!(!code_matches(
+            &expected,
+            &[0x60, 0x81, 0x12, 0x34, 0x55, 0xaa, 0, 1]
+        ));
+        // Different length.
+        assert!(!code_matches(&expected, &[0x60, 0x80, 0x55, 0xaa, 0, 1]));
+    }
+
+    #[test]
+    fn test_has_selector() {
+        // PUSH4 selector.
+        assert!(has_selector(&[0x80, 0x63, 1, 2, 3, 4, 0x14], [1, 2, 3, 4]));
+        // Leading zeros are pushed with a shorter instruction.
+        assert!(has_selector(&[0x80, 0x62, 2, 3, 4, 0x14], [0, 2, 3, 4]));
+        assert!(!has_selector(&[0x80, 0x63, 0, 2, 3, 4], [0, 2, 3, 4]));
+        // The bytes must follow a push.
+        assert!(!has_selector(&[0x80, 0x64, 1, 2, 3, 4], [1, 2, 3, 4]));
+        assert!(!has_selector(&[0x63, 1, 2, 3], [1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_bundled_code_has_expected_function() {
+        for contract in <Contract as strum::VariantArray>::VARIANTS {
+            let Some(function) = expected_function(*contract) else {
+                continue;
+            };
+            let code = match contract {
+                Contract::LightClientProxy => &*LIGHTCLIENT_DEPLOYED_BYTECODE,
+                Contract::FeeContractProxy => &*FEECONTRACT_DEPLOYED_BYTECODE,
+                _ => match expected_runtime_code(*contract).first() {
+                    Some(code) => *code,
+                    None => continue,
+                },
+            };
+            assert!(
+                has_selector(code, function.short_signature()),
+                "{contract:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_bundled_code_matches_itself() {
+        for contract in <Contract as strum::VariantArray>::VARIANTS {
+            for code in expected_runtime_code(*contract) {
+                assert!(code_matches(code, code), "{contract:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//! Deterministic deployment with CREATE2.
+//!
+//! Contracts deployed with CREATE land at an address determined by the deployer account and its
+//! nonce, so the same deployment lands at different addresses on different chains. Deploying
+//! through a CREATE2 factory instead makes the address a function of only the factory, a salt, and
+//! the init code (including constructor arguments), so it is the same on every chain.
+//!
+//! With [`Contracts::with_create2`], the libraries `LightClient.sol` links with are deployed this
+//! way, so that they land at the same address on every network. Since the library addresses are
+//! known before anything is sent, linking works the same as for regular deployments.
+
+use super::{
+    code::{code_matches, expected_runtime_code},
+    dry_run::{DeployMode, PlanStep},
+    error::DeployerError,
+    send_tx_once, Contract, Contracts, DeployEvent, SendError,
+};
+use anyhow::{anyhow, ensure, Context};
+use async_std::{sync::Arc, task::sleep};
+use ethers::{
+    prelude::*,
+    types::transaction::eip2718::TypedTransaction,
+    utils::{get_create2_address, keccak256},
+};
+use futures::FutureExt;
+use std::ops::Deref;
+
+/// The canonical deterministic deployment proxy.
+///
+/// This factory is deployed at the same address on most EVM chains, and is predeployed by Anvil. It
+/// takes calldata consisting of a 32-byte salt followed by init code, and deploys the init code with
+/// CREATE2.
+pub const CREATE2_FACTORY: Address = H160([
+    0x4e, 0x59, 0xb4, 0x48, 0x47, 0xb3, 0x79, 0x57, 0x85, 0x88, 0x92, 0x0c, 0xa7, 0x8f, 0xbf, 0x26,
+    0xc0, 0xb4, 0x95, 0x6c,
+]);
+
+/// The address at which `init_code` deployed through `factory` with `salt` will land.
+pub fn create2_address(factory: Address, salt: H256, init_code: &[u8]) -> Address {
+    get_create2_address(factory, salt, init_code)
+}
+
+/// The salt used to deploy `name` with CREATE2.
+///
+/// The salt is a hash of the name of the contract, so that different contracts never collide,
+/// together with `salt`, if given, so that operators can deploy a fresh set of contracts when they
+/// need to.
+pub fn create2_salt(name: Contract, salt: Option<&str>) -> H256 {
+    match salt {
+        Some(salt) => keccak256(format!("{name}:{salt}")),
+        None => keccak256(name.to_string()),
+    }
+    .into()
+}
+
+/// Configuration for deploying libraries with CREATE2.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Create2Config {
+    /// The factory to deploy through, which must follow the calldata convention of
+    /// [`CREATE2_FACTORY`].
+    pub factory: Address,
+    /// Extra input to the salt for each contract (see [`create2_salt`]).
+    pub salt: Option<String>,
+}
+
+impl Default for Create2Config {
+    fn default() -> Self {
+        Self {
+            factory: CREATE2_FACTORY,
+            salt: None,
+        }
+    }
+}
+
+impl Contracts {
+    /// Deploy libraries with CREATE2, according to `config`.
+    ///
+    /// This affects [`deploy_library`](Self::deploy_library); other contracts are always deployed
+    /// with CREATE.
+    pub fn with_create2(mut self, config: Create2Config) -> Self {
+        self.create2 = Some(config);
+        self
+    }
+
+    /// Deploy a library, with CREATE2 if [configured](Self::with_create2).
+    pub async fn deploy_library<M, C>(
+        &mut self,
+        name: Contract,
+        tx: ContractDeployer<M, C>,
+    ) -> Result<Address, DeployerError>
+    where
+        M: Middleware + 'static,
+        C: Deref<Target = ethers::contract::Contract<M>>
+            + From<ContractInstance<Arc<M>, M>>
+            + Send
+            + 'static,
+    {
+        match self.create2.clone() {
+            Some(config) => {
+                let salt = create2_salt(name, config.salt.as_deref());
+                self.deploy_tx_create2(name, tx, salt, config.factory).await
+            }
+            None => self.deploy_tx(name, tx).await,
+        }
+    }
+
+    /// Deploy a contract at a deterministic address using CREATE2.
+    ///
+    /// Like [`deploy_tx`](Self::deploy_tx), this does nothing if `name` is already in the cache.
+    /// Otherwise, the contract is deployed through `factory` with `salt`, unless the predicted
+    /// address is already occupied, for example by the same deployment on a previous run, in which
+    /// case no transaction is sent at all. Either way, the code at the predicted address is checked
+    /// against the bundled artifact for `name`, and the predicted address is returned.
+    ///
+    /// In [dry-run mode](DeployMode::DryRun), the factory transaction is estimated rather than
+    /// sent (or [written out unsigned](Self::with_unsigned_output)), and the predicted address is
+    /// returned.
+    pub async fn deploy_tx_create2<M, C>(
+        &mut self,
+        name: Contract,
+        tx: ContractDeployer<M, C>,
+        salt: H256,
+        factory: Address,
+    ) -> Result<Address, DeployerError>
+    where
+        M: Middleware + 'static,
+        C: Deref<Target = ethers::contract::Contract<M>>
+            + From<ContractInstance<Arc<M>, M>>
+            + Send
+            + 'static,
+    {
+        self.deploy_fn(name, |contracts| {
+            let retry = contracts.retry;
+            let polling = contracts.receipt_polling;
+            let gas = contracts.gas_config;
+            let explorer = contracts.explorer.clone();
+            async move {
+                let l1 = tx.client();
+                contracts.validate(l1).await?;
+                let init_code = tx
+                    .deployer
+                    .tx
+                    .data()
+                    .context("deployment transaction has no init code")?
+                    .clone();
+                let addr = create2_address(factory, salt, &init_code);
+                contracts.check_duplicate(name, &init_code)?;
+
+                let mut data = salt.as_bytes().to_vec();
+                data.extend_from_slice(&init_code);
+                let mut factory_tx: TypedTransaction =
+                    TransactionRequest::new().to(factory).data(data).into();
+                if let Some(from) = tx.deployer.tx.from() {
+                    factory_tx.set_from(*from);
+                }
+                gas.apply(&mut factory_tx);
+
+                let kind = format!("{name:?} CREATE2 deployment");
+                let mut last_err = None;
+                let mut attempt = 1;
+                loop {
+                    // If the contract is already there, from a previous run or a previous attempt
+                    // which timed out, we are done.
+                    let code = l1
+                        .get_code(addr, None)
+                        .await
+                        .context("error checking for deployed code")?;
+                    if !code.is_empty() {
+                        check_code(name, addr, &code)?;
+                        if contracts.mode() == DeployMode::DryRun {
+                            contracts.plan.push(PlanStep::Skip {
+                                contract: name,
+                                address: addr,
+                            });
+                        } else if attempt == 1 {
+                            tracing::info!("{name} already deployed at {addr:#x} with CREATE2");
+                        }
+                        return Ok(addr);
+                    }
+                    if attempt == 1 {
+                        ensure!(
+                            !l1.get_code(factory, None).await?.is_empty(),
+                            "CREATE2 factory {factory:#x} is not deployed on this chain"
+                        );
+                        if contracts.mode() == DeployMode::DryRun {
+                            let step = contracts.estimate(name, l1, &factory_tx).await;
+                            let gas = step.gas();
+                            contracts.plan.push(step);
+                            if contracts.unsigned.is_some() {
+                                contracts
+                                    .write_unsigned(&format!("{name:?}"), l1, factory_tx, gas)
+                                    .await?;
+                            }
+                            return Ok(addr);
+                        }
+                    } else if attempt > retry.max_attempts {
+                        let err = last_err.unwrap_or_else(|| anyhow!("no code at {addr:#x}"));
+                        return Err(err.context(format!("failed to deploy {name} at {addr:#x}")));
+                    }
+
+                    tracing::info!(
+                        "deploying {name} to {addr:#x} with CREATE2 (attempt {attempt}/{})",
+                        retry.max_attempts
+                    );
+                    let res = async {
+                        gas.wait_for_base_fee(l1, polling.interval).await?;
+                        send_tx_once(l1, factory_tx.clone(), polling, explorer.as_ref(), &kind)
+                            .await
+                    }
+                    .await;
+                    match res {
+                        Ok(receipt) => {
+                            contracts.emit(DeployEvent::TxSubmitted {
+                                contract: name,
+                                tx_hash: receipt.transaction_hash,
+                            });
+                            contracts.record_receipt(name, &receipt);
+                        }
+                        // A revert, or a fee cap which stays below the base fee, will not get
+                        // better by retrying.
+                        Err(SendError::Fatal(err)) => {
+                            return Err(
+                                err.context(format!("failed to deploy {name} with CREATE2"))
+                            );
+                        }
+                        Err(SendError::Transient(err)) => {
+                            tracing::warn!("error deploying {name} with CREATE2: {err:#}");
+                            last_err = Some(err);
+                        }
+                    }
+                    if attempt < retry.max_attempts {
+                        // Whatever happened, check the code before (possibly) trying again.
+                        if l1.get_code(addr, None).await.map_or(true, |c| c.is_empty()) {
+                            sleep(retry.delay(attempt)).await;
+                        }
+                    }
+                    attempt += 1;
+                }
+            }
+            .boxed()
+        })
+        .await
+    }
+}
+
+/// Check that `code`, found at the CREATE2 address for `name`, is the code we expect.
+///
+/// Since the CREATE2 address commits to the init code, a mismatch here means something is badly
+/// wrong, like a collision with a different deployment. The check tolerates differences in
+/// placeholders the compiler fills in at deploy time, like a library's own address (see
+/// [`code_matches`]).
+fn check_code(name: Contract, addr: Address, code: &[u8]) -> anyhow::Result<()> {
+    let expected = expected_runtime_code(name);
+    ensure!(
+        expected.is_empty() || expected.iter().any(|exp| code_matches(exp, code)),
+        "code at {addr:#x} does not match the bundled artifact for {name}"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{test_helpers::TEST_MNEMONIC, RetryPolicy},
+        init_signer, AnvilOptions,
+    };
+    use contract_bindings::{
+        light_client_state_update_vk::LightClientStateUpdateVK, plonk_verifier::PlonkVerifier,
+    };
+
+    #[async_std::test]
+    async fn test_deploy_create2() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let salt = H256::repeat_byte(1);
+
+        let mut contracts = Contracts::default();
+        let tx = PlonkVerifier::deploy(l1.clone(), ()).unwrap();
+        let predicted = create2_address(CREATE2_FACTORY, salt, tx.deployer.tx.data().unwrap());
+        let addr = contracts
+            .deploy_tx_create2(Contract::PlonkVerifier, tx, salt, CREATE2_FACTORY)
+            .await
+            .unwrap();
+        assert_eq!(addr, predicted);
+        let code = l1.get_code(addr, None).await.unwrap();
+        check_code(Contract::PlonkVerifier, addr, &code).unwrap();
+        // The code is checked against the right artifact.
+        check_code(Contract::HotShot, addr, &code).unwrap_err();
+
+        // A second run with an empty cache finds the existing deployment without sending anything.
+        let nonce = l1.get_transaction_count(l1.address(), None).await.unwrap();
+        let mut contracts = Contracts::default();
+        assert_eq!(
+            contracts
+                .deploy_tx_create2(
+                    Contract::PlonkVerifier,
+                    PlonkVerifier::deploy(l1.clone(), ()).unwrap(),
+                    salt,
+                    CREATE2_FACTORY,
+                )
+                .await
+                .unwrap(),
+            addr
+        );
+        assert_eq!(
+            l1.get_transaction_count(l1.address(), None).await.unwrap(),
+            nonce
+        );
+
+        // A different salt gives a different address.
+        let mut contracts = Contracts::default();
+        assert_ne!(
+            contracts
+                .deploy_tx_create2(
+                    Contract::PlonkVerifier,
+                    PlonkVerifier::deploy(l1.clone(), ()).unwrap(),
+                    H256::repeat_byte(2),
+                    CREATE2_FACTORY,
+                )
+                .await
+                .unwrap(),
+            addr
+        );
+    }
+
+    #[async_std::test]
+    async fn test_deploy_create2_revert() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        // A "factory" which reverts on every call.
+        let factory = PlonkVerifier::deploy(l1.clone(), ())
+            .unwrap()
+            .send()
+            .await
+            .unwrap()
+            .address();
+
+        // The revert is reported right away, with its cause, rather than retried.
+        let nonce = l1.get_transaction_count(l1.address(), None).await.unwrap();
+        let mut contracts = Contracts::default().with_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            ..Default::default()
+        });
+        let err = contracts
+            .deploy_tx_create2(
+                Contract::PlonkVerifier,
+                PlonkVerifier::deploy(l1.clone(), ()).unwrap(),
+                H256::repeat_byte(1),
+                factory,
+            )
+            .await
+            .unwrap_err();
+        let err = format!("{:#}", anyhow::Error::new(err));
+        assert!(err.contains("failed to deploy"), "{err}");
+        assert!(err.contains("error sending"), "{err}");
+        assert_eq!(
+            l1.get_transaction_count(l1.address(), None).await.unwrap(),
+            nonce
+        );
+    }
+
+    #[async_std::test]
+    async fn test_deploy_libraries_create2() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let config = Create2Config {
+            salt: Some("test".into()),
+            ..Default::default()
+        };
+
+        async fn deploy_libraries<M: Middleware + 'static>(
+            l1: Arc<M>,
+            config: Create2Config,
+        ) -> (Address, Address) {
+            let mut contracts = Contracts::default().with_create2(config);
+            let plonk = contracts
+                .deploy_library(
+                    Contract::PlonkVerifier,
+                    PlonkVerifier::deploy(l1.clone(), ()).unwrap(),
+                )
+                .await
+                .unwrap();
+            let vk = contracts
+                .deploy_library(
+                    Contract::StateUpdateVK,
+                    LightClientStateUpdateVK::deploy(l1, ()).unwrap(),
+                )
+                .await
+                .unwrap();
+            (plonk, vk)
+        }
+
+        let nonce = l1.get_transaction_count(l1.address(), None).await.unwrap();
+        let (plonk, vk) = deploy_libraries(l1.clone(), config.clone()).await;
+        assert_eq!(
+            plonk,
+            create2_address(
+                CREATE2_FACTORY,
+                create2_salt(Contract::PlonkVerifier, Some("test")),
+                PlonkVerifier::deploy(l1.clone(), ())
+                    .unwrap()
+                    .deployer
+                    .tx
+                    .data()
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            l1.get_transaction_count(l1.address(), None).await.unwrap(),
+            nonce + 2
+        );
+
+        // A second run lands at the same addresses, without broadcasting anything.
+        assert_eq!(deploy_libraries(l1.clone(), config).await, (plonk, vk));
+        assert_eq!(
+            l1.get_transaction_count(l1.address(), None).await.unwrap(),
+            nonce + 2
+        );
+
+        // A different salt gives different addresses.
+        let (other_plonk, other_vk) = deploy_libraries(l1.clone(), Create2Config::default()).await;
+        assert_ne!(other_plonk, plonk);
+        assert_ne!(other_vk, vk);
+    }
+
+    #[test]
+    fn test_create2_salt() {
+        assert_ne!(
+            create2_salt(Contract::PlonkVerifier, None),
+            create2_salt(Contract::StateUpdateVK, None)
+        );
+        assert_ne!(
+            create2_salt(Contract::PlonkVerifier, None),
+            create2_salt(Contract::PlonkVerifier, Some("v2"))
+        );
+    }
+}
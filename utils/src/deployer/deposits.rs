@@ -0,0 +1,283 @@
+//! Funding builder accounts in the fee contract.
+//!
+//! Builders pay fees out of their balance in `FeeContract.sol`, so a fresh network needs some
+//! builders funded before it can make progress. Rather than depositing by hand once the fee
+//! contract is deployed, the initial balances can be listed in a JSON file, loaded with
+//! [`BuilderDeposits::from_file`], and deposited with [`deposit_builder_balances`] as part of the
+//! deployment. Deposits made in this run are listed in the
+//! [deployment report](Contracts::write_report).
+
+use super::{dry_run::DeployMode, explorer::fmt_address, send_with_retry, Contract, Contracts};
+use anyhow::{ensure, Context};
+use async_std::sync::Arc;
+use contract_bindings::fee_contract::FeeContract;
+use ethers::{
+    prelude::*,
+    utils::{format_ether, parse_ether},
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{fs, path::Path};
+
+/// The balance a builder account should have in the fee contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuilderDeposit {
+    pub account: Address,
+    /// The balance, in wei. In the config file, this is given in ETH, like `"0.5"`.
+    #[serde(deserialize_with = "deserialize_ether")]
+    pub amount: U256,
+}
+
+/// The initial builder balances, as read from a config file like
+///
+/// ```json
+/// {
+///     "deposits": [
+///         { "account": "0x23618e81e3f5cdf7f54c3d65f7fbc0abf5b21e8f", "amount": "0.5" }
+///     ]
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuilderDeposits {
+    pub deposits: Vec<BuilderDeposit>,
+}
+
+impl BuilderDeposits {
+    /// Read the builder balances in the JSON file at `path`.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("error reading builder deposits {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("invalid builder deposits {}", path.display()))
+    }
+}
+
+/// A deposit sent to the fee contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepositInfo {
+    pub account: Address,
+    /// The amount deposited, in wei.
+    pub amount: U256,
+    /// The balance of the account after the deposit, in wei.
+    pub balance: U256,
+    pub tx_hash: H256,
+}
+
+impl Contracts {
+    /// The builder deposits made in this run, in order.
+    pub fn deposits(&self) -> &[DepositInfo] {
+        &self.deposits
+    }
+}
+
+/// Fund the builder accounts in `deposits` in the fee contract.
+///
+/// The fee contract is [`Contract::FeeContractProxy`], which must be in `contracts`. Each account
+/// is topped up to the balance listed for it: an account which already has at least that balance,
+/// for example because it was funded in a previous run, is skipped, and otherwise only the
+/// difference is deposited, from the sender of `l1`. Every top-up must be within the deposit
+/// bounds of the fee contract, which is checked for all accounts before anything is sent.
+///
+/// Each deposit is retried according to the [retry policy](Contracts::with_retry_policy) of
+/// `contracts`. Before each attempt, the balance is read again, so that an attempt which landed
+/// after all is not repeated.
+pub async fn deposit_builder_balances<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    deposits: &BuilderDeposits,
+) -> anyhow::Result<()> {
+    if deposits.deposits.is_empty() {
+        return Ok(());
+    }
+    let address = contracts
+        .address(Contract::FeeContractProxy)
+        .context("cannot deposit builder balances, fee contract not deployed")?;
+    let explorer = contracts.explorer().cloned();
+    if contracts.mode() == DeployMode::DryRun {
+        for deposit in &deposits.deposits {
+            tracing::info!(
+                "would fund builder {:#x} with {} ETH",
+                deposit.account,
+                format_ether(deposit.amount)
+            );
+        }
+        return Ok(());
+    }
+
+    let fee_contract = FeeContract::new(address, l1.clone());
+    let min = fee_contract
+        .min_deposit_amount()
+        .call()
+        .await
+        .context("error reading minimum deposit")?;
+    let max = fee_contract
+        .max_deposit_amount()
+        .call()
+        .await
+        .context("error reading maximum deposit")?;
+    let mut top_ups = vec![];
+    for deposit in &deposits.deposits {
+        ensure!(
+            !deposit.account.is_zero(),
+            "cannot fund the zero address as a builder"
+        );
+        let balance = read_balance(&fee_contract, deposit.account).await?;
+        if balance >= deposit.amount {
+            tracing::info!(
+                "builder {} already has {} ETH",
+                fmt_address(explorer.as_ref(), deposit.account),
+                format_ether(balance)
+            );
+            continue;
+        }
+        let top_up = deposit.amount - balance;
+        ensure!(
+            top_up >= min && top_up <= max,
+            "deposit of {} ETH for builder {:#x} is outside the bounds of the fee contract ({} to \
+             {} ETH)",
+            format_ether(top_up),
+            deposit.account,
+            format_ether(min),
+            format_ether(max)
+        );
+        top_ups.push(*deposit);
+    }
+
+    let gas = contracts.gas_config;
+    for deposit in top_ups {
+        let account = deposit.account;
+        let fee_contract = &fee_contract;
+        let before = read_balance(fee_contract, account).await?;
+        let what = format!("deposit for builder {account:#x}");
+        let receipt = send_with_retry(&*l1, contracts, &what, |_| async move {
+            let balance = read_balance(fee_contract, account).await?;
+            if balance >= deposit.amount {
+                return Ok(None);
+            }
+            let amount = deposit.amount - balance;
+            tracing::info!(
+                "funding builder {account:#x} with {} ETH",
+                format_ether(amount)
+            );
+            let mut tx = fee_contract.deposit(account).value(amount).tx;
+            gas.apply(&mut tx);
+            Ok::<_, anyhow::Error>(Some(tx))
+        })
+        .await?;
+        let balance = read_balance(fee_contract, account).await?;
+        if let Some(receipt) = receipt {
+            contracts.deposits.push(DepositInfo {
+                account,
+                amount: balance.saturating_sub(before),
+                balance,
+                tx_hash: receipt.transaction_hash,
+            });
+        }
+        ensure!(
+            balance >= deposit.amount,
+            "builder {account:#x} was not funded: balance is {} ETH, expected {} ETH",
+            format_ether(balance),
+            format_ether(deposit.amount)
+        );
+    }
+    Ok(())
+}
+
+/// The balance of `account` in `fee_contract`.
+async fn read_balance<M: Middleware + 'static>(
+    fee_contract: &FeeContract<M>,
+    account: Address,
+) -> anyhow::Result<U256> {
+    fee_contract
+        .balances(account)
+        .call()
+        .await
+        .with_context(|| format!("error reading fee contract balance of {account:#x}"))
+}
+
+/// Parse an amount of ETH, like `"0.5"`, into wei.
+fn deserialize_ether<'de, D: Deserializer<'de>>(d: D) -> Result<U256, D::Error> {
+    let s = String::deserialize(d)?;
+    parse_ether(&s).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{deploy_fee_contract, test_helpers::TEST_MNEMONIC},
+        init_signer, AnvilOptions,
+    };
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[async_std::test]
+    async fn test_deposit_builder_balances() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let builder = Address::repeat_byte(1);
+        let other = Address::repeat_byte(2);
+
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            r#"{{"deposits": [
+                {{"account": "{builder:#x}", "amount": "0.5"}},
+                {{"account": "{other:#x}", "amount": "0.01"}}
+            ]}}"#
+        )
+        .unwrap();
+        let deposits = BuilderDeposits::from_file(file.path()).unwrap();
+        assert_eq!(deposits.deposits[0].amount, parse_ether("0.5").unwrap());
+
+        let mut contracts = Contracts::default();
+        let proxy = deploy_fee_contract(l1.clone(), &mut contracts, l1.address())
+            .await
+            .unwrap();
+        let fee_contract = FeeContract::new(proxy, l1.clone());
+
+        deposit_builder_balances(l1.clone(), &mut contracts, &deposits)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_balance(&fee_contract, builder).await.unwrap(),
+            parse_ether("0.5").unwrap()
+        );
+        assert_eq!(
+            read_balance(&fee_contract, other).await.unwrap(),
+            parse_ether("0.01").unwrap()
+        );
+        assert_eq!(contracts.deposits().len(), 2);
+
+        // Running again only tops up accounts below their balance.
+        let mut more = deposits.clone();
+        more.deposits[1].amount = parse_ether("0.02").unwrap();
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::FeeContractProxy, proxy);
+        deposit_builder_balances(l1.clone(), &mut contracts, &more)
+            .await
+            .unwrap();
+        assert_eq!(
+            contracts.deposits(),
+            [DepositInfo {
+                account: other,
+                amount: parse_ether("0.01").unwrap(),
+                balance: parse_ether("0.02").unwrap(),
+                tx_hash: contracts.deposits()[0].tx_hash,
+            }]
+        );
+
+        // Deposits outside the bounds of the fee contract are refused before anything is sent.
+        let mut too_large = deposits.clone();
+        too_large.deposits[1].amount = parse_ether("5").unwrap();
+        let nonce = l1.get_transaction_count(l1.address(), None).await.unwrap();
+        let err = deposit_builder_balances(l1.clone(), &mut contracts, &too_large)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("outside the bounds"), "{err}");
+        assert_eq!(
+            l1.get_transaction_count(l1.address(), None).await.unwrap(),
+            nonce
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! Comparing a deployment on chain against the deployment it is supposed to be.
+//!
+//! [`audit`](super::status::audit) reports what is deployed, and compares networks with each
+//! other. [`diff_deployment`] instead compares one network against expectations: the addresses in
+//! an env or JSON file written by the deployer, the artifacts bundled in this crate, and optionally
+//! a genesis state. Every check is listed in a [`DeploymentDiff`], so that it can be printed as a
+//! table, and [`DeploymentDiff::is_clean`] tells whether anything did not match, for use as a
+//! post-deployment gate.
+//!
+//! The stake table capacity the light client was deployed for is not stored on chain, but it
+//! determines the stake table commitments in the genesis state, so it is covered by comparing the
+//! genesis state.
+
+use super::{
+    code::{code_matches, expected_runtime_code},
+    read_proxy_implementation, Contract, Contracts,
+};
+use anyhow::Context;
+use async_std::sync::Arc;
+use contract_bindings::light_client::LightClient;
+use ethers::{abi::AbiEncode, prelude::*, utils::keccak256};
+use hotshot_contract_adapter::light_client::ParsedLightClientState;
+use serde::Serialize;
+use std::{fs, io::Write, path::Path};
+
+/// One check of a deployed contract against its expected state.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DiffEntry {
+    pub contract: Contract,
+    pub address: Address,
+    /// What was checked, like `code` or `implementation`.
+    pub property: String,
+    pub expected: String,
+    pub actual: String,
+    pub matches: bool,
+}
+
+/// The result of [`diff_deployment`].
+#[derive(Clone, Debug, Serialize)]
+pub struct DeploymentDiff {
+    pub chain_id: u64,
+    pub entries: Vec<DiffEntry>,
+}
+
+impl DeploymentDiff {
+    /// Whether every check passed.
+    pub fn is_clean(&self) -> bool {
+        self.entries.iter().all(|entry| entry.matches)
+    }
+
+    /// The checks which did not pass.
+    pub fn mismatches(&self) -> impl Iterator<Item = &DiffEntry> {
+        self.entries.iter().filter(|entry| !entry.matches)
+    }
+
+    /// Write the checks as a human-readable table, followed by the number of mismatches.
+    pub fn write_table(&self, mut w: impl Write) -> anyhow::Result<()> {
+        writeln!(
+            w,
+            "{:<20} {:<44} {:<16} {:<68} {:<68} RESULT",
+            "CONTRACT", "ADDRESS", "PROPERTY", "EXPECTED", "ACTUAL"
+        )?;
+        for entry in &self.entries {
+            writeln!(
+                w,
+                "{:<20} {:<44} {:<16} {:<68} {:<68} {}",
+                format!("{:?}", entry.contract),
+                format!("{:#x}", entry.address),
+                entry.property,
+                entry.expected,
+                entry.actual,
+                if entry.matches { "ok" } else { "MISMATCH" }
+            )?;
+        }
+        match self.mismatches().count() {
+            0 => writeln!(w, "\ndeployment on chain {} matches", self.chain_id)?,
+            n => writeln!(w, "\n{n} mismatches on chain {}", self.chain_id)?,
+        }
+        Ok(())
+    }
+
+    /// Write the checks as JSON.
+    pub fn write_json(&self, w: impl Write) -> anyhow::Result<()> {
+        serde_json::to_writer_pretty(w, self)?;
+        Ok(())
+    }
+}
+
+/// Load the contract addresses in `path`, either a .env file as written by [`Contracts::write`]
+/// or a JSON file as written by [`Contracts::write_json`].
+///
+/// JSON files are recognized by their contents, not their extension. Keys which do not name a
+/// known contract, like `warnings`, are ignored.
+pub fn load_deployment_file(path: &Path) -> anyhow::Result<Contracts> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("error reading {}", path.display()))?;
+    if !contents.trim_start().starts_with('{') {
+        return Contracts::read(contents.as_bytes())
+            .with_context(|| format!("error reading {}", path.display()));
+    }
+    let json: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&contents)
+        .with_context(|| format!("{} is not valid JSON", path.display()))?;
+    let mut contracts = Contracts::default();
+    for (key, value) in json {
+        let Ok(contract) = key.parse::<Contract>() else {
+            continue;
+        };
+        let address = value
+            .as_str()
+            .and_then(|value| value.parse().ok())
+            .with_context(|| format!("invalid address for {key}: {value}"))?;
+        contracts.addresses.insert(contract, address);
+    }
+    Ok(contracts)
+}
+
+/// Compare the contracts in `contracts` as deployed on the chain `l1` is connected to against
+/// what they are expected to be.
+///
+/// For every contract, this checks that there is code at its address, and that the code matches
+/// one of the artifacts bundled in this crate, ignoring library addresses, immutables and the
+/// metadata trailer. For proxies, it checks that the implementation slot points at the listed
+/// implementation, if one is listed. For the light client (through its proxy, if listed), it checks
+/// the number of blocks per epoch against the [parameters](Contracts::light_client_params) of
+/// `contracts` and, if given, the genesis state against `genesis`.
+pub async fn diff_deployment<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &Contracts,
+    genesis: Option<&ParsedLightClientState>,
+) -> anyhow::Result<DeploymentDiff> {
+    let chain_id = l1
+        .get_chainid()
+        .await
+        .context("error fetching chain ID")?
+        .as_u64();
+    let mut entries = vec![];
+    let mut entry = |contract, address, property: &str, expected, actual, matches| {
+        entries.push(DiffEntry {
+            contract,
+            address,
+            property: property.into(),
+            expected,
+            actual,
+            matches,
+        })
+    };
+
+    let mut light_client = None;
+    for (contract, address) in contracts.sorted() {
+        let code = l1
+            .get_code(address, None)
+            .await
+            .with_context(|| format!("error fetching code for {contract:?} at {address:#x}"))?;
+        if code.is_empty() {
+            entry(
+                contract,
+                address,
+                "code",
+                "code".into(),
+                "no code".into(),
+                false,
+            );
+            continue;
+        }
+        let expected = expected_runtime_code(contract);
+        entry(
+            contract,
+            address,
+            "code",
+            if expected.is_empty() {
+                "no bundled artifact".into()
+            } else {
+                "bundled artifact".into()
+            },
+            format!("{:#x}", H256::from(keccak256(&code))),
+            expected.is_empty()
+                || expected
+                    .iter()
+                    .any(|expected| code_matches(expected, &code)),
+        );
+
+        let implementation = match contract {
+            Contract::LightClientProxy => Some(Contract::LightClient),
+            Contract::FeeContractProxy => Some(Contract::FeeContract),
+            _ => None,
+        };
+        if let Some(implementation) = implementation {
+            let actual = read_proxy_implementation(&*l1, address).await?;
+            let (expected, matches) = match contracts.address(implementation) {
+                Some(listed) => (format!("{listed:#x}"), listed == actual),
+                None => (format!("{implementation:?} not listed"), true),
+            };
+            entry(
+                contract,
+                address,
+                "implementation",
+                expected,
+                format!("{actual:#x}"),
+                matches,
+            );
+        }
+
+        // Read the light client parameters through the proxy if there is one, otherwise from the
+        // light client contract itself (as is the case for the non-upgradable mock).
+        if contract == Contract::LightClientProxy
+            || (contract == Contract::LightClient && light_client.is_none())
+        {
+            light_client = Some((contract, address));
+        }
+    }
+
+    if let Some((contract, address)) = light_client {
+        let light_client = LightClient::new(address, l1.clone());
+        let blocks_per_epoch = light_client
+            .blocks_per_epoch()
+            .call()
+            .await
+            .context("error reading blocks per epoch")?;
+        let expected = contracts.light_client_params().blocks_per_epoch;
+        entry(
+            contract,
+            address,
+            "blocks per epoch",
+            expected.to_string(),
+            blocks_per_epoch.to_string(),
+            blocks_per_epoch == expected,
+        );
+
+        if let Some(genesis) = genesis {
+            let actual: ParsedLightClientState = light_client
+                .get_genesis_state()
+                .call()
+                .await
+                .context("error reading genesis state")?
+                .into();
+            entry(
+                contract,
+                address,
+                "genesis state",
+                fmt_state(genesis),
+                fmt_state(&actual),
+                actual == *genesis,
+            );
+        }
+    }
+
+    Ok(DeploymentDiff { chain_id, entries })
+}
+
+/// A commitment to `state`: the hash of its ABI encoding.
+fn fmt_state(state: &ParsedLightClientState) -> String {
+    format!("{:#x}", H256::from(keccak256(state.clone().encode())))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{deploy_light_client_and_initialize_proxy, test_helpers::TEST_MNEMONIC},
+        init_signer, AnvilOptions,
+    };
+    use contract_bindings::hot_shot::HotShot;
+    use tempfile::TempDir;
+
+    #[async_std::test]
+    async fn test_diff_deployment() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let genesis = ParsedLightClientState::dummy_genesis();
+
+        let mut contracts = Contracts::default();
+        let hotshot = contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+        deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            genesis.clone(),
+            l1.address(),
+        )
+        .await
+        .unwrap();
+
+        // The deployment, read back from either output format, matches.
+        let dir = TempDir::new().unwrap();
+        let env = dir.path().join("deployment.env");
+        contracts.write(fs::File::create(&env).unwrap()).unwrap();
+        let json = dir.path().join("deployment.json");
+        contracts
+            .write_json(fs::File::create(&json).unwrap())
+            .unwrap();
+        for path in [env, json] {
+            let loaded = load_deployment_file(&path).unwrap();
+            assert_eq!(loaded.sorted(), contracts.sorted(), "{}", path.display());
+            let diff = diff_deployment(l1.clone(), &loaded, Some(&genesis))
+                .await
+                .unwrap();
+            assert!(diff.is_clean(), "{diff:#?}");
+            assert!(diff
+                .entries
+                .iter()
+                .any(|entry| entry.property == "genesis state"));
+        }
+
+        // Point the light client implementation at HotShot, HotShot at an address without code,
+        // and expect a different genesis.
+        let proxy = contracts.address(Contract::LightClientProxy).unwrap();
+        let mut wrong = Contracts::default();
+        wrong.insert(Contract::LightClientProxy, proxy);
+        wrong.insert(Contract::LightClient, hotshot);
+        wrong.insert(Contract::HotShot, Address::repeat_byte(1));
+        let other_genesis = ParsedLightClientState {
+            threshold: 2.into(),
+            ..genesis.clone()
+        };
+        let diff = diff_deployment(l1.clone(), &wrong, Some(&other_genesis))
+            .await
+            .unwrap();
+        assert!(!diff.is_clean());
+        let mismatches: Vec<_> = diff
+            .mismatches()
+            .map(|entry| (entry.contract, entry.property.as_str()))
+            .collect();
+        assert_eq!(
+            mismatches,
+            [
+                (Contract::HotShot, "code"),
+                (Contract::LightClient, "code"),
+                (Contract::LightClientProxy, "implementation"),
+                (Contract::LightClientProxy, "genesis state"),
+            ]
+        );
+
+        let mut table = vec![];
+        diff.write_table(&mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert!(table.contains("MISMATCH"), "{table}");
+        assert!(table.contains("4 mismatches"), "{table}");
+    }
+}
@@ -0,0 +1,365 @@
+//! Dry runs, which plan a deployment without sending any transactions.
+//!
+//! In dry-run mode, [`Contracts`] walks the same dependency graph as a real deployment, but instead
+//! of broadcasting deployment transactions it estimates their gas usage and records a
+//! [`PlanStep`] for each contract. Contracts which would be deployed are given a deterministic
+//! placeholder address, so that contracts depending on them, like `LightClient.sol`, which links
+//! the library addresses into its bytecode, can still be planned.
+//!
+//! The plan lists the size and hash of the init code each deployment would send, so that linking
+//! errors are caught, and the bytecode can be compared against a build, before anything is spent.
+//! Libraries which would be deployed are linked at their placeholder addresses, so the hash of a
+//! contract linking with them differs from the one the real deployment will send.
+
+use super::{Contract, Contracts};
+use ethers::{
+    prelude::*,
+    types::transaction::eip2718::TypedTransaction,
+    utils::{format_ether, keccak256},
+};
+use std::io::Write;
+
+/// Whether deployment transactions are actually sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeployMode {
+    /// Send deployment transactions.
+    #[default]
+    Execute,
+    /// Estimate the gas for deployment transactions, but do not send them.
+    DryRun,
+}
+
+/// What a dry run found would happen to one contract.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlanStep {
+    /// The contract is already deployed, and would be reused.
+    Skip {
+        contract: Contract,
+        address: Address,
+    },
+    /// The contract would be deployed, using about `gas` gas.
+    Deploy { contract: Contract, gas: U256 },
+    /// The contract would be deployed, but the gas cannot be estimated because the deployment
+    /// depends on contracts which are not deployed yet.
+    Unestimated {
+        contract: Contract,
+        depends_on: Vec<Contract>,
+    },
+    /// The deployment would fail.
+    Revert { contract: Contract, error: String },
+}
+
+impl PlanStep {
+    /// The contract this step is for.
+    pub fn contract(&self) -> Contract {
+        match self {
+            Self::Skip { contract, .. }
+            | Self::Deploy { contract, .. }
+            | Self::Unestimated { contract, .. }
+            | Self::Revert { contract, .. } => *contract,
+        }
+    }
+
+    /// The estimated gas for this step, if it is a deployment which could be estimated.
+    pub fn gas(&self) -> Option<U256> {
+        match self {
+            Self::Deploy { gas, .. } => Some(*gas),
+            _ => None,
+        }
+    }
+}
+
+/// The address used in place of `contract` in a dry run, when it would be deployed.
+///
+/// This is a deterministic function of the contract, so that plans are reproducible.
+pub fn placeholder_address(contract: Contract) -> Address {
+    Address::from_slice(&keccak256(format!("dry-run:{contract}"))[12..])
+}
+
+impl Contracts {
+    /// Set whether deployment transactions are actually sent.
+    pub fn with_mode(mut self, mode: DeployMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn mode(&self) -> DeployMode {
+        self.mode
+    }
+
+    /// The steps recorded so far in a dry run.
+    pub fn plan(&self) -> &[PlanStep] {
+        &self.plan
+    }
+
+    /// The init code the dry run would send to deploy `contract`, including any linked library
+    /// addresses and constructor arguments.
+    ///
+    /// This is `None` for contracts which would be skipped.
+    pub fn planned_init_code(&self, contract: Contract) -> Option<&Bytes> {
+        self.plan_init_codes.get(&contract)
+    }
+
+    /// Whether every deployment in the dry run is expected to succeed.
+    pub fn plan_succeeds(&self) -> bool {
+        !self
+            .plan
+            .iter()
+            .any(|step| matches!(step, PlanStep::Revert { .. }))
+    }
+
+    /// The total estimated gas of the deployments in the dry run which could be estimated.
+    pub fn estimated_gas(&self) -> U256 {
+        self.plan
+            .iter()
+            .filter_map(PlanStep::gas)
+            .fold(U256::zero(), |a, b| a + b)
+    }
+
+    /// Estimate the gas for the deployment of `contract` by `tx`.
+    pub(super) async fn estimate<M: Middleware + 'static>(
+        &self,
+        contract: Contract,
+        client: &M,
+        tx: &TypedTransaction,
+    ) -> PlanStep {
+        match client.estimate_gas(tx, None).await {
+            Ok(gas) => PlanStep::Deploy { contract, gas },
+            Err(err) => {
+                // If the deployment refers to contracts which only exist in the plan, it may well
+                // fail now and yet succeed in a real deployment.
+                let depends_on = self.placeholder_dependencies(tx);
+                if depends_on.is_empty() {
+                    PlanStep::Revert {
+                        contract,
+                        error: err.to_string(),
+                    }
+                } else {
+                    tracing::debug!("cannot estimate gas for {contract}: {err}");
+                    PlanStep::Unestimated {
+                        contract,
+                        depends_on,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Contracts planned for deployment whose planned address appears in `tx`.
+    fn placeholder_dependencies(&self, tx: &TypedTransaction) -> Vec<Contract> {
+        let Some(data) = tx.data() else {
+            return vec![];
+        };
+        self.plan
+            .iter()
+            .filter_map(|step| match step {
+                PlanStep::Deploy { contract, .. }
+                | PlanStep::Unestimated { contract, .. }
+                | PlanStep::Revert { contract, .. } => Some(*contract),
+                PlanStep::Skip { .. } => None,
+            })
+            .filter(|contract| {
+                self.addresses
+                    .get(contract)
+                    .is_some_and(|addr| data.windows(20).any(|w| w == addr.as_bytes()))
+            })
+            .collect()
+    }
+
+    /// Write a human-readable summary of the dry run.
+    ///
+    /// Every deployment is listed with the size and hash of its [init
+    /// code](Self::planned_init_code). The total cost is estimated at `gas_price`.
+    pub fn write_plan(&self, mut w: impl Write, gas_price: U256) -> anyhow::Result<()> {
+        writeln!(w, "Dry run: no transactions were sent.")?;
+        for step in &self.plan {
+            match step {
+                PlanStep::Skip { contract, address } => writeln!(
+                    w,
+                    "skip     {:<20} already deployed at {address:#x}",
+                    fmt(contract)
+                )?,
+                PlanStep::Deploy { contract, gas } => {
+                    writeln!(w, "deploy   {:<20} gas {gas}", fmt(contract))?
+                }
+                PlanStep::Unestimated {
+                    contract,
+                    depends_on,
+                } => writeln!(
+                    w,
+                    "deploy   {:<20} gas unknown, depends on {} which would be deployed first",
+                    fmt(contract),
+                    depends_on.iter().map(fmt).collect::<Vec<_>>().join(", ")
+                )?,
+                PlanStep::Revert { contract, error } => {
+                    writeln!(w, "REVERT   {:<20} {error}", fmt(contract))?
+                }
+            }
+            if let Some(init_code) = self.planned_init_code(step.contract()) {
+                writeln!(
+                    w,
+                    "         {:<20} calldata {} bytes, init code {:#x}",
+                    "",
+                    init_code.len(),
+                    H256(keccak256(init_code))
+                )?;
+            }
+        }
+        let total_gas = self.estimated_gas();
+        let cost = total_gas * gas_price;
+        writeln!(
+            w,
+            "total gas {total_gas}, at gas price {gas_price} wei: {} ETH",
+            format_ether(cost)
+        )?;
+        if !self.plan_succeeds() {
+            writeln!(w, "Some deployments would fail.")?;
+        }
+        Ok(())
+    }
+}
+
+fn fmt(contract: &Contract) -> String {
+    format!("{contract:?}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{deploy_light_client_and_initialize_proxy, test_helpers::TEST_MNEMONIC},
+        init_signer, AnvilOptions,
+    };
+    use async_std::sync::Arc;
+    use contract_bindings::hot_shot::HotShot;
+    use futures::FutureExt;
+    use hotshot_contract_adapter::light_client::ParsedLightClientState;
+
+    #[async_std::test]
+    async fn test_dry_run() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let nonce = l1.get_transaction_count(l1.address(), None).await.unwrap();
+
+        let mut contracts = Contracts::default().with_mode(DeployMode::DryRun);
+        contracts.insert(Contract::HotShot, Address::repeat_byte(1));
+        contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            l1.address(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(proxy, placeholder_address(Contract::LightClientProxy));
+
+        // Nothing was sent.
+        assert_eq!(
+            l1.get_transaction_count(l1.address(), None).await.unwrap(),
+            nonce
+        );
+
+        let plan = contracts.plan();
+        assert_eq!(
+            plan[0],
+            PlanStep::Skip {
+                contract: Contract::HotShot,
+                address: Address::repeat_byte(1)
+            }
+        );
+        for (step, contract) in plan[1..4].iter().zip([
+            Contract::PlonkVerifier,
+            Contract::StateUpdateVK,
+            Contract::LightClient,
+        ]) {
+            assert!(
+                matches!(step, PlanStep::Deploy { contract: c, gas } if *c == contract && !gas.is_zero()),
+                "{step:?}"
+            );
+        }
+        // The proxy initializes the light client, which doesn't exist yet.
+        assert_eq!(
+            plan[4],
+            PlanStep::Unestimated {
+                contract: Contract::LightClientProxy,
+                depends_on: vec![Contract::LightClient],
+            }
+        );
+        assert!(contracts.plan_succeeds());
+        let total = plan[1..4]
+            .iter()
+            .map(|step| step.gas().unwrap())
+            .fold(U256::zero(), |a, b| a + b);
+        assert_eq!(contracts.estimated_gas(), total);
+
+        let mut out = vec![];
+        contracts
+            .write_plan(&mut out, 1_000_000_000.into())
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("skip     HotShot"), "{out}");
+        assert!(out.contains("depends on LightClient"), "{out}");
+        assert!(out.contains(&format!("total gas {total}")), "{out}");
+
+        // Every deployment lists the init code it would send, with the libraries linked in.
+        assert_eq!(contracts.planned_init_code(Contract::HotShot), None);
+        let light_client = contracts.planned_init_code(Contract::LightClient).unwrap();
+        for library in [Contract::PlonkVerifier, Contract::StateUpdateVK] {
+            let address = placeholder_address(library);
+            assert!(
+                light_client.windows(20).any(|w| w == address.as_bytes()),
+                "{library} not linked"
+            );
+        }
+        for contract in [
+            Contract::PlonkVerifier,
+            Contract::StateUpdateVK,
+            Contract::LightClient,
+            Contract::LightClientProxy,
+        ] {
+            let init_code = contracts.planned_init_code(contract).unwrap();
+            assert!(
+                out.contains(&format!(
+                    "calldata {} bytes, init code {:#x}",
+                    init_code.len(),
+                    H256(keccak256(init_code))
+                )),
+                "{out}"
+            );
+        }
+    }
+
+    #[async_std::test]
+    async fn test_dry_run_revert() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        // A constructor which always reverts: `PUSH1 0 DUP1 REVERT`.
+        let tx: TypedTransaction = TransactionRequest::new()
+            .data(vec![0x60, 0x00, 0x80, 0xfd])
+            .into();
+        let mut contracts = Contracts::default().with_mode(DeployMode::DryRun);
+        contracts
+            .deploy_fn(Contract::HotShot, |contracts| {
+                async move { contracts.send_tx(Contract::HotShot, &*l1, tx).await }.boxed()
+            })
+            .await
+            .unwrap();
+        assert!(
+            matches!(
+                contracts.plan(),
+                [PlanStep::Revert {
+                    contract: Contract::HotShot,
+                    ..
+                }]
+            ),
+            "{:?}",
+            contracts.plan()
+        );
+        assert!(!contracts.plan_succeeds());
+    }
+}
@@ -0,0 +1,302 @@
+//! Typed errors for deploying contracts.
+//!
+//! [`Contracts::deploy_fn`](super::Contracts::deploy_fn),
+//! [`Contracts::deploy_tx`](super::Contracts::deploy_tx) and the light client deployment functions
+//! fail with a [`DeployerError`], so that code embedding the deployer can tell a linking failure
+//! from an unreachable RPC or a reverted constructor without matching on error messages. [`DeployerError`] is a standard error, so `?` still converts it into an
+//! [`anyhow::Error`], from which it can be recovered with [`anyhow::Error::downcast`].
+//!
+//! Reverts are reported with the revert data, which is decoded against the custom errors in the ABI
+//! of the contract being deployed when possible, so that the message reads `InvalidProof()` rather
+//! than a raw hex blob.
+
+use super::{stake_table::STAKETABLE_ABI, Contract, LightClientArtifact};
+use contract_bindings::{
+    erc1967_proxy::ERC1967PROXY_ABI, fee_contract::FEECONTRACT_ABI, hot_shot::HOTSHOT_ABI,
+    light_client_mock::LIGHTCLIENTMOCK_ABI,
+    light_client_state_update_vk::LIGHTCLIENTSTATEUPDATEVK_ABI, plonk_verifier::PLONKVERIFIER_ABI,
+};
+use ethers::{
+    abi::{self, Abi, ParamType, Token},
+    prelude::*,
+    providers::MiddlewareError as _,
+    utils::id,
+};
+use snafu::{IntoError, Snafu};
+
+/// A boxed error, as the source of a [`DeployerError`].
+pub type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Why deploying a contract failed.
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(super)))]
+pub enum DeployerError {
+    /// The bytecode of `contract` could not be linked with `library`, given by its fully qualified
+    /// name.
+    #[snafu(display("error linking {library} into {contract:?}"))]
+    Linking {
+        contract: Contract,
+        library: &'static str,
+    },
+    /// The L1 RPC could not be reached or returned an error.
+    ///
+    /// The source is the error of the middleware, for which
+    /// [`as_provider_error`](ethers::providers::MiddlewareError::as_provider_error) gives the
+    /// underlying [`ProviderError`].
+    #[snafu(display("RPC error deploying {contract:?}"))]
+    Rpc {
+        contract: Contract,
+        source: BoxError,
+    },
+    /// The constructor of `contract` reverted.
+    ///
+    /// If the revert was detected when estimating gas, `data` is the revert data, and there is no
+    /// transaction. If the deployment transaction was mined but reverted, its hash is given, but
+    /// the revert data is not available.
+    #[snafu(display(
+        "{contract:?} deployment reverted{}: {}",
+        tx_hash.map_or(String::new(), |hash| format!(" (tx {hash:#x})")),
+        decode_revert(*contract, data)
+    ))]
+    Revert {
+        contract: Contract,
+        data: Bytes,
+        tx_hash: Option<H256>,
+    },
+    /// There is no code at `address`, where `library` would be linked into the light client.
+    ///
+    /// The linked light client would deploy, but its calls into the library would silently do
+    /// nothing.
+    #[snafu(display(
+        "{library:?} is linked into the light client at {address:#x}, but there is no code at that \
+         address; was it deployed to a different chain?"
+    ))]
+    EmptyLibrary { library: Contract, address: Address },
+    /// The bytecode of the light client `artifact` does not implement the functions `missing` from
+    /// its ABI, so the artifact and the bindings are out of sync.
+    #[snafu(display(
+        "{artifact:?} light client bytecode does not match its ABI, missing {}; the bytecode \
+         artifact is likely stale",
+        missing.join(", ")
+    ))]
+    AbiMismatch {
+        artifact: LightClientArtifact,
+        missing: Vec<String>,
+    },
+    /// The bytecode of `artifact` still has library placeholders after linking with the light
+    /// client libraries.
+    #[snafu(display(
+        "{artifact:?} has unresolved library placeholders after linking: {}",
+        placeholders.join(", ")
+    ))]
+    UnresolvedPlaceholders {
+        artifact: LightClientArtifact,
+        placeholders: Vec<String>,
+    },
+    /// `contract` has the same init code as `original`, which was already deployed in this run.
+    #[snafu(display(
+        "{contract} has the same init code as {original}, which was already deployed in this run; \
+         allow duplicate bytecode to deploy it anyway"
+    ))]
+    DuplicateBytecode {
+        contract: Contract,
+        original: Contract,
+    },
+    /// `contract` is not in the cache: it was neither deployed nor given as predeployed.
+    #[snafu(display(
+        "{contract:?} is not deployed; deploy it, or give its address with {contract}"
+    ))]
+    NotDeployed { contract: Contract },
+    /// The L1 provider is connected to chain `actual`, but the contracts in the cache belong to
+    /// chain `expected`.
+    #[snafu(display(
+        "contracts were recorded for chain {expected}, but the L1 provider is on chain {actual}"
+    ))]
+    WrongChain { expected: u64, actual: u64 },
+    /// Any other failure, like invalid configuration.
+    #[snafu(transparent)]
+    Other { source: BoxError },
+}
+
+impl DeployerError {
+    /// Classify an error deploying `contract` through middleware `M`.
+    ///
+    /// Errors from the middleware are recognized by type, so this works through any context added
+    /// to `err`.
+    pub(super) fn classify<M: Middleware + 'static>(
+        contract: Contract,
+        err: anyhow::Error,
+    ) -> Self {
+        let err = match err.downcast::<Self>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        if let Some(Reverted { tx_hash }) = err.downcast_ref::<Reverted>() {
+            return RevertSnafu {
+                contract,
+                data: Bytes::default(),
+                tx_hash: Some(*tx_hash),
+            }
+            .build();
+        }
+        if let Some(cause) = err.downcast_ref::<M::Error>() {
+            if let Some(resp) = cause.as_error_response().filter(|resp| resp.is_revert()) {
+                return RevertSnafu {
+                    contract,
+                    data: resp.as_revert_data().unwrap_or_default(),
+                    tx_hash: None,
+                }
+                .build();
+            }
+            if cause.as_provider_error().is_some() {
+                return RpcSnafu { contract }.into_error(err.into());
+            }
+        }
+        Self::Other {
+            source: err.context(format!("failed to deploy {contract}")).into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for DeployerError {
+    /// Recover a [`DeployerError`] converted into `err`, or wrap `err` as [`Other`](Self::Other).
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<Self>() {
+            Ok(err) => err,
+            Err(err) => Self::Other { source: err.into() },
+        }
+    }
+}
+
+impl<M: Middleware + 'static> From<ContractError<M>> for DeployerError {
+    fn from(err: ContractError<M>) -> Self {
+        Self::Other {
+            source: Box::new(err),
+        }
+    }
+}
+
+/// A transaction which was mined, but reverted.
+///
+/// This is the cause of the error returned when waiting for such a transaction, so that a revert
+/// can be told apart from other failures.
+#[derive(Debug, Snafu)]
+#[snafu(display("execution reverted"), visibility(pub(super)))]
+pub(super) struct Reverted {
+    pub(super) tx_hash: H256,
+}
+
+/// Describe the revert data `data` of a call to `contract`.
+///
+/// Custom errors in the ABI of `contract` are decoded, as are `Error(string)` reasons. Anything else
+/// is shown as hex.
+pub fn decode_revert(contract: Contract, data: &Bytes) -> String {
+    if data.is_empty() {
+        return "no revert data".into();
+    }
+    if data.len() >= 4 {
+        let (selector, args) = data.split_at(4);
+        for error in contract_abis(contract)
+            .into_iter()
+            .flat_map(|abi| abi.errors())
+        {
+            if error.signature()[..4] != *selector {
+                continue;
+            }
+            if let Ok(tokens) = error.decode(args) {
+                let args = tokens
+                    .iter()
+                    .map(Token::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                return format!("{}({args})", error.name);
+            }
+        }
+        if *selector == id("Error(string)") {
+            if let Ok(tokens) = abi::decode(&[ParamType::String], args) {
+                if let [Token::String(reason)] = &tokens[..] {
+                    return reason.clone();
+                }
+            }
+        }
+    }
+    data.to_string()
+}
+
+/// The ABIs whose custom errors `contract` may revert with.
+///
+/// A proxy reverts with the errors of its implementation, since it is initialized by a delegatecall.
+/// The light client is decoded with the ABI of the mock, which has all the errors of the production
+/// contract.
+fn contract_abis(contract: Contract) -> Vec<&'static Abi> {
+    match contract {
+        Contract::HotShot => vec![&*HOTSHOT_ABI],
+        Contract::PlonkVerifier => vec![&*PLONKVERIFIER_ABI],
+        Contract::StateUpdateVK => vec![&*LIGHTCLIENTSTATEUPDATEVK_ABI],
+        Contract::LightClient => vec![&*LIGHTCLIENTMOCK_ABI],
+        Contract::LightClientProxy => vec![&*LIGHTCLIENTMOCK_ABI, &*ERC1967PROXY_ABI],
+        Contract::FeeContract => vec![&*FEECONTRACT_ABI],
+        Contract::FeeContractProxy => vec![&*FEECONTRACT_ABI, &*ERC1967PROXY_ABI],
+        Contract::StakeTable => vec![&*STAKETABLE_ABI],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_decode_revert() {
+        assert_eq!(
+            decode_revert(Contract::LightClient, &Bytes::default()),
+            "no revert data"
+        );
+
+        // A custom error of the contract.
+        let data = Bytes::from(id("InvalidProof()").to_vec());
+        assert_eq!(
+            decode_revert(Contract::LightClient, &data),
+            "InvalidProof()"
+        );
+        assert_eq!(
+            decode_revert(Contract::LightClientProxy, &data),
+            "InvalidProof()"
+        );
+        // The same error is not known to an unrelated contract.
+        assert_eq!(decode_revert(Contract::HotShot, &data), data.to_string());
+
+        // A revert reason.
+        let mut data = id("Error(string)").to_vec();
+        data.extend(abi::encode(&[Token::String("nope".into())]));
+        assert_eq!(decode_revert(Contract::HotShot, &data.into()), "nope");
+    }
+
+    #[test]
+    fn test_downcast_deployer_error() {
+        let err = DeployerError::Revert {
+            contract: Contract::LightClient,
+            data: id("InvalidProof()").to_vec().into(),
+            tx_hash: None,
+        };
+        assert_eq!(
+            err.to_string(),
+            "LightClient deployment reverted: InvalidProof()"
+        );
+
+        // A typed error survives a round trip through anyhow, even with added context.
+        let err = anyhow::Error::from(err).context("while deploying the light client");
+        assert!(matches!(
+            DeployerError::from(err),
+            DeployerError::Revert {
+                contract: Contract::LightClient,
+                ..
+            }
+        ));
+
+        // Anything else is just wrapped.
+        let err = DeployerError::from(anyhow!("bad config"));
+        assert!(matches!(err, DeployerError::Other { .. }));
+        assert_eq!(err.to_string(), "bad config");
+    }
+}
@@ -0,0 +1,130 @@
+//! Links to deployed contracts and transactions on a block explorer.
+
+use ethers::types::{Address, H256};
+use url::Url;
+
+/// Block explorers for well-known chains, by chain ID.
+const KNOWN_EXPLORERS: &[(u64, &str)] = &[
+    (1, "https://etherscan.io"),
+    (5, "https://goerli.etherscan.io"),
+    (10, "https://optimistic.etherscan.io"),
+    (8453, "https://basescan.org"),
+    (17000, "https://holesky.etherscan.io"),
+    (42161, "https://arbiscan.io"),
+    (84532, "https://sepolia.basescan.org"),
+    (421614, "https://sepolia.arbiscan.io"),
+    (11155111, "https://sepolia.etherscan.io"),
+];
+
+/// An Etherscan-style block explorer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Explorer {
+    base: Url,
+}
+
+impl Explorer {
+    pub fn new(mut base: Url) -> Self {
+        // Make sure relative paths are joined onto the base path, rather than replacing its last
+        // segment.
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        Self { base }
+    }
+
+    /// The built-in explorer for `chain_id`, if there is one.
+    pub fn for_chain(chain_id: u64) -> Option<Self> {
+        let (_, url) = KNOWN_EXPLORERS.iter().find(|(id, _)| *id == chain_id)?;
+        Some(Self::new(url.parse().unwrap()))
+    }
+
+    /// Pick an explorer: `url` if given, otherwise the built-in explorer for `chain_id`, if any.
+    pub fn resolve(url: Option<Url>, chain_id: u64) -> Option<Self> {
+        url.map(Self::new).or_else(|| Self::for_chain(chain_id))
+    }
+
+    pub fn address(&self, address: Address) -> Url {
+        self.link("address", &format!("{address:#x}"))
+    }
+
+    pub fn tx(&self, hash: H256) -> Url {
+        self.link("tx", &format!("{hash:#x}"))
+    }
+
+    pub fn token(&self, address: Address) -> Url {
+        self.link("token", &format!("{address:#x}"))
+    }
+
+    fn link(&self, kind: &str, id: &str) -> Url {
+        self.base
+            .join(&format!("{kind}/{id}"))
+            .expect("hex identifiers are valid URL paths")
+    }
+}
+
+/// Format `address`, with a link to it if there is an explorer.
+pub fn fmt_address(explorer: Option<&Explorer>, address: Address) -> String {
+    match explorer {
+        Some(explorer) => format!("{address:#x} ({})", explorer.address(address)),
+        None => format!("{address:#x}"),
+    }
+}
+
+/// Format the transaction `hash`, with a link to it if there is an explorer.
+pub fn fmt_tx(explorer: Option<&Explorer>, hash: H256) -> String {
+    match explorer {
+        Some(explorer) => format!("{hash:#x} ({})", explorer.tx(hash)),
+        None => format!("{hash:#x}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_links() {
+        let address = Address::repeat_byte(0xab);
+        let hash = H256::repeat_byte(0x01);
+        let explorer = Explorer::for_chain(11155111).unwrap();
+        assert_eq!(
+            explorer.address(address).as_str(),
+            format!("https://sepolia.etherscan.io/address/{address:#x}")
+        );
+        assert_eq!(
+            explorer.tx(hash).as_str(),
+            format!("https://sepolia.etherscan.io/tx/{hash:#x}")
+        );
+        assert_eq!(
+            explorer.token(address).as_str(),
+            format!("https://sepolia.etherscan.io/token/{address:#x}")
+        );
+    }
+
+    #[test]
+    fn test_custom_explorer() {
+        let address = Address::repeat_byte(0xab);
+        // The base path is preserved, with or without a trailing slash.
+        for base in [
+            "http://localhost:4000/explorer",
+            "http://localhost:4000/explorer/",
+        ] {
+            let explorer = Explorer::resolve(Some(base.parse().unwrap()), 1).unwrap();
+            assert_eq!(
+                explorer.address(address).as_str(),
+                format!("http://localhost:4000/explorer/address/{address:#x}")
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_chain() {
+        let address = Address::repeat_byte(0xab);
+        assert_eq!(Explorer::resolve(None, 31337), None);
+        assert_eq!(fmt_address(None, address), format!("{address:#x}"));
+        assert_eq!(
+            fmt_address(Explorer::for_chain(1).as_ref(), address),
+            format!("{address:#x} (https://etherscan.io/address/{address:#x})")
+        );
+    }
+}
@@ -0,0 +1,500 @@
+//! Fee policy for transactions sent by the deployer.
+//!
+//! By default, every transaction the deployer sends is an EIP-1559 transaction, and both the fee
+//! cap and the priority fee are estimated by the provider when each transaction is sent, so they
+//! track the market through retries. A [`GasConfig`], set with [`Contracts::with_gas_config`],
+//! pins either fee instead, or switches to legacy transactions, for every transaction in the
+//! deployment. On chains whose blocks have no base fee, and so do not support EIP-1559, legacy
+//! transactions are sent unless EIP-1559 fees were pinned. The gas limit is estimated by the
+//! provider, and can be raised by a fixed percentage for chains where the estimate is unreliable.
+//!
+//! A transaction whose fee cap (or legacy gas price) is below the current base fee can never be
+//! included, so rather than sending one, we wait for the base fee to drop, up to
+//! [`GasConfig::fee_wait_timeout`]. A transaction which was sent, but is stuck because the base fee
+//! rose afterwards, can be [replaced](super::replace) with a higher fee after
+//! [`GasConfig::tx_timeout`].
+
+use super::{clear_gas, Contracts, SendError};
+use anyhow::anyhow;
+use async_std::task::sleep;
+use ethers::{prelude::*, types::transaction::eip2718::TypedTransaction, utils::format_units};
+use std::time::{Duration, Instant};
+
+/// The fee parameters to send transactions with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GasConfig {
+    /// The EIP-1559 fee cap, estimated by the provider if not set.
+    pub max_fee_per_gas: Option<U256>,
+    /// The EIP-1559 priority fee, estimated by the provider if not set.
+    pub max_priority_fee_per_gas: Option<U256>,
+    /// Send legacy transactions with this gas price, instead of EIP-1559 transactions.
+    ///
+    /// This takes precedence over the EIP-1559 fees.
+    pub legacy_gas_price: Option<U256>,
+    /// Send legacy transactions, with the gas price estimated by the provider unless
+    /// [`legacy_gas_price`](Self::legacy_gas_price) is set.
+    pub legacy: bool,
+    /// The gas limit of each transaction, as a percentage of the estimated gas.
+    pub gas_limit_percent: u64,
+    /// How long to wait for the base fee to drop below the fee cap before giving up.
+    pub fee_wait_timeout: Duration,
+    /// How long to wait for a deployment transaction to be mined before replacing it with a higher
+    /// fee, or never to replace it if not set.
+    pub tx_timeout: Option<Duration>,
+    /// The percentage by which each replacement raises the fees of the transaction it replaces.
+    ///
+    /// The raised fees never exceed the fee cap, if there is one.
+    pub fee_bump_percent: u64,
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        Self {
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            legacy_gas_price: None,
+            legacy: false,
+            gas_limit_percent: 100,
+            fee_wait_timeout: Duration::from_secs(600),
+            tx_timeout: None,
+            // Nodes reject replacements which raise the fees by less than 10%.
+            fee_bump_percent: 15,
+        }
+    }
+}
+
+impl GasConfig {
+    /// Prepare `tx` to be sent according to this config.
+    ///
+    /// `tx` is converted to a legacy or EIP-1559 transaction as configured, its gas limit is
+    /// cleared, so that it is estimated afresh, and its fees are set to the configured ones, or
+    /// cleared to be estimated by the provider.
+    pub(super) fn apply(&self, tx: &mut TypedTransaction) {
+        clear_gas(tx);
+        if self.legacy || self.legacy_gas_price.is_some() {
+            let mut req = match tx {
+                TypedTransaction::Legacy(tx) => tx.clone(),
+                TypedTransaction::Eip2930(tx) => tx.tx.clone(),
+                TypedTransaction::Eip1559(tx) => TransactionRequest {
+                    from: tx.from,
+                    to: tx.to.clone(),
+                    value: tx.value,
+                    data: tx.data.clone(),
+                    nonce: tx.nonce,
+                    chain_id: tx.chain_id,
+                    ..Default::default()
+                },
+            };
+            req.gas_price = self.legacy_gas_price;
+            *tx = req.into();
+            return;
+        }
+
+        let mut req = match tx {
+            TypedTransaction::Eip1559(tx) => tx.clone(),
+            TypedTransaction::Legacy(tx) => Eip1559TransactionRequest {
+                from: tx.from,
+                to: tx.to.clone(),
+                value: tx.value,
+                data: tx.data.clone(),
+                nonce: tx.nonce,
+                chain_id: tx.chain_id,
+                ..Default::default()
+            },
+            TypedTransaction::Eip2930(tx) => Eip1559TransactionRequest {
+                from: tx.tx.from,
+                to: tx.tx.to.clone(),
+                value: tx.tx.value,
+                data: tx.tx.data.clone(),
+                nonce: tx.tx.nonce,
+                chain_id: tx.tx.chain_id,
+                access_list: tx.access_list.clone(),
+                ..Default::default()
+            },
+        };
+        req.max_fee_per_gas = self.max_fee_per_gas;
+        req.max_priority_fee_per_gas = self.max_priority_fee_per_gas;
+        *tx = req.into();
+    }
+
+    /// This config, for a chain which does or does not support EIP-1559.
+    ///
+    /// Without EIP-1559 support, transactions are sent as legacy transactions, unless EIP-1559 fees
+    /// were explicitly configured.
+    pub fn for_eip1559_support(self, supported: bool) -> Self {
+        let pinned = self.max_fee_per_gas.is_some() || self.max_priority_fee_per_gas.is_some();
+        Self {
+            legacy: self.legacy || (!supported && !pinned),
+            ..self
+        }
+    }
+
+    /// This config, for the chain `client` is connected to.
+    ///
+    /// Support for EIP-1559 is detected from the base fee of the latest block. This needs no RPC if
+    /// the transaction type is already determined by the config. If the latest block cannot be
+    /// fetched, the config is returned as is.
+    pub(super) async fn for_chain<M: Middleware + 'static>(self, client: &M) -> Self {
+        if self.legacy
+            || self.legacy_gas_price.is_some()
+            || self.max_fee_per_gas.is_some()
+            || self.max_priority_fee_per_gas.is_some()
+        {
+            return self;
+        }
+        match client.get_block(BlockNumber::Latest).await {
+            Ok(block) => {
+                let supported = block.is_some_and(|block| block.base_fee_per_gas.is_some());
+                if !supported {
+                    tracing::info!("L1 does not support EIP-1559, sending legacy transactions");
+                }
+                self.for_eip1559_support(supported)
+            }
+            Err(err) => {
+                tracing::warn!("error checking for EIP-1559 support: {err}");
+                self
+            }
+        }
+    }
+
+    /// Set the gas limit of `tx` to [`gas_limit_percent`](Self::gas_limit_percent) of its
+    /// estimated gas.
+    ///
+    /// If the limit is not raised, or the gas cannot be estimated, the gas limit is left to the
+    /// provider, which reports any error when the transaction is sent.
+    pub(super) async fn scale_gas_limit<M: Middleware + 'static>(
+        &self,
+        client: &M,
+        tx: &mut TypedTransaction,
+    ) {
+        if self.gas_limit_percent == 100 {
+            return;
+        }
+        if tx.from().is_none() {
+            if let Some(sender) = client.default_sender() {
+                tx.set_from(sender);
+            }
+        }
+        match client.estimate_gas(tx, None).await {
+            Ok(gas) => tx.set_gas(gas * self.gas_limit_percent / 100),
+            Err(err) => tracing::debug!("error estimating gas, leaving it to the provider: {err}"),
+        }
+    }
+
+    /// The most a transaction may pay per gas, if configured.
+    pub(super) fn cap(&self) -> Option<U256> {
+        self.legacy_gas_price.or(self.max_fee_per_gas)
+    }
+
+    /// Wait until the base fee is within the configured cap, polling every `interval`.
+    ///
+    /// Returns immediately if there is no cap, or the chain has no base fee. Failing to fetch the
+    /// base fee is a transient error; the base fee staying too high for longer than
+    /// [`fee_wait_timeout`](Self::fee_wait_timeout) is not, since the cap itself is too low.
+    pub(super) async fn wait_for_base_fee<M: Middleware + 'static>(
+        &self,
+        client: &M,
+        interval: Duration,
+    ) -> Result<(), SendError> {
+        let Some(cap) = self.cap() else {
+            return Ok(());
+        };
+        let start = Instant::now();
+        loop {
+            let block = client.get_block(BlockNumber::Latest).await.map_err(|err| {
+                SendError::Transient(anyhow::Error::new(err).context("error fetching base fee"))
+            })?;
+            let Some(base_fee) = block.and_then(|block| block.base_fee_per_gas) else {
+                return Ok(());
+            };
+            if base_fee <= cap {
+                return Ok(());
+            }
+            if start.elapsed() >= self.fee_wait_timeout {
+                return Err(SendError::Fatal(anyhow!(
+                    "base fee {} gwei still exceeds the maximum fee of {} gwei after {:?}",
+                    fmt_gwei(base_fee),
+                    fmt_gwei(cap),
+                    self.fee_wait_timeout
+                )));
+            }
+            tracing::warn!(
+                "base fee {} gwei exceeds the maximum fee of {} gwei, waiting for it to drop",
+                fmt_gwei(base_fee),
+                fmt_gwei(cap)
+            );
+            sleep(interval).await;
+        }
+    }
+}
+
+pub(super) fn fmt_gwei(wei: U256) -> String {
+    format_units(wei, "gwei").unwrap_or_else(|_| format!("{wei} wei"))
+}
+
+impl Contracts {
+    /// Send every transaction according to `gas`.
+    pub fn with_gas_config(mut self, gas: GasConfig) -> Self {
+        self.gas_config = gas;
+        self
+    }
+
+    /// Send every transaction with a fee cap of `max_fee_per_gas`, rather than an estimate.
+    pub fn with_max_fee_per_gas(mut self, max_fee_per_gas: U256) -> Self {
+        self.gas_config.max_fee_per_gas = Some(max_fee_per_gas);
+        self
+    }
+
+    /// Send every transaction with a priority fee of `max_priority_fee_per_gas`, rather than an
+    /// estimate.
+    pub fn with_max_priority_fee_per_gas(mut self, max_priority_fee_per_gas: U256) -> Self {
+        self.gas_config.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+        self
+    }
+
+    pub fn gas_config(&self) -> GasConfig {
+        self.gas_config
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{test_helpers::TEST_MNEMONIC, Contract},
+        init_signer, AnvilOptions,
+    };
+    use async_std::sync::Arc;
+    use contract_bindings::hot_shot::HotShot;
+    use ethers::types::transaction::eip2930::{AccessList, Eip2930TransactionRequest};
+
+    #[test]
+    fn test_apply_fee_policy() {
+        let req = TransactionRequest::new()
+            .from(Address::repeat_byte(1))
+            .data(vec![1, 2, 3])
+            .nonce(5)
+            .gas(100)
+            .gas_price(10);
+        let legacy: TypedTransaction = req.clone().into();
+
+        // By default, fees are left to the provider.
+        let mut tx = legacy.clone();
+        GasConfig::default().apply(&mut tx);
+        let TypedTransaction::Eip1559(req) = &tx else {
+            panic!("not converted to EIP-1559: {tx:?}");
+        };
+        assert_eq!(req.max_fee_per_gas, None);
+        assert_eq!(req.max_priority_fee_per_gas, None);
+        assert_eq!(tx.gas(), None);
+        // Everything else is preserved.
+        assert_eq!(tx.from(), legacy.from());
+        assert_eq!(tx.data(), legacy.data());
+        assert_eq!(tx.nonce(), legacy.nonce());
+
+        let config = GasConfig {
+            max_fee_per_gas: Some(20.into()),
+            max_priority_fee_per_gas: Some(2.into()),
+            ..Default::default()
+        };
+        let mut tx: TypedTransaction =
+            Eip2930TransactionRequest::new(req, AccessList::default()).into();
+        config.apply(&mut tx);
+        let TypedTransaction::Eip1559(req) = &tx else {
+            panic!("not converted to EIP-1559: {tx:?}");
+        };
+        assert_eq!(req.max_fee_per_gas, Some(20.into()));
+        assert_eq!(req.max_priority_fee_per_gas, Some(2.into()));
+
+        // A legacy gas price turns any transaction back into a legacy one.
+        let config = GasConfig {
+            legacy_gas_price: Some(30.into()),
+            ..config
+        };
+        config.apply(&mut tx);
+        let TypedTransaction::Legacy(req) = &tx else {
+            panic!("not converted to legacy: {tx:?}");
+        };
+        assert_eq!(req.gas_price, Some(30.into()));
+        assert_eq!(tx.from(), legacy.from());
+        assert_eq!(tx.data(), legacy.data());
+        assert_eq!(tx.nonce(), legacy.nonce());
+
+        // So does the legacy flag, leaving the gas price to the provider.
+        let config = GasConfig {
+            legacy: true,
+            ..Default::default()
+        };
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new().data(vec![1]).into();
+        config.apply(&mut tx);
+        let TypedTransaction::Legacy(req) = &tx else {
+            panic!("not converted to legacy: {tx:?}");
+        };
+        assert_eq!(req.gas_price, None);
+    }
+
+    #[test]
+    fn test_legacy_fallback() {
+        // Without EIP-1559 support, legacy transactions are sent...
+        let config = GasConfig::default();
+        assert_eq!(config.for_eip1559_support(true), config);
+        assert!(config.for_eip1559_support(false).legacy);
+
+        // ...unless EIP-1559 fees were explicitly configured.
+        let config = GasConfig {
+            max_priority_fee_per_gas: Some(1.into()),
+            ..Default::default()
+        };
+        assert!(!config.for_eip1559_support(false).legacy);
+    }
+
+    #[async_std::test]
+    async fn test_gas_limit_multiplier() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        // Deploy the same contract with the estimated gas limit, and with 150% of it.
+        let mut gas_limits = vec![];
+        for gas_limit_percent in [100, 150] {
+            let mut contracts = Contracts::default().with_gas_config(GasConfig {
+                gas_limit_percent,
+                ..Default::default()
+            });
+            contracts
+                .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+                .await
+                .unwrap();
+            let tx = l1
+                .get_transaction(
+                    contracts
+                        .receipt(Contract::HotShot)
+                        .unwrap()
+                        .transaction_hash,
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            gas_limits.push(tx.gas);
+        }
+        assert_eq!(gas_limits[1], gas_limits[0] * 150 / 100);
+    }
+
+    #[async_std::test]
+    async fn test_deploy_legacy_estimated_gas_price() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        let mut contracts = Contracts::default().with_gas_config(GasConfig {
+            legacy: true,
+            ..Default::default()
+        });
+        contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+
+        let block = l1
+            .get_block_with_txs(BlockNumber::Latest)
+            .await
+            .unwrap()
+            .unwrap();
+        let tx = &block.transactions[0];
+        assert_eq!(tx.transaction_type, Some(0.into()));
+        assert!(tx.gas_price.unwrap() >= block.base_fee_per_gas.unwrap());
+    }
+
+    #[async_std::test]
+    async fn test_deploy_legacy() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let gas_price = U256::from(50_000_000_000u64);
+
+        let mut contracts = Contracts::default().with_gas_config(GasConfig {
+            legacy_gas_price: Some(gas_price),
+            ..Default::default()
+        });
+        contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+
+        let block = l1
+            .get_block_with_txs(BlockNumber::Latest)
+            .await
+            .unwrap()
+            .unwrap();
+        let tx = &block.transactions[0];
+        assert_eq!(tx.transaction_type, Some(0.into()));
+        assert_eq!(tx.gas_price, Some(gas_price));
+    }
+
+    #[async_std::test]
+    async fn test_wait_for_base_fee() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        // A cap far above the base fee does not wait.
+        GasConfig {
+            max_fee_per_gas: Some(U256::exp10(20)),
+            ..Default::default()
+        }
+        .wait_for_base_fee(&*l1, Duration::from_millis(10))
+        .await
+        .unwrap_or_else(|_| panic!("waited for base fee"));
+
+        // A cap below the base fee gives up after the timeout.
+        let config = GasConfig {
+            max_fee_per_gas: Some(1.into()),
+            fee_wait_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let Err(SendError::Fatal(err)) = config
+            .wait_for_base_fee(&*l1, Duration::from_millis(10))
+            .await
+        else {
+            panic!("expected fatal error");
+        };
+        assert!(
+            err.to_string().contains("exceeds the maximum fee"),
+            "{err:#}"
+        );
+
+        // Deployments are not sent at all.
+        let nonce = l1.get_transaction_count(l1.address(), None).await.unwrap();
+        let mut contracts = Contracts::default().with_gas_config(config);
+        contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            l1.get_transaction_count(l1.address(), None).await.unwrap(),
+            nonce
+        );
+    }
+
+    #[async_std::test]
+    async fn test_deploy_with_fee_policy() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let max_fee = U256::from(50_000_000_000u64);
+        let priority_fee = U256::from(2_000_000_000u64);
+
+        let mut contracts = Contracts::default()
+            .with_max_fee_per_gas(max_fee)
+            .with_max_priority_fee_per_gas(priority_fee);
+        contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+
+        let block = l1
+            .get_block_with_txs(BlockNumber::Latest)
+            .await
+            .unwrap()
+            .unwrap();
+        let tx = &block.transactions[0];
+        assert_eq!(tx.transaction_type, Some(2.into()));
+        assert_eq!(tx.max_fee_per_gas, Some(max_fee));
+        assert_eq!(tx.max_priority_fee_per_gas, Some(priority_fee));
+    }
+}
@@ -0,0 +1,216 @@
+//! Keeping production, mock and dev deployments apart in a shared cache.
+//!
+//! Integration tests often deploy the mock light client and later the production one using the same
+//! [`Contracts`] cache. Without care, the second deployment silently reuses contracts from the
+//! first, like a mock verification key library or a light client proxy pointing at a mock
+//! implementation. To prevent this, every contract deployed through [`Contracts::deploy_fn`] is
+//! tagged with the [`Flavor`] of the deployment which created it, and reusing it in a deployment of
+//! a different flavor is an error, unless explicitly allowed for that contract with
+//! [`Contracts::allow_cross_flavor`]. Contracts given as predeployed have no flavor, and can be
+//! reused by any deployment.
+
+use super::{Contract, Contracts};
+use anyhow::bail;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// The kind of deployment a contract was created by.
+#[derive(Clone, Copy, Debug, Default, Display, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Flavor {
+    /// A deployment of the production contracts.
+    #[default]
+    #[display(fmt = "production")]
+    Production,
+    /// A deployment of mock contracts, which skip proof verification.
+    #[display(fmt = "mock")]
+    Mock,
+    /// A deployment for local development.
+    #[display(fmt = "dev")]
+    Dev,
+}
+
+impl Contracts {
+    /// Set the flavor of the deployment, for contracts deployed from now on.
+    pub fn with_flavor(mut self, flavor: Flavor) -> Self {
+        self.flavor = flavor;
+        self
+    }
+
+    /// Change the flavor of the deployment, for contracts deployed from now on.
+    ///
+    /// This allows one cache to be used for deployments of different flavors in turn. Contracts
+    /// already in the cache keep the flavor they were deployed with.
+    pub fn set_flavor(&mut self, flavor: Flavor) {
+        self.flavor = flavor;
+    }
+
+    pub fn flavor(&self) -> Flavor {
+        self.flavor
+    }
+
+    /// The flavor of the deployment which created `name`, if it was deployed by this tool.
+    pub fn flavor_of(&self, name: Contract) -> Option<Flavor> {
+        self.flavors.get(&name).copied()
+    }
+
+    /// Allow `name` to be reused by deployments of a different flavor than the one which created
+    /// it.
+    ///
+    /// This is appropriate for contracts which are identical across flavors, like
+    /// `PlonkVerifier.sol`.
+    pub fn allow_cross_flavor(mut self, name: Contract) -> Self {
+        self.cross_flavor.insert(name);
+        self
+    }
+
+    /// Check that the cached `name` may be reused by the current deployment.
+    pub(super) fn check_flavor(&self, name: Contract) -> anyhow::Result<()> {
+        match self.flavor_of(name) {
+            Some(flavor) if flavor != self.flavor && !self.cross_flavor.contains(&name) => bail!(
+                "{name:?} was deployed by a {flavor} deployment and cannot be reused by a {} \
+                 deployment; allow cross-flavor reuse of {name:?} to reuse it anyway",
+                self.flavor
+            ),
+            Some(flavor) if flavor != self.flavor => {
+                tracing::info!(
+                    "reusing {name:?} from a {flavor} deployment in a {} deployment, as allowed",
+                    self.flavor
+                );
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::types::Address;
+    use futures::FutureExt;
+    use tempfile::TempDir;
+
+    async fn deploy(
+        contracts: &mut Contracts,
+        name: Contract,
+        byte: u8,
+    ) -> anyhow::Result<Address> {
+        contracts
+            .deploy_fn(name, |_| {
+                async move { Ok(Address::repeat_byte(byte)) }.boxed()
+            })
+            .await
+            .map_err(Into::into)
+    }
+
+    #[async_std::test]
+    async fn test_mock_then_production() {
+        let mut contracts = Contracts::default()
+            .with_flavor(Flavor::Mock)
+            .allow_cross_flavor(Contract::PlonkVerifier);
+        deploy(&mut contracts, Contract::PlonkVerifier, 1)
+            .await
+            .unwrap();
+        deploy(&mut contracts, Contract::StateUpdateVK, 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            contracts.flavor_of(Contract::StateUpdateVK),
+            Some(Flavor::Mock)
+        );
+
+        contracts.set_flavor(Flavor::Production);
+        // The shared library may be reused...
+        assert_eq!(
+            deploy(&mut contracts, Contract::PlonkVerifier, 3)
+                .await
+                .unwrap(),
+            Address::repeat_byte(1)
+        );
+        // ...but not the mock verification key.
+        let err = deploy(&mut contracts, Contract::StateUpdateVK, 4)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("mock deployment"), "{err}");
+        assert!(err.contains("production deployment"), "{err}");
+    }
+
+    #[async_std::test]
+    async fn test_production_then_mock() {
+        let mut contracts = Contracts::default();
+        deploy(&mut contracts, Contract::LightClient, 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            contracts.flavor_of(Contract::LightClient),
+            Some(Flavor::Production)
+        );
+
+        contracts.set_flavor(Flavor::Mock);
+        let err = deploy(&mut contracts, Contract::LightClient, 2)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("production deployment"), "{err}");
+        assert!(err.contains("mock deployment"), "{err}");
+
+        // Opting in for the contract allows it to be reused.
+        let mut contracts = contracts.allow_cross_flavor(Contract::LightClient);
+        assert_eq!(
+            deploy(&mut contracts, Contract::LightClient, 2)
+                .await
+                .unwrap(),
+            Address::repeat_byte(1)
+        );
+    }
+
+    #[async_std::test]
+    async fn test_flavor_survives_state_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state.json");
+        let mut contracts = Contracts::default()
+            .with_flavor(Flavor::Mock)
+            .with_state_file(&path, 1)
+            .unwrap();
+        deploy(&mut contracts, Contract::LightClient, 1)
+            .await
+            .unwrap();
+
+        // A resumed production run does not reuse the mock light client.
+        let mut contracts = Contracts::default().with_state_file(&path, 1).unwrap();
+        assert_eq!(
+            contracts.flavor_of(Contract::LightClient),
+            Some(Flavor::Mock)
+        );
+        let err = deploy(&mut contracts, Contract::LightClient, 2)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("mock deployment"), "{err}");
+
+        // A resumed mock run does.
+        let mut contracts = Contracts::default()
+            .with_flavor(Flavor::Mock)
+            .with_state_file(&path, 1)
+            .unwrap();
+        assert_eq!(
+            deploy(&mut contracts, Contract::LightClient, 2)
+                .await
+                .unwrap(),
+            Address::repeat_byte(1)
+        );
+    }
+
+    #[async_std::test]
+    async fn test_predeployed_has_no_flavor() {
+        let mut contracts = Contracts::default().with_flavor(Flavor::Dev);
+        contracts.insert(Contract::HotShot, Address::repeat_byte(1));
+        assert_eq!(contracts.flavor_of(Contract::HotShot), None);
+        assert_eq!(
+            deploy(&mut contracts, Contract::HotShot, 2).await.unwrap(),
+            Address::repeat_byte(1)
+        );
+    }
+}
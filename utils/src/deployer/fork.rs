@@ -0,0 +1,305 @@
+//! Pre-flight simulation of a production deployment against a fork of the target chain.
+//!
+//! A deployment which reverts halfway through on mainnet costs real gas, and leaves contracts
+//! behind which have to be cleaned up or resumed. [`simulate_production_stack`] runs
+//! [`deploy_production_stack`] unchanged against a throwaway local fork of the target chain
+//! instead, sending from the same deployer account as the real deployment would, so that the proxy
+//! is initialized with the real genesis against the real state of the chain. Nothing is sent to the
+//! target chain itself.
+//!
+//! The fork is served by a local `anvil` node, which fetches the state it needs from the target
+//! chain as the simulation goes. The deployer account is impersonated, so no key is needed, and
+//! funded, so the simulation does not depend on its real balance. A [`Fork`] can also be used
+//! directly, to rehearse any other deployment.
+//!
+//! Once deployed, the light client is sanity checked on the fork with [`check_genesis_state`], so
+//! that a proxy which was initialized wrongly is caught as well as one which failed to deploy.
+
+use super::{
+    deploy_production_stack, dry_run::DeployMode, error::DeployerError, prover::ProverConfig,
+    Contract, Contracts,
+};
+use crate::{Anvil, AnvilOptions};
+use anyhow::{ensure, Context};
+use async_std::sync::Arc;
+use contract_bindings::light_client::LightClient;
+use ethers::prelude::*;
+use hotshot_contract_adapter::light_client::ParsedLightClientState;
+use url::Url;
+
+/// Balance the deployer account is given on the fork: 1000 ETH.
+const FORK_BALANCE: u128 = 1_000_000_000_000_000_000_000;
+
+/// A fork of the target chain to simulate a deployment against.
+#[derive(Clone, Debug)]
+pub struct ForkConfig {
+    /// RPC of the target chain.
+    pub rpc_url: Url,
+    /// Block to fork at, or the latest block if not given.
+    pub block_number: Option<u64>,
+    /// Account the real deployment will be sent from.
+    pub deployer: Address,
+}
+
+/// A local fork of a target chain, on which the deployer account is impersonated and funded.
+///
+/// The fork runs as long as this is alive.
+pub struct Fork {
+    /// The block of the target chain the fork was taken at.
+    pub block: u64,
+    /// A provider for the fork, which sends transactions from the deployer account.
+    pub l1: Arc<Provider<Http>>,
+    _anvil: Anvil,
+}
+
+impl Fork {
+    /// Fork the chain in `config`.
+    pub async fn spawn(config: &ForkConfig) -> anyhow::Result<Self> {
+        let target = Provider::<Http>::try_from(config.rpc_url.as_str())
+            .with_context(|| format!("invalid RPC URL {}", config.rpc_url))?;
+        let block = match config.block_number {
+            Some(block) => block,
+            None => target
+                .get_block_number()
+                .await
+                .with_context(|| format!("error connecting to {}", config.rpc_url))?
+                .as_u64(),
+        };
+        tracing::info!("forking {} at block {block}", config.rpc_url);
+        let anvil = AnvilOptions::default()
+            .fork(config.rpc_url.clone(), Some(block))
+            .spawn()
+            .await;
+        let l1 = Arc::new(impersonate(&anvil, config.deployer).await?);
+        Ok(Self {
+            block,
+            l1,
+            _anvil: anvil,
+        })
+    }
+
+    /// A copy of `contracts` to deploy on the fork.
+    ///
+    /// Predeployed contracts are reused exactly like in the real deployment, but nothing deployed
+    /// on the fork is written to the state file or unsigned transaction output of `contracts`, and
+    /// transactions are sent even if `contracts` is in dry-run mode.
+    pub fn contracts(&self, contracts: &Contracts) -> Contracts {
+        let mut contracts = contracts.clone();
+        contracts.state_file = None;
+        contracts.unsigned = None;
+        contracts.nonce = None;
+        contracts.mode = DeployMode::Execute;
+        contracts
+    }
+}
+
+/// Check that the light client behind `proxy` answers `getGenesisState()` with `genesis`.
+pub async fn check_genesis_state<M: Middleware + 'static>(
+    l1: Arc<M>,
+    proxy: Address,
+    genesis: &ParsedLightClientState,
+) -> anyhow::Result<()> {
+    let actual: ParsedLightClientState = LightClient::new(proxy, l1)
+        .get_genesis_state()
+        .call()
+        .await
+        .with_context(|| format!("error reading genesis state of light client {proxy:#x}"))?
+        .into();
+    ensure!(
+        actual == *genesis,
+        "light client {proxy:#x} has genesis state {actual:?}, expected {genesis:?}"
+    );
+    Ok(())
+}
+
+/// The outcome of a simulated deployment.
+#[derive(Debug)]
+pub struct ForkSimulation {
+    /// The block of the target chain the simulation forked from.
+    pub fork_block: u64,
+    /// The contracts at the end of the simulation, including the contracts it deployed on the
+    /// fork.
+    ///
+    /// Contracts deployed with CREATE are at the addresses the real deployment will use only if
+    /// the deployer sends no other transactions in the meantime.
+    pub contracts: Contracts,
+    /// The light client proxy, if the deployment succeeded.
+    pub proxy: Option<Address>,
+    /// Why the deployment failed, if it did.
+    ///
+    /// For a reverted deployment this is a [`DeployerError::Revert`], with the decoded revert
+    /// reason. A deployment whose light client does not report the requested genesis state
+    /// afterwards fails as well.
+    pub error: Option<DeployerError>,
+}
+
+impl ForkSimulation {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// The addresses of the contracts at the end of the simulation, sorted like the .env file.
+    pub fn addresses(&self) -> Vec<(Contract, Address)> {
+        self.contracts.sorted()
+    }
+}
+
+/// Run [`deploy_production_stack`] against a fork of the chain in `fork`.
+///
+/// The deployment starts from a [copy](Fork::contracts) of `contracts`, which itself is left
+/// untouched. Once deployed, the [genesis state](check_genesis_state) of the light client is
+/// checked.
+///
+/// An error is returned only if the fork could not be set up. A failed deployment is reported in
+/// [`ForkSimulation::error`].
+pub async fn simulate_production_stack(
+    fork: &ForkConfig,
+    contracts: &Contracts,
+    genesis: ParsedLightClientState,
+    owner: Address,
+    prover: ProverConfig,
+    transfer_to: Option<Address>,
+) -> anyhow::Result<ForkSimulation> {
+    let fork = Fork::spawn(fork).await?;
+    tracing::info!("simulating deployment on the fork");
+    let mut contracts = fork.contracts(contracts);
+    let res = async {
+        let proxy = deploy_production_stack(
+            fork.l1.clone(),
+            &mut contracts,
+            genesis.clone(),
+            owner,
+            prover,
+            transfer_to,
+        )
+        .await?;
+        check_genesis_state(fork.l1.clone(), proxy, &genesis).await?;
+        Ok::<_, anyhow::Error>(proxy)
+    }
+    .await;
+    let (proxy, error) = match res {
+        Ok(proxy) => (Some(proxy), None),
+        Err(err) => {
+            tracing::warn!("simulated deployment failed: {err:#}");
+            (None, Some(err.into()))
+        }
+    };
+    Ok(ForkSimulation {
+        fork_block: fork.block,
+        contracts,
+        proxy,
+        error,
+    })
+}
+
+/// A provider for `anvil` which sends transactions from `account`, without its key.
+async fn impersonate(anvil: &Anvil, account: Address) -> anyhow::Result<Provider<Http>> {
+    let provider = anvil.provider().with_sender(account);
+    provider
+        .request::<_, ()>("anvil_impersonateAccount", [account])
+        .await
+        .context("error impersonating deployer")?;
+    provider
+        .request::<_, ()>("anvil_setBalance", (account, U256::from(FORK_BALANCE)))
+        .await
+        .context("error funding deployer")?;
+    Ok(provider)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deployer::deploy_light_client_and_initialize_proxy;
+
+    #[async_std::test]
+    async fn test_simulate_production_stack() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let target = anvil.provider();
+        // An account which is not one of the funded dev accounts, and whose key we do not have.
+        let deployer = Address::repeat_byte(0x42);
+        let fork = ForkConfig {
+            rpc_url: anvil.url(),
+            block_number: None,
+            deployer,
+        };
+        let block = target.get_block_number().await.unwrap().as_u64();
+
+        let contracts = Contracts::default();
+        let sim = simulate_production_stack(
+            &fork,
+            &contracts,
+            ParsedLightClientState::dummy_genesis(),
+            deployer,
+            ProverConfig::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(sim.succeeded(), "{:?}", sim.error);
+        assert_eq!(sim.fork_block, block);
+        let proxy = sim.proxy.unwrap();
+        assert_eq!(
+            sim.contracts.address(Contract::LightClientProxy),
+            Some(proxy)
+        );
+        assert!(sim
+            .addresses()
+            .contains(&(Contract::LightClientProxy, proxy)));
+
+        // Nothing was deployed on the target chain, or recorded in the original cache.
+        assert!(target.get_code(proxy, None).await.unwrap().is_empty());
+        assert_eq!(
+            target.get_transaction_count(deployer, None).await.unwrap(),
+            0.into()
+        );
+        assert_eq!(contracts.address(Contract::LightClientProxy), None);
+
+        // The light client was checked to have the requested genesis state, and the check fails
+        // for any other.
+        let chain = Fork::spawn(&fork).await.unwrap();
+        let mut forked = chain.contracts(&contracts);
+        let proxy = deploy_light_client_and_initialize_proxy(
+            chain.l1.clone(),
+            &mut forked,
+            ParsedLightClientState::dummy_genesis(),
+            deployer,
+        )
+        .await
+        .unwrap();
+        check_genesis_state(
+            chain.l1.clone(),
+            proxy,
+            &ParsedLightClientState::dummy_genesis(),
+        )
+        .await
+        .unwrap();
+        let other = ParsedLightClientState {
+            threshold: 2.into(),
+            ..ParsedLightClientState::dummy_genesis()
+        };
+        let err = check_genesis_state(chain.l1.clone(), proxy, &other)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("genesis state"), "{err}");
+
+        // A deployment which reverts is reported with its revert reason: the light client cannot
+        // be owned by the zero address.
+        let sim = simulate_production_stack(
+            &fork,
+            &contracts,
+            ParsedLightClientState::dummy_genesis(),
+            Address::zero(),
+            ProverConfig::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(sim.proxy, None);
+        let err = sim.error.unwrap();
+        let DeployerError::Revert { contract, .. } = &err else {
+            panic!("{err:?}");
+        };
+        assert_eq!(*contract, Contract::LightClientProxy);
+        assert!(err.to_string().contains("OwnableInvalidOwner"), "{err}");
+    }
+}
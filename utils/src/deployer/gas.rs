@@ -0,0 +1,130 @@
+//! Accounting for the gas spent on a deployment.
+//!
+//! Every deployment transaction mined in this run is recorded against the contract it deployed,
+//! with the gas it used and what that cost at its effective gas price. Contracts which were
+//! predeployed, or resumed from a state file, cost nothing in this run and do not appear.
+
+use super::{Contract, Contracts};
+use ethers::{prelude::*, utils::format_ether};
+use std::io::Write;
+
+impl Contracts {
+    /// Record the gas used by a transaction deploying `name`.
+    ///
+    /// If a contract takes several transactions to deploy, the figures are accumulated.
+    pub(super) fn record_gas(&mut self, name: Contract, receipt: &TransactionReceipt) {
+        let gas = receipt.gas_used.unwrap_or_default();
+        let cost = gas * receipt.effective_gas_price.unwrap_or_default();
+        tracing::debug!("{name} deployment used {gas} gas, costing {cost} wei");
+        match self
+            .gas_used
+            .iter_mut()
+            .find(|(contract, _, _)| *contract == name)
+        {
+            Some((_, total_gas, total_cost)) => {
+                *total_gas += gas;
+                *total_cost += cost;
+            }
+            None => self.gas_used.push((name, gas, cost)),
+        }
+    }
+
+    /// The gas used and the cost in wei of each contract deployed in this run, in the order they
+    /// were deployed.
+    pub fn gas_report(&self) -> Vec<(Contract, U256, U256)> {
+        self.gas_used.clone()
+    }
+
+    /// Write a human-readable summary of the [gas report](Self::gas_report), with a total.
+    pub fn write_gas_report(&self, mut w: impl Write) -> anyhow::Result<()> {
+        writeln!(w, "Gas used:")?;
+        let mut total_gas = U256::zero();
+        let mut total_cost = U256::zero();
+        for (contract, gas, cost) in &self.gas_used {
+            total_gas += *gas;
+            total_cost += *cost;
+            writeln!(
+                w,
+                "  {:<20} {gas:>12} gas {:>24} ETH",
+                format!("{contract:?}"),
+                format_ether(*cost)
+            )?;
+        }
+        writeln!(
+            w,
+            "  {:<20} {total_gas:>12} gas {:>24} ETH",
+            "total",
+            format_ether(total_cost)
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{deployer::test_helpers::TEST_MNEMONIC, init_signer, AnvilOptions};
+    use async_std::sync::Arc;
+    use contract_bindings::{hot_shot::HotShot, plonk_verifier::PlonkVerifier};
+
+    fn receipt(gas: u64, price: u64) -> TransactionReceipt {
+        TransactionReceipt {
+            gas_used: Some(gas.into()),
+            effective_gas_price: Some(price.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_gas_report() {
+        let mut contracts = Contracts::default();
+        contracts.record_gas(Contract::PlonkVerifier, &receipt(100, 2));
+        contracts.record_gas(Contract::LightClient, &receipt(10, 3));
+        contracts.record_gas(Contract::PlonkVerifier, &receipt(50, 2));
+        assert_eq!(
+            contracts.gas_report(),
+            [
+                (Contract::PlonkVerifier, 150.into(), 300.into()),
+                (Contract::LightClient, 10.into(), 30.into()),
+            ]
+        );
+
+        let mut out = vec![];
+        contracts.write_gas_report(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("PlonkVerifier"), "{out}");
+        let total = out.lines().last().unwrap();
+        assert!(total.contains("total"), "{out}");
+        assert!(total.contains(" 160 gas"), "{out}");
+    }
+
+    #[async_std::test]
+    async fn test_gas_report_skips_predeployed() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::PlonkVerifier, Address::repeat_byte(1));
+        contracts
+            .deploy_tx(
+                Contract::PlonkVerifier,
+                PlonkVerifier::deploy(l1.clone(), ()).unwrap(),
+            )
+            .await
+            .unwrap();
+        // Deploy HotShot, then skip it the second time.
+        for _ in 0..2 {
+            contracts
+                .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+                .await
+                .unwrap();
+        }
+
+        let report = contracts.gas_report();
+        assert_eq!(report.len(), 1);
+        let (contract, gas, cost) = report[0];
+        assert_eq!(contract, Contract::HotShot);
+        assert!(!gas.is_zero());
+        assert!(!cost.is_zero());
+    }
+}
@@ -0,0 +1,176 @@
+//! Checking the genesis state the light client is initialized with.
+//!
+//! The light client verifies every state update against the stake table committed to in its
+//! genesis state, so a proxy initialized with the wrong genesis, for example one computed from a
+//! different stake table than the sequencer network actually runs with, rejects every update and
+//! has to be redeployed. [`check_genesis`] catches states which cannot be a genesis at all, and
+//! [`reconcile_genesis`] cross-checks a genesis given explicitly against the one the running network
+//! reports.
+
+use super::{warnings::Warning, Contract, Contracts};
+use anyhow::{bail, ensure};
+use ethers::types::U256;
+use hotshot_contract_adapter::light_client::ParsedLightClientState;
+
+/// Check that `genesis` is plausible as the genesis state of a light client.
+///
+/// The genesis must be at block height 0, and must commit to a non-empty stake table, in which case
+/// the threshold, two thirds of the total stake, is not zero.
+pub fn check_genesis(genesis: &ParsedLightClientState) -> anyhow::Result<()> {
+    ensure!(
+        genesis.block_height == 0,
+        "genesis state is at block height {}, expected 0",
+        genesis.block_height
+    );
+    ensure!(
+        !genesis.threshold.is_zero(),
+        "genesis state has a zero stake threshold, so its stake table is empty"
+    );
+    ensure!(
+        !genesis.bls_key_comm.is_zero()
+            && !genesis.schnorr_key_comm.is_zero()
+            && !genesis.amount_comm.is_zero(),
+        "genesis state does not commit to a stake table"
+    );
+    Ok(())
+}
+
+/// The fields in which two genesis states differ, with the value in each.
+pub fn genesis_diff(
+    a: &ParsedLightClientState,
+    b: &ParsedLightClientState,
+) -> Vec<(&'static str, U256, U256)> {
+    [
+        ("view_num", a.view_num.into(), b.view_num.into()),
+        ("block_height", a.block_height.into(), b.block_height.into()),
+        ("block_comm_root", a.block_comm_root, b.block_comm_root),
+        ("fee_ledger_comm", a.fee_ledger_comm, b.fee_ledger_comm),
+        ("bls_key_comm", a.bls_key_comm, b.bls_key_comm),
+        ("schnorr_key_comm", a.schnorr_key_comm, b.schnorr_key_comm),
+        ("amount_comm", a.amount_comm, b.amount_comm),
+        ("threshold", a.threshold, b.threshold),
+    ]
+    .into_iter()
+    .filter(|(_, a, b)| a != b)
+    .collect()
+}
+
+/// Choose the genesis state to initialize the light client with.
+///
+/// `explicit` is a genesis given by the operator, and `fetched` the genesis reported by the running
+/// sequencer network. If both are given, they must match, unless `force` is set, in which case the
+/// mismatch is recorded in `contracts` as a [`Warning::GenesisMismatch`] and `explicit` is used. Whichever genesis is chosen is checked with
+/// [`check_genesis`]. Returns `None` if neither is given.
+pub fn reconcile_genesis(
+    contracts: &mut Contracts,
+    explicit: Option<ParsedLightClientState>,
+    fetched: Option<ParsedLightClientState>,
+    force: bool,
+) -> anyhow::Result<Option<ParsedLightClientState>> {
+    let genesis = match (explicit, fetched) {
+        (Some(explicit), Some(fetched)) => {
+            let diff = genesis_diff(&explicit, &fetched);
+            if !diff.is_empty() {
+                let diff = diff
+                    .into_iter()
+                    .map(|(field, given, actual)| {
+                        format!("{field}: given {given}, network {actual}")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                if !force {
+                    bail!(
+                        "genesis state does not match the one reported by the sequencer network \
+                         ({diff}); force the given genesis to use it anyway"
+                    );
+                }
+                contracts.warn(Warning::GenesisMismatch {
+                    contract: Contract::LightClient,
+                    diff,
+                });
+            }
+            explicit
+        }
+        (Some(genesis), None) | (None, Some(genesis)) => genesis,
+        (None, None) => return Ok(None),
+    };
+    check_genesis(&genesis)?;
+    Ok(Some(genesis))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_genesis() {
+        check_genesis(&ParsedLightClientState::dummy_genesis()).unwrap();
+
+        let mut genesis = ParsedLightClientState::dummy_genesis();
+        genesis.block_height = 5;
+        let err = check_genesis(&genesis).unwrap_err().to_string();
+        assert!(err.contains("block height 5"), "{err}");
+
+        let mut genesis = ParsedLightClientState::dummy_genesis();
+        genesis.threshold = U256::zero();
+        let err = check_genesis(&genesis).unwrap_err().to_string();
+        assert!(err.contains("stake table is empty"), "{err}");
+    }
+
+    #[test]
+    fn test_reconcile_genesis() {
+        let genesis = ParsedLightClientState::dummy_genesis();
+        let mut other = genesis.clone();
+        other.bls_key_comm = 456.into();
+        let mut contracts = Contracts::default();
+
+        assert_eq!(
+            reconcile_genesis(&mut contracts, None, None, false).unwrap(),
+            None
+        );
+        assert_eq!(
+            reconcile_genesis(&mut contracts, None, Some(genesis.clone()), false).unwrap(),
+            Some(genesis.clone())
+        );
+        assert_eq!(
+            reconcile_genesis(
+                &mut contracts,
+                Some(genesis.clone()),
+                Some(genesis.clone()),
+                false
+            )
+            .unwrap(),
+            Some(genesis.clone())
+        );
+
+        // A mismatch is refused, unless forced.
+        let err = reconcile_genesis(
+            &mut contracts,
+            Some(other.clone()),
+            Some(genesis.clone()),
+            false,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("bls_key_comm: given 456, network 123"),
+            "{err}"
+        );
+        assert_eq!(
+            reconcile_genesis(&mut contracts, Some(other.clone()), Some(genesis), true).unwrap(),
+            Some(other)
+        );
+        assert_eq!(
+            contracts.warnings(),
+            [Warning::GenesisMismatch {
+                contract: Contract::LightClient,
+                diff: "bls_key_comm: given 456, network 123".into(),
+            }]
+        );
+
+        // Even a forced genesis must be plausible.
+        let mut bogus = ParsedLightClientState::dummy_genesis();
+        bogus.block_height = 1;
+        reconcile_genesis(&mut contracts, Some(bogus), None, true).unwrap_err();
+    }
+}
@@ -0,0 +1,389 @@
+//! Deploying to several chains in one run.
+//!
+//! Some contracts live on a different chain than the rest, like the fee contract, which is deployed
+//! to the rollup's own chain while the light client is deployed to the L1. A
+//! [`MultiChainContracts`] holds a separate [`Contracts`] cache for each chain, named by a label
+//! like `sepolia`, and [`ChainTargets`] declares which chain each contract is deployed to.
+//!
+//! Since an address is only meaningful on the chain it was deployed to, predeployed addresses can
+//! be scoped to a chain by prefixing them with its label, like `sepolia:0x...`. An unscoped address
+//! belongs to the chain its contract is deployed to. Each cache should also record the
+//! [chain ID](Contracts::with_chain_id) of its chain, so that it is never used with a provider
+//! connected to a different chain.
+//!
+//! The addresses deployed to every chain are written either to one `.env` file per chain, or to a
+//! single file in which each variable is prefixed with the label of its chain.
+
+use super::{Contract, Contracts};
+use anyhow::{ensure, Context};
+use clap::ValueEnum;
+use ethers::{types::Address, utils::to_checksum};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display, Formatter},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// A predeployed address, optionally scoped to a chain, in the form `[CHAIN:]ADDRESS`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScopedAddress {
+    /// The label of the chain the address belongs to, if given.
+    pub chain: Option<String>,
+    pub address: Address,
+    /// Whether the address was given in mixed case which is not a valid EIP-55 checksum.
+    bad_checksum: bool,
+}
+
+impl ScopedAddress {
+    /// Whether the address was given with a valid EIP-55 checksum, or in a single case, so that it
+    /// has no checksum.
+    pub fn checksum_valid(&self) -> bool {
+        !self.bad_checksum
+    }
+
+    /// Whether this address belongs to the chain `label`, given that its contract is deployed to
+    /// the chain `target`.
+    pub fn belongs_to(&self, label: &str, target: &str) -> bool {
+        self.chain.as_deref().unwrap_or(target) == label
+    }
+}
+
+impl From<Address> for ScopedAddress {
+    fn from(address: Address) -> Self {
+        Self {
+            chain: None,
+            address,
+            bad_checksum: false,
+        }
+    }
+}
+
+impl FromStr for ScopedAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (chain, address) = match s.split_once(':') {
+            Some(("", _)) => return Err(format!("missing chain name in {s}")),
+            Some((chain, address)) => (Some(chain.to_string()), address),
+            None => (None, s),
+        };
+        let hex = address.strip_prefix("0x").unwrap_or(address);
+        let parsed = address
+            .parse()
+            .map_err(|err| format!("invalid address {address}: {err}"))?;
+        let mixed_case = hex.chars().any(|c| c.is_ascii_uppercase())
+            && hex.chars().any(|c| c.is_ascii_lowercase());
+        Ok(Self {
+            chain,
+            address: parsed,
+            bad_checksum: mixed_case && to_checksum(&parsed, None)[2..] != *hex,
+        })
+    }
+}
+
+impl Display for ScopedAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(chain) = &self.chain {
+            write!(f, "{chain}:")?;
+        }
+        write!(f, "{:#x}", self.address)
+    }
+}
+
+/// Which chain each contract is deployed to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainTargets {
+    default: String,
+    targets: HashMap<Contract, String>,
+}
+
+impl ChainTargets {
+    /// Deploy every contract to the chain `default`, unless declared otherwise.
+    pub fn new(default: impl Into<String>) -> Self {
+        Self {
+            default: default.into(),
+            targets: Default::default(),
+        }
+    }
+
+    /// Deploy `contract` to the chain `label`.
+    pub fn deploy_on(mut self, contract: Contract, label: impl Into<String>) -> Self {
+        self.targets.insert(contract, label.into());
+        self
+    }
+
+    /// The chain `contract` is deployed to.
+    pub fn target(&self, contract: Contract) -> &str {
+        self.targets.get(&contract).unwrap_or(&self.default)
+    }
+
+    /// The chain contracts are deployed to unless declared otherwise.
+    pub fn default_chain(&self) -> &str {
+        &self.default
+    }
+
+    /// Every chain some contract is deployed to, including the default chain.
+    pub fn chains(&self) -> Vec<&str> {
+        let mut chains: Vec<_> = self.targets.values().map(String::as_str).collect();
+        chains.push(&self.default);
+        chains.sort();
+        chains.dedup();
+        chains
+    }
+}
+
+/// How to write the addresses deployed to several chains.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum EnvLayout {
+    /// A single `.env` file, with each variable prefixed by the label of its chain, like
+    /// `SEPOLIA_ESPRESSO_SEQUENCER_LIGHT_CLIENT_PROXY_ADDRESS`.
+    #[default]
+    Prefixed,
+    /// A directory with one `.env` file per chain, named after the label of the chain.
+    PerChain,
+}
+
+/// Caches of contracts on several chains, deployed in the same run.
+#[derive(Clone, Debug)]
+pub struct MultiChainContracts {
+    targets: ChainTargets,
+    chains: BTreeMap<String, Contracts>,
+}
+
+impl MultiChainContracts {
+    /// Deploy contracts to chains according to `targets`.
+    ///
+    /// A cache must be [added](Self::add_chain) for each chain before deploying to it.
+    pub fn new(targets: ChainTargets) -> Self {
+        Self {
+            targets,
+            chains: Default::default(),
+        }
+    }
+
+    /// Add the cache of contracts on the chain `label`.
+    ///
+    /// `contracts` should record the [chain ID](Contracts::with_chain_id) of the chain, so that it
+    /// is never used on another chain.
+    pub fn add_chain(
+        &mut self,
+        label: impl Into<String>,
+        contracts: Contracts,
+    ) -> anyhow::Result<()> {
+        let label = label.into();
+        ensure!(
+            !self.chains.contains_key(&label),
+            "chain {label} was added twice"
+        );
+        self.chains.insert(label, contracts);
+        Ok(())
+    }
+
+    /// Which chain each contract is deployed to.
+    pub fn targets(&self) -> &ChainTargets {
+        &self.targets
+    }
+
+    /// The cache of contracts on the chain `label`.
+    pub fn chain(&self, label: &str) -> Option<&Contracts> {
+        self.chains.get(label)
+    }
+
+    /// The cache of contracts on the chain `label`, to deploy to it.
+    pub fn chain_mut(&mut self, label: &str) -> Option<&mut Contracts> {
+        self.chains.get_mut(label)
+    }
+
+    /// The cache of contracts on the chain `contract` is deployed to.
+    pub fn chain_for(&mut self, contract: Contract) -> anyhow::Result<&mut Contracts> {
+        let label = self.targets.target(contract);
+        self.chains
+            .get_mut(label)
+            .with_context(|| format!("{contract:?} is deployed to unknown chain {label}"))
+    }
+
+    /// Each chain, with its cache of contracts, sorted by label.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Contracts)> {
+        self.chains
+            .iter()
+            .map(|(label, contracts)| (label.as_str(), contracts))
+    }
+
+    /// Write the contracts on every chain to a single `.env` file, prefixing each variable with the
+    /// label of its chain.
+    pub fn write_prefixed(&self, mut w: impl Write) -> anyhow::Result<()> {
+        for (label, contracts) in self.iter() {
+            let prefix = env_prefix(label);
+            for (contract, address) in contracts.sorted() {
+                writeln!(w, "{prefix}_{contract}={address:#x}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the contracts on each chain to its own `.env` file in `dir`, named `LABEL.env`.
+    ///
+    /// `dir` is created if it does not exist. Returns the paths of the files written.
+    pub fn write_per_chain(&self, dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        fs::create_dir_all(dir).with_context(|| format!("error creating {}", dir.display()))?;
+        let mut paths = vec![];
+        for (label, contracts) in self.iter() {
+            let path = dir.join(format!("{label}.env"));
+            let file =
+                File::create(&path).with_context(|| format!("error writing {}", path.display()))?;
+            contracts.write(file)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+/// The prefix of env vars for the chain `label`: the label in upper case, with anything which is
+/// not allowed in a variable name replaced by `_`.
+fn env_prefix(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deployer::DeployedContracts;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_scoped_address() {
+        let addr = Address::repeat_byte(1);
+        assert_eq!(
+            format!("{addr:#x}").parse::<ScopedAddress>().unwrap(),
+            ScopedAddress::from(addr)
+        );
+        let scoped: ScopedAddress = format!("sepolia:{addr:#x}").parse().unwrap();
+        assert_eq!(scoped.chain.as_deref(), Some("sepolia"));
+        assert_eq!(scoped.address, addr);
+        assert_eq!(scoped.to_string(), format!("sepolia:{addr:#x}"));
+
+        format!(":{addr:#x}").parse::<ScopedAddress>().unwrap_err();
+        "sepolia:0x12".parse::<ScopedAddress>().unwrap_err();
+
+        // Checksums are checked only for mixed-case addresses.
+        let addr = "0x4e59b44847b379578588920cA78FbF26c0B4956C";
+        assert!(addr.parse::<ScopedAddress>().unwrap().checksum_valid());
+        assert!(addr
+            .to_lowercase()
+            .parse::<ScopedAddress>()
+            .unwrap()
+            .checksum_valid());
+        let bad = addr.replace("cA78", "Ca78");
+        let scoped: ScopedAddress = bad.parse().unwrap();
+        assert!(!scoped.checksum_valid());
+        assert_eq!(scoped.address, addr.parse::<Address>().unwrap());
+    }
+
+    #[test]
+    fn test_predeployed_addresses_are_scoped() {
+        let targets = ChainTargets::new("l1")
+            .deploy_on(Contract::FeeContract, "rollup")
+            .deploy_on(Contract::FeeContractProxy, "rollup");
+        assert_eq!(targets.chains(), ["l1", "rollup"]);
+
+        let deployed = DeployedContracts::parse_from([
+            "deploy".to_string(),
+            format!("--hotshot={:#x}", Address::repeat_byte(1)),
+            format!(
+                "--light-client-proxy=sepolia:{:#x}",
+                Address::repeat_byte(2)
+            ),
+            format!("--fee-contract={:#x}", Address::repeat_byte(3)),
+            format!("--fee-contract-proxy=l1:{:#x}", Address::repeat_byte(4)),
+        ]);
+
+        // Unscoped addresses belong to the chain their contract is deployed to.
+        let l1 = deployed.for_chain("l1", &targets);
+        assert_eq!(l1.address(Contract::HotShot), Some(Address::repeat_byte(1)));
+        assert_eq!(l1.address(Contract::FeeContract), None);
+        // A scoped address belongs to its chain, wherever its contract is deployed.
+        assert_eq!(
+            l1.address(Contract::FeeContractProxy),
+            Some(Address::repeat_byte(4))
+        );
+        assert_eq!(l1.address(Contract::LightClientProxy), None);
+
+        let rollup = deployed.for_chain("rollup", &targets);
+        assert_eq!(
+            rollup.address(Contract::FeeContract),
+            Some(Address::repeat_byte(3))
+        );
+        assert_eq!(rollup.address(Contract::HotShot), None);
+        assert_eq!(rollup.address(Contract::FeeContractProxy), None);
+
+        let sepolia = deployed.for_chain("sepolia", &ChainTargets::new("sepolia"));
+        assert_eq!(
+            sepolia.address(Contract::LightClientProxy),
+            Some(Address::repeat_byte(2))
+        );
+        assert_eq!(deployed.scoped_chains(), ["l1", "sepolia"]);
+    }
+
+    #[test]
+    fn test_write_multi_chain() {
+        let targets = ChainTargets::new("l1").deploy_on(Contract::FeeContractProxy, "my-rollup");
+        let mut multi = MultiChainContracts::new(targets);
+
+        let mut l1 = Contracts::default();
+        l1.insert(Contract::HotShot, Address::repeat_byte(1));
+        let mut rollup = Contracts::default();
+        rollup.insert(Contract::FeeContractProxy, Address::repeat_byte(2));
+        multi.add_chain("l1", l1).unwrap();
+        multi.add_chain("my-rollup", rollup).unwrap();
+        multi.add_chain("l1", Contracts::default()).unwrap_err();
+
+        assert_eq!(
+            multi
+                .chain_for(Contract::FeeContractProxy)
+                .unwrap()
+                .address(Contract::FeeContractProxy),
+            Some(Address::repeat_byte(2))
+        );
+
+        let mut out = vec![];
+        multi.write_prefixed(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "L1_{}={:#x}\nMY_ROLLUP_{}={:#x}\n",
+                Contract::HotShot,
+                Address::repeat_byte(1),
+                Contract::FeeContractProxy,
+                Address::repeat_byte(2)
+            )
+        );
+
+        let dir = TempDir::new().unwrap();
+        let paths = multi.write_per_chain(&dir.path().join("out")).unwrap();
+        assert_eq!(paths.len(), 2);
+        let rollup = Contracts::read(
+            fs::read_to_string(dir.path().join("out/my-rollup.env"))
+                .unwrap()
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            rollup.address(Contract::FeeContractProxy),
+            Some(Address::repeat_byte(2))
+        );
+        assert_eq!(rollup.address(Contract::HotShot), None);
+    }
+}
@@ -0,0 +1,117 @@
+//! Assigning nonces to deployment transactions explicitly.
+//!
+//! By default, the nonce of each deployment transaction is the pending transaction count of the
+//! sender, as reported by the L1 provider. Providers are not always consistent about the pending
+//! count while transactions are in flight, especially behind load balancers, so two transactions
+//! can end up with the same nonce. With [`Contracts::with_managed_nonce`], the deployer instead
+//! fetches the starting nonce once and counts up from there, so each deployment transaction gets the
+//! next nonce, and a retry reuses the nonce of the attempt it replaces.
+//!
+//! Other transactions sent from the same account, like ownership transfers, consume nonces without
+//! advancing the counter. The counter therefore never goes below the number of mined transactions
+//! of the sender, which, unlike the pending count, does not depend on what is in flight.
+
+use super::Contracts;
+use anyhow::Context;
+use ethers::prelude::*;
+
+impl Contracts {
+    /// Assign nonces to deployment transactions sent from the default sender of `l1` explicitly,
+    /// starting from its current pending transaction count.
+    pub async fn with_managed_nonce<M: Middleware + 'static>(
+        mut self,
+        l1: &M,
+    ) -> anyhow::Result<Self> {
+        let sender = l1
+            .default_sender()
+            .context("managing nonces requires a default sender")?;
+        let nonce = l1
+            .get_transaction_count(sender, Some(BlockNumber::Pending.into()))
+            .await
+            .context("error fetching nonce")?;
+        tracing::info!("managing nonces of {sender:#x}, starting from {nonce}");
+        self.nonce = Some((sender, nonce));
+        Ok(self)
+    }
+
+    /// The account whose nonces are managed, and the nonce of its next deployment transaction.
+    pub fn managed_nonce(&self) -> Option<(Address, U256)> {
+        self.nonce
+    }
+
+    /// The nonce to send the next deployment transaction from `sender` with, if its nonces are
+    /// managed.
+    pub(super) async fn next_nonce<M: Middleware + 'static>(
+        &self,
+        l1: &M,
+        sender: Option<Address>,
+    ) -> anyhow::Result<Option<U256>> {
+        let (Some((managed, next)), Some(sender)) = (self.nonce, sender) else {
+            return Ok(None);
+        };
+        if managed != sender {
+            return Ok(None);
+        }
+        let mined = l1
+            .get_transaction_count(sender, None)
+            .await
+            .context("error fetching nonce")?;
+        Ok(Some(next.max(mined)))
+    }
+
+    /// Record that the nonces of `sender` before `next` have been used.
+    pub(super) fn advance_nonce(&mut self, sender: Option<Address>, next: U256) {
+        if let Some((managed, nonce)) = &mut self.nonce {
+            if Some(*managed) == sender {
+                *nonce = (*nonce).max(next);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{test_helpers::TEST_MNEMONIC, Contract},
+        init_signer, AnvilOptions,
+    };
+    use async_std::sync::Arc;
+    use contract_bindings::{hot_shot::HotShot, plonk_verifier::PlonkVerifier};
+    use ethers::utils::get_contract_address;
+
+    #[async_std::test]
+    async fn test_managed_nonce() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let sender = l1.address();
+
+        let start = l1.get_transaction_count(sender, None).await.unwrap();
+        let mut contracts = Contracts::default().with_managed_nonce(&*l1).await.unwrap();
+        assert_eq!(contracts.managed_nonce(), Some((sender, start)));
+
+        let hotshot = contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(hotshot, get_contract_address(sender, start));
+        assert_eq!(contracts.managed_nonce(), Some((sender, start + 1)));
+
+        // A transaction sent outside the deployer consumes a nonce without advancing the counter,
+        // which catches up.
+        l1.send_transaction(TransactionRequest::pay(sender, 1), None)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let plonk = contracts
+            .deploy_tx(
+                Contract::PlonkVerifier,
+                PlonkVerifier::deploy(l1.clone(), ()).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(plonk, get_contract_address(sender, start + 2));
+        assert_eq!(contracts.managed_nonce(), Some((sender, start + 3)));
+    }
+}
@@ -0,0 +1,502 @@
+//! Webhook notifications about the progress of a deployment.
+//!
+//! A [`DeploymentNotifier`] posts a JSON [`Notification`] to a webhook (e.g. a Slack incoming
+//! webhook relay) when a deployment starts, finishes or fails. It follows the deployment through
+//! the [`DeployEvent`] stream of the [`Contracts`] cache. Delivery is best effort: failures are
+//! retried a few times and then logged, but never affect the outcome of the deployment.
+
+use super::{
+    error::DeployerError, serialize_decimal, warnings::Warning, Contract, Contracts, DeployEvent,
+};
+use async_std::{sync::Arc, task::sleep};
+use ethers::types::{Address, U256};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// A webhook to post notifications to.
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    url: Url,
+    attempts: usize,
+    delay: Duration,
+}
+
+impl Webhook {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            attempts: 3,
+            delay: Duration::from_secs(1),
+        }
+    }
+
+    /// Try to deliver each notification up to `attempts` times, waiting `delay` between attempts.
+    pub fn with_retries(mut self, attempts: usize, delay: Duration) -> Self {
+        self.attempts = attempts.max(1);
+        self.delay = delay;
+        self
+    }
+
+    /// Post a notification, returning whether it was delivered.
+    ///
+    /// Failures are logged, not returned, since a notification is never worth failing over.
+    pub async fn post(&self, notification: &Notification) -> bool {
+        for attempt in 1..=self.attempts {
+            let res = match surf::post(&self.url).body_json(notification) {
+                Ok(req) => req.await,
+                Err(err) => {
+                    tracing::warn!("error encoding webhook notification: {err}");
+                    return false;
+                }
+            };
+            match res {
+                Ok(res) if res.status().is_success() => return true,
+                Ok(res) => tracing::warn!(
+                    "webhook {} responded with {} (attempt {attempt}/{})",
+                    self.url,
+                    res.status(),
+                    self.attempts
+                ),
+                Err(err) => tracing::warn!(
+                    "error posting to webhook {} (attempt {attempt}/{}): {err}",
+                    self.url,
+                    self.attempts
+                ),
+            }
+            if attempt < self.attempts {
+                sleep(self.delay).await;
+            }
+        }
+        false
+    }
+}
+
+/// The payload posted to the webhook.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notification {
+    Started {
+        chain_id: u64,
+        deployer: Address,
+        /// Contracts which will be deployed, excluding predeployed contracts.
+        planned: Vec<Contract>,
+    },
+    Finished {
+        chain_id: u64,
+        deployer: Address,
+        /// The address of every contract, including predeployed contracts.
+        contracts: BTreeMap<Contract, Address>,
+        /// Block explorer links for each contract, if the chain has a known explorer.
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        links: BTreeMap<Contract, String>,
+        /// Contracts deployed in this run, in order.
+        deployed: Vec<Contract>,
+        /// The amount of ETH spent by the deployer, in wei.
+        #[serde(serialize_with = "serialize_decimal")]
+        cost: U256,
+        /// Warnings recorded during the deployment.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        warnings: Vec<Warning>,
+        duration_secs: f64,
+    },
+    Failed {
+        chain_id: u64,
+        deployer: Address,
+        error: String,
+        classification: ErrorClass,
+        /// The last contract successfully deployed in this run, if any.
+        last_deployed: Option<Contract>,
+        duration_secs: f64,
+    },
+}
+
+/// A coarse classification of a deployment failure, to tell at a glance what went wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// A transaction reverted.
+    Revert,
+    /// The deployer account ran out of funds.
+    InsufficientFunds,
+    /// The L1 RPC could not be reached or returned an error.
+    Rpc,
+    /// Anything else, like invalid configuration.
+    Other,
+}
+
+impl ErrorClass {
+    /// Classify `err`, by the [`DeployerError`] it was caused by, if any, or by its message.
+    pub fn classify(err: &anyhow::Error) -> Self {
+        let msg = format!("{err:#}");
+        let cause = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<DeployerError>());
+        if matches!(cause, Some(DeployerError::Revert { .. })) || msg.contains("revert") {
+            Self::Revert
+        } else if msg.contains("insufficient funds") {
+            Self::InsufficientFunds
+        } else if matches!(cause, Some(DeployerError::Rpc { .. }))
+            || err
+                .chain()
+                .any(|cause| cause.is::<ethers::providers::ProviderError>())
+        {
+            Self::Rpc
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Sends notifications about a deployment to a webhook.
+#[derive(Debug)]
+pub struct DeploymentNotifier {
+    webhook: Webhook,
+    chain_id: u64,
+    deployer: Address,
+    start: Instant,
+    deployed: Arc<Mutex<Vec<Contract>>>,
+}
+
+impl DeploymentNotifier {
+    /// Notify the webhook that a deployment is starting.
+    pub async fn start(
+        webhook: Webhook,
+        chain_id: u64,
+        deployer: Address,
+        planned: Vec<Contract>,
+    ) -> Self {
+        webhook
+            .post(&Notification::Started {
+                chain_id,
+                deployer,
+                planned,
+            })
+            .await;
+        Self {
+            webhook,
+            chain_id,
+            deployer,
+            start: Instant::now(),
+            deployed: Default::default(),
+        }
+    }
+
+    /// An observer to register with [`Contracts::with_observer`] to track the deployment.
+    pub fn observer(&self) -> impl Fn(&DeployEvent) + Send + Sync + 'static {
+        let deployed = self.deployed.clone();
+        move |event| {
+            if let DeployEvent::Deployed { contract, .. } = event {
+                deployed.lock().unwrap().push(*contract);
+            }
+        }
+    }
+
+    /// Notify the webhook that the deployment finished successfully.
+    ///
+    /// `cost` is the amount of ETH spent by the deployer.
+    pub async fn finish(&self, contracts: &Contracts, cost: U256) {
+        let deployed = self.deployed.lock().unwrap().clone();
+        self.webhook
+            .post(&Notification::Finished {
+                chain_id: self.chain_id,
+                deployer: self.deployer,
+                contracts: contracts.iter().collect(),
+                links: contracts
+                    .explorer()
+                    .map(|explorer| {
+                        contracts
+                            .iter()
+                            .map(|(c, a)| (c, explorer.address(a).to_string()))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                deployed,
+                cost,
+                warnings: contracts.warnings().to_vec(),
+                duration_secs: self.start.elapsed().as_secs_f64(),
+            })
+            .await;
+    }
+
+    /// Notify the webhook that the deployment failed.
+    pub async fn fail(&self, err: &anyhow::Error) {
+        let last_deployed = self.deployed.lock().unwrap().last().copied();
+        self.webhook
+            .post(&Notification::Failed {
+                chain_id: self.chain_id,
+                deployer: self.deployer,
+                error: format!("{err:#}"),
+                classification: ErrorClass::classify(err),
+                last_deployed,
+                duration_secs: self.start.elapsed().as_secs_f64(),
+            })
+            .await;
+    }
+}
+
+/// The contracts a full deployment will deploy, given the predeployed `contracts`.
+///
+/// `mock` indicates whether the mock light client is deployed, rather than the production light
+/// client behind a proxy.
+pub fn planned_contracts(contracts: &Contracts, mock: bool) -> Vec<Contract> {
+    let mut planned = vec![
+        Contract::HotShot,
+        Contract::PlonkVerifier,
+        Contract::StateUpdateVK,
+        Contract::LightClient,
+    ];
+    if !mock {
+        planned.push(Contract::LightClientProxy);
+    }
+    planned.extend([Contract::FeeContract, Contract::FeeContractProxy]);
+    // The libraries are only needed to deploy the light client.
+    if contracts.contains(Contract::LightClient) {
+        planned.retain(|c| !matches!(c, Contract::PlonkVerifier | Contract::StateUpdateVK));
+    }
+    planned.retain(|&c| !contracts.contains(c));
+    planned
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{explorer::Explorer, test_helpers::TEST_MNEMONIC},
+        init_signer, AnvilOptions,
+    };
+    use anyhow::anyhow;
+    use async_std::{
+        channel::{unbounded, Receiver},
+        io::{prelude::BufReadExt, BufReader, ReadExt, WriteExt},
+        net::TcpListener,
+        task::spawn,
+    };
+    use contract_bindings::hot_shot::HotShot;
+    use futures::FutureExt;
+    use serde_json::{json, Value};
+
+    /// A mock webhook which records the body of each request.
+    ///
+    /// The first `failures` requests get a 500 response, the rest succeed.
+    async fn mock_webhook(failures: usize) -> (Webhook, Receiver<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let (tx, rx) = unbounded();
+        spawn(async move {
+            let mut requests = 0;
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut reader = BufReader::new(stream.clone());
+                let mut len = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    let line = line.trim().to_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("content-length:") {
+                        len = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).await.unwrap();
+
+                requests += 1;
+                let status = if requests <= failures {
+                    "500 Internal Server Error"
+                } else {
+                    tx.send(serde_json::from_slice(&body).unwrap())
+                        .await
+                        .unwrap();
+                    "200 OK"
+                };
+                let mut stream = stream;
+                stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {status}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+        (
+            Webhook::new(url).with_retries(3, Duration::from_millis(10)),
+            rx,
+        )
+    }
+
+    #[async_std::test]
+    async fn test_notify_success() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let (webhook, rx) = mock_webhook(0).await;
+
+        let notifier =
+            DeploymentNotifier::start(webhook, 31337, l1.address(), vec![Contract::HotShot]).await;
+        let started = rx.recv().await.unwrap();
+        assert_eq!(started["event"], "started");
+        assert_eq!(started["chain_id"], 31337);
+        assert_eq!(started["deployer"], format!("{:#x}", l1.address()));
+        assert_eq!(started["planned"], json!([Contract::HotShot.to_string()]));
+
+        let mut contracts = Contracts::default()
+            .with_observer(notifier.observer())
+            .with_explorer(Explorer::new("http://explorer".parse().unwrap()));
+        contracts.insert(Contract::PlonkVerifier, Address::repeat_byte(1));
+        let hotshot = contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+        contracts.skip_verify_predeployed();
+        notifier.finish(&contracts, 1000.into()).await;
+
+        let finished = rx.recv().await.unwrap();
+        assert_eq!(finished["event"], "finished");
+        assert_eq!(
+            finished["contracts"],
+            serde_json::to_value(BTreeMap::from([
+                (Contract::HotShot, hotshot),
+                (Contract::PlonkVerifier, Address::repeat_byte(1)),
+            ]))
+            .unwrap()
+        );
+        assert_eq!(
+            finished["links"][Contract::HotShot.to_string()],
+            format!("http://explorer/address/{hotshot:#x}")
+        );
+        assert_eq!(finished["deployed"], json!([Contract::HotShot.to_string()]));
+        assert_eq!(finished["cost"], "1000");
+        assert_eq!(finished["warnings"][0]["kind"], "unverified");
+    }
+
+    #[async_std::test]
+    async fn test_notify_failure() {
+        let (webhook, rx) = mock_webhook(0).await;
+        let notifier = DeploymentNotifier::start(
+            webhook,
+            1,
+            Address::zero(),
+            vec![Contract::PlonkVerifier, Contract::HotShot],
+        )
+        .await;
+        rx.recv().await.unwrap();
+
+        let mut contracts = Contracts::default().with_observer(notifier.observer());
+        contracts
+            .deploy_fn(Contract::PlonkVerifier, |_| {
+                async { Ok(Address::repeat_byte(1)) }.boxed()
+            })
+            .await
+            .unwrap();
+        let err = contracts
+            .deploy_fn(Contract::HotShot, |_| {
+                async { Err(anyhow!("deployment transaction reverted")) }.boxed()
+            })
+            .await
+            .unwrap_err();
+        notifier.fail(&err.into()).await;
+
+        let failed = rx.recv().await.unwrap();
+        assert_eq!(failed["event"], "failed");
+        assert_eq!(failed["error"], "deployment transaction reverted");
+        assert_eq!(failed["classification"], "revert");
+        assert_eq!(failed["last_deployed"], Contract::PlonkVerifier.to_string());
+    }
+
+    #[async_std::test]
+    async fn test_webhook_retries() {
+        // Delivered after failing twice.
+        let (webhook, rx) = mock_webhook(2).await;
+        let notification = Notification::Started {
+            chain_id: 1,
+            deployer: Address::zero(),
+            planned: vec![],
+        };
+        assert!(webhook.post(&notification).await);
+        assert_eq!(rx.recv().await.unwrap()["event"], "started");
+
+        // Given up after too many failures.
+        let (webhook, _rx) = mock_webhook(3).await;
+        assert!(!webhook.post(&notification).await);
+
+        // An unreachable webhook is not an error either.
+        let port = portpicker::pick_unused_port().unwrap();
+        let webhook = Webhook::new(format!("http://127.0.0.1:{port}").parse().unwrap())
+            .with_retries(2, Duration::from_millis(10));
+        assert!(!webhook.post(&notification).await);
+    }
+
+    #[test]
+    fn test_planned_contracts() {
+        let mut contracts = Contracts::default();
+        assert_eq!(
+            planned_contracts(&contracts, true),
+            [
+                Contract::HotShot,
+                Contract::PlonkVerifier,
+                Contract::StateUpdateVK,
+                Contract::LightClient,
+                Contract::FeeContract,
+                Contract::FeeContractProxy,
+            ]
+        );
+
+        contracts.insert(Contract::LightClient, Address::repeat_byte(1));
+        assert_eq!(
+            planned_contracts(&contracts, false),
+            [
+                Contract::HotShot,
+                Contract::LightClientProxy,
+                Contract::FeeContract,
+                Contract::FeeContractProxy,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_classify_errors() {
+        assert_eq!(
+            ErrorClass::classify(&anyhow!("execution reverted").context("failed to deploy")),
+            ErrorClass::Revert
+        );
+        assert_eq!(
+            ErrorClass::classify(&anyhow!("insufficient funds for gas * price + value")),
+            ErrorClass::InsufficientFunds
+        );
+        assert_eq!(
+            ErrorClass::classify(
+                &anyhow::Error::new(ethers::providers::ProviderError::CustomError(
+                    "connection refused".into()
+                ))
+                .context("failed to deploy")
+            ),
+            ErrorClass::Rpc
+        );
+        // A typed error is classified by its variant, whatever the middleware error.
+        assert_eq!(
+            ErrorClass::classify(
+                &anyhow::Error::new(DeployerError::Rpc {
+                    contract: Contract::HotShot,
+                    source: "connection refused".into(),
+                })
+                .context("failed to deploy")
+            ),
+            ErrorClass::Rpc
+        );
+        assert_eq!(
+            ErrorClass::classify(&anyhow!("bad config")),
+            ErrorClass::Other
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Machine-readable formats for deployment results.
+//!
+//! The addresses in the cache are written as a `.env` file by [`Contracts::write`], but tools
+//! other than the sequencer are better served by other formats. [`OutputFormat`] selects between
+//! the `.env` file, the JSON object of [`Contracts::write_json`], a flat TOML table
+//! ([`Contracts::write_toml`]), and a Foundry-style broadcast artifact
+//! ([`Contracts::write_broadcast`]), which also describes the transaction that deployed each
+//! contract.
+
+use super::{flavor::Flavor, Contract, Contracts, LightClientArtifact};
+use clap::ValueEnum;
+use ethers::{prelude::*, utils::to_checksum};
+use serde::Serialize;
+use std::{
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The format to write deployment results in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// A `.env` file, as written by [`Contracts::write`].
+    #[default]
+    Env,
+    /// A JSON object, as written by [`Contracts::write_json`].
+    Json,
+    /// A TOML table, as written by [`Contracts::write_toml`].
+    Toml,
+    /// A Foundry-style broadcast artifact, as written by [`Contracts::write_broadcast`].
+    Broadcast,
+}
+
+/// A deployment in the format of the `broadcast` artifacts written by `forge script`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Broadcast {
+    pub transactions: Vec<BroadcastTransaction>,
+    pub receipts: Vec<TransactionReceipt>,
+    /// Libraries linked into the deployed contracts, as `path:Name:address`.
+    pub libraries: Vec<String>,
+    /// Creation time, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub chain: u64,
+}
+
+/// A deployment transaction in a [`Broadcast`].
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BroadcastTransaction {
+    pub hash: H256,
+    /// Always `CREATE`, since only deployment transactions are listed.
+    pub transaction_type: String,
+    /// The env var name of the contract.
+    pub contract_name: Contract,
+    pub contract_address: Address,
+    pub transaction: BroadcastRequest,
+}
+
+/// The request of a [`BroadcastTransaction`].
+#[derive(Clone, Debug, Serialize)]
+pub struct BroadcastRequest {
+    pub from: Address,
+    /// The init code sent, which is the creation bytecode followed by the ABI-encoded constructor
+    /// arguments, if known.
+    pub input: Option<Bytes>,
+    pub value: U256,
+}
+
+impl Contracts {
+    /// Write the deployment results in `format`.
+    ///
+    /// `chain_id` is the chain the contracts were deployed to, which only the broadcast format
+    /// records.
+    pub fn write_as(
+        &self,
+        format: OutputFormat,
+        w: impl Write,
+        chain_id: u64,
+    ) -> anyhow::Result<()> {
+        match format {
+            OutputFormat::Env => self.write(w),
+            OutputFormat::Json => self.write_json(w),
+            OutputFormat::Toml => self.write_toml(w),
+            OutputFormat::Broadcast => self.write_broadcast(w, chain_id),
+        }
+    }
+
+    /// Write a TOML table mapping the env var name of each contract to its checksummed address.
+    pub fn write_toml(&self, mut w: impl Write) -> anyhow::Result<()> {
+        for (contract, address) in self.sorted() {
+            writeln!(w, "{contract} = \"{}\"", to_checksum(&address, None))?;
+        }
+        Ok(())
+    }
+
+    /// The transactions of this run as a Foundry-style [`Broadcast`] on `chain_id`.
+    ///
+    /// Only contracts deployed in this run, which have a [receipt](Self::receipts), are listed, in
+    /// the order they were deployed.
+    pub fn broadcast(&self, chain_id: u64) -> Broadcast {
+        let transactions = self
+            .receipts
+            .iter()
+            .filter_map(|(contract, receipt)| {
+                Some(BroadcastTransaction {
+                    hash: receipt.transaction_hash,
+                    transaction_type: "CREATE".into(),
+                    contract_name: *contract,
+                    contract_address: receipt.contract_address?,
+                    transaction: BroadcastRequest {
+                        from: receipt.from,
+                        input: self.init_codes.get(contract).cloned(),
+                        value: U256::zero(),
+                    },
+                })
+            })
+            .collect();
+
+        // The light client links with its libraries at their deployed addresses.
+        let mut libraries = vec![];
+        if self.receipt(Contract::LightClient).is_some() {
+            let artifact = match self.flavor_of(Contract::LightClient) {
+                Some(Flavor::Mock) => LightClientArtifact::Mock,
+                _ => LightClientArtifact::Production,
+            };
+            for (library, contract) in artifact.libraries() {
+                if let Some(address) = self.address(contract) {
+                    libraries.push(format!("{library}:{}", to_checksum(&address, None)));
+                }
+            }
+        }
+
+        Broadcast {
+            transactions,
+            receipts: self
+                .receipts
+                .iter()
+                .map(|(_, receipt)| receipt.clone())
+                .collect(),
+            libraries,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            chain: chain_id,
+        }
+    }
+
+    /// Write the transactions of this run as a Foundry-style [broadcast](Self::broadcast) artifact.
+    pub fn write_broadcast(&self, w: impl Write, chain_id: u64) -> anyhow::Result<()> {
+        serde_json::to_writer_pretty(w, &self.broadcast(chain_id))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{deploy_light_client_contract, test_helpers::TEST_MNEMONIC},
+        init_signer, AnvilOptions,
+    };
+    use async_std::sync::Arc;
+    use futures::{FutureExt, TryFutureExt};
+
+    #[async_std::test]
+    async fn test_output_formats() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let chain_id = l1.get_chainid().await.unwrap().as_u64();
+
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::HotShot, Address::repeat_byte(1));
+        contracts
+            .deploy_fn(Contract::LightClient, |contracts| {
+                deploy_light_client_contract(l1.clone(), contracts)
+                    .err_into()
+                    .boxed()
+            })
+            .await
+            .unwrap();
+        let plonk = contracts.address(Contract::PlonkVerifier).unwrap();
+        let light_client = contracts.address(Contract::LightClient).unwrap();
+
+        let mut toml = vec![];
+        contracts
+            .write_as(OutputFormat::Toml, &mut toml, chain_id)
+            .unwrap();
+        let toml = String::from_utf8(toml).unwrap();
+        assert!(
+            toml.contains(&format!(
+                "{} = \"{}\"\n",
+                Contract::HotShot,
+                to_checksum(&Address::repeat_byte(1), None)
+            )),
+            "{toml}"
+        );
+        assert_eq!(toml.lines().count(), contracts.sorted().len());
+
+        // The broadcast lists the transactions of this run, but not the predeployed HotShot.
+        let mut json = vec![];
+        contracts
+            .write_as(OutputFormat::Broadcast, &mut json, chain_id)
+            .unwrap();
+        let broadcast: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(broadcast["chain"], chain_id);
+        let transactions = broadcast["transactions"].as_array().unwrap();
+        assert_eq!(transactions.len(), 3);
+        assert_eq!(broadcast["receipts"].as_array().unwrap().len(), 3);
+        let tx = transactions
+            .iter()
+            .find(|tx| tx["contractName"] == Contract::LightClient.to_string())
+            .unwrap();
+        assert_eq!(tx["transactionType"], "CREATE");
+        assert_eq!(
+            tx["contractAddress"],
+            serde_json::to_value(light_client).unwrap()
+        );
+        assert_eq!(
+            tx["transaction"]["input"],
+            serde_json::to_value(&contracts.init_codes[&Contract::LightClient]).unwrap()
+        );
+        assert_eq!(
+            tx["hash"],
+            serde_json::to_value(
+                contracts
+                    .receipt(Contract::LightClient)
+                    .unwrap()
+                    .transaction_hash
+            )
+            .unwrap()
+        );
+        let library = serde_json::Value::from(format!(
+            "contracts/src/libraries/PlonkVerifier.sol:PlonkVerifier:{}",
+            to_checksum(&plonk, None)
+        ));
+        assert!(
+            broadcast["libraries"]
+                .as_array()
+                .unwrap()
+                .contains(&library),
+            "{broadcast}"
+        );
+    }
+}
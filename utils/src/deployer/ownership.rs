@@ -0,0 +1,352 @@
+//! Handing ownership of deployed contracts over from the deployer.
+//!
+//! In production, the deployer account should not remain the owner of the light client, which is
+//! also the only account allowed to upgrade it (`LightClient.sol` is a UUPS contract, so the
+//! [`ERC1967Proxy`](contract_bindings::erc1967_proxy::ERC1967Proxy) in front of it has no separate
+//! admin). Once the deployment is complete, ownership is transferred to a timelock or multisig
+//! with [`transfer_ownership`]. A contract which already belongs to a Safe is handed over by the
+//! Safe, with a transaction [prepared](super::safe::send_or_prepare) for it.
+
+use super::{
+    dry_run::DeployMode,
+    explorer::fmt_address,
+    safe::{send_or_prepare, SafeBatch, SafeTransaction, Submission},
+    warnings::Warning,
+    Contract, Contracts,
+};
+use anyhow::{bail, ensure, Context};
+use async_std::sync::Arc;
+use contract_bindings::light_client::LightClient;
+use ethers::prelude::*;
+
+/// Transfer ownership of `contract` from `deployer` to `new_owner`.
+///
+/// `contract` is an `Ownable` contract: [`Contract::LightClientProxy`],
+/// [`Contract::FeeContractProxy`], or [`Contract::LightClient`] for the mock light client, which
+/// has no proxy. The transfer is skipped, with a log message, if `new_owner` is the
+/// deployer or already owns the contract.
+///
+/// The transfer is made with [`send_or_prepare`]: if the deployer owns the contract, the transfer
+/// transaction is sent, and retried according to the [retry policy](Contracts::with_retry_policy)
+/// of `contracts`. Before each attempt, the owner is read again, so that an attempt which landed
+/// after all is not repeated. Once the transaction succeeds, the transfer is verified by reading
+/// the owner back. If the contract is owned by a Safe, for example because it was predeployed and
+/// handed over in a previous run, the transfer is added to the
+/// [batch](Contracts::safe_batches) prepared for the Safe instead. If it is owned by any other
+/// account, the current owner is reported with a [`Warning::ForeignOwner`] and nothing is sent,
+/// since the transfer would revert.
+///
+/// Transferring to the zero address is refused, since it would leave the contract without an
+/// owner, and so impossible to upgrade, forever.
+pub async fn transfer_ownership<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    contract: Contract,
+    deployer: Address,
+    new_owner: Address,
+) -> anyhow::Result<()> {
+    ensure!(
+        !new_owner.is_zero(),
+        "refusing to transfer ownership of {contract:?} to the zero address"
+    );
+    if new_owner == deployer {
+        tracing::info!("{contract:?} owner is the deployer {deployer:#x}, not transferring");
+        return Ok(());
+    }
+    let address = contracts
+        .address(contract)
+        .with_context(|| format!("cannot transfer ownership of {contract:?}, not deployed"))?;
+    let light_client = LightClient::new(address, l1.clone());
+    let tx = light_client.transfer_ownership(new_owner).tx;
+    if contracts.mode() == DeployMode::DryRun {
+        tracing::info!("would transfer ownership of {contract:?} to {new_owner:#x}");
+        if contracts.unsigned.is_some() {
+            let gas = l1.estimate_gas(&tx, None).await.ok();
+            contracts
+                .write_unsigned(&format!("{contract:?}-transfer-ownership"), &*l1, tx, gas)
+                .await?;
+        }
+        return Ok(());
+    }
+    let explorer = contracts.explorer().cloned();
+    let owner = read_owner(&light_client).await?;
+    if owner == new_owner {
+        tracing::info!("{contract:?} is already owned by {new_owner:#x}");
+        return Ok(());
+    }
+    if owner != deployer && !contracts.can_act_as(&*l1, owner).await? {
+        contracts.warn(Warning::ForeignOwner {
+            contract,
+            owner,
+            expected: new_owner,
+        });
+        return Ok(());
+    }
+
+    tracing::info!("transferring ownership of {contract:?} from {owner:#x} to {new_owner:#x}");
+    let data = tx.data().cloned().unwrap_or_default();
+    let light_client = &light_client;
+    let submission = send_or_prepare(
+        &*l1,
+        contracts,
+        owner,
+        SafeTransaction::new(address, data),
+        &format!("{contract:?} ownership transfer"),
+        move || async move {
+            let current = read_owner(light_client).await?;
+            ensure!(
+                current == owner || current == new_owner,
+                "{contract:?} was taken over by {current:#x} while transferring its ownership"
+            );
+            Ok(current == new_owner)
+        },
+    )
+    .await?;
+    if let Submission::Prepared { safe } = submission {
+        tracing::info!(
+            "prepared ownership transfer of {contract:?} to {new_owner:#x} for its owner {safe:#x}"
+        );
+        return Ok(());
+    }
+
+    let owner = read_owner(light_client).await?;
+    ensure!(
+        owner == new_owner,
+        "ownership of {contract:?} was not transferred: owner is {owner:#x}, expected \
+         {new_owner:#x}"
+    );
+    tracing::info!(
+        "{contract:?} is owned by {}",
+        fmt_address(explorer.as_ref(), owner)
+    );
+    Ok(())
+}
+
+/// Transfer ownership of the light client proxy at `proxy` to `new_owner`.
+///
+/// This is [`transfer_ownership`] for a proxy which is not in a [`Contracts`] cache, sent from the
+/// default sender of `l1` with the default retry policy. It returns once the transfer has been
+/// confirmed and the new owner read back or, if the proxy is owned by a Safe, with the batch
+/// prepared for the Safe to make the transfer. Unlike [`transfer_ownership`], finding the proxy
+/// owned by any other account is an error.
+pub async fn transfer_light_client_ownership<M: Middleware + 'static>(
+    l1: Arc<M>,
+    proxy: Address,
+    new_owner: Address,
+) -> anyhow::Result<Option<SafeBatch>> {
+    let sender = l1
+        .default_sender()
+        .context("cannot transfer ownership of light client, sender is unknown")?;
+    let mut contracts = Contracts::default();
+    contracts.insert(Contract::LightClientProxy, proxy);
+    transfer_ownership(
+        l1,
+        &mut contracts,
+        Contract::LightClientProxy,
+        sender,
+        new_owner,
+    )
+    .await?;
+    if let Some(warning) = contracts.warnings().first() {
+        bail!("{warning}");
+    }
+    Ok(contracts.safe_batches().first().cloned())
+}
+
+async fn read_owner<M: Middleware + 'static>(
+    light_client: &LightClient<M>,
+) -> anyhow::Result<Address> {
+    light_client
+        .owner()
+        .call()
+        .await
+        .context("error reading owner")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{
+            deploy_light_client_and_initialize_proxy,
+            test_helpers::{deploy_safe_stub, execute_safe_batch, TEST_MNEMONIC},
+        },
+        init_signer, AnvilOptions,
+    };
+    use hotshot_contract_adapter::light_client::ParsedLightClientState;
+
+    #[async_std::test]
+    async fn test_transfer_ownership() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let deployer = l1.address();
+        let new_owner = Address::repeat_byte(1);
+
+        let mut contracts = Contracts::default();
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            deployer,
+        )
+        .await
+        .unwrap();
+        let light_client = LightClient::new(proxy, l1.clone());
+
+        // Transferring to the deployer is a no-op.
+        let nonce = l1.get_transaction_count(deployer, None).await.unwrap();
+        transfer_ownership(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            deployer,
+            deployer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            l1.get_transaction_count(deployer, None).await.unwrap(),
+            nonce
+        );
+
+        // Transfer to a different owner.
+        transfer_ownership(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            deployer,
+            new_owner,
+        )
+        .await
+        .unwrap();
+        assert_eq!(read_owner(&light_client).await.unwrap(), new_owner);
+
+        // Transferring again is a no-op.
+        let nonce = l1.get_transaction_count(deployer, None).await.unwrap();
+        transfer_ownership(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            deployer,
+            new_owner,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            l1.get_transaction_count(deployer, None).await.unwrap(),
+            nonce
+        );
+        assert!(contracts.warnings().is_empty());
+
+        // A contract owned by someone else is reported, not an error.
+        transfer_ownership(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            deployer,
+            Address::repeat_byte(2),
+        )
+        .await
+        .unwrap();
+        assert_eq!(read_owner(&light_client).await.unwrap(), new_owner);
+        assert_eq!(
+            contracts.warnings(),
+            [Warning::ForeignOwner {
+                contract: Contract::LightClientProxy,
+                owner: new_owner,
+                expected: Address::repeat_byte(2),
+            }]
+        );
+
+        // Transferring to the zero address is refused.
+        let err = transfer_ownership(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            deployer,
+            Address::zero(),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("zero address"), "{err}");
+    }
+
+    #[async_std::test]
+    async fn test_transfer_light_client_ownership() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let new_owner = Address::repeat_byte(1);
+
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut Contracts::default(),
+            ParsedLightClientState::dummy_genesis(),
+            l1.address(),
+        )
+        .await
+        .unwrap();
+        let batch = transfer_light_client_ownership(l1.clone(), proxy, new_owner)
+            .await
+            .unwrap();
+        assert_eq!(batch, None);
+        let light_client = LightClient::new(proxy, l1.clone());
+        assert_eq!(read_owner(&light_client).await.unwrap(), new_owner);
+
+        // Once we no longer own the proxy, transferring elsewhere fails.
+        let err = transfer_light_client_ownership(l1.clone(), proxy, Address::repeat_byte(2))
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("not the deployer"), "{err}");
+    }
+
+    #[async_std::test]
+    async fn test_transfer_ownership_from_safe() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let deployer = l1.address();
+        let new_owner = Address::repeat_byte(1);
+
+        // A light client which was handed over to a Safe in an earlier run.
+        let safe = deploy_safe_stub(&l1).await.unwrap();
+        let mut contracts = Contracts::default();
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            safe,
+        )
+        .await
+        .unwrap();
+        let light_client = LightClient::new(proxy, l1.clone());
+
+        // The transfer is prepared for the Safe, and nothing is sent.
+        let nonce = l1.get_transaction_count(deployer, None).await.unwrap();
+        transfer_ownership(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            deployer,
+            new_owner,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            l1.get_transaction_count(deployer, None).await.unwrap(),
+            nonce
+        );
+        assert_eq!(read_owner(&light_client).await.unwrap(), safe);
+        assert!(contracts.warnings().is_empty());
+        let [batch] = contracts.safe_batches() else {
+            panic!("expected one batch: {:?}", contracts.safe_batches());
+        };
+        assert_eq!(batch.meta.created_from_safe_address, safe);
+        assert_eq!(batch.transactions.len(), 1);
+        assert_eq!(batch.transactions[0].to, proxy);
+
+        // Executing the batch from the Safe transfers ownership.
+        execute_safe_batch(&l1, batch).await.unwrap();
+        assert_eq!(read_owner(&light_client).await.unwrap(), new_owner);
+    }
+}
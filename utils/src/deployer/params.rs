@@ -0,0 +1,245 @@
+//! Parameters the light client is deployed with, other than its genesis state.
+//!
+//! The stake table capacity determines the genesis state the light client is initialized with,
+//! since the stake table commitments are computed over a table of that size, and it has to match
+//! the capacity of the circuit the prover generates proofs with. The number of blocks per epoch is
+//! passed to the light client directly, by its constructor for the mock light client and by
+//! `initialize` otherwise. [`LightClientDeployParams`] collects both, so that the deployer takes
+//! them from the command line, and [`Contracts::with_light_client_params`] checks them before
+//! anything is deployed.
+
+use super::{warnings::Warning, Contract, Contracts};
+use anyhow::ensure;
+use clap::Args;
+pub use hotshot_stake_table::config::STAKE_TABLE_CAPACITY;
+
+/// Parameters of the light client.
+#[derive(Args, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LightClientDeployParams {
+    /// Stake table capacity for the prover circuit.
+    ///
+    /// This must not exceed the capacity the bundled verifying key was generated for.
+    #[clap(
+        short,
+        long,
+        env = "ESPRESSO_SEQUENCER_STAKE_TABLE_CAPACITY",
+        default_value_t = STAKE_TABLE_CAPACITY
+    )]
+    pub stake_table_capacity: usize,
+
+    /// Number of HotShot blocks in each epoch of the light client.
+    ///
+    /// The default is effectively a single epoch, which is what the prover expects.
+    #[clap(
+        long,
+        env = "ESPRESSO_DEPLOYER_BLOCKS_PER_EPOCH",
+        default_value_t = u32::MAX
+    )]
+    pub blocks_per_epoch: u32,
+}
+
+impl Default for LightClientDeployParams {
+    fn default() -> Self {
+        Self {
+            stake_table_capacity: STAKE_TABLE_CAPACITY,
+            blocks_per_epoch: u32::MAX,
+        }
+    }
+}
+
+impl LightClientDeployParams {
+    /// Check that the light client can be deployed with these parameters.
+    ///
+    /// The stake table capacity must be at least 1, and at most [`STAKE_TABLE_CAPACITY`], the
+    /// capacity `LightClientStateUpdateVK.sol` was generated for: proofs over a larger stake table
+    /// would never verify. Epochs must have at least one block, or the light client refuses to be
+    /// initialized.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.stake_table_capacity > 0,
+            "stake table capacity must be at least 1"
+        );
+        ensure!(
+            self.stake_table_capacity <= STAKE_TABLE_CAPACITY,
+            "stake table capacity {} exceeds {STAKE_TABLE_CAPACITY}, the capacity the light client \
+             verifying key supports",
+            self.stake_table_capacity
+        );
+        ensure!(
+            self.blocks_per_epoch > 0,
+            "blocks per epoch must be at least 1"
+        );
+        Ok(())
+    }
+}
+
+impl Contracts {
+    /// Deploy the light client with `params`, after checking them with
+    /// [`validate`](LightClientDeployParams::validate).
+    ///
+    /// A stake table capacity below [`STAKE_TABLE_CAPACITY`] is allowed, but since the prover has
+    /// to be configured to match, it is recorded as a [`Warning::SmallStakeTable`].
+    pub fn with_light_client_params(
+        mut self,
+        params: LightClientDeployParams,
+    ) -> anyhow::Result<Self> {
+        params.validate()?;
+        if params.stake_table_capacity < STAKE_TABLE_CAPACITY {
+            self.warn(Warning::SmallStakeTable {
+                contract: Contract::LightClient,
+                capacity: params.stake_table_capacity,
+            });
+        }
+        self.light_client_params = params;
+        Ok(self)
+    }
+
+    /// The parameters the light client is deployed with.
+    pub fn light_client_params(&self) -> LightClientDeployParams {
+        self.light_client_params
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{
+            deploy_light_client_and_initialize_proxy,
+            test_helpers::{DeployedTestStack, TestStackOptions, TEST_MNEMONIC},
+            Contract,
+        },
+        init_signer, AnvilOptions,
+    };
+    use async_std::sync::Arc;
+    use clap::Parser;
+    use contract_bindings::light_client::LightClient;
+    use ethers::prelude::*;
+    use hotshot_contract_adapter::light_client::ParsedLightClientState;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        params: LightClientDeployParams,
+    }
+
+    #[test]
+    fn test_default_params() {
+        let cli = Cli::try_parse_from(["deploy"]).unwrap();
+        assert_eq!(cli.params, LightClientDeployParams::default());
+        cli.params.validate().unwrap();
+
+        let cli = Cli::try_parse_from([
+            "deploy",
+            "--stake-table-capacity",
+            "4",
+            "--blocks-per-epoch",
+            "10",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.params,
+            LightClientDeployParams {
+                stake_table_capacity: 4,
+                blocks_per_epoch: 10,
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_params() {
+        for (params, constraint) in [
+            (
+                LightClientDeployParams {
+                    stake_table_capacity: 0,
+                    ..Default::default()
+                },
+                "capacity must be at least 1",
+            ),
+            (
+                LightClientDeployParams {
+                    stake_table_capacity: STAKE_TABLE_CAPACITY + 1,
+                    ..Default::default()
+                },
+                "verifying key supports",
+            ),
+            (
+                LightClientDeployParams {
+                    blocks_per_epoch: 0,
+                    ..Default::default()
+                },
+                "blocks per epoch must be at least 1",
+            ),
+        ] {
+            let err = params.validate().unwrap_err().to_string();
+            assert!(err.contains(constraint), "{params:?}: {err}");
+            Contracts::default()
+                .with_light_client_params(params)
+                .unwrap_err();
+        }
+
+        let params = LightClientDeployParams {
+            stake_table_capacity: 1,
+            blocks_per_epoch: 1,
+        };
+        params.validate().unwrap();
+        let contracts = Contracts::default()
+            .with_light_client_params(params)
+            .unwrap();
+        assert_eq!(contracts.light_client_params(), params);
+        assert_eq!(
+            contracts.warnings(),
+            [Warning::SmallStakeTable {
+                contract: Contract::LightClient,
+                capacity: 1,
+            }]
+        );
+
+        // The full capacity is not worth a warning.
+        let contracts = Contracts::default()
+            .with_light_client_params(LightClientDeployParams::default())
+            .unwrap();
+        assert!(contracts.warnings().is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_deploy_with_params() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let params = LightClientDeployParams {
+            blocks_per_epoch: 10,
+            ..Default::default()
+        };
+
+        let mut contracts = Contracts::default()
+            .with_light_client_params(params)
+            .unwrap();
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            l1.address(),
+        )
+        .await
+        .unwrap();
+        let light_client = LightClient::new(proxy, l1.clone());
+        assert_eq!(light_client.blocks_per_epoch().call().await.unwrap(), 10);
+
+        // The mock light client, as deployed for tests by the test stack.
+        let stack = DeployedTestStack::launch(
+            TestStackOptions::default()
+                .rpc_url(anvil.url())
+                .blocks_per_epoch(10),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            stack.contracts.address(Contract::LightClient),
+            Some(stack.light_client.address())
+        );
+        assert_eq!(
+            stack.light_client.blocks_per_epoch().call().await.unwrap(),
+            10
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! Restricting light client state updates to a permissioned prover.
+//!
+//! `LightClient.sol` starts out permissionless: anyone with a valid proof can update the finalized
+//! state. The owner can restrict updates to a single prover with `setPermissionedProver`, which
+//! [`set_permissioned_prover`] does as part of the deployment, while the deployer still owns the
+//! light client. The prover is read back once set, and recorded in the
+//! [deployment report](Contracts::write_report).
+
+use super::{
+    dry_run::DeployMode, explorer::fmt_address, send_with_retry, warnings::Warning, Contract,
+    Contracts,
+};
+use anyhow::{bail, ensure, Context};
+use async_std::sync::Arc;
+use contract_bindings::light_client::LightClient;
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Which prover, if any, the light client should be restricted to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProverConfig {
+    /// The permissioned prover, or `None` to leave the light client permissionless.
+    pub prover: Option<Address>,
+    /// Replace a different prover which is already set on a predeployed light client.
+    pub force: bool,
+}
+
+impl ProverConfig {
+    /// Restrict the light client to `prover`.
+    pub fn permissioned(prover: Address) -> Self {
+        Self {
+            prover: Some(prover),
+            force: false,
+        }
+    }
+}
+
+/// The prover mode of the light client at the end of a deployment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProverInfo {
+    pub contract: Contract,
+    /// The permissioned prover, or `None` if the light client is permissionless.
+    pub prover: Option<Address>,
+    /// The transaction which set the prover, if one was sent in this run.
+    pub tx_hash: Option<H256>,
+}
+
+impl Contracts {
+    /// The prover mode of the light client, if it was configured in this run.
+    pub fn prover_info(&self) -> Option<ProverInfo> {
+        self.prover
+    }
+}
+
+/// Restrict state updates of the light client `contract` to the prover in `config`.
+///
+/// `contract` is [`Contract::LightClientProxy`], or [`Contract::LightClient`] for the mock light
+/// client, and must be owned by the sender of `l1`. Without a prover, nothing is sent and the
+/// light client is left in whatever mode it is in, which for a fresh deployment is permissionless.
+/// A light client which is already restricted to some prover is left that way, with a
+/// [`Warning::ProverKept`]. If the prover is already set, nothing is sent either. If a different
+/// prover is set, which can only happen when the light client was predeployed, it is replaced only
+/// if [`force`](ProverConfig::force) is set, with a [`Warning::ProverReplaced`].
+///
+/// The transaction is retried according to the [retry policy](Contracts::with_retry_policy) of
+/// `contracts`, and once it succeeds the prover is read back to verify it.
+pub async fn set_permissioned_prover<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    contract: Contract,
+    config: ProverConfig,
+) -> anyhow::Result<()> {
+    let address = contracts
+        .address(contract)
+        .with_context(|| format!("cannot set permissioned prover of {contract:?}, not deployed"))?;
+    let light_client = LightClient::new(address, l1.clone());
+    let explorer = contracts.explorer().cloned();
+
+    let Some(prover) = config.prover else {
+        if contracts.mode() == DeployMode::DryRun {
+            tracing::info!("{contract:?} will be left in its current prover mode");
+            return Ok(());
+        }
+        let current = read_prover(&light_client).await?;
+        match current {
+            Some(prover) => contracts.warn(Warning::ProverKept { contract, prover }),
+            None => tracing::info!(
+                "no permissioned prover given, {contract:?} is in permissionless mode"
+            ),
+        }
+        contracts.prover = Some(ProverInfo {
+            contract,
+            prover: current,
+            tx_hash: None,
+        });
+        return Ok(());
+    };
+    ensure!(
+        !prover.is_zero(),
+        "the permissioned prover of {contract:?} cannot be the zero address"
+    );
+
+    let tx = light_client.set_permissioned_prover(prover).tx;
+    if contracts.mode() == DeployMode::DryRun {
+        tracing::info!("would set permissioned prover of {contract:?} to {prover:#x}");
+        if contracts.unsigned.is_some() {
+            let gas = l1.estimate_gas(&tx, None).await.ok();
+            contracts
+                .write_unsigned(&format!("{contract:?}-set-prover"), &*l1, tx, gas)
+                .await?;
+        }
+        return Ok(());
+    }
+
+    match read_prover(&light_client).await? {
+        Some(current) if current != prover && !config.force => bail!(
+            "{contract:?} is already restricted to prover {current:#x}; refusing to replace it \
+             with {prover:#x} without --force-prover-update"
+        ),
+        Some(current) if current != prover => contracts.warn(Warning::ProverReplaced {
+            contract,
+            old: current,
+            new: prover,
+        }),
+        _ => {}
+    }
+
+    let gas = contracts.gas_config;
+    let explorer = explorer.as_ref();
+    let (light_client, tx) = (&light_client, &tx);
+    let what = format!("{contract:?} prover update");
+    let receipt = send_with_retry(&*l1, contracts, &what, |_| async move {
+        if read_prover(light_client).await? == Some(prover) {
+            tracing::info!(
+                "{contract:?} is restricted to prover {}",
+                fmt_address(explorer, prover)
+            );
+            return Ok(None);
+        }
+        tracing::info!("setting permissioned prover of {contract:?} to {prover:#x}");
+        let mut tx = tx.clone();
+        gas.apply(&mut tx);
+        Ok::<_, anyhow::Error>(Some(tx))
+    })
+    .await?;
+    let tx_hash = receipt.map(|receipt| receipt.transaction_hash);
+
+    let current = read_prover(light_client).await?;
+    ensure!(
+        current == Some(prover),
+        "permissioned prover of {contract:?} was not set: prover is {}, expected {prover:#x}",
+        current.map_or("none".into(), |current| format!("{current:#x}"))
+    );
+    contracts.prover = Some(ProverInfo {
+        contract,
+        prover: Some(prover),
+        tx_hash,
+    });
+    Ok(())
+}
+
+/// The permissioned prover of `light_client`, or `None` if it is permissionless.
+async fn read_prover<M: Middleware + 'static>(
+    light_client: &LightClient<M>,
+) -> anyhow::Result<Option<Address>> {
+    let enabled = light_client
+        .permissioned_prover_enabled()
+        .call()
+        .await
+        .context("error reading permissioned prover mode")?;
+    if !enabled {
+        return Ok(None);
+    }
+    let prover = light_client
+        .permissioned_prover()
+        .call()
+        .await
+        .context("error reading permissioned prover")?;
+    Ok(Some(prover))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{deploy_light_client_and_initialize_proxy, test_helpers::TEST_MNEMONIC},
+        init_signer, AnvilOptions,
+    };
+    use hotshot_contract_adapter::light_client::ParsedLightClientState;
+
+    #[async_std::test]
+    async fn test_set_permissioned_prover() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let deployer = l1.address();
+        let prover = Address::repeat_byte(1);
+
+        let mut contracts = Contracts::default();
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            deployer,
+        )
+        .await
+        .unwrap();
+        let light_client = LightClient::new(proxy, l1.clone());
+
+        // Without a prover, the light client stays permissionless.
+        let nonce = l1.get_transaction_count(deployer, None).await.unwrap();
+        set_permissioned_prover(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            ProverConfig::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(read_prover(&light_client).await.unwrap(), None);
+        assert_eq!(
+            contracts.prover_info(),
+            Some(ProverInfo {
+                contract: Contract::LightClientProxy,
+                prover: None,
+                tx_hash: None,
+            })
+        );
+
+        // Set a prover.
+        set_permissioned_prover(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            ProverConfig::permissioned(prover),
+        )
+        .await
+        .unwrap();
+        assert_eq!(read_prover(&light_client).await.unwrap(), Some(prover));
+        let info = contracts.prover_info().unwrap();
+        assert_eq!(info.prover, Some(prover));
+        assert!(info.tx_hash.is_some());
+        assert_eq!(
+            l1.get_transaction_count(deployer, None).await.unwrap(),
+            nonce + 1
+        );
+
+        // Setting the same prover again is a no-op.
+        set_permissioned_prover(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            ProverConfig::permissioned(prover),
+        )
+        .await
+        .unwrap();
+        assert_eq!(contracts.prover_info().unwrap().tx_hash, None);
+        assert_eq!(
+            l1.get_transaction_count(deployer, None).await.unwrap(),
+            nonce + 1
+        );
+
+        // A different prover is only set when forced.
+        let other = Address::repeat_byte(2);
+        let err = set_permissioned_prover(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            ProverConfig::permissioned(other),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("--force-prover-update"), "{err}");
+        assert_eq!(read_prover(&light_client).await.unwrap(), Some(prover));
+        set_permissioned_prover(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            ProverConfig {
+                prover: Some(other),
+                force: true,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(read_prover(&light_client).await.unwrap(), Some(other));
+        assert_eq!(
+            contracts.warnings(),
+            [Warning::ProverReplaced {
+                contract: Contract::LightClientProxy,
+                old: prover,
+                new: other,
+            }]
+        );
+
+        // Without a prover, a permissioned light client is left as is, with a warning.
+        set_permissioned_prover(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            ProverConfig::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(read_prover(&light_client).await.unwrap(), Some(other));
+        assert_eq!(contracts.prover_info().unwrap().prover, Some(other));
+        assert_eq!(
+            contracts.warnings()[1],
+            Warning::ProverKept {
+                contract: Contract::LightClientProxy,
+                prover: other,
+            }
+        );
+
+        // The zero address is refused.
+        let err = set_permissioned_prover(
+            l1.clone(),
+            &mut contracts,
+            Contract::LightClientProxy,
+            ProverConfig::permissioned(Address::zero()),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("zero address"), "{err}");
+    }
+}
@@ -0,0 +1,373 @@
+//! Waiting for transactions to be mined.
+//!
+//! Some providers aggressively prune their view of pending transactions, so a transaction can
+//! disappear from `eth_getTransactionByHash` and still be mined later. Treating such a transaction
+//! as dropped as soon as it disappears, as ethers' `PendingTransaction` does, leads to spurious
+//! redeployments. Instead, [`wait_for_receipt`] polls for the receipt, the transaction itself, and
+//! the sender's nonce, and feeds what it sees to a [`DropDetector`], which only declares the
+//! transaction dropped when its nonce has been consumed by a different transaction, or when it has
+//! not been seen at all for a configurable number of blocks.
+//!
+//! A mined transaction is only reported once it has the configured number of confirmations. If it
+//! is reorged out while we wait, we go back to waiting for it to be mined, so that a transaction
+//! which is dropped in the reorg is detected, and can be resent, like any other dropped
+//! transaction.
+
+use async_std::task::sleep;
+use derive_more::Display;
+use ethers::prelude::*;
+use std::time::Duration;
+
+/// How to wait for transactions to be mined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReceiptPolling {
+    /// The delay between polls of the provider.
+    pub interval: Duration,
+    /// The number of blocks a transaction may go without any trace before it is considered
+    /// dropped.
+    pub drop_after_blocks: u64,
+    /// The number of blocks, including the one the transaction was mined in, to wait for before a
+    /// transaction is considered mined.
+    ///
+    /// With 0, the transaction is considered mined as soon as the provider returns a receipt, which
+    /// is useful for in-memory test providers that never produce further blocks.
+    pub confirmations: usize,
+    /// How long to wait for a transaction to be mined and confirmed before giving up on it.
+    pub timeout: Duration,
+}
+
+impl Default for ReceiptPolling {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            drop_after_blocks: 10,
+            confirmations: 1,
+            timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Whether a transaction mined in block `mined` has `confirmations` confirmations at block
+/// `latest`.
+fn is_confirmed(mined: U64, latest: U64, confirmations: usize) -> bool {
+    confirmations == 0 || latest + 1 >= mined + confirmations
+}
+
+/// Whether a transaction first seen mined with `original` is still in the same block, given its
+/// `current` receipt.
+fn still_included(original: &TransactionReceipt, current: Option<&TransactionReceipt>) -> bool {
+    current.is_some_and(|current| current.block_hash == original.block_hash)
+}
+
+/// What one poll of the provider found out about a pending transaction.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Observation {
+    /// The latest block number.
+    pub block: U64,
+    /// The receipt of the transaction, if it has been mined.
+    pub receipt: Option<TransactionReceipt>,
+    /// The nonce of the transaction, if the provider knows about the transaction.
+    pub tx_nonce: Option<U256>,
+    /// The number of transactions the sender has mined, if the sender is known.
+    pub sender_nonce: Option<U256>,
+}
+
+/// Why a transaction was considered dropped.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq)]
+pub(super) enum DropReason {
+    /// The nonce of the transaction was used by a different transaction.
+    #[display(fmt = "nonce {} was consumed by a different transaction", _0)]
+    NonceConsumed(U256),
+    /// The transaction has not been seen for too many blocks.
+    #[display(fmt = "no trace of the transaction for {} blocks", _0)]
+    NoTrace(u64),
+}
+
+/// The conclusion drawn from the observations so far.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Verdict {
+    Mined(TransactionReceipt),
+    Pending,
+    Dropped(DropReason),
+}
+
+/// Decides, from a sequence of [`Observation`]s, whether a transaction has been mined or dropped.
+#[derive(Clone, Debug)]
+pub(super) struct DropDetector {
+    /// The nonce of the transaction, once known.
+    nonce: Option<U256>,
+    /// The last block at which we saw any trace of the transaction.
+    last_seen: Option<U64>,
+    drop_after_blocks: u64,
+}
+
+impl DropDetector {
+    /// Watch a transaction with `nonce`, if known.
+    pub(super) fn new(nonce: Option<U256>, drop_after_blocks: u64) -> Self {
+        Self {
+            nonce,
+            last_seen: None,
+            drop_after_blocks,
+        }
+    }
+
+    pub(super) fn observe(&mut self, obs: Observation) -> Verdict {
+        if let Some(receipt) = obs.receipt {
+            return Verdict::Mined(receipt);
+        }
+        if let Some(nonce) = obs.tx_nonce {
+            // The provider still knows about the transaction, so it is pending, whatever else we
+            // observe: the sender nonce may just be ahead of the receipt index.
+            self.nonce = Some(nonce);
+            self.last_seen = Some(obs.block);
+            return Verdict::Pending;
+        }
+        if let (Some(nonce), Some(sender_nonce)) = (self.nonce, obs.sender_nonce) {
+            // With no receipt and no transaction, a mined nonce means some other transaction took
+            // our place.
+            if sender_nonce > nonce {
+                return Verdict::Dropped(DropReason::NonceConsumed(nonce));
+            }
+        }
+        // The transaction was just sent, so it counts as seen at the first observation.
+        let last_seen = *self.last_seen.get_or_insert(obs.block);
+        let blocks = obs.block.saturating_sub(last_seen).as_u64();
+        if blocks >= self.drop_after_blocks {
+            Verdict::Dropped(DropReason::NoTrace(blocks))
+        } else {
+            Verdict::Pending
+        }
+    }
+}
+
+/// Wait for the transaction `hash` to be mined, with the configured number of confirmations.
+///
+/// `sender` and `nonce` describe the transaction, if known; they allow a replaced transaction to be
+/// detected quickly. Returns [`None`] if the transaction was dropped, including if it was dropped
+/// after being reorged out while waiting for confirmations. This does not time out on its own;
+/// callers should wrap it in a timeout of [`ReceiptPolling::timeout`].
+pub(super) async fn wait_for_receipt<M: Middleware + 'static>(
+    client: &M,
+    hash: H256,
+    sender: Option<Address>,
+    nonce: Option<U256>,
+    polling: ReceiptPolling,
+) -> Result<Option<TransactionReceipt>, M::Error> {
+    let mut detector = DropDetector::new(nonce, polling.drop_after_blocks);
+    loop {
+        let block = client.get_block_number().await?;
+        let receipt = client.get_transaction_receipt(hash).await?;
+        let tx_nonce = if receipt.is_none() {
+            client.get_transaction(hash).await?.map(|tx| tx.nonce)
+        } else {
+            None
+        };
+        let sender_nonce = match sender {
+            Some(sender) if receipt.is_none() && tx_nonce.is_none() => {
+                Some(client.get_transaction_count(sender, None).await?)
+            }
+            _ => None,
+        };
+        match detector.observe(Observation {
+            block,
+            receipt,
+            tx_nonce,
+            sender_nonce,
+        }) {
+            Verdict::Mined(receipt) => {
+                if wait_for_confirmations(client, &receipt, polling).await? {
+                    return Ok(Some(receipt));
+                }
+                tracing::warn!(
+                    "transaction {hash:#x} was reorged out of block {}, waiting for it to be \
+                     mined again",
+                    receipt.block_number.unwrap_or_default()
+                );
+                detector = DropDetector::new(nonce, polling.drop_after_blocks);
+            }
+            Verdict::Pending => sleep(polling.interval).await,
+            Verdict::Dropped(reason) => {
+                tracing::warn!("transaction {hash:#x} dropped: {reason}");
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Wait for the transaction mined with `receipt` to have the configured number of confirmations.
+///
+/// Returns `false` if the transaction is reorged out in the meantime.
+async fn wait_for_confirmations<M: Middleware + 'static>(
+    client: &M,
+    receipt: &TransactionReceipt,
+    polling: ReceiptPolling,
+) -> Result<bool, M::Error> {
+    // With a single confirmation, the receipt we have is all the confirmation there is.
+    let Some(mined) = receipt.block_number.filter(|_| polling.confirmations > 1) else {
+        return Ok(true);
+    };
+    loop {
+        let latest = client.get_block_number().await?;
+        let current = client
+            .get_transaction_receipt(receipt.transaction_hash)
+            .await?;
+        if !still_included(receipt, current.as_ref()) {
+            return Ok(false);
+        }
+        if is_confirmed(mined, latest, polling.confirmations) {
+            return Ok(true);
+        }
+        sleep(polling.interval).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn receipt() -> TransactionReceipt {
+        TransactionReceipt {
+            transaction_hash: H256::repeat_byte(1),
+            ..Default::default()
+        }
+    }
+
+    fn at(block: u64) -> Observation {
+        Observation {
+            block: block.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_confirmations() {
+        // Mined in the latest block counts as one confirmation.
+        assert!(is_confirmed(10.into(), 10.into(), 1));
+        assert!(!is_confirmed(10.into(), 10.into(), 2));
+        assert!(is_confirmed(10.into(), 11.into(), 2));
+        assert!(!is_confirmed(10.into(), 11.into(), 3));
+        // Zero confirmations never waits, even if the provider is behind.
+        assert!(is_confirmed(10.into(), 9.into(), 0));
+    }
+
+    #[test]
+    fn test_still_included() {
+        let original = TransactionReceipt {
+            block_hash: Some(H256::repeat_byte(2)),
+            ..receipt()
+        };
+        assert!(still_included(&original, Some(&original)));
+        // Reorged out, and not mined again yet.
+        assert!(!still_included(&original, None));
+        // Mined again, in a different block.
+        let remined = TransactionReceipt {
+            block_hash: Some(H256::repeat_byte(3)),
+            ..original.clone()
+        };
+        assert!(!still_included(&original, Some(&remined)));
+    }
+
+    #[test]
+    fn test_mined() {
+        let mut detector = DropDetector::new(Some(0.into()), 3);
+        assert_eq!(detector.observe(at(10)), Verdict::Pending);
+        assert_eq!(
+            detector.observe(Observation {
+                receipt: Some(receipt()),
+                ..at(11)
+            }),
+            Verdict::Mined(receipt())
+        );
+    }
+
+    #[test]
+    fn test_pruned_then_mined() {
+        // The provider forgets the transaction for a while, but not for long enough to give up on
+        // it, and then it is mined.
+        let mut detector = DropDetector::new(Some(5.into()), 3);
+        let seen = |block| Observation {
+            tx_nonce: Some(5.into()),
+            ..at(block)
+        };
+        let unseen = |block| Observation {
+            sender_nonce: Some(5.into()),
+            ..at(block)
+        };
+        assert_eq!(detector.observe(seen(10)), Verdict::Pending);
+        assert_eq!(detector.observe(unseen(11)), Verdict::Pending);
+        assert_eq!(detector.observe(unseen(12)), Verdict::Pending);
+        assert_eq!(detector.observe(seen(13)), Verdict::Pending);
+        // The gap is measured from the last sighting.
+        assert_eq!(detector.observe(unseen(15)), Verdict::Pending);
+        assert_eq!(
+            detector.observe(Observation {
+                receipt: Some(receipt()),
+                ..at(16)
+            }),
+            Verdict::Mined(receipt())
+        );
+    }
+
+    #[test]
+    fn test_no_trace() {
+        let mut detector = DropDetector::new(None, 3);
+        assert_eq!(detector.observe(at(10)), Verdict::Pending);
+        assert_eq!(detector.observe(at(10)), Verdict::Pending);
+        assert_eq!(detector.observe(at(12)), Verdict::Pending);
+        assert_eq!(
+            detector.observe(at(13)),
+            Verdict::Dropped(DropReason::NoTrace(3))
+        );
+    }
+
+    #[test]
+    fn test_nonce_consumed() {
+        let mut detector = DropDetector::new(Some(5.into()), 100);
+        // Without the receipt or the transaction, an advanced nonce means we were replaced.
+        assert_eq!(
+            detector.observe(Observation {
+                sender_nonce: Some(6.into()),
+                ..at(10)
+            }),
+            Verdict::Dropped(DropReason::NonceConsumed(5.into()))
+        );
+
+        // But not while the provider still knows about our transaction.
+        let mut detector = DropDetector::new(Some(5.into()), 100);
+        assert_eq!(
+            detector.observe(Observation {
+                tx_nonce: Some(5.into()),
+                sender_nonce: Some(6.into()),
+                ..at(10)
+            }),
+            Verdict::Pending
+        );
+    }
+
+    #[test]
+    fn test_nonce_learned_from_tx() {
+        // If we don't know the nonce up front, we learn it when we see the transaction.
+        let mut detector = DropDetector::new(None, 100);
+        assert_eq!(
+            detector.observe(Observation {
+                sender_nonce: Some(6.into()),
+                ..at(10)
+            }),
+            Verdict::Pending
+        );
+        assert_eq!(
+            detector.observe(Observation {
+                tx_nonce: Some(6.into()),
+                ..at(11)
+            }),
+            Verdict::Pending
+        );
+        assert_eq!(
+            detector.observe(Observation {
+                sender_nonce: Some(7.into()),
+                ..at(12)
+            }),
+            Verdict::Dropped(DropReason::NonceConsumed(6.into()))
+        );
+    }
+}
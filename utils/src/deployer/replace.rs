@@ -0,0 +1,537 @@
+//! Replacing transactions which are stuck in the mempool.
+//!
+//! A transaction whose fees were right when it was sent can get stuck if the base fee rises
+//! afterwards. Waiting out the [pending timeout](ReceiptPolling::timeout) and resending does not
+//! help, since nodes only accept a transaction with the same nonce if it pays more. So with a
+//! [`tx_timeout`](GasConfig::tx_timeout), a deployment transaction which is not mined in time is
+//! rebroadcast with its fees raised by [`fee_bump_percent`](GasConfig::fee_bump_percent), up to
+//! the fee cap. The replacement has the same nonce and the same payload, so whichever of the
+//! transactions is mined, the contract is deployed exactly once, at the same address.
+//!
+//! A stuck transaction which should not be mined at all can be cleared with
+//! [`cancel_transaction`], which replaces it with a zero-value transfer to the sender.
+
+use super::{
+    await_tx, broadcast_tx, check_receipt,
+    explorer::{fmt_tx, Explorer},
+    fees::{fmt_gwei, GasConfig},
+    receipt::{wait_for_receipt, ReceiptPolling},
+    send_with_retry, Contracts, SendError,
+};
+use anyhow::{anyhow, bail, ensure, Context};
+use async_std::{future::timeout, sync::Arc};
+use ethers::{prelude::*, types::transaction::eip2718::TypedTransaction};
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+/// The fees a transaction pays per gas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Fees {
+    Legacy {
+        gas_price: U256,
+    },
+    Eip1559 {
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    },
+}
+
+impl Fees {
+    /// The fees paid by `tx`, if the provider reported them.
+    fn of(tx: &Transaction) -> Option<Self> {
+        match (tx.max_fee_per_gas, tx.max_priority_fee_per_gas) {
+            (Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) => Some(Self::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            }),
+            _ => tx.gas_price.map(|gas_price| Self::Legacy { gas_price }),
+        }
+    }
+
+    /// These fees raised by `percent`, but not above `cap`.
+    ///
+    /// Returns `None` if the cap leaves no room to raise the fees at all.
+    fn bump(self, percent: u64, cap: Option<U256>) -> Option<Self> {
+        let raise = |fee: U256| {
+            let raised = (fee * (100 + percent) + 99) / 100;
+            raised.max(fee + 1)
+        };
+        let limit = |fee: U256| cap.map_or(fee, |cap| fee.min(cap));
+        match self {
+            Self::Legacy { gas_price } => {
+                let raised = limit(raise(gas_price));
+                (raised > gas_price).then_some(Self::Legacy { gas_price: raised })
+            }
+            Self::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => {
+                let raised = limit(raise(max_fee_per_gas));
+                (raised > max_fee_per_gas).then_some(Self::Eip1559 {
+                    max_fee_per_gas: raised,
+                    max_priority_fee_per_gas: raise(max_priority_fee_per_gas).min(raised),
+                })
+            }
+        }
+    }
+
+    /// Set these fees on `tx`.
+    fn set(self, tx: &mut TypedTransaction) {
+        match (self, tx) {
+            (
+                Self::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                },
+                TypedTransaction::Eip1559(tx),
+            ) => {
+                tx.max_fee_per_gas = Some(max_fee_per_gas);
+                tx.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+            }
+            (Self::Legacy { gas_price }, tx)
+            | (
+                Self::Eip1559 {
+                    max_fee_per_gas: gas_price,
+                    ..
+                },
+                tx,
+            ) => tx.set_gas_price(gas_price),
+        }
+    }
+
+    fn describe(self) -> String {
+        match self {
+            Self::Legacy { gas_price } => format!("gas price {} gwei", fmt_gwei(gas_price)),
+            Self::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => format!(
+                "max fee {} gwei, priority fee {} gwei",
+                fmt_gwei(max_fee_per_gas),
+                fmt_gwei(max_priority_fee_per_gas)
+            ),
+        }
+    }
+}
+
+/// Send `tx` once and wait for it to be mined, replacing it with higher fees whenever it is not
+/// mined within [`GasConfig::tx_timeout`].
+///
+/// Without a `tx_timeout`, or if the nonce of `tx` is not known, this just waits for `tx`. In any
+/// case, this gives up once [`ReceiptPolling::timeout`] has passed since `tx` was sent. A
+/// replacement which the node rejects, for example because the original was mined in the
+/// meantime, is not an error: we keep waiting for the transactions already sent.
+pub(super) async fn send_replaceable<M: Middleware + 'static>(
+    client: &M,
+    tx: TypedTransaction,
+    gas: GasConfig,
+    polling: ReceiptPolling,
+    explorer: Option<&Explorer>,
+    kind: &str,
+    on_submit: &(dyn Fn(H256) + Sync),
+) -> Result<TransactionReceipt, SendError> {
+    let sender = tx.from().copied().or_else(|| client.default_sender());
+    let nonce = tx.nonce().copied();
+    let mut tx_hash = broadcast_tx(client, tx.clone(), kind).await?;
+    on_submit(tx_hash);
+    let (Some(tx_timeout), Some(_)) = (gas.tx_timeout, nonce) else {
+        return await_tx(client, tx_hash, sender, nonce, polling, explorer, kind).await;
+    };
+
+    let deadline = Instant::now() + polling.timeout;
+    let mut sent = vec![tx_hash];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let wait = wait_for_receipt(client, tx_hash, sender, nonce, polling);
+        match timeout(tx_timeout.min(remaining), wait).await {
+            Ok(Ok(None)) => {
+                // The latest transaction was dropped, which happens when an earlier one took its
+                // nonce.
+                for &earlier in &sent {
+                    if earlier == tx_hash {
+                        continue;
+                    }
+                    if let Ok(Some(_)) = client.get_transaction_receipt(earlier).await {
+                        tracing::info!(
+                            "{kind} was mined before its replacement (tx {})",
+                            fmt_tx(explorer, earlier)
+                        );
+                        let wait = wait_for_receipt(client, earlier, sender, nonce, polling);
+                        return await_within(wait, remaining, earlier, explorer, kind).await;
+                    }
+                }
+                return check_receipt(Ok::<_, ProviderError>(None), tx_hash, explorer, kind);
+            }
+            Ok(res) => return check_receipt(res, tx_hash, explorer, kind),
+            Err(_) if remaining <= tx_timeout => {
+                return Err(SendError::Transient(anyhow!(
+                    "{kind} not mined within {} seconds (tx {})",
+                    polling.timeout.as_secs(),
+                    fmt_tx(explorer, tx_hash)
+                )));
+            }
+            Err(_) => {}
+        }
+
+        let pending = match client.get_transaction(tx_hash).await {
+            Ok(Some(pending)) => pending,
+            // The node has forgotten the transaction, so there is nothing to replace for now. The
+            // drop detector decides whether it is gone for good.
+            Ok(None) => continue,
+            Err(err) => {
+                tracing::warn!("error fetching stuck {kind} (tx {tx_hash:#x}): {err}");
+                continue;
+            }
+        };
+        let Some(fees) =
+            Fees::of(&pending).and_then(|fees| fees.bump(gas.fee_bump_percent, gas.cap()))
+        else {
+            tracing::warn!(
+                "{kind} not mined within {tx_timeout:?} (tx {}), but its fees cannot be raised \
+                 above the fee cap",
+                fmt_tx(explorer, tx_hash)
+            );
+            continue;
+        };
+        let mut replacement = tx.clone();
+        replacement.set_gas(pending.gas);
+        fees.set(&mut replacement);
+        tracing::warn!(
+            "{kind} not mined within {tx_timeout:?} (tx {}), replacing it with {}",
+            fmt_tx(explorer, tx_hash),
+            fees.describe()
+        );
+        match broadcast_tx(client, replacement, kind).await {
+            Ok(hash) => {
+                on_submit(hash);
+                sent.push(hash);
+                tx_hash = hash;
+            }
+            Err(SendError::Transient(err) | SendError::Fatal(err)) => {
+                tracing::warn!("failed to replace {kind}: {err:#}");
+            }
+        }
+    }
+}
+
+/// Wait for `wait`, the receipt of `tx_hash`, for at most `limit`.
+async fn await_within<E: MiddlewareError + 'static>(
+    wait: impl Future<Output = Result<Option<TransactionReceipt>, E>>,
+    limit: Duration,
+    tx_hash: H256,
+    explorer: Option<&Explorer>,
+    kind: &str,
+) -> Result<TransactionReceipt, SendError> {
+    match timeout(limit, wait).await {
+        Ok(res) => check_receipt(res, tx_hash, explorer, kind),
+        Err(_) => Err(SendError::Transient(anyhow!(
+            "{kind} not confirmed in time (tx {})",
+            fmt_tx(explorer, tx_hash)
+        ))),
+    }
+}
+
+/// Clear the stuck transaction with `nonce` sent by `l1`.
+///
+/// The transaction is replaced with a zero-value transfer from the sender to itself, sent with the
+/// fee policy of `contracts`. A pending transaction cannot be looked up by its nonce, so the fees
+/// of the cancellation start at the current estimate raised by
+/// [`fee_bump_percent`](GasConfig::fee_bump_percent), and are raised again by the same percentage,
+/// up to the fee cap, each time the node rejects the cancellation, for as many attempts as the
+/// [retry policy](Contracts::with_retry_policy) allows.
+///
+/// Returns the receipt of the cancellation. Fails if nothing is pending at `nonce`, or if the
+/// stuck transaction is mined before it can be cancelled.
+pub async fn cancel_transaction<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &Contracts,
+    nonce: U256,
+) -> anyhow::Result<TransactionReceipt> {
+    let sender = l1
+        .default_sender()
+        .context("cancelling a transaction requires a sender")?;
+    let mined = l1
+        .get_transaction_count(sender, None)
+        .await
+        .context("error fetching nonce")?;
+    ensure!(
+        nonce >= mined,
+        "nonce {nonce} of {sender:#x} was already mined, so there is nothing to cancel"
+    );
+    let pending = l1
+        .get_transaction_count(sender, Some(BlockNumber::Pending.into()))
+        .await
+        .context("error fetching pending nonce")?;
+    ensure!(
+        nonce < pending,
+        "no transaction of {sender:#x} is pending with nonce {nonce}; the next nonce is {pending}"
+    );
+
+    let gas = contracts.gas_config.for_chain(&*l1).await;
+    let estimate = match gas.legacy_gas_price {
+        Some(gas_price) => Fees::Legacy { gas_price },
+        None if gas.legacy => Fees::Legacy {
+            gas_price: l1
+                .get_gas_price()
+                .await
+                .context("error estimating gas price")?,
+        },
+        None => {
+            let (max_fee_per_gas, max_priority_fee_per_gas) = l1
+                .estimate_eip1559_fees(None)
+                .await
+                .context("error estimating fees")?;
+            Fees::Eip1559 {
+                max_fee_per_gas: gas.max_fee_per_gas.unwrap_or(max_fee_per_gas),
+                max_priority_fee_per_gas: gas
+                    .max_priority_fee_per_gas
+                    .unwrap_or(max_priority_fee_per_gas),
+            }
+        }
+    };
+    let first = estimate
+        .bump(gas.fee_bump_percent, gas.cap())
+        .unwrap_or(estimate);
+    let mut tx: TypedTransaction = TransactionRequest::new()
+        .from(sender)
+        .to(sender)
+        .value(0)
+        .nonce(nonce)
+        .into();
+    gas.apply(&mut tx);
+    tx.set_gas(21_000);
+
+    let kind = format!("cancellation of nonce {nonce}");
+    let (client, tx) = (&*l1, &tx);
+    let receipt = send_with_retry(client, contracts, &kind, |attempt| async move {
+        // Each failed attempt raises the fees of the next one, unless the stuck transaction has
+        // been mined in the meantime.
+        let mut fees = first;
+        if attempt > 1 {
+            if client.get_transaction_count(sender, None).await? > nonce {
+                bail!(
+                    "nonce {nonce} was mined by another transaction before it could be cancelled"
+                );
+            }
+            for _ in 1..attempt {
+                fees = fees
+                    .bump(gas.fee_bump_percent, gas.cap())
+                    .context("the fees of the cancellation cannot be raised above the fee cap")?;
+            }
+        }
+        tracing::info!(
+            "cancelling nonce {nonce} of {sender:#x} with {}",
+            fees.describe()
+        );
+        let mut tx = tx.clone();
+        fees.set(&mut tx);
+        Ok::<_, anyhow::Error>(Some(tx))
+    })
+    .await?
+    .context("cancellation was never sent")?;
+    tracing::info!(
+        "cancelled nonce {nonce} of {sender:#x} (tx {})",
+        fmt_tx(contracts.explorer(), receipt.transaction_hash)
+    );
+    Ok(receipt)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{test_helpers::TEST_MNEMONIC, Contract, DeployEvent},
+        init_signer, AnvilOptions,
+    };
+    use async_std::task::{sleep, spawn};
+    use contract_bindings::hot_shot::HotShot;
+    use ethers::utils::get_contract_address;
+
+    #[test]
+    fn test_bump_fees() {
+        let legacy = Fees::Legacy {
+            gas_price: 100.into(),
+        };
+        assert_eq!(
+            legacy.bump(15, None),
+            Some(Fees::Legacy {
+                gas_price: 115.into()
+            })
+        );
+        // Capped, but still raised.
+        assert_eq!(
+            legacy.bump(15, Some(105.into())),
+            Some(Fees::Legacy {
+                gas_price: 105.into()
+            })
+        );
+        // No room left under the cap.
+        assert_eq!(legacy.bump(15, Some(100.into())), None);
+
+        let fees = Fees::Eip1559 {
+            max_fee_per_gas: 1000.into(),
+            max_priority_fee_per_gas: 10.into(),
+        };
+        assert_eq!(
+            fees.bump(10, None),
+            Some(Fees::Eip1559 {
+                max_fee_per_gas: 1100.into(),
+                max_priority_fee_per_gas: 11.into(),
+            })
+        );
+        // The priority fee never exceeds the fee cap.
+        assert_eq!(
+            Fees::Eip1559 {
+                max_fee_per_gas: 100.into(),
+                max_priority_fee_per_gas: 100.into(),
+            }
+            .bump(50, Some(120.into())),
+            Some(Fees::Eip1559 {
+                max_fee_per_gas: 120.into(),
+                max_priority_fee_per_gas: 120.into(),
+            })
+        );
+        // Tiny fees are still raised, rounding up.
+        assert_eq!(
+            Fees::Legacy {
+                gas_price: 1.into()
+            }
+            .bump(10, None),
+            Some(Fees::Legacy {
+                gas_price: 2.into()
+            })
+        );
+    }
+
+    #[async_std::test]
+    async fn test_replace_stuck_deployment() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let sender = l1.address();
+        let nonce = l1.get_transaction_count(sender, None).await.unwrap();
+
+        // Stop mining, so the deployment is stuck until it has been replaced.
+        let provider = anvil.provider();
+        provider
+            .request::<_, serde_json::Value>("evm_setAutomine", [false])
+            .await
+            .unwrap();
+        let mut contracts = Contracts::default()
+            .with_receipt_polling(ReceiptPolling {
+                interval: Duration::from_millis(100),
+                ..Default::default()
+            })
+            .with_gas_config(GasConfig {
+                max_priority_fee_per_gas: Some(1.into()),
+                tx_timeout: Some(Duration::from_millis(500)),
+                ..Default::default()
+            });
+        let events = contracts.subscribe();
+        let miner = spawn(async move {
+            let mut hashes = vec![];
+            while hashes.len() < 2 {
+                if let DeployEvent::TxSubmitted { tx_hash, .. } = events.recv().await.unwrap() {
+                    hashes.push(tx_hash);
+                }
+            }
+            provider
+                .request::<_, serde_json::Value>("evm_mine", None::<()>)
+                .await
+                .unwrap();
+            (hashes, events)
+        });
+
+        let address = contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+        let (mut hashes, events) = miner.await;
+        while let Ok(event) = events.try_recv() {
+            if let DeployEvent::TxSubmitted { tx_hash, .. } = event {
+                hashes.push(tx_hash);
+            }
+        }
+
+        // The replacement was mined, at the original nonce, so the contract exists exactly once.
+        let receipt = contracts.receipt(Contract::HotShot).unwrap();
+        assert_eq!(Some(&receipt.transaction_hash), hashes.last());
+        assert_ne!(hashes[0], hashes[1]);
+        assert_eq!(address, get_contract_address(sender, nonce));
+        assert_eq!(
+            l1.get_transaction_count(sender, None).await.unwrap(),
+            nonce + 1
+        );
+        assert_eq!(l1.get_transaction_receipt(hashes[0]).await.unwrap(), None);
+        let replacement = l1
+            .get_transaction(receipt.transaction_hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(replacement.max_priority_fee_per_gas.unwrap() > 1.into());
+    }
+
+    #[async_std::test]
+    async fn test_cancel_transaction() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let sender = l1.address();
+        let contracts = Contracts::default().with_receipt_polling(ReceiptPolling {
+            interval: Duration::from_millis(100),
+            ..Default::default()
+        });
+
+        // Nothing is pending yet.
+        let nonce = l1.get_transaction_count(sender, None).await.unwrap();
+        let err = cancel_transaction(l1.clone(), &contracts, nonce)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no transaction"), "{err:#}");
+
+        // Get a transaction stuck.
+        let provider = anvil.provider();
+        provider
+            .request::<_, serde_json::Value>("evm_setAutomine", [false])
+            .await
+            .unwrap();
+        let stuck = l1
+            .send_transaction(
+                TransactionRequest::new()
+                    .to(Address::repeat_byte(1))
+                    .value(1),
+                None,
+            )
+            .await
+            .unwrap()
+            .tx_hash();
+
+        // Mine blocks in the background, once the cancellation has been sent.
+        spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            while provider
+                .request::<_, serde_json::Value>("evm_mine", None::<()>)
+                .await
+                .is_ok()
+            {
+                sleep(Duration::from_millis(200)).await;
+            }
+        });
+        let receipt = cancel_transaction(l1.clone(), &contracts, nonce)
+            .await
+            .unwrap();
+        assert_eq!(receipt.from, sender);
+        assert_eq!(receipt.to, Some(sender));
+        assert_eq!(l1.get_transaction_receipt(stuck).await.unwrap(), None);
+        assert_eq!(
+            l1.get_transaction_count(sender, None).await.unwrap(),
+            nonce + 1
+        );
+
+        // The nonce cannot be cancelled again.
+        let err = cancel_transaction(l1.clone(), &contracts, nonce)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("already mined"), "{err:#}");
+    }
+}
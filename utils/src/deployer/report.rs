@@ -0,0 +1,336 @@
+//! Reports of the transaction which deployed each contract.
+//!
+//! The addresses in the cache are enough to configure a sequencer, but not to reconstruct what
+//! happened during a deployment. So for every contract deployed in this run, the receipt of its
+//! deployment transaction is kept as well, and [`Contracts::write_report`] and
+//! [`Contracts::write_report_json`] list the transaction hash, block and gas used alongside each
+//! address. Contracts which were predeployed, or resumed from a state file, have no transaction in
+//! this run. The receipts themselves can be written out with [`Contracts::write_receipts_json`], for
+//! an audit trail of every deployment transaction. Proxies [upgraded](super::upgrade) in this run
+//! are listed with their old and new implementations, and the [permissioned prover](super::prover)
+//! of the light client, if it was configured in this run, is listed as well, as are light client
+//! [artifacts](super::artifact) deployed from a file instead of the copy embedded in this crate and
+//! [builder deposits](super::deposits) made in this run.
+
+use super::{Contract, Contracts};
+use ethers::{prelude::*, utils::format_ether};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// How a contract in the cache came to be deployed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeploymentInfo {
+    pub address: Address,
+    /// The transaction which deployed the contract, if it was deployed in this run.
+    pub tx_hash: Option<H256>,
+    /// The block the deployment transaction was included in.
+    pub block_number: Option<u64>,
+    /// The gas used by the deployment transaction.
+    pub gas_used: Option<U256>,
+}
+
+/// An upgrade of a proxy to a new implementation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpgradeInfo {
+    pub proxy: Address,
+    /// The implementation the proxy pointed at before the upgrade.
+    pub old_implementation: Address,
+    pub new_implementation: Address,
+    /// The upgrade transaction, if one was needed.
+    pub tx_hash: Option<H256>,
+}
+
+/// A deployment transaction receipt, as written by [`Contracts::write_receipts_json`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReceiptRecord {
+    pub contract: Contract,
+    pub receipt: TransactionReceipt,
+}
+
+impl Contracts {
+    /// Record the receipt of the transaction deploying `name`.
+    ///
+    /// The gas used is also accounted for in the [gas report](Self::gas_report).
+    pub(super) fn record_receipt(&mut self, name: Contract, receipt: &TransactionReceipt) {
+        self.record_gas(name, receipt);
+        self.receipts.retain(|(contract, _)| *contract != name);
+        self.receipts.push((name, receipt.clone()));
+    }
+
+    /// The receipt of the transaction which deployed `name` in this run, if any.
+    pub(super) fn receipt(&self, name: Contract) -> Option<&TransactionReceipt> {
+        self.receipts
+            .iter()
+            .find_map(|(contract, receipt)| (*contract == name).then_some(receipt))
+    }
+
+    /// The receipt of each deployment transaction sent in this run, in the order the contracts
+    /// were deployed.
+    ///
+    /// Contracts which were predeployed, or resumed from a state file, have no receipt and do not
+    /// appear.
+    pub fn receipts(&self) -> &[(Contract, TransactionReceipt)] {
+        &self.receipts
+    }
+
+    /// How `name` was deployed, if it is in the cache.
+    pub fn deployment_info(&self, name: Contract) -> Option<DeploymentInfo> {
+        let address = self.address(name)?;
+        let receipt = self.receipt(name);
+        Some(DeploymentInfo {
+            address,
+            tx_hash: receipt.map(|receipt| receipt.transaction_hash),
+            block_number: receipt
+                .and_then(|receipt| receipt.block_number)
+                .map(|block| block.as_u64()),
+            gas_used: receipt.and_then(|receipt| receipt.gas_used),
+        })
+    }
+
+    /// How each contract in the cache was deployed, sorted like the .env file.
+    pub fn deployments(&self) -> Vec<(Contract, DeploymentInfo)> {
+        self.sorted()
+            .into_iter()
+            .filter_map(|(contract, _)| Some((contract, self.deployment_info(contract)?)))
+            .collect()
+    }
+
+    /// The proxies upgraded in this run, in order.
+    pub fn upgrades(&self) -> &[UpgradeInfo] {
+        &self.upgrades
+    }
+
+    /// Write a human-readable table of the [deployments](Self::deployments),
+    /// [upgrades](Self::upgrades), [prover mode](Self::prover_info),
+    /// [artifact overrides](Self::artifact_overrides) and [builder deposits](Self::deposits), with
+    /// the total gas spent.
+    pub fn write_report(&self, mut w: impl Write) -> anyhow::Result<()> {
+        writeln!(w, "Deployments:")?;
+        for (contract, info) in self.deployments() {
+            let name = format!("{contract:?}");
+            match info.tx_hash {
+                Some(tx_hash) => writeln!(
+                    w,
+                    "  {name:<20} {:#x} tx {tx_hash:#x} block {} gas {}",
+                    info.address,
+                    fmt_opt(info.block_number),
+                    fmt_opt(info.gas_used)
+                )?,
+                None => writeln!(
+                    w,
+                    "  {name:<20} {:#x} not deployed in this run",
+                    info.address
+                )?,
+            }
+        }
+        if !self.upgrades.is_empty() {
+            writeln!(w, "Upgrades:")?;
+            for upgrade in &self.upgrades {
+                writeln!(
+                    w,
+                    "  proxy {:#x} from {:#x} to {:#x} tx {}",
+                    upgrade.proxy,
+                    upgrade.old_implementation,
+                    upgrade.new_implementation,
+                    upgrade
+                        .tx_hash
+                        .map_or("none".into(), |hash| format!("{hash:#x}"))
+                )?;
+            }
+        }
+        if let Some(info) = &self.prover {
+            writeln!(w, "Permissioned prover:")?;
+            let name = format!("{:?}", info.contract);
+            match info.prover {
+                Some(prover) => writeln!(
+                    w,
+                    "  {name:<20} {prover:#x} tx {}",
+                    info.tx_hash
+                        .map_or("none".into(), |hash| format!("{hash:#x}"))
+                )?,
+                None => writeln!(w, "  {name:<20} none (permissionless)")?,
+            }
+        }
+        if !self.artifact_overrides.is_empty() {
+            writeln!(w, "Bytecode overrides:")?;
+            for info in &self.artifact_overrides {
+                writeln!(
+                    w,
+                    "  {:<20} {} keccak {:#x}",
+                    format!("{:?}", info.artifact),
+                    info.path
+                        .as_ref()
+                        .map_or("memory".into(), |path| path.display().to_string()),
+                    info.keccak
+                )?;
+            }
+        }
+        if !self.deposits.is_empty() {
+            writeln!(w, "Builder deposits:")?;
+            for deposit in &self.deposits {
+                writeln!(
+                    w,
+                    "  {:#x} deposited {} ETH, balance {} ETH tx {:#x}",
+                    deposit.account,
+                    format_ether(deposit.amount),
+                    format_ether(deposit.balance),
+                    deposit.tx_hash
+                )?;
+            }
+        }
+        let (gas, cost) = self
+            .gas_used
+            .iter()
+            .fold((U256::zero(), U256::zero()), |(gas, cost), (_, g, c)| {
+                (gas + *g, cost + *c)
+            });
+        writeln!(
+            w,
+            "  total gas spent {gas}, costing {} ETH",
+            format_ether(cost)
+        )?;
+        Ok(())
+    }
+
+    /// Write the [deployments](Self::deployments) as a JSON object keyed by the env var name of
+    /// each contract.
+    ///
+    /// If any proxy was upgraded, the [upgrades](Self::upgrades) are listed under `upgrades`. If
+    /// the [prover mode](Self::prover_info) was configured, it is under `permissioned_prover`. If
+    /// any light client artifact was [overridden](Self::artifact_overrides), the artifacts are
+    /// listed under `bytecode_overrides`. Any [builder deposits](Self::deposits) are listed under
+    /// `builder_deposits`.
+    pub fn write_report_json(&self, w: impl Write) -> anyhow::Result<()> {
+        let mut map: serde_json::Map<_, _> = self
+            .deployments()
+            .into_iter()
+            .map(|(contract, info)| Ok((contract.to_string(), serde_json::to_value(info)?)))
+            .collect::<anyhow::Result<_>>()?;
+        if !self.upgrades.is_empty() {
+            map.insert("upgrades".into(), serde_json::to_value(&self.upgrades)?);
+        }
+        if let Some(info) = &self.prover {
+            map.insert("permissioned_prover".into(), serde_json::to_value(info)?);
+        }
+        if !self.artifact_overrides.is_empty() {
+            map.insert(
+                "bytecode_overrides".into(),
+                serde_json::to_value(&self.artifact_overrides)?,
+            );
+        }
+        if !self.deposits.is_empty() {
+            map.insert(
+                "builder_deposits".into(),
+                serde_json::to_value(&self.deposits)?,
+            );
+        }
+        serde_json::to_writer_pretty(w, &map)?;
+        Ok(())
+    }
+
+    /// Write the [receipts](Self::receipts) of this run as a JSON array, in deployment order.
+    ///
+    /// Each entry has the env var name of the contract and its full receipt, including the
+    /// transaction hash, block number and status.
+    pub fn write_receipts_json(&self, w: impl Write) -> anyhow::Result<()> {
+        let records: Vec<_> = self
+            .receipts
+            .iter()
+            .map(|(contract, receipt)| ReceiptRecord {
+                contract: *contract,
+                receipt: receipt.clone(),
+            })
+            .collect();
+        serde_json::to_writer_pretty(w, &records)?;
+        Ok(())
+    }
+}
+
+fn fmt_opt<T: ToString>(value: Option<T>) -> String {
+    value.map_or("unknown".into(), |value| value.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{deployer::test_helpers::TEST_MNEMONIC, init_signer, AnvilOptions};
+    use async_std::sync::Arc;
+    use contract_bindings::hot_shot::HotShot;
+
+    #[async_std::test]
+    async fn test_deployment_report() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::PlonkVerifier, Address::repeat_byte(1));
+        let hotshot = contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+
+        // A predeployed contract has no transaction.
+        assert_eq!(
+            contracts.deployment_info(Contract::PlonkVerifier),
+            Some(DeploymentInfo {
+                address: Address::repeat_byte(1),
+                tx_hash: None,
+                block_number: None,
+                gas_used: None,
+            })
+        );
+
+        // A deployed contract has the transaction which created it.
+        let info = contracts.deployment_info(Contract::HotShot).unwrap();
+        assert_eq!(info.address, hotshot);
+        let receipt = l1
+            .get_transaction_receipt(info.tx_hash.unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.contract_address, Some(hotshot));
+        assert_eq!(info.block_number, receipt.block_number.map(|b| b.as_u64()));
+        assert_eq!(info.gas_used, receipt.gas_used);
+        assert_eq!(contracts.deployment_info(Contract::LightClient), None);
+
+        let mut out = vec![];
+        contracts.write_report(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.contains(&format!("tx {:#x}", info.tx_hash.unwrap())),
+            "{out}"
+        );
+        assert!(out.contains("not deployed in this run"), "{out}");
+        assert!(
+            out.contains(&format!("total gas spent {}", info.gas_used.unwrap())),
+            "{out}"
+        );
+
+        let mut out = vec![];
+        contracts.write_report_json(&mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let parsed: DeploymentInfo =
+            serde_json::from_value(json[Contract::HotShot.to_string()].clone()).unwrap();
+        assert_eq!(parsed, info);
+
+        // Only the contract deployed in this run has a receipt.
+        assert_eq!(contracts.receipts().len(), 1);
+        assert_eq!(contracts.receipts()[0].0, Contract::HotShot);
+        assert_eq!(
+            contracts.receipts()[0].1.transaction_hash,
+            receipt.transaction_hash
+        );
+
+        let mut out = vec![];
+        contracts.write_receipts_json(&mut out).unwrap();
+        let records: Vec<ReceiptRecord> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].contract, Contract::HotShot);
+        assert_eq!(
+            records[0].receipt.transaction_hash,
+            receipt.transaction_hash
+        );
+        assert_eq!(records[0].receipt.block_number, receipt.block_number);
+        assert_eq!(records[0].receipt.status, Some(1.into()));
+    }
+}
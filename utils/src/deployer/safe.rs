@@ -0,0 +1,471 @@
+//! Support for contracts owned by a Gnosis Safe.
+//!
+//! Transactions which must come from the owner of a contract, like `transferOwnership` or
+//! `upgradeToAndCall`, cannot be sent by this tool when the owner is a Safe multisig. Instead of
+//! letting them revert, [`send_or_prepare`] detects a Safe owner and adds the transaction to a
+//! batch in the format of the Safe Transaction Builder, which the Safe owners can import, sign and
+//! execute. The batches prepared during a run are collected in [`Contracts::safe_batches`].
+
+use super::{send_with_retry, serialize_decimal, Contracts};
+use anyhow::{bail, Context};
+use ethers::{
+    abi::{self, ParamType, Token},
+    prelude::*,
+    types::transaction::eip2718::TypedTransaction,
+    utils::id,
+};
+use serde::Serialize;
+use std::{
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Information about a Safe detected at some address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SafeInfo {
+    pub address: Address,
+    /// The number of owner signatures required to execute a transaction.
+    pub threshold: U256,
+    /// The version of the Safe contract, e.g. `1.3.0`.
+    pub version: String,
+}
+
+/// Check whether `address` is a Safe.
+///
+/// An address is considered a Safe if it has code, and calling both `getThreshold()` and
+/// `VERSION()` succeeds and returns a nonzero threshold and a version string. Failing calls are not
+/// an error, they just mean `address` is not a Safe; only errors talking to the RPC are reported.
+pub async fn detect_safe<M: Middleware + 'static>(
+    l1: &M,
+    address: Address,
+) -> anyhow::Result<Option<SafeInfo>> {
+    let code = l1
+        .get_code(address, None)
+        .await
+        .with_context(|| format!("error fetching code at {address:#x}"))?;
+    if code.is_empty() {
+        return Ok(None);
+    }
+
+    let Some(Token::Uint(threshold)) =
+        call_view(l1, address, "getThreshold()", ParamType::Uint(256)).await
+    else {
+        return Ok(None);
+    };
+    if threshold.is_zero() {
+        // A Safe which has not been set up (such as the singleton behind Safe proxies) cannot
+        // execute transactions.
+        return Ok(None);
+    }
+    let Some(Token::String(version)) = call_view(l1, address, "VERSION()", ParamType::String).await
+    else {
+        return Ok(None);
+    };
+    Ok(Some(SafeInfo {
+        address,
+        threshold,
+        version,
+    }))
+}
+
+/// Call a view function with no arguments, returning `None` if the call fails or the output does
+/// not decode as `output`.
+async fn call_view<M: Middleware + 'static>(
+    l1: &M,
+    address: Address,
+    signature: &str,
+    output: ParamType,
+) -> Option<Token> {
+    let tx: TypedTransaction = TransactionRequest::new()
+        .to(address)
+        .data(id(signature).to_vec())
+        .into();
+    let bytes = match l1.call(&tx, None).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::debug!("{signature} call to {address:#x} failed: {err}");
+            return None;
+        }
+    };
+    abi::decode(&[output], &bytes).ok()?.pop()
+}
+
+/// A transaction to be executed by a Safe.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SafeTransaction {
+    pub to: Address,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub value: U256,
+    pub data: Bytes,
+}
+
+impl SafeTransaction {
+    pub fn new(to: Address, data: impl Into<Bytes>) -> Self {
+        Self {
+            to,
+            value: U256::zero(),
+            data: data.into(),
+        }
+    }
+}
+
+/// A batch of transactions in the Safe Transaction Builder JSON format.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafeBatch {
+    pub version: String,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub chain_id: U256,
+    /// Creation time, in milliseconds since the Unix epoch.
+    pub created_at: u64,
+    pub meta: SafeBatchMeta,
+    pub transactions: Vec<SafeTransaction>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafeBatchMeta {
+    pub name: String,
+    pub description: String,
+    pub created_from_safe_address: Address,
+}
+
+impl SafeBatch {
+    pub fn new(chain_id: U256, safe: Address, description: impl Into<String>) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Self {
+            version: "1.0".into(),
+            chain_id,
+            created_at,
+            meta: SafeBatchMeta {
+                name: "Transactions Batch".into(),
+                description: description.into(),
+                created_from_safe_address: safe,
+            },
+            transactions: vec![],
+        }
+    }
+
+    pub fn push(&mut self, tx: SafeTransaction) {
+        self.transactions.push(tx);
+    }
+
+    /// Write the batch to `path` as JSON, for import into the Safe Transaction Builder.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).with_context(|| format!("writing {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// The outcome of [`send_or_prepare`].
+#[derive(Clone, Debug)]
+pub enum Submission {
+    /// The transaction was sent by the signer and mined.
+    ///
+    /// The receipt is missing if an earlier attempt turned out to have landed, and its receipt was
+    /// never seen.
+    Sent(Option<TransactionReceipt>),
+    /// The owner is the Safe at `safe`, and the transaction was added to the
+    /// [batch](Contracts::safe_batches) prepared for it.
+    Prepared { safe: Address },
+}
+
+/// Execute a transaction which must be sent by `owner`.
+///
+/// If `owner` is the signer of `l1`, the transaction is sent, and retried according to the
+/// [retry policy](Contracts::with_retry_policy) of `contracts`. Before each attempt, `landed` is
+/// checked, so that an attempt which landed after all is not repeated. If `owner` is a Safe,
+/// nothing is sent; instead the transaction is added to the [batch](Contracts::safe_batches)
+/// prepared for that Safe in this run, to be written out with [`Contracts::write_safe_batches`]
+/// and imported into the Safe. The same goes for any owner if `contracts` was configured to
+/// [prepare owner transactions](Contracts::prepare_owner_transactions). Otherwise, any other owner
+/// is an error, since the transaction would just revert.
+pub async fn send_or_prepare<M, F, Fut>(
+    l1: &M,
+    contracts: &mut Contracts,
+    owner: Address,
+    tx: SafeTransaction,
+    description: &str,
+    landed: F,
+) -> anyhow::Result<Submission>
+where
+    M: Middleware + 'static,
+    F: Fn() -> Fut,
+    Fut: Future<Output = anyhow::Result<bool>>,
+{
+    let sender = l1.default_sender();
+    if sender == Some(owner) && !contracts.prepare_owner_transactions {
+        let gas = contracts.gas_config;
+        let (landed, tx) = (&landed, &tx);
+        let receipt = send_with_retry(l1, contracts, description, |_| async move {
+            if landed().await? {
+                return Ok(None);
+            }
+            let mut req: TypedTransaction = TransactionRequest::new()
+                .from(owner)
+                .to(tx.to)
+                .value(tx.value)
+                .data(tx.data.clone())
+                .into();
+            gas.apply(&mut req);
+            Ok::<_, anyhow::Error>(Some(req))
+        })
+        .await?;
+        return Ok(Submission::Sent(receipt));
+    }
+
+    match detect_safe(l1, owner).await? {
+        Some(safe) => tracing::warn!(
+            "{description}: owner {owner:#x} is a Safe (version {}, threshold {}), not sending \
+             transaction. Import the prepared batch into the Safe Transaction Builder and collect \
+             {} owner signature(s) to execute it.",
+            safe.version,
+            safe.threshold,
+            safe.threshold,
+        ),
+        None if contracts.prepare_owner_transactions => tracing::warn!(
+            "{description}: not sending transaction, it is prepared for the owner {owner:#x} to \
+             send"
+        ),
+        None => bail!(
+            "{description}: owner {owner:#x} is neither the signer ({}) nor a Safe, the \
+             transaction must be sent by the owner",
+            sender.map_or("none".into(), |addr| format!("{addr:#x}"))
+        ),
+    }
+    let chain_id = l1.get_chainid().await.context("error fetching chain ID")?;
+    contracts.prepare_safe_tx(chain_id, owner, tx, description);
+    Ok(Submission::Prepared { safe: owner })
+}
+
+impl Contracts {
+    /// Prepare every transaction which must be sent by the owner of a contract, instead of sending
+    /// it.
+    ///
+    /// By default, [`send_or_prepare`] only prepares transactions for owners which are detected to
+    /// be a Safe. With this set, it prepares them whoever the owner is, even the signer, so the
+    /// owner can be a multisig which is not a Safe, or the transactions can be reviewed before
+    /// they are sent.
+    pub fn prepare_owner_transactions(mut self, prepare: bool) -> Self {
+        self.prepare_owner_transactions = prepare;
+        self
+    }
+
+    /// Whether a transaction which must be sent by `owner` can be [sent or
+    /// prepared](send_or_prepare): `owner` is the signer of `l1`, or a Safe, or owner transactions
+    /// are [prepared](Self::prepare_owner_transactions) regardless.
+    pub async fn can_act_as<M: Middleware + 'static>(
+        &self,
+        l1: &M,
+        owner: Address,
+    ) -> anyhow::Result<bool> {
+        if self.prepare_owner_transactions || l1.default_sender() == Some(owner) {
+            return Ok(true);
+        }
+        Ok(detect_safe(l1, owner).await?.is_some())
+    }
+
+    /// Transactions prepared in this run for Safes to execute, in one batch per Safe.
+    ///
+    /// These are the transactions [`send_or_prepare`] could not send, because they must come from
+    /// a Safe.
+    pub fn safe_batches(&self) -> &[SafeBatch] {
+        &self.safe_batches
+    }
+
+    /// Write each [prepared batch](Self::safe_batches) to `dir`, for import into the Safe
+    /// Transaction Builder.
+    ///
+    /// The batch for each Safe is written to `safe-<address>.json`. Returns the paths written.
+    pub fn write_safe_batches(&self, dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        if self.safe_batches.is_empty() {
+            return Ok(vec![]);
+        }
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        self.safe_batches
+            .iter()
+            .map(|batch| {
+                let safe = batch.meta.created_from_safe_address;
+                let path = dir.join(format!("safe-{safe:#x}.json"));
+                batch.write(&path)?;
+                Ok(path)
+            })
+            .collect()
+    }
+
+    /// Add `tx` to the batch prepared for `safe`, starting the batch if necessary.
+    fn prepare_safe_tx(
+        &mut self,
+        chain_id: U256,
+        safe: Address,
+        tx: SafeTransaction,
+        description: &str,
+    ) {
+        let batch = match self
+            .safe_batches
+            .iter_mut()
+            .position(|batch| batch.meta.created_from_safe_address == safe)
+        {
+            Some(i) => {
+                let batch = &mut self.safe_batches[i];
+                batch.meta.description = format!("{}; {description}", batch.meta.description);
+                batch
+            }
+            None => {
+                self.safe_batches
+                    .push(SafeBatch::new(chain_id, safe, description));
+                self.safe_batches.last_mut().unwrap()
+            }
+        };
+        batch.push(tx);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::test_helpers::{deploy_safe_stub, execute_safe_batch, TEST_MNEMONIC},
+        init_signer, AnvilOptions,
+    };
+    use async_std::sync::Arc;
+    use contract_bindings::plonk_verifier::PlonkVerifier;
+
+    #[async_std::test]
+    async fn test_detect_safe() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        // An EOA is not a Safe.
+        assert_eq!(detect_safe(&*l1, l1.address()).await.unwrap(), None);
+
+        // Neither is a contract which does not implement the Safe interface.
+        let verifier = PlonkVerifier::deploy(l1.clone(), ())
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(detect_safe(&*l1, verifier.address()).await.unwrap(), None);
+
+        let safe = deploy_safe_stub(&l1).await.unwrap();
+        assert_eq!(
+            detect_safe(&*l1, safe).await.unwrap(),
+            Some(SafeInfo {
+                address: safe,
+                threshold: 2.into(),
+                version: "1.3.0".into(),
+            })
+        );
+    }
+
+    #[async_std::test]
+    async fn test_send_or_prepare() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap();
+        let target = Address::random();
+        let tx = SafeTransaction::new(target, vec![1, 2, 3]);
+        let not_landed = || async { Ok(false) };
+        let mut contracts = Contracts::default();
+
+        // A Safe owner gets a prepared batch and nothing is sent.
+        let safe = deploy_safe_stub(&l1).await.unwrap();
+        let nonce = l1.get_transaction_count(l1.address(), None).await.unwrap();
+        for description in ["first", "second"] {
+            let submission = send_or_prepare(
+                &l1,
+                &mut contracts,
+                safe,
+                tx.clone(),
+                description,
+                not_landed,
+            )
+            .await
+            .unwrap();
+            assert!(matches!(submission, Submission::Prepared { safe: s } if s == safe));
+        }
+        assert_eq!(
+            l1.get_transaction_count(l1.address(), None).await.unwrap(),
+            nonce
+        );
+        // Transactions for the same Safe go into the same batch.
+        let [batch] = contracts.safe_batches() else {
+            panic!("expected one batch: {:?}", contracts.safe_batches());
+        };
+        assert_eq!(batch.transactions, [tx.clone(), tx.clone()]);
+        assert_eq!(batch.meta.description, "first; second");
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let paths = contracts.write_safe_batches(dir.path()).unwrap();
+        assert_eq!(paths, [dir.path().join(format!("safe-{safe:#x}.json"))]);
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&paths[0]).unwrap()).unwrap();
+        assert_eq!(json["chainId"], l1.get_chainid().await.unwrap().to_string());
+        assert_eq!(json["meta"]["createdFromSafeAddress"], format!("{safe:#x}"));
+        assert_eq!(
+            json["transactions"][0],
+            serde_json::json!({
+                "to": format!("{target:#x}"),
+                "value": "0",
+                "data": "0x010203",
+            })
+        );
+
+        // The batch can be executed from the Safe.
+        let receipts = execute_safe_batch(&l1, batch).await.unwrap();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0].from, safe);
+        assert_eq!(receipts[0].to, Some(target));
+
+        // The signer sends the transaction itself.
+        let Submission::Sent(Some(receipt)) = send_or_prepare(
+            &l1,
+            &mut contracts,
+            l1.address(),
+            tx.clone(),
+            "test",
+            not_landed,
+        )
+        .await
+        .unwrap() else {
+            panic!("expected transaction to be sent");
+        };
+        assert_eq!(receipt.to, Some(target));
+
+        // Unless it already landed.
+        let nonce = l1.get_transaction_count(l1.address(), None).await.unwrap();
+        let submission = send_or_prepare(
+            &l1,
+            &mut contracts,
+            l1.address(),
+            tx.clone(),
+            "test",
+            || async { Ok(true) },
+        )
+        .await
+        .unwrap();
+        assert!(matches!(submission, Submission::Sent(None)));
+        assert_eq!(
+            l1.get_transaction_count(l1.address(), None).await.unwrap(),
+            nonce
+        );
+
+        // Anyone else is an error.
+        send_or_prepare(
+            &l1,
+            &mut contracts,
+            Address::random(),
+            tx,
+            "test",
+            not_landed,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(contracts.safe_batches().len(), 1);
+    }
+}
@@ -0,0 +1,266 @@
+//! Signing deployment transactions on an external device.
+//!
+//! A hardware wallet asks the user to confirm every transaction on the device, which shows little
+//! more than raw fields. [`PromptingSigner`] wraps any [`Signer`], like a Ledger, and before each
+//! transaction is handed to the device, prints which contract it is for and a summary of what is
+//! about to be signed, so the user can check the device against it. The contract is tracked by
+//! registering [`PromptingSigner::observer`] with the [`Contracts`](super::Contracts) being
+//! deployed.
+//!
+//! If the user rejects a transaction on the device, the signer returns an error, which is never
+//! retried (see [`SendError`](super::SendError)), so the deployment stops cleanly.
+
+use super::{fees::fmt_gwei, Contract, DeployEvent};
+use async_trait::async_trait;
+use ethers::{
+    prelude::*,
+    types::transaction::{eip2718::TypedTransaction, eip712::Eip712},
+    utils::format_ether,
+};
+use std::sync::{Arc, Mutex};
+
+/// A [`Signer`] which describes each transaction before signing it.
+#[derive(Clone, Debug)]
+pub struct PromptingSigner<S> {
+    inner: S,
+    current: Arc<Mutex<Option<Contract>>>,
+}
+
+impl<S> PromptingSigner<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            current: Default::default(),
+        }
+    }
+
+    /// An observer to register with [`Contracts::with_observer`](super::Contracts::with_observer),
+    /// which keeps track of the contract being deployed.
+    pub fn observer(&self) -> impl Fn(&DeployEvent) + Send + Sync + 'static {
+        let current = self.current.clone();
+        move |event| {
+            *current.lock().unwrap() = match event {
+                DeployEvent::Started { contract } => Some(*contract),
+                _ => None,
+            };
+        }
+    }
+
+    /// A human-readable summary of `tx`, as it is about to be signed.
+    pub fn summary(&self, tx: &TypedTransaction) -> String {
+        let to = tx.to().map(|to| match to {
+            NameOrAddress::Address(addr) => format!("{addr:#x}"),
+            NameOrAddress::Name(name) => name.clone(),
+        });
+        let what = match (*self.current.lock().unwrap(), to) {
+            (Some(contract), None) => format!("{contract:?} deployment"),
+            (Some(contract), Some(to)) => format!("{contract:?} deployment, call to {to}"),
+            (None, None) => "contract deployment".into(),
+            (None, Some(to)) => format!("call to {to}"),
+        };
+        let fee = match tx {
+            TypedTransaction::Eip1559(req) => format!(
+                "max fee {} gwei, priority fee {} gwei",
+                fmt_opt_gwei(req.max_fee_per_gas),
+                fmt_opt_gwei(req.max_priority_fee_per_gas)
+            ),
+            _ => format!("gas price {} gwei", fmt_opt_gwei(tx.gas_price())),
+        };
+        format!(
+            "Sign {what}: nonce {}, gas limit {}, {fee}, value {} ETH, {} bytes of data, signing hash \
+             {:#x}. Confirm on your device.",
+            fmt_opt(tx.nonce()),
+            fmt_opt(tx.gas()),
+            format_ether(tx.value().copied().unwrap_or_default()),
+            tx.data().map_or(0, |data| data.len()),
+            tx.sighash()
+        )
+    }
+}
+
+fn fmt_opt(value: Option<&U256>) -> String {
+    value.map_or("unset".into(), U256::to_string)
+}
+
+fn fmt_opt_gwei(value: Option<U256>) -> String {
+    value.map_or("unset".into(), fmt_gwei)
+}
+
+#[async_trait]
+impl<S: Signer> Signer for PromptingSigner<S> {
+    type Error = S::Error;
+
+    async fn sign_message<T: Send + Sync + AsRef<[u8]>>(
+        &self,
+        message: T,
+    ) -> Result<Signature, Self::Error> {
+        eprintln!("Sign message. Confirm on your device.");
+        self.inner.sign_message(message).await
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, Self::Error> {
+        eprintln!("{}", self.summary(tx));
+        self.inner.sign_transaction(tx).await.map_err(|err| {
+            tracing::error!("transaction was not signed: {err}");
+            err
+        })
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(
+        &self,
+        payload: &T,
+    ) -> Result<Signature, Self::Error> {
+        eprintln!("Sign typed data. Confirm on your device.");
+        self.inner.sign_typed_data(payload).await
+    }
+
+    fn address(&self) -> Address {
+        self.inner.address()
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.inner.chain_id()
+    }
+
+    fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+        Self {
+            inner: self.inner.with_chain_id(chain_id),
+            current: self.current,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{test_helpers::TEST_MNEMONIC, Contracts, RetryPolicy},
+        AnvilOptions,
+    };
+    use contract_bindings::hot_shot::HotShot;
+    use ethers::core::k256;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    /// A signer which, like a user rejecting every transaction on the device, refuses to sign.
+    #[derive(Debug)]
+    struct Rejecting {
+        wallet: LocalWallet,
+        attempts: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Signer for Rejecting {
+        type Error = WalletError;
+
+        async fn sign_message<T: Send + Sync + AsRef<[u8]>>(
+            &self,
+            message: T,
+        ) -> Result<Signature, Self::Error> {
+            self.wallet.sign_message(message).await
+        }
+
+        async fn sign_transaction(&self, _: &TypedTransaction) -> Result<Signature, Self::Error> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            Err(WalletError::EcdsaError(k256::ecdsa::Error::new()))
+        }
+
+        async fn sign_typed_data<T: Eip712 + Send + Sync>(
+            &self,
+            payload: &T,
+        ) -> Result<Signature, Self::Error> {
+            self.wallet.sign_typed_data(payload).await
+        }
+
+        fn address(&self) -> Address {
+            self.wallet.address()
+        }
+
+        fn chain_id(&self) -> u64 {
+            self.wallet.chain_id()
+        }
+
+        fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+            Self {
+                wallet: self.wallet.with_chain_id(chain_id),
+                ..self
+            }
+        }
+    }
+
+    fn wallet(chain_id: u64) -> LocalWallet {
+        MnemonicBuilder::<coins_bip39::English>::default()
+            .phrase(TEST_MNEMONIC)
+            .build()
+            .unwrap()
+            .with_chain_id(chain_id)
+    }
+
+    #[async_std::test]
+    async fn test_prompting_signer() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let provider = anvil.provider();
+        let chain_id = provider.get_chainid().await.unwrap().as_u64();
+        let signer = PromptingSigner::new(wallet(chain_id));
+        let l1 = Arc::new(SignerMiddleware::new(provider, signer.clone()));
+
+        let tx: TypedTransaction = TransactionRequest::new()
+            .data(vec![1, 2, 3])
+            .nonce(7)
+            .into();
+        let summary = signer.summary(&tx);
+        assert!(summary.starts_with("Sign contract deployment"), "{summary}");
+        assert!(summary.contains("nonce 7"), "{summary}");
+        assert!(summary.contains("3 bytes of data"), "{summary}");
+
+        // While a contract is being deployed, its name is shown.
+        let mut contracts = Contracts::default().with_observer(signer.observer());
+        let seen = Arc::new(Mutex::new(None));
+        contracts = contracts.with_observer({
+            let signer = signer.clone();
+            let seen = seen.clone();
+            let tx = tx.clone();
+            move |event| {
+                if let DeployEvent::Started { .. } = event {
+                    *seen.lock().unwrap() = Some(signer.summary(&tx));
+                }
+            }
+        });
+        contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+        let summary = seen.lock().unwrap().clone().unwrap();
+        assert!(summary.starts_with("Sign HotShot deployment"), "{summary}");
+
+        // Afterwards, it is not.
+        assert!(signer.summary(&tx).starts_with("Sign contract deployment"));
+    }
+
+    #[async_std::test]
+    async fn test_rejected_signature_not_retried() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let provider = anvil.provider();
+        let chain_id = provider.get_chainid().await.unwrap().as_u64();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let signer = PromptingSigner::new(Rejecting {
+            wallet: wallet(chain_id),
+            attempts: attempts.clone(),
+        });
+        let l1 = Arc::new(SignerMiddleware::new(provider, signer));
+
+        let mut contracts = Contracts::default().with_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+        });
+        contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert!(!contracts.contains(Contract::HotShot));
+    }
+}
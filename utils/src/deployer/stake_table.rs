@@ -0,0 +1,351 @@
+//! Deploying and operating the permissionless stake table, `StakeTable.sol`.
+//!
+//! There are no generated bindings for the stake table yet, so the part of its interface needed
+//! here is declared in this module, and its bytecode is loaded from the artifact Foundry writes
+//! when the contracts are built, `contracts/out/StakeTable.sol/StakeTable.json`.
+//!
+//! Nodes register in the stake table with their BLS staking key and their Schnorr state key, by
+//! depositing stake tokens from an L1 account. [`register_node`], [`request_exit`] and
+//! [`withdraw_stake`] send the transactions for each step, and [`read_stake_table`] lists the
+//! registered nodes in the form the sequencer takes its stake table in.
+
+use super::{
+    artifact::parse_artifact, explorer::fmt_address, send_with_retry, Contract, Contracts,
+};
+use anyhow::{ensure, Context};
+use async_std::sync::Arc;
+use ethers::{abi::AbiDecode, prelude::*};
+use futures::FutureExt;
+use hotshot_contract_adapter::stake_table::{
+    bls_key_from_sol, bls_key_to_sol, schnorr_key_from_sol, schnorr_key_to_sol, sign_account,
+    ParsedG2Point,
+};
+use hotshot_types::{
+    light_client::StateVerKey,
+    signature_key::{BLSPrivKey, BLSPubKey},
+    traits::signature_key::SignatureKey,
+    PeerConfig,
+};
+use std::{fs, path::Path};
+
+abigen!(
+    StakeTable,
+    r#"[
+        struct G1Point { uint256 x; uint256 y; }
+        struct G2Point { uint256 x0; uint256 x1; uint256 y0; uint256 y1; }
+        struct EdOnBN254Point { uint256 x; uint256 y; }
+        struct Node { address account; uint8 stakeType; uint64 balance; uint64 registerEpoch; uint64 exitEpoch; EdOnBN254Point schnorrVK; }
+        constructor(address token, address lightClient, uint64 churnRate)
+        function register(G2Point blsVK, EdOnBN254Point schnorrVK, uint64 amount, uint8 stakeType, G1Point blsSig, uint64 validUntilEpoch)
+        function requestExit(G2Point blsVK)
+        function withdrawFunds(G2Point blsVK) returns (uint64)
+        function lookupNode(G2Point blsVK) view returns (Node)
+        function nextRegistrationEpoch() view returns (uint64, uint64)
+        function currentEpoch() view returns (uint64)
+        function tokenAddress() view returns (address)
+        function totalStake() view returns (uint256, uint256)
+        event Registered(bytes32 blsVKhash, uint64 registerEpoch, uint8 stakeType, uint256 amountDeposited)
+        event Exit(bytes32 blsVKhash, uint64 exitEpoch)
+        error RestakingNotImplemented()
+        error InvalidNextRegistrationEpoch(uint64, uint64)
+        error NodeAlreadyRegistered()
+        error Unauthenticated()
+        error PrematureDeposit()
+        error PrematureExit()
+        error ExitRequestInProgress()
+        error PrematureWithdrawal()
+        error BLSSigVerificationFailed()
+    ]"#
+);
+
+abigen!(
+    StakeToken,
+    r#"[
+        function approve(address spender, uint256 amount) returns (bool)
+        function allowance(address owner, address spender) view returns (uint256)
+    ]"#
+);
+
+/// `StakeType.Native`, the only stake type the stake table supports so far.
+const NATIVE_STAKE: u8 = 0;
+
+/// The default location of the stake table artifact, relative to the root of the repository.
+pub const STAKE_TABLE_ARTIFACT: &str = "contracts/out/StakeTable.sol/StakeTable.json";
+
+/// A node registered in the stake table.
+#[derive(Clone, Debug)]
+pub struct RegisteredNode {
+    /// The L1 account which registered the node, and which can withdraw its stake.
+    pub account: Address,
+    pub staking_key: BLSPubKey,
+    pub state_key: StateVerKey,
+    pub balance: u64,
+    /// The epoch from which the node is part of the stake table.
+    pub register_epoch: u64,
+}
+
+impl RegisteredNode {
+    /// The stake table entry of this node, as the sequencer is configured with it.
+    pub fn peer_config(&self) -> PeerConfig<BLSPubKey> {
+        PeerConfig {
+            stake_table_entry: self.staking_key.get_stake_table_entry(self.balance),
+            state_ver_key: self.state_key.clone(),
+        }
+    }
+}
+
+impl From<ParsedG2Point> for G2Point {
+    fn from(p: ParsedG2Point) -> Self {
+        Self {
+            x_0: p.x0,
+            x_1: p.x1,
+            y_0: p.y0,
+            y_1: p.y1,
+        }
+    }
+}
+
+impl From<G2Point> for ParsedG2Point {
+    fn from(p: G2Point) -> Self {
+        Self {
+            x0: p.x_0,
+            x1: p.x_1,
+            y0: p.y_0,
+            y1: p.y_1,
+        }
+    }
+}
+
+/// Load the bytecode of the stake table from the Foundry artifact at `path`.
+fn load_bytecode(path: &Path) -> anyhow::Result<Bytes> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("error reading stake table artifact {}", path.display()))?;
+    let bytecode = parse_artifact(&bytes)
+        .with_context(|| format!("error parsing stake table artifact {}", path.display()))?;
+    bytecode
+        .as_bytes()
+        .cloned()
+        .with_context(|| format!("stake table artifact {} is not linked", path.display()))
+}
+
+/// Deploy the stake table from the Foundry artifact at `artifact`, staking `token`, with a churn
+/// rate of `churn_rate` registrations or exits per epoch.
+///
+/// The artifact is only read if the stake table is not already in `contracts`. The stake table reads the current epoch from the light client, which must already be in
+/// `contracts`: [`Contract::LightClientProxy`], or [`Contract::LightClient`] for the mock.
+pub async fn deploy_stake_table<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    artifact: &Path,
+    token: Address,
+    churn_rate: u64,
+) -> anyhow::Result<Address> {
+    let light_client = contracts
+        .address(Contract::LightClientProxy)
+        .or_else(|| contracts.address(Contract::LightClient))
+        .context("the light client must be deployed before the stake table")?;
+    ensure!(churn_rate > 0, "stake table churn rate must be positive");
+    let artifact = artifact.to_path_buf();
+    Ok(contracts
+        .deploy_fn(Contract::StakeTable, |contracts| {
+            async move {
+                let bytecode = load_bytecode(&artifact)?;
+                let factory = ContractFactory::new(STAKETABLE_ABI.clone(), bytecode, l1.clone());
+                let deployer = factory.deploy((token, light_client, churn_rate))?;
+                contracts
+                    .send_tx(Contract::StakeTable, &*l1, deployer.tx)
+                    .await
+            }
+            .boxed()
+        })
+        .await?)
+}
+
+/// Register the node with `staking_key` and `state_key` in the stake table, depositing `amount`
+/// stake tokens from the sender of `l1`.
+///
+/// The stake table is allowed to transfer the tokens first, if it is not already. The registration
+/// is refused by the stake table if it cannot take effect by `valid_until_epoch`, which defaults to
+/// the first epoch a registration can take effect in right now. Returns the epoch the node joins
+/// the stake table in.
+pub async fn register_node<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &Contracts,
+    staking_key: &BLSPrivKey,
+    state_key: &StateVerKey,
+    amount: u64,
+    valid_until_epoch: Option<u64>,
+) -> anyhow::Result<u64> {
+    let stake_table = stake_table(&l1, contracts)?;
+    let account = l1
+        .default_sender()
+        .context("registering a node requires a sender account")?;
+    let bls_vk = bls_key_to_sol(&BLSPubKey::from_private(staking_key));
+    let node = stake_table
+        .lookup_node(bls_vk.clone().into())
+        .call()
+        .await?;
+    ensure!(
+        node.account.is_zero(),
+        "node is already registered by {:#x}",
+        node.account
+    );
+
+    let token = StakeToken::new(stake_table.token_address().call().await?, l1.clone());
+    let allowance = token
+        .allowance(account, stake_table.address())
+        .call()
+        .await?;
+    if allowance < amount.into() {
+        let tx = token.approve(stake_table.address(), amount.into()).tx;
+        send(&l1, contracts, tx, "stake token approval").await?;
+    }
+
+    let (next_epoch, _) = stake_table.next_registration_epoch().call().await?;
+    let valid_until_epoch = valid_until_epoch.unwrap_or(next_epoch);
+    ensure!(
+        next_epoch <= valid_until_epoch,
+        "registration would take effect in epoch {next_epoch}, after epoch {valid_until_epoch}"
+    );
+    let (x, y) = schnorr_key_to_sol(state_key);
+    let sig = sign_account(staking_key, account);
+    let tx = stake_table
+        .register(
+            bls_vk.into(),
+            EdOnBN254Point { x, y },
+            amount,
+            NATIVE_STAKE,
+            G1Point { x: sig.x, y: sig.y },
+            valid_until_epoch,
+        )
+        .tx;
+    let receipt = send(&l1, contracts, tx, "stake table registration").await?;
+    let epoch = receipt
+        .logs
+        .iter()
+        .find_map(|log| parse_log::<RegisteredFilter>(log.clone()).ok())
+        .context("registration transaction did not emit Registered")?
+        .register_epoch;
+    tracing::info!(
+        "registered node {} from {}, joining the stake table in epoch {epoch}",
+        BLSPubKey::from_private(staking_key),
+        fmt_address(contracts.explorer(), account)
+    );
+    Ok(epoch)
+}
+
+/// Request that the node with `staking_key` leave the stake table.
+///
+/// The request must be sent from the account which registered the node. Returns the epoch from
+/// which its stake can be withdrawn with [`withdraw_stake`].
+pub async fn request_exit<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &Contracts,
+    staking_key: &BLSPubKey,
+) -> anyhow::Result<u64> {
+    let stake_table = stake_table(&l1, contracts)?;
+    let tx = stake_table
+        .request_exit(bls_key_to_sol(staking_key).into())
+        .tx;
+    let receipt = send(&l1, contracts, tx, "stake table exit").await?;
+    let epoch = receipt
+        .logs
+        .iter()
+        .find_map(|log| parse_log::<ExitFilter>(log.clone()).ok())
+        .context("exit transaction did not emit Exit")?
+        .exit_epoch;
+    tracing::info!("node {staking_key} exits, stake can be withdrawn from epoch {epoch}");
+    Ok(epoch)
+}
+
+/// Withdraw the stake of the node with `staking_key` after it has exited.
+///
+/// Returns the amount of stake tokens returned to the account which registered the node.
+pub async fn withdraw_stake<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &Contracts,
+    staking_key: &BLSPubKey,
+) -> anyhow::Result<u64> {
+    let stake_table = stake_table(&l1, contracts)?;
+    let call = stake_table.withdraw_funds(bls_key_to_sol(staking_key).into());
+    // Simulate the withdrawal to learn the amount, which is not logged.
+    let amount = call.call().await?;
+    send(&l1, contracts, call.tx, "stake table withdrawal").await?;
+    tracing::info!("withdrew {amount} stake tokens of node {staking_key}");
+    Ok(amount)
+}
+
+/// The nodes in the stake table, which have registered and not requested to exit, in order of
+/// registration.
+///
+/// The stake table only logs the hash of the key of each registered node, so the keys are read
+/// back from the registration transactions.
+pub async fn read_stake_table<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &Contracts,
+) -> anyhow::Result<Vec<RegisteredNode>> {
+    let stake_table = stake_table(&l1, contracts)?;
+    let registrations = stake_table
+        .event::<RegisteredFilter>()
+        .from_block(0)
+        .query_with_meta()
+        .await
+        .context("error reading stake table registrations")?;
+
+    let mut nodes = vec![];
+    for (_, meta) in registrations {
+        let tx = l1
+            .get_transaction(meta.transaction_hash)
+            .await?
+            .with_context(|| format!("registration {:#x} not found", meta.transaction_hash))?;
+        let call = RegisterCall::decode(&tx.input).with_context(|| {
+            format!(
+                "registration {:#x} is not a direct call to the stake table",
+                meta.transaction_hash
+            )
+        })?;
+        let node = stake_table.lookup_node(call.bls_vk.clone()).call().await?;
+        if node.account.is_zero() || node.exit_epoch != 0 {
+            continue;
+        }
+        nodes.push(RegisteredNode {
+            account: node.account,
+            staking_key: bls_key_from_sol(call.bls_vk.into())?,
+            state_key: schnorr_key_from_sol(node.schnorr_vk.x, node.schnorr_vk.y)?,
+            balance: node.balance,
+            register_epoch: node.register_epoch,
+        });
+    }
+    Ok(nodes)
+}
+
+fn stake_table<M: Middleware + 'static>(
+    l1: &Arc<M>,
+    contracts: &Contracts,
+) -> anyhow::Result<StakeTable<M>> {
+    let address = contracts.address(Contract::StakeTable).with_context(|| {
+        format!(
+            "stake table address must be given with {}",
+            Contract::StakeTable
+        )
+    })?;
+    Ok(StakeTable::new(address, l1.clone()))
+}
+
+/// Send `tx`, retrying transient failures according to the retry policy of `contracts`.
+async fn send<M: Middleware + 'static>(
+    l1: &Arc<M>,
+    contracts: &Contracts,
+    tx: TypedTransaction,
+    kind: &str,
+) -> anyhow::Result<TransactionReceipt> {
+    let gas = contracts.gas_config;
+    let tx = &tx;
+    send_with_retry(&**l1, contracts, kind, |_| async move {
+        let mut tx = tx.clone();
+        gas.apply(&mut tx);
+        Ok::<_, anyhow::Error>(Some(tx))
+    })
+    .await?
+    .with_context(|| format!("{kind} was never sent"))
+}
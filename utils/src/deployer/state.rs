@@ -0,0 +1,331 @@
+//! Crash-safe persistence of deployment progress.
+//!
+//! With a state file, every contract deployed is recorded on disk as soon as it is deployed, so
+//! that a deployment which is interrupted part way through can be resumed without redeploying
+//! anything. The state file records the chain ID, so that it is never applied to the wrong chain.
+//!
+//! For contracts deployed by this tool, the state file also records the deployment transaction, its
+//! block, and the init code it sent, from which the constructor arguments can be recovered. These
+//! records are carried over when a deployment is resumed, so the file ends up describing every
+//! transaction of the deployment, whichever run sent it.
+
+use super::{dry_run::DeployMode, flavor::Flavor, warnings::Warning, Contract, Contracts};
+use anyhow::{ensure, Context};
+use ethers::types::{Address, Bytes, H256};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The contents of a state file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct State {
+    chain_id: u64,
+    contracts: BTreeMap<Contract, Address>,
+    /// The [`Flavor`] of the deployment which created each contract, for contracts this tool
+    /// deployed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    flavors: BTreeMap<Contract, Flavor>,
+    /// The transaction which deployed each contract, for contracts this tool deployed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    transactions: BTreeMap<Contract, DeploymentTx>,
+}
+
+/// The transaction which deployed a contract, as recorded in a state file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct DeploymentTx {
+    tx_hash: H256,
+    block_number: Option<u64>,
+    /// The init code sent, which is the creation bytecode followed by the ABI-encoded constructor
+    /// arguments.
+    init_code: Option<Bytes>,
+}
+
+/// A state file which deployment progress is flushed to.
+#[derive(Clone, Debug)]
+pub(super) struct StateFile {
+    path: PathBuf,
+    chain_id: u64,
+    /// Transactions recorded by an earlier run, for contracts resumed from the file.
+    resumed: BTreeMap<Contract, DeploymentTx>,
+}
+
+impl StateFile {
+    /// Load the contracts recorded in the state file at `path`, if it exists.
+    fn load(&self) -> anyhow::Result<Option<State>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let state: State = serde_json::from_str(
+            &fs::read_to_string(&self.path)
+                .with_context(|| format!("error reading {}", self.path.display()))?,
+        )
+        .with_context(|| format!("malformed state file {}", self.path.display()))?;
+        ensure!(
+            state.chain_id == self.chain_id,
+            "state file {} was recorded on chain {}, but we are deploying to chain {}",
+            self.path.display(),
+            state.chain_id,
+            self.chain_id
+        );
+        Ok(Some(state))
+    }
+
+    /// Record the contracts in the cache of `contracts`, and the flavor and deployment
+    /// transaction of those this tool deployed, in the state file.
+    ///
+    /// The file is replaced atomically, so that a crash while saving never leaves it corrupted.
+    pub(super) fn save(&self, contracts: &Contracts) -> anyhow::Result<()> {
+        let transactions = contracts
+            .addresses
+            .keys()
+            .filter_map(|&contract| {
+                let tx = match contracts.receipt(contract) {
+                    Some(receipt) => DeploymentTx {
+                        tx_hash: receipt.transaction_hash,
+                        block_number: receipt.block_number.map(|block| block.as_u64()),
+                        init_code: contracts.init_codes.get(&contract).cloned(),
+                    },
+                    None => self.resumed.get(&contract)?.clone(),
+                };
+                Some((contract, tx))
+            })
+            .collect();
+        let state = State {
+            chain_id: self.chain_id,
+            contracts: contracts.addresses.iter().map(|(&c, &a)| (c, a)).collect(),
+            flavors: contracts.flavors.iter().map(|(&c, &f)| (c, f)).collect(),
+            transactions,
+        };
+        let tmp = tmp_path(&self.path);
+        fs::write(&tmp, serde_json::to_string_pretty(&state)?)
+            .with_context(|| format!("error writing {}", tmp.display()))?;
+        fs::rename(&tmp, &self.path)
+            .with_context(|| format!("error writing {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+impl Contracts {
+    /// Persist deployment progress to the state file at `path`.
+    ///
+    /// If the file exists, contracts recorded in it are loaded into the cache, as if they were
+    /// predeployed, unless the cache already has an address for the same contract. Contracts keep
+    /// the [`Flavor`] they were recorded with, so a resumed run cannot reuse them in a deployment
+    /// of a different flavor. It is an error if the file was recorded on a chain other than
+    /// `chain_id`. From then on, each contract is written to the file as soon as it is deployed,
+    /// along with its deployment transaction. In [dry-run mode](DeployMode::DryRun) the file is only
+    /// read, never written.
+    ///
+    /// The init code of resumed contracts is restored from the file, so that they can still be
+    /// [verified](super::verify).
+    pub fn with_state_file(
+        mut self,
+        path: impl Into<PathBuf>,
+        chain_id: u64,
+    ) -> anyhow::Result<Self> {
+        let mut state = StateFile {
+            path: path.into(),
+            chain_id,
+            resumed: BTreeMap::new(),
+        };
+        if let Some(recorded) = state.load()? {
+            for (contract, address) in recorded.contracts {
+                match self.addresses.get(&contract) {
+                    Some(&given) if given != address => self.warn(Warning::StateConflict {
+                        contract,
+                        recorded: address,
+                        given,
+                    }),
+                    Some(_) => {}
+                    None => {
+                        tracing::info!(
+                            "resuming with {contract} at {address:#x} from {}",
+                            state.path.display()
+                        );
+                        self.addresses.insert(contract, address);
+                    }
+                }
+                if self.addresses.get(&contract) == Some(&address) {
+                    if let Some(&flavor) = recorded.flavors.get(&contract) {
+                        self.flavors.insert(contract, flavor);
+                    }
+                    if let Some(tx) = recorded.transactions.get(&contract) {
+                        if let Some(init_code) = &tx.init_code {
+                            self.init_codes.insert(contract, init_code.clone());
+                        }
+                        state.resumed.insert(contract, tx.clone());
+                    }
+                }
+            }
+        }
+        if self.mode == DeployMode::Execute {
+            state.save(&self)?;
+        }
+        self.state_file = Some(state);
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{deployer::test_helpers::TEST_MNEMONIC, init_signer, AnvilOptions};
+    use async_std::sync::Arc;
+    use contract_bindings::{hot_shot::HotShot, plonk_verifier::PlonkVerifier};
+    use futures::FutureExt;
+    use tempfile::TempDir;
+
+    #[async_std::test]
+    async fn test_resume_from_state_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state.json");
+
+        // Deploy one contract, then fail.
+        let mut contracts = Contracts::default().with_state_file(&path, 1).unwrap();
+        contracts
+            .deploy_fn(Contract::PlonkVerifier, |_| {
+                async { Ok(Address::repeat_byte(1)) }.boxed()
+            })
+            .await
+            .unwrap();
+        contracts
+            .deploy_fn(Contract::LightClient, |_| {
+                async { Err(anyhow::anyhow!("crash")) }.boxed()
+            })
+            .await
+            .unwrap_err();
+
+        // Resuming skips the contract deployed in the first run.
+        let mut contracts = Contracts::default().with_state_file(&path, 1).unwrap();
+        assert_eq!(
+            contracts
+                .deploy_fn(Contract::PlonkVerifier, |_| unreachable!())
+                .await
+                .unwrap(),
+            Address::repeat_byte(1)
+        );
+        contracts
+            .deploy_fn(Contract::LightClient, |_| {
+                async { Ok(Address::repeat_byte(2)) }.boxed()
+            })
+            .await
+            .unwrap();
+
+        let state: State = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            state,
+            State {
+                chain_id: 1,
+                contracts: BTreeMap::from([
+                    (Contract::PlonkVerifier, Address::repeat_byte(1)),
+                    (Contract::LightClient, Address::repeat_byte(2)),
+                ]),
+                flavors: BTreeMap::from([
+                    (Contract::PlonkVerifier, Flavor::Production),
+                    (Contract::LightClient, Flavor::Production),
+                ]),
+                transactions: BTreeMap::new(),
+            }
+        );
+    }
+
+    #[async_std::test]
+    async fn test_state_file_records_transactions() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state.json");
+
+        let mut contracts = Contracts::default().with_state_file(&path, 1).unwrap();
+        let hotshot = contracts
+            .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+            .await
+            .unwrap();
+        let receipt = contracts.receipt(Contract::HotShot).unwrap().clone();
+        let init_code = contracts.init_codes[&Contract::HotShot].clone();
+        let expected = DeploymentTx {
+            tx_hash: receipt.transaction_hash,
+            block_number: receipt.block_number.map(|block| block.as_u64()),
+            init_code: Some(init_code.clone()),
+        };
+        let state: State = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(state.contracts[&Contract::HotShot], hotshot);
+        assert_eq!(state.transactions[&Contract::HotShot], expected);
+
+        // The transaction of a resumed contract is kept when the next contract is recorded, and its
+        // init code is restored.
+        let mut contracts = Contracts::default().with_state_file(&path, 1).unwrap();
+        assert_eq!(contracts.init_codes[&Contract::HotShot], init_code);
+        contracts
+            .deploy_tx(
+                Contract::PlonkVerifier,
+                PlonkVerifier::deploy(l1.clone(), ()).unwrap(),
+            )
+            .await
+            .unwrap();
+        let state: State = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(state.transactions[&Contract::HotShot], expected);
+        assert_eq!(
+            state.transactions[&Contract::PlonkVerifier].tx_hash,
+            contracts
+                .receipt(Contract::PlonkVerifier)
+                .unwrap()
+                .transaction_hash
+        );
+
+        // A contract given explicitly does not inherit the recorded transaction.
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::HotShot, Address::repeat_byte(1));
+        let contracts = contracts.with_state_file(&path, 1).unwrap();
+        assert!(!contracts.init_codes.contains_key(&Contract::HotShot));
+        let state: State = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(!state.transactions.contains_key(&Contract::HotShot));
+        assert!(state.transactions.contains_key(&Contract::PlonkVerifier));
+    }
+
+    #[test]
+    fn test_state_file_wrong_chain() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state.json");
+        Contracts::default().with_state_file(&path, 1).unwrap();
+        let err = Contracts::default()
+            .with_state_file(&path, 2)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("recorded on chain 1"), "{err}");
+    }
+
+    #[test]
+    fn test_state_file_given_address_wins() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state.json");
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::HotShot, Address::repeat_byte(1));
+        contracts.with_state_file(&path, 1).unwrap();
+
+        let mut contracts = Contracts::default();
+        contracts.insert(Contract::HotShot, Address::repeat_byte(2));
+        let contracts = contracts.with_state_file(&path, 1).unwrap();
+        assert_eq!(
+            contracts.addresses[&Contract::HotShot],
+            Address::repeat_byte(2)
+        );
+        assert_eq!(
+            contracts.warnings(),
+            [Warning::StateConflict {
+                contract: Contract::HotShot,
+                recorded: Address::repeat_byte(1),
+                given: Address::repeat_byte(2),
+            }]
+        );
+    }
+}
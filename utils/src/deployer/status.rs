@@ -0,0 +1,536 @@
+//! Read-only audits of deployed contracts.
+//!
+//! [`audit`] inspects a single deployment: whether each contract has code, the hash of that code,
+//! the implementation behind each proxy, and the light client's prover configuration.
+//! [`audit_networks`] runs the same audit against several networks concurrently and compares the
+//! results, so that replicated environments which have drifted apart can be spotted at a glance.
+
+use super::{read_proxy_implementation, Contract, Contracts};
+use anyhow::Context;
+use async_std::sync::Arc;
+use contract_bindings::light_client::LightClient;
+use ethers::{prelude::*, utils::keccak256};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    fs::File,
+    io::{BufReader, Write},
+    path::Path,
+    str::FromStr,
+};
+use url::Url;
+
+/// The observed on-chain state of a single contract.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ContractStatus {
+    pub address: Address,
+    /// Hash of the runtime code at `address`, or [`None`] if there is no code.
+    pub code_hash: Option<H256>,
+    /// For proxies, the implementation contract the proxy delegates to.
+    pub implementation: Option<Address>,
+    /// For proxies, hash of the runtime code of the implementation contract.
+    pub implementation_code_hash: Option<H256>,
+}
+
+/// Configuration of a deployed light client.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LightClientStatus {
+    pub owner: Address,
+    pub permissioned_prover_enabled: bool,
+    pub permissioned_prover: Address,
+}
+
+/// The status of all known contracts on one network.
+#[derive(Clone, Debug, Serialize)]
+pub struct NetworkStatus {
+    pub chain_id: u64,
+    pub contracts: BTreeMap<Contract, ContractStatus>,
+    pub light_client: Option<LightClientStatus>,
+}
+
+/// Audit the contracts in `contracts` as deployed on the chain `l1` is connected to.
+pub async fn audit<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &Contracts,
+) -> anyhow::Result<NetworkStatus> {
+    let chain_id = l1
+        .get_chainid()
+        .await
+        .context("error fetching chain ID")?
+        .as_u64();
+
+    let mut statuses = BTreeMap::new();
+    for (&contract, &address) in &contracts.addresses {
+        let code_hash = code_hash(&*l1, address).await?;
+        let (implementation, implementation_code_hash) =
+            if is_proxy(contract) && code_hash.is_some() {
+                let implementation = read_proxy_implementation(&*l1, address).await?;
+                (Some(implementation), code_hash(&*l1, implementation).await?)
+            } else {
+                (None, None)
+            };
+        statuses.insert(
+            contract,
+            ContractStatus {
+                address,
+                code_hash,
+                implementation,
+                implementation_code_hash,
+            },
+        );
+    }
+
+    // Read the light client configuration through the proxy if there is one, otherwise from the
+    // light client contract itself (as is the case for the non-upgradable mock).
+    let light_client = [Contract::LightClientProxy, Contract::LightClient]
+        .into_iter()
+        .find_map(|contract| statuses.get(&contract).filter(|s| s.code_hash.is_some()));
+    let light_client = match light_client {
+        Some(status) => {
+            let contract = LightClient::new(status.address, l1.clone());
+            Some(LightClientStatus {
+                owner: contract
+                    .owner()
+                    .call()
+                    .await
+                    .context("error reading owner")?,
+                permissioned_prover_enabled: contract
+                    .permissioned_prover_enabled()
+                    .call()
+                    .await
+                    .context("error reading permissioned prover mode")?,
+                permissioned_prover: contract
+                    .permissioned_prover()
+                    .call()
+                    .await
+                    .context("error reading permissioned prover")?,
+            })
+        }
+        None => None,
+    };
+
+    Ok(NetworkStatus {
+        chain_id,
+        contracts: statuses,
+        light_client,
+    })
+}
+
+async fn code_hash<M: Middleware + 'static>(
+    l1: &M,
+    address: Address,
+) -> anyhow::Result<Option<H256>> {
+    let code = l1
+        .get_code(address, None)
+        .await
+        .with_context(|| format!("error fetching code at {address:#x}"))?;
+    if code.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(keccak256(&code).into()))
+    }
+}
+
+fn is_proxy(contract: Contract) -> bool {
+    matches!(
+        contract,
+        Contract::LightClientProxy | Contract::FeeContractProxy
+    )
+}
+
+/// A network to audit.
+#[derive(Clone, Debug)]
+pub struct NetworkTarget {
+    pub name: String,
+    pub rpc_url: Url,
+    pub contracts: Contracts,
+}
+
+/// Load the contract addresses listed in a .env file, as written by [`Contracts::write`].
+///
+/// This is [`Contracts::read`] for the file at `path`, so variables which do not name a known
+/// contract are skipped.
+pub fn load_env_file(path: &Path) -> anyhow::Result<Contracts> {
+    let file = File::open(path).with_context(|| format!("error opening {}", path.display()))?;
+    Contracts::read(BufReader::new(file))
+        .with_context(|| format!("error reading {}", path.display()))
+}
+
+/// A `NAME=VALUE` command line argument, scoping some value to a named network.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkArg<T> {
+    pub name: String,
+    pub value: T,
+}
+
+impl<T> FromStr for NetworkArg<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=VALUE, got {s}"))?;
+        if name.is_empty() {
+            return Err(format!("missing network name in {s}"));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            value: value.parse().map_err(|err| format!("{err}"))?,
+        })
+    }
+}
+
+/// The audit result for one network in a multi-network report.
+#[derive(Clone, Debug, Serialize)]
+pub struct NetworkReport {
+    pub rpc_url: Url,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<NetworkStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A property which differs between networks that were expected to be replicas.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    /// Human-readable description of the property, e.g. `LightClient code hash`.
+    pub property: String,
+    /// The value of the property on each network where it was observed.
+    pub values: BTreeMap<String, String>,
+}
+
+/// Consolidated report of audits across several networks.
+#[derive(Clone, Debug, Serialize)]
+pub struct StatusReport {
+    pub networks: BTreeMap<String, NetworkReport>,
+    pub divergences: Vec<Divergence>,
+}
+
+/// Audit several networks concurrently.
+///
+/// At most `parallelism` networks are audited at once. A failure to audit one network is recorded
+/// in its entry in the report and does not prevent the others from being audited.
+pub async fn audit_networks(targets: Vec<NetworkTarget>, parallelism: usize) -> StatusReport {
+    let networks = stream::iter(targets)
+        .map(|target| async move {
+            let status: anyhow::Result<NetworkStatus> = async {
+                let provider = Provider::<Http>::try_from(target.rpc_url.to_string())?;
+                audit(Arc::new(provider), &target.contracts).await
+            }
+            .await;
+            let report = match status {
+                Ok(status) => NetworkReport {
+                    rpc_url: target.rpc_url,
+                    status: Some(status),
+                    error: None,
+                },
+                Err(err) => {
+                    tracing::warn!("failed to audit network {}: {err:#}", target.name);
+                    NetworkReport {
+                        rpc_url: target.rpc_url,
+                        status: None,
+                        error: Some(format!("{err:#}")),
+                    }
+                }
+            };
+            (target.name, report)
+        })
+        .buffer_unordered(parallelism.max(1))
+        .collect::<BTreeMap<_, _>>()
+        .await;
+    let divergences = find_divergences(&networks);
+    StatusReport {
+        networks,
+        divergences,
+    }
+}
+
+/// Compare the successfully audited networks in `networks` and list the properties which differ.
+///
+/// Only properties observed on at least two networks are compared; a contract which is simply
+/// missing from one network's inputs is not a divergence.
+pub fn find_divergences(networks: &BTreeMap<String, NetworkReport>) -> Vec<Divergence> {
+    let mut properties: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    let mut observe = |property: String, network: &str, value: String| {
+        properties
+            .entry(property)
+            .or_default()
+            .insert(network.to_string(), value);
+    };
+
+    for (network, report) in networks {
+        let Some(status) = &report.status else {
+            continue;
+        };
+        for (contract, c) in &status.contracts {
+            observe(
+                format!("{contract} code hash"),
+                network,
+                fmt_hash(c.code_hash),
+            );
+            if is_proxy(*contract) {
+                observe(
+                    format!("{contract} implementation code hash"),
+                    network,
+                    fmt_hash(c.implementation_code_hash),
+                );
+            }
+        }
+        if let Some(lc) = &status.light_client {
+            observe(
+                "light client permissioned prover".into(),
+                network,
+                if lc.permissioned_prover_enabled {
+                    format!("{:#x}", lc.permissioned_prover)
+                } else {
+                    "disabled".into()
+                },
+            );
+        }
+    }
+
+    properties
+        .into_iter()
+        .filter(|(_, values)| {
+            values.len() > 1 && values.values().collect::<BTreeSet<_>>().len() > 1
+        })
+        .map(|(property, values)| Divergence { property, values })
+        .collect()
+}
+
+fn fmt_hash(hash: Option<H256>) -> String {
+    match hash {
+        Some(hash) => format!("{hash:#x}"),
+        None => "no code".into(),
+    }
+}
+
+impl StatusReport {
+    /// Whether every network was audited successfully and all networks agree.
+    pub fn is_consistent(&self) -> bool {
+        self.divergences.is_empty() && self.networks.values().all(|n| n.error.is_none())
+    }
+
+    /// Write the report as a human-readable table.
+    pub fn write_table(&self, mut w: impl Write) -> anyhow::Result<()> {
+        writeln!(
+            w,
+            "{:<16} {:<56} {:<44} {:<68}",
+            "NETWORK", "CONTRACT", "ADDRESS", "CODE HASH"
+        )?;
+        for (network, report) in &self.networks {
+            let status = match (&report.status, &report.error) {
+                (Some(status), _) => status,
+                (None, err) => {
+                    writeln!(
+                        w,
+                        "{network:<16} ERROR: {}",
+                        err.as_deref().unwrap_or("unknown error")
+                    )?;
+                    continue;
+                }
+            };
+            for (contract, c) in &status.contracts {
+                writeln!(
+                    w,
+                    "{network:<16} {:<56} {:<44} {:<68}",
+                    contract.to_string(),
+                    format!("{:#x}", c.address),
+                    fmt_hash(c.code_hash),
+                )?;
+                if let Some(implementation) = c.implementation {
+                    writeln!(
+                        w,
+                        "{network:<16} {:<56} {:<44} {:<68}",
+                        "  -> implementation",
+                        format!("{implementation:#x}"),
+                        fmt_hash(c.implementation_code_hash),
+                    )?;
+                }
+            }
+            if let Some(lc) = &status.light_client {
+                writeln!(
+                    w,
+                    "{network:<16} light client owner {:#x}, permissioned prover {}",
+                    lc.owner,
+                    if lc.permissioned_prover_enabled {
+                        format!("{:#x}", lc.permissioned_prover)
+                    } else {
+                        "disabled".into()
+                    }
+                )?;
+            }
+        }
+
+        if self.divergences.is_empty() {
+            writeln!(w, "\nno divergences between networks")?;
+        } else {
+            writeln!(w, "\nDIVERGENCES")?;
+            for divergence in &self.divergences {
+                writeln!(w, "{}", divergence.property)?;
+                for (network, value) in &divergence.values {
+                    writeln!(w, "  {network:<16} {value}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the report as JSON.
+    pub fn write_json(&self, w: impl Write) -> anyhow::Result<()> {
+        serde_json::to_writer_pretty(w, self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{deploy_mock_light_client_contract, test_helpers::TEST_MNEMONIC},
+        init_signer, AnvilOptions,
+    };
+    use contract_bindings::{hot_shot::HotShot, plonk_verifier::PlonkVerifier};
+    use futures::{FutureExt, TryFutureExt};
+
+    #[test]
+    fn test_network_arg() {
+        let arg: NetworkArg<Url> = "sepolia=http://localhost:8545".parse().unwrap();
+        assert_eq!(arg.name, "sepolia");
+        assert_eq!(arg.value, Url::parse("http://localhost:8545").unwrap());
+
+        "http://localhost:8545"
+            .parse::<NetworkArg<Url>>()
+            .unwrap_err();
+        "=http://localhost:8545"
+            .parse::<NetworkArg<Url>>()
+            .unwrap_err();
+        "sepolia=not a url".parse::<NetworkArg<Url>>().unwrap_err();
+    }
+
+    #[test]
+    fn test_load_env_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(".env");
+        std::fs::write(
+            &path,
+            format!(
+                "# Deployed contracts\nESPRESSO_SEQUENCER_HOTSHOT_ADDRESS={:#x}\nRUST_LOG=info\n",
+                Address::repeat_byte(1)
+            ),
+        )
+        .unwrap();
+        let contracts = load_env_file(&path).unwrap();
+        assert_eq!(
+            contracts.address(Contract::HotShot),
+            Some(Address::repeat_byte(1))
+        );
+        assert_eq!(contracts.addresses.len(), 1);
+
+        // Errors name the file.
+        std::fs::write(&path, "ESPRESSO_SEQUENCER_HOTSHOT_ADDRESS=0x1234\n").unwrap();
+        let err = format!("{:#}", load_env_file(&path).unwrap_err());
+        assert!(err.contains(".env"), "{err}");
+        assert!(err.contains("line 1"), "{err}");
+        load_env_file(&dir.path().join("missing.env")).unwrap_err();
+    }
+
+    #[async_std::test]
+    async fn test_audit_divergent_networks() {
+        let anvil_a = AnvilOptions::default().spawn().await;
+        let anvil_b = AnvilOptions::default().spawn().await;
+
+        // Deploy the same light client to both networks, but deploy the wrong contract as HotShot
+        // on the second.
+        let mut targets = vec![];
+        for (name, anvil) in [("a", &anvil_a), ("b", &anvil_b)] {
+            let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+            // The decoy HotShot on the second network has the same bytecode as the plonk verifier
+            // the light client needs, which the cache would otherwise reject.
+            let mut contracts = Contracts::default().allow_duplicate_bytecode(name == "b");
+            if name == "a" {
+                contracts
+                    .deploy_tx(Contract::HotShot, HotShot::deploy(l1.clone(), ()).unwrap())
+                    .await
+                    .unwrap();
+            } else {
+                contracts
+                    .deploy_tx(
+                        Contract::HotShot,
+                        PlonkVerifier::deploy(l1.clone(), ()).unwrap(),
+                    )
+                    .await
+                    .unwrap();
+            }
+            contracts
+                .deploy_fn(Contract::LightClient, |contracts| {
+                    deploy_mock_light_client_contract(l1.clone(), contracts, None)
+                        .err_into()
+                        .boxed()
+                })
+                .await
+                .unwrap();
+            targets.push(NetworkTarget {
+                name: name.into(),
+                rpc_url: anvil.url(),
+                contracts,
+            });
+        }
+
+        // Add a network which cannot be reached. It should be reported, but not stop the others.
+        targets.push(NetworkTarget {
+            name: "unreachable".into(),
+            rpc_url: "http://localhost:1".parse().unwrap(),
+            contracts: Contracts::default(),
+        });
+
+        let report = audit_networks(targets, 2).await;
+        assert!(!report.is_consistent());
+        assert!(report.networks["unreachable"].error.is_some());
+
+        let a = report.networks["a"].status.as_ref().unwrap();
+        let b = report.networks["b"].status.as_ref().unwrap();
+        assert_eq!(a.contracts.len(), 4);
+        for status in a.contracts.values().chain(b.contracts.values()) {
+            assert!(status.code_hash.is_some(), "{status:?}");
+        }
+        // Identical contracts deployed identically have identical code.
+        assert_eq!(
+            a.contracts[&Contract::LightClient].code_hash,
+            b.contracts[&Contract::LightClient].code_hash
+        );
+        assert_eq!(a.light_client, b.light_client);
+
+        assert_eq!(report.divergences.len(), 1);
+        let divergence = &report.divergences[0];
+        assert_eq!(
+            divergence.property,
+            format!("{} code hash", Contract::HotShot)
+        );
+        assert_eq!(
+            divergence.values["a"],
+            fmt_hash(a.contracts[&Contract::HotShot].code_hash)
+        );
+        assert_eq!(
+            divergence.values["b"],
+            fmt_hash(b.contracts[&Contract::HotShot].code_hash)
+        );
+
+        // Both output formats render.
+        let mut table = vec![];
+        report.write_table(&mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert!(table.contains("DIVERGENCES"), "{table}");
+        assert!(table.contains("unreachable"), "{table}");
+        let mut json = vec![];
+        report.write_json(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["divergences"].as_array().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,365 @@
+//! Rendering of downstream config templates with deployed contract addresses.
+//!
+//! Services that consume the deployment output often have config files containing placeholders
+//! like `{{LIGHT_CLIENT_PROXY}}`. [`TemplateVars`] substitutes those placeholders with the
+//! addresses in a [`Contracts`] cache, plus any extra variables registered by the caller.
+//!
+//! # Syntax
+//!
+//! * A placeholder is `{{NAME}}`, where `NAME` is a non-empty sequence of ASCII letters, digits and
+//!   underscores. Whitespace directly inside the braces is ignored, so `{{ NAME }}` is the same
+//!   placeholder. Names are case-sensitive.
+//! * Each contract is available under its env var name with the `ESPRESSO_SEQUENCER_` prefix and
+//!   `_ADDRESS` suffix stripped, e.g. `{{LIGHT_CLIENT_PROXY}}` or `{{PLONK_VERIFIER}}`, and renders
+//!   as an EIP-55 checksummed address.
+//! * Each contract deployed in this run is also available as `{{DEPLOY_BLOCK_<NAME>}}`, e.g.
+//!   `{{DEPLOY_BLOCK_LIGHT_CLIENT_PROXY}}`, which renders as the number of the block its deployment
+//!   transaction was mined in, taken from the recorded [receipts](Contracts::receipts). Predeployed
+//!   contracts have no receipt, and so no such variable.
+//! * `\{{` renders as a literal `{{` and is not interpreted as the start of a placeholder. The
+//!   backslash is consumed.
+//! * A `{{` whose contents up to the next `}}` are not a valid name, or which has no closing `}}`,
+//!   is copied to the output verbatim.
+//! * A well-formed placeholder with no corresponding variable is an error. Rendering reports every
+//!   unresolved placeholder in the template at once, rather than stopping at the first.
+
+use super::{Contract, Contracts};
+use anyhow::{ensure, Context};
+use ethers::utils::to_checksum;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Variables available for substitution in a config template.
+#[derive(Clone, Debug, Default)]
+pub struct TemplateVars(BTreeMap<String, String>);
+
+impl TemplateVars {
+    /// Variables for each contract in `contracts`.
+    pub fn new(contracts: &Contracts) -> Self {
+        let mut vars = Self::default();
+        for (contract, address) in &contracts.addresses {
+            vars.0
+                .insert(placeholder_name(*contract), to_checksum(address, None));
+        }
+        for (contract, receipt) in contracts.receipts() {
+            if let Some(block) = receipt.block_number {
+                vars.0.insert(
+                    format!("DEPLOY_BLOCK_{}", placeholder_name(*contract)),
+                    block.to_string(),
+                );
+            }
+        }
+        vars
+    }
+
+    /// Make the chain ID available as `{{CHAIN_ID}}`.
+    pub fn chain_id(self, chain_id: u64) -> Self {
+        self.set("CHAIN_ID", chain_id)
+    }
+
+    /// Set an arbitrary variable, replacing any existing variable with the same name.
+    pub fn set(mut self, name: impl Into<String>, value: impl ToString) -> Self {
+        self.0.insert(name.into(), value.to_string());
+        self
+    }
+
+    /// Get the value of a variable.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    /// Substitute placeholders in `template`.
+    ///
+    /// Fails, listing every unresolved placeholder, if the template references a variable which is
+    /// not set.
+    pub fn render(&self, template: &str) -> anyhow::Result<String> {
+        let mut out = String::with_capacity(template.len());
+        let mut unresolved = BTreeSet::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            // An escaped `{{` is emitted literally, minus the escape character.
+            if rest[..start].ends_with('\\') {
+                out.push_str(&rest[..start - 1]);
+                out.push_str("{{");
+                rest = &rest[start + 2..];
+                continue;
+            }
+
+            out.push_str(&rest[..start]);
+            let inner = &rest[start + 2..];
+            let Some(end) = inner.find("}}") else {
+                // Unterminated, copy the remainder verbatim.
+                out.push_str(&rest[start..]);
+                rest = "";
+                break;
+            };
+            let name = inner[..end].trim();
+            if !is_valid_name(name) {
+                // Not a placeholder. Emit the opening braces and keep scanning after them, so that
+                // a valid placeholder nested in the junk is still found.
+                out.push_str("{{");
+                rest = inner;
+                continue;
+            }
+            match self.0.get(name) {
+                Some(value) => out.push_str(value),
+                None => {
+                    unresolved.insert(name.to_string());
+                }
+            }
+            rest = &inner[end + 2..];
+        }
+        out.push_str(rest);
+
+        ensure!(
+            unresolved.is_empty(),
+            "unresolved placeholders: {}",
+            unresolved
+                .iter()
+                .map(|name| format!("{{{{{name}}}}}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Ok(out)
+    }
+
+    /// Render the template at `template` and write the result to `out`.
+    pub fn render_file(&self, template: &Path, out: &Path) -> anyhow::Result<()> {
+        let contents = fs::read_to_string(template)
+            .with_context(|| format!("reading template {}", template.display()))?;
+        let rendered = self
+            .render(&contents)
+            .with_context(|| format!("rendering template {}", template.display()))?;
+        fs::write(out, rendered).with_context(|| format!("writing {}", out.display()))?;
+        tracing::info!("rendered {} to {}", template.display(), out.display());
+        Ok(())
+    }
+}
+
+/// A template file and the path to write its rendered contents to.
+///
+/// Parsed from a string of the form `TEMPLATE:OUT`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderTarget {
+    pub template: PathBuf,
+    pub out: PathBuf,
+}
+
+impl FromStr for RenderTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (template, out) = s
+            .split_once(':')
+            .context("render target must have the form TEMPLATE:OUT")?;
+        ensure!(
+            !template.is_empty() && !out.is_empty(),
+            "render target must have the form TEMPLATE:OUT"
+        );
+        Ok(Self {
+            template: template.into(),
+            out: out.into(),
+        })
+    }
+}
+
+impl RenderTarget {
+    pub fn render(&self, vars: &TemplateVars) -> anyhow::Result<()> {
+        vars.render_file(&self.template, &self.out)
+    }
+}
+
+/// The name of the placeholder for `contract`, e.g. `LIGHT_CLIENT_PROXY`.
+pub fn placeholder_name(contract: Contract) -> String {
+    let var = contract.to_string();
+    let name = var.strip_prefix("ESPRESSO_SEQUENCER_").unwrap_or(&var);
+    name.strip_suffix("_ADDRESS").unwrap_or(name).to_string()
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::types::{Address, TransactionReceipt};
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    fn vars() -> TemplateVars {
+        let contracts = Contracts {
+            addresses: HashMap::from([
+                (Contract::LightClientProxy, Address::repeat_byte(0xab)),
+                (Contract::HotShot, Address::repeat_byte(0x01)),
+            ]),
+            // The HotShot contract was predeployed, and has no receipt.
+            receipts: vec![(
+                Contract::LightClientProxy,
+                TransactionReceipt {
+                    block_number: Some(1234.into()),
+                    ..Default::default()
+                },
+            )],
+            ..Default::default()
+        };
+        TemplateVars::new(&contracts).chain_id(31337)
+    }
+
+    #[test]
+    fn test_placeholder_names() {
+        assert_eq!(placeholder_name(Contract::HotShot), "HOTSHOT");
+        assert_eq!(
+            placeholder_name(Contract::LightClientProxy),
+            "LIGHT_CLIENT_PROXY"
+        );
+        assert_eq!(
+            placeholder_name(Contract::StateUpdateVK),
+            "LIGHT_CLIENT_STATE_UPDATE_VK"
+        );
+    }
+
+    #[test]
+    fn test_render_substitution() {
+        let vars = vars();
+        let proxy = to_checksum(&Address::repeat_byte(0xab), None);
+        assert_eq!(
+            vars.render("addr={{LIGHT_CLIENT_PROXY}} chain={{ CHAIN_ID }}")
+                .unwrap(),
+            format!("addr={proxy} chain=31337")
+        );
+        // Adjacent placeholders and placeholders at the edges of the template.
+        assert_eq!(
+            vars.render("{{CHAIN_ID}}{{CHAIN_ID}}").unwrap(),
+            "3133731337"
+        );
+        // No placeholders at all.
+        assert_eq!(vars.render("plain text").unwrap(), "plain text");
+    }
+
+    #[test]
+    fn test_render_escaping() {
+        let vars = vars();
+        assert_eq!(
+            vars.render(r"\{{CHAIN_ID}} {{CHAIN_ID}}").unwrap(),
+            "{{CHAIN_ID}} 31337"
+        );
+        // An escaped placeholder referencing an unknown variable is not an error.
+        assert_eq!(vars.render(r"\{{UNKNOWN}}").unwrap(), "{{UNKNOWN}}");
+    }
+
+    #[test]
+    fn test_render_literal_braces() {
+        let vars = vars();
+        // Contents which are not a valid name are left alone.
+        assert_eq!(vars.render("{{a-b}}").unwrap(), "{{a-b}}");
+        assert_eq!(vars.render("{{}}").unwrap(), "{{}}");
+        // Unterminated braces are copied verbatim.
+        assert_eq!(vars.render("x {{CHAIN_ID").unwrap(), "x {{CHAIN_ID");
+        // Single braces are never interpreted.
+        assert_eq!(vars.render("{CHAIN_ID}").unwrap(), "{CHAIN_ID}");
+        // A valid placeholder following junk braces is still substituted.
+        assert_eq!(vars.render("{{ {{CHAIN_ID}}").unwrap(), "{{ 31337");
+    }
+
+    #[test]
+    fn test_render_unresolved() {
+        let vars = vars();
+        let err = vars
+            .render("{{FEE_CONTRACT}} {{CHAIN_ID}} {{PLONK_VERIFIER}} {{FEE_CONTRACT}}")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "unresolved placeholders: {{FEE_CONTRACT}}, {{PLONK_VERIFIER}}"
+        );
+
+        // Names are case-sensitive.
+        vars.render("{{chain_id}}").unwrap_err();
+    }
+
+    #[test]
+    fn test_render_target_parse() {
+        let target: RenderTarget = "in.toml.tmpl:out/in.toml".parse().unwrap();
+        assert_eq!(target.template, PathBuf::from("in.toml.tmpl"));
+        assert_eq!(target.out, PathBuf::from("out/in.toml"));
+
+        "in.toml".parse::<RenderTarget>().unwrap_err();
+        ":out".parse::<RenderTarget>().unwrap_err();
+        "in:".parse::<RenderTarget>().unwrap_err();
+    }
+
+    #[test]
+    fn test_render_prover_config() {
+        let dir = TempDir::new().unwrap();
+        let template = dir.path().join("prover.toml.tmpl");
+        let out = dir.path().join("prover.toml");
+        fs::write(
+            &template,
+            r#"# State prover configuration, generated at deploy time.
+# Literal handlebars used by a downstream tool: \{{ prover_name }}
+[l1]
+provider = "http://localhost:8545"
+chain_id = {{CHAIN_ID}}
+
+[light_client]
+address = "{{ LIGHT_CLIENT_PROXY }}"
+deploy_block = {{DEPLOY_BLOCK_LIGHT_CLIENT_PROXY}}
+
+[sequencer]
+hotshot_address = "{{HOTSHOT}}"
+"#,
+        )
+        .unwrap();
+
+        let target = RenderTarget {
+            template,
+            out: out.clone(),
+        };
+        target.render(&vars()).unwrap();
+
+        let proxy = to_checksum(&Address::repeat_byte(0xab), None);
+        let hotshot = to_checksum(&Address::repeat_byte(0x01), None);
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            format!(
+                r#"# State prover configuration, generated at deploy time.
+# Literal handlebars used by a downstream tool: {{{{ prover_name }}}}
+[l1]
+provider = "http://localhost:8545"
+chain_id = 31337
+
+[light_client]
+address = "{proxy}"
+deploy_block = 1234
+
+[sequencer]
+hotshot_address = "{hotshot}"
+"#
+            )
+        );
+
+        // A template with an unresolved placeholder fails and names the template.
+        let bad = dir.path().join("bad.tmpl");
+        fs::write(&bad, "{{FEE_CONTRACT}}").unwrap();
+        let err = vars()
+            .render_file(&bad, &dir.path().join("bad"))
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("{{FEE_CONTRACT}}"), "{err:#}");
+        assert!(format!("{err:#}").contains("bad.tmpl"), "{err:#}");
+
+        // So does one which needs the deploy block of a predeployed contract.
+        fs::write(&bad, "{{DEPLOY_BLOCK_HOTSHOT}}").unwrap();
+        let err = vars()
+            .render_file(&bad, &dir.path().join("bad"))
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("{{DEPLOY_BLOCK_HOTSHOT}}"),
+            "{err:#}"
+        );
+    }
+}
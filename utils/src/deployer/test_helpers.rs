@@ -0,0 +1,317 @@
+//! A local chain with the light client stack deployed, for tests.
+//!
+//! Integration tests which need a light client on an L1 all start the same way: start Anvil,
+//! connect a wallet to it, deploy the mock light client and remember its address.
+//! [`DeployedTestStack::launch`] does all of this in one call, using the same deployment functions
+//! as production, and keeps the Anvil instance running for as long as the stack is alive. In CI
+//! environments which run Anvil as a service, it can instead attach to an existing RPC.
+//!
+//! Tests of contracts owned by a Gnosis Safe can stand one in with [`deploy_safe_stub`], and execute
+//! the transactions prepared for it with [`execute_safe_batch`].
+//!
+//! This module is only available with the `testing` feature.
+
+use super::{
+    deploy_light_client, params::LightClientDeployParams, safe::SafeBatch, Contracts,
+    LightClientKind,
+};
+use crate::{init_signer, Anvil, AnvilOptions, Signer};
+use anyhow::{ensure, Context};
+use async_std::sync::Arc;
+use contract_bindings::light_client_mock::LightClientMock;
+use ethers::{
+    prelude::*,
+    utils::{id, parse_ether},
+};
+use hotshot_contract_adapter::light_client::ParsedLightClientState;
+use std::time::Duration;
+use url::Url;
+
+/// The mnemonic of the accounts Anvil funds by default.
+pub const TEST_MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+/// Options for [`DeployedTestStack::launch`].
+#[derive(Clone, Debug)]
+pub struct TestStackOptions {
+    anvil: AnvilOptions,
+    rpc_url: Option<Url>,
+    mnemonic: String,
+    account_index: u32,
+    genesis: Option<ParsedLightClientState>,
+    blocks_per_epoch: u32,
+}
+
+impl Default for TestStackOptions {
+    fn default() -> Self {
+        Self {
+            anvil: Default::default(),
+            rpc_url: None,
+            mnemonic: TEST_MNEMONIC.into(),
+            account_index: 0,
+            genesis: None,
+            blocks_per_epoch: u32::MAX,
+        }
+    }
+}
+
+impl TestStackOptions {
+    /// Spawn Anvil with `anvil`, unless attaching to an [existing RPC](Self::rpc_url).
+    pub fn anvil(mut self, anvil: AnvilOptions) -> Self {
+        self.anvil = anvil;
+        self
+    }
+
+    /// Mine a block every `time` instead of on every transaction.
+    pub fn block_time(mut self, time: Duration) -> Self {
+        self.anvil = self.anvil.block_time(time);
+        self
+    }
+
+    /// Fund `accounts` accounts instead of the default 20.
+    pub fn accounts(mut self, accounts: usize) -> Self {
+        self.anvil = self.anvil.accounts(accounts);
+        self
+    }
+
+    /// Deploy to the chain at `url` instead of spawning Anvil.
+    pub fn rpc_url(mut self, url: Url) -> Self {
+        self.rpc_url = Some(url);
+        self
+    }
+
+    /// Deploy from the wallet generated by `mnemonic`, instead of the default Anvil mnemonic.
+    pub fn mnemonic(mut self, mnemonic: impl Into<String>) -> Self {
+        self.mnemonic = mnemonic.into();
+        self
+    }
+
+    /// Deploy from account `index` of the wallet.
+    pub fn account_index(mut self, index: u32) -> Self {
+        self.account_index = index;
+        self
+    }
+
+    /// Initialize the light client with `genesis` instead of a dummy genesis state.
+    pub fn genesis(mut self, genesis: ParsedLightClientState) -> Self {
+        self.genesis = Some(genesis);
+        self
+    }
+
+    /// Initialize the light client with `blocks` blocks per epoch.
+    pub fn blocks_per_epoch(mut self, blocks: u32) -> Self {
+        self.blocks_per_epoch = blocks;
+        self
+    }
+}
+
+/// A local chain with the mock light client and its libraries deployed.
+///
+/// If Anvil was spawned for this stack, it is shut down when the stack is dropped.
+#[derive(Debug)]
+pub struct DeployedTestStack {
+    /// The deployer wallet, connected to the chain.
+    pub l1: Arc<Signer>,
+    /// The deployed contracts.
+    pub contracts: Contracts,
+    /// The light client.
+    pub light_client: LightClientMock<Signer>,
+    url: Url,
+    anvil: Option<Anvil>,
+}
+
+impl DeployedTestStack {
+    /// Start a chain, or attach to one, and deploy the light client stack to it.
+    pub async fn launch(opt: TestStackOptions) -> anyhow::Result<Self> {
+        let (url, anvil) = match opt.rpc_url {
+            Some(url) => (url, None),
+            None => {
+                let anvil = opt.anvil.spawn().await;
+                (anvil.url(), Some(anvil))
+            }
+        };
+        let l1 = Arc::new(
+            init_signer(&url, &opt.mnemonic, opt.account_index)
+                .await
+                .with_context(|| format!("error connecting to L1 at {url}"))?,
+        );
+
+        let mut contracts =
+            Contracts::default().with_light_client_params(LightClientDeployParams {
+                blocks_per_epoch: opt.blocks_per_epoch,
+                ..Default::default()
+            })?;
+        let args = opt
+            .genesis
+            .map(|genesis| (genesis.into(), opt.blocks_per_epoch));
+        let address = deploy_light_client(
+            l1.clone(),
+            &mut contracts,
+            LightClientKind::Mock {
+                constructor_args: args,
+            },
+        )
+        .await?;
+        let light_client = LightClientMock::new(address, l1.clone());
+
+        Ok(Self {
+            l1,
+            contracts,
+            light_client,
+            url,
+            anvil,
+        })
+    }
+
+    /// The URL of the chain.
+    pub fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    /// The Anvil instance spawned for this stack, if any.
+    ///
+    /// This can be used, for example, to restart the chain or force a reorg.
+    pub fn anvil(&mut self) -> Option<&mut Anvil> {
+        self.anvil.as_mut()
+    }
+}
+
+/// Runtime code for a minimal stand-in for a Safe, implementing the view functions used to
+/// [detect](super::safe::detect_safe) a Safe: `getThreshold()` returns 2 and `VERSION()` returns
+/// "1.3.0". Everything else reverts.
+fn safe_stub_code() -> Vec<u8> {
+    let mut code = vec![
+        0x60, 0x00, 0x35, 0x60, 0xe0, 0x1c, // selector = calldata[0..4]
+        0x80, 0x63, // dup, push4 getThreshold()
+    ];
+    code.extend(id("getThreshold()"));
+    code.extend([0x14, 0x60, 29, 0x57, 0x63]); // eq, jumpi 29, push4 VERSION()
+    code.extend(id("VERSION()"));
+    code.extend([
+        0x14, 0x60, 40, 0x57, // eq, jumpi 40
+        0x60, 0x00, 0x80, 0xfd, // revert
+        // 29: return uint256(2)
+        0x5b, 0x60, 0x02, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+        // 40: return "1.3.0"
+        0x5b, 0x60, 0x20, 0x60, 0x00, 0x52, // offset
+        0x60, 0x05, 0x60, 0x20, 0x52, // length
+        0x64, b'1', b'.', b'3', b'.', b'0', 0x60, 0xd8, 0x1b, 0x60, 0x40, 0x52, // data
+        0x60, 0x60, 0x60, 0x00, 0xf3,
+    ]);
+    code
+}
+
+/// Deploy a stand-in for a Safe from `l1`, returning its address.
+///
+/// The stand-in is detected as a Safe, but cannot execute transactions itself; use
+/// [`execute_safe_batch`] to execute the transactions prepared for it.
+pub async fn deploy_safe_stub(l1: &Signer) -> anyhow::Result<Address> {
+    let code = safe_stub_code();
+    // Init code returning the runtime code which follows it: push1 len, dup, push1 11 (the length
+    // of the init code), push1 0, codecopy, push1 0, return.
+    let len = code.len() as u8;
+    let mut init_code = vec![
+        0x60, len, 0x80, 0x60, 0x0b, 0x60, 0x00, 0x39, 0x60, 0x00, 0xf3,
+    ];
+    init_code.extend(code);
+    let receipt = l1
+        .send_transaction(TransactionRequest::new().data(init_code), None)
+        .await
+        .context("error deploying Safe stub")?
+        .await?
+        .context("Safe stub deployment dropped")?;
+    receipt
+        .contract_address
+        .context("Safe stub deployment did not create a contract")
+}
+
+/// Execute the transactions in `batch` from the Safe which the batch was prepared for.
+///
+/// This is what executing the batch through the Safe amounts to once its owners have signed it: the
+/// transactions are sent from the Safe's address, which is impersonated on the Anvil chain `l1` is
+/// connected to.
+pub async fn execute_safe_batch(
+    l1: &Signer,
+    batch: &SafeBatch,
+) -> anyhow::Result<Vec<TransactionReceipt>> {
+    let safe = batch.meta.created_from_safe_address;
+    let provider = l1.provider().clone().with_sender(safe);
+    provider
+        .request::<_, ()>("anvil_impersonateAccount", [safe])
+        .await
+        .context("error impersonating Safe")?;
+    provider
+        .request::<_, ()>("anvil_setBalance", (safe, parse_ether(1)?))
+        .await
+        .context("error funding Safe")?;
+
+    let mut receipts = vec![];
+    for tx in &batch.transactions {
+        let receipt = provider
+            .send_transaction(
+                TransactionRequest::new()
+                    .from(safe)
+                    .to(tx.to)
+                    .value(tx.value)
+                    .data(tx.data.clone()),
+                None,
+            )
+            .await
+            .context("error sending Safe transaction")?
+            .await?
+            .context("Safe transaction dropped")?;
+        ensure!(
+            receipt.status == Some(1.into()),
+            "Safe transaction to {:#x} reverted",
+            tx.to
+        );
+        receipts.push(receipt);
+    }
+    Ok(receipts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deployer::Contract;
+    use ethers::prelude::*;
+
+    #[async_std::test]
+    async fn test_launch_stack() {
+        let stack = DeployedTestStack::launch(Default::default()).await.unwrap();
+        for contract in [
+            Contract::PlonkVerifier,
+            Contract::StateUpdateVK,
+            Contract::LightClient,
+        ] {
+            let address = stack.contracts.address(contract).unwrap();
+            assert!(!stack.l1.get_code(address, None).await.unwrap().is_empty());
+        }
+        let state = stack
+            .light_client
+            .get_finalized_state()
+            .call()
+            .await
+            .unwrap();
+        assert_eq!(
+            state.block_height,
+            ParsedLightClientState::dummy_genesis().block_height
+        );
+
+        // Attach another stack to the same chain.
+        let attached = DeployedTestStack::launch(
+            TestStackOptions::default()
+                .rpc_url(stack.url())
+                .account_index(1),
+        )
+        .await
+        .unwrap();
+        assert_ne!(
+            attached.light_client.address(),
+            stack.light_client.address()
+        );
+        assert_eq!(
+            attached.l1.get_chainid().await.unwrap(),
+            stack.l1.get_chainid().await.unwrap()
+        );
+    }
+}
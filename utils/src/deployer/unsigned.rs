@@ -0,0 +1,203 @@
+//! Preparing deployment transactions for offline signing.
+//!
+//! When the deployer key never touches an online machine, [`Contracts::with_unsigned_output`] turns
+//! a [dry run](super::dry_run) into the first half of a deployment. Instead of a placeholder
+//! address, each contract which would be deployed is assigned the next nonce of the offline
+//! sender, so it gets the address it will really have, and transactions depending on it, like the
+//! light client linking its libraries, are built against that address. Each transaction is filled
+//! in with its nonce, chain ID, gas limit and fees, and written unsigned to its own numbered file
+//! in the output directory, to be signed offline and broadcast in order.
+
+use super::{dry_run::DeployMode, fees::fmt_gwei, Contracts};
+use anyhow::Context;
+use ethers::{
+    prelude::*, types::transaction::eip2718::TypedTransaction, utils::get_contract_address,
+};
+use serde_json::json;
+use std::{fs, path::PathBuf};
+
+/// Where unsigned transactions are written, and who will sign them.
+#[derive(Clone, Debug)]
+pub(super) struct UnsignedOutput {
+    dir: PathBuf,
+    sender: Address,
+    /// The nonce for the next transaction, once it has been fetched.
+    nonce: Option<U256>,
+    /// The number of transactions written so far.
+    written: usize,
+}
+
+impl Contracts {
+    /// Write each transaction, unsigned, to a file in `dir` instead of sending it.
+    ///
+    /// This implies [dry-run mode](DeployMode::DryRun). The transactions are prepared to be signed
+    /// by `sender`, with consecutive nonces starting from its pending nonce, and contracts are
+    /// given the addresses those transactions will deploy them to.
+    pub fn with_unsigned_output(mut self, dir: impl Into<PathBuf>, sender: Address) -> Self {
+        self.mode = DeployMode::DryRun;
+        self.unsigned = Some(UnsignedOutput {
+            dir: dir.into(),
+            sender,
+            nonce: None,
+            written: 0,
+        });
+        self
+    }
+
+    /// The number of unsigned transactions written so far.
+    pub fn unsigned_written(&self) -> usize {
+        self.unsigned.as_ref().map_or(0, |out| out.written)
+    }
+
+    /// Fill in `tx` for offline signing and write it out, labeled `label`.
+    ///
+    /// `gas` is the estimated gas limit, if the transaction could be estimated. Returns the address
+    /// of the contract `tx` creates, if it is a deployment.
+    pub(super) async fn write_unsigned<M: Middleware + 'static>(
+        &mut self,
+        label: &str,
+        client: &M,
+        mut tx: TypedTransaction,
+        gas: Option<U256>,
+    ) -> anyhow::Result<Address> {
+        let gas_config = self.gas_config;
+        let out = self
+            .unsigned
+            .as_mut()
+            .context("unsigned output is not configured")?;
+        let nonce = match out.nonce {
+            Some(nonce) => nonce,
+            None => client
+                .get_transaction_count(out.sender, Some(BlockNumber::Pending.into()))
+                .await
+                .context("error fetching nonce")?,
+        };
+        let chain_id = client
+            .get_chainid()
+            .await
+            .context("error fetching chain ID")?;
+
+        gas_config.apply(&mut tx);
+        match &mut tx {
+            TypedTransaction::Eip1559(req) => {
+                if req.max_fee_per_gas.is_none() || req.max_priority_fee_per_gas.is_none() {
+                    let (max_fee, priority_fee) = client
+                        .estimate_eip1559_fees(None)
+                        .await
+                        .context("error estimating fees")?;
+                    req.max_fee_per_gas.get_or_insert(max_fee);
+                    req.max_priority_fee_per_gas.get_or_insert(priority_fee);
+                }
+            }
+            tx => {
+                if tx.gas_price().is_none() {
+                    tx.set_gas_price(
+                        client
+                            .get_gas_price()
+                            .await
+                            .context("error fetching gas price")?,
+                    );
+                }
+            }
+        }
+        tx.set_from(out.sender);
+        tx.set_nonce(nonce);
+        tx.set_chain_id(chain_id.as_u64());
+        match gas {
+            Some(gas) => {
+                tx.set_gas(gas);
+            }
+            None => tracing::warn!(
+                "{label} could not be estimated; set the gas limit before signing it"
+            ),
+        }
+
+        let path = out.dir.join(format!("{:02}-{label}.json", out.written));
+        let contents = json!({
+            "description": label,
+            "transaction": tx,
+            "rlp": tx.rlp(),
+        });
+        fs::create_dir_all(&out.dir)
+            .with_context(|| format!("error creating {}", out.dir.display()))?;
+        fs::write(&path, serde_json::to_string_pretty(&contents)?)
+            .with_context(|| format!("error writing {}", path.display()))?;
+        tracing::info!(
+            "wrote unsigned {label} transaction (nonce {nonce}, max fee {} gwei) to {}",
+            tx.gas_price().map(fmt_gwei).unwrap_or_default(),
+            path.display()
+        );
+
+        out.nonce = Some(nonce + 1);
+        out.written += 1;
+        Ok(get_contract_address(out.sender, nonce))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{
+            deploy_light_client_and_initialize_proxy, test_helpers::TEST_MNEMONIC, Contract,
+        },
+        init_signer, AnvilOptions,
+    };
+    use async_std::sync::Arc;
+    use ethers::utils::keccak256;
+    use hotshot_contract_adapter::light_client::ParsedLightClientState;
+    use tempfile::TempDir;
+
+    #[async_std::test]
+    async fn test_unsigned_output() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let sender = l1.address();
+        let nonce = l1.get_transaction_count(sender, None).await.unwrap();
+        let chain_id = l1.get_chainid().await.unwrap();
+        let dir = TempDir::new().unwrap();
+
+        let mut contracts = Contracts::default().with_unsigned_output(dir.path(), sender);
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            sender,
+        )
+        .await
+        .unwrap();
+
+        // Nothing was sent.
+        assert_eq!(l1.get_transaction_count(sender, None).await.unwrap(), nonce);
+
+        // The contracts have the addresses the transactions will deploy them to.
+        assert_eq!(contracts.unsigned_written(), 4);
+        assert_eq!(proxy, get_contract_address(sender, nonce + 3));
+        assert_eq!(
+            contracts.address(Contract::LightClient),
+            Some(get_contract_address(sender, nonce + 2))
+        );
+
+        // Each transaction was written with consecutive nonces, ready to sign.
+        let mut files = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files.len(), 4);
+        assert!(files[3].ends_with("03-LightClientProxy.json"), "{files:?}");
+        for (i, path) in files.iter().enumerate() {
+            let contents: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+            let tx: TypedTransaction =
+                serde_json::from_value(contents["transaction"].clone()).unwrap();
+            assert_eq!(tx.nonce(), Some(&(nonce + i)));
+            assert_eq!(tx.from(), Some(&sender));
+            assert_eq!(tx.chain_id(), Some(chain_id.as_u64().into()));
+            assert!(tx.gas_price().is_some());
+
+            let rlp: Bytes = serde_json::from_value(contents["rlp"].clone()).unwrap();
+            assert_eq!(H256(keccak256(&rlp)), tx.sighash());
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Upgrading the light client behind an existing proxy.
+//!
+//! `LightClient.sol` is a UUPS contract: the [`ERC1967Proxy`](contract_bindings::erc1967_proxy)
+//! in front of it delegates to an implementation which the owner of the light client can replace
+//! by calling `upgradeToAndCall` through the proxy. [`upgrade_light_client`] deploys a new
+//! implementation, if one is not given, and points the proxy at it, keeping the proxy's address
+//! and state. Each upgrade is recorded in the [deployment report](super::report).
+//!
+//! A light client owned by a multisig cannot be upgraded by this tool. For that case, the new
+//! implementation is deployed just the same, but the upgrade transaction is
+//! [prepared](super::safe::send_or_prepare) as a [Safe batch](super::safe::SafeBatch), for the
+//! owners to import and send through their multisig. This happens automatically for a light
+//! client owned by a Safe, and for any owner with [`Contracts::prepare_owner_transactions`].
+
+use super::{
+    deploy_light_client_contract,
+    dry_run::DeployMode,
+    read_proxy_implementation,
+    report::UpgradeInfo,
+    safe::{send_or_prepare, SafeTransaction, Submission},
+    Contract, Contracts,
+};
+use anyhow::{bail, ensure, Context};
+use async_std::sync::Arc;
+use contract_bindings::light_client::LightClient;
+use ethers::prelude::*;
+
+/// Upgrade the light client proxy at `proxy` to a new implementation.
+///
+/// If `new_impl` is given, it must be an already deployed `LightClient.sol` implementation.
+/// Otherwise, a new implementation is deployed with [`deploy_light_client_contract`], linking it
+/// with the libraries in `contracts` (which are deployed if necessary). The proxy is then upgraded
+/// with `upgradeToAndCall`, delegatecalling the new implementation with `init_data`, if given, to
+/// run a reinitializer. Either way, the new implementation is recorded in `contracts` as
+/// [`Contract::LightClient`], replacing the old one.
+///
+/// Only the owner of the light client can upgrade it, so before anything is deployed or sent, we
+/// check that the sender of `l1` [can act as](Contracts::can_act_as) the owner, and fail otherwise.
+/// The upgrade is made with [`send_or_prepare`]: if the sender owns the light client, the upgrade
+/// transaction is retried according to the [retry policy](Contracts::with_retry_policy) of
+/// `contracts`, and skipped if the proxy already points at the new implementation, for example
+/// because an earlier attempt landed after all. Afterwards, the implementation slot of the proxy
+/// is read back to check that it points at the new implementation, and the upgrade is recorded in
+/// the [report](Contracts::upgrades). If a Safe owns the light client, or `contracts` is configured
+/// to [prepare owner transactions](Contracts::prepare_owner_transactions), the upgrade transaction
+/// is added to the [batch](Contracts::safe_batches) prepared for the owner instead, and since the
+/// upgrade has not happened, it is not recorded. Returns the address of the new implementation.
+pub async fn upgrade_light_client<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    proxy: Address,
+    new_impl: Option<Address>,
+    init_data: Option<Bytes>,
+) -> anyhow::Result<Address> {
+    let light_client = LightClient::new(proxy, l1.clone());
+    let sender = l1
+        .default_sender()
+        .context("cannot upgrade light client, sender is unknown")?;
+    let owner = light_client
+        .owner()
+        .call()
+        .await
+        .with_context(|| format!("error reading owner of light client proxy {proxy:#x}"))?;
+    if !contracts.can_act_as(&*l1, owner).await? {
+        bail!(
+            "cannot upgrade light client proxy {proxy:#x}: it is owned by {owner:#x}, which is \
+             neither the sender {sender:#x} nor a Safe"
+        );
+    }
+
+    let old_impl = read_proxy_implementation(&*l1, proxy).await?;
+    let new_impl = new_implementation(l1.clone(), contracts, new_impl).await?;
+
+    let tx = light_client
+        .upgrade_to_and_call(new_impl, init_data.unwrap_or_default())
+        .tx;
+    if contracts.mode() == DeployMode::DryRun {
+        tracing::info!("would upgrade light client proxy {proxy:#x} to {new_impl:#x}");
+        if contracts.unsigned.is_some() {
+            let gas = l1.estimate_gas(&tx, None).await.ok();
+            contracts
+                .write_unsigned("LightClientProxy-upgrade", &*l1, tx, gas)
+                .await?;
+        }
+        return Ok(new_impl);
+    }
+
+    tracing::info!("upgrading light client proxy {proxy:#x} to {new_impl:#x}");
+    let data = tx.data().cloned().unwrap_or_default();
+    let client = &*l1;
+    let submission = send_or_prepare(
+        client,
+        contracts,
+        owner,
+        SafeTransaction::new(proxy, data),
+        "light client upgrade",
+        move || async move { Ok(read_proxy_implementation(client, proxy).await? == new_impl) },
+    )
+    .await?;
+    let tx_hash = match submission {
+        Submission::Sent(receipt) => receipt.map(|receipt| receipt.transaction_hash),
+        Submission::Prepared { safe } => {
+            tracing::info!(
+                "prepared upgrade of light client proxy {proxy:#x} to {new_impl:#x} for its owner \
+                 {safe:#x}"
+            );
+            return Ok(new_impl);
+        }
+    };
+
+    let implementation = read_proxy_implementation(&*l1, proxy).await?;
+    ensure!(
+        implementation == new_impl,
+        "light client proxy {proxy:#x} points at implementation {implementation:#x} after the \
+         upgrade, expected {new_impl:#x}"
+    );
+    tracing::info!("upgraded light client proxy {proxy:#x} to {new_impl:#x}");
+    contracts.upgrades.push(UpgradeInfo {
+        proxy,
+        old_implementation: old_impl,
+        new_implementation: new_impl,
+        tx_hash,
+    });
+    Ok(new_impl)
+}
+
+/// Record `new_impl` as the new light client implementation, or deploy one if not given.
+async fn new_implementation<M: Middleware + 'static>(
+    l1: Arc<M>,
+    contracts: &mut Contracts,
+    new_impl: Option<Address>,
+) -> anyhow::Result<Address> {
+    match new_impl {
+        Some(new_impl) => {
+            ensure!(
+                !l1.get_code(new_impl, None).await?.is_empty(),
+                "new light client implementation {new_impl:#x} is not deployed"
+            );
+            contracts.addresses.insert(Contract::LightClient, new_impl);
+            contracts.flavors.remove(&Contract::LightClient);
+            contracts
+                .receipts
+                .retain(|(contract, _)| *contract != Contract::LightClient);
+            Ok(new_impl)
+        }
+        None => {
+            tracing::info!("deploying new {}", Contract::LightClient);
+            let new_impl = deploy_light_client_contract(l1.clone(), contracts).await?;
+            contracts.record_deployed(Contract::LightClient, new_impl)?;
+            Ok(new_impl)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deployer::{
+            deploy_light_client_and_initialize_proxy,
+            test_helpers::{deploy_safe_stub, execute_safe_batch, TEST_MNEMONIC},
+        },
+        init_signer, AnvilOptions,
+    };
+    use hotshot_contract_adapter::light_client::ParsedLightClientState;
+
+    #[async_std::test]
+    async fn test_upgrade_light_client() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        let mut contracts = Contracts::default();
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            l1.address(),
+        )
+        .await
+        .unwrap();
+        let old_impl = contracts.address(Contract::LightClient).unwrap();
+        let light_client = LightClient::new(proxy, l1.clone());
+        let state = light_client.get_finalized_state().call().await.unwrap();
+
+        let new_impl = upgrade_light_client(l1.clone(), &mut contracts, proxy, None, None)
+            .await
+            .unwrap();
+        assert_ne!(new_impl, old_impl);
+        assert_eq!(
+            read_proxy_implementation(&*l1, proxy).await.unwrap(),
+            new_impl
+        );
+        assert_eq!(contracts.address(Contract::LightClient), Some(new_impl));
+        let upgrade = contracts.upgrades()[0];
+        assert_eq!(upgrade.proxy, proxy);
+        assert_eq!(upgrade.old_implementation, old_impl);
+        assert_eq!(upgrade.new_implementation, new_impl);
+        let receipt = l1
+            .get_transaction_receipt(upgrade.tx_hash.unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.status, Some(1.into()));
+        // The proxy keeps its state.
+        assert_eq!(
+            light_client.get_finalized_state().call().await.unwrap(),
+            state
+        );
+
+        // Upgrading back to a given implementation deploys nothing new.
+        upgrade_light_client(l1.clone(), &mut contracts, proxy, Some(old_impl), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_proxy_implementation(&*l1, proxy).await.unwrap(),
+            old_impl
+        );
+        assert_eq!(contracts.address(Contract::LightClient), Some(old_impl));
+        assert_eq!(contracts.upgrades().len(), 2);
+
+        let mut out = vec![];
+        contracts.write_report(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.contains(&format!(
+                "proxy {proxy:#x} from {new_impl:#x} to {old_impl:#x}"
+            )),
+            "{out}"
+        );
+    }
+
+    #[async_std::test]
+    async fn test_upgrade_light_client_not_owner() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        let mut contracts = Contracts::default();
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            Address::repeat_byte(1),
+        )
+        .await
+        .unwrap();
+
+        let nonce = l1.get_transaction_count(l1.address(), None).await.unwrap();
+        let err = upgrade_light_client(l1.clone(), &mut contracts, proxy, None, None)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("owned by"), "{err}");
+        // Nothing was deployed or sent.
+        assert_eq!(
+            l1.get_transaction_count(l1.address(), None).await.unwrap(),
+            nonce
+        );
+    }
+
+    #[async_std::test]
+    async fn test_upgrade_light_client_owned_by_safe() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+
+        let safe = deploy_safe_stub(&l1).await.unwrap();
+        let mut contracts = Contracts::default();
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            safe,
+        )
+        .await
+        .unwrap();
+        let old_impl = contracts.address(Contract::LightClient).unwrap();
+
+        // The new implementation is deployed, but the upgrade is prepared for the Safe.
+        let new_impl = upgrade_light_client(l1.clone(), &mut contracts, proxy, None, None)
+            .await
+            .unwrap();
+        assert_ne!(new_impl, old_impl);
+        assert_eq!(contracts.address(Contract::LightClient), Some(new_impl));
+        assert_eq!(
+            read_proxy_implementation(&*l1, proxy).await.unwrap(),
+            old_impl
+        );
+        assert!(contracts.upgrades().is_empty());
+        let [batch] = contracts.safe_batches() else {
+            panic!("expected one batch: {:?}", contracts.safe_batches());
+        };
+        assert_eq!(batch.meta.created_from_safe_address, safe);
+        assert_eq!(batch.transactions.len(), 1);
+        assert_eq!(batch.transactions[0].to, proxy);
+
+        // Executing the batch from the Safe upgrades the proxy.
+        execute_safe_batch(&l1, batch).await.unwrap();
+        assert_eq!(
+            read_proxy_implementation(&*l1, proxy).await.unwrap(),
+            new_impl
+        );
+    }
+
+    #[async_std::test]
+    async fn test_upgrade_light_client_prepared() {
+        let anvil = AnvilOptions::default().spawn().await;
+        let l1 = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 0).await.unwrap());
+        let owner = Arc::new(init_signer(&anvil.url(), TEST_MNEMONIC, 1).await.unwrap());
+
+        // A light client owned by some other account, like a multisig which is not a Safe.
+        let mut contracts = Contracts::default().prepare_owner_transactions(true);
+        let proxy = deploy_light_client_and_initialize_proxy(
+            l1.clone(),
+            &mut contracts,
+            ParsedLightClientState::dummy_genesis(),
+            owner.address(),
+        )
+        .await
+        .unwrap();
+        let old_impl = contracts.address(Contract::LightClient).unwrap();
+
+        // The new implementation is deployed, but the proxy is not upgraded.
+        let new_impl = upgrade_light_client(l1.clone(), &mut contracts, proxy, None, None)
+            .await
+            .unwrap();
+        assert_ne!(new_impl, old_impl);
+        assert!(!l1.get_code(new_impl, None).await.unwrap().is_empty());
+        assert_eq!(
+            read_proxy_implementation(&*l1, proxy).await.unwrap(),
+            old_impl
+        );
+        assert!(contracts.upgrades().is_empty());
+        let [batch] = contracts.safe_batches() else {
+            panic!("expected one batch: {:?}", contracts.safe_batches());
+        };
+        assert_eq!(batch.meta.created_from_safe_address, owner.address());
+        assert_eq!(batch.transactions.len(), 1);
+        let tx = batch.transactions[0].clone();
+
+        // The owner can send the prepared transaction to upgrade it.
+        assert_eq!(tx.to, proxy);
+        let receipt = owner
+            .send_transaction(TransactionRequest::new().to(tx.to).data(tx.data), None)
+            .await
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.status, Some(1.into()));
+        assert_eq!(
+            read_proxy_implementation(&*l1, proxy).await.unwrap(),
+            new_impl
+        );
+    }
+}
@@ -0,0 +1,550 @@
+//! Verifying deployed contracts on an Etherscan-compatible block explorer.
+//!
+//! After a deployment, [`Contracts::verify`] submits the source of every contract deployed in the
+//! run to the contract verification API of Etherscan, or of any explorer implementing the same API,
+//! like Blockscout, and reports a [`VerificationStatus`] for each contract. The source is taken from the solc build info files written by
+//! `forge build --build-info`, which hold the exact standard JSON input the contracts were compiled
+//! from. The constructor arguments are recovered from the init code each contract was deployed
+//! with, and the libraries `LightClient.sol` links with are added to the compiler settings, at the
+//! addresses they were linked at. [`verify_contracts`] reports a contract which cannot be verified
+//! as a [`Warning::VerificationFailed`], never as an error, since the deployment itself succeeded.
+
+use super::{flavor::Flavor, warnings::Warning, Contract, Contracts, LightClientArtifact};
+use anyhow::{anyhow, bail, ensure, Context};
+use async_std::task::sleep;
+use ethers::{
+    types::{Address, Chain},
+    utils::hex,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use url::Url;
+
+/// Where `forge build --build-info` writes build info files, relative to the repository root.
+pub const DEFAULT_BUILD_INFO: &str = "contracts/out/build-info";
+
+/// An Etherscan-compatible contract verification API.
+#[derive(Clone, Debug)]
+pub struct EtherscanConfig {
+    /// The API endpoint, like `https://api.etherscan.io/api`.
+    pub api_url: Url,
+    /// The API key, if the explorer requires one.
+    pub api_key: Option<String>,
+    /// The directory of solc build info files for the deployed contracts.
+    pub build_info: PathBuf,
+    /// How often to poll the status of a submission.
+    pub poll_interval: Duration,
+    /// How many times to poll the status of a submission before giving up.
+    pub max_polls: usize,
+}
+
+impl EtherscanConfig {
+    pub fn new(api_url: Url, build_info: impl Into<PathBuf>) -> Self {
+        Self {
+            api_url,
+            api_key: None,
+            build_info: build_info.into(),
+            poll_interval: Duration::from_secs(5),
+            max_polls: 60,
+        }
+    }
+
+    /// The Etherscan API for `chain_id`, if it is a well-known chain.
+    pub fn for_chain(chain_id: u64, build_info: impl Into<PathBuf>) -> Option<Self> {
+        let (url, _) = Chain::try_from(chain_id).ok()?.etherscan_urls()?;
+        Some(Self::new(url.parse().ok()?, build_info))
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Poll the status of each submission every `interval`, up to `max_polls` times.
+    pub fn with_polling(mut self, interval: Duration, max_polls: usize) -> Self {
+        self.poll_interval = interval;
+        self.max_polls = max_polls.max(1);
+        self
+    }
+}
+
+/// The outcome of verifying a contract.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum VerificationStatus {
+    /// The explorer verified the source of the contract, or had already verified it.
+    Verified,
+    /// The contract was not submitted for verification.
+    Skipped { note: String },
+    /// The contract could not be verified.
+    Failed { reason: String },
+}
+
+impl Contracts {
+    /// Verify the contracts deployed in this run on the Etherscan explorer for `chain`.
+    ///
+    /// The source is read from the build info in [`DEFAULT_BUILD_INFO`]. Fails if there is no
+    /// known Etherscan API for `chain`; use [`verify_with`](Self::verify_with) for other explorers.
+    pub async fn verify(
+        &self,
+        api_key: &str,
+        chain: Chain,
+    ) -> anyhow::Result<Vec<(Contract, VerificationStatus)>> {
+        let api = EtherscanConfig::for_chain(chain.into(), DEFAULT_BUILD_INFO)
+            .with_context(|| format!("no verification API is known for {chain}"))?
+            .with_api_key(api_key);
+        Ok(self.verify_with(&api).await)
+    }
+
+    /// Submit the source of each contract deployed in this run for verification with `api`.
+    ///
+    /// Contracts which were predeployed, or resumed from a state file, are skipped, since we do not
+    /// know the init code they were deployed with. Each submission is polled until the explorer
+    /// reports a result, which is logged. Returns the status of every contract in the cache, sorted
+    /// like the .env file.
+    pub async fn verify_with(&self, api: &EtherscanConfig) -> Vec<(Contract, VerificationStatus)> {
+        let mut build_infos = None;
+        let mut statuses = vec![];
+        for (contract, address) in self.sorted() {
+            if self.receipt(contract).is_none() {
+                statuses.push((
+                    contract,
+                    VerificationStatus::Skipped {
+                        note: "not deployed in this run".into(),
+                    },
+                ));
+                continue;
+            }
+            let build_infos = build_infos.get_or_insert_with(|| load_build_info(&api.build_info));
+            tracing::info!("verifying {contract:?} at {address:#x}");
+            let res = match build_infos {
+                Ok(build_infos) => verify_contract(self, api, build_infos, contract, address).await,
+                Err(err) => Err(anyhow!("{err:#}")),
+            };
+            let status = match res {
+                Ok(()) => {
+                    tracing::info!("verified {contract:?} at {address:#x}");
+                    VerificationStatus::Verified
+                }
+                Err(err) => {
+                    tracing::warn!("failed to verify {contract:?} at {address:#x}: {err:#}");
+                    VerificationStatus::Failed {
+                        reason: format!("{err:#}"),
+                    }
+                }
+            };
+            statuses.push((contract, status));
+        }
+        statuses
+    }
+}
+
+/// Verify each contract deployed in this run of `contracts` with `api`.
+///
+/// Returns a [`Warning::VerificationFailed`] for each contract which could not be verified, for the
+/// caller to record.
+pub async fn verify_contracts(contracts: &Contracts, api: EtherscanConfig) -> Vec<Warning> {
+    contracts
+        .verify_with(&api)
+        .await
+        .into_iter()
+        .filter_map(|(contract, status)| match status {
+            VerificationStatus::Failed { reason } => Some(Warning::VerificationFailed {
+                contract,
+                address: contracts.address(contract)?,
+                reason,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+async fn verify_contract(
+    contracts: &Contracts,
+    api: &EtherscanConfig,
+    build_infos: &[BuildInfo],
+    contract: Contract,
+    address: Address,
+) -> anyhow::Result<()> {
+    let mock = contracts.flavor_of(contract) == Some(Flavor::Mock);
+    let name = solidity_name(contract, mock);
+    let (build_info, file, compiled) = build_infos
+        .iter()
+        .find_map(|info| {
+            info.output
+                .contracts
+                .iter()
+                .find_map(|(file, contracts)| Some((info, file, contracts.get(name)?)))
+        })
+        .with_context(|| format!("{name} not found in build info"))?;
+
+    // The init code is the creation bytecode, whose length linking does not change, followed by
+    // the ABI-encoded constructor arguments.
+    let init_code = contracts
+        .init_codes
+        .get(&contract)
+        .with_context(|| format!("init code of {contract:?} is not known"))?;
+    let bytecode_len = compiled.evm.bytecode.object.trim_start_matches("0x").len() / 2;
+    ensure!(
+        init_code.len() >= bytecode_len,
+        "init code of {contract:?} is shorter than the compiled bytecode of {name}"
+    );
+    let constructor_args = hex::encode(&init_code[bytecode_len..]);
+
+    let mut input = build_info.input.clone();
+    if contract == Contract::LightClient {
+        // Link the same libraries, at the same addresses, as when the light client was deployed.
+        let artifact = if mock {
+            LightClientArtifact::Mock
+        } else {
+            LightClientArtifact::Production
+        };
+        for (library, lib_contract) in artifact.libraries() {
+            let (lib_file, lib_name) = library.split_once(':').unwrap();
+            let lib_address = contracts
+                .address(lib_contract)
+                .with_context(|| format!("{name} links with {lib_name}, which is not deployed"))?;
+            input["settings"]["libraries"][lib_file][lib_name] = format!("{lib_address:#x}").into();
+        }
+    }
+
+    let form = [
+        ("module", "contract".to_string()),
+        ("action", "verifysourcecode".to_string()),
+        ("apikey", api.api_key.clone().unwrap_or_default()),
+        ("contractaddress", format!("{address:#x}")),
+        ("sourceCode", input.to_string()),
+        ("codeformat", "solidity-standard-json-input".to_string()),
+        ("contractname", format!("{file}:{name}")),
+        (
+            "compilerversion",
+            format!("v{}", build_info.solc_long_version),
+        ),
+        // Sic: the parameter is misspelled in the Etherscan API.
+        ("constructorArguements", constructor_args),
+    ];
+    let body = surf::Body::from_form(&form).map_err(|err| anyhow!("{err}"))?;
+    let submitted: ApiResponse = request(surf::post(&api.api_url).body(body)).await?;
+    let guid = match submitted.status.as_str() {
+        "1" => submitted.result,
+        _ if is_already_verified(&submitted.result) => return Ok(()),
+        _ => bail!("submission rejected: {}", submitted.result),
+    };
+    tracing::debug!("submitted {contract:?} for verification, GUID {guid}");
+
+    let mut status_url = api.api_url.clone();
+    status_url
+        .query_pairs_mut()
+        .append_pair("module", "contract")
+        .append_pair("action", "checkverifystatus")
+        .append_pair("guid", &guid)
+        .append_pair("apikey", api.api_key.as_deref().unwrap_or_default());
+    for _ in 0..api.max_polls {
+        sleep(api.poll_interval).await;
+        let status: ApiResponse = request(surf::get(&status_url)).await?;
+        if status.result.starts_with("Pass") || is_already_verified(&status.result) {
+            return Ok(());
+        }
+        if !status.result.contains("Pending") {
+            bail!("verification failed: {}", status.result);
+        }
+    }
+    bail!("verification still pending after {} polls", api.max_polls)
+}
+
+fn is_already_verified(result: &str) -> bool {
+    result.to_lowercase().contains("already verified")
+}
+
+/// A response from the verification API.
+#[derive(Debug, Deserialize)]
+struct ApiResponse {
+    status: String,
+    result: String,
+}
+
+async fn request(req: surf::RequestBuilder) -> anyhow::Result<ApiResponse> {
+    let mut res = req
+        .await
+        .map_err(|err| anyhow!("error contacting verification API: {err}"))?;
+    ensure!(
+        res.status().is_success(),
+        "verification API responded with {}",
+        res.status()
+    );
+    res.body_json()
+        .await
+        .map_err(|err| anyhow!("malformed response from verification API: {err}"))
+}
+
+/// The name of the Solidity contract deployed as `contract`, or as its mock if `mock`.
+fn solidity_name(contract: Contract, mock: bool) -> &'static str {
+    match contract {
+        Contract::HotShot => "HotShot",
+        Contract::PlonkVerifier => "PlonkVerifier",
+        Contract::StateUpdateVK if mock => "LightClientStateUpdateVKMock",
+        Contract::StateUpdateVK => "LightClientStateUpdateVK",
+        Contract::LightClient if mock => "LightClientMock",
+        Contract::LightClient => "LightClient",
+        Contract::LightClientProxy | Contract::FeeContractProxy => "ERC1967Proxy",
+        Contract::FeeContract => "FeeContract",
+        Contract::StakeTable => "StakeTable",
+    }
+}
+
+/// The parts of a solc build info file we need.
+#[derive(Debug, Deserialize)]
+struct BuildInfo {
+    #[serde(rename = "solcLongVersion")]
+    solc_long_version: String,
+    /// The standard JSON input the contracts were compiled from.
+    input: Value,
+    output: BuildOutput,
+}
+
+#[derive(Debug, Deserialize)]
+struct BuildOutput {
+    /// Compiled contracts, by source file and name.
+    #[serde(default)]
+    contracts: BTreeMap<String, BTreeMap<String, CompiledContract>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompiledContract {
+    evm: Evm,
+}
+
+#[derive(Debug, Deserialize)]
+struct Evm {
+    bytecode: Bytecode,
+}
+
+#[derive(Debug, Deserialize)]
+struct Bytecode {
+    object: String,
+}
+
+/// Load every build info file in `dir`.
+fn load_build_info(dir: &Path) -> anyhow::Result<Vec<BuildInfo>> {
+    let mut build_infos = vec![];
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("error reading build info {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let build_info = serde_json::from_str(&fs::read_to_string(&path)?)
+                .with_context(|| format!("malformed build info {}", path.display()))?;
+            build_infos.push(build_info);
+        }
+    }
+    Ok(build_infos)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::{
+        channel::{unbounded, Receiver},
+        io::{prelude::BufReadExt, BufReader, ReadExt, WriteExt},
+        net::TcpListener,
+        task::spawn,
+    };
+    use ethers::types::{Bytes, TransactionReceipt};
+    use tempfile::TempDir;
+
+    /// A request received by the mock API: the request line and the body.
+    type Request = (String, String);
+
+    /// A mock verification API.
+    ///
+    /// Submissions of HotShot are accepted, and reported as pending once before passing.
+    /// Submissions of anything else are rejected.
+    async fn mock_api() -> (Url, Receiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let (tx, rx) = unbounded();
+        spawn(async move {
+            let mut polls = 0;
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut reader = BufReader::new(stream.clone());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).await.unwrap();
+                let mut len = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    let line = line.trim().to_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("content-length:") {
+                        len = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).await.unwrap();
+                let body = String::from_utf8(body).unwrap();
+
+                let response = if request_line.starts_with("POST") {
+                    if body.contains("HotShot.sol%3AHotShot") {
+                        json!({"status": "1", "message": "OK", "result": "guid"})
+                    } else {
+                        json!({"status": "0", "message": "NOTOK", "result": "Invalid source"})
+                    }
+                } else {
+                    polls += 1;
+                    let result = if polls == 1 {
+                        "Pending in queue"
+                    } else {
+                        "Pass - Verified"
+                    };
+                    json!({"status": "1", "message": "OK", "result": result})
+                };
+                tx.send((request_line.trim().to_string(), body))
+                    .await
+                    .unwrap();
+                let response = response.to_string();
+                let mut stream = stream;
+                stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                             content-length: {}\r\nconnection: close\r\n\r\n{response}",
+                            response.len()
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    fn form(body: &str) -> BTreeMap<String, String> {
+        url::form_urlencoded::parse(body.as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    #[async_std::test]
+    async fn test_verify_contracts() {
+        let dir = TempDir::new().unwrap();
+        let placeholder = format!("__${}$__", "0".repeat(34));
+        fs::write(
+            dir.path().join("build.json"),
+            json!({
+                "solcLongVersion": "0.8.23+commit.f704f362",
+                "input": {"language": "Solidity", "settings": {"optimizer": {"enabled": true}}},
+                "output": {"contracts": {
+                    "contracts/src/HotShot.sol": {
+                        "HotShot": {"evm": {"bytecode": {"object": "6001", "linkReferences": {}}}}
+                    },
+                    "contracts/src/LightClient.sol": {
+                        "LightClient": {"evm": {"bytecode": {
+                            "object": format!("60{placeholder}"),
+                            "linkReferences": {
+                                "contracts/src/libraries/PlonkVerifier.sol": {
+                                    "PlonkVerifier": [{"start": 1, "length": 20}]
+                                }
+                            }
+                        }}}
+                    }
+                }}
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        // HotShot and LightClient were deployed in this run, the libraries were predeployed.
+        let mut contracts = Contracts::default();
+        let plonk = Address::repeat_byte(3);
+        let vk = Address::repeat_byte(4);
+        contracts.insert(Contract::PlonkVerifier, plonk);
+        contracts.insert(Contract::StateUpdateVK, vk);
+        for (contract, byte, init_code) in [
+            (Contract::HotShot, 1, vec![0x60, 0x01, 0xab, 0xcd]),
+            (Contract::LightClient, 2, [vec![0x60], vec![0; 20]].concat()),
+        ] {
+            contracts.insert(contract, Address::repeat_byte(byte));
+            contracts
+                .init_codes
+                .insert(contract, Bytes::from(init_code));
+            contracts
+                .receipts
+                .push((contract, TransactionReceipt::default()));
+        }
+
+        let (url, requests) = mock_api().await;
+        let api = EtherscanConfig::new(url, dir.path())
+            .with_api_key("key")
+            .with_polling(Duration::from_millis(10), 5);
+        let statuses = contracts.verify_with(&api).await;
+
+        // HotShot was submitted with its constructor arguments, then polled until it passed.
+        let (line, body) = requests.recv().await.unwrap();
+        assert!(line.starts_with("POST /api"), "{line}");
+        let submission = form(&body);
+        assert_eq!(submission["action"], "verifysourcecode");
+        assert_eq!(submission["apikey"], "key");
+        assert_eq!(
+            submission["contractaddress"],
+            format!("{:#x}", Address::repeat_byte(1))
+        );
+        assert_eq!(
+            submission["contractname"],
+            "contracts/src/HotShot.sol:HotShot"
+        );
+        assert_eq!(submission["compilerversion"], "v0.8.23+commit.f704f362");
+        assert_eq!(submission["constructorArguements"], "abcd");
+        for _ in 0..2 {
+            let (line, _) = requests.recv().await.unwrap();
+            assert!(line.starts_with("GET /api?"), "{line}");
+            assert!(line.contains("action=checkverifystatus"), "{line}");
+            assert!(line.contains("guid=guid"), "{line}");
+        }
+
+        // LightClient was submitted with its library links, and rejected.
+        let (_, body) = requests.recv().await.unwrap();
+        let submission = form(&body);
+        let input: Value = serde_json::from_str(&submission["sourceCode"]).unwrap();
+        assert_eq!(
+            input["settings"]["libraries"]["contracts/src/libraries/PlonkVerifier.sol"]
+                ["PlonkVerifier"],
+            format!("{plonk:#x}")
+        );
+        assert_eq!(
+            input["settings"]["libraries"]["contracts/src/libraries/LightClientStateUpdateVK.sol"]
+                ["LightClientStateUpdateVK"],
+            format!("{vk:#x}")
+        );
+        assert_eq!(input["settings"]["optimizer"]["enabled"], true);
+        assert_eq!(submission["constructorArguements"], "");
+
+        // Predeployed contracts are skipped, and the failure is reported with its reason.
+        let skipped = VerificationStatus::Skipped {
+            note: "not deployed in this run".into(),
+        };
+        let statuses: BTreeMap<_, _> = statuses.into_iter().collect();
+        assert_eq!(statuses.len(), 4, "{statuses:?}");
+        assert_eq!(statuses[&Contract::HotShot], VerificationStatus::Verified);
+        assert_eq!(statuses[&Contract::PlonkVerifier], skipped);
+        assert_eq!(statuses[&Contract::StateUpdateVK], skipped);
+        assert!(
+            matches!(
+                &statuses[&Contract::LightClient],
+                VerificationStatus::Failed { reason } if reason.contains("Invalid source")
+            ),
+            "{statuses:?}"
+        );
+    }
+}
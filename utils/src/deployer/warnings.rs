@@ -0,0 +1,381 @@
+//! Warnings collected over the course of a deployment.
+//!
+//! Some conditions are worth an operator's attention without being errors, like a predeployed
+//! contract whose code differs from the bundled artifact, or a mock contract on a public network.
+//! Rather than only logging these where they are detected, [`Contracts`] collects them as typed
+//! [`Warning`]s, so that they can be surfaced in every output format, and optionally turned into
+//! errors with [`Contracts::check_warnings`].
+
+use super::{dry_run::DeployMode, params::STAKE_TABLE_CAPACITY, Contract, Contracts};
+use anyhow::{ensure, Context};
+use async_std::sync::Arc;
+use clap::ValueEnum;
+use contract_bindings::light_client::LightClient;
+use derive_more::Display;
+use ethers::{prelude::*, utils::to_checksum};
+use serde::Serialize;
+use std::io::Write;
+
+/// Chains used for local development, on which mock contracts are expected.
+const LOCAL_CHAIN_IDS: &[u64] = &[1337, 31337];
+
+/// Whether `chain_id` identifies a local development chain, rather than a public network.
+pub fn is_local_chain(chain_id: u64) -> bool {
+    LOCAL_CHAIN_IDS.contains(&chain_id)
+}
+
+/// How much attention a [`Warning`] deserves.
+#[derive(
+    Clone, Copy, Debug, Display, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Expected in some workflows, but worth knowing about.
+    #[display(fmt = "low")]
+    Low,
+    /// Likely needs follow-up before the deployment is used in production.
+    #[display(fmt = "medium")]
+    Medium,
+    /// Almost certainly a mistake.
+    #[display(fmt = "high")]
+    High,
+}
+
+/// A condition worth an operator's attention, which did not stop the deployment.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Warning {
+    /// The deployer account still owns a contract after deployment.
+    DeployerOwns { contract: Contract, owner: Address },
+    /// A contract which was to be handed over to `expected` is owned by some other account.
+    ForeignOwner {
+        contract: Contract,
+        owner: Address,
+        expected: Address,
+    },
+    /// A mock contract, which skips proof verification, was deployed to a public network.
+    MockOnPublicNetwork { contract: Contract, chain_id: u64 },
+    /// The code of a predeployed contract was not checked against the bundled artifacts.
+    Unverified {
+        contract: Contract,
+        address: Address,
+    },
+    /// The code of a predeployed contract differs from the bundled artifacts, so it may be an older
+    /// or newer version of the contract.
+    CodeMismatch {
+        contract: Contract,
+        address: Address,
+    },
+    /// A contract was given an address which conflicts with the one recorded in the state file.
+    StateConflict {
+        contract: Contract,
+        recorded: Address,
+        given: Address,
+    },
+    /// A contract was deployed with the same init code as another contract in the same run.
+    DuplicateBytecode {
+        contract: Contract,
+        original: Contract,
+    },
+    /// The source of a contract deployed in this run could not be verified on a block explorer.
+    VerificationFailed {
+        contract: Contract,
+        address: Address,
+        reason: String,
+    },
+    /// A predeployed address was given in mixed case which is not a valid EIP-55 checksum, so it
+    /// may contain a typo.
+    BadChecksum {
+        contract: Contract,
+        address: Address,
+    },
+    /// No permissioned prover was given, but the light client is already restricted to `prover`,
+    /// and was left that way.
+    ProverKept { contract: Contract, prover: Address },
+    /// The permissioned prover `old` of a predeployed light client was replaced with `new`.
+    ProverReplaced {
+        contract: Contract,
+        old: Address,
+        new: Address,
+    },
+    /// The light client was initialized with the given genesis state, although it differs from the
+    /// one reported by the sequencer network in the fields listed in `diff`.
+    GenesisMismatch { contract: Contract, diff: String },
+    /// The light client is deployed for a stake table of `capacity`, smaller than the capacity its
+    /// verifying key was generated for, so the prover must be configured with the same capacity.
+    SmallStakeTable { contract: Contract, capacity: usize },
+}
+
+impl Warning {
+    /// The contract this warning is about.
+    pub fn contract(&self) -> Contract {
+        match self {
+            Self::DeployerOwns { contract, .. }
+            | Self::ForeignOwner { contract, .. }
+            | Self::MockOnPublicNetwork { contract, .. }
+            | Self::Unverified { contract, .. }
+            | Self::CodeMismatch { contract, .. }
+            | Self::StateConflict { contract, .. }
+            | Self::DuplicateBytecode { contract, .. }
+            | Self::VerificationFailed { contract, .. }
+            | Self::BadChecksum { contract, .. }
+            | Self::ProverKept { contract, .. }
+            | Self::ProverReplaced { contract, .. }
+            | Self::GenesisMismatch { contract, .. }
+            | Self::SmallStakeTable { contract, .. } => *contract,
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::StateConflict { .. }
+            | Self::DuplicateBytecode { .. }
+            | Self::VerificationFailed { .. }
+            | Self::ProverReplaced { .. }
+            | Self::SmallStakeTable { .. } => Severity::Low,
+            Self::DeployerOwns { .. }
+            | Self::ForeignOwner { .. }
+            | Self::Unverified { .. }
+            | Self::CodeMismatch { .. }
+            | Self::BadChecksum { .. }
+            | Self::ProverKept { .. }
+            | Self::GenesisMismatch { .. } => Severity::Medium,
+            Self::MockOnPublicNetwork { .. } => Severity::High,
+        }
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DeployerOwns { contract, owner } => {
+                write!(f, "{contract:?} is still owned by the deployer {owner:#x}")
+            }
+            Self::ForeignOwner {
+                contract,
+                owner,
+                expected,
+            } => write!(
+                f,
+                "{contract:?} is owned by {owner:#x}, not the deployer, so ownership was not \
+                 transferred to {expected:#x}"
+            ),
+            Self::MockOnPublicNetwork { contract, chain_id } => write!(
+                f,
+                "{contract:?} is a mock which skips proof verification, but chain {chain_id} is \
+                 not a local development chain"
+            ),
+            Self::Unverified { contract, address } => write!(
+                f,
+                "code of predeployed {contract:?} at {address:#x} was not verified"
+            ),
+            Self::CodeMismatch { contract, address } => write!(
+                f,
+                "code at {address:#x} for {contract:?} does not match the bundled artifact, it \
+                 may have been deployed from a different version of the contract"
+            ),
+            Self::StateConflict {
+                contract,
+                recorded,
+                given,
+            } => write!(
+                f,
+                "{contract:?} is recorded at {recorded:#x} in the state file, but was given as \
+                 {given:#x}; using {given:#x}"
+            ),
+            Self::DuplicateBytecode { contract, original } => write!(
+                f,
+                "{contract:?} was deployed with the same init code as {original:?}"
+            ),
+            Self::VerificationFailed {
+                contract,
+                address,
+                reason,
+            } => write!(
+                f,
+                "source of {contract:?} at {address:#x} was not verified on the block explorer: \
+                 {reason}"
+            ),
+            Self::BadChecksum { contract, address } => write!(
+                f,
+                "{contract} was given as an address which fails its EIP-55 checksum, check it for \
+                 typos; its checksummed form is {}",
+                to_checksum(address, None)
+            ),
+            Self::ProverKept { contract, prover } => write!(
+                f,
+                "no permissioned prover was given, but {contract:?} is already restricted to \
+                 prover {prover:#x}"
+            ),
+            Self::ProverReplaced { contract, old, new } => write!(
+                f,
+                "replaced permissioned prover {old:#x} of {contract:?} with {new:#x}"
+            ),
+            Self::GenesisMismatch { contract, diff } => write!(
+                f,
+                "{contract:?} uses the given genesis state despite a mismatch with the sequencer \
+                 network ({diff})"
+            ),
+            Self::SmallStakeTable { contract, capacity } => write!(
+                f,
+                "{contract:?} is deployed with stake table capacity {capacity}, smaller than \
+                 {STAKE_TABLE_CAPACITY}, the capacity the light client verifying key was generated \
+                 for; the prover must use the same capacity"
+            ),
+        }
+    }
+}
+
+impl Contracts {
+    /// Record a warning, and log it.
+    pub fn warn(&mut self, warning: Warning) {
+        tracing::warn!("{warning}");
+        self.warnings.push(warning);
+    }
+
+    /// The warnings recorded so far.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Fail if any warning of at least `min` severity was recorded.
+    pub fn check_warnings(&self, min: Severity) -> anyhow::Result<()> {
+        let count = self
+            .warnings
+            .iter()
+            .filter(|warning| warning.severity() >= min)
+            .count();
+        ensure!(
+            count == 0,
+            "{count} warning(s) of severity {min} or higher were emitted"
+        );
+        Ok(())
+    }
+
+    /// Write the warnings section of the human-readable summary.
+    ///
+    /// Nothing is written if there are no warnings.
+    pub fn write_warnings(&self, mut w: impl Write) -> anyhow::Result<()> {
+        if self.warnings.is_empty() {
+            return Ok(());
+        }
+        writeln!(w, "Warnings:")?;
+        for warning in &self.warnings {
+            writeln!(w, "  [{}] {warning}", warning.severity())?;
+        }
+        Ok(())
+    }
+
+    /// Warn if the deployer account still owns the light client.
+    ///
+    /// The owner is read through the proxy if there is one, otherwise from the (mock) light client
+    /// contract itself. Nothing is checked in [dry-run mode](DeployMode::DryRun), since the
+    /// contracts do not exist.
+    pub async fn check_owner<M: Middleware + 'static>(
+        &mut self,
+        l1: Arc<M>,
+        deployer: Address,
+    ) -> anyhow::Result<()> {
+        if self.mode == DeployMode::DryRun {
+            return Ok(());
+        }
+        let Some((contract, address)) = [Contract::LightClientProxy, Contract::LightClient]
+            .into_iter()
+            .find_map(|contract| Some((contract, self.address(contract)?)))
+        else {
+            return Ok(());
+        };
+        let owner = LightClient::new(address, l1)
+            .owner()
+            .call()
+            .await
+            .with_context(|| format!("error reading owner of {contract:?}"))?;
+        if owner == deployer {
+            self.warn(Warning::DeployerOwns { contract, owner });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collect_warnings() {
+        let mut contracts = Contracts::default();
+        assert!(contracts.warnings().is_empty());
+        contracts.check_warnings(Severity::Low).unwrap();
+
+        let low = Warning::DuplicateBytecode {
+            contract: Contract::LightClient,
+            original: Contract::PlonkVerifier,
+        };
+        let medium = Warning::Unverified {
+            contract: Contract::HotShot,
+            address: Address::repeat_byte(1),
+        };
+        contracts.warn(low.clone());
+        contracts.warn(medium.clone());
+        assert_eq!(contracts.warnings(), [low, medium]);
+        assert_eq!(contracts.warnings()[1].contract(), Contract::HotShot);
+
+        let mut out = vec![];
+        contracts.write_warnings(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Warnings:\n"), "{out}");
+        assert!(
+            out.contains("[medium] code of predeployed HotShot"),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn test_warnings_as_errors() {
+        let mut contracts = Contracts::default();
+        contracts.warn(Warning::Unverified {
+            contract: Contract::HotShot,
+            address: Address::repeat_byte(1),
+        });
+        contracts.check_warnings(Severity::Low).unwrap_err();
+        contracts.check_warnings(Severity::Medium).unwrap_err();
+        contracts.check_warnings(Severity::High).unwrap();
+
+        contracts.warn(Warning::MockOnPublicNetwork {
+            contract: Contract::LightClient,
+            chain_id: 1,
+        });
+        let err = contracts
+            .check_warnings(Severity::High)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("1 warning(s) of severity high"), "{err}");
+    }
+
+    #[test]
+    fn test_serialize_warning() {
+        let warning = Warning::CodeMismatch {
+            contract: Contract::LightClient,
+            address: Address::repeat_byte(1),
+        };
+        let json = serde_json::to_value(&warning).unwrap();
+        assert_eq!(json["kind"], "code_mismatch");
+        assert_eq!(json["contract"], Contract::LightClient.to_string());
+        assert_eq!(json["address"], format!("{:#x}", Address::repeat_byte(1)));
+
+        // Warnings are included in the JSON output.
+        let mut contracts = Contracts::default();
+        contracts.warn(warning);
+        let mut out = vec![];
+        contracts.write_json(&mut out).unwrap();
+        let out: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(out["warnings"], serde_json::json!([json]));
+    }
+
+    #[test]
+    fn test_local_chain() {
+        assert!(is_local_chain(31337));
+        assert!(!is_local_chain(1));
+        assert!(!is_local_chain(11155111));
+    }
+}
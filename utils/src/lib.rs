@@ -21,6 +21,7 @@ use tempfile::TempDir;
 use url::Url;
 
 pub mod deployer;
+pub mod state_relay;
 pub mod test_utils;
 
 pub type Signer = SignerMiddleware<Provider<Http>, LocalWallet>;
@@ -33,6 +34,8 @@ pub struct AnvilOptions {
     load_state: Option<PathBuf>,
     accounts: Option<usize>,
     chain_id: Option<u64>,
+    fork_url: Option<Url>,
+    fork_block_number: Option<u64>,
 }
 
 impl AnvilOptions {
@@ -61,6 +64,13 @@ impl AnvilOptions {
         self
     }
 
+    /// Fork the chain at `url`, at `block` or, if not given, the latest block.
+    pub fn fork(mut self, url: Url, block: Option<u64>) -> Self {
+        self.fork_url = Some(url);
+        self.fork_block_number = block;
+        self
+    }
+
     pub async fn spawn(self) -> Anvil {
         let state_dir = TempDir::new().unwrap();
         let (child, url) = Anvil::spawn_server(&self, Some(state_dir.path())).await;
@@ -138,6 +148,12 @@ impl Anvil {
         if let Some(chain_id) = opt.chain_id {
             command.args(["--chain-id", &chain_id.to_string()]);
         }
+        if let Some(fork_url) = &opt.fork_url {
+            command.args(["--fork-url", fork_url.as_str()]);
+        }
+        if let Some(block) = opt.fork_block_number {
+            command.args(["--fork-block-number", &block.to_string()]);
+        }
 
         tracing::info!("Starting Anvil: {:?}", &command);
 
@@ -363,10 +379,40 @@ macro_rules! impl_to_fixed_bytes {
     };
 }
 
+/// Why [`contract_send`] failed.
+#[derive(Debug)]
+pub enum ContractSendError<E> {
+    /// The call reverted with a known contract error before it was sent, when estimating gas.
+    Revert(E),
+    /// The transaction was mined, but reverted.
+    Reverted(TransactionReceipt),
+    /// The transaction could not be sent or confirmed.
+    Other(anyhow::Error),
+}
+
+impl<E: Debug> std::fmt::Display for ContractSendError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Revert(revert) => write!(f, "contract revert: {revert:?}"),
+            Self::Reverted(receipt) => write!(
+                f,
+                "contract call {:x}: transaction reverted",
+                receipt.transaction_hash
+            ),
+            Self::Other(err) => write!(f, "{err:#}"),
+        }
+    }
+}
+
+impl<E: Debug> std::error::Error for ContractSendError<E> {}
+
 /// send a transaction and wait for confirmation before returning the tx receipt and block included.
+///
+/// Reverts are reported separately from other failures, so callers can tell an expected contract
+/// error, or a transaction which lost a race after passing gas estimation, from a real failure.
 pub async fn contract_send<M: Middleware, T: Detokenize, E>(
     call: &ContractCall<M, T>,
-) -> Result<(TransactionReceipt, u64), anyhow::Error>
+) -> Result<(TransactionReceipt, u64), ContractSendError<E>>
 where
     M::Provider: Clone,
     E: ContractRevert + Debug,
@@ -374,9 +420,10 @@ where
     let pending = match call.send().await {
         Ok(pending) => pending,
         Err(err) => {
-            let e = err.decode_contract_revert::<E>().unwrap();
-            tracing::error!("contract revert: {:?}", e);
-            return Err(anyhow!("error sending transaction: {:?}", e));
+            return Err(match err.decode_contract_revert::<E>() {
+                Some(revert) => ContractSendError::Revert(revert),
+                None => ContractSendError::Other(anyhow!("error sending transaction: {err}")),
+            });
         }
     };
 
@@ -385,22 +432,24 @@ where
     tracing::debug!("submitted contract call {:x}", hash);
 
     if !wait_for_transaction_to_be_mined(&provider, hash).await {
-        return Err(anyhow!("transaction not mined"));
+        return Err(ContractSendError::Other(anyhow!("transaction not mined")));
     }
 
     let receipt = match provider.get_transaction_receipt(hash).await {
         Ok(Some(receipt)) => receipt,
         Ok(None) => {
-            return Err(anyhow!("contract call {hash:x}: no receipt"));
+            return Err(ContractSendError::Other(anyhow!(
+                "contract call {hash:x}: no receipt"
+            )));
         }
         Err(err) => {
-            return Err(anyhow!(
+            return Err(ContractSendError::Other(anyhow!(
                 "contract call {hash:x}: error getting transaction receipt: {err}"
-            ))
+            )))
         }
     };
     if receipt.status != Some(1.into()) {
-        return Err(anyhow!("contract call {hash:x}: transaction reverted"));
+        return Err(ContractSendError::Reverted(receipt));
     }
 
     // If a transaction is mined and we get a receipt for it, the block number should _always_ be
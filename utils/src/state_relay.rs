@@ -0,0 +1,29 @@
+//! Types shared between the state relay server and its clients.
+
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+/// Progress of the state relay server towards a quorum of signatures on recent light client states.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuorumProgress {
+    /// Weight of signatures needed for a light client state to be available.
+    pub threshold: U256,
+    /// Total weight of the stake table, if the relay server knows it.
+    pub total_weight: Option<U256>,
+    /// Block height of the latest light client state with enough signatures, if any.
+    pub latest_available: Option<u64>,
+    /// Light client states newer than the latest available one, which are still collecting
+    /// signatures, in order of block height.
+    pub pending: Vec<PendingState>,
+}
+
+/// A light client state which does not have enough signatures yet.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingState {
+    pub block_height: u64,
+    pub view_number: u64,
+    /// Total weight of the signatures collected so far.
+    pub accumulated_weight: U256,
+    /// Number of nodes which have signed the state.
+    pub signers: usize,
+}